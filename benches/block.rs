@@ -0,0 +1,57 @@
+//! Block hashing, the mining inner loop, and block encoding and decoding
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use aarwyn_chain::bench;
+use aarwyn_chain::block::Block;
+use aarwyn_chain::codec::DecodeLimits;
+use aarwyn_chain::difficulty::Difficulty;
+
+/// Transactions per block for the encoding benchmarks
+const TRANSACTION_COUNTS: [usize; 3] = [1, 100, 1_000];
+
+/// Nonces per call of the mining loop
+const ATTEMPT_COUNTS: [u64; 2] = [1_000, 10_000];
+
+fn hash(c: &mut Criterion) {
+    // The hash covers the header only, so the body does not matter
+    let block = bench::block(1);
+    c.bench_function("block/hash", |b| b.iter(|| black_box(&block).hash()));
+}
+
+fn mine(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block/mine");
+    // Whether a nonce meets the difficulty does not change the work of
+    // trying it
+    let difficulty = Difficulty::LeadingZeroBits(32);
+    for attempts in ATTEMPT_COUNTS {
+        let mut block = bench::block(1);
+        group.throughput(Throughput::Elements(attempts));
+        group.bench_function(BenchmarkId::from_parameter(attempts), |b| {
+            b.iter(|| bench::mine_n_attempts(&mut block, difficulty, black_box(attempts)))
+        });
+    }
+    group.finish();
+}
+
+fn encoding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("block/encoding");
+    let limits = DecodeLimits::default();
+    for count in TRANSACTION_COUNTS {
+        let block = bench::block(count);
+        let bytes = block.to_bytes();
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+        group.bench_with_input(BenchmarkId::new("to_bytes", count), &block, |b, block| {
+            b.iter(|| block.to_bytes())
+        });
+        group.bench_with_input(BenchmarkId::new("from_bytes", count), &bytes, |b, bytes| {
+            b.iter(|| Block::from_bytes(black_box(bytes), &limits).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, hash, mine, encoding);
+criterion_main!(benches);
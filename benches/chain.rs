@@ -0,0 +1,81 @@
+//! Appending blocks to a chain, in memory and in a file store
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+
+use aarwyn_chain::bench;
+use aarwyn_chain::block::Block;
+use aarwyn_chain::chain::Blockchain;
+use aarwyn_chain::store::{ChainStore, FileStore, MemoryStore};
+
+/// Blocks appended per iteration
+const BLOCKS: usize = 100;
+
+/// Transactions per block
+const TRANSACTION_COUNTS: [usize; 2] = [1, 100];
+
+/// A directory of its own for each file store, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "aarwyn-bench-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        TempDir(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn append_all<S: ChainStore>(mut chain: Blockchain<S>, blocks: Vec<Block>) -> Blockchain<S> {
+    for block in blocks {
+        chain.append(block).unwrap();
+    }
+    chain
+}
+
+fn append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chain/append");
+    group.throughput(Throughput::Elements(BLOCKS as u64));
+    let params = bench::params();
+    for count in TRANSACTION_COUNTS {
+        let blocks = bench::chain_blocks(BLOCKS, count);
+        group.bench_with_input(BenchmarkId::new("memory", count), &blocks, |b, blocks| {
+            b.iter_batched(
+                || {
+                    let chain = Blockchain::open(&params, MemoryStore::new()).unwrap();
+                    (chain, blocks.clone())
+                },
+                |(chain, blocks)| append_all(chain, blocks),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("file", count), &blocks, |b, blocks| {
+            b.iter_batched(
+                || {
+                    let dir = TempDir::new();
+                    let chain =
+                        Blockchain::open(&params, FileStore::open(&dir.0).unwrap()).unwrap();
+                    (dir, chain, blocks.clone())
+                },
+                |(dir, chain, blocks)| (dir, append_all(chain, blocks)),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, append);
+criterion_main!(benches);
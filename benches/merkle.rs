@@ -0,0 +1,47 @@
+//! Merkle tree construction, and proof generation and verification, over
+//! trees of a thousand, a hundred thousand and a million leaves
+
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use aarwyn_chain::bench;
+use aarwyn_chain::merkle_trie::MerkleTree;
+
+const LEAF_COUNTS: [usize; 3] = [1_000, 100_000, 1_000_000];
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merkle/build");
+    group.sample_size(10);
+    for count in LEAF_COUNTS {
+        let leaves = bench::leaves(count);
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(count), &leaves, |b, leaves| {
+            b.iter(|| MerkleTree::new(black_box(leaves)))
+        });
+    }
+    group.finish();
+}
+
+fn proofs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("merkle/proof");
+    for count in LEAF_COUNTS {
+        let leaves = bench::leaves(count);
+        let tree = MerkleTree::new(&leaves).unwrap();
+        // A leaf in the middle has a sibling at every level
+        let index = count / 2;
+        group.bench_with_input(BenchmarkId::new("generate", count), &index, |b, &index| {
+            b.iter(|| tree.generate_proof(black_box(index)))
+        });
+        let proof = tree.generate_proof(index).unwrap();
+        group.bench_with_input(
+            BenchmarkId::new("verify", count),
+            &leaves[index],
+            |b, leaf| b.iter(|| assert!(proof.verify(black_box(leaf)))),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, build, proofs);
+criterion_main!(benches);
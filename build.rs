@@ -0,0 +1,16 @@
+//! Generates the `proto` module's messages from `proto/aarwyn.proto` when
+//! the `proto` feature is on. The schema is compiled by `protox`, so no
+//! `protoc` needs to be installed.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "proto")]
+    {
+        println!("cargo:rerun-if-changed=proto/aarwyn.proto");
+        let descriptors = protox::compile(["proto/aarwyn.proto"], ["proto"])
+            .expect("proto/aarwyn.proto compiles");
+        prost_build::Config::new()
+            .compile_fds(descriptors)
+            .expect("prost generates the messages");
+    }
+}
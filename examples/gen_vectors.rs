@@ -0,0 +1,25 @@
+//! Write the frozen test vectors to `vectors/`, or to the directory given as
+//! the first argument:
+//!
+//! ```text
+//! cargo run --example gen_vectors --features vectors [DIR]
+//! ```
+
+use std::fs;
+use std::path::PathBuf;
+
+use aarwyn_chain::test_vectors;
+
+fn main() -> std::io::Result<()> {
+    let dir = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("vectors"));
+    fs::create_dir_all(&dir)?;
+    for (name, contents) in test_vectors::files() {
+        let path = dir.join(name);
+        fs::write(&path, contents)?;
+        println!("wrote {}", path.display());
+    }
+    Ok(())
+}
@@ -0,0 +1,73 @@
+/*
+ * C interface to aarwyn-chain's merkle proof and header checks, built into
+ * the crate's shared library with the `ffi` feature:
+ *
+ *     cargo build --release --features ffi
+ *
+ * Memory is owned by the caller throughout. Pointers passed in are only
+ * used during the call and never kept, and results are written to buffers
+ * the caller allocates. No function returns memory for the caller to free.
+ * A null pointer is accepted only with a length of zero.
+ *
+ * Every function returns AARWYN_OK or another non-negative result on
+ * success, and one of the negative AARWYN_ERR_ codes on failure. Panics
+ * never unwind into the caller; they are reported as AARWYN_ERR_PANIC.
+ */
+
+#ifndef AARWYN_H
+#define AARWYN_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Length of a hash, and the least an output buffer must hold */
+#define AARWYN_HASH_LEN 32
+
+#define AARWYN_OK 0
+/* A pointer was null with a non-zero length */
+#define AARWYN_ERR_NULL_POINTER (-1)
+/* Input bytes did not decode */
+#define AARWYN_ERR_DECODE (-2)
+/* The output buffer is shorter than AARWYN_HASH_LEN */
+#define AARWYN_ERR_BUFFER_TOO_SMALL (-3)
+/* A hash was given with a length other than AARWYN_HASH_LEN */
+#define AARWYN_ERR_INVALID_LENGTH (-4)
+/* A merkle root was asked for no leaves */
+#define AARWYN_ERR_EMPTY (-5)
+/* The call panicked */
+#define AARWYN_ERR_PANIC (-6)
+
+/*
+ * Check that `proof`, a serialized merkle proof, places the leaf `data`
+ * under `root`, which must be AARWYN_HASH_LEN bytes. Returns 1 if it does
+ * and 0 if not.
+ */
+int32_t aarwyn_verify_proof(const uint8_t *root, size_t root_len,
+                            const uint8_t *data, size_t data_len,
+                            const uint8_t *proof, size_t proof_len);
+
+/*
+ * Write the hash of the block whose serialized header is `header` to the
+ * first AARWYN_HASH_LEN bytes of `out`.
+ */
+int32_t aarwyn_block_hash(const uint8_t *header, size_t header_len,
+                          uint8_t *out, size_t out_len);
+
+/*
+ * Write the merkle root of `leaf_count` leaves, leaf i being the
+ * `leaf_lens[i]` bytes at `leaves[i]`, to the first AARWYN_HASH_LEN bytes
+ * of `out`.
+ */
+int32_t aarwyn_merkle_root(const uint8_t *const *leaves,
+                           const size_t *leaf_lens, size_t leaf_count,
+                           uint8_t *out, size_t out_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* AARWYN_H */
@@ -0,0 +1,621 @@
+//! Addresses outputs pay to.
+//!
+//! An [`Address`] is the hash of a public key, so an output names its owner
+//! without revealing the key until it is spent. Addresses are written out for
+//! people in one of two forms:
+//!
+//! - base58check: a version byte, the hash and a four byte checksum, in an
+//!   alphabet that leaves out look-alike characters
+//! - bech32m (BIP-350): a human-readable part naming the network, taken from
+//!   [`ChainParams::address_hrp`](crate::params::ChainParams::address_hrp),
+//!   then the hash and a checksum that catches any four wrong characters
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::crypto::PublicKey;
+
+/// The base58 alphabet, without 0, O, I and l
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Length of the checksum ending a base58check string
+const CHECKSUM_LENGTH: usize = 4;
+
+/// The bech32 alphabet, one character for each five-bit value
+const BECH32_ALPHABET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+/// Longest bech32 string, human-readable part included
+const BECH32_MAX_LENGTH: usize = 90;
+
+/// Length of the checksum ending a bech32 string, in characters
+const BECH32_CHECKSUM_LENGTH: usize = 6;
+
+/// The hash of the public key an output pays to.
+///
+/// This is the single-hash variant: the SHA-256 of the key's bytes, with no
+/// RIPEMD-160 round, so an address is 32 bytes. Keys of the two schemes have
+/// different lengths, 32 bytes for ed25519 and 33 for secp256k1, so their
+/// encodings never coincide. The address of an ed25519 key is its
+/// [`pubkey_hash`](crate::transaction::pubkey_hash).
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Address([u8; 32]);
+
+/// Reasons a base58check or bech32 string is not an address
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum AddressError {
+    /// The string holds a character outside its encoding's alphabet
+    #[error("invalid address character {0:?}")]
+    InvalidCharacter(char),
+    /// The string decodes to `len` bytes, the wrong number for an address
+    #[error("address decodes to {0} bytes, the wrong number")]
+    InvalidLength(usize),
+    /// The checksum does not match the rest of the string
+    #[error("address checksum does not match")]
+    InvalidChecksum,
+    /// A bech32 string mixes upper and lower case
+    #[error("address mixes upper and lower case")]
+    MixedCase,
+    /// A bech32 string is longer than the 90 characters allowed
+    #[error("address is {0} characters, over the {max} allowed", max = BECH32_MAX_LENGTH)]
+    TooLong(usize),
+    /// A bech32 string has no separator, an empty or non-printable
+    /// human-readable part, or too short a checksum
+    #[error("address is not well-formed bech32")]
+    InvalidFormat,
+    /// A bech32 string's padding bits are not zero or not the fewest needed
+    #[error("address has invalid padding")]
+    InvalidPadding,
+    /// A bech32 string is for the network with human-readable part `found`
+    /// rather than `expected`
+    #[error("address is for network {found:?}, expected {expected:?}")]
+    WrongNetwork { expected: String, found: String },
+}
+
+impl Address {
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Address(bytes)
+    }
+
+    /// The address paying to `key`
+    pub fn from_public_key(key: &PublicKey) -> Self {
+        Address(Sha256::digest(key.as_bytes()).into())
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    /// The base58check encoding of `version` followed by the address
+    pub fn to_base58check(&self, version: u8) -> String {
+        let mut bytes = Vec::with_capacity(1 + 32 + CHECKSUM_LENGTH);
+        bytes.push(version);
+        bytes.extend_from_slice(&self.0);
+        let checksum = checksum(&bytes);
+        bytes.extend_from_slice(&checksum);
+        base58_encode(&bytes)
+    }
+
+    /// Decode a string made by [`Address::to_base58check`], whatever its
+    /// version byte, refusing it if the checksum does not match
+    pub fn from_base58check(s: &str) -> Result<Address, AddressError> {
+        let bytes = base58_decode(s)?;
+        if bytes.len() != 1 + 32 + CHECKSUM_LENGTH {
+            return Err(AddressError::InvalidLength(bytes.len()));
+        }
+        let (payload, check) = bytes.split_at(1 + 32);
+        if checksum(payload) != check {
+            return Err(AddressError::InvalidChecksum);
+        }
+        let mut address = [0u8; 32];
+        address.copy_from_slice(&payload[1..]);
+        Ok(Address(address))
+    }
+
+    /// The bech32m encoding of the address under the human-readable part
+    /// `hrp`, in lower case.
+    ///
+    /// Fails with [`AddressError::InvalidFormat`] if `hrp` is empty or holds a
+    /// character outside printable ASCII, and with [`AddressError::TooLong`]
+    /// if it is too long for the address to fit in 90 characters.
+    pub fn to_bech32(&self, hrp: &str) -> Result<String, AddressError> {
+        bech32_encode(hrp, &to_base32(&self.0), Variant::Bech32m)
+    }
+
+    /// Decode a string made by [`Address::to_bech32`], refusing it with
+    /// [`AddressError::WrongNetwork`] if its human-readable part is not
+    /// `expected_hrp`. Either case is accepted, but not a mix of both.
+    pub fn from_bech32(s: &str, expected_hrp: &str) -> Result<Address, AddressError> {
+        let (hrp, data, variant) = bech32_decode(s)?;
+        if variant != Variant::Bech32m {
+            return Err(AddressError::InvalidChecksum);
+        }
+        if hrp != expected_hrp.to_ascii_lowercase() {
+            return Err(AddressError::WrongNetwork {
+                expected: expected_hrp.to_ascii_lowercase(),
+                found: hrp,
+            });
+        }
+        let bytes = from_base32(&data)?;
+        let address = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| AddressError::InvalidLength(bytes.len()))?;
+        Ok(Address(address))
+    }
+}
+
+impl From<&PublicKey> for Address {
+    fn from(key: &PublicKey) -> Self {
+        Address::from_public_key(key)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Address({})", self)
+    }
+}
+
+/// The first bytes of the double SHA-256 of `payload`
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_LENGTH] {
+    let hash = Sha256::digest(Sha256::digest(payload));
+    let mut checksum = [0u8; CHECKSUM_LENGTH];
+    checksum.copy_from_slice(&hash[..CHECKSUM_LENGTH]);
+    checksum
+}
+
+/// `bytes` as a big-endian number in base 58, with a leading '1' for each
+/// leading zero byte
+fn base58_encode(bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|&&byte| byte == 0).count();
+    // Little-endian base 58 digits of the number so far
+    let mut digits: Vec<u8> = Vec::with_capacity(bytes.len() * 138 / 100 + 1);
+    for &byte in &bytes[zeros..] {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    std::iter::repeat_n('1', zeros)
+        .chain(
+            digits
+                .iter()
+                .rev()
+                .map(|&digit| ALPHABET[digit as usize] as char),
+        )
+        .collect()
+}
+
+/// The bytes [`base58_encode`] made `s` from
+fn base58_decode(s: &str) -> Result<Vec<u8>, AddressError> {
+    let zeros = s.chars().take_while(|&c| c == '1').count();
+    // Little-endian bytes of the number so far
+    let mut bytes: Vec<u8> = Vec::with_capacity(s.len());
+    for c in s.chars().skip(zeros) {
+        let mut carry = ALPHABET
+            .iter()
+            .position(|&a| a as char == c)
+            .ok_or(AddressError::InvalidCharacter(c))? as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    bytes.extend(std::iter::repeat_n(0, zeros));
+    bytes.reverse();
+    Ok(bytes)
+}
+
+/// The two checksums of bech32 strings, differing in the constant the
+/// checksum is xored with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Variant {
+    /// BIP-173
+    Bech32,
+    /// BIP-350, which fixes bech32's blindness to inserted or deleted `q`s
+    /// before a final `p`
+    Bech32m,
+}
+
+impl Variant {
+    fn constant(self) -> u32 {
+        match self {
+            Variant::Bech32 => 1,
+            Variant::Bech32m => 0x2bc8_30a3,
+        }
+    }
+}
+
+/// The BCH code remainder of `values` the checksum is built from
+fn polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = ((checksum & 0x01ff_ffff) << 5) ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+/// The human-readable part as the checksum sees it: the high bits of each
+/// character, a zero, then the low bits
+fn hrp_expand(hrp: &str) -> impl Iterator<Item = u8> + '_ {
+    hrp.bytes()
+        .map(|c| c >> 5)
+        .chain(std::iter::once(0))
+        .chain(hrp.bytes().map(|c| c & 31))
+}
+
+fn is_valid_hrp(hrp: &str) -> bool {
+    !hrp.is_empty() && hrp.bytes().all(|c| (33..=126).contains(&c))
+}
+
+/// `hrp`, a separator, `data` as five-bit values, and the checksum
+fn bech32_encode(hrp: &str, data: &[u8], variant: Variant) -> Result<String, AddressError> {
+    let hrp = hrp.to_ascii_lowercase();
+    if !is_valid_hrp(&hrp) {
+        return Err(AddressError::InvalidFormat);
+    }
+    let len = hrp.len() + 1 + data.len() + BECH32_CHECKSUM_LENGTH;
+    if len > BECH32_MAX_LENGTH {
+        return Err(AddressError::TooLong(len));
+    }
+    let check = polymod(
+        hrp_expand(&hrp)
+            .chain(data.iter().copied())
+            .chain([0; BECH32_CHECKSUM_LENGTH]),
+    ) ^ variant.constant();
+    let checksum = (0..BECH32_CHECKSUM_LENGTH).map(|i| ((check >> (5 * (5 - i))) & 31) as u8);
+    let mut s = hrp.clone();
+    s.push('1');
+    s.extend(
+        data.iter()
+            .copied()
+            .chain(checksum)
+            .map(|value| BECH32_ALPHABET[value as usize] as char),
+    );
+    Ok(s)
+}
+
+/// The lower-case human-readable part and five-bit data of a bech32 string,
+/// with the variant its checksum matches
+fn bech32_decode(s: &str) -> Result<(String, Vec<u8>, Variant), AddressError> {
+    if s.len() > BECH32_MAX_LENGTH {
+        return Err(AddressError::TooLong(s.len()));
+    }
+    let has_lower = s.chars().any(|c| c.is_ascii_lowercase());
+    let has_upper = s.chars().any(|c| c.is_ascii_uppercase());
+    if has_lower && has_upper {
+        return Err(AddressError::MixedCase);
+    }
+    let s = s.to_ascii_lowercase();
+    let (hrp, data) = s.rsplit_once('1').ok_or(AddressError::InvalidFormat)?;
+    if !is_valid_hrp(hrp) || data.len() < BECH32_CHECKSUM_LENGTH {
+        return Err(AddressError::InvalidFormat);
+    }
+    let data = data
+        .chars()
+        .map(|c| {
+            BECH32_ALPHABET
+                .iter()
+                .position(|&a| a as char == c)
+                .map(|value| value as u8)
+                .ok_or(AddressError::InvalidCharacter(c))
+        })
+        .collect::<Result<Vec<u8>, _>>()?;
+    let check = polymod(hrp_expand(hrp).chain(data.iter().copied()));
+    let variant = [Variant::Bech32, Variant::Bech32m]
+        .into_iter()
+        .find(|variant| variant.constant() == check)
+        .ok_or(AddressError::InvalidChecksum)?;
+    let payload = data[..data.len() - BECH32_CHECKSUM_LENGTH].to_vec();
+    Ok((hrp.to_string(), payload, variant))
+}
+
+/// `bytes` regrouped into five-bit values, zero padding the last
+fn to_base32(bytes: &[u8]) -> Vec<u8> {
+    let mut values = Vec::with_capacity((bytes.len() * 8).div_ceil(5));
+    let (mut acc, mut bits) = (0u32, 0);
+    for &byte in bytes {
+        acc = (acc << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            values.push(((acc >> bits) & 31) as u8);
+        }
+    }
+    if bits > 0 {
+        values.push(((acc << (5 - bits)) & 31) as u8);
+    }
+    values
+}
+
+/// The bytes [`to_base32`] made `values` from, refusing padding longer than
+/// needed or not zero
+fn from_base32(values: &[u8]) -> Result<Vec<u8>, AddressError> {
+    let mut bytes = Vec::with_capacity(values.len() * 5 / 8);
+    let (mut acc, mut bits) = (0u32, 0);
+    for &value in values {
+        acc = ((acc << 5) | value as u32) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 5 || (acc << (8 - bits)) & 0xff != 0 {
+        return Err(AddressError::InvalidPadding);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{ed25519, secp256k1, Signer};
+
+    #[test]
+    fn test_base58_vectors() {
+        // Vectors from the Bitcoin Core test suite
+        let vectors = [
+            ("", ""),
+            ("61", "2g"),
+            ("626262", "a3gV"),
+            ("636363", "aPEr"),
+            ("00000000000000000000", "1111111111"),
+            (
+                "00eb15231dfceb60925886b67d065299925915aeb172c06647",
+                "1NS17iag9jJgTHD1VXjvLCEnZuQ3rJDE9L",
+            ),
+            ("516b6fcd0f", "ABnLTmg"),
+            ("572e4794", "3EFU7m"),
+        ];
+        for (hex, base58) in vectors {
+            let bytes = hex::decode(hex).unwrap();
+            assert_eq!(base58_encode(&bytes), base58);
+            assert_eq!(base58_decode(base58), Ok(bytes));
+        }
+        assert_eq!(
+            base58_decode("0OIl"),
+            Err(AddressError::InvalidCharacter('0'))
+        );
+    }
+
+    #[test]
+    fn test_base58check_round_trip() {
+        let ed = ed25519::SigningKey::from_bytes(&[1; 32]).public_key();
+        let secp = secp256k1::SigningKey::from_bytes(&[1; 32])
+            .unwrap()
+            .public_key();
+        for key in [ed, secp] {
+            let address = Address::from_public_key(&key);
+            for version in [0, 0x6f, 0xff] {
+                let encoded = address.to_base58check(version);
+                assert_eq!(Address::from_base58check(&encoded), Ok(address));
+            }
+        }
+        assert_ne!(
+            Address::from_public_key(&ed),
+            Address::from_public_key(&secp)
+        );
+        // Version zero shows as leading ones
+        assert!(Address::default().to_base58check(0).starts_with("1111"));
+        assert_eq!(
+            Address::from_base58check(&Address::default().to_base58check(0)),
+            Ok(Address::default())
+        );
+    }
+
+    #[test]
+    fn test_corrupted_base58check_is_refused() {
+        let address = Address::from_bytes([0x5a; 32]);
+        let encoded = address.to_base58check(0x17);
+
+        // Changing any one character breaks the checksum
+        for i in 0..encoded.len() {
+            let mut corrupted: Vec<char> = encoded.chars().collect();
+            corrupted[i] = if corrupted[i] == 'z' { 'y' } else { 'z' };
+            let corrupted: String = corrupted.into_iter().collect();
+            assert!(
+                matches!(
+                    Address::from_base58check(&corrupted),
+                    Err(AddressError::InvalidChecksum | AddressError::InvalidLength(_))
+                ),
+                "{}",
+                corrupted
+            );
+        }
+        assert_eq!(
+            Address::from_base58check(&encoded[1..]),
+            Err(AddressError::InvalidLength(36))
+        );
+        assert_eq!(
+            Address::from_base58check(&format!("{}0", &encoded[1..])),
+            Err(AddressError::InvalidCharacter('0'))
+        );
+    }
+
+    #[test]
+    fn test_bech32_vectors() {
+        // Valid strings from BIP-173 and BIP-350
+        let valid = [
+            (Variant::Bech32, "A12UEL5L"),
+            (Variant::Bech32, "a12uel5l"),
+            (
+                Variant::Bech32,
+                "an83characterlonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1tt5tgs",
+            ),
+            (Variant::Bech32, "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw"),
+            (
+                Variant::Bech32,
+                "11qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqc8247j",
+            ),
+            (Variant::Bech32, "split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w"),
+            (Variant::Bech32, "?1ezyfcl"),
+            (Variant::Bech32m, "A1LQFN3A"),
+            (Variant::Bech32m, "a1lqfn3a"),
+            (
+                Variant::Bech32m,
+                "an83characterlonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11sg7hg6",
+            ),
+            (Variant::Bech32m, "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx"),
+            (
+                Variant::Bech32m,
+                "11llllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllludsr8",
+            ),
+            (Variant::Bech32m, "split1checkupstagehandshakeupstreamerranterredcaperredlc445v"),
+            (Variant::Bech32m, "?1v759aa"),
+        ];
+        for (variant, s) in valid {
+            let (hrp, data, decoded) = bech32_decode(s).unwrap();
+            assert_eq!(decoded, variant, "{}", s);
+            assert_eq!(
+                bech32_encode(&hrp, &data, variant).unwrap(),
+                s.to_ascii_lowercase()
+            );
+        }
+
+        // Invalid strings from BIP-173 and BIP-350
+        let invalid = [
+            ("\x201nwldj5", AddressError::InvalidFormat),
+            ("\x7f1axkwrx", AddressError::InvalidFormat),
+            ("\u{80}1eym55h", AddressError::InvalidFormat),
+            (
+                "an84characterslonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1569pvx",
+                AddressError::TooLong(91),
+            ),
+            ("pzry9x0s0muk", AddressError::InvalidFormat),
+            ("1pzry9x0s0muk", AddressError::InvalidFormat),
+            ("x1b4n0q5v", AddressError::InvalidCharacter('b')),
+            ("li1dgmt3", AddressError::InvalidFormat),
+            ("de1lg7wt\u{ff}", AddressError::InvalidCharacter('\u{ff}')),
+            ("A1G7SGD8", AddressError::InvalidChecksum),
+            ("10a06t8", AddressError::InvalidFormat),
+            ("1qzzfhee", AddressError::InvalidFormat),
+            ("\x201xj0phk", AddressError::InvalidFormat),
+            ("\x7f1g6xzxy", AddressError::InvalidFormat),
+            ("\u{80}1vctc34", AddressError::InvalidFormat),
+            (
+                "an84characterslonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11d6pts4",
+                AddressError::TooLong(91),
+            ),
+            ("qyrz8wqd2c9m", AddressError::InvalidFormat),
+            ("1qyrz8wqd2c9m", AddressError::InvalidFormat),
+            ("y1b0jsk6g", AddressError::InvalidCharacter('b')),
+            ("lt1igcx5c0", AddressError::InvalidCharacter('i')),
+            ("in1muywd", AddressError::InvalidFormat),
+            ("mm1crxm3i", AddressError::InvalidCharacter('i')),
+            ("au1s5cgom", AddressError::InvalidCharacter('o')),
+            ("M1VUXWEZ", AddressError::InvalidChecksum),
+            ("16plkw9", AddressError::InvalidFormat),
+            ("1p2gdwpf", AddressError::InvalidFormat),
+        ];
+        for (s, err) in invalid {
+            assert_eq!(bech32_decode(s), Err(err), "{:?}", s);
+        }
+        assert_eq!(bech32_decode("a12UEL5L"), Err(AddressError::MixedCase));
+    }
+
+    #[test]
+    fn test_bech32_addresses() {
+        use crate::params::ChainParams;
+
+        let mainnet = ChainParams::mainnet_defaults().address_hrp;
+        let testnet = ChainParams::test_defaults().address_hrp;
+        let key = secp256k1::SigningKey::from_bytes(&[3; 32])
+            .unwrap()
+            .public_key();
+        let address = Address::from_public_key(&key);
+
+        let encoded = address.to_bech32(&mainnet).unwrap();
+        assert!(encoded.starts_with("arw1"));
+        assert_eq!(Address::from_bech32(&encoded, &mainnet), Ok(address));
+        assert_eq!(
+            Address::from_bech32(&encoded.to_uppercase(), &mainnet),
+            Ok(address)
+        );
+        assert_eq!(
+            Address::from_bech32(&encoded, &testnet),
+            Err(AddressError::WrongNetwork {
+                expected: "tarw".to_string(),
+                found: "arw".to_string(),
+            })
+        );
+        assert_ne!(address.to_bech32(&testnet).unwrap(), encoded);
+        // A human-readable part no string could carry is refused
+        assert_eq!(address.to_bech32(""), Err(AddressError::InvalidFormat));
+        assert_eq!(address.to_bech32("arw\n"), Err(AddressError::InvalidFormat));
+        assert_eq!(
+            address.to_bech32(&"a".repeat(32)),
+            Err(AddressError::TooLong(91))
+        );
+
+        // Any one character changed is caught
+        for i in 4..encoded.len() {
+            let mut corrupted = encoded.clone().into_bytes();
+            corrupted[i] = if corrupted[i] == b'q' { b'p' } else { b'q' };
+            let corrupted = String::from_utf8(corrupted).unwrap();
+            assert_eq!(
+                Address::from_bech32(&corrupted, &mainnet),
+                Err(AddressError::InvalidChecksum)
+            );
+        }
+
+        // The same data under a bech32 checksum, or of the wrong length, is refused
+        let data = to_base32(address.as_bytes());
+        let legacy = bech32_encode(&mainnet, &data, Variant::Bech32).unwrap();
+        assert_eq!(
+            Address::from_bech32(&legacy, &mainnet),
+            Err(AddressError::InvalidChecksum)
+        );
+        let short = bech32_encode(
+            &mainnet,
+            &to_base32(&address.as_bytes()[..31]),
+            Variant::Bech32m,
+        )
+        .unwrap();
+        assert_eq!(
+            Address::from_bech32(&short, &mainnet),
+            Err(AddressError::InvalidLength(31))
+        );
+        let mut padded = data.clone();
+        *padded.last_mut().unwrap() |= 1;
+        let padded = bech32_encode(&mainnet, &padded, Variant::Bech32m).unwrap();
+        assert_eq!(
+            Address::from_bech32(&padded, &mainnet),
+            Err(AddressError::InvalidPadding)
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Fixtures and hooks for the Criterion benchmarks in `benches/`, under the
+//! `bench` feature.
+//!
+//! The fixtures are deterministic, so two runs measure the same work. The
+//! hooks reach past the public API where it would make a measurement depend
+//! on luck: [`mine_n_attempts`] hashes an exact number of nonces, where
+//! [`Block::mine`] stops at the first that meets the difficulty.
+//!
+//! Run the benchmarks with:
+//!
+//! ```text
+//! cargo bench --features bench
+//! ```
+//!
+//! The fixtures only build blocks from inputs they have already made valid,
+//! so a failure to build one is a bug in the fixture to surface at once
+//! rather than an error to pass on.
+
+#![allow(clippy::expect_used)]
+
+use sha2::{Digest, Sha256};
+
+use crate::block::{Block, BlockBuilder, BlockHash};
+use crate::difficulty::Difficulty;
+use crate::params::{ChainParams, ConsensusMode};
+
+/// The difficulty fixture blocks are mined at, which every hash meets
+pub const DIFFICULTY: Difficulty = Difficulty::LeadingZeroBits(0);
+
+/// Bytes in each fixture transaction, about those of a transfer with one
+/// input and two outputs
+pub const TRANSACTION_BYTES: usize = 250;
+
+/// `count` distinct 32-byte leaves
+pub fn leaves(count: usize) -> Vec<Vec<u8>> {
+    (0..count as u64)
+        .map(|i| Sha256::digest(i.to_le_bytes()).to_vec())
+        .collect()
+}
+
+/// `count` distinct transactions of [`TRANSACTION_BYTES`] each, numbered
+/// from `first`
+pub fn transactions(first: u64, count: usize) -> Vec<Vec<u8>> {
+    (first..first + count as u64)
+        .map(|i| {
+            let seed = Sha256::digest(i.to_le_bytes());
+            seed.iter()
+                .copied()
+                .cycle()
+                .take(TRANSACTION_BYTES)
+                .collect()
+        })
+        .collect()
+}
+
+/// A block of `transactions` transactions on a zero parent, mined at
+/// [`DIFFICULTY`]
+pub fn block(transactions: usize) -> Block {
+    mined(
+        BlockBuilder::new(BlockHash::ZERO),
+        self::transactions(0, transactions),
+        1_700_000_000,
+    )
+}
+
+/// Parameters the fixture chains follow: [`ChainParams::test_defaults`]
+/// without proof of work
+pub fn params() -> ChainParams {
+    ChainParams {
+        initial_difficulty: DIFFICULTY,
+        consensus_mode: ConsensusMode::ProofOfWork {
+            difficulty: DIFFICULTY,
+        },
+        ..ChainParams::test_defaults()
+    }
+}
+
+/// `count` blocks of `transactions` transactions each extending the genesis
+/// block of [`params`], ten seconds apart
+pub fn chain_blocks(count: usize, transactions: usize) -> Vec<Block> {
+    let mut parent = params().genesis_block();
+    let mut blocks = Vec::with_capacity(count);
+    for height in 0..count {
+        let first = (height * transactions) as u64;
+        let block = mined(
+            parent.next_builder(),
+            self::transactions(first, transactions),
+            parent.timestamp() + 10,
+        );
+        parent = block.clone();
+        blocks.push(block);
+    }
+    blocks
+}
+
+/// Hash exactly `attempts` nonces of `block` from its current one, as the
+/// inner loop of [`Block::mine`] does, and return how many met `difficulty`.
+/// The nonce is left after the last one tried, so repeated calls try new
+/// nonces.
+pub fn mine_n_attempts(block: &mut Block, difficulty: Difficulty, attempts: u64) -> u64 {
+    block.try_nonces(difficulty, attempts)
+}
+
+fn mined(builder: BlockBuilder, transactions: Vec<Vec<u8>>, timestamp: u64) -> Block {
+    let mut block = builder
+        .transactions(transactions)
+        .timestamp(timestamp)
+        .difficulty(DIFFICULTY)
+        .build()
+        .expect("a fixture block has a transaction");
+    block.mine(DIFFICULTY);
+    block
+}
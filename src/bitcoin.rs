@@ -0,0 +1,343 @@
+//! Bitcoin's merkle tree, for checking the tree in [`merkle_trie`] against
+//! block data from outside this chain.
+//!
+//! Bitcoin differs from [`MerkleTree`] in three ways: nodes are hashed
+//! with double SHA-256, the leaves are the txids as they are rather than a
+//! hash of them, and the last node of an odd level is paired with itself
+//! instead of being promoted. Txids and block hashes are shown reversed,
+//! so the hex an explorer displays is the last byte of the hash first;
+//! [`reverse_hash_display`] and [`parse_hash_display`] convert between the
+//! two orders.
+//!
+//! [`merkle_trie`]: crate::merkle_trie
+//! [`MerkleTree`]: crate::merkle_trie::MerkleTree
+
+use sha2::{Digest, Sha256};
+
+use crate::encoding::{self, EncodingError};
+use crate::hash::Hash32;
+use crate::merkle_trie::MerkleError;
+
+/// SHA-256 of the SHA-256 of `data`, Bitcoin's hash for txids, block
+/// headers and merkle nodes
+pub fn double_sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+/// `hash`, in the byte order it is hashed in, as the hex explorers display
+pub fn reverse_hash_display(hash: &[u8; 32]) -> String {
+    let mut reversed = *hash;
+    reversed.reverse();
+    encoding::to_hex(&reversed)
+}
+
+/// The hash whose displayed hex is `s`, in the byte order it is hashed in
+pub fn parse_hash_display(s: &str) -> Result<[u8; 32], EncodingError> {
+    let mut hash = s.parse::<Hash32>()?.into_bytes();
+    hash.reverse();
+    Ok(hash)
+}
+
+/// A merkle tree over txids built as Bitcoin builds it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitcoinMerkleTree {
+    /// Node hashes by level, from the txids up to the root
+    levels: Vec<Vec<[u8; 32]>>,
+    root: [u8; 32],
+}
+
+impl BitcoinMerkleTree {
+    /// The tree over `txids`, each in the byte order it is hashed in, of
+    /// which there must be at least one
+    pub fn new(txids: &[[u8; 32]]) -> Result<Self, MerkleError> {
+        let mut levels = Vec::new();
+        let mut level = txids.to_vec();
+        loop {
+            match level[..] {
+                [] => return Err(MerkleError::Empty),
+                [root] => {
+                    levels.push(level);
+                    return Ok(BitcoinMerkleTree { levels, root });
+                }
+                _ => {}
+            }
+            let parents = level
+                .chunks(2)
+                .map(|pair| {
+                    // An odd level pairs its last node with itself
+                    let right = pair.get(1).unwrap_or(&pair[0]);
+                    node_hash(&pair[0], right)
+                })
+                .collect();
+            levels.push(std::mem::replace(&mut level, parents));
+        }
+    }
+
+    /// The root, as a block header commits to it
+    pub fn root(&self) -> [u8; 32] {
+        self.root
+    }
+
+    /// A proof that the txid at `index` is under the root
+    pub fn generate_proof(&self, index: usize) -> Result<BitcoinMerkleProof, MerkleError> {
+        let leaves = self.levels[0].len();
+        if index >= leaves {
+            return Err(MerkleError::IndexOutOfRange { index, leaves });
+        }
+
+        let mut branch = Vec::with_capacity(self.levels.len() - 1);
+        let mut position = index;
+        for level in &self.levels[..self.levels.len() - 1] {
+            let is_right = position % 2 == 1;
+            let sibling = if is_right {
+                level[position - 1]
+            } else {
+                // The last node of an odd level is its own sibling
+                *level.get(position + 1).unwrap_or(&level[position])
+            };
+            branch.push((sibling, !is_right));
+            position /= 2;
+        }
+        Ok(BitcoinMerkleProof {
+            branch,
+            txid: self.levels[0][index],
+            root: self.root,
+        })
+    }
+}
+
+/// A proof that a txid is under a Bitcoin merkle root
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BitcoinMerkleProof {
+    /// The siblings from the txid up, each with a flag set if it is on the
+    /// right
+    branch: Vec<([u8; 32], bool)>,
+    txid: [u8; 32],
+    root: [u8; 32],
+}
+
+impl BitcoinMerkleProof {
+    /// A proof from a branch taken from elsewhere, such as a `merkleblock`
+    /// message, ordered from the txid up
+    pub fn new(txid: [u8; 32], branch: Vec<([u8; 32], bool)>, root: [u8; 32]) -> Self {
+        BitcoinMerkleProof { branch, txid, root }
+    }
+
+    /// Whether the branch leads from `txid` to the root
+    pub fn verify(&self, txid: &[u8; 32]) -> bool {
+        if *txid != self.txid {
+            return false;
+        }
+        let computed = self.branch.iter().fold(*txid, |node, (sibling, is_right)| {
+            if *is_right {
+                node_hash(&node, sibling)
+            } else {
+                node_hash(sibling, &node)
+            }
+        });
+        computed == self.root
+    }
+
+    /// The root the proof claims the txid is under; compare it against a
+    /// header before relying on `verify`
+    pub fn root(&self) -> &[u8; 32] {
+        &self.root
+    }
+}
+
+fn node_hash(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut pair = [0u8; 64];
+    pair[..32].copy_from_slice(left);
+    pair[32..].copy_from_slice(right);
+    double_sha256(&pair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The fields of a block header, hashes in displayed hex
+    struct Header {
+        version: u32,
+        prev: &'static str,
+        merkle_root: &'static str,
+        time: u32,
+        bits: u32,
+        nonce: u32,
+    }
+
+    impl Header {
+        /// The displayed hash of the 80-byte serialized header
+        fn hash(&self) -> String {
+            let mut bytes = Vec::with_capacity(80);
+            bytes.extend_from_slice(&self.version.to_le_bytes());
+            bytes.extend_from_slice(&parse_hash_display(self.prev).unwrap());
+            bytes.extend_from_slice(&parse_hash_display(self.merkle_root).unwrap());
+            bytes.extend_from_slice(&self.time.to_le_bytes());
+            bytes.extend_from_slice(&self.bits.to_le_bytes());
+            bytes.extend_from_slice(&self.nonce.to_le_bytes());
+            reverse_hash_display(&double_sha256(&bytes))
+        }
+    }
+
+    /// A mainnet block with every txid it holds
+    struct Fixture {
+        hash: &'static str,
+        header: Header,
+        txids: &'static [&'static str],
+    }
+
+    /// Block 100000, with four transactions
+    const BLOCK_100000: Fixture = Fixture {
+        hash: "000000000003ba27aa200b1cecaad478d2b00432346c3f1f3986da1afd33e506",
+        header: Header {
+            version: 1,
+            prev: "000000000002d01c1fccc21636b607dfd930d31d01c3a62104612a1719011250",
+            merkle_root: "f3e94742aca4b5ef85488dc37c06c3282295ffec960994b2c0d5ac2a25a95766",
+            time: 1293623863,
+            bits: 0x1b04864c,
+            nonce: 274148111,
+        },
+        txids: &[
+            "8c14f0db3df150123e6f3dbbf30f8b955a8249b62ac1d1ff16284aefa3d06d87",
+            "fff2525b8931402dd09222c50775608f75787bd2b87e56995a7bdd30f79702c4",
+            "6359f0868171b1d194cbee1af2f16ea598ae8fad666d9b012c8ed2b79a236ec4",
+            "e9a66845e05d5abc0ad04ec80f774a7e585c6e8db975962d069a522137b80c1d",
+        ],
+    };
+
+    /// Block 170, holding the first transaction between two people
+    const BLOCK_170: Fixture = Fixture {
+        hash: "00000000d1145790a8694403d4063f323d499e655c83426834d4ce2f8dd4a2ee",
+        header: Header {
+            version: 1,
+            prev: "000000002a22cfee1f2c846adbd12b3e183d4f97683f85dad08a79780a84bd55",
+            merkle_root: "7dac2c5666815c17a3b36427de37bb9d2e2c5ccec3f8633eb91a4205cb4c10ff",
+            time: 1231731025,
+            bits: 0x1d00ffff,
+            nonce: 1889418792,
+        },
+        txids: &[
+            "b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082",
+            "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+        ],
+    };
+
+    /// The genesis block, whose one transaction makes an odd count
+    const GENESIS: Fixture = Fixture {
+        hash: "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f",
+        header: Header {
+            version: 1,
+            prev: "0000000000000000000000000000000000000000000000000000000000000000",
+            merkle_root: "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b",
+            time: 1231006505,
+            bits: 0x1d00ffff,
+            nonce: 2083236893,
+        },
+        txids: &["4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b"],
+    };
+
+    #[test]
+    fn test_merkle_roots_match_headers() {
+        for block in [BLOCK_100000, BLOCK_170, GENESIS] {
+            // The header, root included, is the one the block hash commits to
+            assert_eq!(block.header.hash(), block.hash);
+
+            let txids: Vec<[u8; 32]> = block
+                .txids
+                .iter()
+                .map(|txid| parse_hash_display(txid).unwrap())
+                .collect();
+            let tree = BitcoinMerkleTree::new(&txids).unwrap();
+            assert_eq!(reverse_hash_display(&tree.root()), block.header.merkle_root);
+            for (index, txid) in txids.iter().enumerate() {
+                let proof = tree.generate_proof(index).unwrap();
+                assert_eq!(proof.root(), &tree.root());
+                assert!(
+                    proof.verify(txid),
+                    "{} in {}",
+                    block.txids[index],
+                    block.hash
+                );
+                assert!(!proof.verify(&[0; 32]));
+            }
+        }
+    }
+
+    #[test]
+    fn test_odd_count_block_branch() {
+        // A block of seven transactions, and the branch for its fifth from
+        // the `merkleblock` a node sends for it, hashes in the byte order
+        // they are hashed in: the sixth txid, the node over the seventh
+        // paired with itself, then the node over the first four.
+        let header = Header {
+            version: 1,
+            prev: "0000000000016780c81d42b7eff86974c36f5ae026e8662a4393a7f39c86bb82",
+            merkle_root: "8772d9d0fdf8c1303c7b1167e3c73b095fd970e33c799c6563d98b2e96c5167f",
+            time: 1293629558,
+            bits: 0x1b04864c,
+            nonce: 696601429,
+        };
+        assert_eq!(
+            header.hash(),
+            "000000000000b731f2eef9e8c63173adfb07e41bd53eb0ef0a6b720d6cb6dea4"
+        );
+        let hash = |hex: &str| hex.parse::<Hash32>().unwrap().into_bytes();
+        let txid = hash("019f5b01d4195ecbc9398fbf3c3b1fa9bb3183301d7a1fb3bd174fcfa40a2b65");
+        let branch = vec![
+            (
+                hash("41ed70551dd7e841883ab8f0b16bf04176b7d1480e4f0af9f3d4c3595768d068"),
+                true,
+            ),
+            (
+                hash("20d2a7bc994987302e5b1ac80fc425fe25f8b63169ea78e68fbaaefa59379bbf"),
+                true,
+            ),
+            (
+                hash("3612262624047ee87660be1a707519a443b1c1ce3d248cbfc6c15870f6c5daa2"),
+                false,
+            ),
+        ];
+        let root = parse_hash_display(header.merkle_root).unwrap();
+        let proof = BitcoinMerkleProof::new(txid, branch.clone(), root);
+        assert!(proof.verify(&txid));
+        assert!(!proof.verify(&branch[0].0));
+
+        // The same branch with its sides swapped, or with a sibling missing
+        let mut swapped = branch.clone();
+        swapped[1].1 = false;
+        assert!(!BitcoinMerkleProof::new(txid, swapped, root).verify(&txid));
+        assert!(!BitcoinMerkleProof::new(txid, branch[..2].to_vec(), root).verify(&txid));
+    }
+
+    #[test]
+    fn test_odd_levels_duplicate_the_last_node() {
+        let txids: Vec<[u8; 32]> = (0..5u8).map(|i| [i; 32]).collect();
+        let tree = BitcoinMerkleTree::new(&txids).unwrap();
+        let pair = |left, right| node_hash(left, right);
+        let level1 = [
+            pair(&txids[0], &txids[1]),
+            pair(&txids[2], &txids[3]),
+            pair(&txids[4], &txids[4]),
+        ];
+        let level2 = [pair(&level1[0], &level1[1]), pair(&level1[2], &level1[2])];
+        assert_eq!(tree.root(), pair(&level2[0], &level2[1]));
+        for (index, txid) in txids.iter().enumerate() {
+            assert!(tree.generate_proof(index).unwrap().verify(txid));
+        }
+    }
+
+    #[test]
+    fn test_display_order() {
+        let hash = parse_hash_display(BLOCK_100000.hash).unwrap();
+        assert_eq!(&hash[28..], &[0, 0, 0, 0]);
+        assert_eq!(reverse_hash_display(&hash), BLOCK_100000.hash);
+        assert_eq!(
+            parse_hash_display("0x12"),
+            Err(EncodingError::WrongLength {
+                expected: 32,
+                got: 1
+            })
+        );
+    }
+}
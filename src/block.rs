@@ -1,105 +1,1000 @@
-use crate::merkle_trie::MerkleTree;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
 
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+
+use crate::codec::{self, DecodeError, DecodeLimits, Reader};
+use crate::crypto::ed25519;
+#[cfg(any(test, feature = "crypto"))]
+use crate::crypto::Signer;
+use crate::crypto::{PublicKey, Signature, SignatureScheme, Verifier};
+use crate::difficulty::Difficulty;
+use crate::encoding::EncodingError;
+use crate::hash::Hash32;
+use crate::merkle_trie::{MerkleError, MerkleTree};
+use crate::Error;
+use crate::state::StateView;
+use crate::trace;
+use crate::transaction::{self, BlockTransaction, FeeError, OutPoint, SigError, Transaction, TxInput, TxOutput, Txid};
+use crate::utxo::UtxoView;
+
+/// The SHA-256 hash identifying a block.
+///
+/// Block hashes are a distinct type from other digests, so the hash of a
+/// parent block is what links a child to it:
+///
+/// ```
+/// use aarwyn_chain::block::{Block, BlockHash};
+///
+/// let parent = Block::new(vec![b"tx".to_vec()], BlockHash::ZERO)?;
+/// let child = Block::new(vec![b"tx".to_vec()], parent.hash())?;
+/// assert!(child.verify_link(&parent));
+/// # Ok::<(), aarwyn_chain::block::BlockError>(())
+/// ```
+///
+/// while passing some other digest, such as a merkle root, does not compile:
+///
+/// ```compile_fail
+/// use aarwyn_chain::block::{Block, BlockHash};
+///
+/// let parent = Block::new(vec![b"tx".to_vec()], BlockHash::ZERO)?;
+/// let child = Block::new(vec![b"tx".to_vec()], parent.merkle_root().to_vec())?;
+/// # Ok::<(), aarwyn_chain::block::BlockError>(())
+/// ```
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BlockHash([u8; 32]);
+
+// Errors from parsing or converting a block hash
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BlockHashError {
+    // The input was not valid hex
+    #[error("block hash is not valid hex: {0}")]
+    InvalidHex(EncodingError),
+    // The input decoded to the wrong number of bytes
+    #[error("block hash must be 32 bytes, got {0}")]
+    InvalidLength(usize),
+}
+
+impl From<EncodingError> for BlockHashError {
+    fn from(err: EncodingError) -> Self {
+        match err {
+            EncodingError::WrongLength { got, .. } => BlockHashError::InvalidLength(got),
+            err => BlockHashError::InvalidHex(err),
+        }
+    }
+}
+
+// Errors from reading a block out of a stream with `Block::read_from`
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BlockDecodeError {
+    // The stream failed before the block was read
+    #[error("reading block failed: {0}")]
+    Io(String),
+    // The bytes read are not a valid block; a stream ending part way through one is
+    // `DecodeError::UnexpectedEof`, as with `Block::from_bytes`
+    #[error("malformed block: {0}")]
+    Decode(DecodeError),
+}
+
+impl From<io::Error> for BlockDecodeError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::UnexpectedEof => BlockDecodeError::Decode(DecodeError::UnexpectedEof),
+            _ => BlockDecodeError::Io(err.to_string()),
+        }
+    }
+}
+
+impl From<DecodeError> for BlockDecodeError {
+    fn from(err: DecodeError) -> Self {
+        BlockDecodeError::Decode(err)
+    }
+}
+
+// Errors from assembling a block with `BlockBuilder`
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum BlockError {
+    // A block commits to at least one transaction, so there is a merkle root
+    #[error("a block needs at least one transaction")]
+    NoTransactions,
+    // The parent hash given as raw bytes is not a block hash
+    #[error("invalid previous block hash: {0}")]
+    InvalidPrevHash(BlockHashError),
+}
+
+impl From<BlockHashError> for BlockError {
+    fn from(err: BlockHashError) -> Self {
+        BlockError::InvalidPrevHash(err)
+    }
+}
+
+impl From<MerkleError> for BlockError {
+    // Building a tree fails only for want of leaves, and proofs are not built here
+    fn from(_: MerkleError) -> Self {
+        BlockError::NoTransactions
+    }
+}
+
+impl BlockHash {
+    // The all-zero hash, used as the previous hash of a genesis block
+    pub const ZERO: BlockHash = BlockHash([0; 32]);
+    
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        BlockHash(bytes)
+    }
+    
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+    
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+impl AsRef<[u8]> for BlockHash {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Hash32> for BlockHash {
+    fn from(hash: Hash32) -> Self {
+        BlockHash(hash.0)
+    }
+}
+
+impl From<BlockHash> for Hash32 {
+    fn from(hash: BlockHash) -> Self {
+        Hash32(hash.0)
+    }
+}
+
+impl TryFrom<&[u8]> for BlockHash {
+    type Error = BlockHashError;
+    
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let array: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| BlockHashError::InvalidLength(bytes.len()))?;
+        Ok(BlockHash(array))
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BlockHash({})", self)
+    }
+}
+
+impl FromStr for BlockHash {
+    type Err = BlockHashError;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(BlockHash(s.parse::<Hash32>()?.into_bytes()))
+    }
+}
+
+// First header version to commit to the state after the block
+pub const STATE_ROOT_VERSION: u32 = 2;
+
+// Serialized as its header, signature and transactions, in that order as in the binary
+// format; the merkle tree is rebuilt from the transactions
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "BlockFields")]
 pub struct Block {
     header: BlockHeader,
     transactions: Vec<Vec<u8>>,
     merkle_tree: MerkleTree,
+    // Authority signature over the serialized header, for proof-of-authority chains
+    signature: Option<Signature>,
 }
 
-pub struct BlockHeader {
+// Limits on how much a single block may carry
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockLimits {
+    // Maximum total size of the block's transactions in bytes
+    pub max_bytes: usize,
+    // Maximum number of transactions, including the coinbase
+    pub max_transactions: usize,
+}
+
+// A block template together with the transactions that did not fit into it
+pub struct BlockTemplate {
+    pub block: Block,
+    // Offered transactions left out of the block, in their original order
+    pub skipped: Vec<Vec<u8>>,
+}
+
+// A single transaction rejected by application-level validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxFailure {
+    // Position of the transaction within the block
+    pub index: usize,
+    // The message returned by the application's check
+    pub message: String,
+}
+
+// Transactions in a block that failed application-level validation
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub struct TxValidationError {
+    // Failures in transaction order; never empty, which only the constructor below
+    // upholds, so the field stays private
+    failures: Vec<TxFailure>,
+}
+
+impl TxValidationError {
+    // An error for `failures`, or success if there are none
+    fn check(failures: Vec<TxFailure>) -> Result<(), TxValidationError> {
+        if failures.is_empty() {
+            Ok(())
+        } else {
+            Err(TxValidationError { failures })
+        }
+    }
+    
+    // Failures in transaction order, at least one
+    pub fn failures(&self) -> &[TxFailure] {
+        &self.failures
+    }
+    
+    // Index of the first failing transaction
+    pub fn index(&self) -> usize {
+        self.failures[0].index
+    }
+    
+    // Message for the first failing transaction
+    pub fn message(&self) -> &str {
+        &self.failures[0].message
+    }
+}
+
+impl fmt::Display for TxValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "transaction {} is invalid: {}", self.index(), self.message())?;
+        if self.failures.len() > 1 {
+            write!(f, " (and {} more)", self.failures.len() - 1)?;
+        }
+        Ok(())
+    }
+}
+
+// Incrementally assembles a block on top of a known parent
+pub struct BlockBuilder {
     version: u32,
-    prev_block_hash: Vec<u8>,
-    merkle_root: Vec<u8>,
-    timestamp: u64,
+    prev_block_hash: BlockHash,
+    transactions: Vec<Vec<u8>>,
+    timestamp: Option<u64>,
+    bits: u32,
     nonce: u64,
+    state_root: Option<[u8; 32]>,
 }
 
-impl Block {
-    // Create a new block with given transactions and previous block hash
-    pub fn new(transactions: Vec<Vec<u8>>, prev_block_hash: Vec<u8>) -> Self {
+impl BlockBuilder {
+    // Start a block whose parent has the given hash
+    pub fn new(prev_block_hash: BlockHash) -> Self {
+        BlockBuilder {
+            version: 1,
+            prev_block_hash,
+            transactions: Vec::new(),
+            timestamp: None,
+            bits: Difficulty::LeadingZeroBits(0).to_compact(),
+            nonce: 0,
+            state_root: None,
+        }
+    }
+    
+    // Deprecated shim for callers still holding the parent hash as raw bytes, which must be
+    // exactly 32 bytes long
+    #[deprecated(note = "use BlockBuilder::new with a BlockHash")]
+    pub fn from_raw_prev_hash(prev_block_hash: Vec<u8>) -> Result<Self, BlockHashError> {
+        Ok(Self::new(BlockHash::try_from(prev_block_hash.as_slice())?))
+    }
+    
+    pub fn version(mut self, version: u32) -> Self {
+        self.version = version;
+        self
+    }
+    
+    // Add a transaction, raw bytes or a structured `Transaction` stored as its encoding
+    pub fn transaction(mut self, tx: impl BlockTransaction) -> Self {
+        self.transactions.push(tx.encode());
+        self
+    }
+    
+    pub fn transactions<T: BlockTransaction>(mut self, txs: impl IntoIterator<Item = T>) -> Self {
+        self.transactions.extend(txs.into_iter().map(|tx| tx.encode()));
+        self
+    }
+    
+    // Override the timestamp, which otherwise defaults to the current time
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+    
+    // Commit the block to a proof-of-work difficulty, stored in compact form. A compact
+    // target is committed as given, so a header's own `difficulty()` builds it again
+    pub fn difficulty(mut self, difficulty: Difficulty) -> Self {
+        self.bits = match difficulty {
+            Difficulty::CompactTarget(bits) => bits,
+            difficulty => difficulty.to_compact(),
+        };
+        self
+    }
+    
+    // Set the starting nonce, e.g. one already known to meet the difficulty
+    pub fn nonce(mut self, nonce: u64) -> Self {
+        self.nonce = nonce;
+        self
+    }
+    
+    // Commit to the state after the block, raising the version to at least
+    // `STATE_ROOT_VERSION`; a later, lower `version` drops the root again
+    pub fn state_root(mut self, state_root: [u8; 32]) -> Self {
+        self.version = self.version.max(STATE_ROOT_VERSION);
+        self.state_root = Some(state_root);
+        self
+    }
+    
+    // Build the block committing to the root `state` would have after its
+    // transactions, failing if they do not apply to it
+    pub fn build_with_state<V: StateView>(self, state: &V) -> Result<Block, Error>
+    where
+        Error: From<V::Error>,
+    {
+        let mut block = self.state_root([0; 32]).build()?;
+        block.header.state_root = Some(state.root_after(&block)?);
+        Ok(block)
+    }
+    
+    // A header of version `STATE_ROOT_VERSION` or later always commits to a
+    // state root, all zeros unless one was given. A block needs at least one transaction.
+    pub fn build(self) -> Result<Block, BlockError> {
         // Create Merkle tree from transactions
-        let merkle_tree = MerkleTree::new(&transactions);
+        let merkle_tree = MerkleTree::new(&self.transactions)?;
         
         // Create block header
         let header = BlockHeader {
-            version: 1,
-            prev_block_hash,
-            merkle_root: merkle_tree.root_hash().to_vec(),
-            timestamp: Self::current_timestamp(),
-            nonce: 0,
+            version: self.version,
+            prev_block_hash: self.prev_block_hash,
+            merkle_root: merkle_tree.root(),
+            state_root: (self.version >= STATE_ROOT_VERSION).then(|| self.state_root.unwrap_or([0; 32])),
+            timestamp: self.timestamp.unwrap_or_else(Block::current_timestamp),
+            bits: self.bits,
+            nonce: self.nonce,
         };
         
-        Block {
+        Ok(Block {
             header,
-            transactions,
+            transactions: self.transactions,
             merkle_tree,
+            signature: None,
+        })
+    }
+}
+
+// The serde field order below is part of the serialized format; a header whose state
+// root does not match its version is refused
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields, try_from = "HeaderFields")]
+pub struct BlockHeader {
+    version: u32,
+    prev_block_hash: BlockHash,
+    merkle_root: Hash32,
+    // Root of the state after the block; present exactly from `STATE_ROOT_VERSION` on
+    state_root: Option<[u8; 32]>,
+    timestamp: u64,
+    // Compact encoding of the difficulty the block commits to
+    bits: u32,
+    nonce: u64,
+}
+
+impl BlockHeader {
+    // Serialize the header by concatenating its fixed-size fields
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(self.encoded_len());
+        
+        // Add version
+        buffer.extend_from_slice(&self.version.to_le_bytes());
+        // Add prev block hash
+        buffer.extend_from_slice(self.prev_block_hash.as_ref());
+        // Add merkle root
+        buffer.extend_from_slice(self.merkle_root.as_ref());
+        // Add state root, from `STATE_ROOT_VERSION` on
+        if let Some(state_root) = &self.state_root {
+            buffer.extend_from_slice(state_root);
+        }
+        // Add timestamp
+        buffer.extend_from_slice(&self.timestamp.to_le_bytes());
+        // Add difficulty bits
+        buffer.extend_from_slice(&self.bits.to_le_bytes());
+        // Add nonce
+        buffer.extend_from_slice(&self.nonce.to_le_bytes());
+        
+        buffer
+    }
+    
+    // Size of an encoded header without a state root in bytes
+    pub const ENCODED_LEN: usize = 4 + 32 + 32 + 8 + 4 + 8;
+    
+    // Size of an encoded header with a state root in bytes
+    pub const MAX_ENCODED_LEN: usize = Self::ENCODED_LEN + 32;
+    
+    // Size of an encoded header of the given version, which the first four
+    // bytes of the encoding hold
+    pub fn encoded_len_of(version: u32) -> usize {
+        if version >= STATE_ROOT_VERSION {
+            Self::MAX_ENCODED_LEN
+        } else {
+            Self::ENCODED_LEN
         }
     }
     
+    pub fn encoded_len(&self) -> usize {
+        Self::encoded_len_of(self.version)
+    }
+    
+    // Decode a header produced by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<BlockHeader, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let header = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(header)
+    }
+    
+    pub(crate) fn decode(reader: &mut Reader<'_>) -> Result<BlockHeader, DecodeError> {
+        let version = reader.read_u32()?;
+        Ok(BlockHeader {
+            version,
+            prev_block_hash: BlockHash::from_bytes(reader.read_array()?),
+            merkle_root: Hash32(reader.read_array()?),
+            state_root: if version >= STATE_ROOT_VERSION { Some(reader.read_array()?) } else { None },
+            timestamp: reader.read_u64()?,
+            bits: reader.read_u32()?,
+            nonce: reader.read_u64()?,
+        })
+    }
+    
+    // Hash of the serialized header, which identifies the block
+    pub fn hash(&self) -> BlockHash {
+        BlockHash(Sha256::digest(self.to_bytes()).into())
+    }
+    
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    
+    pub fn prev_block_hash(&self) -> BlockHash {
+        self.prev_block_hash
+    }
+    
+    pub fn merkle_root(&self) -> &[u8] {
+        self.merkle_root.as_ref()
+    }
+    
+    pub fn merkle_root_hash(&self) -> Hash32 {
+        self.merkle_root
+    }
+    
+    // The state root committed to, for headers from `STATE_ROOT_VERSION` on
+    pub fn state_root(&self) -> Option<&[u8; 32]> {
+        self.state_root.as_ref()
+    }
+    
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+    
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+    
+    // The difficulty committed to by the header's bits
+    pub fn difficulty(&self) -> Difficulty {
+        Difficulty::CompactTarget(self.bits)
+    }
+    
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
+impl Block {
+    // Create a new block with given transactions, of which there must be at least one, and
+    // previous block hash
+    pub fn new(transactions: Vec<Vec<u8>>, prev_block_hash: BlockHash) -> Result<Self, BlockError> {
+        BlockBuilder::new(prev_block_hash).transactions(transactions).build()
+    }
+    
+    // Deprecated shim for callers still holding the parent hash as raw bytes, which must be
+    // exactly 32 bytes long
+    #[deprecated(note = "use Block::new with a BlockHash")]
+    pub fn new_from_raw_prev_hash(transactions: Vec<Vec<u8>>, prev_block_hash: Vec<u8>) -> Result<Self, BlockError> {
+        #[allow(deprecated)]
+        BlockBuilder::from_raw_prev_hash(prev_block_hash)?.transactions(transactions).build()
+    }
+    
+    // Start building a child of this block
+    pub fn next_builder(&self) -> BlockBuilder {
+        BlockBuilder::new(self.hash())
+    }
+    
+    // Check that this block names `parent` as its previous block
+    pub fn verify_link(&self, parent: &Block) -> bool {
+        self.header.prev_block_hash == parent.hash()
+    }
+    
+    // Create an unmined block template: the coinbase first, then as many of `txs` as fit
+    pub fn template(
+        prev_hash: BlockHash,
+        txs: impl IntoIterator<Item = Vec<u8>>,
+        limits: &BlockLimits,
+        coinbase: Vec<u8>,
+    ) -> Block {
+        Self::fill_template(prev_hash, txs, limits, coinbase).block
+    }
+    
+    // Like `template`, but also return the transactions that were skipped
+    pub fn fill_template(
+        prev_hash: BlockHash,
+        txs: impl IntoIterator<Item = Vec<u8>>,
+        limits: &BlockLimits,
+        coinbase: Vec<u8>,
+    ) -> BlockTemplate {
+        // The coinbase is always included, even if it alone exceeds the limits
+        let mut total_bytes = coinbase.len();
+        let mut transactions = vec![coinbase];
+        let mut skipped = Vec::new();
+        
+        for tx in txs {
+            // Oversized transactions are skipped so that smaller ones can still fit
+            if transactions.len() >= limits.max_transactions ||
+                total_bytes + tx.len() > limits.max_bytes {
+                skipped.push(tx);
+                continue;
+            }
+            total_bytes += tx.len();
+            transactions.push(tx);
+        }
+        
+        // The coinbase is always included, so the block is never empty
+        #[allow(clippy::expect_used)]
+        let block = Block::new(transactions, prev_hash).expect("the template holds its coinbase");
+        BlockTemplate { block, skipped }
+    }
+    
     // Calculate the hash of this block
-    pub fn hash(&self) -> Vec<u8> {
-        // Serialize header and hash it
-        let serialized = self.serialize_header();
-        MerkleTree::hash(&serialized)
+    pub fn hash(&self) -> BlockHash {
+        self.header.hash()
     }
     
     // Helper function to serialize the header for hashing
     fn serialize_header(&self) -> Vec<u8> {
-        // Simple serialization by concatenating fields
-        let mut buffer = Vec::new();
+        self.header.to_bytes()
+    }
+    
+    // Serialize the whole block: header, optional signature, then transactions
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = self.serialize_prefix();
+        for tx in &self.transactions {
+            codec::write_bytes(&mut buffer, tx);
+        }
+        buffer
+    }
+    
+    // The header, signature flag and signature, and transaction count that start the encoding
+    fn serialize_prefix(&self) -> Vec<u8> {
+        let mut buffer = self.serialize_header();
+        match &self.signature {
+            Some(signature) => {
+                buffer.push(signature.scheme().tag());
+                buffer.extend_from_slice(&signature.to_bytes());
+            }
+            None => buffer.push(0),
+        }
+        codec::write_varint(&mut buffer, self.transactions.len() as u64);
+        buffer
+    }
+    
+    // Write the bytes of `to_bytes` to `w` a transaction at a time, without building the whole
+    // encoding first, and return how many were written
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<usize> {
+        let mut buffer = self.serialize_prefix();
+        w.write_all(&buffer)?;
+        let mut written = buffer.len();
         
-        // Add version
-        buffer.extend_from_slice(&self.header.version.to_le_bytes());
-        // Add prev block hash
-        buffer.extend_from_slice(&self.header.prev_block_hash);
-        // Add merkle root
-        buffer.extend_from_slice(&self.header.merkle_root);
-        // Add timestamp
-        buffer.extend_from_slice(&self.header.timestamp.to_le_bytes());
-        // Add nonce
-        buffer.extend_from_slice(&self.header.nonce.to_le_bytes());
+        for tx in &self.transactions {
+            buffer.clear();
+            codec::write_varint(&mut buffer, tx.len() as u64);
+            w.write_all(&buffer)?;
+            w.write_all(tx)?;
+            written += buffer.len() + tx.len();
+        }
+        Ok(written)
+    }
+    
+    // Decode a block produced by `to_bytes`, enforcing `limits` on untrusted input
+    pub fn from_bytes(bytes: &[u8], limits: &DecodeLimits) -> Result<Block, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let mut reader = Reader::new(bytes);
         
-        buffer
+        let header = BlockHeader::decode(&mut reader)?;
+        let signature = match reader.read_u8()? {
+            0 => None,
+            tag => {
+                let scheme = SignatureScheme::from_tag(tag).ok_or(DecodeError::InvalidValue("signature flag"))?;
+                Some(Signature::from_bytes(scheme, &reader.read_array()?))
+            }
+        };
+        
+        let count = reader.read_len("transaction count", limits.max_transactions)?;
+        if count == 0 {
+            return Err(DecodeError::InvalidValue("block has no transactions"));
+        }
+        // Every transaction takes at least one byte, so never reserve more than the input can hold
+        let mut transactions = Vec::with_capacity(count.min(reader.remaining()));
+        for _ in 0..count {
+            let tx = reader.read_var_bytes("transaction size", limits.max_transaction_bytes)?;
+            transactions.push(tx.to_vec());
+        }
+        reader.finish()?;
+        
+        let merkle_tree = MerkleTree::new(&transactions).map_err(|_| DecodeError::InvalidValue("block has no transactions"))?;
+        Ok(Block {
+            header,
+            transactions,
+            merkle_tree,
+            signature,
+        })
     }
     
-    // Helper function to get current timestamp (seconds since epoch)
+    // Read a block written by `to_bytes` or `write_to` from `r` a transaction at a time, hashing
+    // each into the merkle tree as it arrives, so the encoding is never held whole. A block
+    // `from_bytes` accepts is read the same, and one it refuses is refused for the same reason,
+    // except that `limits.max_decode_bytes` is checked as bytes arrive rather than up front.
+    // Reading stops at the end of the block, leaving anything after it in `r` unread.
+    pub fn read_from(r: &mut impl Read, limits: &DecodeLimits) -> Result<Block, BlockDecodeError> {
+        let mut reader = StreamReader {
+            inner: r,
+            read: 0,
+            max: limits.max_decode_bytes,
+        };
+        
+        // The version says how long the rest of the header is
+        let version = reader.read_array()?;
+        let mut header = version.to_vec();
+        header.extend(reader.read_bytes(BlockHeader::encoded_len_of(u32::from_le_bytes(version)) - 4)?);
+        let header = BlockHeader::from_bytes(&header)?;
+        let signature = match reader.read_u8()? {
+            0 => None,
+            tag => {
+                let scheme = SignatureScheme::from_tag(tag).ok_or(DecodeError::InvalidValue("signature flag"))?;
+                Some(Signature::from_bytes(scheme, &reader.read_array()?))
+            }
+        };
+        
+        let count = reader.read_len("transaction count", limits.max_transactions)?;
+        let mut transactions = Vec::new();
+        let mut leaves = Vec::new();
+        for _ in 0..count {
+            let len = reader.read_len("transaction size", limits.max_transaction_bytes)?;
+            let tx = reader.read_bytes(len)?;
+            leaves.push(MerkleTree::hash(&tx));
+            transactions.push(tx);
+        }
+        
+        Ok(Block {
+            header,
+            transactions,
+            merkle_tree: MerkleTree::from_leaf_hashes(leaves).map_err(|_| DecodeError::InvalidValue("block has no transactions"))?,
+            signature,
+        })
+    }
+    
+    // Helper function to get current timestamp (seconds since epoch), 0 for a clock set before it
     fn current_timestamp() -> u64 {
         use std::time::{SystemTime, UNIX_EPOCH};
         SystemTime::now()
             .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards")
+            .unwrap_or_default()
             .as_secs()
     }
     
-    // Mine the block until its hash has the required number of leading zeros
-    pub fn mine(&mut self, difficulty: usize) {
-        // let target = vec![0; difficulty / 8];
-        let remainder = difficulty % 8;
-        let mask = if remainder > 0 { 0xff >> remainder } else { 0 };
-        
-        loop {
-            let hash = self.hash();
-            
-            // Check if hash meets difficulty (has enough leading zeros)
-            let meets_difficulty = hash.iter().take(difficulty / 8).all(|&b| b == 0) &&
-                (remainder == 0 || (hash[difficulty / 8] & !mask) == 0);
-                
-            if meets_difficulty {
-                break;
-            }
-            
+    // Mine the block until its hash meets the required difficulty
+    pub fn mine(&mut self, difficulty: Difficulty) {
+        let _span = trace::span!("mine", difficulty = ?difficulty);
+        #[cfg(feature = "tracing")]
+        let start = self.header.nonce;
+        while !self.verify_pow(difficulty) {
             // Increment nonce and try again
             self.header.nonce += 1;
         }
+        trace::event!(
+            debug,
+            attempts = self.header.nonce - start + 1,
+            nonce = self.header.nonce,
+            hash = %trace::short(self.hash()),
+            "mined"
+        );
+    }
+    
+    // Try at most `tries` nonces from the current one, stopping at the first whose hash
+    // meets `difficulty`, and return whether one did
+    pub fn mine_up_to(&mut self, difficulty: Difficulty, tries: u64) -> bool {
+        let _span = trace::span!("mine", difficulty = ?difficulty, tries);
+        for _attempt in 0..tries {
+            if self.verify_pow(difficulty) {
+                trace::event!(
+                    debug,
+                    attempts = _attempt + 1,
+                    nonce = self.header.nonce,
+                    hash = %trace::short(self.hash()),
+                    "mined"
+                );
+                return true;
+            }
+            self.header.nonce = self.header.nonce.wrapping_add(1);
+        }
+        trace::event!(debug, attempts = tries, "no nonce met the difficulty");
+        false
+    }
+    
+    // Hash `tries` nonces from the current one whatever their hashes, leaving the
+    // nonce after the last, and return how many met `difficulty`
+    #[cfg(feature = "bench")]
+    pub(crate) fn try_nonces(&mut self, difficulty: Difficulty, tries: u64) -> u64 {
+        let mut met = 0;
+        for _ in 0..tries {
+            met += u64::from(self.verify_pow(difficulty));
+            self.header.nonce = self.header.nonce.wrapping_add(1);
+        }
+        met
+    }
+    
+    // Check whether the block hash meets `difficulty`
+    pub fn verify_pow(&self, difficulty: Difficulty) -> bool {
+        difficulty.is_met_by(self.hash().as_bytes())
+    }
+    
+    // Check that the header's merkle root commits to the block's transactions
+    pub fn verify_merkle_root(&self) -> bool {
+        MerkleTree::new(&self.transactions).is_ok_and(|tree| tree.root() == self.header.merkle_root)
+    }
+    
+    // Run an application-specific check over each transaction, stopping at the first failure
+    pub fn validate_transactions<F: Fn(usize, &[u8]) -> Result<(), String>>(
+        &self,
+        f: F,
+    ) -> Result<(), TxValidationError> {
+        self.check_transactions(f, false)
+    }
+    
+    // Like `validate_transactions`, but report every failing transaction
+    pub fn validate_all_transactions<F: Fn(usize, &[u8]) -> Result<(), String>>(
+        &self,
+        f: F,
+    ) -> Result<(), TxValidationError> {
+        self.check_transactions(f, true)
+    }
+    
+    fn check_transactions<F: Fn(usize, &[u8]) -> Result<(), String>>(
+        &self,
+        f: F,
+        all_errors: bool,
+    ) -> Result<(), TxValidationError> {
+        let mut failures = Vec::new();
+        for (index, tx) in self.transactions.iter().enumerate() {
+            if let Err(message) = f(index, tx) {
+                failures.push(TxFailure { index, message });
+                if !all_errors {
+                    break;
+                }
+            }
+        }
+        TxValidationError::check(failures)
+    }
+    
+    // Reject transactions spending an outpoint an earlier transaction in the block already spends
+    //
+    // Every transaction must decode as a `Transaction`; all failures are reported.
+    pub fn check_no_duplicate_inputs(&self) -> Result<(), TxValidationError> {
+        let mut spenders: HashMap<OutPoint, usize> = HashMap::new();
+        let mut failures = Vec::new();
+        for (index, tx) in self.transactions.iter().enumerate() {
+            let tx = match Transaction::decode_from_block(tx) {
+                Ok(tx) => tx,
+                Err(err) => {
+                    failures.push(TxFailure { index, message: format!("does not decode: {}", err) });
+                    continue;
+                }
+            };
+            for input in &tx.inputs {
+                let out = input.prev_out;
+                match spenders.get(&out) {
+                    Some(&first) => {
+                        failures.push(TxFailure {
+                            index,
+                            message: format!("spends {}:{} already spent by transaction {}", out.txid, out.index, first),
+                        });
+                        break;
+                    }
+                    None => {
+                        spenders.insert(out, index);
+                    }
+                }
+            }
+        }
+        TxValidationError::check(failures)
+    }
+    
+    // Sum the fees the block's transactions pay, looking up spent outputs in `utxos`
+    //
+    // Outputs created earlier in the block may be spent later in it. Every transaction
+    // without inputs counts as coinbase, and together they may pay out at most `subsidy`,
+    // the reward the schedule allows at the block's height, plus the fees.
+    pub fn total_fees(&self, utxos: &impl UtxoView, subsidy: u64) -> Result<u64, FeeError> {
+        let mut created = HashMap::new();
+        let mut fees = 0u64;
+        let mut coinbase_paid = 0u64;
+        for (index, tx) in self.transactions.iter().enumerate() {
+            let tx = Transaction::decode_from_block(tx).map_err(|err| FeeError::Decode { index, err })?;
+            if tx.is_coinbase() {
+                coinbase_paid = coinbase_paid.checked_add(tx.value_out()?).ok_or(FeeError::AmountOverflow)?;
+            } else {
+                let view = BlockView { base: utxos, created: &created };
+                fees = fees.checked_add(tx.fee(&view)?).ok_or(FeeError::AmountOverflow)?;
+            }
+            let txid = tx.txid();
+            for (i, output) in tx.outputs.into_iter().enumerate() {
+                created.insert(OutPoint { txid, index: i as u32 }, output);
+            }
+        }
+        
+        let allowed = subsidy.checked_add(fees).ok_or(FeeError::AmountOverflow)?;
+        if coinbase_paid > allowed {
+            return Err(FeeError::CoinbaseOverpays { paid: coinbase_paid, allowed });
+        }
+        Ok(fees)
+    }
+    
+    // Check the signature on every input of the block's transactions against
+    // the key `resolver` gives for the input, typically that of the output it
+    // spends, with the ed25519 signatures checked in one batch
+    //
+    // When a check fails, the signatures are checked one at a time to name
+    // the first bad one. A lone ed25519 signature is checked as a batch of
+    // one, so the result never depends on the other signatures in the block.
+    // A signature of another scheme than its key is invalid.
+    pub fn verify_signatures_batch(&self, resolver: impl Fn(&TxInput) -> Option<PublicKey>) -> Result<(), SigError> {
+        self.verify_single_key_signatures(|_, _| false, |input, _| resolver(input))
+    }
+    
+    // Like `verify_signatures_batch`, passing over the inputs `skip` picks by
+    // transaction and input index, and passing `resolver` the input's sighash
+    // too. Every other input must carry exactly one signature.
+    fn verify_single_key_signatures(&self, skip: impl Fn(usize, usize) -> bool, resolver: impl Fn(&TxInput, &[u8; 32]) -> Option<PublicKey>) -> Result<(), SigError> {
+        // (transaction index, input index, sighash, signature, key)
+        let mut checks = Vec::new();
+        for (index, tx) in self.transactions.iter().enumerate() {
+            let tx = Transaction::decode_from_block(tx).map_err(|err| SigError::Decode { index, err })?;
+            if tx.inputs.is_empty() {
+                continue;
+            }
+            let unsigned = tx.unsigned_encoding();
+            for (input, tx_input) in tx.inputs.iter().enumerate() {
+                if skip(index, input) {
+                    continue;
+                }
+                let signature = match tx_input.signatures.as_slice() {
+                    [signature] => *signature,
+                    [] => return Err(SigError::MissingSignature { index, input }),
+                    _ => return Err(SigError::ExtraSignatures { index, input }),
+                };
+                let sighash = transaction::sighash(&unsigned, input);
+                let key = resolver(tx_input, &sighash).ok_or(SigError::UnknownKey { index, input })?;
+                checks.push((index, input, sighash, signature, key));
+            }
+        }
+        
+        let batch: Vec<_> = checks
+            .iter()
+            .filter_map(|(_, _, message, signature, key)| match (signature, key) {
+                (Signature::Ed25519(signature), PublicKey::Ed25519(key)) => Some((message.as_slice(), signature, key)),
+                _ => None,
+            })
+            .collect();
+        let verify_one = |message: &[u8], signature: &Signature, key: &PublicKey| match (signature, key) {
+            (Signature::Ed25519(signature), PublicKey::Ed25519(key)) => ed25519::verify_batch(&[(message, signature, key)]).is_ok(),
+            _ => key.verify_message(message, signature).is_ok(),
+        };
+        let others_valid = checks
+            .iter()
+            .filter(|(_, _, _, signature, key)| signature.scheme() != SignatureScheme::Ed25519 || key.scheme() != SignatureScheme::Ed25519)
+            .all(|(_, _, message, signature, key)| verify_one(message, signature, key));
+        if others_valid && ed25519::verify_batch(&batch).is_ok() {
+            return Ok(());
+        }
+        // The checks fail only if one of the signatures does, which is the one to report
+        match checks.iter().find(|(_, _, message, signature, key)| !verify_one(message, signature, key)) {
+            Some((index, input, ..)) => Err(SigError::InvalidSignature { index: *index, input: *input }),
+            None => Ok(()),
+        }
+    }
+    
+    // Check that every input meets the spend condition of the output it
+    // spends, looking up spent outputs in `utxos`: a single-key output needs
+    // the key its address names revealed, or recovered from the signature,
+    // and signing, a multisig output at least `m` signatures by distinct keys
+    // of its own
+    //
+    // Each input is checked as `Transaction::verify_spends` checks it, with
+    // the single-key signatures checked in one batch. Outputs created earlier
+    // in the block may be spent later in it. An input spending an output that
+    // does not exist has no known key.
+    pub fn verify_spends(&self, utxos: &impl UtxoView) -> Result<(), SigError> {
+        let mut created = HashMap::new();
+        let mut multisig = HashSet::new();
+        for (index, tx) in self.transactions.iter().enumerate() {
+            let tx = Transaction::decode_from_block(tx).map_err(|err| SigError::Decode { index, err })?;
+            let view = BlockView { base: utxos, created: &created };
+            let unsigned = tx.unsigned_encoding();
+            for (input, tx_input) in tx.inputs.iter().enumerate() {
+                let output = view.output(&tx_input.prev_out).ok_or(SigError::UnknownKey { index, input })?;
+                if transaction::check_spend(index, input, tx_input, &unsigned, &output)?.is_none() {
+                    multisig.insert((index, input));
+                }
+            }
+            let txid = tx.txid();
+            for (i, output) in tx.outputs.into_iter().enumerate() {
+                created.insert(OutPoint { txid, index: i as u32 }, output);
+            }
+        }
+        self.verify_single_key_signatures(|index, input| multisig.contains(&(index, input)), |input, sighash| input.signing_key(sighash))
+    }
+    
+    // Seal the block with an authority signature over the serialized header,
+    // of whichever scheme the key uses
+    #[cfg(any(test, feature = "crypto"))]
+    pub fn sign(&mut self, keypair: &impl Signer) {
+        self.signature = Some(keypair.sign_message(&self.serialize_header()));
+    }
+    
+    // Check that the block carries a valid signature from one of the allowed keys
+    pub fn verify_signature(&self, allowed_keys: &[impl Verifier]) -> bool {
+        let signature = match &self.signature {
+            Some(signature) => signature,
+            None => return false,
+        };
+        let header = self.serialize_header();
+        allowed_keys.iter().any(|key| key.verify_message(&header, signature).is_ok())
     }
     
     // Accessors
+    pub fn header(&self) -> &BlockHeader {
+        &self.header
+    }
+    
     pub fn merkle_root(&self) -> &[u8] {
-        &self.header.merkle_root
+        self.header.merkle_root.as_ref()
     }
     
-    pub fn prev_block_hash(&self) -> &[u8] {
-        &self.header.prev_block_hash
+    pub fn merkle_root_hash(&self) -> Hash32 {
+        self.header.merkle_root
+    }
+    
+    pub fn prev_block_hash(&self) -> BlockHash {
+        self.header.prev_block_hash
     }
     
     pub fn timestamp(&self) -> u64 {
@@ -110,7 +1005,911 @@ impl Block {
         self.header.nonce
     }
     
+    pub fn difficulty(&self) -> Difficulty {
+        self.header.difficulty()
+    }
+    
     pub fn transactions(&self) -> &[Vec<u8>] {
         &self.transactions
     }
+    
+    pub fn merkle_tree(&self) -> &MerkleTree {
+        &self.merkle_tree
+    }
+    
+    // Txids of the transactions in block order, the leaves of the merkle tree
+    pub fn txids(&self) -> Vec<Txid> {
+        self.transactions.iter().map(|tx| Txid::of(tx)).collect()
+    }
+    
+    // Decode every transaction as a structured `Transaction`
+    pub fn decode_transactions(&self, limits: &DecodeLimits) -> Result<Vec<Transaction>, DecodeError> {
+        self.transactions
+            .iter()
+            .map(|tx| Transaction::decode(tx, limits))
+            .collect()
+    }
+    
+    pub fn signature(&self) -> Option<&Signature> {
+        self.signature.as_ref()
+    }
+    
+    // Assemble a block from a header, signature and transactions received apart, as
+    // compact block relay does; the merkle root is left to the caller to check
+    pub(crate) fn from_parts(header: BlockHeader, signature: Option<Signature>, transactions: Vec<Vec<u8>>) -> Result<Block, DecodeError> {
+        let merkle_tree = MerkleTree::new(&transactions).map_err(|_| DecodeError::InvalidValue("block has no transactions"))?;
+        Ok(Block {
+            header,
+            transactions,
+            merkle_tree,
+            signature,
+        })
+    }
+}
+
+// The fields of a block as serialized, before the merkle tree is rebuilt
+#[derive(Deserialize)]
+#[serde(rename = "Block", deny_unknown_fields)]
+struct BlockFields {
+    header: BlockHeader,
+    signature: Option<Signature>,
+    transactions: Vec<Vec<u8>>,
+}
+
+impl TryFrom<BlockFields> for Block {
+    type Error = DecodeError;
+    
+    fn try_from(fields: BlockFields) -> Result<Self, DecodeError> {
+        Block::from_parts(fields.header, fields.signature, fields.transactions)
+    }
+}
+
+impl Serialize for Block {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        #[serde(rename = "Block")]
+        struct BlockRef<'a> {
+            header: &'a BlockHeader,
+            signature: &'a Option<Signature>,
+            transactions: &'a Vec<Vec<u8>>,
+        }
+        BlockRef {
+            header: &self.header,
+            signature: &self.signature,
+            transactions: &self.transactions,
+        }
+        .serialize(serializer)
+    }
+}
+
+// The fields of a header as serialized, before they are checked against each other
+#[derive(Deserialize)]
+#[serde(rename = "BlockHeader", deny_unknown_fields)]
+pub(crate) struct HeaderFields {
+    pub(crate) version: u32,
+    pub(crate) prev_block_hash: BlockHash,
+    pub(crate) merkle_root: Hash32,
+    pub(crate) state_root: Option<[u8; 32]>,
+    pub(crate) timestamp: u64,
+    pub(crate) bits: u32,
+    pub(crate) nonce: u64,
+}
+
+impl TryFrom<HeaderFields> for BlockHeader {
+    type Error = DecodeError;
+    
+    fn try_from(fields: HeaderFields) -> Result<Self, Self::Error> {
+        if fields.state_root.is_some() != (fields.version >= STATE_ROOT_VERSION) {
+            return Err(DecodeError::InvalidValue("state root for header version"));
+        }
+        Ok(BlockHeader {
+            version: fields.version,
+            prev_block_hash: fields.prev_block_hash,
+            merkle_root: fields.merkle_root,
+            state_root: fields.state_root,
+            timestamp: fields.timestamp,
+            bits: fields.bits,
+            nonce: fields.nonce,
+        })
+    }
+}
+
+// Unspent outputs as seen part way through a block: the outputs before it and those
+// created earlier in it
+struct BlockView<'a, V> {
+    base: &'a V,
+    created: &'a HashMap<OutPoint, TxOutput>,
+}
+
+impl<V: UtxoView> UtxoView for BlockView<'_, V> {
+    fn output(&self, out: &OutPoint) -> Option<TxOutput> {
+        self.created.get(out).cloned().or_else(|| self.base.output(out))
+    }
+}
+
+// A block's fields read from a stream, holding the bytes read to `max`
+struct StreamReader<'a, R> {
+    inner: &'a mut R,
+    read: usize,
+    max: usize,
+}
+
+impl<R: Read> StreamReader<'_, R> {
+    // Count `len` more bytes against the limit before reading them
+    fn reserve(&mut self, len: usize) -> Result<(), DecodeError> {
+        let total = self.read.saturating_add(len);
+        if total > self.max {
+            return Err(DecodeError::LimitExceeded {
+                what: "input size",
+                value: total as u64,
+                max: self.max as u64,
+            });
+        }
+        self.read = total;
+        Ok(())
+    }
+    
+    // The next `len` bytes, grown as they arrive rather than allocated up front, since `len`
+    // came from the untrusted stream
+    fn read_bytes(&mut self, len: usize) -> Result<Vec<u8>, BlockDecodeError> {
+        self.reserve(len)?;
+        let mut bytes = Vec::new();
+        (&mut *self.inner).take(len as u64).read_to_end(&mut bytes)?;
+        if bytes.len() < len {
+            return Err(DecodeError::UnexpectedEof.into());
+        }
+        Ok(bytes)
+    }
+    
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], BlockDecodeError> {
+        self.reserve(N)?;
+        let mut bytes = [0u8; N];
+        self.inner.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+    
+    fn read_u8(&mut self) -> Result<u8, BlockDecodeError> {
+        let [byte] = self.read_array()?;
+        Ok(byte)
+    }
+    
+    // A varint length or count, checked against `max`
+    fn read_len(&mut self, what: &'static str, max: usize) -> Result<usize, BlockDecodeError> {
+        let value = codec::read_varint_with(|| self.read_u8())?;
+        if value > max as u64 {
+            return Err(DecodeError::LimitExceeded {
+                what,
+                value,
+                max: max as u64,
+            }
+            .into());
+        }
+        Ok(value as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::codec::ChunkedReader;
+    use crate::crypto::ed25519::{SigningKey, VerifyingKey};
+    use crate::testing;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    fn block() -> Block {
+        Block::new(vec![b"tx1".to_vec(), b"tx2".to_vec()], BlockHash::ZERO).unwrap()
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let authority = SigningKey::from_bytes(&[1; 32]);
+        let stranger = SigningKey::from_bytes(&[2; 32]);
+
+        let mut block = block();
+        assert!(!block.verify_signature(&[authority.verifying_key()]));
+
+        block.sign(&authority);
+        assert!(block.signature().is_some());
+        assert!(block.verify_signature(&[authority.verifying_key()]));
+        assert!(block.verify_signature(&[stranger.verifying_key(), authority.verifying_key()]));
+        assert!(!block.verify_signature(&[stranger.verifying_key()]));
+        assert!(!block.verify_signature(&[] as &[VerifyingKey]));
+    }
+
+    #[test]
+    fn test_signature_covers_header() {
+        let authority = SigningKey::from_bytes(&[1; 32]);
+        let keys = [authority.verifying_key()];
+
+        let mut block = block();
+        block.sign(&authority);
+        block.header.timestamp += 1;
+        assert!(!block.verify_signature(&keys));
+
+        let mut block = self::block();
+        block.sign(&authority);
+        block.header.merkle_root.0[0] ^= 1;
+        assert!(!block.verify_signature(&keys));
+
+        let mut block = self::block();
+        block.sign(&authority);
+        block.header.nonce += 1;
+        assert!(!block.verify_signature(&keys));
+    }
+
+    #[test]
+    fn test_template_respects_limits() {
+        let limits = BlockLimits { max_bytes: 30, max_transactions: 4 };
+        let txs = vec![
+            vec![1; 8],
+            vec![2; 20], // would exceed the byte limit
+            vec![3; 8],
+            vec![4; 4],
+            vec![5; 1], // would exceed the count limit
+            vec![6; 1],
+        ];
+        
+        let template = Block::fill_template(BlockHash::ZERO, txs, &limits, b"coinbase".to_vec());
+        let block = &template.block;
+        let included = vec![b"coinbase".to_vec(), vec![1; 8], vec![3; 8], vec![4; 4]];
+        
+        assert_eq!(block.transactions(), included.as_slice());
+        assert_eq!(template.skipped, vec![vec![2; 20], vec![5; 1], vec![6; 1]]);
+        assert!(block.transactions().iter().map(Vec::len).sum::<usize>() <= limits.max_bytes);
+        assert_eq!(block.merkle_root(), MerkleTree::new(&included).unwrap().root_hash());
+        assert_eq!(block.nonce(), 0);
+        assert!(block.timestamp() > 0);
+    }
+
+    #[test]
+    fn test_template_always_includes_coinbase() {
+        let limits = BlockLimits { max_bytes: 4, max_transactions: 1 };
+        let block = Block::template(BlockHash::ZERO, vec![vec![1]], &limits, vec![9; 16]);
+        assert_eq!(block.transactions(), &[vec![9; 16]]);
+    }
+
+    #[test]
+    fn test_block_hash_hex_round_trip() {
+        let hash = block().hash();
+        let parsed: BlockHash = hash.to_string().parse().unwrap();
+        assert_eq!(parsed, hash);
+        assert_eq!(hash.to_string().len(), 64);
+        
+        let bad_digit = EncodingError::InvalidCharacter { position: 1, character: 'z' };
+        assert_eq!("0z".parse::<BlockHash>(), Err(BlockHashError::InvalidHex(bad_digit)));
+        assert_eq!(format!("0x{}", hash).parse::<BlockHash>(), Ok(hash));
+        assert_eq!("abcd".parse::<BlockHash>(), Err(BlockHashError::InvalidLength(2)));
+        assert_eq!(BlockHash::try_from(&[0u8; 31][..]), Err(BlockHashError::InvalidLength(31)));
+    }
+
+    #[test]
+    fn test_next_builder_links_chain() {
+        let mut chain = vec![block()];
+        for i in 0..5u8 {
+            let next = chain.last().unwrap().next_builder().transaction(vec![i]).build().unwrap();
+            chain.push(next);
+        }
+        
+        for pair in chain.windows(2) {
+            assert!(pair[1].verify_link(&pair[0]));
+            assert_eq!(pair[1].prev_block_hash(), pair[0].hash());
+        }
+        assert!(!chain[2].verify_link(&chain[0]));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_raw_prev_hash_shim() {
+        let parent = block();
+        let child = Block::new_from_raw_prev_hash(vec![b"tx".to_vec()], parent.hash().to_vec()).unwrap();
+        assert!(child.verify_link(&parent));
+        
+        let err = Block::new_from_raw_prev_hash(vec![b"tx".to_vec()], vec![0; 31]).unwrap_err();
+        assert_eq!(err, BlockError::InvalidPrevHash(BlockHashError::InvalidLength(31)));
+        assert_eq!(err.to_string(), "invalid previous block hash: block hash must be 32 bytes, got 31");
+    }
+
+    #[test]
+    fn test_block_needs_a_transaction() {
+        assert_eq!(Block::new(Vec::new(), BlockHash::ZERO), Err(BlockError::NoTransactions));
+        assert_eq!(BlockBuilder::new(BlockHash::ZERO).build(), Err(BlockError::NoTransactions));
+        assert_eq!(BlockError::NoTransactions.to_string(), "a block needs at least one transaction");
+    }
+
+    #[test]
+    fn test_validate_transactions() {
+        let block = Block::new(
+            vec![b"ok".to_vec(), b"bad!".to_vec(), b"fine".to_vec(), b"!!".to_vec()],
+            BlockHash::ZERO,
+        ).unwrap();
+        let reject_bang = |_: usize, tx: &[u8]| {
+            if tx.contains(&b'!') {
+                Err(format!("contains '!' ({} bytes)", tx.len()))
+            } else {
+                Ok(())
+            }
+        };
+        
+        let err = block.validate_transactions(reject_bang).unwrap_err();
+        assert_eq!(err.failures(), [TxFailure { index: 1, message: "contains '!' (4 bytes)".into() }]);
+        assert_eq!(err.index(), 1);
+        
+        let err = block.validate_all_transactions(reject_bang).unwrap_err();
+        let indexes: Vec<usize> = err.failures().iter().map(|failure| failure.index).collect();
+        assert_eq!(indexes, vec![1, 3]);
+        assert_eq!(err.to_string(), "transaction 1 is invalid: contains '!' (4 bytes) (and 1 more)");
+        
+        assert_eq!(block.validate_transactions(|_, _| Ok(())), Ok(()));
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut block = block();
+        assert_eq!(Block::from_bytes(&block.to_bytes(), &DecodeLimits::default()), Ok(block.clone()));
+        
+        block.sign(&SigningKey::from_bytes(&[1; 32]));
+        let decoded = Block::from_bytes(&block.to_bytes(), &DecodeLimits::default()).unwrap();
+        assert_eq!(decoded, block);
+        assert_eq!(decoded.hash(), block.hash());
+        
+        let header = block.header();
+        assert_eq!(BlockHeader::from_bytes(&header.to_bytes()).as_ref(), Ok(header));
+    }
+
+    proptest! {
+        #[test]
+        fn test_corrupted_streams_match_bytes(
+            mut block in testing::block(),
+            signed in any::<bool>(),
+            edits in vec((any::<Index>(), any::<u8>()), 1..4),
+            chunk in 1..9usize,
+        ) {
+            let limits = DecodeLimits::default();
+            if signed {
+                block.sign(&SigningKey::from_bytes(&[1; 32]));
+            }
+            let mut corrupt = block.to_bytes();
+            for (index, byte) in edits {
+                let index = index.index(corrupt.len());
+                corrupt[index] = byte;
+            }
+            
+            // The stream reads one block and leaves what follows
+            let streamed = Block::read_from(&mut ChunkedReader::new(&corrupt, chunk), &limits);
+            match Block::from_bytes(&corrupt, &limits) {
+                Err(DecodeError::TrailingBytes(n)) => {
+                    prop_assert_eq!(streamed.unwrap().to_bytes(), &corrupt[..corrupt.len() - n]);
+                }
+                expected => prop_assert_eq!(streamed, expected.map_err(BlockDecodeError::Decode)),
+            }
+        }
+        
+        #[test]
+        fn test_generated_blocks_round_trip(block in testing::block(), chunk in 1..64usize) {
+            let limits = DecodeLimits::default();
+            prop_assert!(block.verify_merkle_root());
+            prop_assert!(block.verify_pow(testing::DIFFICULTY));
+            
+            let bytes = block.to_bytes();
+            prop_assert_eq!(&Block::from_bytes(&bytes, &limits).unwrap(), &block);
+            prop_assert_eq!(&Block::read_from(&mut ChunkedReader::new(&bytes, chunk), &limits).unwrap(), &block);
+            let bincode = codec::to_bincode(&block).unwrap();
+            prop_assert_eq!(&codec::from_bincode::<Block>(&bincode, &limits).unwrap(), &block);
+            
+            let header = block.header();
+            prop_assert_eq!(&BlockHeader::from_bytes(&header.to_bytes()).unwrap(), header);
+            prop_assert_eq!(header.merkle_root_hash(), block.merkle_tree().root());
+        }
+        
+        #[test]
+        fn test_flipped_merkle_root_is_detected(block in testing::corrupt_merkle_block()) {
+            // The block still decodes, but its transactions no longer match its header
+            prop_assert!(!block.verify_merkle_root());
+            prop_assert_ne!(block.merkle_root_hash(), block.merkle_tree().root());
+            let decoded = Block::from_bytes(&block.to_bytes(), &DecodeLimits::default()).unwrap();
+            prop_assert!(!decoded.verify_merkle_root());
+        }
+    }
+
+    #[test]
+    fn test_streams_match_bytes() {
+        let limits = DecodeLimits::default();
+        let mut signed = block();
+        signed.sign(&SigningKey::from_bytes(&[1; 32]));
+        // Empty transactions and lengths past a one-byte varint, under a version 2 header
+        let large = BlockBuilder::new(BlockHash::ZERO)
+            .transactions((0..300u32).map(|i| vec![i as u8; i as usize]))
+            .timestamp(1_700_000_000)
+            .state_root([7; 32])
+            .build()
+            .unwrap();
+        
+        for block in [block(), signed.clone(), large] {
+            let bytes = block.to_bytes();
+            let mut written = Vec::new();
+            assert_eq!(block.write_to(&mut written).unwrap(), bytes.len());
+            assert_eq!(written, bytes);
+            for chunk in [1, 2, 3, 7, 64, bytes.len()] {
+                let decoded = Block::read_from(&mut ChunkedReader::new(&bytes, chunk), &limits).unwrap();
+                assert_eq!(decoded, block);
+                assert_eq!(decoded.merkle_tree(), block.merkle_tree());
+            }
+        }
+        
+        // Each read stops at the end of its block
+        let stream = [block().to_bytes(), signed.to_bytes()].concat();
+        let mut reader = ChunkedReader::new(&stream, 5);
+        assert_eq!(Block::read_from(&mut reader, &limits), Ok(block()));
+        assert_eq!(Block::read_from(&mut reader, &limits), Ok(signed));
+        assert_eq!(
+            Block::read_from(&mut reader, &limits),
+            Err(BlockDecodeError::Decode(DecodeError::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_streamed_errors_match_bytes() {
+        let limits = DecodeLimits::default();
+        let mut block = BlockBuilder::new(BlockHash::ZERO)
+            .transactions([b"one".to_vec(), Vec::new(), vec![9; 200]])
+            .timestamp(1_700_000_000)
+            .build()
+            .unwrap();
+        block.sign(&SigningKey::from_bytes(&[1; 32]));
+        let bytes = block.to_bytes();
+        let streamed = |bytes: &[u8], chunk: usize| Block::read_from(&mut ChunkedReader::new(bytes, chunk), &limits);
+        
+        for len in 0..bytes.len() {
+            assert_eq!(Block::from_bytes(&bytes[..len], &limits), Err(DecodeError::UnexpectedEof));
+            assert_eq!(streamed(&bytes[..len], 3), Err(BlockDecodeError::Decode(DecodeError::UnexpectedEof)));
+        }
+        
+        let small = DecodeLimits { max_decode_bytes: 16, ..limits };
+        assert!(matches!(
+            Block::read_from(&mut bytes.as_slice(), &small),
+            Err(BlockDecodeError::Decode(DecodeError::LimitExceeded { what: "input size", .. }))
+        ));
+    }
+
+    #[test]
+    fn test_state_root_is_version_gated() {
+        let v1 = block();
+        assert_eq!(v1.header().state_root(), None);
+        assert_eq!(v1.header().to_bytes().len(), BlockHeader::ENCODED_LEN);
+        
+        let v2 = BlockBuilder::new(BlockHash::ZERO).transaction(b"tx".to_vec()).timestamp(0).state_root([7; 32]).build().unwrap();
+        let header = v2.header();
+        assert_eq!(header.version(), STATE_ROOT_VERSION);
+        assert_eq!(header.state_root(), Some(&[7; 32]));
+        assert_eq!(header.to_bytes().len(), BlockHeader::MAX_ENCODED_LEN);
+        assert_eq!(BlockHeader::from_bytes(&header.to_bytes()).as_ref(), Ok(header));
+        assert_eq!(Block::from_bytes(&v2.to_bytes(), &DecodeLimits::default()), Ok(v2.clone()));
+        
+        // The root is committed to by the hash, and a later version always carries one
+        let other = BlockBuilder::new(BlockHash::ZERO).transaction(b"tx".to_vec()).timestamp(0).state_root([8; 32]).build().unwrap();
+        assert_ne!(other.hash(), v2.hash());
+        let bare = BlockBuilder::new(BlockHash::ZERO).transaction(b"tx".to_vec()).version(3).build().unwrap();
+        assert_eq!(bare.header().state_root(), Some(&[0; 32]));
+        
+        // A version 1 encoding followed by a root leaves trailing bytes
+        let mut v1_bytes = v1.header().to_bytes();
+        v1_bytes.extend_from_slice(&[7; 32]);
+        assert_eq!(BlockHeader::from_bytes(&v1_bytes), Err(DecodeError::TrailingBytes(32)));
+        assert_eq!(BlockHeader::from_bytes(&header.to_bytes()[..BlockHeader::ENCODED_LEN]), Err(DecodeError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_hostile_inputs_are_rejected() {
+        use crate::merkle_trie::MerkleProof;
+        
+        let limits = DecodeLimits::default();
+        let header = block().header().to_bytes();
+        let with_header = |tail: &[u8]| [header.as_slice(), tail].concat();
+        let varint = |value: u64| {
+            let mut buf = Vec::new();
+            codec::write_varint(&mut buf, value);
+            buf
+        };
+        
+        let corpus: Vec<Vec<u8>> = vec![
+            vec![],
+            header[..40].to_vec(),
+            with_header(&[]),
+            with_header(&[2]),
+            // No transactions
+            with_header(&[0, 0]),
+            // Transaction count of u64::MAX
+            with_header(&[[0].as_slice(), &varint(u64::MAX)].concat()),
+            // A plausible count with nothing behind it
+            with_header(&[[0].as_slice(), &varint(100_000)].concat()),
+            // Transaction length of u64::MAX
+            with_header(&[[0].as_slice(), &varint(1), &varint(u64::MAX)].concat()),
+            // Huge count followed by huge lengths
+            with_header(&[[0].as_slice(), &varint(99_999), &varint(1 << 20), &varint(1 << 20)].concat()),
+            // Zero-length transaction then trailing garbage
+            with_header(&[0, 1, 0, 0xff]),
+            // Non-canonical varint count
+            with_header(&[0, 0x81, 0x00]),
+        ];
+        for input in &corpus {
+            assert!(Block::from_bytes(input, &limits).is_err(), "accepted {:?}", input);
+        }
+        
+        let small = DecodeLimits { max_decode_bytes: 16, ..limits };
+        assert!(matches!(
+            Block::from_bytes(&block().to_bytes(), &small),
+            Err(DecodeError::LimitExceeded { what: "input size", .. })
+        ));
+        
+        let proof_corpus: Vec<Vec<u8>> = vec![
+            vec![],
+            vec![0; 31],
+            [vec![0; 64], varint(u64::MAX)].concat(),
+            [vec![0; 64], varint(64)].concat(),
+            [vec![0; 64], varint(1), vec![1]].concat(),
+            [vec![0; 64], varint(1), vec![7], vec![0; 32]].concat(),
+        ];
+        for input in &proof_corpus {
+            assert!(MerkleProof::from_bytes(input, &limits).is_err(), "accepted {:?}", input);
+        }
+    }
+
+    #[test]
+    fn test_check_no_duplicate_inputs() {
+        use crate::transaction::TxInput;
+        
+        let spend = |txid: u8, index: u32, lock_time: u32| Transaction {
+            inputs: vec![TxInput {
+                prev_out: OutPoint { txid: Txid::from_bytes([txid; 32]), index },
+                signatures: vec![],
+                public_key: None,
+                recovery_id: None,
+            }],
+            outputs: vec![TxOutput::to_address(1, Address::from_bytes([0; 32]))],
+            lock_time,
+        };
+        let coinbase = Transaction::default();
+        let ok = BlockBuilder::new(BlockHash::ZERO)
+            .transactions([coinbase.clone(), spend(1, 0, 0), spend(1, 1, 0), spend(2, 0, 0)])
+            .build()
+            .unwrap();
+        assert_eq!(ok.check_no_duplicate_inputs(), Ok(()));
+        
+        // The second and fourth transactions spend the same outpoint
+        let conflicting = BlockBuilder::new(BlockHash::ZERO)
+            .transactions([coinbase, spend(1, 0, 0), spend(2, 0, 0), spend(1, 0, 1)])
+            .build()
+            .unwrap();
+        let err = conflicting.check_no_duplicate_inputs().unwrap_err();
+        assert_eq!(err.failures().len(), 1);
+        assert_eq!(err.index(), 3);
+        assert!(err.message().ends_with("already spent by transaction 1"));
+        
+        assert_eq!(block().check_no_duplicate_inputs().unwrap_err().failures().len(), 2);
+    }
+    
+    #[test]
+    fn test_total_fees() {
+        use crate::transaction::TxInput;
+        use crate::utxo::UtxoSet;
+        
+        let pay = |inputs: &[OutPoint], amounts: &[u64]| Transaction {
+            inputs: inputs.iter().map(|&prev_out| TxInput { prev_out, signatures: vec![], public_key: None, recovery_id: None }).collect(),
+            outputs: amounts.iter().map(|&amount| TxOutput::to_address(amount, Address::from_bytes([0; 32]))).collect(),
+            lock_time: 0,
+        };
+        let out = |tx: &Transaction, index: u32| OutPoint { txid: tx.txid(), index };
+        let funding = pay(&[], &[30, 20, 50]);
+        let mut utxos = UtxoSet::new();
+        utxos.apply_block(&Block::new(vec![funding.encode()], BlockHash::ZERO).unwrap()).unwrap();
+        
+        // Two inputs worth 50 paying out 45
+        let multi = pay(&[out(&funding, 0), out(&funding, 1)], &[40, 5]);
+        assert_eq!(multi.fee(&utxos), Ok(5));
+        let negative = pay(&[out(&funding, 2)], &[30, 21]);
+        assert_eq!(
+            negative.fee(&utxos),
+            Err(FeeError::NegativeFee { txid: negative.txid(), spent: 50, paid: 51 })
+        );
+        let missing = pay(&[out(&multi, 0)], &[1]);
+        assert_eq!(missing.fee(&utxos), Err(FeeError::MissingInput(out(&multi, 0))));
+        assert_eq!(pay(&[], &[u64::MAX, 1]).value_out(), Err(FeeError::AmountOverflow));
+        
+        // The block spends an output it creates, for fees of 5 + 2 + 1
+        let chained = pay(&[out(&multi, 0)], &[38]);
+        let third = pay(&[out(&funding, 2)], &[49]);
+        let block = |coinbase: u64| {
+            Block::new(
+                vec![pay(&[], &[coinbase]).encode(), multi.encode(), chained.encode(), third.encode()],
+                BlockHash::ZERO,
+            ).unwrap()
+        };
+        assert_eq!(block(100 + 8).total_fees(&utxos, 100), Ok(8));
+        assert_eq!(block(90).total_fees(&utxos, 100), Ok(8));
+        assert_eq!(
+            block(100 + 9).total_fees(&utxos, 100),
+            Err(FeeError::CoinbaseOverpays { paid: 109, allowed: 108 })
+        );
+        let overspending = Block::new(vec![pay(&[], &[1]).encode(), negative.encode()], BlockHash::ZERO).unwrap();
+        assert!(matches!(overspending.total_fees(&utxos, 100), Err(FeeError::NegativeFee { .. })));
+    }
+    
+    #[test]
+    fn test_verify_signatures_batch() {
+        use crate::transaction::TxInput;
+        
+        let keys: Vec<SigningKey> = (1..=5u8).map(|seed| SigningKey::from_bytes(&[seed; 32])).collect();
+        let mut owners = HashMap::new();
+        let mut txs = vec![Transaction { lock_time: 1, ..Transaction::default() }];
+        for i in 0..50u32 {
+            // Two inputs each, signed by different keys
+            let prev_outs = [0, 1].map(|index| OutPoint { txid: Txid::of(&i.to_le_bytes()), index });
+            let mut tx = Transaction {
+                inputs: prev_outs.iter().map(|&prev_out| TxInput { prev_out, signatures: vec![], public_key: None, recovery_id: None }).collect(),
+                outputs: vec![TxOutput::to_address(i as u64 + 1, Address::from_bytes([0; 32]))],
+                lock_time: 0,
+            };
+            for (input, prev_out) in prev_outs.into_iter().enumerate() {
+                let key = &keys[(i as usize + input) % keys.len()];
+                owners.insert(prev_out, key.public_key());
+                tx.sign_input(input, key);
+            }
+            txs.push(tx);
+        }
+        let resolver = |input: &TxInput| owners.get(&input.prev_out).copied();
+        let block = |txs: &[Transaction]| BlockBuilder::new(BlockHash::ZERO).transactions(txs.iter().cloned()).build().unwrap();
+        assert_eq!(block(&txs).verify_signatures_batch(resolver), Ok(()));
+        
+        // A signature over a different sighash fails and is named
+        let mut bad = txs.clone();
+        let key = keys.iter().find(|key| Some(key.public_key()) == resolver(&bad[37].inputs[1])).unwrap();
+        bad[37].inputs[1].signatures = vec![key.sign_message(&bad[37].sighash(0))];
+        assert_eq!(block(&bad).verify_signatures_batch(resolver), Err(SigError::InvalidSignature { index: 37, input: 1 }));
+        
+        let mut bad = txs.clone();
+        let mut bytes = bad[12].inputs[0].signatures[0].to_bytes();
+        bytes[3] ^= 1;
+        bad[12].inputs[0].signatures = vec![Signature::from_bytes(SignatureScheme::Ed25519, &bytes)];
+        assert_eq!(block(&bad).verify_signatures_batch(resolver), Err(SigError::InvalidSignature { index: 12, input: 0 }));
+        
+        // Changing an output invalidates every signature on the transaction
+        let mut bad = txs.clone();
+        bad[5].outputs[0].amount += 1;
+        assert_eq!(block(&bad).verify_signatures_batch(resolver), Err(SigError::InvalidSignature { index: 5, input: 0 }));
+        
+        let mut bad = txs.clone();
+        bad[8].inputs[1].signatures.clear();
+        assert_eq!(block(&bad).verify_signatures_batch(resolver), Err(SigError::MissingSignature { index: 8, input: 1 }));
+        assert_eq!(block(&txs).verify_signatures_batch(|_| None), Err(SigError::UnknownKey { index: 1, input: 0 }));
+    }
+    
+    #[test]
+    fn test_mixed_signature_schemes() {
+        use crate::crypto::secp256k1;
+        use crate::transaction::TxInput;
+        
+        let ed = SigningKey::from_bytes(&[1; 32]);
+        let secp = secp256k1::SigningKey::from_bytes(&[2; 32]).unwrap();
+        let prev_outs = [1u8, 2, 3].map(|n| OutPoint { txid: Txid::from_bytes([n; 32]), index: 0 });
+        let owners = HashMap::from([
+            (prev_outs[0], ed.public_key()),
+            (prev_outs[1], secp.public_key()),
+            (prev_outs[2], secp.public_key()),
+        ]);
+        let resolver = |input: &TxInput| owners.get(&input.prev_out).copied();
+        let spend = |outs: &[OutPoint]| Transaction {
+            inputs: outs.iter().map(|&prev_out| TxInput { prev_out, signatures: vec![], public_key: None, recovery_id: None }).collect(),
+            outputs: vec![TxOutput::to_address(1, Address::from_bytes([0; 32]))],
+            lock_time: 0,
+        };
+        let block = |txs: &[&Transaction]| {
+            BlockBuilder::new(BlockHash::ZERO)
+                .transaction(Transaction::default())
+                .transactions(txs.iter().map(|&tx| tx.clone()))
+                .build()
+                .unwrap()
+        };
+        
+        let mut both = spend(&prev_outs[..2]);
+        both.sign_input(0, &ed);
+        both.sign_input(1, &secp);
+        let mut secp_only = spend(&prev_outs[2..]);
+        secp_only.sign_input(0, &secp);
+        let mixed = block(&[&both, &secp_only]);
+        assert_eq!(mixed.verify_signatures_batch(resolver), Ok(()));
+        let decoded = Block::from_bytes(&mixed.to_bytes(), &DecodeLimits::default()).unwrap();
+        assert_eq!(decoded.verify_signatures_batch(resolver), Ok(()));
+        
+        // A signature of the other scheme than its key is refused
+        let mut wrong_scheme = both.clone();
+        wrong_scheme.sign_input(1, &ed);
+        assert_eq!(
+            block(&[&secp_only, &wrong_scheme]).verify_signatures_batch(resolver),
+            Err(SigError::InvalidSignature { index: 2, input: 1 })
+        );
+        let mut retagged = both.clone();
+        let bytes = retagged.inputs[0].signatures[0].to_bytes();
+        retagged.inputs[0].signatures = vec![Signature::from_bytes(SignatureScheme::Secp256k1, &bytes)];
+        assert_eq!(block(&[&retagged]).verify_signatures_batch(resolver), Err(SigError::InvalidSignature { index: 1, input: 0 }));
+        
+        // Authorities may sign with either scheme
+        let mut sealed = mixed.clone();
+        sealed.sign(&secp);
+        assert!(sealed.verify_signature(&[ed.public_key(), secp.public_key()]));
+        assert!(!sealed.verify_signature(&[ed.public_key()]));
+        let decoded = Block::from_bytes(&sealed.to_bytes(), &DecodeLimits::default()).unwrap();
+        assert_eq!(decoded.signature(), sealed.signature());
+        assert!(decoded.verify_signature(&[secp.verifying_key()]));
+    }
+    
+    #[test]
+    fn test_verify_spends() {
+        use crate::crypto::secp256k1;
+        use crate::transaction::TxInput;
+        
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let bob = secp256k1::SigningKey::from_bytes(&[2; 32]).unwrap();
+        let mallory = SigningKey::from_bytes(&[3; 32]);
+        let pay = |key: &dyn Signer, amount| TxOutput::to_address(amount, Address::from_public_key(&key.public_key()));
+        let funding = OutPoint { txid: Txid::from_bytes([1; 32]), index: 0 };
+        let utxos = HashMap::from([(funding, pay(&alice, 10))]);
+        
+        // Alice pays Bob, who spends it on in the same block
+        let mut to_bob = Transaction {
+            inputs: vec![TxInput { prev_out: funding, signatures: vec![], public_key: None, recovery_id: None }],
+            outputs: vec![pay(&bob, 9)],
+            lock_time: 0,
+        };
+        to_bob.sign_input(0, &alice);
+        let mut onward = Transaction {
+            inputs: vec![TxInput { prev_out: OutPoint { txid: to_bob.txid(), index: 0 }, signatures: vec![], public_key: None, recovery_id: None }],
+            outputs: vec![pay(&alice, 8)],
+            lock_time: 0,
+        };
+        onward.sign_input(0, &bob);
+        let block = |txs: &[&Transaction]| {
+            BlockBuilder::new(BlockHash::ZERO)
+                .transaction(Transaction::default())
+                .transactions(txs.iter().map(|&tx| tx.clone()))
+                .build()
+                .unwrap()
+        };
+        assert_eq!(block(&[&to_bob, &onward]).verify_spends(&utxos), Ok(()));
+        
+        // A valid signature by a key other than the one the output pays to is refused
+        let mut stolen = to_bob.clone();
+        stolen.sign_input(0, &mallory);
+        assert_eq!(block(&[&stolen]).verify_signatures_batch(|input| input.public_key), Ok(()));
+        assert_eq!(block(&[&stolen]).verify_spends(&utxos), Err(SigError::KeyMismatch { index: 1, input: 0 }));
+        
+        // The right key with a signature by another is refused too
+        let mut forged = to_bob.clone();
+        forged.inputs[0].signatures = stolen.inputs[0].signatures.clone();
+        assert_eq!(block(&[&forged]).verify_spends(&utxos), Err(SigError::InvalidSignature { index: 1, input: 0 }));
+        
+        let mut hidden = to_bob.clone();
+        hidden.inputs[0].public_key = None;
+        assert_eq!(block(&[&hidden]).verify_spends(&utxos), Err(SigError::UnknownKey { index: 1, input: 0 }));
+        // Bob's output does not exist before the transaction creating it
+        assert_eq!(block(&[&onward, &to_bob]).verify_spends(&utxos), Err(SigError::UnknownKey { index: 1, input: 0 }));
+        
+        let mut doubled = to_bob.clone();
+        let signature = doubled.inputs[0].signatures[0];
+        doubled.inputs[0].signatures.push(signature);
+        assert_eq!(block(&[&doubled]).verify_spends(&utxos), Err(SigError::ExtraSignatures { index: 1, input: 0 }));
+    }
+    
+    #[test]
+    fn test_verify_multisig_spends() {
+        use crate::crypto::secp256k1;
+        use crate::transaction::{SpendCondition, TxInput};
+        
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let bob = secp256k1::SigningKey::from_bytes(&[2; 32]).unwrap();
+        let carol = SigningKey::from_bytes(&[3; 32]);
+        let signers: [&dyn Signer; 3] = [&alice, &bob, &carol];
+        let condition = SpendCondition::MultiSig { m: 2, keys: signers.map(|key| key.public_key()).to_vec() };
+        let funding = OutPoint { txid: Txid::from_bytes([1; 32]), index: 0 };
+        let utxos = HashMap::from([(funding, TxOutput { amount: 10, condition })]);
+        let spend = Transaction {
+            inputs: vec![TxInput { prev_out: funding, signatures: vec![], public_key: None, recovery_id: None }],
+            outputs: vec![TxOutput::to_address(9, Address::from_public_key(&alice.public_key()))],
+            lock_time: 0,
+        };
+        let block = |tx: &Transaction| BlockBuilder::new(BlockHash::ZERO).transaction(Transaction::default()).transaction(tx.clone()).build().unwrap();
+        
+        // Any two of the three keys may spend, signing in either order
+        for (first, second) in [(0, 1), (0, 2), (1, 2), (2, 0)] {
+            let mut tx = spend.clone();
+            tx.add_multisig_signature(0, signers[first]);
+            tx.add_multisig_signature(0, signers[second]);
+            assert_eq!(block(&tx).verify_spends(&utxos), Ok(()), "signed by {} and {}", first, second);
+        }
+        let mut all = spend.clone();
+        signers.iter().for_each(|&key| all.add_multisig_signature(0, key));
+        assert_eq!(block(&all).verify_spends(&utxos), Ok(()));
+        
+        let mut one = spend.clone();
+        one.add_multisig_signature(0, &bob);
+        assert_eq!(
+            block(&one).verify_spends(&utxos),
+            Err(SigError::NotEnoughSignatures { index: 1, input: 0, valid: 1, required: 2 })
+        );
+        // The same key's signature twice counts once
+        let mut repeated = one.clone();
+        repeated.add_multisig_signature(0, &bob);
+        assert_eq!(
+            block(&repeated).verify_spends(&utxos),
+            Err(SigError::NotEnoughSignatures { index: 1, input: 0, valid: 1, required: 2 })
+        );
+        // Beyond the threshold, a signature that counts for nothing is refused
+        let mut padded = all.clone();
+        let signature = padded.inputs[0].signatures[1];
+        padded.inputs[0].signatures.push(signature);
+        assert_eq!(block(&padded).verify_spends(&utxos), Err(SigError::InvalidSignature { index: 1, input: 0 }));
+        let mut stranger = one.clone();
+        stranger.add_multisig_signature(0, &SigningKey::from_bytes(&[4; 32]));
+        assert!(matches!(block(&stranger).verify_spends(&utxos), Err(SigError::NotEnoughSignatures { valid: 1, .. })));
+        // Multisig inputs reveal no key
+        let mut revealed = all.clone();
+        revealed.inputs[0].public_key = Some(alice.public_key());
+        assert_eq!(block(&revealed).verify_spends(&utxos), Err(SigError::KeyMismatch { index: 1, input: 0 }));
+    }
+        
+    #[test]
+    fn test_verify_recovered_key_spends() {
+        use crate::crypto::secp256k1::{self, RecoveryId};
+        use crate::transaction::TxInput;
+        
+        let alice = secp256k1::SigningKey::from_bytes(&[1; 32]).unwrap();
+        let funding = OutPoint { txid: Txid::from_bytes([1; 32]), index: 0 };
+        let utxos = HashMap::from([(funding, TxOutput::to_address(10, Address::from_public_key(&alice.public_key())))]);
+        let mut tx = Transaction {
+            inputs: vec![TxInput { prev_out: funding, signatures: vec![], public_key: None, recovery_id: None }],
+            outputs: vec![TxOutput::to_address(9, Address::from_bytes([2; 32]))],
+            lock_time: 0,
+        };
+        let block = |tx: &Transaction| BlockBuilder::new(BlockHash::ZERO).transaction(Transaction::default()).transaction(tx.clone()).build().unwrap();
+        
+        // The key is left out and recovered from the signature
+        tx.sign_input_recoverable(0, &alice);
+        assert_eq!(tx.inputs[0].public_key, None);
+        assert_eq!(tx.inputs[0].signing_key(&tx.sighash(0)), Some(alice.public_key()));
+        assert_eq!(block(&tx).verify_spends(&utxos), Ok(()));
+        let mut revealed = tx.clone();
+        revealed.sign_input(0, &alice);
+        assert!(tx.encode().len() < revealed.encode().len());
+        
+        // The wrong recovery id recovers a key the output does not pay
+        let mut wrong_id = tx.clone();
+        let id = wrong_id.inputs[0].recovery_id.unwrap();
+        wrong_id.inputs[0].recovery_id = RecoveryId::from_u8(id.to_u8() ^ 1);
+        assert_eq!(block(&wrong_id).verify_spends(&utxos), Err(SigError::KeyMismatch { index: 1, input: 0 }));
+        // As does a signature over another transaction
+        let mut changed = tx.clone();
+        changed.outputs[0].amount = 8;
+        assert_eq!(block(&changed).verify_spends(&utxos), Err(SigError::KeyMismatch { index: 1, input: 0 }));
+        // Only secp256k1 signatures recover a key
+        let mut ed = tx.clone();
+        ed.inputs[0].signatures = vec![SigningKey::from_bytes(&[1; 32]).sign_message(&tx.sighash(0))];
+        assert_eq!(block(&ed).verify_spends(&utxos), Err(SigError::UnknownKey { index: 1, input: 0 }));
+    }
+        
+    #[test]
+    fn test_mine_meets_difficulty() {
+        let mut block = block();
+        let mut rounds = block.clone();
+        block.mine(Difficulty::LeadingZeroBits(8));
+        // Mining a few nonces at a time finds the same one
+        assert!(!rounds.mine_up_to(Difficulty::LeadingZeroBits(8), 0));
+        while !rounds.mine_up_to(Difficulty::LeadingZeroBits(8), 16) {}
+        assert_eq!(rounds, block);
+        assert!(block.verify_pow(Difficulty::LeadingZeroBits(8)));
+        assert!(block.verify_pow(Difficulty::CompactTarget(Difficulty::LeadingZeroBits(8).to_compact())));
+        assert_eq!(block.hash().as_bytes()[0], 0);
+        assert!(block.verify_merkle_root());
+    }
 }
@@ -0,0 +1,311 @@
+//! Canonical JSON for block headers, transactions and transaction bundles,
+//! so integrators can sign or hash a JSON form and get the same bytes from
+//! every writer.
+//!
+//! The canonical form is compact JSON, with no whitespace between tokens,
+//! where the keys of every object are sorted by their bytes. The keys here
+//! are all ASCII, so this is also the order of RFC 8785. Hashes, keys,
+//! signatures and raw transactions are lowercase hex strings, integers are
+//! written in decimal without a fraction or exponent, and a value that may
+//! be absent is `null`. Integers are written exactly even above 2^53, so a
+//! reader that parses numbers as doubles cannot be trusted to re-serialize
+//! them.
+//!
+//! The objects have the fields of the types' serde forms, under the same
+//! names:
+//!
+//! - [`BlockHeader`]: `version`, `prev_block_hash`, `merkle_root`,
+//!   `state_root`, `timestamp`, `bits` and `nonce`
+//! - [`Transaction`]: `inputs`, `outputs` and `lock_time`
+//! - an input: `prev_out`, an object of `txid` and `index`, `signatures`,
+//!   `public_key` and `recovery_id`
+//! - an output: `amount` and `condition`, which is `{"SingleKey": address}`
+//!   or `{"MultiSig": {"keys": [...], "m": m}}`
+//! - a signature or public key: an object of its scheme's name, `Ed25519`
+//!   or `Secp256k1`, to its hex
+//! - [`MerkleProof`]: `leaf_hash`, `root_hash` and `proof`, the siblings
+//!   from the leaf up as `[hash, is_right]` pairs
+//! - [`TxWithProof`]: `tx`, `block_header`, `height` and `proof`
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::block::BlockHeader;
+use crate::chain::TxWithProof;
+use crate::crypto::{PublicKey, Signature};
+use crate::merkle_trie::MerkleProof;
+use crate::transaction::{SpendCondition, Transaction};
+
+/// A type with a canonical JSON form
+pub trait CanonicalJson {
+    /// The value as JSON, its objects' keys in any order
+    fn to_json_value(&self) -> Value;
+
+    /// The canonical JSON text
+    fn to_canonical_json(&self) -> String {
+        canonical(self.to_json_value()).to_string()
+    }
+
+    /// SHA-256 of the UTF-8 bytes of [`CanonicalJson::to_canonical_json`]
+    fn canonical_json_hash(&self) -> [u8; 32] {
+        Sha256::digest(self.to_canonical_json()).into()
+    }
+}
+
+impl CanonicalJson for BlockHeader {
+    fn to_json_value(&self) -> Value {
+        json!({
+            "version": self.version(),
+            "prev_block_hash": hex::encode(self.prev_block_hash().as_bytes()),
+            "merkle_root": hex::encode(self.merkle_root()),
+            "state_root": self.state_root().map(hex::encode),
+            "timestamp": self.timestamp(),
+            "bits": self.bits(),
+            "nonce": self.nonce(),
+        })
+    }
+}
+
+impl CanonicalJson for Transaction {
+    fn to_json_value(&self) -> Value {
+        let inputs: Vec<Value> = self
+            .inputs
+            .iter()
+            .map(|input| {
+                json!({
+                    "prev_out": {
+                        "txid": hex::encode(input.prev_out.txid),
+                        "index": input.prev_out.index,
+                    },
+                    "signatures": input.signatures.iter().map(signature).collect::<Vec<_>>(),
+                    "public_key": input.public_key.as_ref().map(public_key),
+                    "recovery_id": input.recovery_id.map(|id| id.to_u8()),
+                })
+            })
+            .collect();
+        let outputs: Vec<Value> = self
+            .outputs
+            .iter()
+            .map(|output| {
+                let condition = match &output.condition {
+                    SpendCondition::SingleKey(address) => {
+                        json!({ "SingleKey": hex::encode(address.as_bytes()) })
+                    }
+                    SpendCondition::MultiSig { m, keys } => json!({
+                        "MultiSig": {
+                            "m": m,
+                            "keys": keys.iter().map(public_key).collect::<Vec<_>>(),
+                        },
+                    }),
+                };
+                json!({
+                    "amount": output.amount,
+                    "condition": condition,
+                })
+            })
+            .collect();
+        json!({
+            "inputs": inputs,
+            "outputs": outputs,
+            "lock_time": self.lock_time,
+        })
+    }
+}
+
+impl CanonicalJson for MerkleProof {
+    fn to_json_value(&self) -> Value {
+        let siblings: Vec<Value> = self
+            .siblings()
+            .iter()
+            .map(|(sibling, is_right)| json!([hex::encode(sibling), is_right]))
+            .collect();
+        json!({
+            "proof": siblings,
+            "leaf_hash": hex::encode(self.leaf_hash()),
+            "root_hash": hex::encode(self.root_hash()),
+        })
+    }
+}
+
+impl CanonicalJson for TxWithProof {
+    fn to_json_value(&self) -> Value {
+        json!({
+            "tx": hex::encode(&self.tx),
+            "block_header": self.block_header.to_json_value(),
+            "height": self.height,
+            "proof": self.proof.to_json_value(),
+        })
+    }
+}
+
+/// `value` with the keys of every object in it sorted by their bytes,
+/// whatever order the objects kept them in
+pub fn canonical(value: Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.into_iter().map(canonical).collect()),
+        Value::Object(fields) => {
+            let mut fields: Vec<(String, Value)> = fields
+                .into_iter()
+                .map(|(key, value)| (key, canonical(value)))
+                .collect();
+            fields.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Object(fields.into_iter().collect())
+        }
+        value => value,
+    }
+}
+
+fn signature(signature: &Signature) -> Value {
+    let scheme = match signature {
+        Signature::Ed25519(_) => "Ed25519",
+        Signature::Secp256k1(_) => "Secp256k1",
+    };
+    json!({ (scheme): hex::encode(signature.to_bytes()) })
+}
+
+fn public_key(key: &PublicKey) -> Value {
+    let scheme = match key {
+        PublicKey::Ed25519(_) => "Ed25519",
+        PublicKey::Secp256k1(_) => "Secp256k1",
+    };
+    json!({ (scheme): hex::encode(key.as_bytes()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    use super::*;
+    use crate::block::{BlockBuilder, BlockHash};
+    use crate::difficulty::Difficulty;
+    use crate::test_vectors::SerdeFixtures;
+
+    fn bundle() -> TxWithProof {
+        let block = BlockBuilder::new(BlockHash::from_bytes([0; 32]))
+            .transactions([b"first".to_vec(), b"second".to_vec()])
+            .timestamp(1_700_000_000)
+            .difficulty(Difficulty::LeadingZeroBits(8))
+            .nonce(42)
+            .build()
+            .unwrap();
+        TxWithProof {
+            tx: b"second".to_vec(),
+            block_header: block.header().clone(),
+            height: 7,
+            proof: block.merkle_tree().generate_proof(1).unwrap(),
+        }
+    }
+
+    /// Check `value` survives a serde_json round trip, and its canonical
+    /// text survives reformatting by serde_json, with the canonical bytes
+    /// unchanged
+    fn check_round_trip<T: CanonicalJson + Serialize + DeserializeOwned>(value: &T) {
+        let expected = value.to_canonical_json();
+        let decoded: T = serde_json::from_str(&serde_json::to_string(value).unwrap()).unwrap();
+        assert_eq!(decoded.to_canonical_json(), expected);
+
+        let reformatted: Value = serde_json::from_str(&expected).unwrap();
+        let reformatted = serde_json::to_string_pretty(&reformatted).unwrap();
+        assert_ne!(reformatted, expected);
+        let reparsed: Value = serde_json::from_str(&reformatted).unwrap();
+        assert_eq!(canonical(reparsed).to_string(), expected);
+    }
+
+    #[test]
+    fn test_canonical_json_pinned() {
+        let bundle = bundle();
+        let header = concat!(
+            r#"{"bits":536936448,"merkle_root":""#,
+            "2f3416032c534e1b3cd58f0e0528c5a8f57dea586b788b040c4fab0d37055d28",
+            r#"","nonce":42,"prev_block_hash":""#,
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            r#"","state_root":null,"timestamp":1700000000,"version":1}"#,
+        );
+        assert_eq!(bundle.block_header.to_canonical_json(), header);
+        assert_eq!(
+            bundle.to_canonical_json(),
+            format!(
+                concat!(
+                    r#"{{"block_header":{},"height":7,"proof":{{"leaf_hash":""#,
+                    "16367aacb67a4a017c8da8ab95682ccb390863780f7114dda0a0e0c55644c7c4",
+                    r#"","proof":[[""#,
+                    "a7937b64b8caa58f03721bb6bacf5c78cb235febe0e70b1b84cd99541461a08e",
+                    r#"",false]],"root_hash":""#,
+                    "2f3416032c534e1b3cd58f0e0528c5a8f57dea586b788b040c4fab0d37055d28",
+                    r#""}},"tx":"7365636f6e64"}}"#,
+                ),
+                header
+            )
+        );
+        assert_eq!(
+            hex::encode(bundle.canonical_json_hash()),
+            "b9fead3d146075e0022a79b19a8e335f88a5b18d9f82969b8b0e49221c8f4548"
+        );
+
+        assert_eq!(
+            Transaction::default().to_canonical_json(),
+            r#"{"inputs":[],"lock_time":0,"outputs":[]}"#
+        );
+        let tx = SerdeFixtures::new().transaction;
+        let ed25519_key = "8a88e3dd7409f195fd52db2d3cba5d72ca6709bf1d94121bf3748801b40f6f5c";
+        let funding = "2514e1475addffb378fdb07e9a1092176c09dbfbd129ebcaacd0099818d2534c";
+        assert_eq!(
+            tx.to_canonical_json(),
+            format!(
+                concat!(
+                    r#"{{"inputs":["#,
+                    r#"{{"prev_out":{{"index":0,"txid":"{funding}"}},"#,
+                    r#""public_key":{{"Ed25519":"{key}"}},"recovery_id":null,"#,
+                    r#""signatures":[{{"Ed25519":""#,
+                    "e0f070a7d805216a70e56bb49a515470f3c2f7e2652884cb58ffdb4f50c545c0",
+                    "23fb3e26f5e6004040964320eef4530255aebf87ecbb7a1de12c310186bce10f",
+                    r#""}}]}},"#,
+                    r#"{{"prev_out":{{"index":1,"txid":"{funding}"}},"#,
+                    r#""public_key":null,"recovery_id":0,"#,
+                    r#""signatures":[{{"Secp256k1":""#,
+                    "e9a9e0938fabff87285064d203f0f0f6dffb6b3aec22760f60af7fd398cbccc5",
+                    "2202eeaafc48183ff9c674303c4f9417c613e05c5d0f7a8bb2bb27cf0c0706b9",
+                    r#""}}]}}],"#,
+                    r#""lock_time":100,"outputs":["#,
+                    r#"{{"amount":5000,"condition":{{"SingleKey":""#,
+                    "34750f98bd59fcfc946da45aaabe933be154a4b5094e1c4abf42866505f3c97e",
+                    r#""}}}},"#,
+                    r#"{{"amount":12345,"condition":{{"MultiSig":{{"keys":["#,
+                    r#"{{"Ed25519":"{key}"}},{{"Secp256k1":""#,
+                    "024d4b6cd1361032ca9bd2aeb9d900aa4d45d9ead80ac9423374c451a7254d0766",
+                    r#""}}],"m":1}}}}}}]}}"#,
+                ),
+                funding = funding,
+                key = ed25519_key,
+            )
+        );
+        assert_eq!(
+            hex::encode(tx.canonical_json_hash()),
+            "5c892ba952831912344024eb941a6f17c917705ffe935498b1fa22cc559a43f0"
+        );
+    }
+
+    #[test]
+    fn test_serde_json_round_trip() {
+        let fixtures = SerdeFixtures::new();
+        for header in &fixtures.headers {
+            check_round_trip(header);
+        }
+        check_round_trip(&fixtures.transaction);
+        check_round_trip(&fixtures.proof);
+        check_round_trip(&bundle());
+    }
+
+    #[test]
+    fn test_key_order_does_not_matter() {
+        let shuffled = json!({
+            "b": { "z": 1, "a": null },
+            "a": [{ "y": true, "x": "" }],
+        });
+        assert_eq!(
+            canonical(shuffled).to_string(),
+            r#"{"a":[{"x":"","y":true}],"b":{"a":null,"z":1}}"#
+        );
+    }
+}
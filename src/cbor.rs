@@ -0,0 +1,458 @@
+//! Canonical CBOR for block headers, merkle proofs and transaction bundles,
+//! for consumers that speak CBOR rather than JSON.
+//!
+//! Each type is a map from its field names, as text, to their values, with
+//! hashes and transactions as byte strings. Encoding is deterministic as RFC
+//! 8949 section 4.2.1 lays out: integers and lengths take their shortest
+//! form, nothing is of indefinite length, and map keys are sorted by their
+//! encoded bytes, which for text keys puts shorter keys first. Two encoders
+//! following it produce the same bytes for the same value.
+//!
+//! Decoding is as strict as the chain's binary decoders. A map missing a
+//! key, or holding one the type does not have, is refused rather than
+//! ignored, as is any input that is not the canonical encoding of what it
+//! decodes to, so every value has exactly one encoding.
+
+use ciborium::Value;
+
+use crate::block::{BlockHash, BlockHeader, HeaderFields};
+use crate::chain::TxWithProof;
+use crate::codec::{self, DecodeError, DecodeLimits};
+use crate::hash::Hash32;
+use crate::merkle_trie::MerkleProof;
+
+/// How deeply arrays and maps may nest in input; the types here need five
+const MAX_NESTING: usize = 16;
+
+/// A type with a canonical CBOR encoding
+pub trait Cbor: Sized {
+    /// The value as CBOR, its maps in any key order
+    fn to_value(&self) -> Value;
+
+    /// Read the value back from what [`Cbor::to_value`] made
+    fn from_value(value: Value, limits: &DecodeLimits) -> Result<Self, DecodeError>;
+
+    /// The canonical encoding
+    fn to_cbor(&self) -> Vec<u8> {
+        encode(&canonical(self.to_value()))
+    }
+
+    /// Decode what [`Cbor::to_cbor`] wrote, enforcing `limits` on untrusted
+    /// input and refusing any other encoding of the same value
+    fn from_cbor(bytes: &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let value: Value = ciborium::de::from_reader_with_recursion_limit(bytes, MAX_NESTING)
+            .map_err(|err| match err {
+                ciborium::de::Error::Io(_) => DecodeError::UnexpectedEof,
+                ciborium::de::Error::RecursionLimitExceeded => {
+                    DecodeError::InvalidValue("cbor nesting")
+                }
+                _ => DecodeError::InvalidValue("cbor"),
+            })?;
+        let encoded = encode(&canonical(value.clone()));
+        if encoded.len() < bytes.len() && bytes.starts_with(&encoded) {
+            return Err(DecodeError::TrailingBytes(bytes.len() - encoded.len()));
+        }
+        if encoded != bytes {
+            return Err(DecodeError::NonCanonicalEncoding);
+        }
+        Self::from_value(value, limits)
+    }
+}
+
+impl Cbor for BlockHeader {
+    fn to_value(&self) -> Value {
+        Value::Map(vec![
+            text_key("version", self.version().into()),
+            text_key(
+                "prev_block_hash",
+                Value::Bytes(self.prev_block_hash().as_bytes().to_vec()),
+            ),
+            text_key("merkle_root", Value::Bytes(self.merkle_root().to_vec())),
+            text_key(
+                "state_root",
+                self.state_root()
+                    .map_or(Value::Null, |root| Value::Bytes(root.to_vec())),
+            ),
+            text_key("timestamp", self.timestamp().into()),
+            text_key("bits", self.bits().into()),
+            text_key("nonce", self.nonce().into()),
+        ])
+    }
+
+    fn from_value(value: Value, _: &DecodeLimits) -> Result<Self, DecodeError> {
+        let mut fields = Fields::new(value)?;
+        let header = HeaderFields {
+            version: uint(fields.take("version")?, "version")?,
+            prev_block_hash: BlockHash::from_bytes(hash(
+                fields.take("prev_block_hash")?,
+                "prev_block_hash",
+            )?),
+            merkle_root: Hash32(hash(fields.take("merkle_root")?, "merkle_root")?),
+            state_root: match fields.take("state_root")? {
+                Value::Null => None,
+                root => Some(hash(root, "state_root")?),
+            },
+            timestamp: uint(fields.take("timestamp")?, "timestamp")?,
+            bits: uint(fields.take("bits")?, "bits")?,
+            nonce: uint(fields.take("nonce")?, "nonce")?,
+        };
+        fields.finish()?;
+        BlockHeader::try_from(header)
+    }
+}
+
+/// The siblings under `proof` are pairs of the sibling's hash and whether
+/// it is on the right, from the leaf up
+impl Cbor for MerkleProof {
+    fn to_value(&self) -> Value {
+        let siblings = self
+            .siblings()
+            .iter()
+            .map(|(sibling, is_right)| {
+                Value::Array(vec![Value::Bytes(sibling.to_vec()), Value::Bool(*is_right)])
+            })
+            .collect();
+        Value::Map(vec![
+            text_key("proof", Value::Array(siblings)),
+            text_key("leaf_hash", Value::Bytes(self.leaf_hash().to_vec())),
+            text_key("root_hash", Value::Bytes(self.root_hash().to_vec())),
+        ])
+    }
+
+    fn from_value(value: Value, limits: &DecodeLimits) -> Result<Self, DecodeError> {
+        let mut fields = Fields::new(value)?;
+        let Value::Array(siblings) = fields.take("proof")? else {
+            return Err(DecodeError::InvalidValue("proof"));
+        };
+        if siblings.len() > limits.max_proof_depth {
+            return Err(DecodeError::LimitExceeded {
+                what: "proof depth",
+                value: siblings.len() as u64,
+                max: limits.max_proof_depth as u64,
+            });
+        }
+        let proof = siblings
+            .into_iter()
+            .map(|sibling| match sibling {
+                Value::Array(pair) => match <[Value; 2]>::try_from(pair) {
+                    Ok([sibling, Value::Bool(is_right)]) => {
+                        Ok((Hash32(hash(sibling, "proof")?), is_right))
+                    }
+                    _ => Err(DecodeError::InvalidValue("proof")),
+                },
+                _ => Err(DecodeError::InvalidValue("proof")),
+            })
+            .collect::<Result<_, _>>()?;
+        let leaf_hash = hash(fields.take("leaf_hash")?, "leaf_hash")?;
+        let root_hash = hash(fields.take("root_hash")?, "root_hash")?;
+        fields.finish()?;
+        Ok(MerkleProof::from_parts(
+            proof,
+            Hash32(leaf_hash),
+            Hash32(root_hash),
+        ))
+    }
+}
+
+impl Cbor for TxWithProof {
+    fn to_value(&self) -> Value {
+        Value::Map(vec![
+            text_key("tx", Value::Bytes(self.tx.clone())),
+            text_key("block_header", self.block_header.to_value()),
+            text_key("height", self.height.into()),
+            text_key("proof", self.proof.to_value()),
+        ])
+    }
+
+    fn from_value(value: Value, limits: &DecodeLimits) -> Result<Self, DecodeError> {
+        let mut fields = Fields::new(value)?;
+        let Value::Bytes(tx) = fields.take("tx")? else {
+            return Err(DecodeError::InvalidValue("tx"));
+        };
+        if tx.len() > limits.max_transaction_bytes {
+            return Err(DecodeError::LimitExceeded {
+                what: "transaction size",
+                value: tx.len() as u64,
+                max: limits.max_transaction_bytes as u64,
+            });
+        }
+        let bundle = TxWithProof {
+            tx,
+            block_header: BlockHeader::from_value(fields.take("block_header")?, limits)?,
+            height: uint(fields.take("height")?, "height")?,
+            proof: MerkleProof::from_value(fields.take("proof")?, limits)?,
+        };
+        fields.finish()?;
+        Ok(bundle)
+    }
+}
+
+fn text_key(key: &str, value: Value) -> (Value, Value) {
+    (Value::Text(key.to_string()), value)
+}
+
+#[allow(clippy::expect_used)]
+fn encode(value: &Value) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    ciborium::ser::into_writer(value, &mut buffer).expect("writing to a vector does not fail");
+    buffer
+}
+
+/// `value` with the keys of every map in it sorted by their encoded bytes
+fn canonical(value: Value) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(items.into_iter().map(canonical).collect()),
+        Value::Map(entries) => {
+            let mut entries: Vec<(Vec<u8>, Value, Value)> = entries
+                .into_iter()
+                .map(|(key, value)| {
+                    let key = canonical(key);
+                    (encode(&key), key, canonical(value))
+                })
+                .collect();
+            entries.sort_by(|a, b| a.0.cmp(&b.0));
+            Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(_, key, value)| (key, value))
+                    .collect(),
+            )
+        }
+        Value::Tag(tag, inner) => Value::Tag(tag, Box::new(canonical(*inner))),
+        value => value,
+    }
+}
+
+/// The entries of a map, taken out one key at a time
+struct Fields(Vec<(Value, Value)>);
+
+impl Fields {
+    fn new(value: Value) -> Result<Self, DecodeError> {
+        match value {
+            Value::Map(entries) => Ok(Fields(entries)),
+            _ => Err(DecodeError::InvalidValue("cbor map")),
+        }
+    }
+
+    fn take(&mut self, key: &'static str) -> Result<Value, DecodeError> {
+        let index = self
+            .0
+            .iter()
+            .position(|(found, _)| found.as_text() == Some(key))
+            .ok_or(DecodeError::InvalidValue(key))?;
+        Ok(self.0.remove(index).1)
+    }
+
+    /// Refuse keys left over once every field is taken, repeated ones
+    /// among them
+    fn finish(self) -> Result<(), DecodeError> {
+        match self.0.is_empty() {
+            true => Ok(()),
+            false => Err(DecodeError::InvalidValue("unknown cbor map key")),
+        }
+    }
+}
+
+fn uint<T: TryFrom<u64>>(value: Value, what: &'static str) -> Result<T, DecodeError> {
+    value
+        .as_integer()
+        .and_then(|int| u64::try_from(int).ok())
+        .and_then(|int| T::try_from(int).ok())
+        .ok_or(DecodeError::InvalidValue(what))
+}
+
+fn hash(value: Value, what: &'static str) -> Result<[u8; 32], DecodeError> {
+    match value {
+        Value::Bytes(bytes) => bytes
+            .try_into()
+            .map_err(|_| DecodeError::InvalidValue(what)),
+        _ => Err(DecodeError::InvalidValue(what)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    use super::*;
+    use crate::block::{Block, BlockBuilder, STATE_ROOT_VERSION};
+    use crate::difficulty::Difficulty;
+    use crate::merkle_trie::MerkleTree;
+    use crate::testing;
+
+    fn block(version: u32) -> Block {
+        let mut builder = BlockBuilder::new(BlockHash::from_bytes([7; 32]))
+            .version(version)
+            .transactions([b"one".to_vec(), b"two".to_vec(), b"three".to_vec()])
+            .timestamp(1_700_000_000)
+            .difficulty(Difficulty::LeadingZeroBits(4));
+        if version >= STATE_ROOT_VERSION {
+            builder = builder.state_root([9; 32]);
+        }
+        let mut block = builder.build().unwrap();
+        block.mine(Difficulty::LeadingZeroBits(4));
+        block
+    }
+
+    fn bundle(block: &Block, index: usize) -> TxWithProof {
+        TxWithProof {
+            tx: block.transactions()[index].clone(),
+            block_header: block.header().clone(),
+            height: 12,
+            proof: block.merkle_tree().generate_proof(index).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_round_trips_and_proofs_still_verify() {
+        let limits = DecodeLimits::default();
+        for version in [1, STATE_ROOT_VERSION] {
+            let block = block(version);
+            let header = block.header();
+            assert_eq!(
+                BlockHeader::from_cbor(&header.to_cbor(), &limits).as_ref(),
+                Ok(header)
+            );
+            for index in 0..3 {
+                let bundle = bundle(&block, index);
+                let decoded = TxWithProof::from_cbor(&bundle.to_cbor(), &limits).unwrap();
+                assert_eq!(decoded, bundle);
+                assert!(decoded.verify(header.hash().as_bytes()));
+
+                let proof = MerkleProof::from_cbor(&bundle.proof.to_cbor(), &limits).unwrap();
+                assert!(proof.verify(&bundle.tx));
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_values_round_trip(block in testing::block(), index in any::<Index>()) {
+            let limits = DecodeLimits::default();
+            let header = block.header();
+            prop_assert_eq!(&BlockHeader::from_cbor(&header.to_cbor(), &limits).unwrap(), header);
+
+            let bundle = bundle(&block, index.index(block.transactions().len()));
+            let bytes = bundle.to_cbor();
+            let decoded = TxWithProof::from_cbor(&bytes, &limits).unwrap();
+            prop_assert!(decoded.verify(header.hash().as_bytes()));
+            prop_assert_eq!(decoded.to_cbor(), bytes);
+            prop_assert_eq!(decoded, bundle);
+        }
+
+        #[test]
+        fn test_generated_proofs_round_trip((leaves, index, proof) in testing::merkle_proof()) {
+            let bytes = proof.to_cbor();
+            let decoded = MerkleProof::from_cbor(&bytes, &DecodeLimits::default()).unwrap();
+            prop_assert!(decoded.verify(&leaves[index]));
+            prop_assert_eq!(decoded.to_cbor(), bytes);
+            prop_assert_eq!(decoded, proof);
+        }
+    }
+
+    #[test]
+    fn test_canonical_encoding_pinned() {
+        // Keys sort shortest first, then bytewise: "bits", "nonce",
+        // "version", "timestamp", "state_root", "merkle_root",
+        // "prev_block_hash"
+        let header = BlockBuilder::new(BlockHash::from_bytes([0; 32]))
+            .transaction(b"genesis".to_vec())
+            .timestamp(1_700_000_000)
+            .difficulty(Difficulty::LeadingZeroBits(8))
+            .nonce(42)
+            .build()
+            .unwrap();
+        assert_eq!(
+            hex::encode(header.header().to_cbor()),
+            concat!(
+                "a7",
+                "6462697473",
+                "1a20010000",
+                "656e6f6e6365",
+                "182a",
+                "6776657273696f6e",
+                "01",
+                "6974696d657374616d70",
+                "1a6553f100",
+                "6a73746174655f726f6f74",
+                "f6",
+                "6b6d65726b6c655f726f6f74",
+                "5820",
+                "aeebad4a796fcc2e15dc4c6061b45ed9b373f26adfc798ca7d2d8cc58182718e",
+                "6f707265765f626c6f636b5f68617368",
+                "5820",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+            )
+        );
+
+        let tree = MerkleTree::new(&[b"a", b"b"]).unwrap();
+        assert_eq!(
+            hex::encode(tree.generate_proof(0).unwrap().to_cbor()),
+            concat!(
+                "a3",
+                "6570726f6f66",
+                "81",
+                "82",
+                "5820",
+                "3e23e8160039594a33894f6564e1b1348bbd7a0088d42c4acb73eeaed59c009d",
+                "f5",
+                "696c6561665f68617368",
+                "5820",
+                "ca978112ca1bbdcafac231b39a23dc4da786eff8147c4e72b9807785afee48bb",
+                "69726f6f745f68617368",
+                "5820",
+                "e5a01fee14e0ed5c48714f22180f25ad8365b53f9779f79dc4a3d7e93963f94a",
+            )
+        );
+    }
+
+    #[test]
+    fn test_refuses_other_encodings() {
+        let limits = DecodeLimits::default();
+        let header = block(1).header().to_cbor();
+
+        // Keys out of order
+        let mut value: Value = ciborium::de::from_reader(header.as_slice()).unwrap();
+        if let Value::Map(entries) = &mut value {
+            entries.reverse();
+        }
+        assert_eq!(
+            BlockHeader::from_cbor(&encode(&value), &limits),
+            Err(DecodeError::NonCanonicalEncoding)
+        );
+
+        // An unknown key, and a missing one
+        if let Value::Map(entries) = &mut value {
+            entries.push(text_key("extra", Value::Null));
+        }
+        assert_eq!(
+            BlockHeader::from_cbor(&encode(&canonical(value.clone())), &limits),
+            Err(DecodeError::InvalidValue("unknown cbor map key"))
+        );
+        if let Value::Map(entries) = &mut value {
+            entries.retain(|(key, _)| key.as_text() != Some("nonce"));
+        }
+        assert_eq!(
+            BlockHeader::from_cbor(&encode(&canonical(value)), &limits),
+            Err(DecodeError::InvalidValue("nonce"))
+        );
+
+        // Trailing bytes, and input over the size limit
+        let mut trailing = header.clone();
+        trailing.push(0);
+        assert_eq!(
+            BlockHeader::from_cbor(&trailing, &limits),
+            Err(DecodeError::TrailingBytes(1))
+        );
+        let tight = DecodeLimits {
+            max_decode_bytes: header.len() - 1,
+            ..limits
+        };
+        assert!(matches!(
+            BlockHeader::from_cbor(&header, &tight),
+            Err(DecodeError::LimitExceeded { .. })
+        ));
+    }
+}
@@ -0,0 +1,335 @@
+use std::collections::HashMap;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+
+use thiserror::Error;
+
+use super::Blockchain;
+use crate::block::{Block, BlockHash};
+use crate::codec::{check_input_size, DecodeError, DecodeLimits};
+use crate::store::ChainStore;
+
+/// First bytes of a block archive
+const MAGIC: &[u8; 4] = b"AWCA";
+const VERSION: u32 = 1;
+
+/// Reasons a block archive cannot be imported
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CarError {
+    /// The archive could not be read
+    #[error("failed to read archive: {0}")]
+    Io(String),
+    /// The archive's header or framing is malformed
+    #[error("malformed archive: {0}")]
+    Format(String),
+    /// Record `index` does not decode as a block
+    #[error("record {index} cannot be decoded: {err}")]
+    Decode { index: u64, err: DecodeError },
+    /// Record `index` claims the hash `claimed` for a block that hashes to
+    /// `computed`
+    #[error("record {index} claims hash {claimed} but its block hashes to {computed}")]
+    HashMismatch {
+        index: u64,
+        claimed: BlockHash,
+        computed: BlockHash,
+    },
+    /// Record `index` holds a block whose transactions do not match its
+    /// merkle root
+    #[error("record {index} holds transactions that do not match its merkle root")]
+    MerkleRootMismatch { index: u64 },
+    /// Record `index` repeats the hash of an earlier record with other bytes
+    #[error("record {index} holds block {hash} with other bytes than an earlier record")]
+    Conflict { index: u64, hash: BlockHash },
+    /// No record holds the tip the header names
+    #[error("no record holds the tip {0}")]
+    MissingTip(BlockHash),
+    /// The block with this hash is not on the parent links down from the tip
+    #[error("block {0} is not on the chain down from the tip")]
+    Unlinked(BlockHash),
+}
+
+impl CarError {
+    /// Index of the offending record, for errors tied to one
+    pub fn index(&self) -> Option<u64> {
+        match self {
+            CarError::Decode { index, .. }
+            | CarError::HashMismatch { index, .. }
+            | CarError::MerkleRootMismatch { index }
+            | CarError::Conflict { index, .. } => Some(*index),
+            CarError::Io(_)
+            | CarError::Format(_)
+            | CarError::MissingTip(_)
+            | CarError::Unlinked(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for CarError {
+    fn from(err: io::Error) -> Self {
+        CarError::Io(err.to_string())
+    }
+}
+
+impl<S: ChainStore> Blockchain<S> {
+    /// Write every block of the active chain to `w` as a content-addressed
+    /// archive, which [`Blockchain::import_car`] reads back.
+    ///
+    /// The archive is `AWCA`, a `u32` version, the hash of the tip and a
+    /// `u64` record count, then a record per block, genesis first: the
+    /// block's hash, the `u32` length of its binary encoding and the
+    /// encoding, all little-endian. A pruned chain no longer has every block
+    /// and cannot be exported.
+    pub fn export_car(&self, w: impl Write) -> io::Result<()> {
+        if self.pruned_height() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a pruned chain cannot be exported",
+            ));
+        }
+        let mut w = BufWriter::new(w);
+        write_header(&mut w, &self.tip().hash(), self.active.len() as u64)?;
+        for block in self.try_iter() {
+            let block = block.map_err(io::Error::other)?;
+            write_record(&mut w, &block.hash(), &block.to_bytes())?;
+        }
+        w.flush()
+    }
+}
+
+impl Blockchain {
+    /// Read the blocks of an archive written by [`Blockchain::export_car`],
+    /// in order from the first block to the tip.
+    ///
+    /// Every record's block must hash to the hash it is stored under and
+    /// match its merkle root, and every block must be on the parent links
+    /// down from the tip the header names. Records may come in any order,
+    /// and a record may repeat an earlier one. The blocks are not validated
+    /// against any chain's rules; append them to do that.
+    pub fn import_car(r: impl Read) -> Result<Vec<Block>, CarError> {
+        let mut r = BufReader::new(r);
+        if &read_array::<4>(&mut r)? != MAGIC {
+            return Err(CarError::Format("not a block archive".to_string()));
+        }
+        let version = u32::from_le_bytes(read_array(&mut r)?);
+        if version != VERSION {
+            return Err(CarError::Format(format!("unknown version {}", version)));
+        }
+        let tip = BlockHash::from_bytes(read_array(&mut r)?);
+        let count = u64::from_le_bytes(read_array(&mut r)?);
+
+        let limits = DecodeLimits::default();
+        let mut blocks: HashMap<BlockHash, (Vec<u8>, Block)> = HashMap::new();
+        for index in 0..count {
+            let claimed = BlockHash::from_bytes(read_array(&mut r)?);
+            let len = u32::from_le_bytes(read_array(&mut r)?) as usize;
+            check_input_size(len, &limits).map_err(|err| CarError::Decode { index, err })?;
+            let mut bytes = Vec::new();
+            (&mut r).take(len as u64).read_to_end(&mut bytes)?;
+            if bytes.len() < len {
+                return Err(CarError::Format(format!("record {} is truncated", index)));
+            }
+            if let Some((earlier, _)) = blocks.get(&claimed) {
+                if *earlier != bytes {
+                    return Err(CarError::Conflict {
+                        index,
+                        hash: claimed,
+                    });
+                }
+                continue;
+            }
+            let block = Block::from_bytes(&bytes, &limits)
+                .map_err(|err| CarError::Decode { index, err })?;
+            let computed = block.hash();
+            if computed != claimed {
+                return Err(CarError::HashMismatch {
+                    index,
+                    claimed,
+                    computed,
+                });
+            }
+            if !block.verify_merkle_root() {
+                return Err(CarError::MerkleRootMismatch { index });
+            }
+            blocks.insert(claimed, (bytes, block));
+        }
+        if r.read(&mut [0])? != 0 {
+            return Err(CarError::Format(format!(
+                "bytes follow the {} records",
+                count
+            )));
+        }
+
+        // Follow the parent links down from the tip until they leave the
+        // archive, then put the blocks the right way up
+        let mut chain = Vec::with_capacity(blocks.len());
+        let mut next = Some(tip);
+        while let Some((_, block)) = next.and_then(|hash| blocks.remove(&hash)) {
+            next = Some(block.header().prev_block_hash());
+            chain.push(block);
+        }
+        if chain.is_empty() {
+            return Err(CarError::MissingTip(tip));
+        }
+        if let Some(hash) = blocks.keys().min() {
+            return Err(CarError::Unlinked(*hash));
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+}
+
+fn write_header(w: &mut impl Write, tip: &BlockHash, count: u64) -> io::Result<()> {
+    w.write_all(MAGIC)?;
+    w.write_all(&VERSION.to_le_bytes())?;
+    w.write_all(tip.as_bytes())?;
+    w.write_all(&count.to_le_bytes())
+}
+
+fn write_record(w: &mut impl Write, hash: &BlockHash, bytes: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "block over 4 GiB"))?;
+    w.write_all(hash.as_bytes())?;
+    w.write_all(&len.to_le_bytes())?;
+    w.write_all(bytes)
+}
+
+fn read_array<const N: usize>(r: &mut impl Read) -> Result<[u8; N], CarError> {
+    let mut bytes = [0; N];
+    r.read_exact(&mut bytes).map_err(|err| match err.kind() {
+        io::ErrorKind::UnexpectedEof => CarError::Format("archive ends part way through".into()),
+        _ => err.into(),
+    })?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{mined_chain, mined_child};
+    use super::*;
+    use crate::testing;
+    use proptest::prelude::*;
+
+    /// An archive of `records` under the header naming `tip`
+    fn archive(tip: &Block, records: &[(BlockHash, Vec<u8>)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_header(&mut out, &tip.hash(), records.len() as u64).unwrap();
+        for (hash, bytes) in records {
+            write_record(&mut out, hash, bytes).unwrap();
+        }
+        out
+    }
+
+    fn record(block: &Block) -> (BlockHash, Vec<u8>) {
+        (block.hash(), block.to_bytes())
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let chain = mined_chain(6);
+        let mut car = Vec::new();
+        chain.export_car(&mut car).unwrap();
+        let blocks = Blockchain::import_car(&car[..]).unwrap();
+        assert!(blocks.iter().eq(chain.iter()));
+
+        // Shuffled and repeated records read the same
+        let mut records: Vec<_> = chain.iter().map(record).collect();
+        records.reverse();
+        records.swap(1, 4);
+        records.push(records[2].clone());
+        records.insert(0, records[5].clone());
+        let blocks = Blockchain::import_car(&archive(chain.tip(), &records)[..]).unwrap();
+        assert!(blocks.iter().eq(chain.iter()));
+
+        // An archive may hold a stretch of a chain not starting at genesis
+        let records: Vec<_> = chain.iter().skip(3).map(record).collect();
+        let blocks = Blockchain::import_car(&archive(chain.tip(), &records)[..]).unwrap();
+        assert!(blocks.iter().eq(chain.iter().skip(3)));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_generated_chains_round_trip(blocks in testing::chain(0..8)) {
+            let chain = testing::blockchain(blocks);
+            let mut car = Vec::new();
+            chain.export_car(&mut car).unwrap();
+            let blocks = Blockchain::import_car(&car[..]).unwrap();
+            prop_assert!(blocks.iter().eq(chain.iter()));
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_records() {
+        let chain = mined_chain(5);
+        let mut records: Vec<_> = chain.iter().map(record).collect();
+        let import = |records: &[(BlockHash, Vec<u8>)]| {
+            Blockchain::import_car(&archive(chain.tip(), records)[..])
+        };
+
+        // Record 3 holds block 2 under the hash of block 3
+        let mut swapped = records.clone();
+        swapped[3].1 = records[2].1.clone();
+        let err = import(&swapped).unwrap_err();
+        assert_eq!(
+            err,
+            CarError::HashMismatch {
+                index: 3,
+                claimed: chain.get(3).unwrap().hash(),
+                computed: chain.get(2).unwrap().hash(),
+            }
+        );
+        assert_eq!(err.index(), Some(3));
+
+        // A transaction byte changed leaves the hash alone but not the root
+        let mut flipped = records.clone();
+        let last = flipped[4].1.len() - 1;
+        flipped[4].1[last] ^= 1;
+        assert_eq!(
+            import(&flipped),
+            Err(CarError::MerkleRootMismatch { index: 4 })
+        );
+        let mut conflict = records.clone();
+        conflict.push(flipped[4].clone());
+        assert_eq!(
+            import(&conflict),
+            Err(CarError::Conflict {
+                index: 5,
+                hash: chain.get(4).unwrap().hash(),
+            })
+        );
+        let mut truncated = records.clone();
+        truncated[1].1.pop();
+        assert!(matches!(
+            import(&truncated),
+            Err(CarError::Decode { index: 1, .. })
+        ));
+
+        // A block off the chain to the tip, and a tip that is missing
+        let stray = mined_child(chain.get(2).unwrap(), b"stray");
+        records.push(record(&stray));
+        assert_eq!(import(&records), Err(CarError::Unlinked(stray.hash())));
+        records.pop();
+        records.pop();
+        assert_eq!(
+            import(&records),
+            Err(CarError::MissingTip(chain.tip().hash()))
+        );
+
+        let mut car = Vec::new();
+        chain.export_car(&mut car).unwrap();
+        assert!(matches!(
+            Blockchain::import_car(&car[..car.len() - 1]),
+            Err(CarError::Format(_))
+        ));
+        car.push(0);
+        assert!(matches!(
+            Blockchain::import_car(&car[..]),
+            Err(CarError::Format(_))
+        ));
+        car[0] = b'X';
+        assert_eq!(
+            Blockchain::import_car(&car[..]),
+            Err(CarError::Format("not a block archive".to_string()))
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::sync::mpsc::{self, Receiver, SyncSender};
+
+use super::Reorg;
+use crate::block::BlockHash;
+
+/// Events a subscriber buffers before it counts as lagging
+pub const DEFAULT_SUBSCRIBER_CAPACITY: usize = 1024;
+
+/// A change to the active chain, delivered to subscribers in the order it happened.
+///
+/// A change of tip sends `Disconnected` for each block leaving the active
+/// chain, tip first, then `Connected` for each block joining it, parent first.
+/// When any block was disconnected by a switch of branch, `ReorgCompleted`
+/// follows last. A rollback sends only the `Disconnected` events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChainEvent {
+    Connected { hash: BlockHash, height: u64 },
+    Disconnected { hash: BlockHash, height: u64 },
+    ReorgCompleted { fork_point: BlockHash, depth: u64 },
+}
+
+/// The senders for every live subscription
+#[derive(Debug, Default)]
+pub(super) struct Subscribers {
+    senders: Vec<SyncSender<ChainEvent>>,
+}
+
+impl Subscribers {
+    pub(super) fn subscribe(&mut self, capacity: usize) -> Receiver<ChainEvent> {
+        let (sender, receiver) = mpsc::sync_channel(capacity);
+        self.senders.push(sender);
+        receiver
+    }
+
+    /// Send `event` to every subscriber without blocking, dropping any whose
+    /// buffer is full or whose receiver is gone
+    pub(super) fn send(&mut self, event: ChainEvent) {
+        self.senders
+            .retain(|sender| sender.try_send(event.clone()).is_ok());
+    }
+
+    /// Send the events describing `reorg`, where the fork point is at `fork_height`
+    pub(super) fn send_reorg(&mut self, reorg: &Reorg, fork_height: u64) {
+        if self.senders.is_empty() {
+            return;
+        }
+        let depth = reorg.depth();
+        for (i, &hash) in reorg.disconnected.iter().enumerate() {
+            let height = fork_height + depth - i as u64;
+            self.send(ChainEvent::Disconnected { hash, height });
+        }
+        for (i, &hash) in reorg.connected.iter().enumerate() {
+            let height = fork_height + 1 + i as u64;
+            self.send(ChainEvent::Connected { hash, height });
+        }
+        if depth > 0 {
+            self.send(ChainEvent::ReorgCompleted {
+                fork_point: reorg.fork_point,
+                depth,
+            });
+        }
+    }
+}
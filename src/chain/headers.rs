@@ -0,0 +1,377 @@
+use super::ChainError;
+use crate::block::{BlockHash, BlockHeader};
+use crate::merkle_trie::MerkleProof;
+use crate::params::{ChainParams, ConsensusMode};
+use crate::retarget::{is_retarget_height, next_difficulty};
+use crate::validation::ValidationError;
+
+type EncodedHeader = Box<[u8]>;
+
+/// The headers of the active chain without their transactions, for light clients.
+///
+/// Each header is held in its encoding, so the chain grows by
+/// [`BlockHeader::ENCODED_LEN`] bytes per block, or
+/// [`BlockHeader::MAX_ENCODED_LEN`] for one committing to a state root.
+/// Appended headers are checked for their link to the tip, their version,
+/// their proof of work against the committed difficulty, the retargeting rule
+/// and the timestamp rules, the median time past and the clock's drift
+/// included, as the full chain checks them. The transactions, any state root and any authority
+/// signature are not available to check, so a header chain trusts them to the
+/// miners' work.
+#[derive(Clone, Debug)]
+pub struct HeaderChain {
+    params: ChainParams,
+    headers: Vec<EncodedHeader>,
+    tip: BlockHeader,
+    tip_hash: BlockHash,
+}
+
+impl HeaderChain {
+    /// Start from the genesis header derived from `params`
+    pub fn new(params: &ChainParams) -> Self {
+        let genesis = params.genesis_block().header().clone();
+        HeaderChain {
+            params: params.clone(),
+            headers: vec![encode(&genesis)],
+            tip_hash: genesis.hash(),
+            tip: genesis,
+        }
+    }
+
+    /// Add `header` on top of the tip
+    pub fn append(&mut self, header: BlockHeader) -> Result<(), ChainError> {
+        let hash = check_header(&self.params, &self.tip, self.tip_hash, &header)?;
+        if self.params.median_time_span != 0 {
+            let median_time_past = self.median_time_past();
+            if header.timestamp() <= median_time_past {
+                return Err(ChainError::TimestampTooOld {
+                    median_time_past,
+                    got: header.timestamp(),
+                });
+            }
+        }
+        if self.params.retarget_interval != 0 {
+            let expected = self.next_bits();
+            if header.bits() != expected {
+                return Err(ChainError::UnexpectedDifficulty {
+                    expected,
+                    got: header.bits(),
+                });
+            }
+        }
+
+        self.headers.push(encode(&header));
+        self.tip = header;
+        self.tip_hash = hash;
+        Ok(())
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        &self.tip
+    }
+
+    /// Height of the tip, where the genesis header is at height 0
+    pub fn height(&self) -> u64 {
+        self.headers.len() as u64 - 1
+    }
+
+    /// The header at `height`, or `None` above the tip
+    pub fn get(&self, height: u64) -> Option<BlockHeader> {
+        let encoded = self.headers.get(usize::try_from(height).ok()?)?;
+        Some(decode(encoded))
+    }
+
+    /// Median timestamp of the tip and the headers below it, as
+    /// [`Blockchain::median_time_past`] takes it for the same blocks
+    ///
+    /// [`Blockchain::median_time_past`]: super::Blockchain::median_time_past
+    pub fn median_time_past(&self) -> u64 {
+        let span = self.params.median_time_span.max(1);
+        let start = self.headers.len().saturating_sub(span);
+        let mut timestamps: Vec<u64> = self.headers[start..]
+            .iter()
+            .map(|encoded| decode(encoded).timestamp())
+            .collect();
+        timestamps.sort_unstable();
+        timestamps[timestamps.len() / 2]
+    }
+
+    /// Whether `proof` shows `tx` is among the transactions of the block at
+    /// `height`, judged against the merkle root in its stored header
+    pub fn verify_inclusion(&self, height: u64, tx: &[u8], proof: &MerkleProof) -> bool {
+        match self.get(height) {
+            Some(header) => header.merkle_root() == proof.root_hash() && proof.verify(tx),
+            None => false,
+        }
+    }
+
+    /// The compact difficulty the header after the tip must commit to
+    fn next_bits(&self) -> u32 {
+        let height = self.height() + 1;
+        if !is_retarget_height(height, &self.params) {
+            return self.tip.bits();
+        }
+        let start = (height - self.params.retarget_interval) as usize;
+        let window: Vec<BlockHeader> = self.headers[start..].iter().map(decode).collect();
+        // The window holds `retarget_interval` headers, never none
+        next_difficulty(&window, &self.params).map_or(self.tip.bits(), |next| next.to_compact())
+    }
+}
+
+/// Check the rules a header can be held to without the blocks before its
+/// parent: its link to `parent`, whose hash is `parent_hash`, its version, its
+/// proof of work, the timestamp rule and the drift allowed past the clock.
+/// Gives the header's hash.
+pub(crate) fn check_header(
+    params: &ChainParams,
+    parent: &BlockHeader,
+    parent_hash: BlockHash,
+    header: &BlockHeader,
+) -> Result<BlockHash, ChainError> {
+    if header.prev_block_hash() != parent_hash {
+        return Err(ChainError::PrevHashMismatch {
+            expected: parent_hash,
+            got: header.prev_block_hash(),
+        });
+    }
+    let version = header.version();
+    if !params.allowed_versions.contains(&version) {
+        return Err(ValidationError::UnsupportedVersion(version).into());
+    }
+    let hash = header.hash();
+    if let ConsensusMode::ProofOfWork {
+        difficulty: minimum,
+    } = &params.consensus_mode
+    {
+        if !minimum.is_met_by(hash.as_bytes()) {
+            return Err(ValidationError::InsufficientProofOfWork.into());
+        }
+        if header.difficulty().to_target() > minimum.normalized().to_target() {
+            return Err(ValidationError::DifficultyBelowMinimum.into());
+        }
+        if !header.difficulty().is_met_by(hash.as_bytes()) {
+            return Err(ValidationError::InsufficientProofOfWork.into());
+        }
+    }
+    if !params
+        .timestamp_rule
+        .allows(parent.timestamp(), header.timestamp())
+    {
+        return Err(ChainError::InvalidTimestamp {
+            parent: parent.timestamp(),
+            got: header.timestamp(),
+        });
+    }
+    if let Some(drift) = params.max_future_drift {
+        let max = params.clock.now().saturating_add(drift.as_secs());
+        if header.timestamp() > max {
+            return Err(ChainError::TimestampTooNew {
+                max,
+                got: header.timestamp(),
+            });
+        }
+    }
+    Ok(hash)
+}
+
+fn encode(header: &BlockHeader) -> EncodedHeader {
+    header.to_bytes().into_boxed_slice()
+}
+
+#[allow(clippy::expect_used)]
+fn decode(encoded: &EncodedHeader) -> BlockHeader {
+    BlockHeader::from_bytes(encoded).expect("stored headers were encoded by `encode`")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::block::Block;
+    use crate::chain::Blockchain;
+    use crate::params::{FixedClock, TimestampRule};
+
+    /// Mine the next block for `chain`, committing to the difficulty it requires
+    fn next_block(chain: &Blockchain, params: &ChainParams) -> Block {
+        let parent = chain.tip();
+        let height = chain.height() + 1;
+        let difficulty = if is_retarget_height(height, params) {
+            let window: Vec<BlockHeader> = chain
+                .range(height - params.retarget_interval..)
+                .unwrap()
+                .map(|block| block.header().clone())
+                .collect();
+            next_difficulty(&window, params).unwrap()
+        } else {
+            parent.difficulty()
+        };
+        let mut block = parent
+            .next_builder()
+            .transactions((0..4u64).map(|i| [height, i].map(u64::to_le_bytes).concat()))
+            .difficulty(difficulty)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(difficulty);
+        block
+    }
+
+    #[test]
+    fn test_syncs_headers_and_verifies_inclusion() {
+        // Blocks arrive twice as fast as intended, so every interval retargets harder
+        let params = ChainParams {
+            target_block_time: Duration::from_secs(20),
+            retarget_interval: 250,
+            ..ChainParams::test_defaults()
+        };
+        let mut chain = Blockchain::new_from_params(&params);
+        for _ in 0..1000 {
+            chain.append(next_block(&chain, &params)).unwrap();
+        }
+        assert!(chain.tip().difficulty().work() > params.initial_difficulty.work());
+
+        let mut headers = HeaderChain::new(&params);
+        assert_eq!(headers.tip().hash(), chain.get(0).unwrap().hash());
+        for block in chain.iter().skip(1) {
+            headers.append(block.header().clone()).unwrap();
+        }
+        assert_eq!(headers.height(), 1000);
+        assert_eq!(headers.tip().hash(), chain.tip().hash());
+        assert_eq!(headers.get(500).as_ref(), chain.get(500).map(Block::header));
+        assert!(headers.get(1001).is_none());
+
+        // A proof from the full node checks out against the header alone
+        let block = chain.get(617).unwrap();
+        let tx = &block.transactions()[2];
+        let proof = block.merkle_tree().generate_proof(2).unwrap();
+        assert!(headers.verify_inclusion(617, tx, &proof));
+        assert!(!headers.verify_inclusion(617, &block.transactions()[1], &proof));
+        assert!(!headers.verify_inclusion(618, tx, &proof));
+        assert!(!headers.verify_inclusion(5000, tx, &proof));
+    }
+
+    #[test]
+    fn test_rejects_invalid_headers() {
+        let params = ChainParams {
+            target_block_time: Duration::from_secs(20),
+            retarget_interval: 4,
+            ..ChainParams::test_defaults()
+        };
+        let mut chain = Blockchain::new_from_params(&params);
+        let mut headers = HeaderChain::new(&params);
+
+        let block = next_block(&chain, &params);
+        let orphan = block
+            .next_builder()
+            .transaction(b"orphan".to_vec())
+            .timestamp(block.timestamp() + 10)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            headers.append(orphan.header().clone()),
+            Err(ChainError::PrevHashMismatch { .. })
+        ));
+        let unmined = (0..)
+            .map(|nonce| {
+                chain
+                    .tip()
+                    .next_builder()
+                    .transaction(b"unmined".to_vec())
+                    .difficulty(params.initial_difficulty)
+                    .timestamp(block.timestamp())
+                    .nonce(nonce)
+                    .build()
+                    .unwrap()
+            })
+            .find(|block| !block.verify_pow(params.initial_difficulty))
+            .unwrap();
+        assert_eq!(
+            headers.append(unmined.header().clone()),
+            Err(ChainError::InvalidBlock(
+                ValidationError::InsufficientProofOfWork
+            ))
+        );
+
+        for _ in 0..3 {
+            let block = next_block(&chain, &params);
+            headers.append(block.header().clone()).unwrap();
+            chain.append(block).unwrap();
+        }
+
+        // Height 4 retargets: keeping the parent's difficulty is no longer allowed
+        let mut stale = chain
+            .tip()
+            .next_builder()
+            .transaction(b"stale".to_vec())
+            .difficulty(chain.tip().difficulty())
+            .timestamp(chain.tip().timestamp() + 10)
+            .build()
+            .unwrap();
+        stale.mine(chain.tip().difficulty());
+        assert!(matches!(
+            headers.append(stale.header().clone()),
+            Err(ChainError::UnexpectedDifficulty { .. })
+        ));
+        headers
+            .append(next_block(&chain, &params).header().clone())
+            .unwrap();
+        assert_eq!(headers.height(), 4);
+    }
+
+    #[test]
+    fn test_checks_timestamps_as_the_full_chain_does() {
+        let genesis_time = ChainParams::test_defaults().genesis_timestamp;
+        let params = ChainParams {
+            timestamp_rule: TimestampRule::Any,
+            clock: Arc::new(FixedClock(genesis_time + 100)),
+            ..ChainParams::test_defaults()
+        };
+        let mut chain = Blockchain::new_from_params(&params);
+        let mut headers = HeaderChain::new(&params);
+        let child = |parent: &Block, offset: u64| {
+            let mut block = parent
+                .next_builder()
+                .transaction(offset.to_le_bytes().to_vec())
+                .difficulty(params.initial_difficulty)
+                .timestamp(genesis_time + offset)
+                .build()
+                .unwrap();
+            block.mine(params.initial_difficulty);
+            block
+        };
+        for offset in [20, 40, 30, 35] {
+            let block = child(chain.tip(), offset);
+            headers.append(block.header().clone()).unwrap();
+            chain.append(block).unwrap();
+        }
+        // The median of 0, 20, 30, 35 and 40
+        assert_eq!(headers.median_time_past(), genesis_time + 30);
+        assert_eq!(
+            headers.median_time_past(),
+            chain.median_time_past().unwrap()
+        );
+
+        // A header stamped at the median time past is as old as the chain allows
+        let at_median = child(chain.tip(), 30);
+        let too_old = Err(ChainError::TimestampTooOld {
+            median_time_past: genesis_time + 30,
+            got: genesis_time + 30,
+        });
+        assert_eq!(headers.append(at_median.header().clone()), too_old);
+        assert_eq!(chain.append(at_median), too_old);
+
+        // At most two hours past the clock
+        let max = genesis_time + 100 + 2 * 60 * 60;
+        let ahead = child(chain.tip(), max - genesis_time + 1);
+        let too_new = Err(ChainError::TimestampTooNew { max, got: max + 1 });
+        assert_eq!(headers.append(ahead.header().clone()), too_new);
+        assert_eq!(chain.append(ahead), too_new);
+
+        let block = child(chain.tip(), 31);
+        headers.append(block.header().clone()).unwrap();
+        chain.append(block).unwrap();
+        assert_eq!(headers.tip().hash(), chain.tip().hash());
+    }
+}
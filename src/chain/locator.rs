@@ -0,0 +1,162 @@
+use super::Blockchain;
+use crate::block::{BlockHash, BlockHeader};
+use crate::store::ChainStore;
+
+/// Number of blocks below the tip a locator lists one by one before it
+/// starts doubling the step
+const DENSE_LOCATOR_ENTRIES: usize = 10;
+
+impl<S: ChainStore> Blockchain<S> {
+    /// Hashes of the active chain from the tip back to genesis, for a peer to
+    /// find where its chain leaves this one.
+    ///
+    /// The tip and the ten blocks below it are listed one by one, then the
+    /// step doubles with each entry, and genesis always comes last. A chain
+    /// of height `h` gives about `10 + log2(h)` entries.
+    /// Pruned heights still have their hashes, so they are listed too.
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let mut locator = Vec::new();
+        let mut height = self.active.len() - 1;
+        let mut step = 1;
+        loop {
+            locator.push(self.active[height]);
+            if height == 0 {
+                return locator;
+            }
+            if locator.len() > DENSE_LOCATOR_ENTRIES {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+    }
+
+    /// The height and hash of the first entry of `locator` on the active
+    /// chain, which for a locator from [`Blockchain::locator`] is the highest
+    /// block both chains share.
+    ///
+    /// Entries this chain does not know, or knows only on a side branch, are
+    /// skipped, so a locator may be longer than this chain or reach past its
+    /// tip. A locator from a chain with another genesis shares nothing and
+    /// gives `None`, as does an empty one.
+    pub fn find_fork_point(&self, locator: &[BlockHash]) -> Option<(u64, BlockHash)> {
+        locator
+            .iter()
+            .find_map(|hash| self.heights.get(hash).map(|&height| (height, *hash)))
+    }
+
+    /// Headers of the active chain after the fork point of `locator`, up to
+    /// and including `stop` if it is on the way, and at most `max` of them:
+    /// what a peer that sent the locator is missing. A locator sharing
+    /// nothing with this chain gets none.
+    pub fn headers_after(
+        &self,
+        locator: &[BlockHash],
+        stop: Option<BlockHash>,
+        max: usize,
+    ) -> Vec<BlockHeader> {
+        let Some((fork_height, _)) = self.find_fork_point(locator) else {
+            return Vec::new();
+        };
+        let mut headers = Vec::new();
+        for height in fork_height + 1..=self.height() {
+            if headers.len() == max {
+                break;
+            }
+            // Headers the store cannot read end the batch early
+            let Ok(header) = self.header_at(height as usize) else {
+                break;
+            };
+            headers.push(header.clone());
+            if stop == Some(self.active[height as usize]) {
+                break;
+            }
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{mined_chain, mined_child, test_params};
+    use super::*;
+    use crate::params::ChainParams;
+
+    #[test]
+    fn test_locator_spacing() {
+        let chain = mined_chain(100);
+        let heights: Vec<u64> = chain
+            .locator()
+            .iter()
+            .map(|hash| chain.height_of(hash.as_ref()).unwrap())
+            .collect();
+        assert_eq!(
+            heights,
+            [99, 98, 97, 96, 95, 94, 93, 92, 91, 90, 89, 87, 83, 75, 59, 27, 0]
+        );
+
+        let genesis = mined_chain(1);
+        assert_eq!(genesis.locator(), [genesis.tip().hash()]);
+    }
+
+    #[test]
+    fn test_fork_point_between_diverging_chains() {
+        let ours = mined_chain(60);
+        let mut theirs = Blockchain::new_from_params(&test_params());
+        for block in ours.range(1..=37).unwrap() {
+            theirs.append(block.clone()).unwrap();
+        }
+        for i in 0..40 {
+            let block = mined_child(theirs.tip(), &[b't', i]);
+            theirs.append(block).unwrap();
+        }
+        let shared = (37, ours.get(37).unwrap().hash());
+        assert_eq!(ours.find_fork_point(&theirs.locator()), Some(shared));
+        // Our locator skips from 43 to 35, so they can only narrow it to 35
+        assert_eq!(
+            theirs.find_fork_point(&ours.locator()),
+            Some((35, ours.get(35).unwrap().hash()))
+        );
+
+        // A locator longer than our chain is matched as far as it goes
+        let short = mined_chain(5);
+        assert_eq!(
+            short.find_fork_point(&ours.locator()),
+            Some((0, short.get(0).unwrap().hash()))
+        );
+        assert_eq!(
+            ours.find_fork_point(&short.locator()),
+            Some((4, short.tip().hash()))
+        );
+
+        // Nothing is shared with a chain on another genesis
+        let foreign = Blockchain::new_from_params(&ChainParams {
+            genesis_timestamp: 1,
+            ..test_params()
+        });
+        assert_eq!(ours.find_fork_point(&foreign.locator()), None);
+        assert_eq!(ours.find_fork_point(&[]), None);
+    }
+
+    #[test]
+    fn test_headers_after_fork_point() {
+        let ours = mined_chain(30);
+        let theirs = mined_chain(12);
+        let header = |height| ours.get(height).unwrap().header().clone();
+
+        let headers = ours.headers_after(&theirs.locator(), None, 100);
+        assert_eq!(headers, (12..30).map(header).collect::<Vec<_>>());
+        assert_eq!(
+            ours.headers_after(&theirs.locator(), None, 5),
+            (12..17).map(header).collect::<Vec<_>>()
+        );
+        let stop = ours.get(14).unwrap().hash();
+        assert_eq!(
+            ours.headers_after(&theirs.locator(), Some(stop), 100),
+            (12..15).map(header).collect::<Vec<_>>()
+        );
+
+        // Nothing after our own tip, nor for a chain sharing nothing
+        assert!(ours.headers_after(&ours.locator(), None, 100).is_empty());
+        assert!(ours.headers_after(&[], None, 100).is_empty());
+    }
+}
@@ -0,0 +1,136 @@
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+/// Hooks through which a chain reports its activity, for export to whatever
+/// metrics system the node uses; install one with [`super::Blockchain::set_metrics`].
+///
+/// Every hook does nothing by default. Hooks are called on the thread
+/// changing the chain, with values already worked out, so they should return
+/// quickly. A chain without metrics installed does not measure anything.
+pub trait ChainMetrics: Send + Sync {
+    /// A block holding `tx_count` transactions was stored at `height`, on the
+    /// active chain or a side branch.
+    ///
+    /// `validation_time` runs from the start of the block's checks until it
+    /// was stored. For an orphan it covers only the checks made once its
+    /// parent arrived, and for a block from
+    /// [`super::Blockchain::import_batch`] only the checks made after the
+    /// batch's parallel ones.
+    fn block_connected(&self, height: u64, tx_count: usize, validation_time: Duration) {
+        let _ = (height, tx_count, validation_time);
+    }
+
+    /// The active chain switched branch, disconnecting `depth` blocks
+    fn reorg(&self, depth: u64) {
+        let _ = depth;
+    }
+
+    /// The orphan pool now holds `size` blocks
+    fn orphan_pool_size(&self, size: usize) {
+        let _ = size;
+    }
+}
+
+/// What a [`CountingMetrics`] has seen so far
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct MetricCounts {
+    pub blocks_connected: u64,
+    pub transactions: u64,
+    /// Height of the most recently stored block
+    pub last_height: Option<u64>,
+    pub validation_time: Duration,
+    pub reorgs: u64,
+    /// Blocks disconnected across all reorgs
+    pub reorged_blocks: u64,
+    pub orphan_pool_size: usize,
+}
+
+/// Metrics kept in memory by counting every hook call.
+///
+/// Clones share their counts, so one clone can be installed on a chain and
+/// another kept to read them.
+#[derive(Clone, Debug, Default)]
+pub struct CountingMetrics {
+    counts: Arc<Mutex<MetricCounts>>,
+}
+
+impl CountingMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counts so far
+    pub fn counts(&self) -> MetricCounts {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MetricCounts> {
+        // Counting never panics part way, so a poisoned lock holds sound counts
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl ChainMetrics for CountingMetrics {
+    fn block_connected(&self, height: u64, tx_count: usize, validation_time: Duration) {
+        let mut counts = self.lock();
+        counts.blocks_connected += 1;
+        counts.transactions += tx_count as u64;
+        counts.last_height = Some(height);
+        counts.validation_time += validation_time;
+    }
+
+    fn reorg(&self, depth: u64) {
+        let mut counts = self.lock();
+        counts.reorgs += 1;
+        counts.reorged_blocks += depth;
+    }
+
+    fn orphan_pool_size(&self, size: usize) {
+        self.lock().orphan_pool_size = size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{mined_chain, mined_child, test_params};
+    use super::*;
+    use crate::block::Block;
+    use crate::chain::Blockchain;
+
+    #[test]
+    fn test_hooks_follow_appends_orphans_and_reorgs() {
+        let source = mined_chain(4);
+        let mut chain = Blockchain::new_from_params(&test_params());
+        let metrics = CountingMetrics::new();
+        chain.set_metrics(Box::new(metrics.clone()));
+
+        let blocks: Vec<Block> = source.range(1..).unwrap().cloned().collect();
+        chain.append(blocks[0].clone()).unwrap();
+        assert!(chain.insert(blocks[2].clone()).is_err());
+        assert_eq!(metrics.counts().orphan_pool_size, 1);
+        assert_eq!(metrics.counts().blocks_connected, 1);
+
+        // The orphan connects behind its parent and leaves the pool
+        chain.insert(blocks[1].clone()).unwrap();
+        let counts = metrics.counts();
+        assert_eq!(counts.blocks_connected, 3);
+        assert_eq!(counts.transactions, 3);
+        assert_eq!(counts.last_height, Some(3));
+        assert_eq!(counts.orphan_pool_size, 0);
+        assert_eq!(counts.reorgs, 0);
+
+        // Two blocks from height 1 tie, the third switches away from two blocks
+        let fork_point = chain.get(1).unwrap().clone();
+        let mut parent = fork_point;
+        for i in 0..3u8 {
+            let block = mined_child(&parent, &[b'f', i]);
+            chain.insert(block.clone()).unwrap();
+            parent = block;
+        }
+        let counts = metrics.counts();
+        assert_eq!(counts.blocks_connected, 6);
+        assert_eq!(counts.last_height, Some(4));
+        assert_eq!(counts.reorgs, 1);
+        assert_eq!(counts.reorged_blocks, 2);
+    }
+}
@@ -0,0 +1,3219 @@
+use std::collections::HashMap;
+use std::iter::Rev;
+use std::ops::{Bound, RangeBounds};
+use std::sync::mpsc::Receiver;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+use crate::block::{Block, BlockHash, BlockHeader, BlockLimits};
+use crate::checkpoint::CheckpointCert;
+use crate::difficulty::{Difficulty, Work};
+use crate::params::{ChainParams, ConsensusMode, TimestampRule};
+use crate::retarget::{is_retarget_height, next_difficulty};
+use crate::store::{ChainStore, InfallibleStore, MemoryStore, StoreError};
+use crate::trace;
+use crate::transaction::{FeeError, SigError};
+use crate::utxo::UtxoError;
+use crate::validation::{
+    BlockLimitsRule, CommittedDifficultyRule, ConsensusRule, MerkleRootRule, Rule, ValidationError,
+    Validator, VersionRule,
+};
+
+mod car;
+mod events;
+mod headers;
+mod locator;
+mod metrics;
+mod orphan;
+mod shared;
+mod snapshot;
+mod stats;
+mod tree;
+mod tx_index;
+mod utxo;
+
+pub use car::CarError;
+pub use events::{ChainEvent, DEFAULT_SUBSCRIBER_CAPACITY};
+pub(crate) use headers::check_header;
+pub use headers::HeaderChain;
+pub use metrics::{ChainMetrics, CountingMetrics, MetricCounts};
+pub use orphan::{OrphanPool, DEFAULT_MAX_ORPHANS, DEFAULT_MAX_ORPHAN_BYTES};
+pub use shared::{ChainView, SharedChain};
+pub use snapshot::{ImportError, Snapshot, SnapshotFormat};
+pub use stats::ChainStats;
+pub use tree::{BlockTree, StoredBlock};
+pub use tx_index::{TxLocation, TxWithProof};
+
+use events::Subscribers;
+use thiserror::Error;
+use tx_index::TxIndex;
+use utxo::ChainUtxos;
+
+/// Reasons a block cannot be added to the chain
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ChainError {
+    /// The block does not build on the current tip
+    #[error("block builds on {got} but the tip is {expected}")]
+    PrevHashMismatch { expected: BlockHash, got: BlockHash },
+    /// The block's parent is not in the block tree
+    #[error("parent block {0} is unknown")]
+    UnknownParent(BlockHash),
+    /// The block is already in the block tree
+    #[error("block {0} is already known")]
+    DuplicateBlock(BlockHash),
+    /// The block is, or builds on, the block with this hash, which has been
+    /// marked invalid
+    #[error("block {0} has been marked invalid")]
+    MarkedInvalid(BlockHash),
+    /// The genesis block cannot be marked invalid
+    #[error("the genesis block cannot be marked invalid")]
+    GenesisMarkedInvalid,
+    /// The block's committed difficulty is not the one the retargeting rule requires
+    #[error("block commits to bits {got:#010x} but {expected:#010x} is required")]
+    UnexpectedDifficulty { expected: u32, got: u32 },
+    /// The block's timestamp breaks the chain's timestamp rule
+    #[error("timestamp {got} not allowed after {parent}")]
+    InvalidTimestamp { parent: u64, got: u64 },
+    /// The block's timestamp is not newer than the median time past of its parent
+    #[error("timestamp {got} is not after the median time past {median_time_past}")]
+    TimestampTooOld { median_time_past: u64, got: u64 },
+    /// Under a signing rotation, the block is stamped in its parent's slot
+    /// or an earlier one
+    #[error("slot {got} is not after the parent's slot {parent}")]
+    SlotNotAfterParent { parent: u64, got: u64 },
+    /// The block's timestamp is further ahead of the clock than the allowed drift
+    #[error("timestamp {got} is later than the allowed {max}")]
+    TimestampTooNew { max: u64, got: u64 },
+    /// Switching to the block's branch would disconnect more blocks than allowed
+    #[error("reorg of {depth} blocks exceeds the limit of {max}")]
+    ReorgTooDeep { depth: u64, max: u64 },
+    /// A rollback target above the current tip
+    #[error("cannot roll back to height {height} above the tip at {tip}")]
+    RollbackOutOfRange { height: u64, tip: u64 },
+    /// The block or rollback reaches the active chain at `height`, below
+    /// `pruned_height`, where block bodies have been dropped
+    #[error("height {height} is below the pruned height {pruned_height}")]
+    BelowPrunedHeight { height: u64, pruned_height: u64 },
+    /// The block's branch contradicts a checkpoint: `expected` is pinned at `height`
+    #[error("block {got} at height {height} contradicts checkpointed block {expected}")]
+    CheckpointViolation {
+        height: u64,
+        expected: BlockHash,
+        got: BlockHash,
+    },
+    /// The certificate for a checkpoint on this hash is not signed by enough
+    /// of the checkpoint committee
+    #[error("checkpoint certificate for {0} is not valid")]
+    InvalidCheckpointCert(BlockHash),
+    /// The block failed validation against the chain's rules
+    #[error("invalid block: {0}")]
+    InvalidBlock(ValidationError),
+    /// The transactions of `block`, this block or one on its branch, do not
+    /// apply to the unspent outputs below it
+    #[error("block {block} spends invalidly: {err}")]
+    InvalidSpend { block: BlockHash, err: UtxoError },
+    /// An input of `block`, this block or one on its branch, does not meet
+    /// the spend condition of the output it spends
+    #[error("block {block} is not signed for its spends: {err}")]
+    InvalidSignature { block: BlockHash, err: SigError },
+    /// The coinbase of `block`, this block or one on its branch, pays out more
+    /// than the subsidy at its height plus the block's fees
+    #[error("block {block} claims an invalid reward: {err}")]
+    InvalidReward { block: BlockHash, err: FeeError },
+    /// The header of `block`, this block or one on its branch, commits to a
+    /// state root other than the `expected` root of the unspent outputs after it
+    #[error("block {block} commits to state root {} but the state root is {}", hex::encode(.got), hex::encode(.expected))]
+    StateRootMismatch {
+        block: BlockHash,
+        expected: [u8; 32],
+        got: [u8; 32],
+    },
+    /// The store holds a chain with a different genesis block than the params
+    #[error("stored genesis is {got} but expected {expected}")]
+    GenesisMismatch { expected: BlockHash, got: BlockHash },
+    /// The backing store failed
+    #[error("{0}")]
+    Store(StoreError),
+}
+
+impl From<ValidationError> for ChainError {
+    fn from(err: ValidationError) -> Self {
+        ChainError::InvalidBlock(err)
+    }
+}
+
+impl From<StoreError> for ChainError {
+    fn from(err: StoreError) -> Self {
+        ChainError::Store(err)
+    }
+}
+
+/// The check that failed during full-chain validation
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ChainValidationErrorKind {
+    /// The first block is not the chain's genesis block
+    #[error("genesis is {got} but expected {expected}")]
+    GenesisMismatch { expected: BlockHash, got: BlockHash },
+    /// The block does not build on the block below it
+    #[error("builds on {got} but the parent is {expected}")]
+    PrevHashMismatch { expected: BlockHash, got: BlockHash },
+    /// The header version is not one the chain accepts
+    #[error("version {0} is not allowed")]
+    UnsupportedVersion(u32),
+    /// The block exceeds the chain's size limits
+    #[error("{0}")]
+    ExceedsBlockLimits(ValidationError),
+    /// The header's merkle root does not match the block's transactions
+    #[error("merkle root does not match transactions")]
+    MerkleRootMismatch,
+    /// The block hash does not meet the difficulty its header commits to
+    #[error("hash does not meet committed difficulty")]
+    InsufficientProofOfWork,
+    /// The committed difficulty is easier than the chain's minimum
+    #[error("committed difficulty is below the chain minimum")]
+    DifficultyBelowMinimum,
+    /// The committed difficulty is not the one the retargeting rule requires
+    #[error("commits to bits {got:#010x} but {expected:#010x} is required")]
+    UnexpectedDifficulty { expected: u32, got: u32 },
+    /// The block is not signed by any of the chain's authorities
+    #[error("not signed by an authority")]
+    InvalidAuthoritySignature,
+    /// The timestamp breaks the chain's timestamp rule
+    #[error("timestamp {got} not allowed after {parent}")]
+    InvalidTimestamp { parent: u64, got: u64 },
+    /// Under a signing rotation, the block is stamped in its parent's slot
+    /// or an earlier one
+    #[error("slot {got} is not after the parent's slot {parent}")]
+    SlotNotAfterParent { parent: u64, got: u64 },
+    /// The block is not the one checkpointed at its height
+    #[error("hash is {got} but {expected} is checkpointed")]
+    CheckpointViolation { expected: BlockHash, got: BlockHash },
+    /// The header commits to no state root for the unspent outputs given with it
+    #[error("header commits to no state root")]
+    MissingStateRoot,
+    /// The header commits to a state root other than the `expected` root of
+    /// the unspent outputs given with it
+    #[error("commits to state root {} but the state root is {}", hex::encode(.got), hex::encode(.expected))]
+    StateRootMismatch { expected: [u8; 32], got: [u8; 32] },
+    /// The block or a header the check needs could not be read from the store
+    #[error("cannot be read: {0}")]
+    Unavailable(ChainError),
+}
+
+/// A full-chain validation failure and the height at which it occurred
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("block at height {height} is invalid: {kind}")]
+pub struct ChainValidationError {
+    pub height: u64,
+    pub kind: ChainValidationErrorKind,
+}
+
+/// Where a known block stands, from [`Blockchain::status_of`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockStatus {
+    /// On the active chain
+    Active,
+    /// In the block tree on a branch off the active chain
+    SideChain,
+    /// In the orphan pool, waiting for its parent
+    Orphan,
+    /// Marked invalid with [`Blockchain::mark_invalid`], or built on such a block
+    Invalid,
+}
+
+/// A change of the active chain caused by inserting a block.
+///
+/// Consumers keeping state derived from the active chain should undo the
+/// `disconnected` blocks in the order given, which is tip first, and then apply
+/// the `connected` blocks in order, which is parent first. Extending the tip is
+/// reported as a reorg with nothing disconnected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reorg {
+    /// Blocks removed from the active chain, from the old tip down
+    pub disconnected: Vec<BlockHash>,
+    /// Blocks added to the active chain, from just above the fork point up to the new tip
+    pub connected: Vec<BlockHash>,
+    /// The last block shared by the old and new active chains
+    pub fork_point: BlockHash,
+}
+
+impl Reorg {
+    /// Number of blocks disconnected from the old active chain
+    pub fn depth(&self) -> u64 {
+        self.disconnected.len() as u64
+    }
+}
+
+/// A tree of blocks rooted at a genesis block, with the best branch as the active chain.
+///
+/// Blocks can be inserted on any known parent; the active chain always follows
+/// [`BlockTree::best_tip`]. Height-based accessors and iteration read the
+/// active chain only.
+///
+/// Every accepted block is written to the store `S`, and the store's height
+/// index and tip follow the active chain. A chain reopened from a store holds
+/// only its tip in memory and fetches older blocks on first access. Accessors
+/// returning plain references treat a block the store fails to read as
+/// absent; [`Blockchain::try_get`], [`Blockchain::try_header`] and the
+/// methods returning a `Result` report why.
+///
+/// [`Blockchain::prune`] drops the bodies of old blocks and keeps their
+/// headers. Pruned heights have no [`Blockchain::get`] block and are skipped
+/// by iteration, but [`Blockchain::header`] still answers for them.
+pub struct Blockchain<S: ChainStore = MemoryStore> {
+    tree: BlockTree,
+    active: Vec<BlockHash>,
+    /// Height of every block on the active chain
+    heights: HashMap<BlockHash, u64>,
+    /// Active blocks below the tree's root, loaded from the store on demand
+    archived: Vec<OnceLock<Block>>,
+    /// Headers of the active blocks below the pruned height, loaded from the
+    /// store on demand
+    pruned: Vec<OnceLock<BlockHeader>>,
+    orphans: OrphanPool,
+    subscribers: Subscribers,
+    /// Transactions of the active chain by txid, if enabled
+    tx_index: Option<TxIndex>,
+    /// Unspent outputs at the tip, if tracked
+    utxos: Option<ChainUtxos>,
+    metrics: Option<Box<dyn ChainMetrics>>,
+    store: S,
+    validator: Validator,
+    max_reorg_depth: Option<u64>,
+    params: ChainParams,
+    /// Proof-of-work and signature checks run by `validate`
+    #[cfg(test)]
+    proof_checks: std::sync::atomic::AtomicU64,
+}
+
+impl Blockchain {
+    /// Start a chain from the genesis block derived from `params`, validating
+    /// every later block against them
+    #[allow(clippy::expect_used)]
+    pub fn new_from_params(params: &ChainParams) -> Self {
+        Self::create(params.genesis_block(), params.clone(), MemoryStore::new())
+            .expect("the memory store does not fail")
+    }
+
+    /// Start a chain from `genesis` that accepts any proof of work
+    pub fn new(genesis: Block) -> Self {
+        Self::with_difficulty(genesis, Difficulty::LeadingZeroBits(0))
+    }
+
+    /// Start a chain from `genesis` whose blocks must commit to and meet at
+    /// least `difficulty`.
+    ///
+    /// The genesis block itself is trusted and not checked. Its version,
+    /// timestamp, nonce, difficulty and transactions make up the chain's
+    /// [`ChainParams`], so that they derive it again, as long as it is one
+    /// [`ChainParams::genesis_block`] can derive: a first block, with no
+    /// state root, meeting its own difficulty. Later blocks may take any
+    /// version from the genesis block's on, any size and any timestamp, and
+    /// difficulty is not retargeted; use [`Blockchain::new_from_params`] for
+    /// a fully configured chain.
+    #[allow(clippy::expect_used)]
+    pub fn with_difficulty(genesis: Block, difficulty: Difficulty) -> Self {
+        let params = ChainParams {
+            genesis_transactions: genesis.transactions().to_vec(),
+            genesis_timestamp: genesis.timestamp(),
+            genesis_nonce: genesis.nonce(),
+            initial_difficulty: genesis.difficulty(),
+            consensus_mode: ConsensusMode::ProofOfWork { difficulty },
+            timestamp_rule: TimestampRule::Any,
+            median_time_span: 0,
+            max_future_drift: None,
+            retarget_interval: 0,
+            block_limits: BlockLimits {
+                max_bytes: usize::MAX,
+                max_transactions: usize::MAX,
+            },
+            allowed_versions: genesis.header().version()..=u32::MAX,
+            ..ChainParams::test_defaults()
+        };
+        Self::create(genesis, params, MemoryStore::new()).expect("the memory store does not fail")
+    }
+}
+
+impl<S: ChainStore> Blockchain<S> {
+    /// Open the chain held in `store`, or start one from the genesis block
+    /// derived from `params` if the store is empty.
+    ///
+    /// Only the tip is read up front. Blocks below it can be read but not
+    /// built on, so a reorg can never reach below the tip at open time.
+    pub fn open(params: &ChainParams, store: S) -> Result<Self, ChainError> {
+        let Some(tip) = store.get_tip()? else {
+            return Self::create(params.genesis_block(), params.clone(), store);
+        };
+
+        let expected = params.genesis_hash();
+        let got = store
+            .get_hash_at_height(0)?
+            .ok_or(StoreError::MissingHeight(0))?;
+        if got != expected {
+            return Err(ChainError::GenesisMismatch { expected, got });
+        }
+
+        let active = (0..=tip.height)
+            .map(|height| {
+                store
+                    .get_hash_at_height(height)?
+                    .ok_or(StoreError::MissingHeight(height))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let tip_block = store
+            .get_block(&tip.hash)?
+            .ok_or(StoreError::MissingBlock(tip.hash))?;
+        let store_pruned_height = store.pruned_height()?;
+
+        let mut chain = Self::from_parts(
+            BlockTree::with_root(tip_block, tip.height, tip.cumulative_work),
+            params.clone(),
+            store,
+        );
+        chain.archived = (0..tip.height).map(|_| OnceLock::new()).collect();
+        chain.pruned = (0..store_pruned_height).map(|_| OnceLock::new()).collect();
+        chain.set_active(0, &active);
+        for (&height, &expected) in params.checkpoints.range(..=tip.height) {
+            let got = chain.active[height as usize];
+            if got != expected {
+                return Err(ChainError::CheckpointViolation {
+                    height,
+                    expected,
+                    got,
+                });
+            }
+        }
+        Ok(chain)
+    }
+
+    /// Start a chain on `genesis` and record it in `store`
+    fn create(genesis: Block, params: ChainParams, mut store: S) -> Result<Self, ChainError> {
+        store.put_block(&genesis)?;
+        store.put_tip(0, &[genesis.hash()], genesis.difficulty().work())?;
+        Ok(Self::from_parts(BlockTree::new(genesis), params, store))
+    }
+
+    fn from_parts(tree: BlockTree, params: ChainParams, store: S) -> Self {
+        let validator = Validator::new()
+            .with_rule(VersionRule::new(params.allowed_versions.clone()))
+            .with_rule(BlockLimitsRule::new(params.block_limits))
+            .with_rule(MerkleRootRule);
+        let validator = match &params.consensus_mode {
+            ConsensusMode::ProofOfWork { difficulty } => {
+                validator.with_rule(CommittedDifficultyRule::new(*difficulty))
+            }
+            mode @ ConsensusMode::ProofOfAuthority { .. } => {
+                validator.with_rule(ConsensusRule::new(mode.clone()))
+            }
+        };
+
+        let root = tree.root();
+        let mut chain = Blockchain {
+            active: Vec::new(),
+            heights: HashMap::new(),
+            archived: Vec::new(),
+            pruned: Vec::new(),
+            orphans: OrphanPool::default(),
+            subscribers: Subscribers::default(),
+            tx_index: None,
+            utxos: None,
+            metrics: None,
+            tree,
+            store,
+            validator,
+            max_reorg_depth: None,
+            params,
+            #[cfg(test)]
+            proof_checks: Default::default(),
+        };
+        chain.set_active(0, &[root]);
+        chain
+    }
+
+    /// Replace the active chain from `from_height` up with `hashes`
+    fn set_active(&mut self, from_height: usize, hashes: &[BlockHash]) {
+        let mut tx_index = self.tx_index.take();
+        for hash in self.active.drain(from_height..) {
+            self.heights.remove(&hash);
+            if let Some(index) = &mut tx_index {
+                index.disconnect(&hash);
+            }
+        }
+        for (offset, hash) in hashes.iter().enumerate() {
+            self.heights.insert(*hash, (from_height + offset) as u64);
+        }
+        self.active.extend_from_slice(hashes);
+        if let Some(index) = &mut tx_index {
+            // Blocks joining the active chain are in the tree, just inserted or
+            // on the branch being switched to
+            for (offset, hash) in hashes.iter().enumerate() {
+                index.connect(self.block(hash), (from_height + offset) as u64);
+            }
+        }
+        self.tx_index = tx_index;
+    }
+
+    /// The backing store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    /// The parameters blocks are validated against
+    pub fn params(&self) -> &ChainParams {
+        &self.params
+    }
+
+    /// Refuse blocks whose branch would disconnect more than `depth` blocks
+    /// from the active chain. Reorgs are unlimited by default.
+    pub fn with_max_reorg_depth(mut self, depth: u64) -> Self {
+        self.max_reorg_depth = Some(depth);
+        self
+    }
+
+    /// The tip of the active chain
+    pub fn tip(&self) -> &Block {
+        self.block(&self.active[self.active.len() - 1])
+    }
+
+    /// Height of the tip; the genesis block is at height 0
+    pub fn height(&self) -> u64 {
+        self.active.len() as u64 - 1
+    }
+
+    /// The block at `height` on the active chain, if the chain is that long
+    /// and the block has not been pruned; see [`Blockchain::is_pruned`]
+    pub fn get(&self, height: u64) -> Option<&Block> {
+        self.try_get(height).ok().flatten()
+    }
+
+    /// [`Blockchain::get`], failing if the store cannot read the block
+    pub fn try_get(&self, height: u64) -> Result<Option<&Block>, ChainError> {
+        match usize::try_from(height) {
+            Ok(height) if (self.pruned.len()..self.active.len()).contains(&height) => {
+                self.block_at(height).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// The header at `height` on the active chain, whether or not the block
+    /// has been pruned
+    pub fn header(&self, height: u64) -> Option<&BlockHeader> {
+        self.try_header(height).ok().flatten()
+    }
+
+    /// [`Blockchain::header`], failing if the store cannot read the header
+    pub fn try_header(&self, height: u64) -> Result<Option<&BlockHeader>, ChainError> {
+        match usize::try_from(height) {
+            Ok(height) if height < self.active.len() => self.header_at(height).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    /// Whether the body of the active block at `height` has been pruned
+    pub fn is_pruned(&self, height: u64) -> bool {
+        height < self.pruned_height()
+    }
+
+    /// Height below which active blocks have been pruned; 0 if none have
+    pub fn pruned_height(&self) -> u64 {
+        self.pruned.len() as u64
+    }
+
+    /// Drop the transactions of every active block more than `keep_recent`
+    /// blocks below the tip, keeping their headers, and return how many
+    /// blocks were newly pruned.
+    ///
+    /// The bodies are deleted from the store as well. Side branches forking
+    /// below the new pruned height are dropped, and blocks or rollbacks that
+    /// would reach below it are refused with [`ChainError::BelowPrunedHeight`],
+    /// since the blocks a reorg disconnects may no longer be available.
+    pub fn prune(&mut self, keep_recent: u64) -> Result<u64, ChainError> {
+        let old_height = self.pruned_height();
+        let new_height = self.height().saturating_sub(keep_recent);
+        if new_height <= old_height {
+            return Ok(0);
+        }
+        let headers = (old_height..new_height)
+            .map(|height| self.header_at(height as usize).cloned())
+            .collect::<Result<Vec<_>, _>>()?;
+        self.store.prune_below(new_height)?;
+
+        let index = new_height as usize;
+        if new_height > self.stored(&self.tree.root()).height() {
+            self.tree.reroot(self.active[index]);
+            self.archived.resize_with(index, OnceLock::new);
+        }
+        for slot in self.archived.iter_mut().take(index) {
+            *slot = OnceLock::new();
+        }
+        if let Some(tx_index) = &mut self.tx_index {
+            for hash in &self.active[old_height as usize..index] {
+                tx_index.disconnect(hash);
+            }
+        }
+        if let Some(utxos) = &mut self.utxos {
+            for hash in &self.active[old_height as usize..index] {
+                utxos.forget(hash);
+            }
+        }
+        self.pruned.extend(headers.into_iter().map(OnceLock::from));
+        Ok(new_height - old_height)
+    }
+
+    /// Total work of the active chain from genesis to tip
+    pub fn total_work(&self) -> Work {
+        let tip = self.active[self.active.len() - 1];
+        Work(self.stored(&tip).cumulative_work())
+    }
+
+    /// Total work of the active chain from genesis up to and including
+    /// `height`, if the chain is that long.
+    ///
+    /// For a chain reopened from a store, heights below the loaded root are
+    /// worked out by subtracting the work of the blocks above them, which
+    /// loads their headers; `None` if the store cannot.
+    pub fn work_at(&self, height: u64) -> Option<Work> {
+        let index = usize::try_from(height)
+            .ok()
+            .filter(|&h| h < self.active.len())?;
+        self.work_at_index(index).ok()
+    }
+
+    /// [`Blockchain::work_at`] for the active height `index`
+    fn work_at_index(&self, index: usize) -> Result<Work, ChainError> {
+        if let Some(stored) = self.tree.get(&self.active[index]) {
+            return Ok(Work(stored.cumulative_work()));
+        }
+        let root = self.stored(&self.tree.root());
+        let above = (index + 1..root.height() as usize).try_fold(
+            root.block().difficulty().work(),
+            |work, height| -> Result<u128, ChainError> {
+                Ok(work.saturating_add(self.header_at(height)?.difficulty().work()))
+            },
+        )?;
+        Ok(Work(root.cumulative_work().saturating_sub(above)))
+    }
+
+    /// The block with `hash` if it is on the active chain.
+    ///
+    /// Blocks on side branches are not reported here; look them up in
+    /// [`Blockchain::tree`] instead.
+    pub fn get_by_hash(&self, hash: &[u8]) -> Option<&Block> {
+        self.get(self.height_of(hash)?)
+    }
+
+    /// Height of the block with `hash` if it is on the active chain
+    pub fn height_of(&self, hash: &[u8]) -> Option<u64> {
+        let hash = BlockHash::try_from(hash).ok()?;
+        self.heights.get(&hash).copied()
+    }
+
+    /// Whether the block with `hash` is on the active chain
+    pub fn contains(&self, hash: &[u8]) -> bool {
+        self.height_of(hash).is_some()
+    }
+
+    /// Blocks on the active chain from genesis, or the lowest unpruned
+    /// height, to tip, each read from the store on first access. A block the
+    /// store cannot read is reported in its place and iteration goes on.
+    pub fn try_iter(&self) -> TryIter<'_, S> {
+        TryIter {
+            chain: self,
+            front: self.pruned.len(),
+            back: self.active.len(),
+        }
+    }
+
+    /// Blocks on the active chain at the heights in `heights`, read like
+    /// [`Blockchain::try_iter`].
+    ///
+    /// Bounds follow [`slice::get`]: `try_range(h..h)` and
+    /// `try_range(len..)` are empty, and a range that ends past the tip or
+    /// starts after it ends is `None`. So is a non-empty range that starts
+    /// below the pruned height.
+    pub fn try_range(&self, heights: impl RangeBounds<u64>) -> Option<TryIter<'_, S>> {
+        let len = self.active.len();
+        let index = |height: u64| usize::try_from(height).unwrap_or(usize::MAX);
+        let front = match heights.start_bound() {
+            Bound::Included(&start) => index(start),
+            Bound::Excluded(&start) => index(start).saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let back = match heights.end_bound() {
+            Bound::Included(&end) => index(end).saturating_add(1),
+            Bound::Excluded(&end) => index(end),
+            Bound::Unbounded => len,
+        };
+        if back > len || front > back || (front < self.pruned.len() && front != back) {
+            return None;
+        }
+        Some(TryIter {
+            chain: self,
+            front,
+            back,
+        })
+    }
+
+    /// Every known block, including those on side branches
+    pub fn tree(&self) -> &BlockTree {
+        &self.tree
+    }
+
+    /// Bound the orphan pool to `max_count` blocks and `max_bytes` of
+    /// encoded blocks, replacing the defaults of [`DEFAULT_MAX_ORPHANS`] and
+    /// [`DEFAULT_MAX_ORPHAN_BYTES`] and dropping any orphans already held
+    pub fn with_orphan_limits(mut self, max_count: usize, max_bytes: usize) -> Self {
+        self.orphans = OrphanPool::new(max_count, max_bytes);
+        self
+    }
+
+    /// Receive every later change to the active chain; see [`ChainEvent`]
+    /// for the order events arrive in.
+    ///
+    /// The chain never blocks on a subscriber. One that falls
+    /// [`DEFAULT_SUBSCRIBER_CAPACITY`] events behind is dropped, so its
+    /// receiver reports disconnection once drained and the subscriber should
+    /// resynchronise from the chain and subscribe again.
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        self.subscribe_with_capacity(DEFAULT_SUBSCRIBER_CAPACITY)
+    }
+
+    /// Like [`Blockchain::subscribe`], buffering up to `capacity` events
+    pub fn subscribe_with_capacity(&mut self, capacity: usize) -> Receiver<ChainEvent> {
+        self.subscribers.subscribe(capacity)
+    }
+
+    /// Report the chain's activity to `metrics` from now on, replacing any
+    /// metrics set before
+    pub fn set_metrics(&mut self, metrics: Box<dyn ChainMetrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    /// The time a block's checks started, if metrics or traces want to know
+    /// how long they took
+    fn start_timer(&self) -> Option<Instant> {
+        (cfg!(feature = "tracing") || self.metrics.is_some()).then(Instant::now)
+    }
+
+    /// Number of blocks waiting for their parent
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.len()
+    }
+
+    /// Blocks waiting for their parent, oldest first
+    pub fn orphans(&self) -> impl Iterator<Item = &Block> {
+        self.orphans.iter()
+    }
+
+    /// Validate `block` against the tip and append it
+    pub fn append(&mut self, block: Block) -> Result<(), ChainError> {
+        let _span = trace::span!(
+            "append",
+            hash = %trace::short(block.hash()),
+            prev = %trace::short(block.prev_block_hash())
+        );
+        let started = self.start_timer();
+        let checked = self.validator.validate(&block);
+        self.append_checked(block, checked, started)
+            .inspect_err(|_err| {
+                trace::event!(info, error = %_err, "block rejected");
+            })
+    }
+
+    /// Append `block`, whose context-free validation gave `checked`
+    fn append_checked(
+        &mut self,
+        block: Block,
+        checked: Result<(), ValidationError>,
+        started: Option<Instant>,
+    ) -> Result<(), ChainError> {
+        let expected = self.tip().hash();
+        if block.prev_block_hash() != expected {
+            return Err(ChainError::PrevHashMismatch {
+                expected,
+                got: block.prev_block_hash(),
+            });
+        }
+        checked?;
+
+        self.insert_checked(block, started).map(|_| ())
+    }
+
+    /// Validate `block` and attach it to any known parent.
+    ///
+    /// If the block's branch becomes the best one, the active chain switches to
+    /// it and the change is returned; a block landing on a side branch returns
+    /// `None`. A switch deeper than the configured maximum is refused with
+    /// [`ChainError::ReorgTooDeep`] and the block is not stored.
+    ///
+    /// A block whose parent is unknown is held in the orphan pool and
+    /// [`ChainError::UnknownParent`] is returned. Once a block connects, any
+    /// orphans building on it are connected too, and the returned change
+    /// covers all of them. Orphans that fail to connect are dropped along with
+    /// their descendants.
+    ///
+    /// A block building on a pruned block is refused with
+    /// [`ChainError::BelowPrunedHeight`].
+    pub fn insert(&mut self, block: Block) -> Result<Option<Reorg>, ChainError> {
+        let _span = trace::span!(
+            "insert",
+            hash = %trace::short(block.hash()),
+            prev = %trace::short(block.prev_block_hash())
+        );
+        let started = self.start_timer();
+        self.validator
+            .validate(&block)
+            .map_err(ChainError::from)
+            .and_then(|()| self.insert_checked(block, started))
+            .inspect_err(|_err| {
+                trace::event!(info, error = %_err, "block rejected");
+            })
+    }
+
+    /// Insert `block`, which has passed the validator
+    fn insert_checked(
+        &mut self,
+        block: Block,
+        started: Option<Instant>,
+    ) -> Result<Option<Reorg>, ChainError> {
+        let parent = block.prev_block_hash();
+        if !self.tree.contains(&parent) && !self.tree.contains(&block.hash()) {
+            if let Some(&height) = self.heights.get(&parent) {
+                if height < self.pruned_height() {
+                    return Err(ChainError::BelowPrunedHeight {
+                        height,
+                        pruned_height: self.pruned_height(),
+                    });
+                }
+            }
+            self.orphans.insert(block);
+            if let Some(metrics) = &self.metrics {
+                metrics.orphan_pool_size(self.orphans.len());
+            }
+            return Err(ChainError::UnknownParent(parent));
+        }
+
+        let old_tip = self.active[self.active.len() - 1];
+        let hash = self.connect(block, started)?;
+        let orphans = self.orphans.len();
+        self.connect_orphans(hash);
+        let reorg = self.reorg_since(old_tip);
+        if let Some(reorg) = &reorg {
+            let fork_height = self.heights[&reorg.fork_point];
+            self.subscribers.send_reorg(reorg, fork_height);
+            if reorg.depth() > 0 {
+                trace::event!(
+                    debug,
+                    depth = reorg.depth(),
+                    fork_height,
+                    fork_point = %trace::short(reorg.fork_point),
+                    old_tip = %trace::short(old_tip),
+                    new_tip = %trace::short(self.tip().hash()),
+                    "reorg"
+                );
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            if self.orphans.len() != orphans {
+                metrics.orphan_pool_size(self.orphans.len());
+            }
+            if let Some(reorg) = reorg.as_ref().filter(|reorg| reorg.depth() > 0) {
+                metrics.reorg(reorg.depth());
+            }
+        }
+        Ok(reorg)
+    }
+
+    /// Attach a validated block to its parent in the tree, switching the
+    /// active chain if it becomes the best tip
+    fn connect(&mut self, block: Block, started: Option<Instant>) -> Result<BlockHash, ChainError> {
+        let stored = self.tree.prepare(block)?;
+        let (height, tx_count) = (stored.height(), stored.block().transactions().len());
+        if let Some(expected) = self.pinned_hash(stored.height()) {
+            if expected != stored.hash() {
+                return Err(ChainError::CheckpointViolation {
+                    height: stored.height(),
+                    expected,
+                    got: stored.hash(),
+                });
+            }
+        }
+        let parent = self.block(&stored.block().prev_block_hash());
+        let (parent_timestamp, timestamp) = (parent.timestamp(), stored.block().timestamp());
+        if !self
+            .params
+            .timestamp_rule
+            .allows(parent_timestamp, timestamp)
+        {
+            return Err(ChainError::InvalidTimestamp {
+                parent: parent_timestamp,
+                got: timestamp,
+            });
+        }
+        let mode = &self.params.consensus_mode;
+        if let (Some(parent), Some(got)) = (mode.slot(parent_timestamp), mode.slot(timestamp)) {
+            if got <= parent {
+                return Err(ChainError::SlotNotAfterParent { parent, got });
+            }
+        }
+        if self.params.median_time_span != 0 {
+            let median_time_past =
+                self.median_time_past_of(stored.block().prev_block_hash(), stored.height() - 1)?;
+            if timestamp <= median_time_past {
+                return Err(ChainError::TimestampTooOld {
+                    median_time_past,
+                    got: timestamp,
+                });
+            }
+        }
+        if let Some(drift) = self.params.max_future_drift {
+            let max = self.params.clock.now().saturating_add(drift.as_secs());
+            if timestamp > max {
+                return Err(ChainError::TimestampTooNew {
+                    max,
+                    got: timestamp,
+                });
+            }
+        }
+        let expected = if self.params.retarget_interval != 0 {
+            Some(self.required_difficulty(stored.block().prev_block_hash(), stored.height())?)
+        } else {
+            // Under a signing rotation, the committed work tells in-turn
+            // blocks from out-of-turn ones
+            self.params
+                .consensus_mode
+                .required_authority_difficulty(Some(stored.block()))
+        };
+        if let Some(expected) = expected {
+            let (expected, got) = (expected.to_compact(), stored.block().header().bits());
+            if got != expected {
+                return Err(ChainError::UnexpectedDifficulty { expected, got });
+            }
+        }
+        if !self.tree.beats_best(&stored) {
+            self.store.put_block(stored.block())?;
+            let hash = self.tree.store(stored);
+            self.report_connected(hash, height, tx_count, started);
+            return Ok(hash);
+        }
+
+        let reorg = self.plan_reorg(&stored);
+        if let Some(max) = self.max_reorg_depth {
+            if reorg.depth() > max {
+                return Err(ChainError::ReorgTooDeep {
+                    depth: reorg.depth(),
+                    max,
+                });
+            }
+        }
+
+        self.switch_utxos(&reorg, stored.block())?;
+
+        let fork_height = self.active.len() - reorg.disconnected.len();
+        self.store.put_block(stored.block())?;
+        self.store.put_tip(
+            fork_height as u64,
+            &reorg.connected,
+            stored.cumulative_work(),
+        )?;
+        let hash = self.tree.store(stored);
+        self.set_active(fork_height, &reorg.connected);
+        self.report_connected(hash, height, tx_count, started);
+        Ok(hash)
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn report_connected(
+        &self,
+        hash: BlockHash,
+        height: u64,
+        tx_count: usize,
+        started: Option<Instant>,
+    ) {
+        trace::event!(
+            debug,
+            height,
+            hash = %trace::short(hash),
+            txs = tx_count,
+            elapsed = ?started.map(|started| started.elapsed()).unwrap_or_default(),
+            "block connected"
+        );
+        if let (Some(metrics), Some(started)) = (&self.metrics, started) {
+            metrics.block_connected(height, tx_count, started.elapsed());
+        }
+    }
+
+    /// Connect every orphan descending from the newly connected `hash`
+    fn connect_orphans(&mut self, hash: BlockHash) {
+        let mut connected = vec![hash];
+        let mut rejected = Vec::new();
+        while let Some(parent) = connected.pop() {
+            for orphan in self.orphans.take_children(&parent) {
+                let hash = orphan.hash();
+                match self.connect(orphan, self.start_timer()) {
+                    Ok(hash) => connected.push(hash),
+                    Err(_) => rejected.push(hash),
+                }
+            }
+        }
+        while let Some(parent) = rejected.pop() {
+            let children = self.orphans.take_children(&parent);
+            rejected.extend(children.iter().map(Block::hash));
+        }
+    }
+
+    /// The change from an active chain that ended at `old_tip` to the current one
+    #[allow(clippy::expect_used)]
+    fn reorg_since(&self, old_tip: BlockHash) -> Option<Reorg> {
+        if self.active[self.active.len() - 1] == old_tip {
+            return None;
+        }
+        let mut disconnected = Vec::new();
+        let mut fork_point = old_tip;
+        while !self.heights.contains_key(&fork_point) {
+            disconnected.push(fork_point);
+            fork_point = self
+                .tree
+                .get(&fork_point)
+                .and_then(StoredBlock::parent)
+                .expect("the old tip's branch joins the active chain in the tree");
+        }
+        let fork_height = self.heights[&fork_point] as usize;
+        Some(Reorg {
+            disconnected,
+            connected: self.active[fork_height + 1..].to_vec(),
+            fork_point,
+        })
+    }
+
+    /// Disconnect every block above `height` and make the block there the tip.
+    ///
+    /// Returns the disconnected blocks from the old tip down. They and any
+    /// side branches built on them are dropped from the tree, and the store's
+    /// index and tip move back in one update. Side branches forking lower down
+    /// are kept and will take over again if extended past the new tip's work.
+    /// Heights below the pruned height cannot be rolled back to.
+    pub fn rollback_to(&mut self, height: u64) -> Result<Vec<Block>, ChainError> {
+        let tip = self.height();
+        if height > tip {
+            return Err(ChainError::RollbackOutOfRange { height, tip });
+        }
+        if height < self.pruned_height() {
+            return Err(ChainError::BelowPrunedHeight {
+                height,
+                pruned_height: self.pruned_height(),
+            });
+        }
+        let index = height as usize;
+        let disconnected = (index + 1..self.active.len())
+            .rev()
+            .map(|height| self.block_at(height).cloned())
+            .collect::<Result<Vec<_>, _>>()?;
+        if disconnected.is_empty() {
+            return Ok(disconnected);
+        }
+
+        let new_tip = self.active[index];
+        let work = self.work_at_index(index)?;
+        // If the new tip is archived, the tree restarts from it
+        let restart = if height < self.stored(&self.tree.root()).height() {
+            Some(self.block_at(index)?.clone())
+        } else {
+            None
+        };
+        self.store.put_tip(height, &[new_tip], work.0)?;
+        match restart {
+            Some(block) => {
+                self.archived.truncate(index);
+                self.tree = BlockTree::with_root(block, height, work.0);
+            }
+            None => self.tree.rewind(new_tip, self.active[index + 1]),
+        }
+        if let Some(utxos) = &mut self.utxos {
+            for block in &disconnected {
+                utxos.disconnect(&block.hash());
+            }
+        }
+        self.set_active(index + 1, &[]);
+        for (block, height) in disconnected.iter().zip((height + 1..=tip).rev()) {
+            self.subscribers.send(ChainEvent::Disconnected {
+                hash: block.hash(),
+                height,
+            });
+        }
+        Ok(disconnected)
+    }
+
+    /// Where the block with `hash` stands, or `None` if it is unknown.
+    ///
+    /// Blocks marked invalid report [`BlockStatus::Invalid`] even once they
+    /// have left the tree.
+    pub fn status_of(&self, hash: &[u8]) -> Option<BlockStatus> {
+        let hash = BlockHash::try_from(hash).ok()?;
+        if self.tree.is_invalid(&hash) {
+            Some(BlockStatus::Invalid)
+        } else if self.heights.contains_key(&hash) {
+            Some(BlockStatus::Active)
+        } else if self.tree.contains(&hash) {
+            Some(BlockStatus::SideChain)
+        } else if self.orphans.contains(&hash) {
+            Some(BlockStatus::Orphan)
+        } else {
+            None
+        }
+    }
+
+    /// Every branch off the active chain, from its first block above the
+    /// active chain to its tip, best tip first.
+    ///
+    /// Branches sharing blocks below a fork of their own each list those
+    /// blocks. Invalid branches are included; see [`Blockchain::status_of`].
+    pub fn side_chains(&self) -> Vec<Vec<BlockHash>> {
+        self.tree
+            .tips()
+            .into_iter()
+            .filter(|tip| !self.heights.contains_key(tip))
+            .map(|tip| {
+                let mut branch = Vec::new();
+                let mut current = Some(tip);
+                while let Some(hash) = current.filter(|hash| !self.heights.contains_key(hash)) {
+                    branch.push(hash);
+                    current = self.tree.get(&hash).and_then(StoredBlock::parent);
+                }
+                branch.reverse();
+                branch
+            })
+            .collect()
+    }
+
+    /// Mark the block with `hash` invalid, so that neither it nor any block
+    /// built on it is accepted or chosen as the tip again.
+    ///
+    /// Marked side-chain blocks stay in the tree for inspection. A block on
+    /// the active chain is rolled back as by [`Blockchain::rollback_to`] its
+    /// parent first, and the disconnected blocks are returned; its heaviest
+    /// valid competitor takes over once it is extended past the new tip.
+    pub fn mark_invalid(&mut self, hash: BlockHash) -> Result<Vec<Block>, ChainError> {
+        let disconnected = match self.heights.get(&hash) {
+            Some(0) => return Err(ChainError::GenesisMarkedInvalid),
+            Some(&height) => self.rollback_to(height - 1)?,
+            None => Vec::new(),
+        };
+        self.tree.mark_invalid(hash);
+        Ok(disconnected)
+    }
+
+    /// Pin `hash` at `height` as if it were one of the params' checkpoints,
+    /// on the word of the checkpoint committee.
+    ///
+    /// `cert` must carry signatures over `hash` from at least
+    /// [`ChainParams::checkpoint_threshold`] members of
+    /// [`ChainParams::checkpoint_committee`]. The checkpoint is refused if
+    /// the active chain or an existing checkpoint already fixes a different
+    /// block at `height`. Like the params' checkpoints, it binds only this
+    /// chain and is not kept in the store.
+    pub fn add_certified_checkpoint(
+        &mut self,
+        height: u64,
+        hash: BlockHash,
+        cert: &CheckpointCert,
+    ) -> Result<(), ChainError> {
+        if !cert.verify(
+            &hash,
+            &self.params.checkpoint_committee,
+            self.params.checkpoint_threshold,
+        ) {
+            return Err(ChainError::InvalidCheckpointCert(hash));
+        }
+        let pinned = self
+            .pinned_hash(height)
+            .or_else(|| self.active.get(height as usize).copied());
+        if let Some(expected) = pinned.filter(|&expected| expected != hash) {
+            return Err(ChainError::CheckpointViolation {
+                height,
+                expected,
+                got: hash,
+            });
+        }
+        self.params.checkpoints.insert(height, hash);
+        Ok(())
+    }
+
+    /// Leaf blocks of the tree, best first
+    pub fn tips(&self) -> Vec<BlockHash> {
+        self.tree.tips()
+    }
+
+    /// The tip with the most cumulative work; see [`BlockTree::best_tip`]
+    pub fn best_tip(&self) -> BlockHash {
+        self.tree.best_tip()
+    }
+
+    /// Headers from genesis to the best tip, failing if the store cannot read
+    /// one of them
+    pub fn best_chain(&self) -> Result<Vec<&BlockHeader>, ChainError> {
+        (0..self.active.len())
+            .map(|height| self.header_at(height))
+            .collect()
+    }
+
+    /// The hash the checkpoints fix at `height`: the checkpoint there, or the
+    /// active block there once the active chain has passed a higher
+    /// checkpoint, since that checkpoint pins all of its ancestors
+    fn pinned_hash(&self, height: u64) -> Option<BlockHash> {
+        let passed = self
+            .params
+            .checkpoints
+            .range(..self.active.len() as u64)
+            .next_back();
+        match passed {
+            Some((&checkpoint, _)) if height <= checkpoint => Some(self.active[height as usize]),
+            _ => self.params.checkpoints.get(&height).copied(),
+        }
+    }
+
+    /// Walk from a new tip back to the active chain to find what would change
+    #[allow(clippy::expect_used)]
+    fn plan_reorg(&self, tip: &StoredBlock) -> Reorg {
+        let mut connected = vec![tip.hash()];
+        let mut current = self
+            .tree
+            .get(&tip.parent().expect("a new tip has a parent"))
+            .expect("parents are in the tree");
+        while self.active.get(current.height() as usize) != Some(&current.hash()) {
+            connected.push(current.hash());
+            let parent = current.parent().expect("genesis is on every branch");
+            current = self.tree.get(&parent).expect("parents are in the tree");
+        }
+        connected.reverse();
+
+        let fork_height = current.height() as usize;
+        Reorg {
+            disconnected: self.active[fork_height + 1..]
+                .iter()
+                .rev()
+                .copied()
+                .collect(),
+            connected,
+            fork_point: current.hash(),
+        }
+    }
+
+    /// Median timestamp of the tip and the blocks below it, up to
+    /// [`ChainParams::median_time_span`] blocks in all; the upper median for
+    /// an even count, and the tip's own timestamp if the span is zero.
+    ///
+    /// A block appended to the tip must be stamped later than this. Fails if
+    /// the store cannot read the headers it needs.
+    pub fn median_time_past(&self) -> Result<u64, ChainError> {
+        let tip = self.active[self.active.len() - 1];
+        self.median_time_past_of(tip, self.height())
+    }
+
+    /// [`Blockchain::median_time_past`] for the branch ending at `hash`,
+    /// which sits at `height` in the tree
+    fn median_time_past_of(&self, hash: BlockHash, height: u64) -> Result<u64, ChainError> {
+        let span = self.params.median_time_span.max(1) as u64;
+        let mut timestamps = Vec::with_capacity(span as usize);
+        let mut next = Some(hash);
+        for height in (height.saturating_sub(span - 1)..=height).rev() {
+            let timestamp = match next.and_then(|hash| self.tree.get(&hash)) {
+                Some(entry) => {
+                    next = entry.parent();
+                    entry.block().timestamp()
+                }
+                None => self.header_at(height as usize)?.timestamp(),
+            };
+            timestamps.push(timestamp);
+        }
+        timestamps.sort_unstable();
+        Ok(timestamps[timestamps.len() / 2])
+    }
+
+    /// The difficulty a block appended to the tip must commit to under the
+    /// retargeting rule, the tip's own unless the next height retargets.
+    /// Fails if the store cannot read the headers retargeting looks back on.
+    pub fn next_block_difficulty(&self) -> Result<Difficulty, ChainError> {
+        let tip = self.active[self.active.len() - 1];
+        self.required_difficulty(tip, self.height() + 1)
+    }
+
+    /// The difficulty a block on `parent` at `height` must commit to under
+    /// the retargeting rule
+    fn required_difficulty(
+        &self,
+        parent: BlockHash,
+        height: u64,
+    ) -> Result<Difficulty, ChainError> {
+        if !is_retarget_height(height, &self.params) {
+            return Ok(self.block(&parent).difficulty());
+        }
+
+        // Walk back through the tree, then down the archived part of the active chain
+        let interval = self.params.retarget_interval;
+        let mut window = Vec::with_capacity(interval as usize);
+        let mut next = Some(parent);
+        for height in (height - interval..height).rev() {
+            let header = match next.and_then(|hash| self.tree.get(&hash)) {
+                Some(entry) => {
+                    next = entry.parent();
+                    entry.block().header()
+                }
+                None => self.header_at(height as usize)?,
+            };
+            window.push(header.clone());
+        }
+        window.reverse();
+        // The window holds `retarget_interval` headers, never none
+        let next = next_difficulty(&window, &self.params);
+        Ok(next.unwrap_or_else(|| self.block(&parent).difficulty()))
+    }
+
+    /// A block known to be in the tree: the tree holds every block from its
+    /// root up, including the tip and each branch being walked, so the lookup
+    /// fails only if that invariant is broken
+    #[allow(clippy::expect_used)]
+    fn stored(&self, hash: &BlockHash) -> &StoredBlock {
+        self.tree.get(hash).expect("the block is in the tree")
+    }
+
+    /// A block known to be in the tree; see [`Blockchain::stored`]
+    fn block(&self, hash: &BlockHash) -> &Block {
+        self.stored(hash).block()
+    }
+
+    /// The active block at `height`, which must be in range, read from the
+    /// store on first access if it is below the tree's root
+    fn block_at(&self, height: usize) -> Result<&Block, ChainError> {
+        if height < self.pruned.len() {
+            return Err(ChainError::BelowPrunedHeight {
+                height: height as u64,
+                pruned_height: self.pruned_height(),
+            });
+        }
+        let hash = &self.active[height];
+        let Some(slot) = self.archived.get(height) else {
+            return Ok(self.block(hash));
+        };
+        if let Some(block) = slot.get() {
+            return Ok(block);
+        }
+        let block = self
+            .store
+            .get_block(hash)?
+            .ok_or(StoreError::MissingBlock(*hash))?;
+        Ok(slot.get_or_init(|| block))
+    }
+
+    /// The header of the active block at `height`, which must be in range,
+    /// read from the store on first access if the block has been pruned
+    fn header_at(&self, height: usize) -> Result<&BlockHeader, ChainError> {
+        let Some(slot) = self.pruned.get(height) else {
+            return Ok(self.block_at(height)?.header());
+        };
+        if let Some(header) = slot.get() {
+            return Ok(header);
+        }
+        let hash = &self.active[height];
+        let header = self
+            .store
+            .get_header(hash)?
+            .ok_or(StoreError::MissingBlock(*hash))?;
+        Ok(slot.get_or_init(|| header))
+    }
+
+    /// Check every block and link from genesis to tip against `params`.
+    ///
+    /// Each block is hashed once and nothing is cloned, so this streams over
+    /// arbitrarily long chains. Pruned blocks are checked by their headers
+    /// alone: links, checkpoints, versions, difficulty, proof of work and
+    /// timestamps still are, while transactions, size limits and authority
+    /// signatures went with their bodies.
+    pub fn validate(&self, params: &ChainParams) -> Result<(), ChainValidationError> {
+        let fail = |height: u64, kind| Err(ChainValidationError { height, kind });
+        let unavailable = |height: u64| {
+            move |err| ChainValidationError {
+                height,
+                kind: ChainValidationErrorKind::Unavailable(err),
+            }
+        };
+
+        let genesis = self.header_at(0).map_err(unavailable(0))?;
+        let mut parent_hash = genesis.hash();
+        let genesis_hash = params.genesis_hash();
+        if parent_hash != genesis_hash {
+            return fail(
+                0,
+                ChainValidationErrorKind::GenesisMismatch {
+                    expected: genesis_hash,
+                    got: parent_hash,
+                },
+            );
+        }
+
+        // Blocks up to the highest checkpoint on this chain are pinned by its hash
+        let trusted_height = params
+            .checkpoints
+            .range(..self.active.len() as u64)
+            .rev()
+            .find(|&(&height, hash)| self.active[height as usize] == *hash)
+            .filter(|_| params.trust_checkpoints)
+            .map_or(0, |(&height, _)| height);
+
+        let limits = BlockLimitsRule::new(params.block_limits);
+        let mut parent_timestamp = genesis.timestamp();
+        for height in 1..self.active.len() as u64 {
+            let header = self
+                .header_at(height as usize)
+                .map_err(unavailable(height))?;
+            let body = self.try_get(height).map_err(unavailable(height))?;
+            let hash = header.hash();
+            if let Some(&expected) = params.checkpoints.get(&height) {
+                if hash != expected {
+                    return fail(
+                        height,
+                        ChainValidationErrorKind::CheckpointViolation {
+                            expected,
+                            got: hash,
+                        },
+                    );
+                }
+            }
+            let version = header.version();
+            if !params.allowed_versions.contains(&version) {
+                return fail(
+                    height,
+                    ChainValidationErrorKind::UnsupportedVersion(version),
+                );
+            }
+            if let Some(Err(err)) = body.map(|block| limits.check(block)) {
+                return fail(height, ChainValidationErrorKind::ExceedsBlockLimits(err));
+            }
+            if header.prev_block_hash() != parent_hash {
+                return fail(
+                    height,
+                    ChainValidationErrorKind::PrevHashMismatch {
+                        expected: parent_hash,
+                        got: header.prev_block_hash(),
+                    },
+                );
+            }
+            if body.is_some_and(|block| !block.verify_merkle_root()) {
+                return fail(height, ChainValidationErrorKind::MerkleRootMismatch);
+            }
+
+            let expected = if is_retarget_height(height, params) {
+                let start = (height - params.retarget_interval) as usize;
+                let window = (start..height as usize)
+                    .map(|height| self.header_at(height).cloned())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(unavailable(height))?;
+                next_difficulty(&window, params)
+            } else if params.retarget_interval != 0 {
+                let parent = self.header_at(height as usize - 1);
+                Some(parent.map_err(unavailable(height))?.difficulty())
+            } else {
+                params.consensus_mode.required_authority_difficulty(body)
+            };
+            if let Some(expected) = expected {
+                let (expected, got) = (expected.to_compact(), header.bits());
+                if expected != got {
+                    return fail(
+                        height,
+                        ChainValidationErrorKind::UnexpectedDifficulty { expected, got },
+                    );
+                }
+            }
+
+            if height > trusted_height {
+                #[cfg(test)]
+                self.proof_checks
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                match &params.consensus_mode {
+                    ConsensusMode::ProofOfWork { difficulty } => {
+                        if header.difficulty().to_target() > difficulty.normalized().to_target() {
+                            return fail(height, ChainValidationErrorKind::DifficultyBelowMinimum);
+                        }
+                        if !header.difficulty().is_met_by(hash.as_bytes()) {
+                            return fail(height, ChainValidationErrorKind::InsufficientProofOfWork);
+                        }
+                    }
+                    ConsensusMode::ProofOfAuthority { authorities, .. } => {
+                        if body.is_some_and(|block| !block.verify_signature(authorities)) {
+                            return fail(
+                                height,
+                                ChainValidationErrorKind::InvalidAuthoritySignature,
+                            );
+                        }
+                    }
+                }
+            }
+
+            if !params
+                .timestamp_rule
+                .allows(parent_timestamp, header.timestamp())
+            {
+                return fail(
+                    height,
+                    ChainValidationErrorKind::InvalidTimestamp {
+                        parent: parent_timestamp,
+                        got: header.timestamp(),
+                    },
+                );
+            }
+            let mode = &params.consensus_mode;
+            if let (Some(parent), Some(got)) =
+                (mode.slot(parent_timestamp), mode.slot(header.timestamp()))
+            {
+                if got <= parent {
+                    return fail(
+                        height,
+                        ChainValidationErrorKind::SlotNotAfterParent { parent, got },
+                    );
+                }
+            }
+
+            parent_hash = hash;
+            parent_timestamp = header.timestamp();
+        }
+
+        Ok(())
+    }
+
+    /// Replace the block at `height` without any checks, to simulate corruption
+    #[cfg(test)]
+    fn corrupt(&mut self, height: usize, block: Block) {
+        let hash = block.hash();
+        self.tree
+            .insert_unchecked(block, self.active[height - 1], height as u64);
+        self.heights.remove(&self.active[height]);
+        self.heights.insert(hash, height as u64);
+        self.active[height] = hash;
+    }
+}
+
+impl<S: InfallibleStore> Blockchain<S> {
+    /// Blocks on the active chain from genesis, or the lowest unpruned
+    /// height, to tip
+    pub fn iter(&self) -> Iter<'_, S> {
+        Iter(self.try_iter())
+    }
+
+    /// Blocks on the active chain from tip back to genesis, or the lowest
+    /// unpruned height
+    pub fn iter_rev(&self) -> Rev<Iter<'_, S>> {
+        self.iter().rev()
+    }
+
+    /// Blocks on the active chain at the heights in `heights`, bounded like
+    /// [`Blockchain::try_range`]
+    pub fn range(&self, heights: impl RangeBounds<u64>) -> Option<Iter<'_, S>> {
+        self.try_range(heights).map(Iter)
+    }
+}
+
+impl<'a, S: InfallibleStore> IntoIterator for &'a Blockchain<S> {
+    type Item = &'a Block;
+    type IntoIter = Iter<'a, S>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator over the blocks of the active chain and the errors reading
+/// them, created by [`Blockchain::try_iter`]
+pub struct TryIter<'a, S: ChainStore = MemoryStore> {
+    chain: &'a Blockchain<S>,
+    front: usize,
+    back: usize,
+}
+
+impl<'a, S: ChainStore> Iterator for TryIter<'a, S> {
+    type Item = Result<&'a Block, ChainError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.front += 1;
+        Some(self.chain.block_at(self.front - 1))
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.front = self.front.saturating_add(n).min(self.back);
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl<S: ChainStore> DoubleEndedIterator for TryIter<'_, S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.chain.block_at(self.back))
+    }
+}
+
+impl<S: ChainStore> ExactSizeIterator for TryIter<'_, S> {}
+
+/// Iterator over the blocks of the active chain of a store whose reads
+/// cannot fail, created by [`Blockchain::iter`]
+pub struct Iter<'a, S: InfallibleStore = MemoryStore>(TryIter<'a, S>);
+
+impl<'a, S: InfallibleStore> Iterator for Iter<'a, S> {
+    type Item = &'a Block;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Heights start at or above the pruned height and the store reads
+        // every block, so there is no error to drop
+        self.0.next()?.ok()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.0.nth(n)?.ok()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<S: InfallibleStore> DoubleEndedIterator for Iter<'_, S> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.0.next_back()?.ok()
+    }
+}
+
+impl<S: InfallibleStore> ExactSizeIterator for Iter<'_, S> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::crypto::{PublicKey, Signer};
+    use crate::store::{FileStore, TempDir};
+    use crate::testing;
+    use proptest::prelude::*;
+    use std::time::Duration;
+
+    const DIFFICULTY: Difficulty = Difficulty::LeadingZeroBits(8);
+
+    fn genesis() -> Block {
+        BlockBuilder::new(BlockHash::ZERO)
+            .transaction(b"genesis".to_vec())
+            .timestamp(1_700_000_000)
+            .build()
+            .unwrap()
+    }
+
+    pub(super) fn mined_child(parent: &Block, tx: &[u8]) -> Block {
+        mined_child_at(parent, tx, DIFFICULTY)
+    }
+
+    fn mined_child_at(parent: &Block, tx: &[u8], difficulty: Difficulty) -> Block {
+        let mut block = parent
+            .next_builder()
+            .transaction(tx.to_vec())
+            .difficulty(difficulty)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(difficulty);
+        block
+    }
+
+    pub(super) fn test_params() -> ChainParams {
+        ChainParams {
+            initial_difficulty: DIFFICULTY,
+            consensus_mode: ConsensusMode::ProofOfWork {
+                difficulty: DIFFICULTY,
+            },
+            timestamp_rule: TimestampRule::StrictlyIncreasing,
+            ..ChainParams::test_defaults()
+        }
+    }
+
+    pub(super) fn mined_chain(len: usize) -> Blockchain {
+        let mut chain = Blockchain::new_from_params(&test_params());
+        for i in 1..len {
+            let block = mined_child(chain.tip(), &[i as u8]);
+            chain.append(block).unwrap();
+        }
+        chain
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_generated_chains_append(blocks in testing::chain(0..12)) {
+            let chain = testing::blockchain(blocks.clone());
+            prop_assert_eq!(chain.height(), blocks.len() as u64);
+            prop_assert!(chain.iter().skip(1).eq(blocks.iter()));
+            prop_assert_eq!(chain.validate(&testing::params()), Ok(()));
+        }
+
+        #[test]
+        fn test_flipped_merkle_root_is_refused(
+            blocks in testing::chain(1..6),
+            bit in 0..256usize,
+        ) {
+            let (last, rest) = blocks.split_last().unwrap();
+            let mut chain = testing::blockchain(rest.to_vec());
+            let corrupt = testing::flip_merkle_root_bit(last, bit);
+            prop_assert_eq!(
+                chain.append(corrupt),
+                Err(ChainError::InvalidBlock(ValidationError::MerkleRootMismatch))
+            );
+            prop_assert_eq!(chain.height(), rest.len() as u64);
+            prop_assert_eq!(chain.append(last.clone()), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_chains_from_equal_params_share_genesis() {
+        let params = test_params();
+        let a = Blockchain::new_from_params(&params);
+        let b = Blockchain::new_from_params(&params.clone());
+        assert_eq!(a.tip().hash(), b.tip().hash());
+        assert_eq!(a.tip().hash(), params.genesis_hash());
+        assert_eq!(a.tip().difficulty().to_compact(), DIFFICULTY.to_compact());
+
+        let mut other = params.clone();
+        other.genesis_transactions.push(b"more".to_vec());
+        assert_ne!(
+            Blockchain::new_from_params(&other).tip().hash(),
+            a.tip().hash()
+        );
+    }
+
+    #[test]
+    fn test_params_derive_the_given_genesis() {
+        let g = genesis();
+        assert_eq!(Blockchain::new(g.clone()).params().genesis_hash(), g.hash());
+
+        let mut g = BlockBuilder::new(BlockHash::ZERO)
+            .version(2)
+            .transactions(vec![b"one".to_vec(), b"two".to_vec()])
+            .timestamp(1_700_000_000)
+            .difficulty(DIFFICULTY)
+            .nonce(1 << 40)
+            .build()
+            .unwrap();
+        g.mine(DIFFICULTY);
+        let chain = Blockchain::with_difficulty(g.clone(), DIFFICULTY);
+        assert_eq!(chain.params().genesis_block(), g);
+        assert_eq!(chain.params().magic(), Blockchain::new(g).params().magic());
+    }
+
+    #[test]
+    fn test_append_reads_rules_from_params() {
+        let params = ChainParams {
+            allowed_versions: 1..=2,
+            block_limits: BlockLimits {
+                max_bytes: 8,
+                max_transactions: 2,
+            },
+            ..test_params()
+        };
+        let mut chain = Blockchain::new_from_params(&params);
+        let child = |parent: &Block, version: u32, txs: &[&[u8]], timestamp: u64| {
+            let mut block = parent
+                .next_builder()
+                .version(version)
+                .transactions(txs.iter().map(|tx| tx.to_vec()))
+                .difficulty(DIFFICULTY)
+                .timestamp(timestamp)
+                .build()
+                .unwrap();
+            block.mine(DIFFICULTY);
+            block
+        };
+
+        let tip = chain.tip().clone();
+        let next = tip.timestamp() + 1;
+        assert_eq!(
+            chain.append(child(&tip, 3, &[b"tx"], next)),
+            Err(ChainError::InvalidBlock(
+                ValidationError::UnsupportedVersion(3)
+            ))
+        );
+        assert_eq!(
+            chain.append(child(&tip, 1, &[b"a", b"b", b"c"], next)),
+            Err(ChainError::InvalidBlock(
+                ValidationError::TooManyTransactions { count: 3, max: 2 }
+            ))
+        );
+        assert_eq!(
+            chain.append(child(&tip, 1, &[b"too many bytes"], next)),
+            Err(ChainError::InvalidBlock(ValidationError::BlockTooLarge {
+                bytes: 14,
+                max: 8
+            }))
+        );
+        assert_eq!(
+            chain.append(child(&tip, 1, &[b"tx"], tip.timestamp())),
+            Err(ChainError::InvalidTimestamp {
+                parent: tip.timestamp(),
+                got: tip.timestamp()
+            })
+        );
+        chain.append(child(&tip, 2, &[b"tx"], next)).unwrap();
+        assert_eq!(chain.validate(&params), Ok(()));
+
+        // The same chain checked against stricter params
+        let v1_only = ChainParams {
+            allowed_versions: 1..=1,
+            ..params.clone()
+        };
+        assert_eq!(
+            error_at(&chain, &v1_only),
+            (1, ChainValidationErrorKind::UnsupportedVersion(2))
+        );
+        let harder = ChainParams {
+            consensus_mode: ConsensusMode::ProofOfWork {
+                difficulty: Difficulty::LeadingZeroBits(9),
+            },
+            ..params.clone()
+        };
+        assert_eq!(
+            error_at(&chain, &harder),
+            (1, ChainValidationErrorKind::DifficultyBelowMinimum)
+        );
+        let smaller = ChainParams {
+            block_limits: BlockLimits {
+                max_bytes: 1,
+                max_transactions: 2,
+            },
+            ..params
+        };
+        assert!(matches!(
+            error_at(&chain, &smaller),
+            (1, ChainValidationErrorKind::ExceedsBlockLimits(_))
+        ));
+    }
+
+    #[test]
+    fn test_reopen_from_file_store() {
+        let dir = TempDir::new("chain-reopen");
+        let params = test_params();
+        let open = || Blockchain::open(&params, FileStore::open(dir.path()).unwrap());
+
+        let (hashes, works, sample) = {
+            let mut chain = open().unwrap();
+            for i in 1..20u8 {
+                chain.append(mined_child(chain.tip(), &[i])).unwrap();
+            }
+            // Reorg the top two blocks away before closing
+            let fork_point = chain.get(17).unwrap().clone();
+            let (_, result) = insert_branch(&mut chain, &fork_point, 3);
+            assert_eq!(result.unwrap().unwrap().depth(), 2);
+
+            let hashes: Vec<BlockHash> = chain
+                .try_iter()
+                .map(|block| block.unwrap().hash())
+                .collect();
+            let works: Vec<Work> = (0..=20).map(|h| chain.work_at(h).unwrap()).collect();
+            (hashes, works, chain.get(7).unwrap().clone())
+        };
+
+        let mut chain = open().unwrap();
+        assert_eq!(chain.height(), 20);
+        assert_eq!(chain.tip().hash(), hashes[20]);
+        // Iterating a range only loads the archived blocks it visits
+        let ranged: Vec<BlockHash> = chain
+            .try_range(5..8)
+            .unwrap()
+            .rev()
+            .map(|block| block.unwrap().hash())
+            .collect();
+        assert_eq!(ranged, [hashes[7], hashes[6], hashes[5]]);
+        let loaded = chain.archived.iter().filter(|slot| slot.get().is_some());
+        assert_eq!(loaded.count(), 3);
+        assert_eq!(chain.total_work(), works[20]);
+        for (height, work) in works.iter().enumerate() {
+            assert_eq!(chain.work_at(height as u64).as_ref(), Some(work));
+        }
+        for (height, hash) in hashes.iter().enumerate() {
+            assert_eq!(chain.get(height as u64).unwrap().hash(), *hash);
+        }
+        assert_eq!(chain.get(7), Some(&sample));
+        assert_eq!(chain.height_of(sample.hash().as_ref()), Some(7));
+        assert_eq!(chain.validate(&params), Ok(()));
+
+        // The reopened chain keeps growing and persisting
+        let next = mined_child(chain.tip(), b"after reopen");
+        chain.append(next.clone()).unwrap();
+        drop(chain);
+        let chain = open().unwrap();
+        assert_eq!(chain.tip(), &next);
+        assert_eq!(chain.store().get_hash_at_height(21), Ok(Some(next.hash())));
+
+        let other = ChainParams {
+            genesis_timestamp: params.genesis_timestamp + 1,
+            ..params.clone()
+        };
+        assert!(matches!(
+            Blockchain::open(&other, FileStore::open(dir.path()).unwrap()),
+            Err(ChainError::GenesisMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_build_mined_chain() {
+        let chain = mined_chain(10);
+        assert_eq!(chain.height(), 9);
+        assert_eq!(chain.iter().count(), 10);
+        assert_eq!(chain.get(9).unwrap().hash(), chain.tip().hash());
+        assert!(chain.get(10).is_none());
+
+        for (height, block) in chain.iter().enumerate().skip(1) {
+            assert!(block.verify_link(chain.get(height as u64 - 1).unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_try_iter_reports_unreadable_blocks() {
+        let dir = TempDir::new("chain-try-iter");
+        let params = test_params();
+        let open = || Blockchain::open(&params, FileStore::open(dir.path()).unwrap()).unwrap();
+        let mut chain = open();
+        for i in 1..5u8 {
+            chain.append(mined_child(chain.tip(), &[i])).unwrap();
+        }
+        drop(chain);
+
+        // The blocks below the tip are read from the log only when visited,
+        // and by then the log has lost them
+        let chain = open();
+        let log = std::fs::OpenOptions::new()
+            .write(true)
+            .open(dir.path().join("chain.log"))
+            .unwrap();
+        log.set_len(8).unwrap();
+        let blocks: Vec<_> = chain.try_iter().collect();
+        assert_eq!(blocks.len(), 5);
+        assert!(matches!(blocks[0], Err(ChainError::Store(_))));
+        assert_eq!(blocks[4], Ok(chain.tip()));
+        assert!(chain.try_range(1..3).unwrap().all(|block| block.is_err()));
+    }
+
+    #[test]
+    fn test_iterators_match_indexed_access() {
+        let chain = mined_chain(8);
+        let indexed = |heights: std::ops::Range<u64>| -> Vec<BlockHash> {
+            heights.map(|h| chain.get(h).unwrap().hash()).collect()
+        };
+        fn hashes<'a>(blocks: impl Iterator<Item = &'a Block>) -> Vec<BlockHash> {
+            blocks.map(Block::hash).collect()
+        }
+
+        assert_eq!(hashes(chain.iter()), indexed(0..8));
+        let mut reversed = indexed(0..8);
+        reversed.reverse();
+        assert_eq!(hashes(chain.iter_rev()), reversed);
+
+        assert_eq!(hashes(chain.range(2..5).unwrap()), indexed(2..5));
+        assert_eq!(hashes(chain.range(2..=5).unwrap()), indexed(2..6));
+        assert_eq!(hashes(chain.range(..3).unwrap()), indexed(0..3));
+        assert_eq!(hashes(chain.range(6..).unwrap()), indexed(6..8));
+        assert_eq!(
+            hashes(chain.range(2..5).unwrap().rev()),
+            hashes(chain.range(2..5).unwrap())
+                .into_iter()
+                .rev()
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(chain.range(1..7).unwrap().len(), 6);
+        assert_eq!(
+            chain.range(1..7).unwrap().nth(2).unwrap().hash(),
+            indexed(3..4)[0]
+        );
+
+        // Empty and one-past-the-end ranges behave like slice ranges
+        assert_eq!(chain.range(3..3).unwrap().len(), 0);
+        assert_eq!(chain.range(8..).unwrap().len(), 0);
+        assert_eq!(chain.range(8..8).unwrap().next(), None);
+    }
+
+    #[test]
+    fn test_range_out_of_bounds_is_none() {
+        let chain = mined_chain(3);
+        assert!(chain.range(1..5).is_none());
+        assert!(chain.range(..=4).is_none());
+        #[allow(clippy::reversed_empty_ranges)]
+        let reversed = chain.range(2..1);
+        assert!(reversed.is_none());
+        assert!(chain.try_range(1..5).is_none());
+    }
+
+    #[test]
+    fn test_timestamps_checked_against_median_time_past_and_clock() {
+        let genesis_time = test_params().genesis_timestamp;
+        let params = ChainParams {
+            timestamp_rule: TimestampRule::Any,
+            clock: std::sync::Arc::new(crate::params::FixedClock(genesis_time + 100)),
+            ..test_params()
+        };
+        let mut chain = Blockchain::new_from_params(&params);
+        let child = |parent: &Block, offset: u64| {
+            let mut block = parent
+                .next_builder()
+                .transaction(offset.to_le_bytes().to_vec())
+                .difficulty(DIFFICULTY)
+                .timestamp(genesis_time + offset)
+                .build()
+                .unwrap();
+            block.mine(DIFFICULTY);
+            block
+        };
+        assert_eq!(chain.median_time_past().unwrap(), genesis_time);
+
+        for offset in [20, 40, 30, 35] {
+            chain.append(child(chain.tip(), offset)).unwrap();
+        }
+        // The median of 0, 20, 30, 35 and 40
+        assert_eq!(chain.median_time_past().unwrap(), genesis_time + 30);
+        assert_eq!(
+            chain.append(child(chain.tip(), 30)),
+            Err(ChainError::TimestampTooOld {
+                median_time_past: genesis_time + 30,
+                got: genesis_time + 30,
+            })
+        );
+        chain.append(child(chain.tip(), 31)).unwrap();
+        // The upper median of 0, 20, 30, 31, 35 and 40
+        assert_eq!(chain.median_time_past().unwrap(), genesis_time + 31);
+
+        // Twelve blocks: the genesis timestamp drops out of the window of eleven
+        for offset in 60..66 {
+            chain.append(child(chain.tip(), offset)).unwrap();
+        }
+        assert_eq!(chain.median_time_past().unwrap(), genesis_time + 60);
+
+        // At most two hours past the clock
+        let max = genesis_time + 100 + 2 * 60 * 60;
+        assert_eq!(
+            chain.append(child(chain.tip(), max - genesis_time + 1)),
+            Err(ChainError::TimestampTooNew { max, got: max + 1 })
+        );
+        chain
+            .append(child(chain.tip(), max - genesis_time))
+            .unwrap();
+        assert_eq!(chain.height(), 12);
+    }
+
+    #[test]
+    fn test_rejects_wrong_parent_and_out_of_order() {
+        let mut chain = mined_chain(3);
+        let tip = chain.tip().hash();
+
+        // A block building on an older block
+        let stale = mined_child(chain.get(1).unwrap(), b"stale");
+        assert_eq!(
+            chain.append(stale.clone()),
+            Err(ChainError::PrevHashMismatch {
+                expected: tip,
+                got: stale.prev_block_hash()
+            })
+        );
+
+        // Blocks delivered out of order
+        let next = mined_child(chain.tip(), b"next");
+        let after = mined_child(&next, b"after");
+        assert!(matches!(
+            chain.append(after.clone()),
+            Err(ChainError::PrevHashMismatch { .. })
+        ));
+        chain.append(next).unwrap();
+        chain.append(after).unwrap();
+        assert_eq!(chain.height(), 4);
+    }
+
+    #[test]
+    fn test_rejects_unmined_block() {
+        let mut chain = mined_chain(2);
+        let mut block = chain
+            .tip()
+            .next_builder()
+            .transaction(b"lazy".to_vec())
+            .build()
+            .unwrap();
+        // Make sure the unmined block does not meet the difficulty by luck
+        while block.verify_pow(DIFFICULTY) {
+            block = chain
+                .tip()
+                .next_builder()
+                .transaction(b"lazy".to_vec())
+                .timestamp(block.timestamp() + 1)
+                .build()
+                .unwrap();
+        }
+
+        assert_eq!(
+            chain.append(block),
+            Err(ChainError::InvalidBlock(
+                ValidationError::InsufficientProofOfWork
+            ))
+        );
+        assert_eq!(chain.height(), 1);
+    }
+
+    #[test]
+    fn test_total_work_follows_committed_difficulty() {
+        let mut chain = Blockchain::new_from_params(&test_params());
+        // Work comes from the committed compact bits, a hair less than 2^bits
+        let mut expected = vec![Work::of(chain.tip().difficulty())];
+        assert_eq!(chain.total_work(), Work(255));
+
+        for (i, bits) in [8, 10, 9, 12].into_iter().enumerate() {
+            let block = mined_child_at(chain.tip(), &[i as u8], Difficulty::LeadingZeroBits(bits));
+            expected.push(expected[i] + Work(block.difficulty().work()));
+            chain.append(block).unwrap();
+        }
+        for (height, work) in expected.iter().enumerate() {
+            assert_eq!(chain.work_at(height as u64).as_ref(), Some(work));
+        }
+        assert_eq!(chain.work_at(5), None);
+        assert_eq!(chain.total_work(), Work(255 + 255 + 1023 + 511 + 4095));
+
+        // One heavy block from height 2 outweighs the two blocks above it
+        let fork_point = chain.get(2).unwrap().clone();
+        let block = mined_child_at(&fork_point, b"heavy", Difficulty::LeadingZeroBits(13));
+        chain.insert(block.clone()).unwrap();
+        assert_eq!(chain.best_tip(), block.hash());
+        assert_eq!(chain.total_work(), expected[2] + Work(8191));
+        assert_eq!(chain.work_at(3), Some(chain.total_work()));
+        assert_eq!(chain.work_at(4), None);
+
+        // The best tip is the one with the most work
+        let tip_work = |hash: &BlockHash| Work(chain.tree().get(hash).unwrap().cumulative_work());
+        let tips = chain.tips();
+        assert_eq!(tip_work(&tips[0]), chain.total_work());
+        assert!(tips[1..]
+            .iter()
+            .all(|tip| tip_work(tip) < chain.total_work()));
+    }
+
+    #[test]
+    fn test_subscriber_sees_reorg_in_order() {
+        let mut chain = mined_chain(6);
+        let events = chain.subscribe();
+        let old_tip = chain.tip().clone();
+        let next = mined_child(chain.tip(), b"next");
+        chain.append(next.clone()).unwrap();
+
+        let fork_point = chain.get(4).unwrap().clone();
+        let (branch, result) = insert_branch(&mut chain, &fork_point, 3);
+        assert_eq!(result.unwrap().unwrap().depth(), 2);
+        chain.rollback_to(5).unwrap();
+
+        let received: Vec<ChainEvent> = events.try_iter().collect();
+        let connected = |block: &Block, height| ChainEvent::Connected {
+            hash: block.hash(),
+            height,
+        };
+        let disconnected = |block: &Block, height| ChainEvent::Disconnected {
+            hash: block.hash(),
+            height,
+        };
+        assert_eq!(
+            received,
+            vec![
+                connected(&next, 6),
+                disconnected(&next, 6),
+                disconnected(&old_tip, 5),
+                connected(&branch[0], 5),
+                connected(&branch[1], 6),
+                connected(&branch[2], 7),
+                ChainEvent::ReorgCompleted {
+                    fork_point: fork_point.hash(),
+                    depth: 2,
+                },
+                disconnected(&branch[2], 7),
+                disconnected(&branch[1], 6),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lagging_subscriber_is_dropped() {
+        let mut chain = mined_chain(2);
+        let lagging = chain.subscribe_with_capacity(1);
+        let keeping_up = chain.subscribe();
+        for i in 0..3u8 {
+            chain.append(mined_child(chain.tip(), &[i])).unwrap();
+        }
+
+        // Appending never blocked; the full subscriber was cut off after one event
+        assert_eq!(
+            lagging.recv(),
+            Ok(ChainEvent::Connected {
+                hash: chain.get(2).unwrap().hash(),
+                height: 2
+            })
+        );
+        assert!(lagging.recv().is_err());
+        assert_eq!(keeping_up.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn test_rollback_to() {
+        let mut chain = mined_chain(12);
+        let old: Vec<Block> = chain.iter().cloned().collect();
+        let side = mined_child(&old[9], b"side");
+        chain.insert(side.clone()).unwrap();
+
+        let disconnected = chain.rollback_to(6).unwrap();
+        let expected: Vec<Block> = old[7..].iter().rev().cloned().collect();
+        assert_eq!(disconnected, expected);
+        assert_eq!(chain.height(), 6);
+        assert_eq!(chain.tip(), &old[6]);
+        assert_eq!(chain.best_tip(), old[6].hash());
+        assert_eq!(chain.total_work(), chain.work_at(6).unwrap());
+        assert_eq!(chain.store().get_tip().unwrap().unwrap().height, 6);
+        assert_eq!(chain.store().get_hash_at_height(7), Ok(None));
+        for block in &old[7..] {
+            assert!(!chain.contains(block.hash().as_ref()));
+            assert!(!chain.tree().contains(&block.hash()));
+        }
+        // The side branch on a disconnected block went with it
+        assert!(!chain.tree().contains(&side.hash()));
+        assert_eq!(chain.tips(), vec![old[6].hash()]);
+
+        // New blocks build on the rolled-back tip
+        for i in 0..3u8 {
+            chain.append(mined_child(chain.tip(), &[b'n', i])).unwrap();
+        }
+        assert_eq!(chain.height(), 9);
+        assert!(chain.iter().skip(7).all(|block| !old.contains(block)));
+        assert_eq!(chain.validate(&test_params()), Ok(()));
+
+        assert_eq!(chain.rollback_to(9), Ok(Vec::new()));
+        assert_eq!(
+            chain.rollback_to(10),
+            Err(ChainError::RollbackOutOfRange { height: 10, tip: 9 })
+        );
+    }
+
+    #[test]
+    fn test_prune_keeps_headers() {
+        let mut chain = mined_chain(20);
+        let old: Vec<Block> = chain.iter().cloned().collect();
+        let (side, _) = insert_branch(&mut chain, &old[5], 2);
+
+        assert_eq!(chain.prune(5), Ok(14));
+        assert_eq!(chain.pruned_height(), 14);
+        assert_eq!(chain.prune(5), Ok(0));
+        assert!(chain.is_pruned(13) && !chain.is_pruned(14));
+        assert_eq!(chain.get(13), None);
+        assert_eq!(chain.get(14), Some(&old[14]));
+        assert_eq!(chain.header(3), Some(old[3].header()));
+        assert_eq!(chain.iter().count(), 6);
+        assert_eq!(chain.iter_rev().last(), Some(&old[14]));
+        assert_eq!(chain.best_chain().unwrap().len(), 20);
+        assert_eq!(chain.tree().root(), old[14].hash());
+        assert!(!chain.tree().contains(&side[0].hash()));
+
+        // The bodies are gone from the store too, but links and work still check out
+        assert_eq!(chain.store().get_block(&old[3].hash()), Ok(None));
+        assert_eq!(
+            chain.store().get_header(&old[3].hash()),
+            Ok(Some(old[3].header().clone()))
+        );
+        assert_eq!(chain.validate(&test_params()), Ok(()));
+        assert_eq!(chain.work_at(3), mined_chain(4).work_at(3));
+
+        // Nothing may fork from or roll back to below the pruned height
+        let below = ChainError::BelowPrunedHeight {
+            height: 10,
+            pruned_height: 14,
+        };
+        assert_eq!(
+            chain.insert(mined_child(&old[10], b"deep")),
+            Err(below.clone())
+        );
+        assert_eq!(chain.rollback_to(10), Err(below));
+        let (_, result) = insert_branch(&mut chain, &old[14], 6);
+        assert_eq!(result.unwrap().unwrap().depth(), 5);
+    }
+
+    #[test]
+    fn test_range_below_pruned_height_is_none() {
+        let mut chain = mined_chain(10);
+        chain.prune(2).unwrap();
+        assert!(chain.range(3..).is_none());
+        assert_eq!(chain.range(7..).unwrap().len(), 3);
+        assert_eq!(chain.range(3..3).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_pruned_chain_reopens() {
+        let dir = TempDir::new("chain-prune");
+        let params = test_params();
+        let open = || Blockchain::open(&params, FileStore::open(dir.path()).unwrap()).unwrap();
+
+        let mut chain = open();
+        for i in 1..10u8 {
+            chain.append(mined_child(chain.tip(), &[i])).unwrap();
+        }
+        let old: Vec<Block> = chain
+            .try_iter()
+            .map(|block| block.unwrap().clone())
+            .collect();
+        assert_eq!(chain.prune(3), Ok(6));
+        drop(chain);
+
+        let chain = open();
+        assert_eq!(chain.pruned_height(), 6);
+        assert_eq!(chain.get(5), None);
+        assert_eq!(chain.get(6), Some(&old[6]));
+        assert_eq!(chain.header(2), Some(old[2].header()));
+        assert_eq!(chain.validate(&params), Ok(()));
+        let mut exported = Vec::new();
+        assert!(chain.export(&mut exported, SnapshotFormat::Binary).is_err());
+    }
+
+    #[test]
+    fn test_rollback_below_reopened_root() {
+        let dir = TempDir::new("chain-rollback");
+        let params = test_params();
+        let open = || Blockchain::open(&params, FileStore::open(dir.path()).unwrap()).unwrap();
+
+        let mut chain = open();
+        for i in 1..10u8 {
+            chain.append(mined_child(chain.tip(), &[i])).unwrap();
+        }
+        let old: Vec<Block> = chain
+            .try_iter()
+            .map(|block| block.unwrap().clone())
+            .collect();
+        drop(chain);
+
+        let mut chain = open();
+        assert_eq!(chain.rollback_to(4).unwrap().len(), 5);
+        assert_eq!(chain.tip(), &old[4]);
+        assert_eq!(chain.tree().root(), old[4].hash());
+        chain.append(mined_child(chain.tip(), b"new")).unwrap();
+        drop(chain);
+
+        let chain = open();
+        assert_eq!(chain.height(), 5);
+        assert_eq!(chain.get(4), Some(&old[4]));
+        assert_eq!(chain.validate(&params), Ok(()));
+    }
+
+    #[test]
+    fn test_heavier_shorter_branch_wins() {
+        let mut chain = mined_chain(3);
+        let fork_point = chain.tip().clone();
+
+        // Three blocks at the minimum difficulty
+        let mut light = Vec::new();
+        let mut parent = fork_point.clone();
+        for i in 0..3u8 {
+            let block = mined_child(&parent, &[b'l', i]);
+            chain.insert(block.clone()).unwrap();
+            parent = block.clone();
+            light.push(block);
+        }
+        assert_eq!(chain.best_tip(), light[2].hash());
+        assert_eq!(chain.height(), 5);
+
+        // One block with sixteen times the work per block
+        let heavy = mined_child_at(&fork_point, b"heavy", Difficulty::LeadingZeroBits(12));
+        chain.insert(heavy.clone()).unwrap();
+
+        assert_eq!(chain.best_tip(), heavy.hash());
+        assert_eq!(chain.tips(), vec![heavy.hash(), light[2].hash()]);
+        assert_eq!(chain.height(), 3);
+        assert_eq!(chain.tip().hash(), heavy.hash());
+
+        let best: Vec<BlockHash> = chain
+            .best_chain()
+            .unwrap()
+            .iter()
+            .map(|header| header.hash())
+            .collect();
+        let expected: Vec<BlockHash> = (0..3)
+            .map(|height| chain.get(height).unwrap().hash())
+            .chain([heavy.hash()])
+            .collect();
+        assert_eq!(best, expected);
+        assert_eq!(chain.validate(&test_params()), Ok(()));
+
+        let light_tip = chain.tree().get(&light[2].hash()).unwrap();
+        let heavy_tip = chain.tree().get(&heavy.hash()).unwrap();
+        assert!(heavy_tip.cumulative_work() > light_tip.cumulative_work());
+        assert!(heavy_tip.height() < light_tip.height());
+    }
+
+    #[test]
+    fn test_enforces_retargeted_difficulty() {
+        let params = ChainParams {
+            target_block_time: Duration::from_secs(10),
+            retarget_interval: 4,
+            ..test_params()
+        };
+        let mut chain = Blockchain::new_from_params(&params);
+
+        // Blocks arrive twice as fast as intended
+        let fast = |parent: &Block, difficulty: Difficulty| {
+            let mut block = parent
+                .next_builder()
+                .transaction(parent.timestamp().to_le_bytes().to_vec())
+                .difficulty(difficulty)
+                .timestamp(parent.timestamp() + 5)
+                .build()
+                .unwrap();
+            block.mine(difficulty);
+            block
+        };
+        let harder = Difficulty::LeadingZeroBits(9);
+        assert!(matches!(
+            chain.append(fast(chain.tip(), harder)),
+            Err(ChainError::UnexpectedDifficulty { .. })
+        ));
+        for _ in 1..4 {
+            assert_eq!(
+                chain.next_block_difficulty().unwrap().to_compact(),
+                DIFFICULTY.to_compact()
+            );
+            chain.append(fast(chain.tip(), DIFFICULTY)).unwrap();
+        }
+
+        // Height 4 starts a new interval: 15 seconds elapsed instead of 30
+        let expected = DIFFICULTY.normalized().scaled(1, 2).unwrap();
+        assert!(expected.work() > DIFFICULTY.normalized().work());
+        assert_eq!(
+            chain.next_block_difficulty().unwrap().to_compact(),
+            expected.to_compact()
+        );
+        assert_eq!(
+            chain.append(fast(chain.tip(), DIFFICULTY)),
+            Err(ChainError::UnexpectedDifficulty {
+                expected: expected.to_compact(),
+                got: DIFFICULTY.to_compact(),
+            })
+        );
+        chain.append(fast(chain.tip(), expected)).unwrap();
+        chain.append(fast(chain.tip(), expected)).unwrap();
+        assert_eq!(chain.height(), 5);
+        assert_eq!(chain.validate(&params), Ok(()));
+
+        // Against a slower target the same blocks arrived on schedule, so
+        // the retarget at height 4 should not have happened
+        let slower = ChainParams {
+            target_block_time: Duration::from_secs(5),
+            ..params
+        };
+        assert!(matches!(
+            error_at(&chain, &slower),
+            (4, ChainValidationErrorKind::UnexpectedDifficulty { .. })
+        ));
+    }
+
+    /// Insert a branch of `len` blocks on `parent`, returning the blocks and
+    /// the result of the last insert
+    fn insert_branch<S: ChainStore>(
+        chain: &mut Blockchain<S>,
+        parent: &Block,
+        len: usize,
+    ) -> (Vec<Block>, Result<Option<Reorg>, ChainError>) {
+        let mut blocks: Vec<Block> = Vec::new();
+        let mut result = Ok(None);
+        for i in 0..len {
+            let block = mined_child(blocks.last().unwrap_or(parent), &[b'b', i as u8]);
+            result = chain.insert(block.clone());
+            blocks.push(block);
+        }
+        (blocks, result)
+    }
+
+    #[test]
+    fn test_hash_index_follows_reorgs() {
+        let mut chain = mined_chain(5);
+        let old_tip = chain.tip().clone();
+        for height in 0..5 {
+            let block = chain.get(height).unwrap();
+            assert_eq!(chain.height_of(block.hash().as_ref()), Some(height));
+            assert_eq!(chain.get_by_hash(block.hash().as_ref()), Some(block));
+        }
+        assert!(chain.contains(old_tip.hash().as_ref()));
+        assert!(!chain.contains(BlockHash::ZERO.as_ref()));
+        assert_eq!(chain.height_of(&[0u8; 31]), None);
+
+        let fork_point = chain.get(2).unwrap().clone();
+        let (branch, result) = insert_branch(&mut chain, &fork_point, 3);
+        assert_eq!(result.unwrap().unwrap().depth(), 2);
+
+        // The disconnected tip is off the active chain but still in the tree
+        assert!(!chain.contains(old_tip.hash().as_ref()));
+        assert_eq!(chain.get_by_hash(old_tip.hash().as_ref()), None);
+        assert_eq!(chain.tree().get(&old_tip.hash()).unwrap().block(), &old_tip);
+        for (offset, block) in branch.iter().enumerate() {
+            assert_eq!(
+                chain.height_of(block.hash().as_ref()),
+                Some(3 + offset as u64)
+            );
+        }
+        assert_eq!(chain.height_of(fork_point.hash().as_ref()), Some(2));
+    }
+
+    #[test]
+    fn test_losing_branch_is_kept_and_can_be_invalidated() {
+        let mut chain = mined_chain(5);
+        let losing: Vec<Block> = chain.range(3..).unwrap().cloned().collect();
+        let fork_point = chain.get(2).unwrap().clone();
+        let (branch, result) = insert_branch(&mut chain, &fork_point, 3);
+        assert_eq!(result.unwrap().unwrap().depth(), 2);
+
+        let losing_hashes: Vec<BlockHash> = losing.iter().map(Block::hash).collect();
+        assert_eq!(chain.side_chains(), vec![losing_hashes.clone()]);
+        for block in &losing {
+            let hash = block.hash();
+            assert_eq!(chain.status_of(hash.as_ref()), Some(BlockStatus::SideChain));
+        }
+        let tip = branch[2].hash();
+        assert_eq!(chain.status_of(tip.as_ref()), Some(BlockStatus::Active));
+        assert_eq!(chain.status_of(BlockHash::ZERO.as_ref()), None);
+
+        // Once its fork block is invalid, more work on the losing branch
+        // cannot bring it back
+        assert_eq!(chain.mark_invalid(losing_hashes[0]), Ok(vec![]));
+        for hash in &losing_hashes {
+            assert_eq!(chain.status_of(hash.as_ref()), Some(BlockStatus::Invalid));
+        }
+        let heavier = mined_child(&losing[1], b"heavier");
+        assert_eq!(
+            chain.insert(heavier.clone()),
+            Err(ChainError::MarkedInvalid(losing_hashes[1]))
+        );
+        assert_eq!(
+            chain.insert(losing[0].clone()),
+            Err(ChainError::MarkedInvalid(losing_hashes[0]))
+        );
+        assert_eq!(chain.tip().hash(), tip);
+
+        // Orphans of an invalid block are dropped when they would connect
+        let orphan = mined_child(&heavier, b"orphan");
+        assert!(chain.insert(orphan.clone()).is_err());
+        assert_eq!(
+            chain.status_of(orphan.hash().as_ref()),
+            Some(BlockStatus::Orphan)
+        );
+
+        // Marking an active block rolls the chain back to its parent
+        assert_eq!(chain.mark_invalid(tip), Ok(vec![branch[2].clone()]));
+        assert_eq!(chain.tip(), &branch[1]);
+        assert_eq!(chain.status_of(tip.as_ref()), Some(BlockStatus::Invalid));
+        assert_eq!(
+            chain.insert(branch[2].clone()),
+            Err(ChainError::MarkedInvalid(tip))
+        );
+        assert_eq!(
+            chain.mark_invalid(chain.get(0).unwrap().hash()),
+            Err(ChainError::GenesisMarkedInvalid)
+        );
+    }
+
+    #[test]
+    fn test_orphans_connect_when_parent_arrives() {
+        let source = mined_chain(6);
+        let blocks: Vec<Block> = source.iter().skip(1).cloned().collect();
+        let mut chain = Blockchain::new_from_params(&test_params());
+
+        for block in blocks[1..].iter().rev() {
+            assert_eq!(
+                chain.insert(block.clone()),
+                Err(ChainError::UnknownParent(block.prev_block_hash()))
+            );
+        }
+        assert_eq!(chain.orphan_count(), 4);
+        let held: Vec<BlockHash> = chain.orphans().map(Block::hash).collect();
+        assert_eq!(held, [5, 4, 3, 2].map(|h| source.get(h).unwrap().hash()));
+
+        let reorg = chain.insert(blocks[0].clone()).unwrap().unwrap();
+        assert_eq!(
+            reorg.connected,
+            blocks.iter().map(Block::hash).collect::<Vec<_>>()
+        );
+        assert!(reorg.disconnected.is_empty());
+        assert_eq!(chain.height(), 5);
+        assert_eq!(chain.tip(), source.tip());
+        assert_eq!(chain.orphan_count(), 0);
+    }
+
+    #[test]
+    fn test_orphan_pool_evicts_oldest() {
+        let source = mined_chain(6);
+        let blocks: Vec<Block> = source.iter().skip(1).cloned().collect();
+        let mut chain =
+            Blockchain::new_from_params(&test_params()).with_orphan_limits(2, usize::MAX);
+
+        for block in blocks[1..].iter().rev() {
+            let _ = chain.insert(block.clone());
+        }
+        // The two earliest arrivals, heights 5 and 4, made room for the rest
+        let held: Vec<BlockHash> = chain.orphans().map(Block::hash).collect();
+        assert_eq!(held, [blocks[2].hash(), blocks[1].hash()]);
+
+        chain.insert(blocks[0].clone()).unwrap();
+        assert_eq!(chain.height(), 3);
+        assert_eq!(chain.orphan_count(), 0);
+
+        // Evicted blocks are simply delivered again
+        chain.insert(blocks[4].clone()).unwrap_err();
+        chain.insert(blocks[3].clone()).unwrap();
+        assert_eq!(chain.tip(), source.tip());
+    }
+
+    /// A chain of `len` blocks under `params`, built from the blocks of `mined_chain(len)`
+    fn checkpointed_chain(len: usize, params: &ChainParams) -> Blockchain {
+        let mut chain = Blockchain::new_from_params(params);
+        for block in mined_chain(len).iter().skip(1) {
+            chain.append(block.clone()).unwrap();
+        }
+        chain
+    }
+
+    #[test]
+    fn test_checkpoints_reject_contradicting_forks() {
+        let hashes: Vec<BlockHash> = mined_chain(8).iter().map(Block::hash).collect();
+        let params = test_params().with_checkpoints(vec![(3, hashes[3]), (7, BlockHash::ZERO)]);
+        let mut chain = checkpointed_chain(6, &params);
+        let heavy = Difficulty::LeadingZeroBits(12);
+
+        // A heavier block replacing the checkpointed one
+        let fork_point = chain.get(2).unwrap().clone();
+        let rival = mined_child_at(&fork_point, b"rival", heavy);
+        assert_eq!(
+            chain.insert(rival.clone()),
+            Err(ChainError::CheckpointViolation {
+                height: 3,
+                expected: hashes[3],
+                got: rival.hash(),
+            })
+        );
+
+        // A heavier but shorter branch diverging below the checkpoint
+        let fork_point = chain.get(1).unwrap().clone();
+        let rival = mined_child_at(&fork_point, b"rival", heavy);
+        assert_eq!(
+            chain.insert(rival.clone()),
+            Err(ChainError::CheckpointViolation {
+                height: 2,
+                expected: hashes[2],
+                got: rival.hash(),
+            })
+        );
+        assert!(!chain.tree().contains(&rival.hash()));
+
+        // Forks above the checkpoint still reorg as usual
+        let fork_point = chain.get(4).unwrap().clone();
+        let rival = mined_child_at(&fork_point, b"rival", heavy);
+        assert_eq!(chain.insert(rival.clone()).unwrap().unwrap().depth(), 1);
+
+        // A checkpoint above the tip rejects the wrong block when it arrives
+        let tip = mined_child(chain.tip(), b"6");
+        chain.append(tip.clone()).unwrap();
+        let wrong = mined_child(&tip, b"7");
+        assert!(matches!(
+            chain.append(wrong),
+            Err(ChainError::CheckpointViolation { height: 7, .. })
+        ));
+    }
+
+    #[test]
+    fn test_trusted_checkpoints_skip_proof_checks() {
+        let hashes: Vec<BlockHash> = mined_chain(8).iter().map(Block::hash).collect();
+        let params = test_params().with_checkpoints(vec![(5, hashes[5])]);
+        let chain = checkpointed_chain(8, &params);
+        let proof_checks = || {
+            chain
+                .proof_checks
+                .swap(0, std::sync::atomic::Ordering::Relaxed)
+        };
+
+        assert_eq!(chain.validate(&params), Ok(()));
+        assert_eq!(proof_checks(), 7);
+
+        let trusted = ChainParams {
+            trust_checkpoints: true,
+            ..params.clone()
+        };
+        assert_eq!(chain.validate(&trusted), Ok(()));
+        assert_eq!(proof_checks(), 2);
+
+        // A checkpoint the chain does not match is reported and not trusted
+        let wrong = ChainParams {
+            trust_checkpoints: true,
+            ..test_params().with_checkpoints(vec![(5, hashes[4])])
+        };
+        assert_eq!(
+            error_at(&chain, &wrong),
+            (
+                5,
+                ChainValidationErrorKind::CheckpointViolation {
+                    expected: hashes[4],
+                    got: hashes[5],
+                }
+            )
+        );
+        assert_eq!(proof_checks(), 4);
+    }
+
+    #[test]
+    fn test_certified_checkpoints() {
+        let members: Vec<SigningKey> = (1..=3)
+            .map(|seed| SigningKey::from_bytes(&[seed; 32]))
+            .collect();
+        let committee: Vec<PublicKey> = members.iter().map(Signer::public_key).collect();
+        let params = test_params().with_checkpoint_committee(committee.clone(), 2);
+        let mut chain = checkpointed_chain(6, &params);
+        let certify = |hash: &BlockHash, signers: &[&dyn Signer]| {
+            CheckpointCert::sign(hash, &committee, signers).unwrap()
+        };
+
+        // A certified checkpoint above the tip rejects the wrong block when it arrives
+        let next = mined_child(chain.tip(), b"6");
+        let wrong = mined_child(chain.tip(), b"wrong");
+        let cert = certify(&next.hash(), &[&members[0], &members[2]]);
+        assert_eq!(
+            chain.add_certified_checkpoint(6, next.hash(), &certify(&next.hash(), &[&members[0]])),
+            Err(ChainError::InvalidCheckpointCert(next.hash()))
+        );
+        assert_eq!(
+            chain.add_certified_checkpoint(6, wrong.hash(), &cert),
+            Err(ChainError::InvalidCheckpointCert(wrong.hash()))
+        );
+        chain
+            .add_certified_checkpoint(6, next.hash(), &cert)
+            .unwrap();
+        assert!(matches!(
+            chain.append(wrong),
+            Err(ChainError::CheckpointViolation { height: 6, .. })
+        ));
+        chain.append(next).unwrap();
+
+        // A checkpoint contradicting the active chain is refused
+        let rival = mined_child(chain.get(3).unwrap(), b"rival");
+        let cert = certify(&rival.hash(), &[&members[1], &members[2]]);
+        assert_eq!(
+            chain.add_certified_checkpoint(4, rival.hash(), &cert),
+            Err(ChainError::CheckpointViolation {
+                height: 4,
+                expected: chain.get(4).unwrap().hash(),
+                got: rival.hash(),
+            })
+        );
+
+        // Without a committee no certificate is accepted
+        let mut uncommitted = checkpointed_chain(2, &test_params());
+        let hash = uncommitted.tip().hash();
+        assert_eq!(
+            uncommitted.add_certified_checkpoint(2, hash, &cert),
+            Err(ChainError::InvalidCheckpointCert(hash))
+        );
+    }
+
+    fn authorities() -> Vec<SigningKey> {
+        (1..=3)
+            .map(|seed| SigningKey::from_bytes(&[seed; 32]))
+            .collect()
+    }
+
+    fn signed_child(parent: &Block, signer: &SigningKey, timestamp: u64, in_turn: bool) -> Block {
+        let mut block = parent
+            .next_builder()
+            .transaction(timestamp.to_le_bytes().to_vec())
+            .difficulty(ConsensusMode::authority_difficulty(in_turn))
+            .timestamp(timestamp)
+            .build()
+            .unwrap();
+        block.sign(signer);
+        block
+    }
+
+    // The authority whose turn it is at `timestamp` and one whose turn it is not
+    fn turn<'a>(
+        params: &ChainParams,
+        keys: &'a [SigningKey],
+        timestamp: u64,
+    ) -> (&'a SigningKey, &'a SigningKey) {
+        let expected = params.consensus_mode.expected_signer(timestamp).unwrap();
+        let in_turn = keys
+            .iter()
+            .find(|key| key.public_key() == *expected)
+            .unwrap();
+        let out_of_turn = keys
+            .iter()
+            .find(|key| key.public_key() != *expected)
+            .unwrap();
+        (in_turn, out_of_turn)
+    }
+
+    #[test]
+    fn test_poa_in_turn_blocks() {
+        let keys = authorities();
+        let params =
+            test_params().with_authorities(keys.iter().map(Signer::public_key).collect(), 10);
+        let mut chain = Blockchain::new_from_params(&params);
+
+        // Each slot of ten seconds belongs to the next authority in turn
+        let start = params.genesis_timestamp;
+        let signers: Vec<&PublicKey> = (1..=4)
+            .map(|slot| {
+                params
+                    .consensus_mode
+                    .expected_signer(start + slot * 10)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(signers[3], signers[0]);
+        assert!(signers[0] != signers[1] && signers[1] != signers[2] && signers[0] != signers[2]);
+        assert_eq!(
+            params.consensus_mode.expected_signer(start + 19),
+            Some(signers[0])
+        );
+
+        for slot in 1..=4 {
+            let timestamp = start + slot * 10;
+            let (signer, _) = turn(&params, &keys, timestamp);
+            chain
+                .append(signed_child(chain.tip(), signer, timestamp, true))
+                .unwrap();
+        }
+        assert_eq!(chain.height(), 4);
+        assert_eq!(chain.validate(&params), Ok(()));
+
+        // An out-of-turn block must not claim in-turn work, nor the reverse
+        let timestamp = start + 50;
+        let (signer, other) = turn(&params, &keys, timestamp);
+        let expected = ConsensusMode::authority_difficulty(false).to_compact();
+        let got = ConsensusMode::authority_difficulty(true).to_compact();
+        assert_eq!(
+            chain.append(signed_child(chain.tip(), other, timestamp, true)),
+            Err(ChainError::UnexpectedDifficulty { expected, got })
+        );
+        assert!(matches!(
+            chain.append(signed_child(chain.tip(), signer, timestamp, false)),
+            Err(ChainError::UnexpectedDifficulty { .. })
+        ));
+
+        // Within the skew tolerance, the neighbouring slot's signer is in turn too
+        let (next, _) = turn(&params, &keys, timestamp + 10);
+        let early = signed_child(chain.tip(), next, timestamp + 7, true);
+        assert_eq!(params.consensus_mode.is_in_turn(&early), Some(false));
+        let mode = ConsensusMode::ProofOfAuthority {
+            authorities: keys.iter().map(Signer::public_key).collect(),
+            step_duration: 10,
+            skew_tolerance: 3,
+        };
+        assert_eq!(mode.is_in_turn(&early), Some(true));
+    }
+
+    #[test]
+    fn test_poa_out_of_turn_chain_ranks_below_in_turn() {
+        let keys = authorities();
+        let params =
+            test_params().with_authorities(keys.iter().map(Signer::public_key).collect(), 10);
+        let mut chain = Blockchain::new_from_params(&params);
+        let genesis = chain.tip().clone();
+        let start = params.genesis_timestamp;
+
+        // Three blocks out of turn, worth 3
+        let mut out_of_turn = vec![genesis.clone()];
+        for slot in 1..=3 {
+            let timestamp = start + slot * 10;
+            let (_, other) = turn(&params, &keys, timestamp);
+            let block = signed_child(out_of_turn.last().unwrap(), other, timestamp, false);
+            chain.insert(block.clone()).unwrap();
+            out_of_turn.push(block);
+        }
+        assert_eq!(chain.tip().hash(), out_of_turn[3].hash());
+
+        // Two in-turn blocks are worth 4 and take over despite being shorter
+        let mut in_turn = vec![genesis];
+        for slot in 1..=2 {
+            let timestamp = start + slot * 10 + 1;
+            let (signer, _) = turn(&params, &keys, timestamp);
+            let block = signed_child(in_turn.last().unwrap(), signer, timestamp, true);
+            chain.insert(block.clone()).unwrap();
+            in_turn.push(block);
+        }
+        assert_eq!(chain.tip().hash(), in_turn[2].hash());
+        assert_eq!(chain.height(), 2);
+        assert_eq!(chain.validate(&params), Ok(()));
+    }
+
+    #[test]
+    fn test_poa_rejects_signer_outside_authorities() {
+        let keys = authorities();
+        let params =
+            test_params().with_authorities(keys.iter().map(Signer::public_key).collect(), 10);
+        let mut chain = Blockchain::new_from_params(&params);
+        let timestamp = params.genesis_timestamp + 10;
+
+        let outsider = SigningKey::from_bytes(&[9; 32]);
+        for in_turn in [true, false] {
+            assert_eq!(
+                chain.append(signed_child(chain.tip(), &outsider, timestamp, in_turn)),
+                Err(ChainError::InvalidBlock(
+                    ValidationError::InvalidAuthoritySignature
+                ))
+            );
+        }
+        assert_eq!(chain.height(), 0);
+    }
+
+    #[test]
+    fn test_poa_single_signer_cannot_outwork_rotation() {
+        let keys: Vec<SigningKey> = authorities().into_iter().take(2).collect();
+        let params =
+            test_params().with_authorities(keys.iter().map(Signer::public_key).collect(), 10);
+        let mut chain = Blockchain::new_from_params(&params);
+        let genesis = chain.tip().clone();
+        let start = params.genesis_timestamp;
+
+        // The rotation signs once a slot, always in turn
+        let mut honest = genesis.clone();
+        for slot in 1..=20 {
+            let timestamp = start + slot * 10;
+            let (signer, _) = turn(&params, &keys, timestamp);
+            honest = signed_child(&honest, signer, timestamp, true);
+            chain.insert(honest.clone()).unwrap();
+        }
+
+        // One authority signing every second over the same time only gets
+        // a block into each slot, and only half of those in turn
+        let lone = &keys[0];
+        let mut branch = vec![genesis];
+        for second in 1..=200 {
+            let timestamp = start + second;
+            let in_turn =
+                params.consensus_mode.expected_signer(timestamp) == Some(&lone.public_key());
+            let block = signed_child(branch.last().unwrap(), lone, timestamp, in_turn);
+            match chain.insert(block.clone()) {
+                Ok(_) => branch.push(block),
+                Err(err) => assert!(
+                    matches!(err, ChainError::SlotNotAfterParent { .. }),
+                    "{:?}",
+                    err
+                ),
+            }
+        }
+        assert_eq!(branch.len(), 21);
+        assert_eq!(chain.tip(), &honest);
+        assert_eq!(chain.validate(&params), Ok(()));
+    }
+
+    #[test]
+    fn test_poa_without_rotation_pins_difficulty() {
+        let keys = authorities();
+        let authorities: Vec<PublicKey> = keys.iter().map(Signer::public_key).collect();
+        let params = test_params().with_authorities(authorities.clone(), 0);
+        let mut chain = Blockchain::new_from_params(&params);
+        let timestamp = params.genesis_timestamp + 10;
+        let expected = ConsensusMode::authority_difficulty(true).to_compact();
+
+        // A zero target claims all the work there is, and would outweigh any
+        // honest chain in fork choice
+        let mut greedy = chain
+            .tip()
+            .next_builder()
+            .transaction(b"greedy".to_vec())
+            .difficulty(Difficulty::CompactTarget(0))
+            .timestamp(timestamp)
+            .build()
+            .unwrap();
+        greedy.sign(&keys[0]);
+        assert_eq!(
+            chain.append(greedy),
+            Err(ChainError::UnexpectedDifficulty { expected, got: 0 })
+        );
+        chain
+            .append(signed_child(chain.tip(), &keys[0], timestamp, true))
+            .unwrap();
+        assert_eq!(chain.validate(&params), Ok(()));
+
+        // Validation pins the bits too: out-of-turn blocks of a rotating
+        // chain claim less work than blocks without a rotation must
+        let rotating = test_params().with_authorities(authorities, 10);
+        let mut chain = Blockchain::new_from_params(&rotating);
+        let (_, other) = turn(&rotating, &keys, timestamp);
+        chain
+            .append(signed_child(chain.tip(), other, timestamp, false))
+            .unwrap();
+        assert_eq!(
+            error_at(&chain, &params),
+            (
+                1,
+                ChainValidationErrorKind::UnexpectedDifficulty {
+                    expected,
+                    got: ConsensusMode::authority_difficulty(false).to_compact(),
+                }
+            )
+        );
+    }
+
+    #[test]
+    fn test_reorg_reports_disconnected_and_connected() {
+        let mut chain = mined_chain(6);
+        let old: Vec<BlockHash> = chain.iter().map(Block::hash).collect();
+
+        let next = mined_child(chain.tip(), b"next");
+        assert_eq!(
+            chain.insert(next.clone()),
+            Ok(Some(Reorg {
+                disconnected: vec![],
+                connected: vec![next.hash()],
+                fork_point: old[5],
+            }))
+        );
+
+        // Four blocks from height 2 against the four above it; equal work does
+        // not switch, the fifth block does
+        let fork_point = chain.get(2).unwrap().clone();
+        let (branch, result) = insert_branch(&mut chain, &fork_point, 4);
+        assert_eq!(result, Ok(None));
+        assert_eq!(chain.tip().hash(), next.hash());
+
+        let last = mined_child(&branch[3], b"last");
+        let reorg = chain.insert(last.clone()).unwrap().unwrap();
+        assert_eq!(reorg.fork_point, fork_point.hash());
+        assert_eq!(
+            reorg.disconnected,
+            vec![next.hash(), old[5], old[4], old[3]]
+        );
+        let connected: Vec<BlockHash> = branch.iter().chain([&last]).map(Block::hash).collect();
+        assert_eq!(reorg.connected, connected);
+        assert_eq!(reorg.depth(), 4);
+
+        assert_eq!(chain.tip().hash(), last.hash());
+        assert_eq!(chain.height(), 7);
+        assert_eq!(chain.get(3).unwrap().hash(), branch[0].hash());
+        assert_eq!(chain.validate(&test_params()), Ok(()));
+    }
+
+    #[test]
+    fn test_three_deep_reorg_order() {
+        let mut chain = mined_chain(6);
+        let old: Vec<BlockHash> = chain.iter().map(Block::hash).collect();
+        let fork_point = chain.get(2).unwrap().clone();
+
+        let (branch, result) = insert_branch(&mut chain, &fork_point, 4);
+        assert_eq!(
+            result,
+            Ok(Some(Reorg {
+                disconnected: vec![old[5], old[4], old[3]],
+                connected: branch.iter().map(Block::hash).collect(),
+                fork_point: old[2],
+            }))
+        );
+    }
+
+    #[test]
+    fn test_refuses_reorg_beyond_limit() {
+        let mut chain = mined_chain(6).with_max_reorg_depth(2);
+        let tip = chain.tip().hash();
+        let fork_point = chain.get(2).unwrap().clone();
+
+        let (branch, result) = insert_branch(&mut chain, &fork_point, 4);
+        assert_eq!(result, Err(ChainError::ReorgTooDeep { depth: 3, max: 2 }));
+        assert_eq!(chain.tip().hash(), tip);
+        assert_eq!(chain.best_tip(), tip);
+        assert!(!chain.tree().contains(&branch[3].hash()));
+
+        // A reorg within the limit still goes through
+        let fork_point = chain.get(4).unwrap().clone();
+        let (branch, result) = insert_branch(&mut chain, &fork_point, 2);
+        assert_eq!(result.unwrap().unwrap().depth(), 1);
+        assert_eq!(chain.tip().hash(), branch[1].hash());
+    }
+
+    #[test]
+    fn test_equal_work_prefers_height() {
+        let genesis = genesis();
+        let mut chain = Blockchain::new(genesis.clone());
+
+        // Work 2 in a single block against two blocks of work 1
+        let two = Difficulty::from_target(&Difficulty::LeadingZeroBits(1).to_target());
+        let one = Difficulty::LeadingZeroBits(0);
+        assert_eq!(two.work(), 2);
+        assert_eq!(one.normalized().work(), 1);
+
+        let short = mined_child_at(&genesis, b"short", two);
+        chain.insert(short.clone()).unwrap();
+        let tall = mined_child_at(&genesis, b"tall", one);
+        let taller = mined_child_at(&tall, b"taller", one);
+        chain.insert(tall.clone()).unwrap();
+        assert_eq!(chain.best_tip(), short.hash());
+        chain.insert(taller.clone()).unwrap();
+        assert_eq!(chain.best_tip(), taller.hash());
+
+        // A later sibling with equal work and height does not displace the tip
+        let rival = mined_child_at(&tall, b"rival", one);
+        chain.insert(rival).unwrap();
+        assert_eq!(chain.best_tip(), taller.hash());
+    }
+
+    #[test]
+    fn test_insert_rejects_unknown_parent_and_invalid_blocks() {
+        let mut chain = mined_chain(2);
+        let detached = mined_child(&mined_child(chain.tip(), b"missing"), b"orphan");
+        assert_eq!(
+            chain.insert(detached.clone()),
+            Err(ChainError::UnknownParent(detached.prev_block_hash()))
+        );
+
+        // Side branches are validated just like the tip
+        let genesis = chain.get(0).unwrap().clone();
+        let lazy = genesis
+            .next_builder()
+            .transaction(b"lazy".to_vec())
+            .timestamp(genesis.timestamp() + 1)
+            .build()
+            .unwrap();
+        assert!(chain.insert(lazy).is_err());
+        assert_eq!(chain.tips().len(), 1);
+    }
+
+    fn error_at(chain: &Blockchain, params: &ChainParams) -> (u64, ChainValidationErrorKind) {
+        let err = chain.validate(params).unwrap_err();
+        (err.height, err.kind)
+    }
+
+    #[test]
+    fn test_validate_accepts_good_chain() {
+        let chain = mined_chain(12);
+        assert_eq!(chain.validate(&test_params()), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_reports_corrupted_block() {
+        let params = test_params();
+
+        // Unmined block at height 5 (linked correctly, bits still committed)
+        let mut chain = mined_chain(12);
+        let parent = chain.get(4).unwrap().clone();
+        let mut unmined = parent
+            .next_builder()
+            .transaction(b"x".to_vec())
+            .difficulty(DIFFICULTY)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        while unmined.verify_pow(DIFFICULTY) {
+            unmined = parent
+                .next_builder()
+                .transaction(b"x".to_vec())
+                .difficulty(DIFFICULTY)
+                .timestamp(unmined.timestamp() + 1)
+                .build()
+                .unwrap();
+        }
+        chain.corrupt(5, unmined);
+        assert_eq!(
+            error_at(&chain, &params),
+            (5, ChainValidationErrorKind::InsufficientProofOfWork)
+        );
+
+        // Swapped transactions at height 6
+        let mut chain = mined_chain(12);
+        let mut bytes = chain.get(6).unwrap().to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        chain.corrupt(6, Block::from_bytes(&bytes, &Default::default()).unwrap());
+        assert_eq!(
+            error_at(&chain, &params),
+            (6, ChainValidationErrorKind::MerkleRootMismatch)
+        );
+
+        // A block replaced by a different one breaks the link above it
+        let mut chain = mined_chain(12);
+        let replacement = mined_child(chain.get(6).unwrap(), b"other");
+        let replaced = chain.get(7).unwrap().hash();
+        chain.corrupt(7, replacement.clone());
+        assert_eq!(
+            error_at(&chain, &params),
+            (
+                8,
+                ChainValidationErrorKind::PrevHashMismatch {
+                    expected: replacement.hash(),
+                    got: replaced
+                }
+            )
+        );
+
+        // Timestamp going backwards at height 3
+        let mut chain = mined_chain(12);
+        let parent = chain.get(2).unwrap().clone();
+        let mut early = parent
+            .next_builder()
+            .transaction(b"t".to_vec())
+            .difficulty(DIFFICULTY)
+            .timestamp(parent.timestamp() - 1)
+            .build()
+            .unwrap();
+        early.mine(DIFFICULTY);
+        chain.corrupt(3, early);
+        chain.corrupt(4, mined_child(chain.get(3).unwrap(), b"t4"));
+        assert!(matches!(
+            error_at(&chain, &params),
+            (3, ChainValidationErrorKind::InvalidTimestamp { .. })
+        ));
+
+        // Easier committed difficulty at height 9
+        let mut chain = mined_chain(12);
+        let parent = chain.get(8).unwrap().clone();
+        let easy = parent
+            .next_builder()
+            .transaction(b"easy".to_vec())
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        chain.corrupt(9, easy);
+        assert_eq!(
+            error_at(&chain, &params),
+            (9, ChainValidationErrorKind::DifficultyBelowMinimum)
+        );
+    }
+
+    #[test]
+    fn test_validate_rejects_wrong_genesis() {
+        let chain = mined_chain(3);
+        let mut params = test_params();
+        params.genesis_transactions = vec![b"another genesis".to_vec()];
+        assert!(matches!(
+            error_at(&chain, &params),
+            (0, ChainValidationErrorKind::GenesisMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_bad_merkle_root() {
+        let mut chain = mined_chain(1);
+        let mut bytes = mined_child(chain.tip(), b"tx").to_bytes();
+        // Corrupt the transaction payload, leaving the header intact
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let block = Block::from_bytes(&bytes, &Default::default()).unwrap();
+
+        assert_eq!(
+            chain.append(block),
+            Err(ChainError::InvalidBlock(
+                ValidationError::MerkleRootMismatch
+            ))
+        );
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::{HashMap, VecDeque};
+
+use crate::block::{Block, BlockHash};
+
+/// Default cap on the number of blocks an [`OrphanPool`] holds
+pub const DEFAULT_MAX_ORPHANS: usize = 100;
+/// Default cap on the total encoded size of the blocks an [`OrphanPool`] holds
+pub const DEFAULT_MAX_ORPHAN_BYTES: usize = 16 * 1024 * 1024;
+
+/// Blocks that arrived before their parent, waiting for it to connect.
+///
+/// The pool is bounded by block count and total encoded size; when either is
+/// exceeded the oldest orphans are evicted first.
+#[derive(Clone, Debug)]
+pub struct OrphanPool {
+    blocks: HashMap<BlockHash, (Block, usize)>,
+    /// Orphans waiting on each missing parent, oldest first
+    by_parent: HashMap<BlockHash, Vec<BlockHash>>,
+    /// Arrival order, oldest first
+    order: VecDeque<BlockHash>,
+    max_count: usize,
+    max_bytes: usize,
+    total_bytes: usize,
+}
+
+impl OrphanPool {
+    pub fn new(max_count: usize, max_bytes: usize) -> Self {
+        OrphanPool {
+            blocks: HashMap::new(),
+            by_parent: HashMap::new(),
+            order: VecDeque::new(),
+            max_count,
+            max_bytes,
+            total_bytes: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Total encoded size of the held blocks
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    /// Held blocks, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = &Block> {
+        self.order.iter().map(|hash| &self.blocks[hash].0)
+    }
+
+    /// Hold `block` until its parent connects, evicting the oldest orphans
+    /// to stay within the limits.
+    ///
+    /// Returns `false` if the block was already held or is too large to fit
+    /// in the pool at all.
+    pub fn insert(&mut self, block: Block) -> bool {
+        let hash = block.hash();
+        let size = block.to_bytes().len();
+        if self.blocks.contains_key(&hash) || size > self.max_bytes || self.max_count == 0 {
+            return false;
+        }
+        while self.blocks.len() >= self.max_count || self.total_bytes + size > self.max_bytes {
+            let oldest = self.order[0];
+            self.remove(&oldest);
+        }
+
+        self.by_parent
+            .entry(block.prev_block_hash())
+            .or_default()
+            .push(hash);
+        self.order.push_back(hash);
+        self.total_bytes += size;
+        self.blocks.insert(hash, (block, size));
+        true
+    }
+
+    /// Remove and return the orphans whose parent is `parent`, oldest first
+    pub fn take_children(&mut self, parent: &BlockHash) -> Vec<Block> {
+        let children = self.by_parent.remove(parent).unwrap_or_default();
+        children.iter().filter_map(|hash| self.take(hash)).collect()
+    }
+
+    fn remove(&mut self, hash: &BlockHash) {
+        let Some(block) = self.take(hash) else {
+            return;
+        };
+        let parent = block.prev_block_hash();
+        if let Some(siblings) = self.by_parent.get_mut(&parent) {
+            siblings.retain(|sibling| sibling != hash);
+            if siblings.is_empty() {
+                self.by_parent.remove(&parent);
+            }
+        }
+    }
+
+    /// Drop `hash` from the block map and arrival order, leaving `by_parent` to the caller
+    fn take(&mut self, hash: &BlockHash) -> Option<Block> {
+        let (block, size) = self.blocks.remove(hash)?;
+        self.total_bytes -= size;
+        if let Some(position) = self.order.iter().position(|held| held == hash) {
+            self.order.remove(position);
+        }
+        Some(block)
+    }
+}
+
+impl Default for OrphanPool {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ORPHANS, DEFAULT_MAX_ORPHAN_BYTES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(parent: BlockHash, tx: &[u8]) -> Block {
+        Block::new(vec![tx.to_vec()], parent).unwrap()
+    }
+
+    #[test]
+    fn test_evicts_oldest_when_full() {
+        let parent = BlockHash::ZERO;
+        let blocks: Vec<Block> = (0..4u8).map(|i| block(parent, &[i; 100])).collect();
+        let size = blocks[0].to_bytes().len();
+
+        let mut pool = OrphanPool::new(3, usize::MAX);
+        for block in &blocks {
+            assert!(pool.insert(block.clone()));
+        }
+        assert!(!pool.insert(blocks[3].clone()));
+        let held: Vec<BlockHash> = pool.iter().map(Block::hash).collect();
+        assert_eq!(held, [blocks[1].hash(), blocks[2].hash(), blocks[3].hash()]);
+        assert_eq!(pool.total_bytes(), 3 * size);
+
+        // The byte limit evicts as many of the oldest as it takes
+        let mut pool = OrphanPool::new(10, 2 * size + 1);
+        for block in &blocks {
+            pool.insert(block.clone());
+        }
+        assert_eq!(pool.len(), 2);
+        assert!(!pool.contains(&blocks[1].hash()));
+        assert!(pool.contains(&blocks[2].hash()));
+        assert!(!pool.insert(block(parent, &[0; 1000])));
+
+        let children = pool.take_children(&parent);
+        assert_eq!(children, vec![blocks[2].clone(), blocks[3].clone()]);
+        assert!(pool.is_empty());
+        assert_eq!(pool.total_bytes(), 0);
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::Blockchain;
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::difficulty::Work;
+use crate::merkle_trie::MerkleTree;
+use crate::store::{ChainStore, FileStore, MemoryStore};
+
+// Readers on other threads need these behind an `Arc<RwLock<..>>`
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Block>();
+    assert_send_sync::<MerkleTree>();
+    assert_send_sync::<Blockchain<MemoryStore>>();
+    assert_send_sync::<Blockchain<FileStore>>();
+    assert_send_sync::<SharedChain<MemoryStore>>();
+};
+
+/// The tip of the active chain as seen at one instant, from [`SharedChain::view`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChainView {
+    pub height: u64,
+    pub tip_hash: BlockHash,
+    pub tip: BlockHeader,
+    pub total_work: Work,
+}
+
+/// A chain shared between threads, read by many and written by one at a time.
+///
+/// Each read method takes the lock only long enough to copy its answer out,
+/// so callers never hold it across their own code. Every change to the chain
+/// happens under the write lock, so a read sees the chain either before or
+/// after a reorg, never part way through. Separate calls may straddle a
+/// write, though: to read several facts about the same tip, take a
+/// [`ChainView`].
+pub struct SharedChain<S: ChainStore = MemoryStore> {
+    inner: Arc<RwLock<Blockchain<S>>>,
+}
+
+impl<S: ChainStore> Clone for SharedChain<S> {
+    fn clone(&self) -> Self {
+        SharedChain {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+impl<S: ChainStore> SharedChain<S> {
+    pub fn new(chain: Blockchain<S>) -> Self {
+        SharedChain {
+            inner: Arc::new(RwLock::new(chain)),
+        }
+    }
+
+    /// Hash of the active tip
+    pub fn tip_hash(&self) -> BlockHash {
+        let chain = self.read();
+        chain.active[chain.active.len() - 1]
+    }
+
+    /// Height of the active tip
+    pub fn height(&self) -> u64 {
+        self.read().height()
+    }
+
+    /// The header at `height` on the active chain; see [`Blockchain::header`]
+    pub fn get_header(&self, height: u64) -> Option<BlockHeader> {
+        self.read().header(height).cloned()
+    }
+
+    /// The tip's height, hash, header and total work, all read under one lock
+    pub fn view(&self) -> ChainView {
+        let chain = self.read();
+        let tip = chain.tip().header().clone();
+        ChainView {
+            height: chain.height(),
+            tip_hash: chain.active[chain.active.len() - 1],
+            tip,
+            total_work: chain.total_work(),
+        }
+    }
+
+    /// Run `f` with shared access to the chain, holding the read lock until it returns
+    pub fn with_read<R>(&self, f: impl FnOnce(&Blockchain<S>) -> R) -> R {
+        f(&self.read())
+    }
+
+    /// Run `f` with exclusive access to the chain, holding the write lock
+    /// until it returns
+    pub fn with_write<R>(&self, f: impl FnOnce(&mut Blockchain<S>) -> R) -> R {
+        f(&mut self.write())
+    }
+
+    /// A lock that panicked while held may have left the chain half-updated,
+    /// so poisoning is passed on rather than ignored
+    #[allow(clippy::expect_used)]
+    fn read(&self) -> RwLockReadGuard<'_, Blockchain<S>> {
+        self.inner
+            .read()
+            .expect("a writer panicked while updating the chain")
+    }
+
+    #[allow(clippy::expect_used)]
+    fn write(&self) -> RwLockWriteGuard<'_, Blockchain<S>> {
+        self.inner
+            .write()
+            .expect("a writer panicked while updating the chain")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{mined_chain, mined_child};
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_readers_never_see_a_torn_reorg() {
+        let chain = mined_chain(6);
+        let old = (chain.height(), chain.tip().hash());
+        let fork_point = chain.get(2).unwrap().clone();
+        let mut branch = vec![mined_child(&fork_point, b"b0")];
+        for i in 1..4u8 {
+            let block = mined_child(branch.last().unwrap(), &[b'b', i]);
+            branch.push(block);
+        }
+        let new = (6, branch[3].hash());
+
+        let shared = SharedChain::new(chain);
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (shared, done) = (shared.clone(), Arc::clone(&done));
+                thread::spawn(move || {
+                    let mut views = 0;
+                    while !done.load(Ordering::Acquire) || views == 0 {
+                        let view = shared.view();
+                        assert!([old, new].contains(&(view.height, view.tip_hash)));
+                        assert_eq!(view.tip.hash(), view.tip_hash);
+                        views += 1;
+                    }
+                })
+            })
+            .collect();
+
+        for block in branch {
+            shared.with_write(|chain| chain.insert(block)).unwrap();
+        }
+        done.store(true, Ordering::Release);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+
+        assert_eq!((shared.height(), shared.tip_hash()), new);
+        assert_eq!(
+            shared.get_header(3).unwrap().prev_block_hash(),
+            fork_point.hash()
+        );
+        assert_eq!(shared.get_header(7), None);
+        assert_eq!(
+            shared.view().total_work,
+            shared.with_read(Blockchain::total_work)
+        );
+    }
+}
@@ -0,0 +1,796 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::OnceLock;
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::utxo::ChainUtxos;
+use super::{BlockTree, Blockchain, ChainError, ChainValidationError, ChainValidationErrorKind};
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::codec::{self, DecodeError, DecodeLimits, Reader};
+use crate::difficulty::Work;
+#[cfg(feature = "serde")]
+use crate::encoding;
+use crate::params::ChainParams;
+use crate::store::{ChainStore, MemoryStore};
+use crate::utxo::UtxoSet;
+
+/// First bytes of a binary snapshot
+const MAGIC: &[u8; 4] = b"AWSN";
+const VERSION: u32 = 1;
+
+/// Encodings [`Blockchain::export`] can write; [`Blockchain::import`] reads either
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    /// `AWSN`, a `u32` version and a `u64` block count, then each block in
+    /// the binary block format behind a `u32` length, all little-endian
+    Binary,
+    /// An object with `version` and a `blocks` array; each entry holds the
+    /// block's `height` and `hash` for reading, and the hex of its binary
+    /// encoding under `block`, which alone is used on import
+    #[cfg(feature = "serde")]
+    Json,
+}
+
+/// The JSON form of a snapshot, as [`SnapshotFormat::Json`] describes
+#[cfg(feature = "serde")]
+#[derive(Deserialize)]
+struct JsonSnapshot {
+    version: u32,
+    blocks: Vec<JsonEntry>,
+}
+
+/// An entry of [`JsonSnapshot::blocks`]; `height` and `hash` are only for
+/// reading, so import ignores them
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct JsonEntry {
+    #[serde(skip_deserializing)]
+    height: u64,
+    #[serde(skip_deserializing)]
+    hash: String,
+    block: String,
+}
+
+/// Reasons a snapshot or a batch of blocks cannot be imported
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ImportError {
+    /// The snapshot could not be read
+    #[error("failed to read snapshot: {0}")]
+    Io(String),
+    /// The snapshot's framing or JSON structure is malformed
+    #[error("malformed snapshot: {0}")]
+    Format(String),
+    /// The block at `height` could not be decoded
+    #[error("block at height {height} cannot be decoded: {err}")]
+    Decode { height: u64, err: DecodeError },
+    /// The block at the error's height failed validation
+    #[error("{0}")]
+    Invalid(ChainValidationError),
+    /// The block at `index` of a batch, which would have sat at `height`,
+    /// was refused by the chain
+    #[error("block {index} of the batch cannot be added at height {height}: {err}")]
+    Rejected {
+        index: usize,
+        height: u64,
+        err: ChainError,
+    },
+}
+
+impl ImportError {
+    /// Height of the offending block, for errors tied to one
+    pub fn height(&self) -> Option<u64> {
+        match self {
+            ImportError::Decode { height, .. } => Some(*height),
+            ImportError::Invalid(err) => Some(err.height),
+            ImportError::Rejected { height, .. } => Some(*height),
+            ImportError::Io(_) | ImportError::Format(_) => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(err: io::Error) -> Self {
+        ImportError::Io(err.to_string())
+    }
+}
+
+impl<S: ChainStore> Blockchain<S> {
+    /// Write every block of the active chain, genesis first, to `w`.
+    ///
+    /// A pruned chain no longer has every block and cannot be exported.
+    pub fn export(&self, mut w: impl Write, format: SnapshotFormat) -> io::Result<()> {
+        if self.pruned_height() > 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a pruned chain cannot be exported",
+            ));
+        }
+        match format {
+            SnapshotFormat::Binary => {
+                w.write_all(MAGIC)?;
+                w.write_all(&VERSION.to_le_bytes())?;
+                w.write_all(&(self.active.len() as u64).to_le_bytes())?;
+                for block in self.try_iter() {
+                    let bytes = block.map_err(io::Error::other)?.to_bytes();
+                    w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+                    w.write_all(&bytes)?;
+                }
+            }
+            // Written an entry at a time rather than as one `JsonSnapshot`,
+            // to hold one block's hex in memory instead of the whole chain's
+            #[cfg(feature = "serde")]
+            SnapshotFormat::Json => {
+                write!(w, "{{\"version\":{},\"blocks\":[", VERSION)?;
+                for (height, block) in self.try_iter().enumerate() {
+                    let block = block.map_err(io::Error::other)?;
+                    let entry = JsonEntry {
+                        height: height as u64,
+                        hash: block.hash().to_string(),
+                        block: hex::encode(block.to_bytes()),
+                    };
+                    w.write_all(if height == 0 { b"\n" } else { b",\n" })?;
+                    serde_json::to_writer(&mut w, &entry)?;
+                }
+                writeln!(w, "\n]}}")?;
+            }
+        }
+        w.flush()
+    }
+
+    /// Append `blocks` in order as [`Blockchain::append`] would, and return
+    /// the new height.
+    ///
+    /// The checks that need no other block, such as merkle roots, proof of
+    /// work and sizes, run for the whole batch in parallel first; links,
+    /// difficulty, timestamps and checkpoints are then checked one block at a
+    /// time as each is appended. The outcome matches appending one by one: on
+    /// failure the blocks before the bad one stay appended and the error
+    /// gives its index in `blocks`.
+    pub fn import_batch(&mut self, blocks: Vec<Block>) -> Result<u64, ImportError> {
+        let checks: Vec<_> = blocks
+            .par_iter()
+            .map(|block| self.validator.validate(block))
+            .collect();
+        for (index, (block, checked)) in blocks.into_iter().zip(checks).enumerate() {
+            let height = self.height() + 1;
+            let started = self.start_timer();
+            self.append_checked(block, checked, started)
+                .map_err(|err| ImportError::Rejected { index, height, err })?;
+        }
+        Ok(self.height())
+    }
+}
+
+impl Blockchain {
+    /// Rebuild a chain from a snapshot written by [`Blockchain::export`] in
+    /// either format, fully validating it against `params`.
+    ///
+    /// The first block must be the genesis block `params` derive. On failure
+    /// the error carries the height of the first bad block.
+    pub fn import(r: impl Read, params: &ChainParams) -> Result<Self, ImportError> {
+        let chain = Self::load(r, params)?;
+        chain.validate(params).map_err(ImportError::Invalid)?;
+        Ok(chain)
+    }
+
+    /// Like [`Blockchain::import`], but only check that the genesis block
+    /// matches and every block links to the one before it
+    pub fn import_trusted(r: impl Read, params: &ChainParams) -> Result<Self, ImportError> {
+        Self::load(r, params)
+    }
+
+    fn load(r: impl Read, params: &ChainParams) -> Result<Self, ImportError> {
+        let mut r = BufReader::new(r);
+        let is_json = loop {
+            let buf = r.fill_buf()?;
+            match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+                Some(i) => {
+                    let is_json = buf[i] == b'{';
+                    break is_json;
+                }
+                None if buf.is_empty() => return Err(ImportError::Format("empty input".into())),
+                None => {
+                    let len = buf.len();
+                    r.consume(len);
+                }
+            }
+        };
+
+        let mut chain: Option<Blockchain> = None;
+        let mut push = |height: u64, block: Block| match &mut chain {
+            None => {
+                let expected = params.genesis_hash();
+                if block.hash() != expected {
+                    return Err(invalid(
+                        height,
+                        ChainValidationErrorKind::GenesisMismatch {
+                            expected,
+                            got: block.hash(),
+                        },
+                    ));
+                }
+                chain = Some(Blockchain::new_from_params(params));
+                Ok(())
+            }
+            Some(chain) => chain
+                .push_linked(block)
+                .map_err(|kind| invalid(height, kind)),
+        };
+
+        let limits = DecodeLimits::default();
+        let decode = |height: u64, bytes: &[u8]| {
+            Block::from_bytes(bytes, &limits).map_err(|err| ImportError::Decode { height, err })
+        };
+        if is_json {
+            #[cfg(feature = "serde")]
+            {
+                let snapshot: JsonSnapshot = serde_json::from_reader(r).map_err(|err| {
+                    if err.is_io() {
+                        ImportError::Io(err.to_string())
+                    } else {
+                        ImportError::Format(err.to_string())
+                    }
+                })?;
+                check_version(snapshot.version)?;
+                for (height, entry) in snapshot.blocks.into_iter().enumerate() {
+                    let height = height as u64;
+                    let bytes = encoding::from_hex(&entry.block).map_err(|err| {
+                        ImportError::Format(format!("entry {} block hex: {}", height, err))
+                    })?;
+                    push(height, decode(height, &bytes)?)?;
+                }
+            }
+            #[cfg(not(feature = "serde"))]
+            return Err(ImportError::Format(
+                "JSON snapshots need the `serde` feature".into(),
+            ));
+        } else {
+            let mut header = [0u8; 16];
+            r.read_exact(&mut header)?;
+            let [m0, m1, m2, m3, v0, v1, v2, v3, count @ ..] = header;
+            if &[m0, m1, m2, m3] != MAGIC {
+                return Err(ImportError::Format("not a snapshot".into()));
+            }
+            check_version(u32::from_le_bytes([v0, v1, v2, v3]))?;
+            let count = u64::from_le_bytes(count);
+            for height in 0..count {
+                let mut len = [0u8; 4];
+                r.read_exact(&mut len)?;
+                let len = u32::from_le_bytes(len) as usize;
+                if len > limits.max_decode_bytes {
+                    return Err(ImportError::Decode {
+                        height,
+                        err: DecodeError::LimitExceeded {
+                            what: "input size",
+                            value: len as u64,
+                            max: limits.max_decode_bytes as u64,
+                        },
+                    });
+                }
+                let mut bytes = vec![0u8; len];
+                r.read_exact(&mut bytes)?;
+                push(height, decode(height, &bytes)?)?;
+            }
+        }
+
+        chain.ok_or_else(|| ImportError::Format("no blocks".into()))
+    }
+}
+
+impl Blockchain<MemoryStore> {
+    /// Put `block` on top of the tip without checking anything but its link
+    #[allow(clippy::expect_used)]
+    fn push_linked(&mut self, block: Block) -> Result<(), ChainValidationErrorKind> {
+        let expected = self.active[self.active.len() - 1];
+        if block.prev_block_hash() != expected {
+            return Err(ChainValidationErrorKind::PrevHashMismatch {
+                expected,
+                got: block.prev_block_hash(),
+            });
+        }
+        let stored = self
+            .tree
+            .prepare(block)
+            .expect("a block linking to the tip is new");
+        let height = stored.height();
+        self.store
+            .put_block(stored.block())
+            .and_then(|_| {
+                self.store
+                    .put_tip(height, &[stored.hash()], stored.cumulative_work())
+            })
+            .expect("the memory store does not fail");
+        let hash = self.tree.store(stored);
+        self.set_active(height as usize, &[hash]);
+        Ok(())
+    }
+}
+
+/// The state a node needs to resume a chain at some height without replaying
+/// the blocks below it, created by [`Blockchain::snapshot_at`].
+///
+/// A snapshot holds the header of every block up to its height and the full
+/// block at that height, which new blocks build on. When the source chain
+/// tracks its unspent outputs (see [`Blockchain::with_utxo_set`]), it also
+/// holds the set after the tip, committed to by the state root in the tip's
+/// header; the headers commit to each other by hash, so one that matches a
+/// checkpoint vouches for everything below it.
+///
+/// Serde keeps all three fields, in order. As with [`Snapshot::from_bytes`],
+/// nothing is validated until [`Blockchain::from_snapshot`].
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Snapshot {
+    /// Headers below the tip, genesis first
+    headers: Vec<BlockHeader>,
+    tip: Block,
+    /// Unspent outputs after the tip, if the source chain tracked them
+    utxos: Option<UtxoSet>,
+}
+
+impl Snapshot {
+    /// Height of the snapshot's tip
+    pub fn height(&self) -> u64 {
+        self.headers.len() as u64
+    }
+
+    /// The block the snapshot was taken at
+    pub fn tip(&self) -> &Block {
+        &self.tip
+    }
+
+    /// Headers from genesis up to and including the tip's
+    pub fn headers(&self) -> impl Iterator<Item = &BlockHeader> {
+        self.headers.iter().chain([self.tip.header()])
+    }
+
+    /// The unspent outputs after the tip, if the snapshot carries them
+    pub fn utxo_set(&self) -> Option<&UtxoSet> {
+        self.utxos.as_ref()
+    }
+
+    /// Serialize as a varint header count, the fixed-size encoding of each
+    /// header below the tip, the tip block in the binary block format behind
+    /// a varint length, then a `0` byte, or a `1` byte and the unspent
+    /// outputs as [`UtxoSet::to_bytes`] encodes them
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let tip = self.tip.to_bytes();
+        let mut buffer =
+            Vec::with_capacity(10 + self.headers.len() * BlockHeader::ENCODED_LEN + 10 + tip.len());
+        codec::write_varint(&mut buffer, self.headers.len() as u64);
+        for header in &self.headers {
+            buffer.extend_from_slice(&header.to_bytes());
+        }
+        codec::write_bytes(&mut buffer, &tip);
+        match &self.utxos {
+            Some(set) => {
+                buffer.push(1);
+                buffer.extend_from_slice(&set.to_bytes());
+            }
+            None => buffer.push(0),
+        }
+        buffer
+    }
+
+    /// Decode a snapshot produced by [`Snapshot::to_bytes`], enforcing
+    /// `limits` on untrusted input.
+    ///
+    /// Nothing is validated; [`Blockchain::from_snapshot`] does that.
+    pub fn from_bytes(bytes: &[u8], limits: &DecodeLimits) -> Result<Snapshot, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let mut reader = Reader::new(bytes);
+        let max_headers = reader.remaining() / BlockHeader::ENCODED_LEN;
+        let count = reader.read_len("header count", max_headers)?;
+        let headers = (0..count)
+            .map(|_| BlockHeader::decode(&mut reader))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tip = reader.read_var_bytes("tip block size", limits.max_decode_bytes)?;
+        let tip = Block::from_bytes(tip, limits)?;
+        let utxos = match reader.read_u8()? {
+            0 => None,
+            1 => Some(UtxoSet::decode_from(&mut reader)?),
+            _ => return Err(DecodeError::InvalidValue("unspent outputs flag")),
+        };
+        reader.finish()?;
+        Ok(Snapshot {
+            headers,
+            tip,
+            utxos,
+        })
+    }
+}
+
+impl<S: ChainStore> Blockchain<S> {
+    /// The headers up to `height` on the active chain, the block there and,
+    /// if the chain tracks them, the unspent outputs after it; `None` above
+    /// the tip, below the pruned height or if the store cannot read them
+    pub fn snapshot_at(&self, height: u64) -> Option<Snapshot> {
+        let tip = self.get(height)?.clone();
+        let headers = (0..height as usize)
+            .map(|height| self.header_at(height).cloned())
+            .collect::<Result<_, _>>()
+            .ok()?;
+        // Only the outputs are kept, as the encodings do; the chain restoring
+        // the snapshot resumes them at its own height and maturity
+        let utxos = match &self.utxos {
+            Some(utxos) => Some(
+                utxos
+                    .set_at(&self.active, height)?
+                    .with_coinbase_maturity(0)
+                    .resume_at(0),
+            ),
+            None => None,
+        };
+        Some(Snapshot {
+            headers,
+            tip,
+            utxos,
+        })
+    }
+}
+
+impl Blockchain {
+    /// Resume a chain from `snapshot`, after checking its headers and tip
+    /// block against `params` as [`Blockchain::validate`] would.
+    ///
+    /// The blocks below the snapshot's height are treated as pruned: their
+    /// headers answer [`Blockchain::header`], but they have no bodies and
+    /// nothing can be built on or rolled back to them. The genesis block and
+    /// every checkpoint up to the snapshot's height must match, so a snapshot
+    /// taken at or above a checkpoint is tied to the checkpointed chain.
+    ///
+    /// A snapshot carrying unspent outputs must have a tip header committing
+    /// to their [`UtxoSet::state_root`], and the chain tracks them from there
+    /// as [`Blockchain::with_utxo_set`] would have.
+    #[allow(clippy::expect_used)]
+    pub fn from_snapshot(snapshot: Snapshot, params: &ChainParams) -> Result<Self, ImportError> {
+        let Snapshot {
+            headers,
+            tip,
+            utxos,
+        } = snapshot;
+        let height = headers.len() as u64;
+        let hashes: Vec<BlockHash> = headers
+            .iter()
+            .map(BlockHeader::hash)
+            .chain([tip.hash()])
+            .collect();
+        let work: Work = headers
+            .iter()
+            .chain([tip.header()])
+            .map(|header| Work::of(header.difficulty()))
+            .sum();
+
+        let mut store = MemoryStore::new();
+        for header in &headers {
+            store.put_header(header);
+        }
+        store
+            .put_block(&tip)
+            .and_then(|_| store.put_tip(0, &hashes, work.0))
+            .and_then(|_| store.prune_below(height))
+            .expect("the memory store does not fail");
+
+        let mut chain = Self::from_parts(
+            BlockTree::with_root(tip, height, work.0),
+            params.clone(),
+            store,
+        );
+        chain.archived = (0..height).map(|_| OnceLock::new()).collect();
+        chain.pruned = headers.into_iter().map(OnceLock::from).collect();
+        chain.set_active(0, &hashes);
+        chain.validate(params).map_err(ImportError::Invalid)?;
+        if let Some(set) = utxos {
+            let expected = set.state_root();
+            match chain.tip().header().state_root() {
+                Some(&got) if got == expected => {}
+                Some(&got) => {
+                    return Err(invalid(
+                        height,
+                        ChainValidationErrorKind::StateRootMismatch { expected, got },
+                    ))
+                }
+                None => return Err(invalid(height, ChainValidationErrorKind::MissingStateRoot)),
+            }
+            let set = set
+                .with_coinbase_maturity(params.coinbase_maturity)
+                .resume_at(height + 1);
+            chain.utxos = Some(ChainUtxos::resume(set));
+        }
+        Ok(chain)
+    }
+}
+
+fn invalid(height: u64, kind: ChainValidationErrorKind) -> ImportError {
+    ImportError::Invalid(ChainValidationError { height, kind })
+}
+
+fn check_version(version: u32) -> Result<(), ImportError> {
+    if version != VERSION {
+        return Err(ImportError::Format(format!(
+            "unsupported snapshot version {}",
+            version
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{mined_chain, mined_child, test_params};
+    use super::*;
+
+    #[test]
+    fn test_round_trip_both_formats() {
+        let chain = mined_chain(12);
+        for format in [
+            SnapshotFormat::Binary,
+            #[cfg(feature = "serde")]
+            SnapshotFormat::Json,
+        ] {
+            let mut snapshot = Vec::new();
+            chain.export(&mut snapshot, format).unwrap();
+            for imported in [
+                Blockchain::import(&snapshot[..], &test_params()).unwrap(),
+                Blockchain::import_trusted(&snapshot[..], &test_params()).unwrap(),
+            ] {
+                assert!(imported.iter().eq(chain.iter()));
+                assert_eq!(
+                    imported
+                        .tree()
+                        .get(&imported.tip().hash())
+                        .unwrap()
+                        .cumulative_work(),
+                    chain
+                        .tree()
+                        .get(&chain.tip().hash())
+                        .unwrap()
+                        .cumulative_work()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_reports_height_of_corrupted_block() {
+        let chain = mined_chain(8);
+
+        // Flip the last transaction byte of block 5 in a binary snapshot
+        let mut snapshot = Vec::new();
+        chain.export(&mut snapshot, SnapshotFormat::Binary).unwrap();
+        let end: usize = 16
+            + chain
+                .iter()
+                .take(6)
+                .map(|b| 4 + b.to_bytes().len())
+                .sum::<usize>();
+        snapshot[end - 1] ^= 1;
+        let err = Blockchain::import(&snapshot[..], &test_params())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err,
+            invalid(5, ChainValidationErrorKind::MerkleRootMismatch)
+        );
+        assert_eq!(err.height(), Some(5));
+
+        // Trusted import skips the merkle check and loads the corrupted block
+        let trusted = Blockchain::import_trusted(&snapshot[..], &test_params()).unwrap();
+        assert_eq!(trusted.height(), 7);
+        assert!(matches!(
+            Blockchain::import(&b"definitely not a snapshot"[..], &test_params()),
+            Err(ImportError::Format(_))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_reports_height_of_corrupted_json_block() {
+        let chain = mined_chain(8);
+
+        // Replace block 3 in a JSON snapshot with an unrelated block
+        let mut json = Vec::new();
+        chain.export(&mut json, SnapshotFormat::Json).unwrap();
+        let text = String::from_utf8(json).unwrap();
+        let original = hex::encode(chain.get(3).unwrap().to_bytes());
+        let other = hex::encode(
+            Block::new(vec![b"x".to_vec()], chain.get(1).unwrap().hash())
+                .unwrap()
+                .to_bytes(),
+        );
+        let corrupted = text.replace(&original, &other);
+        let err = Blockchain::import_trusted(corrupted.as_bytes(), &test_params())
+            .err()
+            .unwrap();
+        assert_eq!(err.height(), Some(3));
+        assert!(matches!(
+            err,
+            ImportError::Invalid(ChainValidationError {
+                kind: ChainValidationErrorKind::PrevHashMismatch { .. },
+                ..
+            })
+        ));
+
+        // Truncated block bytes
+        let truncated = text.replace(&original, &original[..original.len() - 2]);
+        let err = Blockchain::import(truncated.as_bytes(), &test_params())
+            .err()
+            .unwrap();
+        assert!(matches!(err, ImportError::Decode { height: 3, .. }));
+
+        // A snapshot from a chain with another genesis
+        let other = ChainParams {
+            genesis_timestamp: 1,
+            ..test_params()
+        };
+        let err = Blockchain::import(text.as_bytes(), &other).err().unwrap();
+        assert!(matches!(
+            err,
+            ImportError::Invalid(ChainValidationError {
+                height: 0,
+                kind: ChainValidationErrorKind::GenesisMismatch { .. },
+            })
+        ));
+
+        // Malformed JSON, and a version this crate does not write
+        for text in [
+            text.replace("]}", "]"),
+            text.replace("\"version\":1", "\"version\":2"),
+            text.replace("\"block\":", "\"blob\":"),
+        ] {
+            assert!(matches!(
+                Blockchain::import(text.as_bytes(), &test_params()),
+                Err(ImportError::Format(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_fast_sync_from_snapshot() {
+        let source = mined_chain(200);
+        let snapshot = source.snapshot_at(150).unwrap();
+        assert_eq!(snapshot.height(), 150);
+        assert_eq!(snapshot.headers().count(), 151);
+        let bytes = snapshot.to_bytes();
+        let decoded = Snapshot::from_bytes(&bytes, &DecodeLimits::default()).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let mut restored = Blockchain::from_snapshot(decoded, &test_params()).unwrap();
+        assert_eq!(restored.height(), 150);
+        assert_eq!(restored.pruned_height(), 150);
+        assert_eq!(restored.tip(), source.get(150).unwrap());
+        assert_eq!(restored.work_at(150), source.work_at(150));
+        assert_eq!(restored.header(10), source.header(10));
+        assert!(restored.get(10).is_none());
+
+        // Catch up with the source, then keep going on our own
+        for block in source.range(151..).unwrap() {
+            restored.append(block.clone()).unwrap();
+        }
+        assert_eq!(restored.tip(), source.tip());
+        assert_eq!(restored.total_work(), source.total_work());
+        for i in 0..5 {
+            let block = mined_child(restored.tip(), &[b'n', i]);
+            restored.append(block).unwrap();
+        }
+        assert_eq!(restored.height(), 204);
+        restored.validate(&test_params()).unwrap();
+
+        assert!(source.snapshot_at(200).is_none());
+        let genesis_only = source.snapshot_at(0).unwrap();
+        assert_eq!(
+            Blockchain::from_snapshot(genesis_only, &test_params())
+                .unwrap()
+                .tip()
+                .hash(),
+            test_params().genesis_hash()
+        );
+    }
+
+    #[test]
+    fn test_from_snapshot_rejects_bad_commitments() {
+        let source = mined_chain(20);
+        let snapshot = source.snapshot_at(15).unwrap();
+
+        // A tip block whose transactions no longer match its merkle root
+        // The last byte of the tip, before the flag for no unspent outputs
+        let mut bytes = snapshot.to_bytes();
+        let last = bytes.len() - 2;
+        bytes[last] ^= 1;
+        let tampered = Snapshot::from_bytes(&bytes, &DecodeLimits::default()).unwrap();
+        assert_eq!(
+            Blockchain::from_snapshot(tampered, &test_params()).err(),
+            Some(invalid(15, ChainValidationErrorKind::MerkleRootMismatch))
+        );
+
+        // A header that no longer links to the one below it
+        let mut headers = snapshot.clone();
+        headers.headers.swap(6, 7);
+        let err = Blockchain::from_snapshot(headers, &test_params())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            ImportError::Invalid(ChainValidationError {
+                height: 6,
+                kind: ChainValidationErrorKind::PrevHashMismatch { .. },
+            })
+        ));
+
+        // A checkpoint the snapshot's headers contradict
+        let checkpointed =
+            test_params().with_checkpoints(vec![(10, source.get(9).unwrap().hash())]);
+        let err = Blockchain::from_snapshot(snapshot.clone(), &checkpointed)
+            .err()
+            .unwrap();
+        assert_eq!(err.height(), Some(10));
+        let checkpointed =
+            test_params().with_checkpoints(vec![(10, source.get(10).unwrap().hash())]);
+        assert!(Blockchain::from_snapshot(snapshot.clone(), &checkpointed).is_ok());
+
+        // A snapshot of another chain
+        let other = ChainParams {
+            genesis_timestamp: 1,
+            ..test_params()
+        };
+        assert!(matches!(
+            Blockchain::from_snapshot(snapshot, &other),
+            Err(ImportError::Invalid(ChainValidationError { height: 0, .. }))
+        ));
+        assert!(Snapshot::from_bytes(&bytes[..bytes.len() - 1], &DecodeLimits::default()).is_err());
+    }
+
+    #[test]
+    fn test_import_batch_matches_sequential_appends() {
+        let source = mined_chain(1001);
+        let blocks: Vec<Block> = source.range(1..).unwrap().cloned().collect();
+
+        let mut batched = Blockchain::new_from_params(&test_params());
+        assert_eq!(batched.import_batch(blocks.clone()), Ok(1000));
+        let mut sequential = Blockchain::new_from_params(&test_params());
+        for block in blocks.clone() {
+            sequential.append(block).unwrap();
+        }
+        assert!(batched.iter().eq(sequential.iter()));
+        assert_eq!(batched.total_work(), sequential.total_work());
+        assert_eq!(batched.store().get_tip(), sequential.store().get_tip());
+        assert_eq!(batched.import_batch(Vec::new()), Ok(1000));
+
+        // A bad merkle root at index 700 is reported only after the broken
+        // link at index 400, as sequential appends would find them
+        let mut broken = blocks;
+        let mut bytes = broken[700].to_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        broken[700] = Block::from_bytes(&bytes, &DecodeLimits::default()).unwrap();
+        broken.remove(400);
+        let mut chain = Blockchain::new_from_params(&test_params());
+        let err = chain.import_batch(broken.clone()).err().unwrap();
+        assert!(matches!(
+            err,
+            ImportError::Rejected {
+                index: 400,
+                height: 401,
+                err: ChainError::PrevHashMismatch { .. },
+            }
+        ));
+        assert_eq!(chain.height(), 400);
+        assert_eq!(chain.tip(), source.get(400).unwrap());
+
+        let mut chain = Blockchain::new_from_params(&test_params());
+        broken.insert(400, source.get(401).unwrap().clone());
+        let err = chain.import_batch(broken).err().unwrap();
+        assert_eq!(err.height(), Some(701));
+        assert!(matches!(
+            err,
+            ImportError::Rejected {
+                index: 700,
+                err: ChainError::InvalidBlock(_),
+                ..
+            }
+        ));
+        assert_eq!(chain.height(), 700);
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{Blockchain, ChainError};
+use crate::difficulty::{Difficulty, Work};
+use crate::store::ChainStore;
+
+/// Aggregate figures for the recent active chain, from [`Blockchain::stats`].
+///
+/// The windowed fields cover the last `blocks` blocks up to the tip. Pruned
+/// blocks have no transactions to count, so the window never reaches below
+/// the pruned height.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChainStats {
+    /// Number of blocks in the window: the requested size, capped at the
+    /// number of unpruned blocks. 0 only for an empty window
+    pub blocks: u64,
+    /// Mean seconds between consecutive blocks in the window, which may be
+    /// negative under [`crate::params::TimestampRule::Any`]. `None` with fewer
+    /// than two blocks, as there is no interval to measure
+    pub average_block_interval: Option<f64>,
+    /// Transactions in the window's blocks; 0 for an empty window
+    pub transactions: u64,
+    /// `transactions` divided by `blocks`; `None` for an empty window
+    pub mean_transactions_per_block: Option<f64>,
+    /// Difficulty the tip commits to, whatever the window
+    pub difficulty: Difficulty,
+    /// Work of the whole active chain, whatever the window
+    pub total_work: Work,
+    /// Seconds from the tip's timestamp to now; 0 for a tip from the future
+    pub tip_age: u64,
+}
+
+impl<S: ChainStore> Blockchain<S> {
+    /// Statistics over at most the last `window` blocks, with the tip's age
+    /// measured against the system clock. Fails if the store cannot read a
+    /// block in the window.
+    pub fn stats(&self, window: usize) -> Result<ChainStats, ChainError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.stats_at(window, now)
+    }
+
+    /// Like [`Blockchain::stats`], measuring the tip's age at the Unix time `now`
+    pub fn stats_at(&self, window: usize, now: u64) -> Result<ChainStats, ChainError> {
+        let mut blocks = 0u64;
+        let mut transactions = 0u64;
+        let mut newest = None;
+        let mut oldest = None;
+        for block in self.try_iter().rev().take(window) {
+            let block = block?;
+            blocks += 1;
+            transactions += block.transactions().len() as u64;
+            newest.get_or_insert(block.timestamp());
+            oldest = Some(block.timestamp());
+        }
+
+        let average_block_interval = match (newest, oldest) {
+            (Some(newest), Some(oldest)) if blocks > 1 => {
+                Some((newest as f64 - oldest as f64) / (blocks - 1) as f64)
+            }
+            _ => None,
+        };
+        let tip = self.tip();
+        Ok(ChainStats {
+            blocks,
+            average_block_interval,
+            transactions,
+            mean_transactions_per_block: (blocks > 0).then(|| transactions as f64 / blocks as f64),
+            difficulty: tip.difficulty(),
+            total_work: self.total_work(),
+            tip_age: now.saturating_sub(tip.timestamp()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::test_params;
+    use super::*;
+    use crate::block::Block;
+
+    fn child(parent: &Block, timestamp: u64, txs: usize) -> Block {
+        let difficulty = test_params().initial_difficulty;
+        let mut block = parent
+            .next_builder()
+            .transactions((0..txs).map(|i| vec![i as u8]))
+            .difficulty(difficulty)
+            .timestamp(timestamp)
+            .build()
+            .unwrap();
+        block.mine(difficulty);
+        block
+    }
+
+    #[test]
+    fn test_stats_over_window() {
+        let mut chain = Blockchain::new_from_params(&test_params());
+        let start = chain.tip().timestamp();
+        // Intervals of 10, 20, 30, 40 with 1 to 4 transactions
+        let mut timestamp = start;
+        for i in 1..=4 {
+            timestamp += 10 * i as u64;
+            let block = child(chain.tip(), timestamp, i);
+            chain.append(block).unwrap();
+        }
+
+        let stats = chain.stats_at(3, timestamp + 7).unwrap();
+        assert_eq!(stats.blocks, 3);
+        assert_eq!(stats.average_block_interval, Some(35.0));
+        assert_eq!(stats.transactions, 9);
+        assert_eq!(stats.mean_transactions_per_block, Some(3.0));
+        assert_eq!(stats.difficulty, chain.tip().difficulty());
+        assert_eq!(stats.total_work, chain.total_work());
+        assert_eq!(stats.tip_age, 7);
+
+        // The window is capped at the chain, genesis included
+        let stats = chain.stats_at(100, timestamp).unwrap();
+        assert_eq!(stats.blocks, 5);
+        assert_eq!(stats.average_block_interval, Some(25.0));
+        assert_eq!(stats.transactions, 11);
+        assert_eq!(stats.mean_transactions_per_block, Some(2.2));
+        assert_eq!(stats.tip_age, 0);
+        assert_eq!(chain.stats_at(100, start).unwrap().tip_age, 0);
+    }
+
+    #[test]
+    fn test_stats_edge_cases() {
+        let chain = Blockchain::new_from_params(&test_params());
+        let genesis = chain.tip().timestamp();
+
+        let empty = chain.stats_at(0, genesis + 5).unwrap();
+        assert_eq!(empty.blocks, 0);
+        assert_eq!(empty.average_block_interval, None);
+        assert_eq!(empty.transactions, 0);
+        assert_eq!(empty.mean_transactions_per_block, None);
+        assert_eq!(empty.tip_age, 5);
+
+        let one = chain.stats_at(10, genesis).unwrap();
+        assert_eq!(one.blocks, 1);
+        assert_eq!(one.average_block_interval, None);
+        assert_eq!(one.transactions, 1);
+        assert_eq!(one.mean_transactions_per_block, Some(1.0));
+    }
+}
@@ -0,0 +1,353 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+
+use super::ChainError;
+use crate::block::{Block, BlockHash};
+
+/// A block held in the tree together with its position and accumulated work
+#[derive(Clone, Debug)]
+pub struct StoredBlock {
+    block: Block,
+    hash: BlockHash,
+    parent: Option<BlockHash>,
+    height: u64,
+    cumulative_work: u128,
+    sequence: u64,
+}
+
+impl StoredBlock {
+    pub fn block(&self) -> &Block {
+        &self.block
+    }
+
+    pub fn hash(&self) -> BlockHash {
+        self.hash
+    }
+
+    /// The parent's hash, or `None` for the tree's root
+    pub fn parent(&self) -> Option<BlockHash> {
+        self.parent
+    }
+
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// Total work from genesis up to and including this block, saturating at `u128::MAX`
+    pub fn cumulative_work(&self) -> u128 {
+        self.cumulative_work
+    }
+
+    /// Fork-choice order, greatest best: more cumulative work wins, then
+    /// greater height, then whichever block was seen first
+    fn fork_choice_key(&self) -> (u128, u64, Reverse<u64>) {
+        (self.cumulative_work, self.height, Reverse(self.sequence))
+    }
+}
+
+/// Every known block, indexed by hash and linked back to a single root block.
+///
+/// The root is the genesis block for a new chain, or the stored tip for a
+/// chain reopened from a [`crate::store::ChainStore`]; blocks below the root
+/// are not held here and cannot be built on.
+///
+/// Blocks may arrive for any known parent, so the tree holds competing
+/// branches side by side. Each block's work is derived from the difficulty
+/// committed in its header; callers are expected to have checked that the
+/// block actually meets it.
+///
+/// Blocks marked invalid stay in the tree for inspection, but neither they
+/// nor anything built on them can be inserted again or become the best tip.
+#[derive(Clone, Debug)]
+pub struct BlockTree {
+    blocks: HashMap<BlockHash, StoredBlock>,
+    tips: HashSet<BlockHash>,
+    /// Blocks marked invalid and their descendants, including any no longer in the tree
+    invalid: HashSet<BlockHash>,
+    root: BlockHash,
+    best: BlockHash,
+    next_sequence: u64,
+}
+
+impl BlockTree {
+    /// Start a tree rooted at `genesis`, which is trusted as-is
+    pub fn new(genesis: Block) -> Self {
+        let work = genesis.difficulty().work();
+        Self::with_root(genesis, 0, work)
+    }
+
+    /// Start a tree rooted at `block`, already known to sit at `height` with
+    /// `cumulative_work` behind it
+    pub fn with_root(block: Block, height: u64, cumulative_work: u128) -> Self {
+        let hash = block.hash();
+        let root = StoredBlock {
+            cumulative_work,
+            block,
+            hash,
+            parent: None,
+            height,
+            sequence: 0,
+        };
+
+        BlockTree {
+            blocks: HashMap::from([(hash, root)]),
+            tips: HashSet::from([hash]),
+            invalid: HashSet::new(),
+            root: hash,
+            best: hash,
+            next_sequence: 1,
+        }
+    }
+
+    pub fn root(&self) -> BlockHash {
+        self.root
+    }
+
+    pub fn contains(&self, hash: &BlockHash) -> bool {
+        self.blocks.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &BlockHash) -> Option<&StoredBlock> {
+        self.blocks.get(hash)
+    }
+
+    /// Attach `block` to its parent, which must already be in the tree.
+    ///
+    /// Returns the block's hash. The best tip moves only if the new block
+    /// beats it under the fork-choice order described on [`BlockTree::best_tip`].
+    pub fn insert(&mut self, block: Block) -> Result<BlockHash, ChainError> {
+        let stored = self.prepare(block)?;
+        Ok(self.store(stored))
+    }
+
+    /// Whether `hash` has been marked invalid or descends from a block that has
+    pub fn is_invalid(&self, hash: &BlockHash) -> bool {
+        self.invalid.contains(hash)
+    }
+
+    /// Work out where `block` would sit in the tree without storing it
+    pub(super) fn prepare(&self, block: Block) -> Result<StoredBlock, ChainError> {
+        let hash = block.hash();
+        let parent_hash = block.prev_block_hash();
+        for hash in [hash, parent_hash] {
+            if self.invalid.contains(&hash) {
+                return Err(ChainError::MarkedInvalid(hash));
+            }
+        }
+        if self.blocks.contains_key(&hash) {
+            return Err(ChainError::DuplicateBlock(hash));
+        }
+        let parent = self
+            .blocks
+            .get(&parent_hash)
+            .ok_or(ChainError::UnknownParent(parent_hash))?;
+
+        Ok(StoredBlock {
+            cumulative_work: parent
+                .cumulative_work
+                .saturating_add(block.difficulty().work()),
+            height: parent.height + 1,
+            block,
+            hash,
+            parent: Some(parent_hash),
+            sequence: self.next_sequence,
+        })
+    }
+
+    /// Whether a prepared block would replace the current best tip
+    pub(super) fn beats_best(&self, stored: &StoredBlock) -> bool {
+        stored.fork_choice_key() > self.blocks[&self.best].fork_choice_key()
+    }
+
+    /// Store a block returned by [`BlockTree::prepare`]
+    pub(super) fn store(&mut self, stored: StoredBlock) -> BlockHash {
+        let hash = stored.hash;
+        if self.beats_best(&stored) {
+            self.best = hash;
+        }
+        self.next_sequence += 1;
+        // Only the root has no parent, and it is never prepared
+        if let Some(parent) = &stored.parent {
+            self.tips.remove(parent);
+        }
+        self.tips.insert(hash);
+        self.blocks.insert(hash, stored);
+        hash
+    }
+
+    /// Blocks with no children, best first
+    pub fn tips(&self) -> Vec<BlockHash> {
+        let mut tips: Vec<&StoredBlock> = self.tips.iter().map(|hash| &self.blocks[hash]).collect();
+        tips.sort_by_key(|tip| Reverse(tip.fork_choice_key()));
+        tips.into_iter().map(StoredBlock::hash).collect()
+    }
+
+    /// The tip with the most cumulative work.
+    ///
+    /// Equal work is broken by the greater height, and if that is also equal
+    /// the tip that was inserted first is kept, so a competing branch has to
+    /// strictly outweigh the current one to replace it.
+    pub fn best_tip(&self) -> BlockHash {
+        self.best
+    }
+
+    /// Drop `first_removed` and every block built on it, and make `tip` the
+    /// best tip whatever its work
+    pub(super) fn rewind(&mut self, tip: BlockHash, first_removed: BlockHash) {
+        let mut by_height: Vec<(u64, BlockHash)> = self
+            .blocks
+            .values()
+            .map(|stored| (stored.height, stored.hash))
+            .collect();
+        by_height.sort_unstable();
+
+        // Parents sort before their children, so one pass finds every descendant
+        let mut removed = HashSet::from([first_removed]);
+        for (_, hash) in by_height {
+            if let Some(parent) = self.blocks[&hash].parent {
+                if removed.contains(&parent) {
+                    removed.insert(hash);
+                }
+            }
+        }
+        for hash in &removed {
+            self.blocks.remove(hash);
+            self.tips.remove(hash);
+        }
+        if !self
+            .blocks
+            .values()
+            .any(|stored| stored.parent == Some(tip))
+        {
+            self.tips.insert(tip);
+        }
+        self.best = tip;
+    }
+
+    /// Make `root` the root of the tree, dropping every block that does not
+    /// descend from it. The best tip must be one of its descendants.
+    pub(super) fn reroot(&mut self, root: BlockHash) {
+        let mut by_height: Vec<(u64, BlockHash)> = self
+            .blocks
+            .values()
+            .map(|stored| (stored.height, stored.hash))
+            .collect();
+        by_height.sort_unstable();
+
+        let mut kept = HashSet::from([root]);
+        for (_, hash) in by_height {
+            if let Some(parent) = self.blocks[&hash].parent {
+                if kept.contains(&parent) {
+                    kept.insert(hash);
+                }
+            }
+        }
+        self.blocks.retain(|hash, _| kept.contains(hash));
+        self.tips.retain(|hash| kept.contains(hash));
+        if let Some(stored) = self.blocks.get_mut(&root) {
+            stored.parent = None;
+        }
+        self.root = root;
+    }
+
+    /// Mark `hash` and every block built on it invalid. The best tip must
+    /// not be among them.
+    pub(super) fn mark_invalid(&mut self, hash: BlockHash) {
+        let mut by_height: Vec<(u64, BlockHash)> = self
+            .blocks
+            .values()
+            .map(|stored| (stored.height, stored.hash))
+            .collect();
+        by_height.sort_unstable();
+
+        self.invalid.insert(hash);
+        for (_, hash) in by_height {
+            if let Some(parent) = self.blocks[&hash].parent {
+                if self.invalid.contains(&parent) {
+                    self.invalid.insert(hash);
+                }
+            }
+        }
+        debug_assert!(!self.invalid.contains(&self.best));
+    }
+
+    /// Hashes from the root to `hash` inclusive, or `None` if `hash` is unknown
+    pub fn path_to(&self, hash: &BlockHash) -> Option<Vec<BlockHash>> {
+        let mut current = self.blocks.get(hash)?;
+        let mut path = Vec::with_capacity(current.height as usize + 1);
+        path.push(current.hash);
+        while let Some(parent) = current.parent {
+            current = &self.blocks[&parent];
+            path.push(current.hash);
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Store `block` without any checks, to simulate corruption
+    #[cfg(test)]
+    pub(super) fn insert_unchecked(&mut self, block: Block, parent: BlockHash, height: u64) {
+        let hash = block.hash();
+        let stored = StoredBlock {
+            cumulative_work: 0,
+            block,
+            hash,
+            parent: Some(parent),
+            height,
+            sequence: self.next_sequence,
+        };
+        self.next_sequence += 1;
+        self.blocks.insert(hash, stored);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockBuilder;
+
+    fn child(parent: &Block, tx: &[u8]) -> Block {
+        parent
+            .next_builder()
+            .transaction(tx.to_vec())
+            .timestamp(parent.timestamp() + 1)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_insert_tracks_tips() {
+        let genesis = BlockBuilder::new(BlockHash::ZERO)
+            .transaction(b"genesis".to_vec())
+            .timestamp(1_700_000_000)
+            .build()
+            .unwrap();
+        let mut tree = BlockTree::new(genesis.clone());
+        assert_eq!(tree.tips(), vec![genesis.hash()]);
+
+        let a = child(&genesis, b"a");
+        let b = child(&genesis, b"b");
+        let a2 = child(&a, b"a2");
+        tree.insert(a.clone()).unwrap();
+        tree.insert(b.clone()).unwrap();
+        tree.insert(a2.clone()).unwrap();
+
+        assert_eq!(tree.tips(), vec![a2.hash(), b.hash()]);
+        assert_eq!(tree.best_tip(), a2.hash());
+        assert_eq!(
+            tree.path_to(&a2.hash()),
+            Some(vec![genesis.hash(), a.hash(), a2.hash()])
+        );
+        assert_eq!(tree.get(&a2.hash()).unwrap().height(), 2);
+
+        assert_eq!(
+            tree.insert(a),
+            Err(ChainError::DuplicateBlock(a2.prev_block_hash()))
+        );
+        let orphan = child(&child(&b, b"missing"), b"orphan");
+        assert_eq!(
+            tree.insert(orphan.clone()),
+            Err(ChainError::UnknownParent(orphan.prev_block_hash()))
+        );
+    }
+}
@@ -0,0 +1,406 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Blockchain, ChainError};
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::codec::{self, DecodeError, DecodeLimits, Reader};
+use crate::hash::Hash32;
+use crate::merkle_trie::{MerkleProof, MerkleTree};
+use crate::store::ChainStore;
+
+/// Where a transaction sits on the active chain
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TxLocation {
+    /// Hash of the block holding the transaction
+    pub block: BlockHash,
+    /// Height of that block
+    pub height: u64,
+    /// Position of the transaction within the block
+    pub index: usize,
+}
+
+/// A transaction together with everything needed to check it was included
+/// in a block: the block's header and a merkle proof under its root
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TxWithProof {
+    /// The transaction's bytes as the block stores them
+    pub tx: Vec<u8>,
+    pub block_header: BlockHeader,
+    /// Height of the block on the chain that produced the bundle; not
+    /// covered by [`TxWithProof::verify`]
+    pub height: u64,
+    pub proof: MerkleProof,
+}
+
+impl TxWithProof {
+    /// Check the bundle against the hash of a header the caller trusts: the
+    /// header must hash to `trusted_header_hash` and meet the difficulty its
+    /// bits commit to, and the proof must place `tx` under its merkle root
+    pub fn verify(&self, trusted_header_hash: &[u8]) -> bool {
+        let hash = self.block_header.hash();
+        hash.as_bytes().as_slice() == trusted_header_hash
+            && self.block_header.difficulty().is_met_by(hash.as_bytes())
+            && self.proof.root_hash() == self.block_header.merkle_root()
+            && self.proof.verify(&self.tx)
+    }
+
+    /// Serialize as the height, the header, then the transaction and the
+    /// proof each behind a varint length
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = self.block_header.to_bytes();
+        let proof = self.proof.to_bytes();
+        let mut buffer = Vec::with_capacity(8 + header.len() + 20 + self.tx.len() + proof.len());
+        buffer.extend_from_slice(&self.height.to_le_bytes());
+        buffer.extend_from_slice(&header);
+        codec::write_bytes(&mut buffer, &self.tx);
+        codec::write_bytes(&mut buffer, &proof);
+        buffer
+    }
+
+    /// Decode a bundle produced by `to_bytes`, enforcing `limits` on
+    /// untrusted input
+    pub fn from_bytes(bytes: &[u8], limits: &DecodeLimits) -> Result<TxWithProof, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let mut reader = Reader::new(bytes);
+        let height = reader.read_u64()?;
+        let block_header = BlockHeader::decode(&mut reader)?;
+        let tx = reader
+            .read_var_bytes("transaction size", limits.max_transaction_bytes)?
+            .to_vec();
+        let proof = reader.read_var_bytes("proof size", limits.max_decode_bytes)?;
+        let proof = MerkleProof::from_bytes(proof, limits)?;
+        reader.finish()?;
+        Ok(TxWithProof {
+            tx,
+            block_header,
+            height,
+            proof,
+        })
+    }
+}
+
+/// Serialized as the hex of [`TxWithProof::to_bytes`]
+impl Serialize for TxWithProof {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(self.to_bytes()))
+    }
+}
+
+impl<'de> Deserialize<'de> for TxWithProof {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct HexVisitor;
+
+        impl de::Visitor<'_> for HexVisitor {
+            type Value = TxWithProof;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "a hex-encoded transaction bundle")
+            }
+
+            fn visit_str<E: de::Error>(self, text: &str) -> Result<TxWithProof, E> {
+                let bytes = hex::decode(text).map_err(E::custom)?;
+                TxWithProof::from_bytes(&bytes, &DecodeLimits::default()).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_str(HexVisitor)
+    }
+}
+
+/// The transactions of the active chain by txid, the hash of their bytes
+#[derive(Clone, Debug, Default)]
+pub(super) struct TxIndex {
+    locations: HashMap<Hash32, Vec<TxLocation>>,
+    /// Txids of each indexed block, so a block can be dropped by hash alone
+    txids: HashMap<BlockHash, Vec<Hash32>>,
+}
+
+impl TxIndex {
+    /// Index the transactions of `block`, now at `height` on the active chain
+    pub(super) fn connect(&mut self, block: &Block, height: u64) {
+        let hash = block.hash();
+        let txids: Vec<Hash32> = block
+            .transactions()
+            .iter()
+            .map(|tx| MerkleTree::hash(tx))
+            .collect();
+        for (index, txid) in txids.iter().enumerate() {
+            self.locations.entry(*txid).or_default().push(TxLocation {
+                block: hash,
+                height,
+                index,
+            });
+        }
+        self.txids.insert(hash, txids);
+    }
+
+    /// Drop the transactions of the block with `hash`, if it was indexed
+    pub(super) fn disconnect(&mut self, hash: &BlockHash) {
+        for txid in self.txids.remove(hash).unwrap_or_default() {
+            if let Some(locations) = self.locations.get_mut(&txid) {
+                locations.retain(|location| location.block != *hash);
+                if locations.is_empty() {
+                    self.locations.remove(&txid);
+                }
+            }
+        }
+    }
+
+    /// The lowest location of `txid`, the first in the block for a
+    /// transaction repeated within one
+    pub(super) fn find(&self, txid: &[u8]) -> Option<TxLocation> {
+        self.locations
+            .get(&Hash32::try_from(txid).ok()?)?
+            .iter()
+            .min_by_key(|location| (location.height, location.index))
+            .copied()
+    }
+}
+
+impl<S: ChainStore> Blockchain<S> {
+    /// Index the transactions of the active chain by txid and keep the index
+    /// up to date as blocks connect, disconnect and are pruned.
+    ///
+    /// Blocks already pruned are not indexed. Fails if the store cannot
+    /// read a block to index.
+    pub fn with_tx_index(mut self) -> Result<Self, ChainError> {
+        let mut index = TxIndex::default();
+        for (block, height) in self.try_iter().zip(self.pruned_height()..) {
+            index.connect(block?, height);
+        }
+        self.tx_index = Some(index);
+        Ok(self)
+    }
+
+    /// Where the transaction whose bytes hash to `txid` sits on the active
+    /// chain, or `None` if it is not there or the chain keeps no index; see
+    /// [`Blockchain::with_tx_index`].
+    ///
+    /// A transaction included more than once is reported at its earliest
+    /// occurrence.
+    pub fn find_transaction(&self, txid: &[u8]) -> Option<TxLocation> {
+        self.tx_index.as_ref()?.find(txid)
+    }
+
+    /// The header of the block holding the transaction `txid` and a merkle
+    /// proof of its inclusion under that header's root, at the location
+    /// [`Blockchain::find_transaction`] reports
+    pub fn prove_transaction(&self, txid: &[u8]) -> Option<(BlockHeader, MerkleProof)> {
+        let location = self.find_transaction(txid)?;
+        let block = self.get(location.height)?;
+        Some((
+            block.header().clone(),
+            block.merkle_tree().generate_proof(location.index).ok()?,
+        ))
+    }
+
+    /// The transaction `txid` bundled with its block's header, height and
+    /// a merkle proof of its inclusion, at the location
+    /// [`Blockchain::find_transaction`] reports
+    pub fn get_transaction_with_proof(&self, txid: &[u8]) -> Option<TxWithProof> {
+        let location = self.find_transaction(txid)?;
+        let block = self.get(location.height)?;
+        Some(TxWithProof {
+            tx: block.transactions()[location.index].clone(),
+            block_header: block.header().clone(),
+            height: location.height,
+            proof: block.merkle_tree().generate_proof(location.index).ok()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{mined_chain, test_params};
+    use super::*;
+    use crate::difficulty::Difficulty;
+    use crate::testing;
+    use proptest::prelude::*;
+
+    fn child(parent: &Block, txs: &[&[u8]]) -> Block {
+        let difficulty = test_params().initial_difficulty;
+        let mut block = parent
+            .next_builder()
+            .transactions(txs.iter().map(|tx| tx.to_vec()))
+            .difficulty(difficulty)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(difficulty);
+        block
+    }
+
+    fn txid(tx: &[u8]) -> Vec<u8> {
+        MerkleTree::hash(tx).to_vec()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_generated_transactions_are_proven(blocks in testing::chain(1..6)) {
+            let chain = testing::blockchain(blocks).with_tx_index().unwrap();
+            let limits = DecodeLimits::default();
+            for (height, block) in chain.iter().enumerate() {
+                for tx in block.transactions() {
+                    // A transaction repeated on the chain is found at its first block
+                    let location = chain.find_transaction(&txid(tx)).unwrap();
+                    prop_assert!(location.height <= height as u64);
+                    let bundle = chain.get_transaction_with_proof(&txid(tx)).unwrap();
+                    prop_assert_eq!(&bundle.tx, tx);
+                    prop_assert!(bundle.verify(bundle.block_header.hash().as_bytes()));
+                    prop_assert_eq!(TxWithProof::from_bytes(&bundle.to_bytes(), &limits), Ok(bundle));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_finds_and_proves_transactions() {
+        let mut chain = mined_chain(3).with_tx_index().unwrap();
+        let first = child(chain.tip(), &[b"a", b"dup", b"b"]);
+        let second = child(&first, &[b"dup", b"c"]);
+        chain.append(first.clone()).unwrap();
+        chain.append(second.clone()).unwrap();
+
+        // Blocks present before the index was built are indexed too
+        let early = chain.get(1).unwrap().transactions()[0].clone();
+        assert_eq!(chain.find_transaction(&txid(&early)).unwrap().height, 1);
+
+        assert_eq!(
+            chain.find_transaction(&txid(b"dup")),
+            Some(TxLocation {
+                block: first.hash(),
+                height: 3,
+                index: 1,
+            })
+        );
+        let (header, proof) = chain.prove_transaction(&txid(b"c")).unwrap();
+        assert_eq!(&header, second.header());
+        assert_eq!(proof.root_hash(), header.merkle_root());
+        assert!(proof.verify(b"c"));
+        assert_eq!(chain.find_transaction(&txid(b"missing")), None);
+
+        chain.rollback_to(3).unwrap();
+        assert_eq!(chain.find_transaction(&txid(b"c")), None);
+        assert_eq!(chain.find_transaction(&txid(b"dup")).unwrap().height, 3);
+
+        // Without an index nothing is found
+        let plain = mined_chain(3);
+        assert_eq!(plain.find_transaction(&txid(&early)), None);
+    }
+
+    #[test]
+    fn test_transaction_with_proof() {
+        use serde::de::value::{Error, StrDeserializer};
+
+        let mut chain = mined_chain(2).with_tx_index().unwrap();
+        let first = child(chain.tip(), &[b"a", b"b", b"c"]);
+        let second = child(&first, &[b"d", b"e"]);
+        chain.append(first.clone()).unwrap();
+        chain.append(second.clone()).unwrap();
+
+        let b = chain.get_transaction_with_proof(&txid(b"b")).unwrap();
+        let e = chain.get_transaction_with_proof(&txid(b"e")).unwrap();
+        assert_eq!((b.tx.as_slice(), b.height), (&b"b"[..], 2));
+        assert_eq!(&e.block_header, second.header());
+        assert!(b.verify(first.hash().as_bytes()));
+        assert!(e.verify(second.hash().as_bytes()));
+        assert!(!b.verify(second.hash().as_bytes()));
+        assert_eq!(chain.get_transaction_with_proof(&txid(b"z")), None);
+
+        // Swapping any component between the two bundles breaks them
+        let swapped = [
+            TxWithProof {
+                tx: e.tx.clone(),
+                ..b.clone()
+            },
+            TxWithProof {
+                block_header: e.block_header.clone(),
+                ..b.clone()
+            },
+            TxWithProof {
+                proof: e.proof.clone(),
+                ..b.clone()
+            },
+        ];
+        for bundle in &swapped {
+            assert!(!bundle.verify(first.hash().as_bytes()));
+            assert!(!bundle.verify(second.hash().as_bytes()));
+        }
+        // A sibling from the same block does not prove the transaction
+        let c = chain.get_transaction_with_proof(&txid(b"c")).unwrap();
+        assert!(!TxWithProof {
+            proof: c.proof,
+            ..b.clone()
+        }
+        .verify(first.hash().as_bytes()));
+
+        // A header that does not meet its own difficulty fails even when trusted
+        let unmined = second
+            .next_builder()
+            .transaction(b"f".to_vec())
+            .difficulty(Difficulty::LeadingZeroBits(64))
+            .build()
+            .unwrap();
+        assert!(!unmined.verify_pow(unmined.difficulty()));
+        let bundle = TxWithProof {
+            tx: b"f".to_vec(),
+            block_header: unmined.header().clone(),
+            height: 4,
+            proof: unmined.merkle_tree().generate_proof(0).unwrap(),
+        };
+        assert!(!bundle.verify(unmined.hash().as_bytes()));
+
+        let limits = DecodeLimits::default();
+        assert_eq!(
+            TxWithProof::from_bytes(&b.to_bytes(), &limits),
+            Ok(b.clone())
+        );
+        let mut truncated = e.to_bytes();
+        truncated.pop();
+        assert!(TxWithProof::from_bytes(&truncated, &limits).is_err());
+        let text = hex::encode(e.to_bytes());
+        let decoded = TxWithProof::deserialize(StrDeserializer::<Error>::new(&text));
+        assert_eq!(decoded, Ok(e));
+        let bad = TxWithProof::deserialize(StrDeserializer::<Error>::new("zz"));
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn test_reorg_moves_transaction() {
+        let mut chain = mined_chain(3).with_tx_index().unwrap();
+        let fork_point = chain.tip().clone();
+        let a = child(&fork_point, &[b"a", b"moved"]);
+        chain.append(a.clone()).unwrap();
+        assert_eq!(
+            chain.find_transaction(&txid(b"moved")).unwrap().block,
+            a.hash()
+        );
+
+        // A heavier branch carrying the transaction one block later
+        let b1 = child(&fork_point, &[b"b1"]);
+        let b2 = child(&b1, &[b"b2", b"other", b"moved"]);
+        assert_eq!(chain.insert(b1.clone()), Ok(None));
+        assert!(chain.insert(b2.clone()).unwrap().is_some());
+        assert_eq!(
+            chain.find_transaction(&txid(b"moved")),
+            Some(TxLocation {
+                block: b2.hash(),
+                height: 4,
+                index: 2,
+            })
+        );
+        assert_eq!(chain.find_transaction(&txid(b"a")), None);
+        let (header, proof) = chain.prove_transaction(&txid(b"moved")).unwrap();
+        assert_eq!(&header, b2.header());
+        assert!(proof.verify(b"moved"));
+
+        // Pruning drops the transactions of pruned blocks
+        chain.prune(0).unwrap();
+        assert_eq!(chain.find_transaction(&txid(b"b1")), None);
+        assert_eq!(chain.find_transaction(&txid(b"moved")).unwrap().height, 4);
+    }
+}
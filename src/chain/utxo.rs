@@ -0,0 +1,584 @@
+use std::collections::HashMap;
+
+use super::{Blockchain, ChainError, Reorg};
+use crate::block::{Block, BlockHash};
+use crate::store::ChainStore;
+use crate::transaction::{OutPoint, TxOutput};
+use crate::utxo::{UndoData, UtxoError, UtxoSet};
+
+/// The unspent outputs at the active tip and how to take each active block
+/// back out of them
+#[derive(Clone, Debug)]
+pub(super) struct ChainUtxos {
+    set: UtxoSet,
+    /// Undo data of the active blocks above the pruned height
+    undo: HashMap<BlockHash, UndoData>,
+}
+
+impl ChainUtxos {
+    fn apply(&mut self, block: &Block) -> Result<(), UtxoError> {
+        let undo = self.set.apply_block(block)?;
+        self.undo.insert(block.hash(), undo);
+        Ok(())
+    }
+
+    /// Apply `block`, whose inputs must meet the conditions of the outputs
+    /// they spend, whose coinbase may pay out `subsidy` plus its fees and
+    /// whose header, if it carries a state root, must commit to the set after it
+    fn connect(&mut self, block: &Block, subsidy: u64) -> Result<(), ChainError> {
+        let hash = block.hash();
+        let undo = self
+            .set
+            .apply_block(block)
+            .map_err(|err| ChainError::InvalidSpend { block: hash, err })?;
+        // Every input the block has is among the outputs it spent
+        let spent: HashMap<OutPoint, TxOutput> = undo
+            .spent()
+            .map(|(out, output)| (*out, output.clone()))
+            .collect();
+        if let Err(err) = block.verify_spends(&spent) {
+            self.set.undo_block(undo);
+            return Err(ChainError::InvalidSignature { block: hash, err });
+        }
+        if let Err(err) = block.total_fees(&spent, subsidy) {
+            self.set.undo_block(undo);
+            return Err(ChainError::InvalidReward { block: hash, err });
+        }
+        if let Some(&got) = block.header().state_root() {
+            let expected = self.set.state_root();
+            if got != expected {
+                self.set.undo_block(undo);
+                return Err(ChainError::StateRootMismatch {
+                    block: hash,
+                    expected,
+                    got,
+                });
+            }
+        }
+        self.undo.insert(hash, undo);
+        Ok(())
+    }
+
+    /// Take the active block `hash` back out of the set
+    #[allow(clippy::expect_used)]
+    pub(super) fn disconnect(&mut self, hash: &BlockHash) {
+        let undo = self
+            .undo
+            .remove(hash)
+            .expect("every active block above the pruned height has undo data");
+        self.set.undo_block(undo);
+    }
+
+    /// Drop the undo data of a block that can no longer be disconnected
+    pub(super) fn forget(&mut self, hash: &BlockHash) {
+        self.undo.remove(hash);
+    }
+
+    /// Resume tracking at `set`, with no undo data for the blocks below it
+    pub(super) fn resume(set: UtxoSet) -> Self {
+        ChainUtxos {
+            set,
+            undo: HashMap::new(),
+        }
+    }
+
+    /// The set after the block at `height` of the `active` chain, undoing
+    /// the blocks above it on a copy, or `None` if one of them has no undo data
+    pub(super) fn set_at(&self, active: &[BlockHash], height: u64) -> Option<UtxoSet> {
+        let mut set = self.set.clone();
+        for hash in active.get(height as usize + 1..)?.iter().rev() {
+            set.undo_block(self.undo.get(hash)?.clone());
+        }
+        Some(set)
+    }
+}
+
+impl<S: ChainStore> Blockchain<S> {
+    /// Track the unspent outputs of the active chain, replaying it from
+    /// genesis, and keep them at the tip as blocks connect and disconnect.
+    ///
+    /// From then on every block must decode as
+    /// [`crate::transaction::Transaction`]s spending only unspent outputs,
+    /// each input meeting the condition of the output it spends as
+    /// [`Block::verify_spends`] checks, with coinbase outputs worth no more than
+    /// [`ChainParams::subsidy_at`](crate::params::ChainParams::subsidy_at) its
+    /// height plus its fees, before it can join the active chain. A block
+    /// stored on a side branch is checked once its branch would take over; if
+    /// any block of the branch fails, the switch is refused with
+    /// [`ChainError::InvalidSignature`], [`ChainError::InvalidSpend`] or
+    /// [`ChainError::InvalidReward`], that
+    /// block is marked invalid and the set is left at the old tip. The genesis
+    /// block's outputs are taken as given.
+    ///
+    /// A chain restored by [`Blockchain::from_snapshot`] from a snapshot
+    /// carrying its unspent outputs already tracks them and is returned as
+    /// is. Otherwise fails with [`ChainError::BelowPrunedHeight`] on a pruned
+    /// chain, whose old transactions are gone, and with either error above if
+    /// the active chain itself does not apply.
+    pub fn with_utxo_set(mut self) -> Result<Self, ChainError> {
+        if self.utxos.is_some() {
+            return Ok(self);
+        }
+        if self.pruned_height() > 0 {
+            return Err(ChainError::BelowPrunedHeight {
+                height: 0,
+                pruned_height: self.pruned_height(),
+            });
+        }
+        let mut utxos = ChainUtxos {
+            set: UtxoSet::new().with_coinbase_maturity(self.params.coinbase_maturity),
+            undo: HashMap::new(),
+        };
+        for (height, block) in self.try_iter().enumerate() {
+            let block = block?;
+            if height == 0 {
+                utxos.apply(block).map_err(|err| ChainError::InvalidSpend {
+                    block: block.hash(),
+                    err,
+                })?;
+            } else {
+                utxos.connect(block, self.params.subsidy_at(height as u64))?;
+            }
+        }
+        self.utxos = Some(utxos);
+        Ok(self)
+    }
+
+    /// The unspent outputs at the active tip, if tracked; see
+    /// [`Blockchain::with_utxo_set`]
+    pub fn utxo_set(&self) -> Option<&UtxoSet> {
+        self.utxos.as_ref().map(|utxos| &utxos.set)
+    }
+
+    /// Move the tracked outputs, if any, across `reorg`, whose last connected
+    /// block is `tip`, not yet in the tree. On failure the outputs are left
+    /// at the old tip.
+    #[allow(clippy::expect_used)]
+    pub(super) fn switch_utxos(&mut self, reorg: &Reorg, tip: &Block) -> Result<(), ChainError> {
+        let Some(mut utxos) = self.utxos.take() else {
+            return Ok(());
+        };
+        for hash in &reorg.disconnected {
+            utxos.disconnect(hash);
+        }
+        let tip_hash = tip.hash();
+        let fork_height = (self.active.len() - reorg.disconnected.len()) as u64;
+        let mut applied = Vec::new();
+        let mut failed = None;
+        for (height, hash) in (fork_height..).zip(&reorg.connected) {
+            let block = if *hash == tip_hash {
+                tip
+            } else {
+                self.block(hash)
+            };
+            match utxos.connect(block, self.params.subsidy_at(height)) {
+                Ok(()) => applied.push(*hash),
+                Err(err) => {
+                    failed = Some((*hash, err));
+                    break;
+                }
+            }
+        }
+
+        let Some((block, err)) = failed else {
+            self.utxos = Some(utxos);
+            return Ok(());
+        };
+        self.tree.mark_invalid(block);
+        for hash in applied.iter().rev() {
+            utxos.disconnect(hash);
+        }
+        for hash in reorg.disconnected.iter().rev() {
+            utxos
+                .apply(self.block(hash))
+                .expect("blocks that were active apply again");
+        }
+        self.utxos = Some(utxos);
+        Err(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::test_params;
+    use super::super::{ChainValidationError, ChainValidationErrorKind, ImportError, Snapshot};
+    use super::*;
+    use crate::codec::DecodeLimits;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::params::ChainParams;
+    use crate::testing::{signed_spend, unsigned_spend};
+    use crate::transaction::{FeeError, SigError, Transaction};
+
+    fn out(tx: &Transaction, index: u32) -> OutPoint {
+        OutPoint {
+            txid: tx.txid(),
+            index,
+        }
+    }
+
+    fn child(parent: &Block, txs: &[&Transaction]) -> Block {
+        let difficulty = test_params().initial_difficulty;
+        let mut block = parent
+            .next_builder()
+            .transactions(txs.iter().map(|&tx| tx.clone()))
+            .difficulty(difficulty)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(difficulty);
+        block
+    }
+
+    /// The set a fresh replay of the active chain gives
+    fn replayed<S: ChainStore>(chain: &Blockchain<S>) -> UtxoSet {
+        let mut set = UtxoSet::new().with_coinbase_maturity(chain.params().coinbase_maturity);
+        for block in chain.try_iter() {
+            set.apply_block(block.unwrap()).unwrap();
+        }
+        set
+    }
+
+    fn utxo_params(genesis: &Transaction) -> ChainParams {
+        ChainParams {
+            genesis_transactions: vec![genesis.encode()],
+            ..test_params()
+        }
+    }
+
+    #[test]
+    fn test_reorg_matches_replay_from_genesis() {
+        let genesis_tx = signed_spend(&[], &[100]);
+        let params = utxo_params(&genesis_tx);
+        let mut chain = Blockchain::new_from_params(&params)
+            .with_utxo_set()
+            .unwrap();
+        let fork_point = chain.tip().clone();
+
+        // The active branch splits the genesis output and spends one half
+        let split = signed_spend(&[out(&genesis_tx, 0)], &[60, 40]);
+        let a1 = child(&fork_point, &[&signed_spend(&[], &[1]), &split]);
+        let spend = signed_spend(&[out(&split, 1)], &[40]);
+        let a2 = child(&a1, &[&signed_spend(&[], &[2]), &spend]);
+        chain.append(a1).unwrap();
+        chain.append(a2).unwrap();
+        assert!(chain.utxo_set().unwrap().contains(&out(&spend, 0)));
+        assert_eq!(chain.utxo_set(), Some(&replayed(&chain)));
+
+        // A heavier branch spends the genesis output differently
+        let other = signed_spend(&[out(&genesis_tx, 0)], &[100]);
+        let b1 = child(&fork_point, &[&signed_spend(&[], &[3]), &other]);
+        let b2 = child(&b1, &[&signed_spend(&[], &[4])]);
+        let b3 = child(&b2, &[&signed_spend(&[], &[5])]);
+        chain.insert(b1).unwrap();
+        chain.insert(b2).unwrap();
+        assert!(chain.insert(b3).unwrap().is_some());
+
+        let set = chain.utxo_set().unwrap();
+        assert!(set.contains(&out(&other, 0)));
+        assert!(!set.contains(&out(&split, 0)));
+        assert_eq!(set.to_bytes(), replayed(&chain).to_bytes());
+
+        // Rolling back restores the outputs the disconnected blocks spent
+        chain.rollback_to(0).unwrap();
+        assert_eq!(
+            chain.utxo_set().unwrap().to_bytes(),
+            replayed(&chain).to_bytes()
+        );
+        assert!(chain.utxo_set().unwrap().contains(&out(&genesis_tx, 0)));
+    }
+
+    #[test]
+    fn test_refuses_blocks_with_invalid_spends() {
+        let genesis_tx = signed_spend(&[], &[100]);
+        let mut chain = Blockchain::new_from_params(&utxo_params(&genesis_tx))
+            .with_utxo_set()
+            .unwrap();
+        let fork_point = chain.tip().clone();
+        let spend = signed_spend(&[out(&genesis_tx, 0)], &[100]);
+        let a1 = child(&fork_point, &[&spend]);
+        chain.append(a1.clone()).unwrap();
+        let before = chain.utxo_set().unwrap().clone();
+
+        // Spending the same output again on top of the tip
+        let again = child(&a1, &[&signed_spend(&[out(&genesis_tx, 0)], &[100])]);
+        assert_eq!(
+            chain.append(again.clone()),
+            Err(ChainError::InvalidSpend {
+                block: again.hash(),
+                err: UtxoError::MissingInput(out(&genesis_tx, 0)),
+            })
+        );
+        assert_eq!(chain.height(), 1);
+        assert_eq!(chain.utxo_set(), Some(&before));
+
+        // A side branch whose first block double spends is refused when it
+        // would take over, and its bad block marked invalid
+        let double = signed_spend(&[out(&genesis_tx, 0)], &[1]);
+        let b1 = child(&fork_point, &[&spend, &double]);
+        let b2 = child(&b1, &[&signed_spend(&[], &[9])]);
+        assert_eq!(chain.insert(b1.clone()), Ok(None));
+        assert_eq!(
+            chain.insert(b2),
+            Err(ChainError::InvalidSpend {
+                block: b1.hash(),
+                err: UtxoError::DoubleSpend(out(&genesis_tx, 0)),
+            })
+        );
+        assert_eq!(chain.tip().hash(), a1.hash());
+        assert_eq!(chain.utxo_set(), Some(&before));
+        assert_eq!(
+            chain.status_of(b1.hash().as_ref()),
+            Some(super::super::BlockStatus::Invalid)
+        );
+
+        // Chains of raw transactions cannot track outputs
+        let raw = Blockchain::new_from_params(&test_params());
+        assert!(matches!(
+            raw.with_utxo_set(),
+            Err(ChainError::InvalidSpend {
+                err: UtxoError::Decode { index: 0, .. },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_refuses_unsigned_and_wrongly_signed_spends() {
+        let genesis_tx = signed_spend(&[], &[100]);
+        let mut chain = Blockchain::new_from_params(&utxo_params(&genesis_tx))
+            .with_utxo_set()
+            .unwrap();
+        let before = chain.utxo_set().unwrap().clone();
+        let spent = [out(&genesis_tx, 0)];
+
+        let mut stolen = unsigned_spend(&spent, &[100]);
+        let thief = SigningKey::from_bytes(&[8; 32]);
+        stolen.sign_input(0, &thief);
+        let mut forged = signed_spend(&spent, &[100]);
+        forged.outputs[0].amount = 99;
+        let cases = [
+            (
+                unsigned_spend(&spent, &[100]),
+                SigError::UnknownKey { index: 1, input: 0 },
+            ),
+            (stolen, SigError::KeyMismatch { index: 1, input: 0 }),
+            (forged, SigError::InvalidSignature { index: 1, input: 0 }),
+        ];
+        for (tx, err) in cases {
+            let block = child(chain.tip(), &[&signed_spend(&[], &[1]), &tx]);
+            assert_eq!(
+                chain.append(block.clone()),
+                Err(ChainError::InvalidSignature {
+                    block: block.hash(),
+                    err
+                })
+            );
+            assert_eq!(chain.utxo_set(), Some(&before));
+        }
+
+        chain
+            .append(child(
+                chain.tip(),
+                &[&signed_spend(&[], &[1]), &signed_spend(&spent, &[100])],
+            ))
+            .unwrap();
+        assert!(!chain.utxo_set().unwrap().contains(&spent[0]));
+    }
+
+    #[test]
+    fn test_coinbase_is_capped_by_subsidy_and_fees() {
+        let genesis_tx = signed_spend(&[], &[100]);
+        let params = ChainParams {
+            initial_subsidy: 50,
+            halving_interval: 2,
+            ..utxo_params(&genesis_tx)
+        };
+        let mut chain = Blockchain::new_from_params(&params)
+            .with_utxo_set()
+            .unwrap();
+
+        // Height 1 may claim the full subsidy plus the fee of 10
+        let spend = signed_spend(&[out(&genesis_tx, 0)], &[90]);
+        let overpaid = child(chain.tip(), &[&signed_spend(&[], &[61]), &spend]);
+        assert!(matches!(
+            chain.append(overpaid.clone()),
+            Err(ChainError::InvalidReward {
+                err: FeeError::CoinbaseOverpays {
+                    paid: 61,
+                    allowed: 60
+                },
+                ..
+            })
+        ));
+        assert_eq!(
+            chain.status_of(overpaid.hash().as_ref()),
+            Some(super::super::BlockStatus::Invalid)
+        );
+        let before = chain.utxo_set().unwrap().clone();
+        assert!(before.contains(&out(&genesis_tx, 0)));
+        let b1 = child(chain.tip(), &[&signed_spend(&[], &[60]), &spend]);
+        chain.append(b1).unwrap();
+
+        // Height 2 is past the first halving
+        let b2 = child(chain.tip(), &[&signed_spend(&[], &[25])]);
+        assert!(chain
+            .append(child(chain.tip(), &[&signed_spend(&[], &[26])]))
+            .is_err());
+        chain.append(b2).unwrap();
+        assert_eq!(chain.utxo_set(), Some(&replayed(&chain)));
+
+        // Replaying a chain checks its rewards too
+        let mut lax = Blockchain::new_from_params(&utxo_params(&genesis_tx));
+        lax.append(child(lax.tip(), &[&signed_spend(&[], &[1 << 40])]))
+            .unwrap();
+        assert!(matches!(
+            lax.with_utxo_set(),
+            Err(ChainError::InvalidReward { .. })
+        ));
+    }
+
+    #[test]
+    fn test_state_root_is_checked_on_connect() {
+        let genesis_tx = signed_spend(&[], &[100]);
+        let params = ChainParams {
+            allowed_versions: 1..=2,
+            ..utxo_params(&genesis_tx)
+        };
+        let mut chain = Blockchain::new_from_params(&params)
+            .with_utxo_set()
+            .unwrap();
+        let difficulty = params.initial_difficulty;
+        let builder = |parent: &Block, txs: &[&Transaction]| {
+            parent
+                .next_builder()
+                .transactions(txs.iter().map(|&tx| tx.clone()))
+                .difficulty(difficulty)
+                .timestamp(parent.timestamp() + 10)
+        };
+
+        // A miner commits to the set after the block
+        let spend = signed_spend(&[out(&genesis_tx, 0)], &[90]);
+        let mut b1 = builder(chain.tip(), &[&signed_spend(&[], &[5]), &spend])
+            .build_with_state(chain.utxo_set().unwrap())
+            .unwrap();
+        b1.mine(difficulty);
+
+        let mut wrong = builder(chain.tip(), &[&signed_spend(&[], &[5]), &spend])
+            .state_root([1; 32])
+            .build()
+            .unwrap();
+        wrong.mine(difficulty);
+        let before = chain.utxo_set().unwrap().clone();
+        assert_eq!(
+            chain.append(wrong.clone()),
+            Err(ChainError::StateRootMismatch {
+                block: wrong.hash(),
+                expected: *b1.header().state_root().unwrap(),
+                got: [1; 32],
+            })
+        );
+        assert_eq!(chain.utxo_set(), Some(&before));
+
+        chain.append(b1).unwrap();
+        assert_eq!(
+            chain.tip().header().state_root(),
+            Some(&chain.utxo_set().unwrap().state_root())
+        );
+
+        // Version 1 headers carry no root and still connect
+        let b2 = child(chain.tip(), &[&signed_spend(&[], &[6])]);
+        assert_eq!(b2.header().state_root(), None);
+        chain.append(b2).unwrap();
+        assert_eq!(chain.utxo_set(), Some(&replayed(&chain)));
+    }
+
+    #[test]
+    fn test_snapshot_carries_the_utxo_set() {
+        let genesis_tx = signed_spend(&[], &[100]);
+        let params = ChainParams {
+            allowed_versions: 1..=2,
+            ..utxo_params(&genesis_tx)
+        };
+        let mut source = Blockchain::new_from_params(&params)
+            .with_utxo_set()
+            .unwrap();
+        let difficulty = params.initial_difficulty;
+        let split = signed_spend(&[out(&genesis_tx, 0)], &[60, 40]);
+        let spend = signed_spend(&[out(&split, 1)], &[40]);
+        for height in 1..20 {
+            let mut txs = vec![signed_spend(&[], &[height])];
+            match height {
+                5 => txs.push(split.clone()),
+                17 => txs.push(spend.clone()),
+                _ => {}
+            }
+            let mut block = source
+                .tip()
+                .next_builder()
+                .transactions(txs)
+                .difficulty(difficulty)
+                .timestamp(source.tip().timestamp() + 10)
+                .build_with_state(source.utxo_set().unwrap())
+                .unwrap();
+            block.mine(difficulty);
+            source.append(block).unwrap();
+        }
+        // A version 1 tip commits to no state root
+        source
+            .append(child(source.tip(), &[&signed_spend(&[], &[20])]))
+            .unwrap();
+
+        let snapshot = source.snapshot_at(15).unwrap();
+        let set = snapshot.utxo_set().unwrap();
+        assert!(set.contains(&out(&split, 1)));
+        assert_eq!(
+            snapshot.tip().header().state_root(),
+            Some(&set.state_root())
+        );
+        let bytes = snapshot.to_bytes();
+        let limits = DecodeLimits::default();
+        assert_eq!(Snapshot::from_bytes(&bytes, &limits).unwrap(), snapshot);
+
+        // The restored chain tracks the set and catches up with the source
+        let mut restored = Blockchain::from_snapshot(snapshot.clone(), &params)
+            .unwrap()
+            .with_utxo_set()
+            .unwrap();
+        assert_eq!(
+            restored.utxo_set(),
+            Some(
+                &set.clone()
+                    .with_coinbase_maturity(params.coinbase_maturity)
+                    .resume_at(16)
+            )
+        );
+        for block in source.range(16..).unwrap() {
+            restored.append(block.clone()).unwrap();
+        }
+        assert!(!restored.utxo_set().unwrap().contains(&out(&split, 1)));
+        assert_eq!(restored.utxo_set(), source.utxo_set());
+
+        // A set other than the one the tip commits to
+        let empty = UtxoSet::new();
+        let set_len = set.to_bytes().len();
+        let tampered = [&bytes[..bytes.len() - set_len], &empty.to_bytes()[..]].concat();
+        let tampered = Snapshot::from_bytes(&tampered, &limits).unwrap();
+        assert_eq!(
+            Blockchain::from_snapshot(tampered, &params).err(),
+            Some(ImportError::Invalid(ChainValidationError {
+                height: 15,
+                kind: ChainValidationErrorKind::StateRootMismatch {
+                    expected: empty.state_root(),
+                    got: set.state_root(),
+                },
+            }))
+        );
+        assert_eq!(
+            Blockchain::from_snapshot(source.snapshot_at(20).unwrap(), &params).err(),
+            Some(ImportError::Invalid(ChainValidationError {
+                height: 20,
+                kind: ChainValidationErrorKind::MissingStateRoot,
+            }))
+        );
+    }
+}
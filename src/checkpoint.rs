@@ -0,0 +1,198 @@
+//! Checkpoints co-signed by a committee.
+//!
+//! Besides the checkpoints fixed in
+//! [`ChainParams::checkpoints`](crate::params::ChainParams::checkpoints), a
+//! chain accepts a block hash that enough members of its
+//! [`checkpoint_committee`](crate::params::ChainParams::checkpoint_committee)
+//! vouch for with a [`CheckpointCert`]. The certificate is not an aggregate
+//! signature: it carries one signature per signing member, and a bitmap of
+//! which members those are.
+
+use sha2::{Digest, Sha256};
+
+use crate::block::BlockHash;
+#[cfg(any(test, feature = "crypto"))]
+use crate::crypto::Signer;
+use crate::crypto::{PublicKey, Signature, Verifier};
+
+/// Domain tag prefixed to a block hash before committee members sign it, so
+/// a checkpoint signature is never valid as any other message
+pub const CHECKPOINT_TAG: &[u8] = b"aarwyn-chain/checkpoint/v1";
+
+/// Signatures of committee members over one block hash.
+///
+/// Bit `i % 8` of byte `i / 8` of `signer_bitmap` is set when member `i` of
+/// the committee signed, and `signatures` holds their signatures in committee
+/// order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CheckpointCert {
+    pub signer_bitmap: Vec<u8>,
+    pub signatures: Vec<Signature>,
+}
+
+impl CheckpointCert {
+    /// Have each of `signers` sign `header_hash`, or `None` if one of them is
+    /// not in `committee`. A member listed twice signs once.
+    #[cfg(any(test, feature = "crypto"))]
+    pub fn sign(
+        header_hash: &BlockHash,
+        committee: &[PublicKey],
+        signers: &[&dyn Signer],
+    ) -> Option<Self> {
+        let message = checkpoint_message(header_hash);
+        let mut signed = vec![None; committee.len()];
+        for signer in signers {
+            let index = committee
+                .iter()
+                .position(|key| *key == signer.public_key())?;
+            signed[index] = Some(signer.sign_message(&message));
+        }
+
+        let mut cert = CheckpointCert {
+            signer_bitmap: vec![0; committee.len().div_ceil(8)],
+            signatures: Vec::new(),
+        };
+        for (index, signature) in signed.into_iter().enumerate() {
+            if let Some(signature) = signature {
+                cert.signer_bitmap[index / 8] |= 1 << (index % 8);
+                cert.signatures.push(signature);
+            }
+        }
+        Some(cert)
+    }
+
+    /// Indices into the committee of the members the bitmap marks as signers
+    pub fn signers(&self) -> impl Iterator<Item = usize> + '_ {
+        self.signer_bitmap
+            .iter()
+            .enumerate()
+            .flat_map(|(byte, bits)| {
+                (0..8)
+                    .filter(move |bit| bits & (1 << bit) != 0)
+                    .map(move |bit| byte * 8 + bit)
+            })
+    }
+
+    /// Whether at least `threshold` members of `committee` signed
+    /// `header_hash`.
+    ///
+    /// The bitmap must be exactly as long as the committee needs, mark no one
+    /// past its end, and mark as many members as there are signatures, each of
+    /// which must be valid. A zero threshold accepts nothing.
+    pub fn verify(
+        &self,
+        header_hash: &BlockHash,
+        committee: &[PublicKey],
+        threshold: usize,
+    ) -> bool {
+        if threshold == 0 || self.signer_bitmap.len() != committee.len().div_ceil(8) {
+            return false;
+        }
+        let signers: Vec<usize> = self.signers().collect();
+        if signers.len() != self.signatures.len()
+            || signers.len() < threshold
+            || signers.last().is_some_and(|&last| last >= committee.len())
+        {
+            return false;
+        }
+        let message = checkpoint_message(header_hash);
+        signers
+            .iter()
+            .zip(&self.signatures)
+            .all(|(&index, signature)| committee[index].verify_message(&message, signature).is_ok())
+    }
+}
+
+/// The message committee members sign to vouch for `header_hash`: the
+/// SHA-256 of [`CHECKPOINT_TAG`] and the hash
+pub fn checkpoint_message(header_hash: &BlockHash) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(CHECKPOINT_TAG);
+    hasher.update(header_hash.as_bytes());
+    hasher.finalize().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{ed25519, secp256k1};
+
+    fn committee() -> (Vec<Box<dyn Signer>>, Vec<PublicKey>) {
+        let mut members: Vec<Box<dyn Signer>> = (1..=9)
+            .map(|seed| Box::new(ed25519::SigningKey::from_bytes(&[seed; 32])) as Box<dyn Signer>)
+            .collect();
+        members.push(Box::new(
+            secp256k1::SigningKey::from_bytes(&[10; 32]).unwrap(),
+        ));
+        let keys = members.iter().map(|member| member.public_key()).collect();
+        (members, keys)
+    }
+
+    #[test]
+    fn test_cert_threshold() {
+        let (members, committee) = committee();
+        let hash = BlockHash::from_bytes([7; 32]);
+        let signers: Vec<&dyn Signer> = [0, 3, 8, 9].iter().map(|&i| members[i].as_ref()).collect();
+        let cert = CheckpointCert::sign(&hash, &committee, &signers).unwrap();
+        assert_eq!(cert.signer_bitmap, vec![0b0000_1001, 0b0000_0011]);
+        assert_eq!(cert.signers().collect::<Vec<_>>(), vec![0, 3, 8, 9]);
+
+        assert!(cert.verify(&hash, &committee, 4));
+        assert!(cert.verify(&hash, &committee, 1));
+        assert!(!cert.verify(&hash, &committee, 5));
+        assert!(!cert.verify(&hash, &committee, 0));
+        // Only members may sign
+        let outsider = ed25519::SigningKey::from_bytes(&[11; 32]);
+        assert_eq!(CheckpointCert::sign(&hash, &committee, &[&outsider]), None);
+        // A bitmap sized for another committee
+        assert!(!cert.verify(&hash, &committee[..8], 1));
+    }
+
+    #[test]
+    fn test_cert_bitmap_must_match_signatures() {
+        let (members, committee) = committee();
+        let hash = BlockHash::from_bytes([7; 32]);
+        let signers: Vec<&dyn Signer> = members.iter().take(3).map(|m| m.as_ref()).collect();
+        let cert = CheckpointCert::sign(&hash, &committee, &signers).unwrap();
+        assert!(cert.verify(&hash, &committee, 3));
+
+        let mut extra_bit = cert.clone();
+        extra_bit.signer_bitmap[0] |= 1 << 3;
+        assert!(!extra_bit.verify(&hash, &committee, 3));
+
+        let mut missing_signature = cert.clone();
+        missing_signature.signatures.pop();
+        assert!(!missing_signature.verify(&hash, &committee, 2));
+
+        // A signature credited to the wrong member
+        let mut shifted = cert.clone();
+        shifted.signer_bitmap[0] = 0b0000_1110;
+        assert!(!shifted.verify(&hash, &committee, 3));
+
+        // A bit past the end of the committee
+        let mut past_end = cert.clone();
+        past_end.signer_bitmap[1] |= 1 << 2;
+        past_end.signatures.push(cert.signatures[0]);
+        assert!(!past_end.verify(&hash, &committee, 3));
+    }
+
+    #[test]
+    fn test_cert_does_not_replay_on_other_hashes() {
+        let (members, committee) = committee();
+        let hash = BlockHash::from_bytes([7; 32]);
+        let signers: Vec<&dyn Signer> = members.iter().map(|m| m.as_ref()).collect();
+        let cert = CheckpointCert::sign(&hash, &committee, &signers).unwrap();
+        assert!(cert.verify(&hash, &committee, 10));
+        assert!(!cert.verify(&BlockHash::from_bytes([8; 32]), &committee, 1));
+
+        // Signatures over the bare hash are not checkpoint signatures
+        let bare = CheckpointCert {
+            signatures: members
+                .iter()
+                .map(|m| m.sign_message(hash.as_bytes()))
+                .collect(),
+            ..cert
+        };
+        assert!(!bare.verify(&hash, &committee, 1));
+    }
+}
@@ -0,0 +1,171 @@
+//! The async client, over `reqwest` on tokio.
+
+use super::{Call, ClientConfig, ClientError};
+use crate::block::{Block, BlockHash};
+use crate::chain::TxWithProof;
+use crate::transaction::{Transaction, Txid};
+
+/// A client calling a node's JSON-RPC methods, awaiting each answer
+pub struct AsyncRpcClient {
+    client: reqwest::Client,
+    url: String,
+    config: ClientConfig,
+}
+
+impl AsyncRpcClient {
+    /// A client for the server at `url`, such as `http://127.0.0.1:8332/`,
+    /// with the default [`ClientConfig`]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::with_config(url, ClientConfig::default())
+    }
+
+    #[allow(clippy::expect_used)]
+    pub fn with_config(url: impl Into<String>, config: ClientConfig) -> Self {
+        let client = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .expect("a client without TLS always builds");
+        AsyncRpcClient {
+            client,
+            url: url.into(),
+            config,
+        }
+    }
+
+    pub async fn get_block_count(&self) -> Result<u64, ClientError> {
+        self.run(Call::get_block_count()).await
+    }
+
+    pub async fn get_best_block_hash(&self) -> Result<BlockHash, ClientError> {
+        self.run(Call::get_best_block_hash()).await
+    }
+
+    pub async fn get_block_hash(&self, height: u64) -> Result<BlockHash, ClientError> {
+        self.run(Call::get_block_hash(height)).await
+    }
+
+    pub async fn get_block(&self, hash: &BlockHash) -> Result<Block, ClientError> {
+        self.run(Call::get_block(hash)).await
+    }
+
+    pub async fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, ClientError> {
+        self.run(Call::get_raw_transaction(txid)).await
+    }
+
+    /// Send `tx` to the mempool, once, returning its txid
+    pub async fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid, ClientError> {
+        self.run(Call::send_raw_transaction(tx)).await
+    }
+
+    pub async fn get_merkle_proof(&self, txid: &Txid) -> Result<TxWithProof, ClientError> {
+        self.run(Call::get_merkle_proof(txid)).await
+    }
+
+    async fn run<T>(&self, call: Call<T>) -> Result<T, ClientError> {
+        let body = call.body();
+        let attempts = call.attempts(&self.config);
+        let mut attempt = 1;
+        loop {
+            match self.post(&body).await {
+                Err(err) if err.is_transient() && attempt < attempts => {
+                    attempt += 1;
+                    tokio::time::sleep(self.config.retry_delay).await;
+                }
+                response => return call.read_response(&response?),
+            }
+        }
+    }
+
+    /// The body of the response to posting `body`
+    async fn post(&self, body: &str) -> Result<Vec<u8>, ClientError> {
+        let transport = |err: reqwest::Error| ClientError::Transport(err.to_string());
+        let mut response = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(transport)?;
+        if response.status() != reqwest::StatusCode::OK {
+            return Err(ClientError::Status(response.status().as_u16()));
+        }
+        let max = self.config.max_response_bytes;
+        let too_large = || ClientError::InvalidResponse(format!("body is over {} bytes", max));
+        if response
+            .content_length()
+            .is_some_and(|len| len > max as u64)
+        {
+            return Err(too_large());
+        }
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(transport)? {
+            if body.len() + chunk.len() > max {
+                return Err(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(all(test, feature = "rpc"))]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::super::tests::{hang_up, quick_retries};
+    use super::*;
+    use crate::rpc::tests::{start, Fixture};
+    use crate::testing::signed_spend;
+
+    #[tokio::test]
+    async fn test_calls_the_server() {
+        let Fixture {
+            server,
+            url,
+            coinbase,
+            block,
+            ..
+        } = start();
+        let client = AsyncRpcClient::new(url);
+        assert_eq!(client.get_block_count().await, Ok(1));
+        assert_eq!(client.get_block(&block.hash()).await, Ok(block.clone()));
+        assert_eq!(
+            client.get_raw_transaction(&coinbase.txid()).await,
+            Ok(coinbase.clone())
+        );
+        let proof = client.get_merkle_proof(&coinbase.txid()).await.unwrap();
+        assert!(proof.verify(block.hash().as_bytes()));
+
+        let unknown = BlockHash::from_bytes([9; 32]);
+        assert_eq!(
+            client.get_block(&unknown).await,
+            Err(ClientError::NotFound(format!(
+                "block {} not found",
+                unknown
+            )))
+        );
+        // The pool takes no transaction without inputs
+        assert!(matches!(
+            client.send_raw_transaction(&coinbase).await,
+            Err(ClientError::Rejected(_))
+        ));
+        server.stop();
+    }
+
+    #[tokio::test]
+    async fn test_retries_only_idempotent_calls() {
+        let (url, requests) = hang_up();
+        let client = AsyncRpcClient::with_config(url, quick_retries());
+        assert!(matches!(
+            client.get_best_block_hash().await,
+            Err(ClientError::Transport(_))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert!(matches!(
+            client.send_raw_transaction(&signed_spend(&[], &[1])).await,
+            Err(ClientError::Transport(_))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+}
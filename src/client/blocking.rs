@@ -0,0 +1,192 @@
+//! The blocking client, over `ureq`.
+
+use std::io::Read;
+use std::thread;
+
+use super::{Call, ClientConfig, ClientError};
+use crate::block::{Block, BlockHash};
+use crate::chain::TxWithProof;
+use crate::transaction::{Transaction, Txid};
+
+/// A client calling a node's JSON-RPC methods, blocking until each answers
+pub struct RpcClient {
+    agent: ureq::Agent,
+    url: String,
+    config: ClientConfig,
+}
+
+impl RpcClient {
+    /// A client for the server at `url`, such as `http://127.0.0.1:8332/`,
+    /// with the default [`ClientConfig`]
+    pub fn new(url: impl Into<String>) -> Self {
+        Self::with_config(url, ClientConfig::default())
+    }
+
+    pub fn with_config(url: impl Into<String>, config: ClientConfig) -> Self {
+        RpcClient {
+            agent: ureq::AgentBuilder::new().timeout(config.timeout).build(),
+            url: url.into(),
+            config,
+        }
+    }
+
+    pub fn get_block_count(&self) -> Result<u64, ClientError> {
+        self.run(Call::get_block_count())
+    }
+
+    pub fn get_best_block_hash(&self) -> Result<BlockHash, ClientError> {
+        self.run(Call::get_best_block_hash())
+    }
+
+    pub fn get_block_hash(&self, height: u64) -> Result<BlockHash, ClientError> {
+        self.run(Call::get_block_hash(height))
+    }
+
+    pub fn get_block(&self, hash: &BlockHash) -> Result<Block, ClientError> {
+        self.run(Call::get_block(hash))
+    }
+
+    pub fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, ClientError> {
+        self.run(Call::get_raw_transaction(txid))
+    }
+
+    /// Send `tx` to the mempool, once, returning its txid
+    pub fn send_raw_transaction(&self, tx: &Transaction) -> Result<Txid, ClientError> {
+        self.run(Call::send_raw_transaction(tx))
+    }
+
+    pub fn get_merkle_proof(&self, txid: &Txid) -> Result<TxWithProof, ClientError> {
+        self.run(Call::get_merkle_proof(txid))
+    }
+
+    fn run<T>(&self, call: Call<T>) -> Result<T, ClientError> {
+        let body = call.body();
+        let attempts = call.attempts(&self.config);
+        let mut attempt = 1;
+        loop {
+            match self.post(&body) {
+                Err(err) if err.is_transient() && attempt < attempts => {
+                    attempt += 1;
+                    thread::sleep(self.config.retry_delay);
+                }
+                response => return call.read_response(&response?),
+            }
+        }
+    }
+
+    /// The body of the response to posting `body`
+    fn post(&self, body: &str) -> Result<Vec<u8>, ClientError> {
+        let response = match self
+            .agent
+            .post(&self.url)
+            .set("Content-Type", "application/json")
+            .send_string(body)
+        {
+            Ok(response) => response,
+            Err(ureq::Error::Status(status, _)) => return Err(ClientError::Status(status)),
+            Err(ureq::Error::Transport(err)) => {
+                return Err(ClientError::Transport(err.to_string()))
+            }
+        };
+        if response.status() != 200 {
+            return Err(ClientError::Status(response.status()));
+        }
+        let max = self.config.max_response_bytes;
+        let mut body = Vec::new();
+        response
+            .into_reader()
+            .take(max as u64 + 1)
+            .read_to_end(&mut body)
+            .map_err(|err| ClientError::Transport(err.to_string()))?;
+        if body.len() > max {
+            return Err(ClientError::InvalidResponse(format!(
+                "body is over {} bytes",
+                max
+            )));
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(all(test, feature = "rpc"))]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::super::tests::{hang_up, quick_retries};
+    use super::*;
+    use crate::rpc::tests::{start, Fixture};
+    use crate::testing::signed_spend;
+    use crate::transaction::OutPoint;
+
+    #[test]
+    fn test_calls_the_server() {
+        let Fixture {
+            server,
+            url,
+            funding,
+            coinbase,
+            block,
+            ..
+        } = start();
+        let client = RpcClient::new(url);
+        assert_eq!(client.get_block_count(), Ok(1));
+        assert_eq!(client.get_best_block_hash(), Ok(block.hash()));
+        assert_eq!(client.get_block_hash(1), Ok(block.hash()));
+        assert_eq!(client.get_block(&block.hash()), Ok(block.clone()));
+        assert_eq!(
+            client.get_raw_transaction(&coinbase.txid()),
+            Ok(coinbase.clone())
+        );
+        let proof = client.get_merkle_proof(&coinbase.txid()).unwrap();
+        assert!(proof.verify(block.hash().as_bytes()));
+
+        let spend = signed_spend(
+            &[OutPoint {
+                txid: funding.txid(),
+                index: 0,
+            }],
+            &[900],
+        );
+        assert_eq!(client.send_raw_transaction(&spend), Ok(spend.txid()));
+        assert_eq!(client.get_raw_transaction(&spend.txid()), Ok(spend.clone()));
+
+        // Errors come back as the variants of their codes
+        let unknown = BlockHash::from_bytes([9; 32]);
+        assert_eq!(
+            client.get_block(&unknown),
+            Err(ClientError::NotFound(format!(
+                "block {} not found",
+                unknown
+            )))
+        );
+        assert!(matches!(
+            client.get_block_hash(5),
+            Err(ClientError::NotFound(_))
+        ));
+        assert!(matches!(
+            client.send_raw_transaction(&spend),
+            Err(ClientError::Rejected(_))
+        ));
+        assert!(matches!(
+            client.get_merkle_proof(&spend.txid()),
+            Err(ClientError::NotFound(_))
+        ));
+        server.stop();
+    }
+
+    #[test]
+    fn test_retries_only_idempotent_calls() {
+        let (url, requests) = hang_up();
+        let client = RpcClient::with_config(url, quick_retries());
+        assert!(matches!(
+            client.get_block_count(),
+            Err(ClientError::Transport(_))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        assert!(matches!(
+            client.send_raw_transaction(&signed_spend(&[], &[1])),
+            Err(ClientError::Transport(_))
+        ));
+        assert_eq!(requests.load(Ordering::SeqCst), 4);
+    }
+}
@@ -0,0 +1,361 @@
+//! Clients for the node's JSON-RPC methods, calling them over HTTP and
+//! reading their results as the crate's types.
+//!
+//! [`RpcClient`] blocks on each call, with the `client` feature, and
+//! [`AsyncRpcClient`] awaits it on tokio, with `client-async`. Both take a
+//! [`ClientConfig`] and fail with a [`ClientError`], in which the JSON-RPC
+//! code of a call the server refused picks the variant. A call that gets no
+//! response, or a 5xx status, is tried again up to
+//! [`ClientConfig::retries`] times if making it twice is harmless, which is
+//! every call but sending a transaction.
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::block::{Block, BlockHash};
+use crate::chain::TxWithProof;
+use crate::codec::DecodeLimits;
+use crate::encoding;
+use crate::transaction::{Transaction, Txid};
+
+#[cfg(feature = "client-async")]
+mod async_client;
+#[cfg(feature = "client")]
+mod blocking;
+
+#[cfg(feature = "client-async")]
+pub use async_client::AsyncRpcClient;
+#[cfg(feature = "client")]
+pub use blocking::RpcClient;
+
+/// Reasons a call failed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ClientError {
+    /// No response arrived: the connection failed or the attempt timed out
+    #[error("no response: {0}")]
+    Transport(String),
+    /// The server answered with an HTTP status other than 200
+    #[error("server answered with status {0}")]
+    Status(u16),
+    /// The body is not a JSON-RPC response, or its result is not what the
+    /// method returns
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    /// No block or transaction has the hash, height or txid; code -5
+    #[error("{0}")]
+    NotFound(String),
+    /// The server refused a parameter; code -32602
+    #[error("{0}")]
+    InvalidParams(String),
+    /// The server could not decode what was sent; code -22
+    #[error("{0}")]
+    Decode(String),
+    /// The mempool refused the transaction; code -26
+    #[error("{0}")]
+    Rejected(String),
+    /// The call failed with another code, given with the server's message
+    #[error("error {code}: {message}")]
+    Rpc { code: i64, message: String },
+}
+
+impl ClientError {
+    /// The error for a response's error `code` and `message`
+    fn from_code(code: i64, message: String) -> Self {
+        match code {
+            -5 => ClientError::NotFound(message),
+            -32602 => ClientError::InvalidParams(message),
+            -22 => ClientError::Decode(message),
+            -26 => ClientError::Rejected(message),
+            _ => ClientError::Rpc { code, message },
+        }
+    }
+
+    /// The JSON-RPC code of an error the server returned
+    pub fn code(&self) -> Option<i64> {
+        match self {
+            ClientError::NotFound(_) => Some(-5),
+            ClientError::InvalidParams(_) => Some(-32602),
+            ClientError::Decode(_) => Some(-22),
+            ClientError::Rejected(_) => Some(-26),
+            ClientError::Rpc { code, .. } => Some(*code),
+            ClientError::Transport(_)
+            | ClientError::Status(_)
+            | ClientError::InvalidResponse(_) => None,
+        }
+    }
+
+    /// Whether the same call may succeed if it is made again
+    fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Transport(_) => true,
+            ClientError::Status(status) => (500..600).contains(status),
+            _ => false,
+        }
+    }
+}
+
+/// How a client makes its calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientConfig {
+    /// Longest one attempt at a call may take, connecting included
+    pub timeout: Duration,
+    /// How many more times an idempotent call is made after a transient
+    /// failure
+    pub retries: u32,
+    /// Pause before each retry
+    pub retry_delay: Duration,
+    /// Largest response body read; a larger one fails the call
+    pub max_response_bytes: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            timeout: Duration::from_secs(30),
+            retries: 2,
+            retry_delay: Duration::from_millis(200),
+            // Room for the hex of the largest block the default limits decode
+            max_response_bytes: 2 * DecodeLimits::default().max_decode_bytes + 64 * 1024,
+        }
+    }
+}
+
+/// A method with its parameters, and how to read its result
+struct Call<T> {
+    method: &'static str,
+    params: Vec<Value>,
+    /// Whether making the call twice has the effect of making it once
+    idempotent: bool,
+    read: fn(Value) -> Result<T, ClientError>,
+}
+
+impl<T> Call<T> {
+    fn query(
+        method: &'static str,
+        params: Vec<Value>,
+        read: fn(Value) -> Result<T, ClientError>,
+    ) -> Self {
+        Call {
+            method,
+            params,
+            idempotent: true,
+            read,
+        }
+    }
+
+    /// The request body
+    fn body(&self) -> String {
+        json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": self.method,
+            "params": self.params,
+        })
+        .to_string()
+    }
+
+    /// How many times to make the call before giving up on transient
+    /// failures
+    fn attempts(&self, config: &ClientConfig) -> u32 {
+        match self.idempotent {
+            true => config.retries.saturating_add(1),
+            false => 1,
+        }
+    }
+
+    /// The result of the call from the response `body`
+    fn read_response(&self, body: &[u8]) -> Result<T, ClientError> {
+        let response: Value = serde_json::from_slice(body)
+            .map_err(|err| ClientError::InvalidResponse(err.to_string()))?;
+        if let Some(error) = response.get("error") {
+            let code = error
+                .get("code")
+                .and_then(Value::as_i64)
+                .ok_or_else(|| invalid("error without a code"))?;
+            let message = error
+                .get("message")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            return Err(ClientError::from_code(code, message.to_string()));
+        }
+        let result = response
+            .get("result")
+            .ok_or_else(|| invalid("response has neither result nor error"))?;
+        (self.read)(result.clone())
+    }
+}
+
+impl Call<u64> {
+    fn get_block_count() -> Self {
+        Call::query("getblockcount", Vec::new(), |value| {
+            value
+                .as_u64()
+                .ok_or_else(|| invalid("block count is not an unsigned integer"))
+        })
+    }
+}
+
+impl Call<BlockHash> {
+    fn get_best_block_hash() -> Self {
+        Call::query("getbestblockhash", Vec::new(), read_hash)
+    }
+
+    fn get_block_hash(height: u64) -> Self {
+        Call::query("getblockhash", vec![height.into()], read_hash)
+    }
+}
+
+impl Call<Block> {
+    fn get_block(hash: &BlockHash) -> Self {
+        Call::query(
+            "getblock",
+            vec![hash.to_string().into(), 0.into()],
+            |value| {
+                Block::from_bytes(&read_hex(&value, "block")?, &DecodeLimits::default())
+                    .map_err(|err| invalid(&format!("block: {}", err)))
+            },
+        )
+    }
+}
+
+impl Call<Transaction> {
+    fn get_raw_transaction(txid: &Txid) -> Self {
+        Call::query(
+            "getrawtransaction",
+            vec![txid.to_string().into()],
+            |value| {
+                Transaction::decode(&read_hex(&value, "transaction")?, &DecodeLimits::default())
+                    .map_err(|err| invalid(&format!("transaction: {}", err)))
+            },
+        )
+    }
+}
+
+impl Call<Txid> {
+    fn send_raw_transaction(tx: &Transaction) -> Self {
+        Call {
+            method: "sendrawtransaction",
+            params: vec![hex::encode(tx.encode()).into()],
+            // A repeat would be refused as already pooled, hiding whether the first took
+            idempotent: false,
+            read: |value| {
+                value
+                    .as_str()
+                    .and_then(|txid| txid.parse().ok())
+                    .ok_or_else(|| invalid("txid is not 32 bytes of hex"))
+            },
+        }
+    }
+}
+
+impl Call<TxWithProof> {
+    fn get_merkle_proof(txid: &Txid) -> Self {
+        Call::query("getmerkleproof", vec![txid.to_string().into()], |value| {
+            TxWithProof::from_bytes(&read_hex(&value, "proof")?, &DecodeLimits::default())
+                .map_err(|err| invalid(&format!("proof: {}", err)))
+        })
+    }
+}
+
+fn invalid(reason: &str) -> ClientError {
+    ClientError::InvalidResponse(reason.to_string())
+}
+
+fn read_hex(value: &Value, what: &str) -> Result<Vec<u8>, ClientError> {
+    let hex = value
+        .as_str()
+        .ok_or_else(|| invalid(&format!("{} is not a string", what)))?;
+    encoding::from_hex(hex).map_err(|err| invalid(&format!("{}: {}", what, err)))
+}
+
+fn read_hash(value: Value) -> Result<BlockHash, ClientError> {
+    value
+        .as_str()
+        .and_then(|hash| hash.parse().ok())
+        .ok_or_else(|| invalid("block hash is not 32 bytes of hex"))
+}
+
+#[cfg(test)]
+#[cfg(feature = "rpc")]
+mod tests {
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    use super::*;
+
+    /// A URL whose listener reads each request and hangs up without
+    /// answering, and the number of requests it has had
+    pub(super) fn hang_up() -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        // Left blocked in `accept` once the test is done with it
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let _ = stream.read(&mut [0; 1024]);
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        (url, requests)
+    }
+
+    pub(super) fn quick_retries() -> ClientConfig {
+        ClientConfig {
+            retries: 2,
+            retry_delay: Duration::from_millis(1),
+            ..ClientConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_reads_responses() {
+        let count = Call::get_block_count();
+        assert_eq!(
+            count.read_response(br#"{"jsonrpc":"2.0","result":7,"id":1}"#),
+            Ok(7)
+        );
+        let cases: [(&[u8], ClientError); 7] = [
+            (
+                br#"{"jsonrpc":"2.0","error":{"code":-5,"message":"block 00 not found"},"id":1}"#,
+                ClientError::NotFound("block 00 not found".to_string()),
+            ),
+            (
+                br#"{"jsonrpc":"2.0","error":{"code":-26,"message":"no"},"id":1}"#,
+                ClientError::Rejected("no".to_string()),
+            ),
+            (
+                br#"{"jsonrpc":"2.0","error":{"code":-32601,"message":"gone"},"id":1}"#,
+                ClientError::Rpc {
+                    code: -32601,
+                    message: "gone".to_string(),
+                },
+            ),
+            (
+                br#"{"jsonrpc":"2.0","error":{"message":"gone"},"id":1}"#,
+                invalid("error without a code"),
+            ),
+            (
+                br#"{"jsonrpc":"2.0","id":1}"#,
+                invalid("response has neither result nor error"),
+            ),
+            (
+                br#"{"jsonrpc":"2.0","result":"7","id":1}"#,
+                invalid("block count is not an unsigned integer"),
+            ),
+            (b"\xff", invalid("expected value at line 1 column 1")),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(count.read_response(body), Err(expected));
+        }
+        assert_eq!(ClientError::from_code(-22, String::new()).code(), Some(-22));
+        assert!(ClientError::Status(503).is_transient());
+        assert!(!ClientError::Status(413).is_transient());
+    }
+}
@@ -0,0 +1,397 @@
+//! Binary encoding helpers shared by the block and proof formats.
+//!
+//! Decoders run on untrusted bytes, so every length read from the input is
+//! checked against both the remaining input and a [`DecodeLimits`] before
+//! anything is allocated.
+//!
+//! Types that cross process boundaries through serde instead, such as
+//! blocks and proofs on a queue, can use [`to_bincode`] and [`from_bincode`],
+//! which fix the bincode options their checked-in fixtures are written with.
+
+use std::io::{self, Read, Write};
+
+use bincode::Options;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+pub use bincode::Error as BincodeError;
+
+/// Limits applied while decoding untrusted input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// Maximum size of the whole encoded object
+    pub max_decode_bytes: usize,
+    /// Maximum number of transactions in a block
+    pub max_transactions: usize,
+    /// Maximum size of a single transaction
+    pub max_transaction_bytes: usize,
+    /// Maximum number of inputs in a transaction
+    pub max_tx_inputs: usize,
+    /// Maximum number of outputs in a transaction
+    pub max_tx_outputs: usize,
+    /// Smallest amount an output of a transaction with inputs may pay;
+    /// coinbase outputs are bounded by the block reward instead
+    pub min_output_amount: u64,
+    /// Maximum number of levels in a merkle proof
+    pub max_proof_depth: usize,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits {
+            max_decode_bytes: 32 * 1024 * 1024,
+            max_transactions: 100_000,
+            max_transaction_bytes: 1024 * 1024,
+            max_tx_inputs: 10_000,
+            max_tx_outputs: 10_000,
+            min_output_amount: 1,
+            max_proof_depth: 64,
+        }
+    }
+}
+
+/// Errors raised while decoding binary data
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    /// The input ended before the object was complete
+    #[error("unexpected end of input")]
+    UnexpectedEof,
+    /// Bytes were left over after the object was decoded
+    #[error("{0} trailing bytes after object")]
+    TrailingBytes(usize),
+    /// A varint was overlong, too large, or not minimally encoded
+    #[error("invalid or non-canonical varint")]
+    InvalidVarint,
+    /// A field holds a value the format does not allow
+    #[error("invalid value: {0}")]
+    InvalidValue(&'static str),
+    /// The input decoded, but re-encoding the result gives different bytes
+    #[error("non-canonical encoding")]
+    NonCanonicalEncoding,
+    /// A size or count exceeded the configured [`DecodeLimits`]
+    #[error("{what} of {value} exceeds limit of {max}")]
+    LimitExceeded {
+        what: &'static str,
+        value: u64,
+        max: u64,
+    },
+}
+
+/// Append `value` as an unsigned LEB128 varint
+pub fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf.push(byte);
+            return;
+        }
+        buf.push(byte | 0x80);
+    }
+}
+
+/// Append a varint length prefix followed by the bytes themselves
+pub fn write_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    write_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Check an encoded object's overall size before decoding it
+pub fn check_input_size(len: usize, limits: &DecodeLimits) -> Result<(), DecodeError> {
+    if len > limits.max_decode_bytes {
+        return Err(DecodeError::LimitExceeded {
+            what: "input size",
+            value: len as u64,
+            max: limits.max_decode_bytes as u64,
+        });
+    }
+    Ok(())
+}
+
+// Fixed-size little-endian integers, so a `u64` is always 8 bytes; decoding
+// also refuses trailing bytes
+fn bincode_options() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .with_little_endian()
+}
+
+/// Serialize `value` with bincode, using fixed-size little-endian integers.
+///
+/// The layout follows the serde form of the type, which for the chain's own
+/// types is every field in the order declared.
+pub fn to_bincode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, BincodeError> {
+    bincode_options().serialize(value)
+}
+
+/// Deserialize what [`to_bincode`] wrote, reading at most
+/// `limits.max_decode_bytes` and refusing bytes left over
+pub fn from_bincode<T: DeserializeOwned>(bytes: &[u8], limits: &DecodeLimits) -> Result<T, BincodeError> {
+    if bytes.len() > limits.max_decode_bytes {
+        return Err(Box::new(bincode::ErrorKind::SizeLimit));
+    }
+    bincode_options()
+        .with_limit(limits.max_decode_bytes as u64)
+        .deserialize(bytes)
+}
+
+/// A cursor over untrusted input
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    /// Number of bytes not yet consumed
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if len > self.remaining() {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.read_array::<1>()?[0])
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, DecodeError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, DecodeError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    /// Read a minimally-encoded LEB128 varint of at most 64 bits
+    pub fn read_varint(&mut self) -> Result<u64, DecodeError> {
+        read_varint_with(|| self.read_u8())
+    }
+
+    /// Read a varint length or count and check it against `max`
+    pub fn read_len(&mut self, what: &'static str, max: usize) -> Result<usize, DecodeError> {
+        let value = self.read_varint()?;
+        if value > max as u64 {
+            return Err(DecodeError::LimitExceeded {
+                what,
+                value,
+                max: max as u64,
+            });
+        }
+        Ok(value as usize)
+    }
+
+    /// Read a length-prefixed byte string of at most `max` bytes
+    pub fn read_var_bytes(&mut self, what: &'static str, max: usize) -> Result<&'a [u8], DecodeError> {
+        let len = self.read_len(what, max)?;
+        self.read_bytes(len)
+    }
+
+    /// Succeed only if the whole input has been consumed
+    pub fn finish(self) -> Result<(), DecodeError> {
+        match self.remaining() {
+            0 => Ok(()),
+            n => Err(DecodeError::TrailingBytes(n)),
+        }
+    }
+}
+
+/// Read a varint as [`Reader::read_varint`] does, taking its bytes one at a
+/// time from `next`, for decoders reading from a stream
+pub fn read_varint_with<E: From<DecodeError>>(mut next: impl FnMut() -> Result<u8, E>) -> Result<u64, E> {
+    let mut value: u64 = 0;
+    for i in 0..10 {
+        let byte = next()?;
+        let bits = (byte & 0x7f) as u64;
+        if i == 9 && bits > 1 {
+            return Err(DecodeError::InvalidVarint.into());
+        }
+        value |= bits << (7 * i);
+        if byte & 0x80 == 0 {
+            // A zero final group means a shorter encoding existed
+            if i > 0 && bits == 0 {
+                return Err(DecodeError::InvalidVarint.into());
+            }
+            return Ok(value);
+        }
+    }
+    Err(DecodeError::InvalidVarint.into())
+}
+
+/// Hashes and counts the bytes read from or written to the stream it wraps,
+/// so an encoding can be checksummed while it streams
+pub(crate) struct Checksummed<T> {
+    inner: T,
+    hasher: Sha256,
+    len: u64,
+    /// The first error `inner` failed with; the error passed on in its
+    /// place has only its kind
+    error: Option<io::Error>,
+}
+
+impl<T> Checksummed<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        Checksummed {
+            inner,
+            hasher: Sha256::new(),
+            len: 0,
+            error: None,
+        }
+    }
+
+    /// Number of bytes passed through so far
+    pub(crate) fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// The first four bytes of the SHA-256 of the bytes passed through
+    pub(crate) fn checksum(&self) -> [u8; 4] {
+        let digest = self.hasher.clone().finalize();
+        [digest[0], digest[1], digest[2], digest[3]]
+    }
+
+    /// The error the wrapped stream failed with, if it did
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn failed(&mut self, err: io::Error) -> io::Error {
+        let kind = err.kind();
+        if kind == io::ErrorKind::Interrupted {
+            return err;
+        }
+        self.error.get_or_insert(err);
+        kind.into()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.inner.read(buf) {
+            Ok(n) => {
+                self.hasher.update(&buf[..n]);
+                self.len += n as u64;
+                Ok(n)
+            }
+            Err(err) => Err(self.failed(err)),
+        }
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.inner.write(buf) {
+            Ok(n) => {
+                self.hasher.update(&buf[..n]);
+                self.len += n as u64;
+                Ok(n)
+            }
+            Err(err) => Err(self.failed(err)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(|err| self.failed(err))
+    }
+}
+
+/// Hands out its bytes at most `chunk` at a time, as a socket might, for
+/// testing decoders that read from a stream
+#[cfg(test)]
+pub(crate) struct ChunkedReader<'a> {
+    bytes: &'a [u8],
+    chunk: usize,
+}
+
+#[cfg(test)]
+impl<'a> ChunkedReader<'a> {
+    pub(crate) fn new(bytes: &'a [u8], chunk: usize) -> Self {
+        ChunkedReader { bytes, chunk }
+    }
+}
+
+#[cfg(test)]
+impl Read for ChunkedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = buf.len().min(self.chunk).min(self.bytes.len());
+        buf[..n].copy_from_slice(&self.bytes[..n]);
+        self.bytes = &self.bytes[n..];
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHeader;
+    use crate::testing::{block_header, transaction};
+    use crate::transaction::Transaction;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_varint_round_trip(value in prop_oneof![any::<u64>(), 0..300u64]) {
+            let mut buf = Vec::new();
+            write_varint(&mut buf, value);
+            let mut reader = Reader::new(&buf);
+            prop_assert_eq!(reader.read_varint(), Ok(value));
+            prop_assert_eq!(reader.finish(), Ok(()));
+        }
+
+        #[test]
+        fn test_bincode_round_trip(tx in transaction(), header in block_header()) {
+            let limits = DecodeLimits::default();
+            let bytes = to_bincode(&tx).unwrap();
+            prop_assert_eq!(from_bincode::<Transaction>(&bytes, &limits).unwrap(), tx);
+            let bytes = to_bincode(&header).unwrap();
+            prop_assert!(from_bincode::<BlockHeader>(&bytes[..bytes.len() - 1], &limits).is_err());
+            prop_assert_eq!(from_bincode::<BlockHeader>(&bytes, &limits).unwrap(), header);
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_varints() {
+        // Overlong encoding of zero
+        assert_eq!(Reader::new(&[0x80, 0x00]).read_varint(), Err(DecodeError::InvalidVarint));
+        // More than 64 bits
+        let mut too_big = vec![0xff; 9];
+        too_big.push(0x02);
+        assert_eq!(Reader::new(&too_big).read_varint(), Err(DecodeError::InvalidVarint));
+        // Unterminated
+        assert_eq!(Reader::new(&[0xff; 3]).read_varint(), Err(DecodeError::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_bincode_is_fixint_little_endian_and_limited() {
+        let bytes = to_bincode(&(1u32, vec![2u64])).unwrap();
+        assert_eq!(bytes, [1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0]);
+        let limits = DecodeLimits::default();
+        assert_eq!(from_bincode::<(u32, Vec<u64>)>(&bytes, &limits).unwrap(), (1, vec![2]));
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(from_bincode::<(u32, Vec<u64>)>(&trailing, &limits).is_err());
+        let tight = DecodeLimits {
+            max_decode_bytes: bytes.len() - 1,
+            ..limits
+        };
+        assert!(from_bincode::<(u32, Vec<u64>)>(&bytes, &tight).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+/// An element of the field GF(2^255 - 19), stored as five 51-bit limbs
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct FieldElement([u64; 5]);
+
+const MASK: u64 = (1 << 51) - 1;
+
+/// p - 2, little endian, used for inversion via Fermat's little theorem
+const P_MINUS_2: [u8; 32] = {
+    let mut e = [0xff; 32];
+    e[0] = 0xeb;
+    e[31] = 0x7f;
+    e
+};
+
+/// (p - 5) / 8, little endian, used for square roots
+const P_MINUS_5_DIV_8: [u8; 32] = {
+    let mut e = [0xff; 32];
+    e[0] = 0xfd;
+    e[31] = 0x0f;
+    e
+};
+
+impl FieldElement {
+    pub const ZERO: FieldElement = FieldElement([0; 5]);
+    pub const ONE: FieldElement = FieldElement([1, 0, 0, 0, 0]);
+
+    /// Decode a little-endian field element, ignoring the top bit
+    pub fn from_bytes(bytes: &[u8; 32]) -> Self {
+        let load = |i: usize| {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[i..i + 8]);
+            u64::from_le_bytes(word)
+        };
+
+        FieldElement([
+            load(0) & MASK,
+            (load(6) >> 3) & MASK,
+            (load(12) >> 6) & MASK,
+            (load(19) >> 1) & MASK,
+            (load(24) >> 12) & MASK,
+        ])
+    }
+
+    /// Encode the canonical (fully reduced) little-endian representation
+    pub fn to_bytes(self) -> [u8; 32] {
+        let mut l = self.weak_reduce().0;
+
+        // Compute whether the value is >= p and subtract p if so
+        let mut q = (l[0] + 19) >> 51;
+        q = (l[1] + q) >> 51;
+        q = (l[2] + q) >> 51;
+        q = (l[3] + q) >> 51;
+        q = (l[4] + q) >> 51;
+
+        l[0] += 19 * q;
+        l[1] += l[0] >> 51;
+        l[0] &= MASK;
+        l[2] += l[1] >> 51;
+        l[1] &= MASK;
+        l[3] += l[2] >> 51;
+        l[2] &= MASK;
+        l[4] += l[3] >> 51;
+        l[3] &= MASK;
+        l[4] &= MASK;
+
+        let mut out = [0u8; 32];
+        let mut acc: u128 = 0;
+        let mut bits = 0;
+        let mut idx = 0;
+        for limb in l {
+            acc |= (limb as u128) << bits;
+            bits += 51;
+            while bits >= 8 {
+                out[idx] = acc as u8;
+                acc >>= 8;
+                bits -= 8;
+                idx += 1;
+            }
+        }
+        out[idx] = acc as u8;
+        out
+    }
+
+    /// Propagate carries so that every limb fits in slightly more than 51 bits
+    fn weak_reduce(self) -> Self {
+        let mut l = self.0;
+        let c0 = l[0] >> 51;
+        let c1 = l[1] >> 51;
+        let c2 = l[2] >> 51;
+        let c3 = l[3] >> 51;
+        let c4 = l[4] >> 51;
+        l[0] &= MASK;
+        l[1] &= MASK;
+        l[2] &= MASK;
+        l[3] &= MASK;
+        l[4] &= MASK;
+        l[0] += c4 * 19;
+        l[1] += c0;
+        l[2] += c1;
+        l[3] += c2;
+        l[4] += c3;
+        FieldElement(l)
+    }
+
+    pub fn add(&self, rhs: &Self) -> Self {
+        let (a, b) = (self.0, rhs.0);
+        FieldElement([a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3], a[4] + b[4]]).weak_reduce()
+    }
+
+    pub fn sub(&self, rhs: &Self) -> Self {
+        // Add 2p before subtracting so that no limb underflows
+        let (a, b) = (self.0, rhs.weak_reduce().0);
+        FieldElement([
+            (a[0] + 0xf_ffff_ffff_ffda) - b[0],
+            (a[1] + 0xf_ffff_ffff_fffe) - b[1],
+            (a[2] + 0xf_ffff_ffff_fffe) - b[2],
+            (a[3] + 0xf_ffff_ffff_fffe) - b[3],
+            (a[4] + 0xf_ffff_ffff_fffe) - b[4],
+        ])
+        .weak_reduce()
+    }
+
+    pub fn neg(&self) -> Self {
+        FieldElement::ZERO.sub(self)
+    }
+
+    pub fn mul(&self, rhs: &Self) -> Self {
+        let (a, b) = (self.0, rhs.0);
+        let m = |x: u64, y: u64| (x as u128) * (y as u128);
+
+        let b1_19 = b[1] * 19;
+        let b2_19 = b[2] * 19;
+        let b3_19 = b[3] * 19;
+        let b4_19 = b[4] * 19;
+
+        let c0 = m(a[0], b[0]) + m(a[4], b1_19) + m(a[3], b2_19) + m(a[2], b3_19) + m(a[1], b4_19);
+        let mut c1 = m(a[1], b[0]) + m(a[0], b[1]) + m(a[4], b2_19) + m(a[3], b3_19) + m(a[2], b4_19);
+        let mut c2 = m(a[2], b[0]) + m(a[1], b[1]) + m(a[0], b[2]) + m(a[4], b3_19) + m(a[3], b4_19);
+        let mut c3 = m(a[3], b[0]) + m(a[2], b[1]) + m(a[1], b[2]) + m(a[0], b[3]) + m(a[4], b4_19);
+        let mut c4 = m(a[4], b[0]) + m(a[3], b[1]) + m(a[2], b[2]) + m(a[1], b[3]) + m(a[0], b[4]);
+
+        c1 += c0 >> 51;
+        c2 += c1 >> 51;
+        c3 += c2 >> 51;
+        c4 += c3 >> 51;
+        let carry = (c4 >> 51) as u64;
+
+        let mut l = [
+            (c0 as u64) & MASK,
+            (c1 as u64) & MASK,
+            (c2 as u64) & MASK,
+            (c3 as u64) & MASK,
+            (c4 as u64) & MASK,
+        ];
+        l[0] += carry * 19;
+        l[1] += l[0] >> 51;
+        l[0] &= MASK;
+        FieldElement(l)
+    }
+
+    pub fn square(&self) -> Self {
+        self.mul(self)
+    }
+
+    /// Raise to a power given as a little-endian exponent
+    fn pow(&self, exp: &[u8; 32]) -> Self {
+        let mut result = FieldElement::ONE;
+        for byte in exp.iter().rev() {
+            for bit in (0..8).rev() {
+                result = result.square();
+                if (byte >> bit) & 1 == 1 {
+                    result = result.mul(self);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn invert(&self) -> Self {
+        self.pow(&P_MINUS_2)
+    }
+
+    pub fn pow_p58(&self) -> Self {
+        self.pow(&P_MINUS_5_DIV_8)
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.to_bytes() == [0u8; 32]
+    }
+}
+
+impl PartialEq for FieldElement {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+}
+
+impl Eq for FieldElement {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inverse() {
+        let mut bytes = [0u8; 32];
+        bytes[0] = 7;
+        bytes[17] = 0x42;
+        let x = FieldElement::from_bytes(&bytes);
+        assert_eq!(x.mul(&x.invert()), FieldElement::ONE);
+    }
+
+    #[test]
+    fn test_bytes_round_trip_reduces() {
+        // p itself encodes to zero
+        let mut p = [0xff; 32];
+        p[0] = 0xed;
+        p[31] = 0x7f;
+        assert!(FieldElement::from_bytes(&p).is_zero());
+
+        let one = FieldElement::ONE.to_bytes();
+        assert_eq!(FieldElement::from_bytes(&one), FieldElement::ONE);
+    }
+}
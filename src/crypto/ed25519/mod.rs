@@ -0,0 +1,449 @@
+//! Ed25519 signatures (RFC 8032) over SHA-512.
+//!
+//! The arithmetic favours clarity over speed and is **not constant time**:
+//! signing branches on the bits of the secret scalar. Verification handles
+//! public data only and is always compiled; [`SigningKey`] needs the `crypto`
+//! feature, and should sign only where its timing cannot be observed.
+//!
+//! Public keys and signatures print, parse and serialize as lowercase hex.
+
+mod field;
+mod point;
+mod scalar;
+pub mod vrf;
+
+use std::fmt;
+#[cfg(any(test, feature = "crypto"))]
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use sha2::{Digest, Sha512};
+#[cfg(any(test, feature = "crypto"))]
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+pub use super::SignatureError;
+use super::unhex;
+use point::EdwardsPoint;
+use scalar::Scalar;
+
+/// Length of a secret key seed in bytes
+pub const SECRET_KEY_LENGTH: usize = 32;
+/// Length of a compressed public key in bytes
+pub const PUBLIC_KEY_LENGTH: usize = 32;
+/// Length of a signature in bytes
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// An ed25519 secret key together with its expanded form and public key.
+///
+/// The secret is wiped when the key is dropped. Keys are not `Clone`, so
+/// that each secret lives in one place; [`SigningKey::to_bytes`] is the way
+/// to copy one out.
+#[cfg(any(test, feature = "crypto"))]
+pub struct SigningKey {
+    seed: [u8; SECRET_KEY_LENGTH],
+    scalar: Scalar,
+    prefix: [u8; 32],
+    verifying_key: VerifyingKey,
+}
+
+#[cfg(any(test, feature = "crypto"))]
+impl SigningKey {
+    /// Derive a signing key from a 32-byte seed
+    pub fn from_bytes(seed: &[u8; SECRET_KEY_LENGTH]) -> Self {
+        let mut digest: [u8; 64] = Sha512::digest(seed).into();
+
+        let mut scalar_bytes = Zeroizing::new([0u8; 32]);
+        scalar_bytes.copy_from_slice(&digest[..32]);
+        scalar_bytes[0] &= 248;
+        scalar_bytes[31] &= 127;
+        scalar_bytes[31] |= 64;
+
+        let mut prefix = [0u8; 32];
+        prefix.copy_from_slice(&digest[32..]);
+
+        let point = EdwardsPoint::basepoint().mul(&scalar_bytes);
+        digest.zeroize();
+        let verifying_key = VerifyingKey {
+            bytes: point.compress(),
+            point,
+        };
+
+        SigningKey {
+            seed: *seed,
+            scalar: Scalar::from_bits(&scalar_bytes),
+            prefix,
+            verifying_key,
+        }
+    }
+
+    /// A fresh key from a seed read off `rng`, a source of secure random
+    /// bytes such as `/dev/urandom`
+    pub fn generate(mut rng: impl Read) -> io::Result<Self> {
+        let mut seed = Zeroizing::new([0u8; SECRET_KEY_LENGTH]);
+        rng.read_exact(seed.as_mut())?;
+        Ok(SigningKey::from_bytes(&seed))
+    }
+
+    /// The seed this key was derived from, wiped when dropped
+    pub fn to_bytes(&self) -> Zeroizing<[u8; SECRET_KEY_LENGTH]> {
+        Zeroizing::new(self.seed)
+    }
+
+    /// The public half of this key
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.verifying_key
+    }
+
+    /// Produce a deterministic signature over `message`
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        let mut r = Scalar::from_bytes_mod_order_wide(&Zeroizing::new(sha512(&[&self.prefix, message])));
+        let big_r = EdwardsPoint::basepoint().mul(&r.to_bytes()).compress();
+
+        let k = Scalar::from_bytes_mod_order_wide(&sha512(&[
+            &big_r,
+            &self.verifying_key.bytes,
+            message,
+        ]));
+        let s = k.mul_add(&self.scalar, &r);
+        r.zeroize();
+
+        let mut bytes = [0u8; SIGNATURE_LENGTH];
+        bytes[..32].copy_from_slice(&big_r);
+        bytes[32..].copy_from_slice(&s.to_bytes());
+        Signature(bytes)
+    }
+}
+
+#[cfg(any(test, feature = "crypto"))]
+impl Drop for SigningKey {
+    fn drop(&mut self) {
+        self.seed.zeroize();
+        self.scalar.zeroize();
+        self.prefix.zeroize();
+    }
+}
+
+#[cfg(any(test, feature = "crypto"))]
+impl ZeroizeOnDrop for SigningKey {}
+
+#[cfg(any(test, feature = "crypto"))]
+impl fmt::Debug for SigningKey {
+    /// The public half only
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey").field("verifying_key", &self.verifying_key).finish_non_exhaustive()
+    }
+}
+
+/// An ed25519 public key
+#[derive(Clone, Copy)]
+pub struct VerifyingKey {
+    bytes: [u8; PUBLIC_KEY_LENGTH],
+    point: EdwardsPoint,
+}
+
+impl VerifyingKey {
+    /// Decode a compressed public key
+    pub fn from_bytes(bytes: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Self, SignatureError> {
+        let point = EdwardsPoint::decompress(bytes).ok_or(SignatureError::InvalidPublicKey)?;
+        Ok(VerifyingKey {
+            bytes: *bytes,
+            point,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
+        &self.bytes
+    }
+
+    pub fn to_bytes(&self) -> [u8; PUBLIC_KEY_LENGTH] {
+        self.bytes
+    }
+
+    /// Check `signature` over `message`, rejecting non-canonical `S` values
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        let mut r_bytes = [0u8; 32];
+        r_bytes.copy_from_slice(&signature.0[..32]);
+        let mut s_bytes = [0u8; 32];
+        s_bytes.copy_from_slice(&signature.0[32..]);
+
+        let s = Scalar::from_canonical_bytes(&s_bytes).ok_or(SignatureError::InvalidSignature)?;
+        let k = Scalar::from_bytes_mod_order_wide(&sha512(&[&r_bytes, &self.bytes, message]));
+
+        // R' = [s]B - [k]A must equal the R committed in the signature
+        let sb = EdwardsPoint::basepoint().mul(&s.to_bytes());
+        let ka = self.point.mul(&k.to_bytes());
+        if sb.add(&ka.neg()).compress() == r_bytes {
+            Ok(())
+        } else {
+            Err(SignatureError::InvalidSignature)
+        }
+    }
+}
+
+/// Check many signatures at once, each `(message, signature, key)`, faster
+/// than checking them one by one. An empty batch passes.
+///
+/// The check is the cofactored one RFC 8032 allows: with coefficients `z`
+/// derived by hashing the whole batch, `[8](Σ z(R + kA) - (Σ zS)B)` must be
+/// the identity. It can accept a signature [`VerifyingKey::verify`] rejects,
+/// but only one whose `R` or key has a small-order component, which honest
+/// signers never produce. Callers that must agree with each other should
+/// use this check throughout, checking a lone signature as a batch of one.
+/// The error does not say which signature failed.
+pub fn verify_batch(items: &[(&[u8], &Signature, &VerifyingKey)]) -> Result<(), SignatureError> {
+    // Coefficients the signers cannot predict, since they depend on every signature
+    let mut transcript = Sha512::new();
+    for (message, signature, key) in items {
+        transcript.update(signature.0);
+        transcript.update(key.bytes);
+        transcript.update(sha512(&[message]));
+    }
+    let seed = transcript.finalize();
+
+    let mut s_sum = Scalar::from_bits(&[0; 32]);
+    let mut terms = Vec::with_capacity(2 * items.len() + 1);
+    for (i, (message, signature, key)) in items.iter().enumerate() {
+        let mut r_bytes = [0u8; 32];
+        r_bytes.copy_from_slice(&signature.0[..32]);
+        let mut s_bytes = [0u8; 32];
+        s_bytes.copy_from_slice(&signature.0[32..]);
+        let s = Scalar::from_canonical_bytes(&s_bytes).ok_or(SignatureError::InvalidSignature)?;
+        let r = EdwardsPoint::decompress(&r_bytes)
+            .filter(|r| r.compress() == r_bytes)
+            .ok_or(SignatureError::InvalidSignature)?;
+        let k = Scalar::from_bytes_mod_order_wide(&sha512(&[&r_bytes, &key.bytes, message]));
+
+        let mut z_bytes = [0u8; 32];
+        z_bytes[..16].copy_from_slice(&sha512(&[&seed, &(i as u64).to_le_bytes()])[..16]);
+        z_bytes[0] |= 1;
+        let z = Scalar::from_bits(&z_bytes);
+
+        s_sum = z.mul_add(&s, &s_sum);
+        terms.push((z.to_bytes(), r));
+        terms.push((z.mul_add(&k, &Scalar::from_bits(&[0; 32])).to_bytes(), key.point));
+    }
+    terms.push((s_sum.to_bytes(), EdwardsPoint::basepoint().neg()));
+
+    let mut check = EdwardsPoint::multiscalar_mul(&terms);
+    for _ in 0..3 {
+        check = check.add(&check);
+    }
+    if check.compress() == EdwardsPoint::identity().compress() {
+        Ok(())
+    } else {
+        Err(SignatureError::InvalidSignature)
+    }
+}
+
+impl PartialEq for VerifyingKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for VerifyingKey {}
+
+impl std::hash::Hash for VerifyingKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state);
+    }
+}
+
+impl fmt::Debug for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VerifyingKey({})", self)
+    }
+}
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.bytes))
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        VerifyingKey::from_bytes(&unhex(s)?)
+    }
+}
+
+/// An ed25519 signature: the encoded point `R` followed by the scalar `S`
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Signature([u8; SIGNATURE_LENGTH]);
+
+impl Signature {
+    pub fn from_bytes(bytes: &[u8; SIGNATURE_LENGTH]) -> Self {
+        Signature(*bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; SIGNATURE_LENGTH] {
+        self.0
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Signature({})", self)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+/// Parses the hex of the signature bytes; whether they make a valid
+/// signature is only known on verification
+impl FromStr for Signature {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Signature(unhex(s)?))
+    }
+}
+
+hex_serde!(VerifyingKey);
+hex_serde!(Signature);
+
+
+fn sha512(parts: &[&[u8]]) -> [u8; 64] {
+    let mut hasher = Sha512::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut out = [0u8; 64];
+    out.copy_from_slice(&hasher.finalize());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex<const N: usize>(s: &str) -> [u8; N] {
+        hex::decode(s).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn test_rfc8032_vectors() {
+        // RFC 8032 section 7.1, tests 1 and 2
+        let cases = [
+            (
+                "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+                "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+                "",
+                "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+            ),
+            (
+                "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+                "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+                "72",
+                "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+            ),
+        ];
+
+        for (secret, public, message, signature) in cases {
+            let key = SigningKey::from_bytes(&unhex(secret));
+            let message = hex::decode(message).unwrap();
+            assert_eq!(key.verifying_key().to_bytes(), unhex::<32>(public));
+
+            let sig = key.sign(&message);
+            assert_eq!(sig.to_bytes(), unhex::<64>(signature));
+            assert!(key.verifying_key().verify(&message, &sig).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_batch_agrees_with_single_checks() {
+        let keys: Vec<SigningKey> = (1..=4u8).map(|seed| SigningKey::from_bytes(&[seed; 32])).collect();
+        let messages: Vec<Vec<u8>> = (0..6u8).map(|i| vec![i; i as usize]).collect();
+        let mut signed: Vec<(Vec<u8>, Signature, VerifyingKey)> = messages
+            .iter()
+            .enumerate()
+            .map(|(i, message)| {
+                let key = &keys[i % keys.len()];
+                (message.clone(), key.sign(message), key.verifying_key())
+            })
+            .collect();
+        let batch = |signed: &[(Vec<u8>, Signature, VerifyingKey)]| {
+            let items: Vec<(&[u8], &Signature, &VerifyingKey)> =
+                signed.iter().map(|(m, s, k)| (m.as_slice(), s, k)).collect();
+            verify_batch(&items)
+        };
+        assert_eq!(batch(&signed), Ok(()));
+        assert_eq!(batch(&[]), Ok(()));
+
+        // Any one bad signature, message or key fails the batch
+        let mut tampered = signed.clone();
+        tampered[3].0.push(0);
+        assert_eq!(batch(&tampered), Err(SignatureError::InvalidSignature));
+        let mut tampered = signed.clone();
+        tampered[5].2 = keys[0].verifying_key();
+        assert_eq!(batch(&tampered), Err(SignatureError::InvalidSignature));
+        let mut bytes = signed[2].1.to_bytes();
+        bytes[40] ^= 1;
+        signed[2].1 = Signature::from_bytes(&bytes);
+        assert!(signed[2].2.verify(&signed[2].0, &signed[2].1).is_err());
+        assert_eq!(batch(&signed), Err(SignatureError::InvalidSignature));
+        assert_eq!(batch(&signed[2..3]), Err(SignatureError::InvalidSignature));
+        assert_eq!(batch(&signed[3..]), Ok(()));
+    }
+
+    #[test]
+    fn test_generated_keys_and_hex_encodings() {
+        use serde::de::value::{Error, StrDeserializer};
+        use serde::Deserialize;
+
+        // A fixed byte source gives the same key as its seed
+        let seed = [5u8; 32];
+        let key = SigningKey::generate(&seed[..]).unwrap();
+        assert_eq!(*key.to_bytes(), seed);
+        assert_eq!(
+            key.verifying_key(),
+            SigningKey::from_bytes(&seed).verifying_key()
+        );
+        assert!(SigningKey::generate(&seed[..31]).is_err());
+
+        let public = key.verifying_key();
+        let sig = key.sign(b"message");
+        assert_eq!(public.to_string(), hex::encode(public.as_bytes()));
+        assert_eq!(public.to_string().parse(), Ok(public));
+        assert_eq!(sig.to_string().parse(), Ok(sig));
+        assert_eq!(
+            "abcd".parse::<VerifyingKey>(),
+            Err(SignatureError::InvalidHex)
+        );
+        assert_eq!(
+            "zz".repeat(64).parse::<Signature>(),
+            Err(SignatureError::InvalidHex)
+        );
+
+        let text = public.to_string();
+        let decoded = VerifyingKey::deserialize(StrDeserializer::<Error>::new(&text));
+        assert_eq!(decoded, Ok(public));
+        let text = sig.to_string();
+        let decoded = Signature::deserialize(StrDeserializer::<Error>::new(&text));
+        assert_eq!(decoded, Ok(sig));
+        assert!(public.verify(b"message", &decoded.unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_tampering() {
+        let key = SigningKey::from_bytes(&[7u8; 32]);
+        let other = SigningKey::from_bytes(&[8u8; 32]);
+        let sig = key.sign(b"message");
+
+        assert!(key.verifying_key().verify(b"massage", &sig).is_err());
+        assert!(other.verifying_key().verify(b"message", &sig).is_err());
+
+        let mut bytes = sig.to_bytes();
+        bytes[40] ^= 1;
+        assert!(key
+            .verifying_key()
+            .verify(b"message", &Signature::from_bytes(&bytes))
+            .is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use super::field::FieldElement;
+
+/// The curve constant d = -121665/121666
+const D_BYTES: [u8; 32] = [
+    0xa3, 0x78, 0x59, 0x13, 0xca, 0x4d, 0xeb, 0x75, 0xab, 0xd8, 0x41, 0x41, 0x4d, 0x0a, 0x70, 0x00,
+    0x98, 0xe8, 0x79, 0x77, 0x79, 0x40, 0xc7, 0x8c, 0x73, 0xfe, 0x6f, 0x2b, 0xee, 0x6c, 0x03, 0x52,
+];
+
+/// 2 * d, used by the addition formula
+const D2_BYTES: [u8; 32] = [
+    0x59, 0xf1, 0xb2, 0x26, 0x94, 0x9b, 0xd6, 0xeb, 0x56, 0xb1, 0x83, 0x82, 0x9a, 0x14, 0xe0, 0x00,
+    0x30, 0xd1, 0xf3, 0xee, 0xf2, 0x80, 0x8e, 0x19, 0xe7, 0xfc, 0xdf, 0x56, 0xdc, 0xd9, 0x06, 0x24,
+];
+
+/// A square root of -1 in the field
+const SQRT_M1_BYTES: [u8; 32] = [
+    0xb0, 0xa0, 0x0e, 0x4a, 0x27, 0x1b, 0xee, 0xc4, 0x78, 0xe4, 0x2f, 0xad, 0x06, 0x18, 0x43, 0x2f,
+    0xa7, 0xd7, 0xfb, 0x3d, 0x99, 0x00, 0x4d, 0x2b, 0x0b, 0xdf, 0xc1, 0x4f, 0x80, 0x24, 0x83, 0x2b,
+];
+
+/// The compressed standard base point (y = 4/5, x even)
+const BASEPOINT_BYTES: [u8; 32] = [
+    0x58, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+    0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66, 0x66,
+];
+
+/// A point on the twisted Edwards curve in extended coordinates (X:Y:Z:T), x = X/Z, y = Y/Z, xy = T/Z
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct EdwardsPoint {
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement,
+    t: FieldElement,
+}
+
+impl EdwardsPoint {
+    pub fn identity() -> Self {
+        EdwardsPoint {
+            x: FieldElement::ZERO,
+            y: FieldElement::ONE,
+            z: FieldElement::ONE,
+            t: FieldElement::ZERO,
+        }
+    }
+
+    #[allow(clippy::expect_used)]
+    pub fn basepoint() -> Self {
+        Self::decompress(&BASEPOINT_BYTES).expect("base point is a valid encoding")
+    }
+
+    /// Decode a compressed point, rejecting non-canonical y and points off the curve
+    pub fn decompress(bytes: &[u8; 32]) -> Option<Self> {
+        let sign = bytes[31] >> 7;
+        let mut y_bytes = *bytes;
+        y_bytes[31] &= 0x7f;
+
+        let y = FieldElement::from_bytes(&y_bytes);
+        if y.to_bytes() != y_bytes {
+            return None;
+        }
+
+        let d = FieldElement::from_bytes(&D_BYTES);
+        let y2 = y.square();
+        let u = y2.sub(&FieldElement::ONE);
+        let v = d.mul(&y2).add(&FieldElement::ONE);
+
+        // x = u v^3 (u v^7)^((p - 5) / 8)
+        let v3 = v.square().mul(&v);
+        let v7 = v3.square().mul(&v);
+        let mut x = u.mul(&v3).mul(&u.mul(&v7).pow_p58());
+
+        let vx2 = v.mul(&x.square());
+        if vx2 != u {
+            if vx2 == u.neg() {
+                x = x.mul(&FieldElement::from_bytes(&SQRT_M1_BYTES));
+            } else {
+                return None;
+            }
+        }
+
+        if x.is_zero() && sign == 1 {
+            return None;
+        }
+        if x.is_negative() != (sign == 1) {
+            x = x.neg();
+        }
+
+        Some(EdwardsPoint {
+            x,
+            y,
+            z: FieldElement::ONE,
+            t: x.mul(&y),
+        })
+    }
+
+    pub fn compress(&self) -> [u8; 32] {
+        let z_inv = self.z.invert();
+        let x = self.x.mul(&z_inv);
+        let y = self.y.mul(&z_inv);
+        let mut bytes = y.to_bytes();
+        bytes[31] ^= (x.is_negative() as u8) << 7;
+        bytes
+    }
+
+    /// Unified addition (add-2008-hwcd-3), also valid for doubling
+    pub fn add(&self, other: &Self) -> Self {
+        let d2 = FieldElement::from_bytes(&D2_BYTES);
+        let a = self.y.sub(&self.x).mul(&other.y.sub(&other.x));
+        let b = self.y.add(&self.x).mul(&other.y.add(&other.x));
+        let c = self.t.mul(&d2).mul(&other.t);
+        let d = self.z.add(&self.z).mul(&other.z);
+        let e = b.sub(&a);
+        let f = d.sub(&c);
+        let g = d.add(&c);
+        let h = b.add(&a);
+
+        EdwardsPoint {
+            x: e.mul(&f),
+            y: g.mul(&h),
+            z: f.mul(&g),
+            t: e.mul(&h),
+        }
+    }
+
+    pub fn neg(&self) -> Self {
+        EdwardsPoint {
+            x: self.x.neg(),
+            y: self.y,
+            z: self.z,
+            t: self.t.neg(),
+        }
+    }
+
+    /// Multiply by a little-endian 256-bit scalar using double-and-add
+    pub fn mul(&self, scalar: &[u8; 32]) -> Self {
+        let mut result = Self::identity();
+        for byte in scalar.iter().rev() {
+            for bit in (0..8).rev() {
+                result = result.add(&result);
+                if (byte >> bit) & 1 == 1 {
+                    result = result.add(self);
+                }
+            }
+        }
+        result
+    }
+
+    /// Sum of each point multiplied by its little-endian 256-bit scalar,
+    /// sharing the doublings between all of them
+    pub fn multiscalar_mul(terms: &[([u8; 32], EdwardsPoint)]) -> Self {
+        let mut result = Self::identity();
+        for i in (0..256).rev() {
+            result = result.add(&result);
+            for (scalar, point) in terms {
+                if (scalar[i / 8] >> (i % 8)) & 1 == 1 {
+                    result = result.add(point);
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_basepoint_round_trip() {
+        let b = EdwardsPoint::basepoint();
+        assert_eq!(b.compress(), BASEPOINT_BYTES);
+        assert_eq!(b.add(&b).compress(), b.mul(&{
+            let mut two = [0u8; 32];
+            two[0] = 2;
+            two
+        }).compress());
+    }
+
+    #[test]
+    fn test_multiscalar_matches_separate_products() {
+        let b = EdwardsPoint::basepoint();
+        let p = b.mul(&[5; 32]);
+        let (x, y) = ([0x11; 32], [0xf3; 32]);
+        let expected = b.mul(&x).add(&p.mul(&y));
+        assert_eq!(EdwardsPoint::multiscalar_mul(&[(x, b), (y, p)]).compress(), expected.compress());
+        assert_eq!(EdwardsPoint::multiscalar_mul(&[]).compress(), EdwardsPoint::identity().compress());
+    }
+
+    #[test]
+    fn test_identity() {
+        let b = EdwardsPoint::basepoint();
+        let identity = EdwardsPoint::identity().compress();
+        assert_eq!(b.add(&b.neg()).compress(), identity);
+        assert_ne!(b.compress(), identity);
+    }
+}
@@ -0,0 +1,147 @@
+use zeroize::Zeroize;
+
+/// The order of the ed25519 base point, 2^252 + 27742317777372353535851937790883648493
+const L: [u64; 4] = [
+    0x5812_631a_5cf5_d3ed,
+    0x14de_f9de_a2f7_9cd6,
+    0x0000_0000_0000_0000,
+    0x1000_0000_0000_0000,
+];
+
+/// An integer modulo `L`, held as four little-endian 64-bit limbs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Scalar([u64; 4]);
+
+impl Zeroize for Scalar {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl Scalar {
+    /// Reduce a 512-bit little-endian integer (e.g. a SHA-512 digest) modulo `L`
+    pub fn from_bytes_mod_order_wide(bytes: &[u8; 64]) -> Self {
+        let mut wide = [0u64; 8];
+        for (i, limb) in wide.iter_mut().enumerate() {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            *limb = u64::from_le_bytes(word);
+        }
+        Scalar(reduce_wide(&wide))
+    }
+
+    /// Interpret 32 little-endian bytes as an integer without reducing it
+    pub fn from_bits(bytes: &[u8; 32]) -> Self {
+        let mut limbs = [0u64; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[i * 8..i * 8 + 8]);
+            *limb = u64::from_le_bytes(word);
+        }
+        Scalar(limbs)
+    }
+
+    /// Decode a scalar, rejecting encodings that are not fully reduced
+    pub fn from_canonical_bytes(bytes: &[u8; 32]) -> Option<Self> {
+        let s = Self::from_bits(bytes);
+        if less_than(&s.0, &L) {
+            Some(s)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; 32] {
+        let mut out = [0u8; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            out[i * 8..i * 8 + 8].copy_from_slice(&limb.to_le_bytes());
+        }
+        out
+    }
+
+    /// Compute `self * b + c (mod L)`
+    pub fn mul_add(&self, b: &Scalar, c: &Scalar) -> Scalar {
+        let mut wide = [0u64; 8];
+        for i in 0..4 {
+            let mut carry: u128 = 0;
+            for j in 0..4 {
+                let t = (self.0[i] as u128) * (b.0[j] as u128) + wide[i + j] as u128 + carry;
+                wide[i + j] = t as u64;
+                carry = t >> 64;
+            }
+            wide[i + 4] = carry as u64;
+        }
+
+        let mut carry: u128 = 0;
+        for (i, limb) in wide.iter_mut().enumerate() {
+            let addend = if i < 4 { c.0[i] as u128 } else { 0 };
+            let t = *limb as u128 + addend + carry;
+            *limb = t as u64;
+            carry = t >> 64;
+        }
+
+        Scalar(reduce_wide(&wide))
+    }
+}
+
+fn less_than(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if a[i] != b[i] {
+            return a[i] < b[i];
+        }
+    }
+    false
+}
+
+fn sub_assign(a: &mut [u64; 4], b: &[u64; 4]) {
+    let mut borrow = 0u64;
+    for i in 0..4 {
+        let (d1, b1) = a[i].overflowing_sub(b[i]);
+        let (d2, b2) = d1.overflowing_sub(borrow);
+        a[i] = d2;
+        borrow = (b1 || b2) as u64;
+    }
+}
+
+/// Binary long division of a 512-bit value by `L`, keeping the remainder
+fn reduce_wide(wide: &[u64; 8]) -> [u64; 4] {
+    let mut r = [0u64; 4];
+    for bit in (0..512).rev() {
+        // r < L < 2^253, so shifting left by one cannot overflow 256 bits
+        r[3] = (r[3] << 1) | (r[2] >> 63);
+        r[2] = (r[2] << 1) | (r[1] >> 63);
+        r[1] = (r[1] << 1) | (r[0] >> 63);
+        r[0] = (r[0] << 1) | ((wide[bit / 64] >> (bit % 64)) & 1);
+        if !less_than(&r, &L) {
+            sub_assign(&mut r, &L);
+        }
+    }
+    r
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_reduces_to_zero() {
+        let mut wide = [0u8; 64];
+        wide[..32].copy_from_slice(&Scalar(L).to_bytes());
+        assert_eq!(Scalar::from_bytes_mod_order_wide(&wide), Scalar([0; 4]));
+        assert!(Scalar::from_canonical_bytes(&Scalar(L).to_bytes()).is_none());
+    }
+
+    #[test]
+    fn test_mul_add() {
+        let two = Scalar([2, 0, 0, 0]);
+        let three = Scalar([3, 0, 0, 0]);
+        let one = Scalar([1, 0, 0, 0]);
+        assert_eq!(two.mul_add(&three, &one), Scalar([7, 0, 0, 0]));
+
+        // (L - 1) * (L - 1) + 0 == 1 (mod L)
+        let mut l_minus_one = L;
+        l_minus_one[0] -= 1;
+        let m = Scalar(l_minus_one);
+        assert_eq!(m.mul_add(&m, &Scalar([0; 4])), one);
+    }
+}
@@ -0,0 +1,305 @@
+//! A verifiable random function over ed25519 keys: ECVRF-EDWARDS25519-SHA512-TAI
+//! (RFC 9381).
+//!
+//! The holder of a [`SigningKey`] maps any input to a pseudorandom
+//! [`VrfOutput`] together with a [`VrfProof`] that anyone with the
+//! [`VerifyingKey`] can check. The output is unique: no key can prove two
+//! different outputs for the same input, and nobody without the key can
+//! predict it. That makes it suited to private leader election, where each
+//! staker evaluates the VRF on the slot and [`is_leader`] decides from the
+//! output whether they may propose.
+//!
+//! Keys are the same as for signatures, so a key that signs can also prove.
+//! Proving, like signing, is not constant time and needs the `crypto`
+//! feature; verifying does not.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::point::EdwardsPoint;
+use super::scalar::Scalar;
+#[cfg(any(test, feature = "crypto"))]
+use super::SigningKey;
+use super::{sha512, unhex, SignatureError, VerifyingKey};
+
+/// The suite string of ECVRF-EDWARDS25519-SHA512-TAI
+const SUITE: u8 = 0x03;
+/// Length of the challenge in a proof, half a scalar
+const CHALLENGE_LENGTH: usize = 16;
+/// Length of a proof: Gamma, the challenge and the response
+pub const PROOF_LENGTH: usize = 32 + CHALLENGE_LENGTH + 32;
+/// Length of an output
+pub const OUTPUT_LENGTH: usize = 64;
+
+/// The pseudorandom output of the VRF for one key and input
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VrfOutput([u8; OUTPUT_LENGTH]);
+
+/// Proof that a [`VrfOutput`] is the one a key gives an input
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VrfProof([u8; PROOF_LENGTH]);
+
+impl VrfOutput {
+    pub fn from_bytes(bytes: &[u8; OUTPUT_LENGTH]) -> Self {
+        VrfOutput(*bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; OUTPUT_LENGTH] {
+        self.0
+    }
+}
+
+impl VrfProof {
+    /// Interpret `bytes` as a proof; whether they make a valid one is only
+    /// known on verification
+    pub fn from_bytes(bytes: &[u8; PROOF_LENGTH]) -> Self {
+        VrfProof(*bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; PROOF_LENGTH] {
+        self.0
+    }
+}
+
+#[cfg(any(test, feature = "crypto"))]
+impl SigningKey {
+    /// The VRF output for `input` under this key, and the proof of it
+    pub fn vrf_prove(&self, input: &[u8]) -> (VrfOutput, VrfProof) {
+        let h = encode_to_curve(&self.verifying_key.bytes, input);
+        let h_bytes = h.compress();
+        let gamma = h.mul(&self.scalar.to_bytes());
+
+        // The nonce is derived as for signatures, from the secret prefix
+        let k = Scalar::from_bytes_mod_order_wide(&sha512(&[&self.prefix, &h_bytes]));
+        let u = EdwardsPoint::basepoint().mul(&k.to_bytes());
+        let v = h.mul(&k.to_bytes());
+        let c = challenge(&[&self.verifying_key.bytes, &h_bytes, &gamma.compress(), &u.compress(), &v.compress()]);
+        let s = challenge_scalar(&c).mul_add(&self.scalar, &k);
+
+        let mut proof = [0u8; PROOF_LENGTH];
+        proof[..32].copy_from_slice(&gamma.compress());
+        proof[32..32 + CHALLENGE_LENGTH].copy_from_slice(&c);
+        proof[32 + CHALLENGE_LENGTH..].copy_from_slice(&s.to_bytes());
+        (proof_to_hash(&gamma), VrfProof(proof))
+    }
+}
+
+impl VerifyingKey {
+    /// Check that `proof` shows `output` to be this key's VRF output for
+    /// `input`. Keys of small order, which could prove many outputs, never
+    /// verify.
+    pub fn vrf_verify(&self, input: &[u8], output: &VrfOutput, proof: &VrfProof) -> bool {
+        if mul_by_cofactor(&self.point).compress() == EdwardsPoint::identity().compress() {
+            return false;
+        }
+        let mut gamma_bytes = [0u8; 32];
+        gamma_bytes.copy_from_slice(&proof.0[..32]);
+        let Some(gamma) = EdwardsPoint::decompress(&gamma_bytes) else {
+            return false;
+        };
+        let mut c = [0u8; CHALLENGE_LENGTH];
+        c.copy_from_slice(&proof.0[32..32 + CHALLENGE_LENGTH]);
+        let mut s_bytes = [0u8; 32];
+        s_bytes.copy_from_slice(&proof.0[32 + CHALLENGE_LENGTH..]);
+        let Some(s) = Scalar::from_canonical_bytes(&s_bytes) else {
+            return false;
+        };
+
+        // U = [s]B - [c]Y and V = [s]H - [c]Gamma recover the prover's [k]B and [k]H
+        let h = encode_to_curve(&self.bytes, input);
+        let c_bytes = challenge_scalar(&c).to_bytes();
+        let u = EdwardsPoint::basepoint().mul(&s.to_bytes()).add(&self.point.mul(&c_bytes).neg());
+        let v = h.mul(&s.to_bytes()).add(&gamma.mul(&c_bytes).neg());
+        let expected = challenge(&[&self.bytes, &h.compress(), &gamma_bytes, &u.compress(), &v.compress()]);
+        expected == c && proof_to_hash(&gamma) == *output
+    }
+}
+
+/// Whether an account holding `stake` of `total_stake` leads the slot whose
+/// VRF output is `output`.
+///
+/// The first 8 bytes of the output, as a big-endian fraction of 2^64, must
+/// fall below `stake / (total_stake * difficulty)`, so each slot has on
+/// average one leader per `difficulty` slots across all stake. The whole
+/// stake at difficulty 1 always leads; no stake, no total stake or a
+/// difficulty of 0 never does.
+pub fn is_leader(output: &VrfOutput, stake: u64, total_stake: u64, difficulty: u64) -> bool {
+    if total_stake == 0 || difficulty == 0 {
+        return false;
+    }
+    let mut head = [0u8; 8];
+    head.copy_from_slice(&output.0[..8]);
+    let value = u64::from_be_bytes(head) as u128;
+    let stake = stake.min(total_stake) as u128;
+    // value / 2^64 < stake / (total * difficulty), kept in integers
+    value * (total_stake as u128 * difficulty as u128) < stake << 64
+}
+
+/// Hash `input` to a point of the prime-order subgroup by try-and-increment,
+/// bound to the key `public_key`
+fn encode_to_curve(public_key: &[u8; 32], input: &[u8]) -> EdwardsPoint {
+    for counter in 0..=u8::MAX {
+        let hash = sha512(&[&[SUITE, 0x01], public_key, input, &[counter, 0x00]]);
+        let mut candidate = [0u8; 32];
+        candidate.copy_from_slice(&hash[..32]);
+        if let Some(point) = EdwardsPoint::decompress(&candidate) {
+            return mul_by_cofactor(&point);
+        }
+    }
+    // Each candidate is a point with probability about 1/2
+    unreachable!("no point among 256 hash candidates")
+}
+
+/// The challenge over the points of a proof, truncated to half a scalar
+fn challenge(points: &[&[u8; 32]; 5]) -> [u8; CHALLENGE_LENGTH] {
+    let mut parts: Vec<&[u8]> = vec![&[SUITE, 0x02]];
+    parts.extend(points.iter().map(|point| &point[..]));
+    parts.push(&[0x00]);
+    let mut c = [0u8; CHALLENGE_LENGTH];
+    c.copy_from_slice(&sha512(&parts)[..CHALLENGE_LENGTH]);
+    c
+}
+
+fn challenge_scalar(c: &[u8; CHALLENGE_LENGTH]) -> Scalar {
+    let mut bytes = [0u8; 32];
+    bytes[..CHALLENGE_LENGTH].copy_from_slice(c);
+    Scalar::from_bits(&bytes)
+}
+
+/// The output a proof's Gamma point commits to
+fn proof_to_hash(gamma: &EdwardsPoint) -> VrfOutput {
+    VrfOutput(sha512(&[&[SUITE, 0x03], &mul_by_cofactor(gamma).compress(), &[0x00]]))
+}
+
+fn mul_by_cofactor(point: &EdwardsPoint) -> EdwardsPoint {
+    let mut result = *point;
+    for _ in 0..3 {
+        result = result.add(&result);
+    }
+    result
+}
+
+impl fmt::Debug for VrfOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VrfOutput({})", self)
+    }
+}
+
+impl fmt::Display for VrfOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for VrfOutput {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(VrfOutput(unhex(s)?))
+    }
+}
+
+impl fmt::Debug for VrfProof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VrfProof({})", self)
+    }
+}
+
+impl fmt::Display for VrfProof {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl FromStr for VrfProof {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(VrfProof(unhex(s)?))
+    }
+}
+
+hex_serde!(VrfOutput);
+hex_serde!(VrfProof);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(secret: &str) -> SigningKey {
+        SigningKey::from_bytes(&unhex(secret).unwrap())
+    }
+
+    #[test]
+    fn test_rfc9381_vectors() {
+        // Appendix B.3 of RFC 9381
+        let signer = key("9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60");
+        let (output, proof) = signer.vrf_prove(b"");
+        assert_eq!(
+            proof.to_string(),
+            "8657106690b5526245a92b003bb079ccd1a92130477671f6fc01ad16f26f723f26f8a57ccaed74ee1b190bed1f479d9727d2d0f9b005a6e456a35d4fb0daab1268a1b0db10836d9826a528ca76567805"
+        );
+        assert_eq!(
+            output.to_string(),
+            "90cf1df3b703cce59e2a35b925d411164068269d7b2d29f3301c03dd757876ff66b71dda49d2de59d03450451af026798e8f81cd2e333de5cdf4f3e140fdd8ae"
+        );
+        assert!(signer.verifying_key().vrf_verify(b"", &output, &proof));
+
+        let signer = key("4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb");
+        let (output, proof) = signer.vrf_prove(&[0x72]);
+        assert_eq!(
+            output.to_string(),
+            "eb4440665d3891d668e7e0fcaf587f1b4bd7fbfe99d0eb2211ccec90496310eb5e33821bc613efb94db5e5b54c70a848a0bef4553a41befc57663b56373a5031"
+        );
+        assert!(signer.verifying_key().vrf_verify(&[0x72], &output, &proof));
+    }
+
+    #[test]
+    fn test_outputs_are_deterministic_and_bound() {
+        let alice = SigningKey::from_bytes(&[1; 32]);
+        let bob = SigningKey::from_bytes(&[2; 32]);
+        let (output, proof) = alice.vrf_prove(b"slot 42");
+        assert_eq!(alice.vrf_prove(b"slot 42"), (output, proof));
+        assert_ne!(alice.vrf_prove(b"slot 43").0, output);
+        assert_ne!(bob.vrf_prove(b"slot 42").0, output);
+
+        let key = alice.verifying_key();
+        assert!(key.vrf_verify(b"slot 42", &output, &proof));
+        assert!(!key.vrf_verify(b"slot 43", &output, &proof));
+        assert!(!bob.verifying_key().vrf_verify(b"slot 42", &output, &proof));
+        let (other_output, other_proof) = alice.vrf_prove(b"slot 43");
+        assert!(!key.vrf_verify(b"slot 42", &other_output, &proof));
+        assert!(!key.vrf_verify(b"slot 42", &output, &other_proof));
+        for i in [0, 40, 79] {
+            let mut bytes = proof.to_bytes();
+            bytes[i] ^= 1;
+            assert!(!key.vrf_verify(b"slot 42", &output, &VrfProof::from_bytes(&bytes)), "byte {}", i);
+        }
+
+        assert_eq!(proof.to_string().parse(), Ok(proof));
+        assert_eq!(output.to_string().parse(), Ok(output));
+    }
+
+    #[test]
+    fn test_leader_threshold() {
+        let output = |head: u64| {
+            let mut bytes = [0xff; OUTPUT_LENGTH];
+            bytes[..8].copy_from_slice(&head.to_be_bytes());
+            VrfOutput::from_bytes(&bytes)
+        };
+        // A quarter of the stake leads below a quarter of the range
+        let quarter = 1u64 << 62;
+        assert!(is_leader(&output(quarter - 1), 25, 100, 1));
+        assert!(!is_leader(&output(quarter), 25, 100, 1));
+        // Doubling the difficulty halves the range
+        assert!(is_leader(&output(quarter / 2 - 1), 25, 100, 2));
+        assert!(!is_leader(&output(quarter / 2), 25, 100, 2));
+
+        assert!(is_leader(&output(u64::MAX), 100, 100, 1));
+        assert!(is_leader(&output(u64::MAX), u64::MAX, u64::MAX, 1));
+        assert!(is_leader(&output(u64::MAX), 200, 100, 1));
+        assert!(!is_leader(&output(0), 0, 100, 1));
+        assert!(!is_leader(&output(0), 100, 0, 1));
+        assert!(!is_leader(&output(0), 100, 100, 0));
+    }
+}
@@ -0,0 +1,619 @@
+//! Hierarchical deterministic keys: a tree of keys derived from one seed.
+//!
+//! secp256k1 keys derive as BIP-32 specifies, so a child's public key can be
+//! derived from its parent's public key alone unless the child is hardened.
+//! ed25519 keys derive as SLIP-0010 specifies, which allows hardened children
+//! only: an ed25519 secret is hashed before use, so no operation on a public
+//! key matches adding to the secret.
+//!
+//! Extended keys carry their depth and child number but, unlike BIP-32, no
+//! parent fingerprint, and they have no `xprv`/`xpub` text form.
+//!
+//! This module needs the `crypto` feature, and secp256k1 trees also the
+//! `secp256k1` feature. Like signing, private derivation is not constant
+//! time; see [`crypto`](super#secret-keys).
+
+use std::fmt;
+use std::str::FromStr;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha512;
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::{ed25519, PublicKey, SecretKey, Signature, SignatureError, SignatureScheme, Signer};
+
+/// The bit marking a hardened child number
+pub const HARDENED: u32 = 1 << 31;
+
+/// Reasons a key cannot be derived
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum DerivationError {
+    /// Seeds must be 16 to 64 bytes long
+    #[error("seed is {0} bytes, not between 16 and 64")]
+    SeedLength(usize),
+    /// Child indices must be below 2^31; the top bit is the hardened flag
+    #[error("child index {0} is not below 2^31")]
+    IndexOutOfRange(u32),
+    /// The child must be hardened: every ed25519 child is, and no hardened
+    /// child derives from a public key
+    #[error("only hardened children can be derived")]
+    HardenedOnly,
+    /// The derivation gave a secret out of range for the curve, as happens
+    /// with probability below 2^-127; BIP-32 moves on to the next index
+    #[error("derived key is out of range")]
+    InvalidKey,
+    /// Keys more than 255 levels below the master are not allowed
+    #[error("keys may be at most 255 levels deep")]
+    DepthExceeded,
+    /// The text is not a derivation path such as `m/44'/0'/0'/0/5`
+    #[error("invalid derivation path {0:?}")]
+    InvalidPath(String),
+    /// Secret keys of the scheme are not compiled in, for want of its feature
+    #[error("secret keys of this scheme need its crate feature")]
+    UnsupportedScheme,
+}
+
+/// The position of a key among its parent's children
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ChildNumber {
+    index: u32,
+    hardened: bool,
+}
+
+impl ChildNumber {
+    /// Child `index` of its parent, which must be below 2^31
+    pub fn new(index: u32, hardened: bool) -> Result<Self, DerivationError> {
+        if index >= HARDENED {
+            return Err(DerivationError::IndexOutOfRange(index));
+        }
+        Ok(ChildNumber { index, hardened })
+    }
+
+    /// The child number [`ChildNumber::to_u32`] gives
+    pub fn from_u32(number: u32) -> Self {
+        ChildNumber {
+            index: number & !HARDENED,
+            hardened: number & HARDENED != 0,
+        }
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn is_hardened(&self) -> bool {
+        self.hardened
+    }
+
+    /// The index with [`HARDENED`] set for a hardened child, as hashed
+    /// into the derivation
+    pub fn to_u32(self) -> u32 {
+        if self.hardened {
+            self.index | HARDENED
+        } else {
+            self.index
+        }
+    }
+}
+
+impl fmt::Display for ChildNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.index, if self.hardened { "'" } else { "" })
+    }
+}
+
+/// A sequence of children to descend through from the master key, written
+/// `m/44'/0'/0'/0/5` with `'`, `h` or `H` marking hardened children
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct DerivationPath(Vec<ChildNumber>);
+
+impl DerivationPath {
+    pub fn children(&self) -> &[ChildNumber] {
+        &self.0
+    }
+}
+
+impl From<Vec<ChildNumber>> for DerivationPath {
+    fn from(children: Vec<ChildNumber>) -> Self {
+        DerivationPath(children)
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = DerivationError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || DerivationError::InvalidPath(s.to_string());
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid());
+        }
+        parts
+            .map(|part| {
+                let (digits, hardened) = match part.strip_suffix(['\'', 'h', 'H']) {
+                    Some(digits) => (digits, true),
+                    None => (part, false),
+                };
+                // Digits only, so no sign and no leading zeros
+                if digits.is_empty()
+                    || !digits.bytes().all(|c| c.is_ascii_digit())
+                    || (digits.len() > 1 && digits.starts_with('0'))
+                {
+                    return Err(invalid());
+                }
+                let index = digits.parse().map_err(|_| invalid())?;
+                ChildNumber::new(index, hardened).map_err(|_| invalid())
+            })
+            .collect::<Result<_, _>>()
+            .map(DerivationPath)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for child in &self.0 {
+            write!(f, "/{}", child)?;
+        }
+        Ok(())
+    }
+}
+
+/// A secret key in a derivation tree, with the chain code its children
+/// derive from. The secret and chain code are wiped when dropped.
+pub struct ExtendedPrivKey {
+    key: SecretKey,
+    chain_code: [u8; 32],
+    depth: u8,
+    child_number: ChildNumber,
+}
+
+impl ExtendedPrivKey {
+    /// The master key of the tree grown from `seed`, 16 to 64 bytes of
+    /// secure randomness
+    pub fn from_seed(scheme: SignatureScheme, seed: &[u8]) -> Result<Self, DerivationError> {
+        if !(16..=64).contains(&seed.len()) {
+            return Err(DerivationError::SeedLength(seed.len()));
+        }
+        let curve: &[u8] = match scheme {
+            SignatureScheme::Ed25519 => b"ed25519 seed",
+            SignatureScheme::Secp256k1 => b"Bitcoin seed",
+        };
+        let (secret, chain_code) = split(hmac_sha512(curve, &[seed]));
+        let key = SecretKey::from_bytes(scheme, &secret).map_err(|error| match error {
+            SignatureError::UnsupportedScheme => DerivationError::UnsupportedScheme,
+            _ => DerivationError::InvalidKey,
+        })?;
+        Ok(ExtendedPrivKey {
+            key,
+            chain_code,
+            depth: 0,
+            child_number: ChildNumber::default(),
+        })
+    }
+
+    /// A key in a tree rebuilt from its parts, as saved elsewhere
+    pub fn from_parts(
+        key: SecretKey,
+        chain_code: [u8; 32],
+        depth: u8,
+        child_number: ChildNumber,
+    ) -> Self {
+        ExtendedPrivKey {
+            key,
+            chain_code,
+            depth,
+            child_number,
+        }
+    }
+
+    /// Child `index` of this key, which must be hardened for ed25519
+    pub fn derive_child(&self, index: u32, hardened: bool) -> Result<Self, DerivationError> {
+        let child_number = ChildNumber::new(index, hardened)?;
+        let depth = self
+            .depth
+            .checked_add(1)
+            .ok_or(DerivationError::DepthExceeded)?;
+        let index = child_number.to_u32().to_be_bytes();
+        let (key, chain_code) = match &self.key {
+            SecretKey::Ed25519(key) => {
+                if !hardened {
+                    return Err(DerivationError::HardenedOnly);
+                }
+                let (secret, chain_code) = split(hmac_sha512(
+                    &self.chain_code,
+                    &[&[0], key.to_bytes().as_ref(), &index],
+                ));
+                (
+                    SecretKey::Ed25519(ed25519::SigningKey::from_bytes(&secret)),
+                    chain_code,
+                )
+            }
+            #[cfg(any(test, feature = "secp256k1"))]
+            SecretKey::Secp256k1(key) => {
+                let mac = if hardened {
+                    hmac_sha512(&self.chain_code, &[&[0], key.to_bytes().as_ref(), &index])
+                } else {
+                    hmac_sha512(&self.chain_code, &[key.verifying_key().as_bytes(), &index])
+                };
+                let (tweak, chain_code) = split(mac);
+                let child = key
+                    .add_tweak(&tweak)
+                    .map_err(|_| DerivationError::InvalidKey)?;
+                (SecretKey::Secp256k1(child), chain_code)
+            }
+        };
+        Ok(ExtendedPrivKey {
+            key,
+            chain_code,
+            depth,
+            child_number,
+        })
+    }
+
+    /// The key reached by descending `path` from this one
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self, DerivationError> {
+        let start = ExtendedPrivKey {
+            key: self.key.duplicate(),
+            chain_code: self.chain_code,
+            depth: self.depth,
+            child_number: self.child_number,
+        };
+        path.children().iter().try_fold(start, |key, child| {
+            key.derive_child(child.index(), child.is_hardened())
+        })
+    }
+
+    /// The public half, which derives the same non-hardened children
+    pub fn extended_public_key(&self) -> ExtendedPubKey {
+        ExtendedPubKey {
+            key: self.public_key(),
+            chain_code: self.chain_code,
+            depth: self.depth,
+            child_number: self.child_number,
+        }
+    }
+
+    /// The 32 secret bytes: the seed of an ed25519 key, the big-endian
+    /// scalar of a secp256k1 key. They are wiped when dropped.
+    pub fn secret_bytes(&self) -> Zeroizing<[u8; 32]> {
+        self.key.to_bytes()
+    }
+
+    /// The key itself, without its place in the tree
+    pub fn secret_key(&self) -> &SecretKey {
+        &self.key
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        self.key.scheme()
+    }
+
+    pub fn chain_code(&self) -> &[u8; 32] {
+        &self.chain_code
+    }
+
+    /// Levels below the master key, which is at zero
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    /// Which child of its parent this key is; zero for the master key
+    pub fn child_number(&self) -> ChildNumber {
+        self.child_number
+    }
+}
+
+impl Signer for ExtendedPrivKey {
+    fn public_key(&self) -> PublicKey {
+        self.key.public_key()
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Signature {
+        self.key.sign_message(message)
+    }
+}
+
+impl Drop for ExtendedPrivKey {
+    fn drop(&mut self) {
+        self.chain_code.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for ExtendedPrivKey {}
+
+/// Shows the public half only
+impl fmt::Debug for ExtendedPrivKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtendedPrivKey")
+            .field("public_key", &self.public_key())
+            .field("depth", &self.depth)
+            .field("child_number", &self.child_number)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A public key in a derivation tree, with the chain code its non-hardened
+/// children derive from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ExtendedPubKey {
+    key: PublicKey,
+    chain_code: [u8; 32],
+    depth: u8,
+    child_number: ChildNumber,
+}
+
+impl ExtendedPubKey {
+    /// Non-hardened child `index` of this key, the public half of the
+    /// parent's [`ExtendedPrivKey::derive_child`]. Only secp256k1 keys have
+    /// public children.
+    pub fn derive_child(&self, index: u32) -> Result<Self, DerivationError> {
+        let child_number = ChildNumber::new(index, false)?;
+        let depth = self
+            .depth
+            .checked_add(1)
+            .ok_or(DerivationError::DepthExceeded)?;
+        let PublicKey::Secp256k1(key) = &self.key else {
+            return Err(DerivationError::HardenedOnly);
+        };
+        let (tweak, chain_code) = split(hmac_sha512(
+            &self.chain_code,
+            &[key.as_bytes(), &index.to_be_bytes()],
+        ));
+        let child = key
+            .add_tweak(&tweak)
+            .map_err(|_| DerivationError::InvalidKey)?;
+        Ok(ExtendedPubKey {
+            key: child.into(),
+            chain_code,
+            depth,
+            child_number,
+        })
+    }
+
+    /// The key reached by descending `path`, which must hold no hardened
+    /// children, from this one
+    pub fn derive_path(&self, path: &DerivationPath) -> Result<Self, DerivationError> {
+        path.children().iter().try_fold(*self, |key, child| {
+            if child.is_hardened() {
+                return Err(DerivationError::HardenedOnly);
+            }
+            key.derive_child(child.index())
+        })
+    }
+
+    pub fn public_key(&self) -> PublicKey {
+        self.key
+    }
+
+    pub fn chain_code(&self) -> &[u8; 32] {
+        &self.chain_code
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn child_number(&self) -> ChildNumber {
+        self.child_number
+    }
+}
+
+/// The two halves of a derivation hash: the secret or tweak, wiped when
+/// dropped, then the chain code
+fn split(mut mac: [u8; 64]) -> (Zeroizing<[u8; 32]>, [u8; 32]) {
+    let mut left = Zeroizing::new([0u8; 32]);
+    let mut right = [0u8; 32];
+    left.copy_from_slice(&mac[..32]);
+    right.copy_from_slice(&mac[32..]);
+    mac.zeroize();
+    (left, right)
+}
+
+/// HMAC-SHA-512 of the concatenated `parts` under `key`
+#[allow(clippy::expect_used)]
+fn hmac_sha512(key: &[u8], parts: &[&[u8]]) -> [u8; 64] {
+    let mut mac = Hmac::<Sha512>::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (path, chain code, secret, public key) along a published test vector
+    type Vector = [(&'static str, &'static str, &'static str, &'static str)];
+
+    fn check_vector(scheme: SignatureScheme, seed: &str, vector: &Vector) {
+        let master = ExtendedPrivKey::from_seed(scheme, &hex::decode(seed).unwrap()).unwrap();
+        for &(path, chain_code, secret, public) in vector {
+            let key = master.derive_path(&path.parse().unwrap()).unwrap();
+            assert_eq!(hex::encode(key.chain_code()), chain_code, "{}", path);
+            assert_eq!(hex::encode(key.secret_bytes()), secret, "{}", path);
+            assert_eq!(hex::encode(key.public_key().as_bytes()), public, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_bip32_vector_1() {
+        // BIP-32 test vector 1, which SLIP-0010 repeats for secp256k1
+        check_vector(
+            SignatureScheme::Secp256k1,
+            "000102030405060708090a0b0c0d0e0f",
+            &[
+                (
+                    "m",
+                    "873dff81c02f525623fd1fe5167eac3a55a049de3d314bb42ee227ffed37d508",
+                    "e8f32e723decf4051aefac8e2c93c9c5b214313817cdb01a1494b917c8436b35",
+                    "0339a36013301597daef41fbe593a02cc513d0b55527ec2df1050e2e8ff49c85c2",
+                ),
+                (
+                    "m/0'",
+                    "47fdacbd0f1097043b78c63c20c34ef4ed9a111d980047ad16282c7ae6236141",
+                    "edb2e14f9ee77d26dd93b4ecede8d16ed408ce149b6cd80b0715a2d911a0afea",
+                    "035a784662a4a20a65bf6aab9ae98a6c068a81c52e4b032c0fb5400c706cfccc56",
+                ),
+                (
+                    "m/0'/1",
+                    "2a7857631386ba23dacac34180dd1983734e444fdbf774041578e9b6adb37c19",
+                    "3c6cb8d0f6a264c91ea8b5030fadaa8e538b020f0a387421a12de9319dc93368",
+                    "03501e454bf00751f24b1b489aa925215d66af2234e3891c3b21a52bedb3cd711c",
+                ),
+                (
+                    "m/0'/1/2'",
+                    "04466b9cc8e161e966409ca52986c584f07e9dc81f735db683c3ff6ec7b1503f",
+                    "cbce0d719ecf7431d88e6a89fa1483e02e35092af60c042b1df2ff59fa424dca",
+                    "0357bfe1e341d01c69fe5654309956cbea516822fba8a601743a012a7896ee8dc2",
+                ),
+                (
+                    "m/0'/1/2'/2",
+                    "cfb71883f01676f587d023cc53a35bc7f88f724b1f8c2892ac1275ac822a3edd",
+                    "0f479245fb19a38a1954c5c7c0ebab2f9bdfd96a17563ef28a6a4b1a2a764ef4",
+                    "02e8445082a72f29b75ca48748a914df60622a609cacfce8ed0e35804560741d29",
+                ),
+                (
+                    "m/0'/1/2'/2/1000000000",
+                    "c783e67b921d2beb8f6b389cc646d7263b4145701dadd2161548a8b078e65e9e",
+                    "471b76e389e528d6de6d816857e012c5455051cad6660850e58372a6c3e6e7c8",
+                    "022a471424da5e657499d1ff51cb43c47481a03b1e77f951fe64cec9f5a48f7011",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_slip10_ed25519_vector_1() {
+        // SLIP-0010 test vector 1 for ed25519, without the 00 byte SLIP-0010
+        // prefixes public keys with
+        check_vector(
+            SignatureScheme::Ed25519,
+            "000102030405060708090a0b0c0d0e0f",
+            &[
+                (
+                    "m",
+                    "90046a93de5380a72b5e45010748567d5ea02bbf6522f979e05c0d8d8ca9fffb",
+                    "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7",
+                    "a4b2856bfec510abab89753fac1ac0e1112364e7d250545963f135f2a33188ed",
+                ),
+                (
+                    "m/0H",
+                    "8b59aa11380b624e81507a27fedda59fea6d0b779a778918a2fd3590e16e9c69",
+                    "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3",
+                    "8c8a13df77a28f3445213a0f432fde644acaa215fc72dcdf300d5efaa85d350c",
+                ),
+                (
+                    "m/0H/1H",
+                    "a320425f77d1b5c2505a6b1b27382b37368ee640e3557c315416801243552f14",
+                    "b1d0bad404bf35da785a64ca1ac54b2617211d2777696fbffaf208f746ae84f2",
+                    "1932a5270f335bed617d5b935c80aedb1a35bd9fc1e31acafd5372c30f5c1187",
+                ),
+                (
+                    "m/0H/1H/2H",
+                    "2e69929e00b5ab250f49c3fb1c12f252de4fed2c1db88387094a0f8c4c9ccd6c",
+                    "92a5b23c0b8a99e37d07df3fb9966917f5d06e02ddbd909c7e184371463e9fc9",
+                    "ae98736566d30ed0e9d2f4486a64bc95740d89c7db33f52121f8ea8f76ff0fc1",
+                ),
+                (
+                    "m/0H/1H/2H/2H",
+                    "8f6d87f93d750e0efccda017d662a1b31a266e4a6f5993b15f5c1f07f74dd5cc",
+                    "30d1dc7e5fc04c31219ab25a27ae00b50f6fd66622f6e9c913253d6511d1e662",
+                    "8abae2d66361c879b900d204ad2cc4984fa2aa344dd7ddc46007329ac76c429c",
+                ),
+                (
+                    "m/0H/1H/2H/2H/1000000000H",
+                    "68789923a0cac2cd5a29172a475fe9e0fb14cd6adb5ad98a3fa70333e7afa230",
+                    "8f94d394a8e8fd6b1bc2f3f49f5c47e385281d5c17e65324b0f62483e37e8793",
+                    "3c24da049451555d51a7014a37337aa4e12d41e485abccfa46b47dfb2af54b7a",
+                ),
+            ],
+        );
+    }
+
+    #[test]
+    fn test_public_derivation_matches_private() {
+        let seed = [0x5a; 32];
+        let master = ExtendedPrivKey::from_seed(SignatureScheme::Secp256k1, &seed).unwrap();
+        let account = master.derive_path(&"m/44'/0'/0'".parse().unwrap()).unwrap();
+        let xpub = account.extended_public_key();
+        for path in ["m/0", "m/0/5", "m/1/0/7", "m/2147483647"] {
+            let path: DerivationPath = path.parse().unwrap();
+            let private = account.derive_path(&path).unwrap();
+            assert_eq!(
+                xpub.derive_path(&path),
+                Ok(private.extended_public_key()),
+                "{}",
+                path
+            );
+        }
+        assert_eq!(
+            xpub.derive_path(&"m/0/1'".parse().unwrap()),
+            Err(DerivationError::HardenedOnly)
+        );
+
+        // ed25519 children are hardened, from the secret key only
+        let master = ExtendedPrivKey::from_seed(SignatureScheme::Ed25519, &seed).unwrap();
+        assert_eq!(
+            master.derive_child(0, false).unwrap_err(),
+            DerivationError::HardenedOnly
+        );
+        assert_eq!(
+            master.extended_public_key().derive_child(0),
+            Err(DerivationError::HardenedOnly)
+        );
+        let child = master.derive_child(0, true).unwrap();
+        assert_eq!(child.depth(), 1);
+        assert_eq!(child.child_number(), ChildNumber::new(0, true).unwrap());
+    }
+
+    #[test]
+    fn test_paths() {
+        let path: DerivationPath = "m/44'/0h/0H/0/5".parse().unwrap();
+        let indices: Vec<u32> = path.children().iter().map(|child| child.to_u32()).collect();
+        assert_eq!(indices, [44 | HARDENED, HARDENED, HARDENED, 0, 5]);
+        assert_eq!(path.to_string(), "m/44'/0'/0'/0/5");
+        assert_eq!("m".parse(), Ok(DerivationPath::default()));
+        assert_eq!(
+            "m/2147483647'"
+                .parse::<DerivationPath>()
+                .unwrap()
+                .children()[0]
+                .to_u32(),
+            u32::MAX
+        );
+
+        for bad in [
+            "",
+            "M/0",
+            "m/",
+            "m//0",
+            "/0",
+            "m/-1",
+            "m/+1",
+            "m/01",
+            "m/0''",
+            "m/2147483648",
+            "m/x",
+            "0/1",
+        ] {
+            assert_eq!(
+                bad.parse::<DerivationPath>(),
+                Err(DerivationError::InvalidPath(bad.to_string())),
+                "{}",
+                bad
+            );
+        }
+
+        assert_eq!(
+            ChildNumber::new(HARDENED, false),
+            Err(DerivationError::IndexOutOfRange(HARDENED))
+        );
+        assert_eq!(
+            ExtendedPrivKey::from_seed(SignatureScheme::Secp256k1, &[0; 15]).unwrap_err(),
+            DerivationError::SeedLength(15)
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Signed messages: proof of control of a key or address made off-chain,
+//! such as an answer to "sign this challenge".
+//!
+//! A message is never signed as is. Its [`message_hash`] starts with
+//! [`MESSAGE_TAG`] and the message length, so a message signature cannot
+//! pass as a transaction input signature, whose sighash starts with another
+//! tag, nor the other way around. Since an address is only a key's hash, a
+//! [`MessageSignature`] carries the key alongside the signature, and is
+//! written for people as base64.
+
+use std::fmt;
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+#[cfg(any(test, feature = "crypto"))]
+use super::Signer;
+use super::{
+    ed25519, secp256k1, PublicKey, Signature, SignatureError, SignatureScheme, Verifier,
+    SIGNATURE_LENGTH,
+};
+use crate::address::Address;
+use crate::codec;
+use crate::encoding;
+
+/// Domain tag that starts every [`message_hash`]
+pub const MESSAGE_TAG: &[u8] = b"aarwyn-chain/signed-message/v1:";
+
+/// Reasons a message signature is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum MessageError {
+    /// The text is not base64 of a signature and key
+    #[error("invalid message signature encoding")]
+    InvalidEncoding,
+    /// The signing key is not the one expected, or does not hash to the
+    /// expected address
+    #[error("message signed by a different key")]
+    KeyMismatch,
+    /// The signature does not match the message and key
+    #[error("{0}")]
+    Signature(SignatureError),
+}
+
+impl From<SignatureError> for MessageError {
+    fn from(err: SignatureError) -> Self {
+        MessageError::Signature(err)
+    }
+}
+
+/// The hash a message signature signs: SHA-256 of [`MESSAGE_TAG`], the
+/// message length as a varint, then the message
+pub fn message_hash(message: &[u8]) -> [u8; 32] {
+    let mut prefix = MESSAGE_TAG.to_vec();
+    codec::write_varint(&mut prefix, message.len() as u64);
+    let mut hasher = Sha256::new();
+    hasher.update(prefix);
+    hasher.update(message);
+    hasher.finalize().into()
+}
+
+/// A signature over a message's [`message_hash`] and the key that made it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MessageSignature {
+    public_key: PublicKey,
+    signature: Signature,
+}
+
+impl MessageSignature {
+    /// Sign `message` with `key`
+    #[cfg(any(test, feature = "crypto"))]
+    pub fn sign(key: &(impl Signer + ?Sized), message: &[u8]) -> Self {
+        MessageSignature {
+            public_key: key.public_key(),
+            signature: key.sign_message(&message_hash(message)),
+        }
+    }
+
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Check that `key` signed `message`
+    pub fn verify(&self, key: &PublicKey, message: &[u8]) -> Result<(), MessageError> {
+        if self.public_key != *key {
+            return Err(MessageError::KeyMismatch);
+        }
+        Ok(key.verify_message(&message_hash(message), &self.signature)?)
+    }
+
+    /// Check that the key of `address` signed `message`
+    pub fn verify_address(&self, address: &Address, message: &[u8]) -> Result<(), MessageError> {
+        if Address::from_public_key(&self.public_key) != *address {
+            return Err(MessageError::KeyMismatch);
+        }
+        self.verify(&self.public_key, message)
+    }
+
+    /// The signature's [`SignatureScheme::tag`] and bytes, then the key's
+    /// tag and bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(2 + SIGNATURE_LENGTH + secp256k1::PUBLIC_KEY_LENGTH);
+        bytes.push(self.signature.scheme().tag());
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        bytes.push(self.public_key.scheme().tag());
+        bytes.extend_from_slice(self.public_key.as_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MessageError> {
+        let (&tag, rest) = bytes.split_first().ok_or(MessageError::InvalidEncoding)?;
+        let scheme = SignatureScheme::from_tag(tag).ok_or(MessageError::InvalidEncoding)?;
+        let (signature, rest) = rest
+            .split_first_chunk::<SIGNATURE_LENGTH>()
+            .ok_or(MessageError::InvalidEncoding)?;
+        let signature = Signature::from_bytes(scheme, signature);
+        let (&tag, key) = rest.split_first().ok_or(MessageError::InvalidEncoding)?;
+        let scheme = SignatureScheme::from_tag(tag).ok_or(MessageError::InvalidEncoding)?;
+        let length = match scheme {
+            SignatureScheme::Ed25519 => ed25519::PUBLIC_KEY_LENGTH,
+            SignatureScheme::Secp256k1 => secp256k1::PUBLIC_KEY_LENGTH,
+        };
+        if key.len() != length {
+            return Err(MessageError::InvalidEncoding);
+        }
+        let public_key =
+            PublicKey::from_bytes(scheme, key).map_err(|_| MessageError::InvalidEncoding)?;
+        Ok(MessageSignature {
+            public_key,
+            signature,
+        })
+    }
+}
+
+impl fmt::Display for MessageSignature {
+    /// Base64 of [`MessageSignature::to_bytes`]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&encoding::to_base64(&self.to_bytes()))
+    }
+}
+
+impl FromStr for MessageSignature {
+    type Err = MessageError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = encoding::from_base64(s).map_err(|_| MessageError::InvalidEncoding)?;
+        MessageSignature::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BlockBuilder, BlockHash};
+    use crate::transaction::{OutPoint, Transaction, TxInput, TxOutput, Txid};
+
+    fn keys() -> [Box<dyn Signer>; 2] {
+        [
+            Box::new(ed25519::SigningKey::from_bytes(&[1; 32])),
+            Box::new(secp256k1::SigningKey::from_bytes(&[2; 32]).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for key in keys() {
+            let signature = MessageSignature::sign(key.as_ref(), b"sign this challenge");
+            let address = Address::from_public_key(&key.public_key());
+            assert_eq!(
+                signature.verify(&key.public_key(), b"sign this challenge"),
+                Ok(())
+            );
+            assert_eq!(
+                signature.verify_address(&address, b"sign this challenge"),
+                Ok(())
+            );
+
+            let text = signature.to_string();
+            assert_eq!(text.parse(), Ok(signature));
+            assert_eq!(
+                MessageSignature::from_bytes(&signature.to_bytes()),
+                Ok(signature)
+            );
+        }
+    }
+
+    #[test]
+    fn test_tampering_rejected() {
+        let [ed, secp] = keys();
+        let signature = MessageSignature::sign(ed.as_ref(), b"pay 10 to bob");
+        assert_eq!(
+            signature.verify(&ed.public_key(), b"pay 11 to bob"),
+            Err(MessageError::Signature(SignatureError::InvalidSignature))
+        );
+        assert_eq!(
+            signature.verify(&secp.public_key(), b"pay 10 to bob"),
+            Err(MessageError::KeyMismatch)
+        );
+        let other = Address::from_public_key(&secp.public_key());
+        assert_eq!(
+            signature.verify_address(&other, b"pay 10 to bob"),
+            Err(MessageError::KeyMismatch)
+        );
+
+        // Swapping in another key does not carry the signature over to it
+        let mut swapped = signature.to_bytes();
+        swapped.truncate(1 + SIGNATURE_LENGTH);
+        swapped.push(SignatureScheme::Ed25519.tag());
+        swapped.extend_from_slice(
+            ed25519::SigningKey::from_bytes(&[3; 32])
+                .public_key()
+                .as_bytes(),
+        );
+        let swapped = MessageSignature::from_bytes(&swapped).unwrap();
+        let address = Address::from_public_key(swapped.public_key());
+        assert!(swapped.verify_address(&address, b"pay 10 to bob").is_err());
+
+        let bytes = signature.to_bytes();
+        for bad in [
+            &bytes[..bytes.len() - 1],
+            &[bytes.as_slice(), &[0]].concat(),
+            &bytes[..1],
+            &[],
+        ] {
+            assert_eq!(
+                MessageSignature::from_bytes(bad),
+                Err(MessageError::InvalidEncoding)
+            );
+        }
+        assert_eq!(
+            "not base64".parse::<MessageSignature>(),
+            Err(MessageError::InvalidEncoding)
+        );
+    }
+
+    #[test]
+    fn test_domain_separation() {
+        let [ed, secp] = keys();
+        let mut tx = Transaction {
+            inputs: vec![TxInput {
+                prev_out: OutPoint {
+                    txid: Txid::from_bytes([1; 32]),
+                    index: 0,
+                },
+                signatures: Vec::new(),
+                public_key: None,
+                recovery_id: None,
+            }],
+            outputs: vec![TxOutput::to_address(5, Address::from_bytes([2; 32]))],
+            lock_time: 0,
+        };
+        let sighash = tx.sighash(0);
+        for key in [&ed, &secp] {
+            // Signing the sighash as a message does not sign the transaction
+            let signature = MessageSignature::sign(key.as_ref(), &sighash);
+            tx.inputs[0].public_key = Some(key.public_key());
+            tx.inputs[0].signatures = vec![*signature.signature()];
+            let block = BlockBuilder::new(BlockHash::ZERO)
+                .transactions([Transaction::default(), tx.clone()])
+                .build()
+                .unwrap();
+            assert!(block
+                .verify_signatures_batch(|input| input.public_key)
+                .is_err());
+
+            // Nor does a transaction signature sign the sighash as a message
+            tx.sign_input(0, key.as_ref());
+            let forged = MessageSignature {
+                public_key: key.public_key(),
+                signature: tx.inputs[0].signatures[0],
+            };
+            assert!(forged.verify(&key.public_key(), &sighash).is_err());
+        }
+    }
+}
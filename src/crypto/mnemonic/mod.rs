@@ -0,0 +1,321 @@
+//! Mnemonic phrases that back up a wallet's seed, as BIP-39 specifies.
+//!
+//! A phrase of 12 to 24 words from the English wordlist encodes 128 to 256
+//! bits of entropy followed by a checksum of its SHA-256 hash. The phrase,
+//! salted with an optional passphrase, stretches into a 64 byte seed for
+//! [`ExtendedPrivKey::from_seed`](super::hd::ExtendedPrivKey::from_seed).
+//!
+//! Passphrases are used as given rather than NFKD normalized as BIP-39
+//! requires, so a caller accepting non-ASCII passphrases should normalize
+//! them first.
+//!
+//! This module needs the `crypto` feature.
+
+use std::fmt;
+use std::io::{self, Read};
+use std::str::FromStr;
+
+use sha2::{Digest, Sha256, Sha512};
+use thiserror::Error;
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+mod english;
+
+use english::WORDS;
+
+/// Rounds of PBKDF2 stretching a phrase into a seed
+const PBKDF2_ROUNDS: u32 = 2048;
+
+/// Reasons a phrase or entropy is refused
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MnemonicError {
+    /// Phrases have 12, 15, 18, 21 or 24 words
+    #[error("phrase has {0} words, not 12, 15, 18, 21 or 24")]
+    WordCount(usize),
+    /// The word is not on the English wordlist
+    #[error("unknown word {0:?}")]
+    UnknownWord(String),
+    /// The last bits of the phrase do not match its entropy's checksum
+    #[error("phrase checksum does not match")]
+    InvalidChecksum,
+    /// Entropy is 16, 20, 24, 28 or 32 bytes
+    #[error("entropy is {0} bytes, not 16, 20, 24, 28 or 32")]
+    EntropyLength(usize),
+}
+
+/// A phrase of English words encoding a seed's entropy, which is wiped
+/// when the phrase is dropped
+#[derive(PartialEq, Eq)]
+pub struct Mnemonic {
+    entropy: Vec<u8>,
+}
+
+impl Mnemonic {
+    /// A fresh phrase of `word_count` words from entropy read off `rng`, a
+    /// source of secure random bytes such as `/dev/urandom`. A `word_count`
+    /// other than 12, 15, 18, 21 or 24 fails with
+    /// [`io::ErrorKind::InvalidInput`], carrying [`MnemonicError::WordCount`].
+    pub fn generate(word_count: usize, mut rng: impl Read) -> io::Result<Self> {
+        if !word_count.is_multiple_of(3) || !(12..=24).contains(&word_count) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, MnemonicError::WordCount(word_count)));
+        }
+        // Every 3 words carry 32 bits of entropy and 1 of checksum
+        let mut entropy = vec![0u8; word_count / 3 * 4];
+        rng.read_exact(&mut entropy)?;
+        Ok(Mnemonic { entropy })
+    }
+
+    /// The phrase encoding `entropy`, 16 to 32 bytes in steps of 4
+    pub fn from_entropy(entropy: &[u8]) -> Result<Self, MnemonicError> {
+        if !entropy.len().is_multiple_of(4) || !(16..=32).contains(&entropy.len()) {
+            return Err(MnemonicError::EntropyLength(entropy.len()));
+        }
+        Ok(Mnemonic {
+            entropy: entropy.to_vec(),
+        })
+    }
+
+    /// Parse a phrase of lowercase words separated by whitespace, checking
+    /// its length first, then each word, then the checksum
+    pub fn from_phrase(phrase: &str) -> Result<Self, MnemonicError> {
+        let words: Vec<&str> = phrase.split_whitespace().collect();
+        if !words.len().is_multiple_of(3) || !(12..=24).contains(&words.len()) {
+            return Err(MnemonicError::WordCount(words.len()));
+        }
+        let mut bits = Zeroizing::new(Vec::with_capacity(words.len() * 11));
+        for word in &words {
+            let index = WORDS
+                .binary_search(word)
+                .map_err(|_| MnemonicError::UnknownWord(word.to_string()))?;
+            bits.extend((0..11).rev().map(|bit| (index >> bit) & 1 == 1));
+        }
+
+        let (entropy_bits, checksum) = bits.split_at(words.len() / 3 * 32);
+        let mut entropy: Vec<u8> = entropy_bits
+            .chunks(8)
+            .map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | bit as u8))
+            .collect();
+        if checksum != checksum_bits(&entropy).as_slice() {
+            entropy.zeroize();
+            return Err(MnemonicError::InvalidChecksum);
+        }
+        Ok(Mnemonic { entropy })
+    }
+
+    /// The entropy the phrase encodes
+    pub fn entropy(&self) -> &[u8] {
+        &self.entropy
+    }
+
+    pub fn word_count(&self) -> usize {
+        self.entropy.len() / 4 * 3
+    }
+
+    /// The phrase's words, in order
+    pub fn words(&self) -> impl Iterator<Item = &'static str> + '_ {
+        let bits: Vec<bool> = self
+            .entropy
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1 == 1))
+            .chain(checksum_bits(&self.entropy))
+            .collect();
+        (0..self.word_count()).map(move |i| {
+            let index = bits[i * 11..(i + 1) * 11]
+                .iter()
+                .fold(0, |acc, &bit| acc << 1 | bit as usize);
+            WORDS[index]
+        })
+    }
+
+    /// The 64 byte seed: PBKDF2-HMAC-SHA512 of the phrase salted with
+    /// `"mnemonic"` and `passphrase`, which may be empty. The seed, like
+    /// every intermediate buffer, is wiped when dropped.
+    pub fn to_seed(&self, passphrase: &str) -> Zeroizing<[u8; 64]> {
+        let phrase = Zeroizing::new(self.to_string());
+        let salt = Zeroizing::new(format!("mnemonic{}", passphrase));
+        let mut seed = Zeroizing::new([0u8; 64]);
+        pbkdf2::pbkdf2_hmac::<Sha512>(phrase.as_bytes(), salt.as_bytes(), PBKDF2_ROUNDS, seed.as_mut());
+        seed
+    }
+}
+
+impl Drop for Mnemonic {
+    fn drop(&mut self) {
+        self.entropy.zeroize();
+    }
+}
+
+impl ZeroizeOnDrop for Mnemonic {}
+
+/// The first `entropy.len() / 4` bits of the entropy's SHA-256 hash
+fn checksum_bits(entropy: &[u8]) -> Vec<bool> {
+    let hash = Sha256::digest(entropy);
+    (0..entropy.len() / 4)
+        .map(|i| (hash[i / 8] >> (7 - i % 8)) & 1 == 1)
+        .collect()
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, word) in self.words().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", word)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Mnemonic {
+    /// Only the length, keeping the phrase out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mnemonic")
+            .field("word_count", &self.word_count())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for Mnemonic {
+    type Err = MnemonicError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Mnemonic::from_phrase(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::crypto::hd::ExtendedPrivKey;
+    use crate::crypto::{SignatureScheme, Signer};
+
+    #[test]
+    fn test_bip39_vectors() {
+        // (entropy, phrase, seed under the passphrase "TREZOR") from the
+        // published BIP-39 English vectors
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank yellow",
+                "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            ),
+            (
+                "80808080808080808080808080808080",
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage above",
+                "d71de856f81a8acc65e6fc851a38d4d7ec216fd0796d0a6827a3ad6ed5511a30fa280f12eb2e47ed2ac03b5c462a0358d18d69fe4f985ec81778c1b370b652a8",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong",
+                "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth useful legal will",
+                "f2b94508732bcbacbcc020faefecfc89feafa6649a5491b8c952cede496c214a0c7b3c392d168748f2d4a612bada0753b52a1c7ac53c1e93abd5c6320b9e95dd",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+                "bda85446c68413707090a52022edd26a1c9462295029f2e60cd7c4f2bbd3097170af7a4d73245cafa9c3cca8d561a7c3de6f5d4a10be8ed2a5e608d68f92fcc8",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+                "dd48c104698c30cfe2b6142103248622fb7bb0ff692eebb00089b32d22484e1613912f0a5b694407be899ffd31ed3992c456cdf60f5d4564b8ba3f05a69890ad",
+            ),
+            (
+                "9e885d952ad362caeb4efe34a8e91bd2",
+                "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic",
+                "274ddc525802f7c828d8ef7ddbcdc5304e87ac3535913611fbbfa986d0c9e5476c91689f9c8a54fd55bd38606aa6a8595ad213d4c9c9f9aca3fb217069a41028",
+            ),
+        ];
+        for (entropy, phrase, seed) in vectors {
+            let mnemonic = Mnemonic::from_entropy(&hex::decode(entropy).unwrap()).unwrap();
+            assert_eq!(mnemonic.to_string(), phrase);
+            let parsed: Mnemonic = phrase.parse().unwrap();
+            assert_eq!(parsed, mnemonic);
+            assert_eq!(hex::encode(parsed.entropy()), entropy);
+            assert_eq!(hex::encode(parsed.to_seed("TREZOR")), seed);
+        }
+    }
+
+    #[test]
+    fn test_phrase_errors() {
+        let valid = "legal winner thank year wave sausage worth useful legal winner thank yellow";
+        assert_eq!(
+            Mnemonic::from_phrase("legal winner thank year wave sausage worth useful legal winner thank"),
+            Err(MnemonicError::WordCount(11))
+        );
+        assert_eq!(Mnemonic::from_phrase(""), Err(MnemonicError::WordCount(0)));
+        assert_eq!(
+            Mnemonic::from_phrase(&valid.replace("wave", "waves")),
+            Err(MnemonicError::UnknownWord("waves".to_string()))
+        );
+        assert_eq!(
+            Mnemonic::from_phrase(&valid.replace("yellow", "year")),
+            Err(MnemonicError::InvalidChecksum)
+        );
+        // Words are matched exactly, though any whitespace separates them
+        assert_eq!(
+            Mnemonic::from_phrase(&valid.replace("legal", "Legal")),
+            Err(MnemonicError::UnknownWord("Legal".to_string()))
+        );
+        assert!(Mnemonic::from_phrase(&format!("  {}\n", valid.replace(' ', "\t"))).is_ok());
+
+        assert_eq!(Mnemonic::from_entropy(&[0; 15]), Err(MnemonicError::EntropyLength(15)));
+        assert_eq!(Mnemonic::from_entropy(&[0; 36]), Err(MnemonicError::EntropyLength(36)));
+    }
+
+    #[test]
+    fn test_generate() {
+        for word_count in [12, 15, 18, 21, 24] {
+            let mnemonic = Mnemonic::generate(word_count, &[0xa5; 32][..]).unwrap();
+            assert_eq!(mnemonic.word_count(), word_count);
+            assert_eq!(mnemonic.words().count(), word_count);
+            assert_eq!(Mnemonic::from_phrase(&mnemonic.to_string()), Ok(mnemonic));
+        }
+        // Running out of randomness is an error, not a weaker phrase
+        assert!(Mnemonic::generate(24, &[0; 16][..]).is_err());
+        let err = Mnemonic::generate(13, &[0; 32][..]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(err.to_string(), MnemonicError::WordCount(13).to_string());
+    }
+
+    #[test]
+    fn test_phrase_to_first_address() {
+        // A fixture pinning the whole path from backup phrase to the first
+        // receiving address, m/44'/0'/0'/0/0, on both curves
+        let mnemonic: Mnemonic = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about"
+            .parse()
+            .unwrap();
+        let seed = mnemonic.to_seed("");
+        assert_eq!(
+            hex::encode(seed.as_ref()),
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4"
+        );
+
+        let secp = ExtendedPrivKey::from_seed(SignatureScheme::Secp256k1, &seed[..]).unwrap();
+        let key = secp.derive_path(&"m/44'/0'/0'/0/0".parse().unwrap()).unwrap();
+        assert_eq!(
+            hex::encode(key.public_key().as_bytes()),
+            "03aaeb52dd7494c361049de67cc680e83ebcbbbdbeb13637d92cd845f70308af5e"
+        );
+        assert_eq!(Address::from_public_key(&key.public_key()).to_bech32("arw").unwrap(),
+            "arw15sdvjdemnp5wczky0dt34esm295a623jk5kv6e52nxu4ltdcdw8s7mh9xq"
+        );
+
+        let ed = ExtendedPrivKey::from_seed(SignatureScheme::Ed25519, &seed[..]).unwrap();
+        let key = ed.derive_path(&"m/44'/0'/0'/0'/0'".parse().unwrap()).unwrap();
+        assert_eq!(Address::from_public_key(&key.public_key()).to_bech32("arw").unwrap(),
+            "arw1dpcefnn9w2u7s6zusuxv9kw0hfrf4gakdmqlxslu9vwgwcpqgx9sy6vwzf"
+        );
+    }
+}
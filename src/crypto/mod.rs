@@ -0,0 +1,416 @@
+//! Cryptographic primitives used for signing blocks and transactions.
+//!
+//! Two signature schemes are supported: ed25519 and ECDSA over secp256k1.
+//! Blocks and transactions carry a [`Signature`] tagged with its
+//! [`SignatureScheme`], and a [`PublicKey`] refuses signatures of the other
+//! scheme. Signatures of both schemes are [`SIGNATURE_LENGTH`] bytes.
+//!
+//! # Secret keys
+//!
+//! **The curve arithmetic here is unaudited and not constant time.** Signing,
+//! key generation and hierarchical derivation branch on the bits of secret
+//! keys, so an attacker who can time them may recover the keys. Only
+//! verification, which handles public data alone, is always compiled. Secret
+//! keys, [`Signer`], [`hd`], [`mnemonic`] and the wallet need the `crypto`
+//! feature, and secp256k1 secret keys also the `secp256k1` feature; enable
+//! them only where signing timings cannot be observed.
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+#[cfg(any(test, feature = "crypto"))]
+use zeroize::Zeroizing;
+
+/// Serialize and deserialize through the hex of `Display` and `FromStr`
+macro_rules! hex_serde {
+    ($type:ty) => {
+        impl ::serde::Serialize for $type {
+            fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.collect_str(self)
+            }
+        }
+
+        impl<'de> ::serde::Deserialize<'de> for $type {
+            fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                <String as ::serde::Deserialize>::deserialize(deserializer)?
+                    .parse()
+                    .map_err(::serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+pub mod ed25519;
+#[cfg(any(test, feature = "crypto"))]
+pub mod hd;
+pub mod message;
+#[cfg(any(test, feature = "crypto"))]
+pub mod mnemonic;
+pub mod secp256k1;
+
+/// Length of a signature of either scheme
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// Errors raised when decoding keys or checking signatures
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum SignatureError {
+    /// The public key bytes are not a valid curve point
+    #[error("invalid public key")]
+    InvalidPublicKey,
+    /// The secret key bytes are out of range for the scheme
+    #[error("invalid secret key")]
+    InvalidSecretKey,
+    /// The signature is malformed or does not match the message and key
+    #[error("invalid signature")]
+    InvalidSignature,
+    /// The signature belongs to a different scheme than the key
+    #[error("signature and key use different schemes")]
+    SchemeMismatch,
+    /// Text is not hex of the expected length
+    #[error("invalid hex encoding")]
+    InvalidHex,
+    /// Secret keys of the scheme are not compiled in, for want of its feature
+    #[error("secret keys of this scheme need its crate feature")]
+    UnsupportedScheme,
+}
+
+/// The signature schemes keys and signatures may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignatureScheme {
+    Ed25519,
+    Secp256k1,
+}
+
+impl SignatureScheme {
+    /// The byte announcing a signature of this scheme in block and
+    /// transaction encodings, where 0 stands for no signature
+    pub fn tag(self) -> u8 {
+        match self {
+            SignatureScheme::Ed25519 => 1,
+            SignatureScheme::Secp256k1 => 2,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(SignatureScheme::Ed25519),
+            2 => Some(SignatureScheme::Secp256k1),
+            _ => None,
+        }
+    }
+}
+
+/// A signature of either scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Signature {
+    Ed25519(ed25519::Signature),
+    Secp256k1(secp256k1::Signature),
+}
+
+impl Signature {
+    /// Interpret `bytes` as a signature of `scheme`
+    pub fn from_bytes(scheme: SignatureScheme, bytes: &[u8; SIGNATURE_LENGTH]) -> Self {
+        match scheme {
+            SignatureScheme::Ed25519 => Signature::Ed25519(ed25519::Signature::from_bytes(bytes)),
+            SignatureScheme::Secp256k1 => Signature::Secp256k1(secp256k1::Signature::from_bytes(bytes)),
+        }
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            Signature::Ed25519(_) => SignatureScheme::Ed25519,
+            Signature::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+
+    /// The signature's bytes, without its scheme
+    pub fn to_bytes(&self) -> [u8; SIGNATURE_LENGTH] {
+        match self {
+            Signature::Ed25519(signature) => signature.to_bytes(),
+            Signature::Secp256k1(signature) => signature.to_bytes(),
+        }
+    }
+}
+
+impl From<ed25519::Signature> for Signature {
+    fn from(signature: ed25519::Signature) -> Self {
+        Signature::Ed25519(signature)
+    }
+}
+
+impl From<secp256k1::Signature> for Signature {
+    fn from(signature: secp256k1::Signature) -> Self {
+        Signature::Secp256k1(signature)
+    }
+}
+
+/// A public key of either scheme
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PublicKey {
+    Ed25519(ed25519::VerifyingKey),
+    Secp256k1(secp256k1::VerifyingKey),
+}
+
+impl PublicKey {
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            PublicKey::Ed25519(_) => SignatureScheme::Ed25519,
+            PublicKey::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+
+    /// The key's bytes, without its scheme: 32 for ed25519, and the 33 byte
+    /// compressed point for secp256k1
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            PublicKey::Ed25519(key) => key.as_bytes(),
+            PublicKey::Secp256k1(key) => key.as_bytes(),
+        }
+    }
+
+    /// Interpret `bytes` as a key of `scheme`, refusing bytes of the wrong
+    /// length or that are not a valid point
+    pub fn from_bytes(scheme: SignatureScheme, bytes: &[u8]) -> Result<Self, SignatureError> {
+        match scheme {
+            SignatureScheme::Ed25519 => {
+                let bytes = bytes.try_into().map_err(|_| SignatureError::InvalidPublicKey)?;
+                ed25519::VerifyingKey::from_bytes(bytes).map(PublicKey::Ed25519)
+            }
+            SignatureScheme::Secp256k1 => {
+                let bytes = bytes.try_into().map_err(|_| SignatureError::InvalidPublicKey)?;
+                secp256k1::VerifyingKey::from_bytes(bytes).map(PublicKey::Secp256k1)
+            }
+        }
+    }
+}
+
+impl From<ed25519::VerifyingKey> for PublicKey {
+    fn from(key: ed25519::VerifyingKey) -> Self {
+        PublicKey::Ed25519(key)
+    }
+}
+
+impl From<secp256k1::VerifyingKey> for PublicKey {
+    fn from(key: secp256k1::VerifyingKey) -> Self {
+        PublicKey::Secp256k1(key)
+    }
+}
+
+/// A secret key of either scheme
+#[cfg(any(test, feature = "crypto"))]
+#[derive(Debug)]
+pub enum SecretKey {
+    Ed25519(ed25519::SigningKey),
+    #[cfg(any(test, feature = "secp256k1"))]
+    Secp256k1(secp256k1::SigningKey),
+}
+
+#[cfg(any(test, feature = "crypto"))]
+impl SecretKey {
+    /// Interpret `bytes` as a secret of `scheme`: the seed of an ed25519 key,
+    /// the big-endian scalar of a secp256k1 key. Without the `secp256k1`
+    /// feature a secp256k1 secret is refused with
+    /// [`SignatureError::UnsupportedScheme`].
+    pub fn from_bytes(scheme: SignatureScheme, bytes: &[u8; 32]) -> Result<Self, SignatureError> {
+        match scheme {
+            SignatureScheme::Ed25519 => Ok(SecretKey::Ed25519(ed25519::SigningKey::from_bytes(bytes))),
+            #[cfg(any(test, feature = "secp256k1"))]
+            SignatureScheme::Secp256k1 => secp256k1::SigningKey::from_bytes(bytes).map(SecretKey::Secp256k1),
+            #[cfg(not(any(test, feature = "secp256k1")))]
+            SignatureScheme::Secp256k1 => Err(SignatureError::UnsupportedScheme),
+        }
+    }
+
+    pub fn scheme(&self) -> SignatureScheme {
+        match self {
+            SecretKey::Ed25519(_) => SignatureScheme::Ed25519,
+            #[cfg(any(test, feature = "secp256k1"))]
+            SecretKey::Secp256k1(_) => SignatureScheme::Secp256k1,
+        }
+    }
+
+    /// The 32 secret bytes [`SecretKey::from_bytes`] takes, wiped when dropped
+    pub fn to_bytes(&self) -> Zeroizing<[u8; 32]> {
+        match self {
+            SecretKey::Ed25519(key) => key.to_bytes(),
+            #[cfg(any(test, feature = "secp256k1"))]
+            SecretKey::Secp256k1(key) => key.to_bytes(),
+        }
+    }
+
+    /// A second key with the same secret, made explicitly since signing
+    /// keys are not `Clone`
+    #[allow(clippy::expect_used)]
+    pub fn duplicate(&self) -> Self {
+        SecretKey::from_bytes(self.scheme(), &self.to_bytes()).expect("a key's own secret is in range")
+    }
+}
+
+#[cfg(any(test, feature = "crypto"))]
+impl From<ed25519::SigningKey> for SecretKey {
+    fn from(key: ed25519::SigningKey) -> Self {
+        SecretKey::Ed25519(key)
+    }
+}
+
+#[cfg(any(test, feature = "secp256k1"))]
+impl From<secp256k1::SigningKey> for SecretKey {
+    fn from(key: secp256k1::SigningKey) -> Self {
+        SecretKey::Secp256k1(key)
+    }
+}
+
+/// A secret key that signs under its scheme
+#[cfg(any(test, feature = "crypto"))]
+pub trait Signer {
+    fn public_key(&self) -> PublicKey;
+
+    /// Sign `message`, tagging the signature with the key's scheme
+    fn sign_message(&self, message: &[u8]) -> Signature;
+}
+
+#[cfg(any(test, feature = "crypto"))]
+impl Signer for ed25519::SigningKey {
+    fn public_key(&self) -> PublicKey {
+        self.verifying_key().into()
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Signature {
+        self.sign(message).into()
+    }
+}
+
+#[cfg(any(test, feature = "secp256k1"))]
+impl Signer for secp256k1::SigningKey {
+    fn public_key(&self) -> PublicKey {
+        self.verifying_key().into()
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Signature {
+        self.sign(message).into()
+    }
+}
+
+#[cfg(any(test, feature = "crypto"))]
+impl Signer for SecretKey {
+    fn public_key(&self) -> PublicKey {
+        match self {
+            SecretKey::Ed25519(key) => key.public_key(),
+            #[cfg(any(test, feature = "secp256k1"))]
+            SecretKey::Secp256k1(key) => key.public_key(),
+        }
+    }
+
+    fn sign_message(&self, message: &[u8]) -> Signature {
+        match self {
+            SecretKey::Ed25519(key) => key.sign_message(message),
+            #[cfg(any(test, feature = "secp256k1"))]
+            SecretKey::Secp256k1(key) => key.sign_message(message),
+        }
+    }
+}
+
+/// A public key that checks signatures under its scheme
+pub trait Verifier {
+    /// Check `signature` over `message`, refusing a signature of another
+    /// scheme with [`SignatureError::SchemeMismatch`]
+    fn verify_message(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError>;
+}
+
+impl Verifier for ed25519::VerifyingKey {
+    fn verify_message(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        match signature {
+            Signature::Ed25519(signature) => self.verify(message, signature),
+            _ => Err(SignatureError::SchemeMismatch),
+        }
+    }
+}
+
+impl Verifier for secp256k1::VerifyingKey {
+    fn verify_message(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        match signature {
+            Signature::Secp256k1(signature) => self.verify(message, signature),
+            _ => Err(SignatureError::SchemeMismatch),
+        }
+    }
+}
+
+impl Verifier for PublicKey {
+    fn verify_message(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        match self {
+            PublicKey::Ed25519(key) => key.verify_message(message, signature),
+            PublicKey::Secp256k1(key) => key.verify_message(message, signature),
+        }
+    }
+}
+
+fn unhex<const N: usize>(s: &str) -> Result<[u8; N], SignatureError> {
+    let bytes = hex::decode(s).map_err(|_| SignatureError::InvalidHex)?;
+    bytes.try_into().map_err(|_| SignatureError::InvalidHex)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schemes_refuse_each_other() {
+        let ed = ed25519::SigningKey::from_bytes(&[1; 32]);
+        let secp = secp256k1::SigningKey::from_bytes(&[1; 32]).unwrap();
+        let ed_sig = ed.sign_message(b"message");
+        let secp_sig = secp.sign_message(b"message");
+        assert_eq!(ed_sig.scheme(), SignatureScheme::Ed25519);
+        assert_eq!(secp.public_key().scheme(), SignatureScheme::Secp256k1);
+
+        assert_eq!(ed.public_key().verify_message(b"message", &ed_sig), Ok(()));
+        assert_eq!(secp.public_key().verify_message(b"message", &secp_sig), Ok(()));
+        assert_eq!(
+            ed.public_key().verify_message(b"message", &secp_sig),
+            Err(SignatureError::SchemeMismatch)
+        );
+        assert_eq!(
+            secp.verifying_key().verify_message(b"message", &ed_sig),
+            Err(SignatureError::SchemeMismatch)
+        );
+        // The same bytes under the other tag do not verify either
+        let retagged = Signature::from_bytes(SignatureScheme::Secp256k1, &ed_sig.to_bytes());
+        assert!(secp.public_key().verify_message(b"message", &retagged).is_err());
+
+        for scheme in [SignatureScheme::Ed25519, SignatureScheme::Secp256k1] {
+            assert_eq!(SignatureScheme::from_tag(scheme.tag()), Some(scheme));
+        }
+        assert_eq!(SignatureScheme::from_tag(0), None);
+        assert_eq!(SignatureScheme::from_tag(3), None);
+    }
+
+    #[test]
+    fn test_secrets_stay_out_of_debug() {
+        fn wiped_on_drop<T: zeroize::ZeroizeOnDrop>(_: &T) {}
+
+        let phrase = mnemonic::Mnemonic::from_entropy(&[0x5a; 16]).unwrap();
+        let seed = phrase.to_seed("");
+        let master = hd::ExtendedPrivKey::from_seed(SignatureScheme::Secp256k1, &seed[..]).unwrap();
+        let ed = ed25519::SigningKey::from_bytes(&[0x5a; 32]);
+        let secp = secp256k1::SigningKey::from_bytes(&[0x5a; 32]).unwrap();
+        wiped_on_drop(&phrase);
+        wiped_on_drop(&master);
+        wiped_on_drop(&ed);
+        wiped_on_drop(&secp);
+
+        let secrets = [
+            (format!("{:?}", ed), hex::encode(ed.to_bytes().as_ref())),
+            (format!("{:?}", secp), hex::encode(secp.to_bytes().as_ref())),
+            (format!("{:?}", master), hex::encode(master.secret_bytes().as_ref())),
+            (format!("{:?}", master), hex::encode(master.chain_code())),
+            (format!("{:?}", phrase), hex::encode(phrase.entropy())),
+            (format!("{:?}", phrase), phrase.words().next().unwrap().to_string()),
+        ];
+        for (debug, secret) in &secrets {
+            assert!(!debug.contains(secret.as_str()), "{} shows {}", debug, secret);
+        }
+        assert!(format!("{:?}", ed).contains(&hex::encode(ed.verifying_key().as_bytes())));
+
+        // Keys still sign with their secrets only reachable through them
+        let signature = master.sign_message(b"message");
+        assert_eq!(master.public_key().verify_message(b"message", &signature), Ok(()));
+    }
+}
@@ -0,0 +1,560 @@
+//! ECDSA signatures over secp256k1, as Bitcoin and Ethereum use them.
+//!
+//! Messages are hashed with SHA-256 and signed with a deterministic nonce
+//! (RFC 6979 with HMAC-SHA-256). Public keys are SEC1 compressed points of
+//! 33 bytes. Signatures are compact, `r` then `s` as 32 big-endian bytes
+//! each, and always low-S: signing normalizes `s` into the lower half of the
+//! group order and verification refuses the high form, so a signature has
+//! exactly one valid encoding. A signature made with
+//! [`SigningKey::sign_recoverable`] also gives a [`RecoveryId`], with which
+//! [`VerifyingKey::recover`] finds the signing key from the signature alone.
+//!
+//! As with ed25519, the arithmetic favours clarity over speed and is **not
+//! constant time**: signing branches on the bits of the secret and of the
+//! nonce. Verification and recovery handle public data only and are always
+//! compiled; [`SigningKey`] needs the `secp256k1` feature, and should sign
+//! only where its timing cannot be observed.
+
+mod modular;
+mod point;
+
+use std::fmt;
+#[cfg(any(test, feature = "secp256k1"))]
+use std::io::{self, Read};
+use std::str::FromStr;
+
+#[cfg(any(test, feature = "secp256k1"))]
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+#[cfg(any(test, feature = "secp256k1"))]
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+use super::SignatureError;
+use modular::{FieldElement, Scalar};
+use point::ProjectivePoint;
+
+/// Length of a secret key in bytes
+pub const SECRET_KEY_LENGTH: usize = 32;
+/// Length of a compressed public key in bytes
+pub const PUBLIC_KEY_LENGTH: usize = 33;
+/// Length of a compact signature in bytes
+pub const SIGNATURE_LENGTH: usize = 64;
+
+/// The group order n, big endian
+const ORDER: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe,
+    0xba, 0xae, 0xdc, 0xe6, 0xaf, 0x48, 0xa0, 0x3b, 0xbf, 0xd2, 0x5e, 0x8c, 0xd0, 0x36, 0x41, 0x41,
+];
+
+/// A secp256k1 secret key together with its public key.
+///
+/// The secret is wiped when the key is dropped, and as with ed25519 keys
+/// there is no `Clone`.
+#[cfg(any(test, feature = "secp256k1"))]
+pub struct SigningKey {
+    secret: Scalar,
+    verifying_key: VerifyingKey,
+}
+
+#[cfg(any(test, feature = "secp256k1"))]
+impl SigningKey {
+    /// A key from its 32-byte big-endian secret, which must be nonzero and
+    /// below the group order
+    #[allow(clippy::expect_used)]
+    pub fn from_bytes(bytes: &[u8; SECRET_KEY_LENGTH]) -> Result<Self, SignatureError> {
+        let secret = Scalar::from_canonical_bytes(bytes)
+            .filter(|secret| !secret.is_zero())
+            .ok_or(SignatureError::InvalidSecretKey)?;
+        let point = ProjectivePoint::basepoint().mul(&secret);
+        let verifying_key = VerifyingKey {
+            bytes: point.compress().expect("a nonzero multiple of the base point is not the identity"),
+            point,
+        };
+        Ok(SigningKey {
+            secret,
+            verifying_key,
+        })
+    }
+
+    /// A fresh key from secrets read off `rng`, a source of secure random
+    /// bytes such as `/dev/urandom`, reading again in the unlikely case a
+    /// secret is out of range
+    pub fn generate(mut rng: impl Read) -> io::Result<Self> {
+        loop {
+            let mut secret = Zeroizing::new([0u8; SECRET_KEY_LENGTH]);
+            rng.read_exact(secret.as_mut())?;
+            if let Ok(key) = SigningKey::from_bytes(&secret) {
+                return Ok(key);
+            }
+        }
+    }
+
+    /// The big-endian secret, wiped when dropped
+    pub fn to_bytes(&self) -> Zeroizing<[u8; SECRET_KEY_LENGTH]> {
+        Zeroizing::new(self.secret.to_bytes())
+    }
+
+    /// The public half of this key
+    pub fn verifying_key(&self) -> VerifyingKey {
+        self.verifying_key
+    }
+
+    /// The key whose secret is this one's plus `tweak` modulo the group
+    /// order, as hierarchical derivation produces children. Fails if `tweak`
+    /// is not below the order or the sum is zero.
+    pub fn add_tweak(&self, tweak: &[u8; 32]) -> Result<SigningKey, SignatureError> {
+        let tweak = Scalar::from_canonical_bytes(tweak).ok_or(SignatureError::InvalidSecretKey)?;
+        SigningKey::from_bytes(&Zeroizing::new(self.secret.add(&tweak).to_bytes()))
+    }
+
+    /// Produce a deterministic low-S signature over the SHA-256 of `message`
+    pub fn sign(&self, message: &[u8]) -> Signature {
+        self.sign_recoverable(message).0
+    }
+
+    /// The signature [`SigningKey::sign`] makes, and the [`RecoveryId`]
+    /// that recovers this key from it
+    pub fn sign_recoverable(&self, message: &[u8]) -> (Signature, RecoveryId) {
+        let digest: [u8; 32] = Sha256::digest(message).into();
+        let z = Scalar::from_bytes_reduced(&digest);
+        let mut nonces = Rfc6979::new(&Zeroizing::new(self.secret.to_bytes()), &z.to_bytes());
+        loop {
+            let mut k = nonces.next();
+            let Some((x, y)) = ProjectivePoint::basepoint().mul(&k).to_affine() else {
+                continue;
+            };
+            let r = Scalar::from_bytes_reduced(&x.to_bytes());
+            if r.is_zero() {
+                continue;
+            }
+            let s = k.invert().mul(&z.add(&r.mul(&self.secret)));
+            k.zeroize();
+            if s.is_zero() {
+                continue;
+            }
+            let mut id = y.is_odd() as u8 | ((r.to_bytes() != x.to_bytes()) as u8) << 1;
+            // Negating s stands for negating the nonce point, flipping its y
+            let s = if s.is_high() {
+                id ^= 1;
+                s.neg()
+            } else {
+                s
+            };
+
+            let mut bytes = [0u8; SIGNATURE_LENGTH];
+            bytes[..32].copy_from_slice(&r.to_bytes());
+            bytes[32..].copy_from_slice(&s.to_bytes());
+            return (Signature(bytes), RecoveryId(id));
+        }
+    }
+}
+
+#[cfg(any(test, feature = "secp256k1"))]
+impl Drop for SigningKey {
+    fn drop(&mut self) {
+        self.secret.zeroize();
+    }
+}
+
+#[cfg(any(test, feature = "secp256k1"))]
+impl ZeroizeOnDrop for SigningKey {}
+
+#[cfg(any(test, feature = "secp256k1"))]
+impl fmt::Debug for SigningKey {
+    /// The public half only
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey").field("verifying_key", &self.verifying_key).finish_non_exhaustive()
+    }
+}
+
+/// A secp256k1 public key
+#[derive(Clone, Copy)]
+pub struct VerifyingKey {
+    bytes: [u8; PUBLIC_KEY_LENGTH],
+    point: ProjectivePoint,
+}
+
+impl VerifyingKey {
+    /// Decode a SEC1 compressed public key
+    pub fn from_bytes(bytes: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Self, SignatureError> {
+        let point = ProjectivePoint::decompress(bytes).ok_or(SignatureError::InvalidPublicKey)?;
+        Ok(VerifyingKey {
+            bytes: *bytes,
+            point,
+        })
+    }
+
+    pub fn as_bytes(&self) -> &[u8; PUBLIC_KEY_LENGTH] {
+        &self.bytes
+    }
+
+    pub fn to_bytes(&self) -> [u8; PUBLIC_KEY_LENGTH] {
+        self.bytes
+    }
+
+    /// The key of the secret [`SigningKey::add_tweak`] gives: this point
+    /// plus `tweak` times the base point. Fails if `tweak` is not below the
+    /// group order or the sum is the identity.
+    pub fn add_tweak(&self, tweak: &[u8; 32]) -> Result<VerifyingKey, SignatureError> {
+        let tweak = Scalar::from_canonical_bytes(tweak).ok_or(SignatureError::InvalidSecretKey)?;
+        let point = ProjectivePoint::basepoint().mul(&tweak).add(&self.point);
+        let bytes = point.compress().ok_or(SignatureError::InvalidPublicKey)?;
+        Ok(VerifyingKey { bytes, point })
+    }
+
+    /// Check `signature` over the SHA-256 of `message`, rejecting high-S
+    /// signatures and `r` or `s` out of range
+    pub fn verify(&self, message: &[u8], signature: &Signature) -> Result<(), SignatureError> {
+        let (r, s) = signature.scalars()?;
+        let digest: [u8; 32] = Sha256::digest(message).into();
+        let z = Scalar::from_bytes_reduced(&digest);
+        let w = s.invert();
+        let point = ProjectivePoint::basepoint().mul_add(&z.mul(&w), &self.point, &r.mul(&w));
+        match point.to_affine() {
+            Some((x, _)) if Scalar::from_bytes_reduced(&x.to_bytes()) == r => Ok(()),
+            _ => Err(SignatureError::InvalidSignature),
+        }
+    }
+
+    /// The key that made `signature` over the SHA-256 of `message`, given
+    /// the [`RecoveryId`] signing returned. The signature is held to the
+    /// same rules as in [`VerifyingKey::verify`], which the recovered key
+    /// always passes; with the wrong message or id a different key, or
+    /// none, comes out.
+    #[allow(clippy::expect_used)]
+    pub fn recover(message: &[u8], signature: &Signature, id: RecoveryId) -> Result<Self, SignatureError> {
+        let (r, s) = signature.scalars()?;
+        // r is x of the nonce point reduced modulo n, so x is r or, rarely, r + n
+        let x = FieldElement::from_canonical_bytes(&r.to_bytes()).expect("n is below p");
+        let x = if id.is_x_reduced() {
+            let x = x.add(&FieldElement::from_canonical_bytes(&ORDER).expect("n is below p"));
+            // The sum wraps past p exactly when it comes out below n
+            if x.to_bytes() < ORDER {
+                return Err(SignatureError::InvalidSignature);
+            }
+            x
+        } else {
+            x
+        };
+        let mut compressed = [0u8; PUBLIC_KEY_LENGTH];
+        compressed[0] = 2 + id.is_y_odd() as u8;
+        compressed[1..].copy_from_slice(&x.to_bytes());
+        let nonce_point = ProjectivePoint::decompress(&compressed).ok_or(SignatureError::InvalidSignature)?;
+
+        let digest: [u8; 32] = Sha256::digest(message).into();
+        let z = Scalar::from_bytes_reduced(&digest);
+        let r_inv = r.invert();
+        let point = ProjectivePoint::basepoint().mul_add(&z.mul(&r_inv).neg(), &nonce_point, &s.mul(&r_inv));
+        let bytes = point.compress().ok_or(SignatureError::InvalidSignature)?;
+        Ok(VerifyingKey { bytes, point })
+    }
+}
+
+impl PartialEq for VerifyingKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.bytes == other.bytes
+    }
+}
+
+impl Eq for VerifyingKey {}
+
+impl std::hash::Hash for VerifyingKey {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.bytes.hash(state);
+    }
+}
+
+impl fmt::Debug for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "VerifyingKey({})", self)
+    }
+}
+
+impl fmt::Display for VerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.bytes))
+    }
+}
+
+impl FromStr for VerifyingKey {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        VerifyingKey::from_bytes(&super::unhex(s)?)
+    }
+}
+
+/// A compact ECDSA signature: `r` followed by `s`, both big endian
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Signature([u8; SIGNATURE_LENGTH]);
+
+impl Signature {
+    pub fn from_bytes(bytes: &[u8; SIGNATURE_LENGTH]) -> Self {
+        Signature(*bytes)
+    }
+
+    pub fn to_bytes(&self) -> [u8; SIGNATURE_LENGTH] {
+        self.0
+    }
+
+    /// `r` and `s`, refusing either out of range or zero and a high `s`
+    fn scalars(&self) -> Result<(Scalar, Scalar), SignatureError> {
+        let mut r_bytes = [0u8; 32];
+        r_bytes.copy_from_slice(&self.0[..32]);
+        let mut s_bytes = [0u8; 32];
+        s_bytes.copy_from_slice(&self.0[32..]);
+        let r = Scalar::from_canonical_bytes(&r_bytes).ok_or(SignatureError::InvalidSignature)?;
+        let s = Scalar::from_canonical_bytes(&s_bytes).ok_or(SignatureError::InvalidSignature)?;
+        if r.is_zero() || s.is_zero() || s.is_high() {
+            return Err(SignatureError::InvalidSignature);
+        }
+        Ok((r, s))
+    }
+}
+
+/// Which of the keys a signature could have come from it was made by: bit 0
+/// is the parity of y of the nonce point, and bit 1 is set when its x was at
+/// least the group order, so that `r` is x reduced
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(into = "u8", try_from = "u8")]
+pub struct RecoveryId(u8);
+
+impl RecoveryId {
+    /// The id numbered `id`, which must be below 4
+    pub fn from_u8(id: u8) -> Option<Self> {
+        (id < 4).then_some(RecoveryId(id))
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self.0
+    }
+
+    pub fn is_y_odd(self) -> bool {
+        self.0 & 1 != 0
+    }
+
+    pub fn is_x_reduced(self) -> bool {
+        self.0 & 2 != 0
+    }
+}
+
+impl TryFrom<u8> for RecoveryId {
+    type Error = SignatureError;
+
+    fn try_from(id: u8) -> Result<Self, Self::Error> {
+        RecoveryId::from_u8(id).ok_or(SignatureError::InvalidSignature)
+    }
+}
+
+impl From<RecoveryId> for u8 {
+    fn from(id: RecoveryId) -> Self {
+        id.0
+    }
+}
+
+impl fmt::Debug for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Signature({})", self)
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+/// Parses the hex of the signature bytes; whether they make a valid
+/// signature is only known on verification
+impl FromStr for Signature {
+    type Err = SignatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(Signature(super::unhex(s)?))
+    }
+}
+
+hex_serde!(VerifyingKey);
+hex_serde!(Signature);
+
+/// The deterministic nonces of RFC 6979 section 3.2 for a secret key and a
+/// reduced message hash, in the order they are tried. The state is wiped
+/// when dropped.
+#[cfg(any(test, feature = "secp256k1"))]
+struct Rfc6979 {
+    k: [u8; 32],
+    v: [u8; 32],
+    started: bool,
+}
+
+#[cfg(any(test, feature = "secp256k1"))]
+impl Rfc6979 {
+    fn new(secret: &[u8; 32], hash: &[u8; 32]) -> Self {
+        let mut nonces = Rfc6979 {
+            k: [0; 32],
+            v: [1; 32],
+            started: false,
+        };
+        nonces.k = hmac_sha256(&nonces.k, &[&nonces.v, &[0], secret, hash]);
+        nonces.v = hmac_sha256(&nonces.k, &[&nonces.v]);
+        nonces.k = hmac_sha256(&nonces.k, &[&nonces.v, &[1], secret, hash]);
+        nonces.v = hmac_sha256(&nonces.k, &[&nonces.v]);
+        nonces
+    }
+
+    /// The next candidate nonce in `[1, n)`
+    fn next(&mut self) -> Scalar {
+        loop {
+            if self.started {
+                self.k = hmac_sha256(&self.k, &[&self.v, &[0]]);
+                self.v = hmac_sha256(&self.k, &[&self.v]);
+            }
+            self.started = true;
+            self.v = hmac_sha256(&self.k, &[&self.v]);
+            if let Some(k) = Scalar::from_canonical_bytes(&self.v).filter(|k| !k.is_zero()) {
+                return k;
+            }
+        }
+    }
+}
+
+#[cfg(any(test, feature = "secp256k1"))]
+impl Drop for Rfc6979 {
+    fn drop(&mut self) {
+        self.k.zeroize();
+        self.v.zeroize();
+    }
+}
+
+/// HMAC-SHA-256 of the concatenated `parts` under a 32-byte key
+#[cfg(any(test, feature = "secp256k1"))]
+#[allow(clippy::expect_used)]
+fn hmac_sha256(key: &[u8; 32], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(secret: &str) -> SigningKey {
+        SigningKey::from_bytes(&hex::decode(secret).unwrap().try_into().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_known_answer_vectors() {
+        // Deterministic signatures widely used to cross-check RFC 6979
+        // implementations on secp256k1
+        let one = key("0000000000000000000000000000000000000000000000000000000000000001");
+        let max = key("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364140");
+        let turing = key("f8b8af8ce3c7cca5e300d33939540c10d45ce001b8f252bfbc57ba0342904181");
+        let vectors: [(&SigningKey, &[u8], &str, &str); 4] = [
+            (
+                &one,
+                b"Satoshi Nakamoto",
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                "934b1ea10a4b3c1757e2b0c017d0b6143ce3c9a7e6a4a49860d7a6ab210ee3d8\
+                 2442ce9d2b916064108014783e923ec36b49743e2ffa1c4496f01a512aafd9e5",
+            ),
+            (
+                &one,
+                b"All those moments will be lost in time, like tears in rain. Time to die...",
+                "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                "8600dbd41e348fe5c9465ab92d23e3db8b98b873beecd930736488696438cb6b\
+                 547fe64427496db33bf66019dacbf0039c04199abb0122918601db38a72cfc21",
+            ),
+            (
+                &max,
+                b"Satoshi Nakamoto",
+                "0379be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+                "fd567d121db66e382991534ada77a6bd3106f0a1098c231e47993447cd6af2d0\
+                 6b39cd0eb1bc8603e159ef5c20a5c8ad685a45b06ce9bebed3f153d10d93bed5",
+            ),
+            (
+                &turing,
+                b"Alan Turing",
+                "0292df7b245b81aa637ab4e867c8d511008f79161a97d64f2ac709600352f7acbc",
+                "7063ae83e7f62bbb171798131b4a0564b956930092b33b07b395615d9ec7e15c\
+                 58dfcc1e00a35e1572f366ffe34ba0fc47db1e7189759b9fb233c5b05ab388ea",
+            ),
+        ];
+        for (key, message, public, signature) in vectors {
+            let public: VerifyingKey = public.parse().unwrap();
+            assert_eq!(key.verifying_key(), public);
+            let sig = key.sign(message);
+            assert_eq!(sig.to_string(), signature);
+            assert_eq!(public.verify(message, &sig), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_rejects_tampering_and_high_s() {
+        let key = key("00000000000000000000000000000000000000000000000000000000000000aa");
+        let other = SigningKey::generate(&[0xbb; 32][..]).unwrap();
+        let sig = key.sign(b"message");
+
+        assert!(key.verifying_key().verify(b"massage", &sig).is_err());
+        assert!(other.verifying_key().verify(b"message", &sig).is_err());
+        let mut bytes = sig.to_bytes();
+        bytes[40] ^= 1;
+        assert!(key.verifying_key().verify(b"message", &Signature::from_bytes(&bytes)).is_err());
+
+        // The high-S twin of a valid signature verifies mathematically, but
+        // only the low form is accepted
+        let s = Scalar::from_canonical_bytes(&sig.0[32..].try_into().unwrap()).unwrap();
+        let mut high = sig.to_bytes();
+        high[32..].copy_from_slice(&s.neg().to_bytes());
+        assert_eq!(
+            key.verifying_key().verify(b"message", &Signature::from_bytes(&high)),
+            Err(SignatureError::InvalidSignature)
+        );
+
+        assert!(SigningKey::from_bytes(&[0; 32]).is_err());
+        assert!(SigningKey::from_bytes(&[0xff; 32]).is_err());
+        // A source yielding an out-of-range secret first is read again
+        let mut source = vec![0xff; 32];
+        source.extend([0x11; 32]);
+        assert_eq!(*SigningKey::generate(&source[..]).unwrap().to_bytes(), [0x11; 32]);
+    }
+
+    #[test]
+    fn test_recovers_signing_key() {
+        let key = key("f8b8af8ce3c7cca5e300d33939540c10d45ce001b8f252bfbc57ba0342904181");
+        let mut seen = [false; 4];
+        for i in 0..32u32 {
+            let message = i.to_le_bytes();
+            let (sig, id) = key.sign_recoverable(&message);
+            assert_eq!(sig, key.sign(&message));
+            seen[id.to_u8() as usize] = true;
+            assert_eq!(VerifyingKey::recover(&message, &sig, id), Ok(key.verifying_key()));
+
+            // The other parity gives another key, which the signature does not verify under
+            let flipped = RecoveryId::from_u8(id.to_u8() ^ 1).unwrap();
+            let other = VerifyingKey::recover(&message, &sig, flipped).unwrap();
+            assert_ne!(other, key.verifying_key());
+            // r + n is past p for all but a 2^-127 share of signatures
+            let reduced = RecoveryId::from_u8(id.to_u8() | 2).unwrap();
+            assert_eq!(VerifyingKey::recover(&message, &sig, reduced), Err(SignatureError::InvalidSignature));
+            // Another message recovers some other key
+            assert_ne!(VerifyingKey::recover(b"other", &sig, id), Ok(key.verifying_key()));
+        }
+        // Both parities of the nonce point turn up; x is never reduced in practice
+        assert_eq!(seen, [true, true, false, false]);
+        assert_eq!(RecoveryId::from_u8(4), None);
+
+        // The high-S twin is refused as in verification
+        let (sig, id) = key.sign_recoverable(b"message");
+        let s = Scalar::from_canonical_bytes(&sig.0[32..].try_into().unwrap()).unwrap();
+        let mut high = sig.to_bytes();
+        high[32..].copy_from_slice(&s.neg().to_bytes());
+        let flipped = RecoveryId::from_u8(id.to_u8() ^ 1).unwrap();
+        assert_eq!(
+            VerifyingKey::recover(b"message", &Signature::from_bytes(&high), flipped),
+            Err(SignatureError::InvalidSignature)
+        );
+    }
+}
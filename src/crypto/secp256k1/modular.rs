@@ -0,0 +1,355 @@
+//! Arithmetic modulo the secp256k1 field prime and group order.
+//!
+//! Both moduli are odd 256-bit numbers above 2^255, so one Montgomery
+//! implementation serves the field elements and the scalars alike.
+
+use std::marker::PhantomData;
+
+use zeroize::Zeroize;
+
+/// A 256-bit odd modulus and the constants Montgomery multiplication needs
+pub(crate) struct Params {
+    /// The modulus as four little-endian 64-bit limbs
+    m: [u64; 4],
+    /// -m^-1 mod 2^64
+    inv: u64,
+    /// 2^512 mod m, which takes values into Montgomery form
+    r2: [u64; 4],
+}
+
+impl Params {
+    const fn new(m: [u64; 4]) -> Params {
+        // Newton's iteration doubles the correct low bits each step
+        let mut inv: u64 = 1;
+        let mut i = 0;
+        while i < 6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m[0].wrapping_mul(inv)));
+            i += 1;
+        }
+
+        // Double 1 up to 2^512, reducing as we go
+        let mut r2 = [1, 0, 0, 0];
+        let mut i = 0;
+        while i < 512 {
+            let carry = r2[3] >> 63;
+            r2 = [
+                r2[0] << 1,
+                (r2[1] << 1) | (r2[0] >> 63),
+                (r2[2] << 1) | (r2[1] >> 63),
+                (r2[3] << 1) | (r2[2] >> 63),
+            ];
+            if carry == 1 || !less_than(&r2, &m) {
+                r2 = sub_limbs(&r2, &m).0;
+            }
+            i += 1;
+        }
+
+        Params {
+            m,
+            inv: inv.wrapping_neg(),
+            r2,
+        }
+    }
+}
+
+/// A modulus a [`Residue`] is taken over
+pub(crate) trait Modulus: Copy + Eq + std::fmt::Debug {
+    const PARAMS: Params;
+}
+
+/// The field prime p = 2^256 - 2^32 - 977
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FieldPrime;
+
+impl Modulus for FieldPrime {
+    const PARAMS: Params = Params::new([
+        0xffff_fffe_ffff_fc2f,
+        0xffff_ffff_ffff_ffff,
+        0xffff_ffff_ffff_ffff,
+        0xffff_ffff_ffff_ffff,
+    ]);
+}
+
+/// The order n of the base point
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct GroupOrder;
+
+impl Modulus for GroupOrder {
+    const PARAMS: Params = Params::new([
+        0xbfd2_5e8c_d036_4141,
+        0xbaae_dce6_af48_a03b,
+        0xffff_ffff_ffff_fffe,
+        0xffff_ffff_ffff_ffff,
+    ]);
+}
+
+/// An integer modulo `M`, held in Montgomery form as four little-endian limbs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Residue<M: Modulus>([u64; 4], PhantomData<M>);
+
+/// An element of the field the curve is defined over
+pub(crate) type FieldElement = Residue<FieldPrime>;
+/// An integer modulo the group order
+pub(crate) type Scalar = Residue<GroupOrder>;
+
+impl<M: Modulus> Zeroize for Residue<M> {
+    fn zeroize(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<M: Modulus> Residue<M> {
+    pub const ZERO: Self = Residue([0; 4], PhantomData);
+
+    pub fn one() -> Self {
+        Self::from_u64(1)
+    }
+
+    pub fn from_u64(value: u64) -> Self {
+        Self::from_limbs([value, 0, 0, 0])
+    }
+
+    /// Decode 32 big-endian bytes, rejecting values not below the modulus
+    pub fn from_canonical_bytes(bytes: &[u8; 32]) -> Option<Self> {
+        let limbs = limbs_from_be(bytes);
+        less_than(&limbs, &M::PARAMS.m).then(|| Self::from_limbs(limbs))
+    }
+
+    /// Decode 32 big-endian bytes, such as a hash, reducing them
+    pub fn from_bytes_reduced(bytes: &[u8; 32]) -> Self {
+        let mut limbs = limbs_from_be(bytes);
+        // The modulus is above 2^255, so one subtraction is enough
+        if !less_than(&limbs, &M::PARAMS.m) {
+            limbs = sub_limbs(&limbs, &M::PARAMS.m).0;
+        }
+        Self::from_limbs(limbs)
+    }
+
+    /// The canonical big-endian encoding
+    pub fn to_bytes(self) -> [u8; 32] {
+        let limbs = self.to_limbs();
+        let mut out = [0u8; 32];
+        for (i, limb) in limbs.iter().enumerate() {
+            out[24 - i * 8..32 - i * 8].copy_from_slice(&limb.to_be_bytes());
+        }
+        out
+    }
+
+    fn from_limbs(limbs: [u64; 4]) -> Self {
+        Residue(mont_mul(&limbs, &M::PARAMS.r2, &M::PARAMS), PhantomData)
+    }
+
+    fn to_limbs(self) -> [u64; 4] {
+        mont_mul(&self.0, &[1, 0, 0, 0], &M::PARAMS)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == [0; 4]
+    }
+
+    pub fn is_odd(&self) -> bool {
+        self.to_limbs()[0] & 1 == 1
+    }
+
+    /// Whether the value exceeds half the modulus
+    pub fn is_high(&self) -> bool {
+        let doubled = self.add(self).to_limbs();
+        // 2v wraps below v exactly when v > m / 2
+        less_than(&doubled, &self.to_limbs())
+    }
+
+    pub fn add(&self, rhs: &Self) -> Self {
+        let (sum, carry) = add_limbs(&self.0, &rhs.0);
+        if carry || !less_than(&sum, &M::PARAMS.m) {
+            Residue(sub_limbs(&sum, &M::PARAMS.m).0, PhantomData)
+        } else {
+            Residue(sum, PhantomData)
+        }
+    }
+
+    pub fn sub(&self, rhs: &Self) -> Self {
+        let (diff, borrow) = sub_limbs(&self.0, &rhs.0);
+        if borrow {
+            Residue(add_limbs(&diff, &M::PARAMS.m).0, PhantomData)
+        } else {
+            Residue(diff, PhantomData)
+        }
+    }
+
+    pub fn neg(&self) -> Self {
+        Self::ZERO.sub(self)
+    }
+
+    pub fn mul(&self, rhs: &Self) -> Self {
+        Residue(mont_mul(&self.0, &rhs.0, &M::PARAMS), PhantomData)
+    }
+
+    pub fn square(&self) -> Self {
+        self.mul(self)
+    }
+
+    /// Raise to the power `exp`, given as little-endian limbs
+    fn pow(&self, exp: &[u64; 4]) -> Self {
+        let mut result = Self::one();
+        for limb in exp.iter().rev() {
+            for bit in (0..64).rev() {
+                result = result.square();
+                if (limb >> bit) & 1 == 1 {
+                    result = result.mul(self);
+                }
+            }
+        }
+        result
+    }
+
+    /// The multiplicative inverse, by Fermat's little theorem; zero maps to zero
+    pub fn invert(&self) -> Self {
+        let exp = sub_limbs(&M::PARAMS.m, &[2, 0, 0, 0]).0;
+        self.pow(&exp)
+    }
+}
+
+impl FieldElement {
+    /// A square root, if there is one: p = 3 (mod 4), so it is the
+    /// (p + 1) / 4 power
+    pub fn sqrt(&self) -> Option<Self> {
+        let m = FieldPrime::PARAMS.m;
+        let exp = [
+            (m[0] >> 2) | (m[1] << 62),
+            (m[1] >> 2) | (m[2] << 62),
+            (m[2] >> 2) | (m[3] << 62),
+            m[3] >> 2,
+        ];
+        // (p + 1) / 4 = (p >> 2) + 1, as p = 3 (mod 4)
+        let exp = add_limbs(&exp, &[1, 0, 0, 0]).0;
+        let root = self.pow(&exp);
+        (root.square() == *self).then_some(root)
+    }
+}
+
+/// Montgomery product `a * b / 2^256 (mod m)`, for `a` and `b` below `m`
+fn mont_mul(a: &[u64; 4], b: &[u64; 4], params: &Params) -> [u64; 4] {
+    let m = &params.m;
+    let mut t = [0u64; 6];
+    for &b_i in b {
+        let mut carry: u128 = 0;
+        for j in 0..4 {
+            let x = t[j] as u128 + a[j] as u128 * b_i as u128 + carry;
+            t[j] = x as u64;
+            carry = x >> 64;
+        }
+        let x = t[4] as u128 + carry;
+        t[4] = x as u64;
+        t[5] = (x >> 64) as u64;
+
+        // Add a multiple of m clearing the low limb, then shift it out
+        let q = t[0].wrapping_mul(params.inv);
+        let x = t[0] as u128 + q as u128 * m[0] as u128;
+        let mut carry = x >> 64;
+        for j in 1..4 {
+            let x = t[j] as u128 + q as u128 * m[j] as u128 + carry;
+            t[j - 1] = x as u64;
+            carry = x >> 64;
+        }
+        let x = t[4] as u128 + carry;
+        t[3] = x as u64;
+        t[4] = t[5] + (x >> 64) as u64;
+        t[5] = 0;
+    }
+    let result = [t[0], t[1], t[2], t[3]];
+    if t[4] != 0 || !less_than(&result, m) {
+        sub_limbs(&result, m).0
+    } else {
+        result
+    }
+}
+
+fn limbs_from_be(bytes: &[u8; 32]) -> [u64; 4] {
+    let mut limbs = [0u64; 4];
+    for (i, limb) in limbs.iter_mut().enumerate() {
+        let mut word = [0u8; 8];
+        word.copy_from_slice(&bytes[24 - i * 8..32 - i * 8]);
+        *limb = u64::from_be_bytes(word);
+    }
+    limbs
+}
+
+const fn less_than(a: &[u64; 4], b: &[u64; 4]) -> bool {
+    let mut i = 4;
+    while i > 0 {
+        i -= 1;
+        if a[i] != b[i] {
+            return a[i] < b[i];
+        }
+    }
+    false
+}
+
+fn add_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut out = [0u64; 4];
+    let mut carry = false;
+    for i in 0..4 {
+        let (sum, c1) = a[i].overflowing_add(b[i]);
+        let (sum, c2) = sum.overflowing_add(carry as u64);
+        out[i] = sum;
+        carry = c1 || c2;
+    }
+    (out, carry)
+}
+
+const fn sub_limbs(a: &[u64; 4], b: &[u64; 4]) -> ([u64; 4], bool) {
+    let mut out = [0u64; 4];
+    let mut borrow = false;
+    let mut i = 0;
+    while i < 4 {
+        let (diff, b1) = a[i].overflowing_sub(b[i]);
+        let (diff, b2) = diff.overflowing_sub(borrow as u64);
+        out[i] = diff;
+        borrow = b1 || b2;
+        i += 1;
+    }
+    (out, borrow)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inverse_and_sqrt() {
+        let mut bytes = [0u8; 32];
+        bytes[31] = 7;
+        bytes[5] = 0x42;
+        let x = FieldElement::from_canonical_bytes(&bytes).unwrap();
+        assert_eq!(x.mul(&x.invert()), FieldElement::one());
+        let s = Scalar::from_canonical_bytes(&bytes).unwrap();
+        assert_eq!(s.mul(&s.invert()), Scalar::one());
+
+        let root = x.square().sqrt().unwrap();
+        assert!(root == x || root == x.neg());
+        // -1 is not a square, as p = 3 (mod 4)
+        assert_eq!(FieldElement::one().neg().sqrt(), None);
+    }
+
+    #[test]
+    fn test_bytes_round_trip_and_reduction() {
+        // n itself is not canonical and reduces to zero
+        let n = Scalar::one().neg().add(&Scalar::one());
+        assert!(n.is_zero());
+        let n_bytes: [u8; 32] =
+            hex::decode("fffffffffffffffffffffffffffffffebaaedce6af48a03bbfd25e8cd0364141")
+                .unwrap()
+                .try_into()
+                .unwrap();
+        assert_eq!(Scalar::from_canonical_bytes(&n_bytes), None);
+        assert!(Scalar::from_bytes_reduced(&n_bytes).is_zero());
+
+        let max = Scalar::one().neg();
+        let mut below = n_bytes;
+        below[31] -= 1;
+        assert_eq!(max.to_bytes(), below);
+        assert!(max.is_high());
+        assert!(!Scalar::from_u64(5).is_high());
+        assert!(Scalar::from_u64(5).is_odd());
+    }
+}
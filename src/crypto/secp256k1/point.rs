@@ -0,0 +1,197 @@
+use super::modular::{FieldElement, Scalar};
+
+/// x of the standard base point, big endian
+const BASEPOINT_X: [u8; 32] = [
+    0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07,
+    0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98,
+];
+
+/// y of the standard base point, big endian
+const BASEPOINT_Y: [u8; 32] = [
+    0x48, 0x3a, 0xda, 0x77, 0x26, 0xa3, 0xc4, 0x65, 0x5d, 0xa4, 0xfb, 0xfc, 0x0e, 0x11, 0x08, 0xa8,
+    0xfd, 0x17, 0xb4, 0x48, 0xa6, 0x85, 0x54, 0x19, 0x9c, 0x47, 0xd0, 0x8f, 0xfb, 0x10, 0xd4, 0xb8,
+];
+
+/// The curve constant b in y^2 = x^3 + b
+const B: u64 = 7;
+
+/// A point on y^2 = x^3 + 7 in projective coordinates (X:Y:Z), x = X/Z,
+/// y = Y/Z, with the identity at (0:1:0)
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ProjectivePoint {
+    x: FieldElement,
+    y: FieldElement,
+    z: FieldElement,
+}
+
+impl ProjectivePoint {
+    pub fn identity() -> Self {
+        ProjectivePoint {
+            x: FieldElement::ZERO,
+            y: FieldElement::one(),
+            z: FieldElement::ZERO,
+        }
+    }
+
+    #[allow(clippy::expect_used)]
+    pub fn basepoint() -> Self {
+        let x = FieldElement::from_canonical_bytes(&BASEPOINT_X).expect("base point x is canonical");
+        let y = FieldElement::from_canonical_bytes(&BASEPOINT_Y).expect("base point y is canonical");
+        ProjectivePoint {
+            x,
+            y,
+            z: FieldElement::one(),
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.z.is_zero()
+    }
+
+    /// Decode a SEC1 compressed point: a 2 or 3 tag giving the parity of y,
+    /// then x big endian. Rejects x off the curve or not below p.
+    pub fn decompress(bytes: &[u8; 33]) -> Option<Self> {
+        let odd = match bytes[0] {
+            2 => false,
+            3 => true,
+            _ => return None,
+        };
+        let mut x_bytes = [0u8; 32];
+        x_bytes.copy_from_slice(&bytes[1..]);
+        let x = FieldElement::from_canonical_bytes(&x_bytes)?;
+        let y = x.square().mul(&x).add(&FieldElement::from_u64(B)).sqrt()?;
+        let y = if y.is_odd() == odd { y } else { y.neg() };
+        Some(ProjectivePoint {
+            x,
+            y,
+            z: FieldElement::one(),
+        })
+    }
+
+    /// The SEC1 compressed encoding; the identity has none
+    pub fn compress(&self) -> Option<[u8; 33]> {
+        let (x, y) = self.to_affine()?;
+        let mut out = [0u8; 33];
+        out[0] = 2 + y.is_odd() as u8;
+        out[1..].copy_from_slice(&x.to_bytes());
+        Some(out)
+    }
+
+    /// The affine coordinates (x, y), or `None` for the identity
+    pub fn to_affine(self) -> Option<(FieldElement, FieldElement)> {
+        if self.is_identity() {
+            return None;
+        }
+        let z_inv = self.z.invert();
+        Some((self.x.mul(&z_inv), self.y.mul(&z_inv)))
+    }
+
+    /// Complete addition (Renes-Costello-Batina 2016, algorithm 7), correct
+    /// for every pair of points including doubling and the identity
+    pub fn add(&self, other: &ProjectivePoint) -> ProjectivePoint {
+        let b3 = FieldElement::from_u64(3 * B);
+        let (x1, y1, z1) = (&self.x, &self.y, &self.z);
+        let (x2, y2, z2) = (&other.x, &other.y, &other.z);
+
+        let t0 = x1.mul(x2);
+        let t1 = y1.mul(y2);
+        let t2 = z1.mul(z2);
+        let t3 = x1.add(y1).mul(&x2.add(y2)).sub(&t0.add(&t1));
+        let t4 = y1.add(z1).mul(&y2.add(z2)).sub(&t1.add(&t2));
+        let y3 = x1.add(z1).mul(&x2.add(z2)).sub(&t0.add(&t2));
+        let t0 = t0.add(&t0).add(&t0);
+        let t2 = b3.mul(&t2);
+        let z3 = t1.add(&t2);
+        let t1 = t1.sub(&t2);
+        let y3 = b3.mul(&y3);
+        let x3 = t3.mul(&t1).sub(&t4.mul(&y3));
+        let y3 = t1.mul(&z3).add(&y3.mul(&t0));
+        let z3 = z3.mul(&t4).add(&t0.mul(&t3));
+        ProjectivePoint {
+            x: x3,
+            y: y3,
+            z: z3,
+        }
+    }
+
+    /// Multiply by `scalar`, by double-and-add from the top bit
+    pub fn mul(&self, scalar: &Scalar) -> ProjectivePoint {
+        let mut result = ProjectivePoint::identity();
+        for byte in scalar.to_bytes() {
+            for bit in (0..8).rev() {
+                result = result.add(&result);
+                if (byte >> bit) & 1 == 1 {
+                    result = result.add(self);
+                }
+            }
+        }
+        result
+    }
+
+    /// `a * self + b * other`, sharing the doublings
+    pub fn mul_add(&self, a: &Scalar, other: &ProjectivePoint, b: &Scalar) -> ProjectivePoint {
+        let both = self.add(other);
+        let mut result = ProjectivePoint::identity();
+        for (a_byte, b_byte) in a.to_bytes().into_iter().zip(b.to_bytes()) {
+            for bit in (0..8).rev() {
+                result = result.add(&result);
+                match ((a_byte >> bit) & 1, (b_byte >> bit) & 1) {
+                    (1, 1) => result = result.add(&both),
+                    (1, 0) => result = result.add(self),
+                    (0, 1) => result = result.add(other),
+                    _ => {}
+                }
+            }
+        }
+        result
+    }
+}
+
+impl PartialEq for ProjectivePoint {
+    /// Compare the points represented, cross-multiplying out the Z
+    fn eq(&self, other: &Self) -> bool {
+        self.x.mul(&other.z) == other.x.mul(&self.z) && self.y.mul(&other.z) == other.y.mul(&self.z)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(compressed: &str) -> ProjectivePoint {
+        ProjectivePoint::decompress(&hex::decode(compressed).unwrap().try_into().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_small_multiples_of_the_basepoint() {
+        let g = ProjectivePoint::basepoint();
+        let two_g = point("02c6047f9441ed7d6d3045406e95c07cd85c778e4b8cef3ca7abac09b95c709ee5");
+        let three_g = point("02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9");
+        assert_eq!(g.add(&g), two_g);
+        assert_eq!(g.add(&two_g), three_g);
+        assert_eq!(g.mul(&Scalar::from_u64(3)), three_g);
+        assert_eq!(g.mul_add(&Scalar::from_u64(2), &two_g, &Scalar::one()), g.mul(&Scalar::from_u64(4)));
+        assert_eq!(g.compress(), point(&hex::encode(g.compress().unwrap())).compress());
+
+        // n G is the identity, and adding it changes nothing
+        let n_minus_one = Scalar::one().neg();
+        assert!(g.mul(&n_minus_one).add(&g).is_identity());
+        assert_eq!(g.mul(&n_minus_one), point("0379be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"));
+        assert_eq!(g.add(&ProjectivePoint::identity()), g);
+        assert_eq!(ProjectivePoint::identity().compress(), None);
+    }
+
+    #[test]
+    fn test_decompress_rejects_bad_encodings() {
+        let mut bytes = [0u8; 33];
+        bytes[0] = 4;
+        assert!(ProjectivePoint::decompress(&bytes).is_none());
+        // x = 0 gives y^2 = 7, which has no root
+        bytes[0] = 2;
+        assert!(ProjectivePoint::decompress(&bytes).is_none());
+        // x = p is not canonical
+        bytes[1..].copy_from_slice(&FieldElement::one().neg().to_bytes());
+        bytes[32] += 1;
+        assert!(ProjectivePoint::decompress(&bytes).is_none());
+    }
+}
@@ -0,0 +1,426 @@
+use std::cmp::Ordering;
+
+/// A proof-of-work requirement.
+///
+/// Both forms describe a 256-bit target: a block hash, read as a big-endian
+/// integer, meets the requirement when it is less than or equal to the target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Difficulty {
+    /// The hash must start with at least this many zero bits
+    LeadingZeroBits(u32),
+    /// A Bitcoin-style "nBits" compact encoding of the target
+    CompactTarget(u32),
+}
+
+const MAX_TARGET: [u8; 32] = [0xff; 32];
+
+impl Difficulty {
+    /// The target as a big-endian 256-bit integer.
+    ///
+    /// `LeadingZeroBits(n)` maps to `2^(256 - n) - 1`. Compact targets that
+    /// overflow 256 bits are clamped to the maximum, and negative or zero
+    /// compact targets map to zero.
+    pub fn to_target(&self) -> [u8; 32] {
+        match *self {
+            Difficulty::LeadingZeroBits(bits) => {
+                let mut target = MAX_TARGET;
+                let bits = bits.min(256) as usize;
+                for byte in target.iter_mut().take(bits / 8) {
+                    *byte = 0;
+                }
+                if bits < 256 && !bits.is_multiple_of(8) {
+                    target[bits / 8] = 0xff >> (bits % 8);
+                }
+                target
+            }
+            Difficulty::CompactTarget(compact) => compact_to_target(compact),
+        }
+    }
+
+    /// The compact encoding of the smallest representable target at or above this one.
+    ///
+    /// For `LeadingZeroBits(n)` this encodes `2^(256 - n)` exactly, so every hash
+    /// with `n` leading zero bits also meets the compact form.
+    pub fn to_compact(&self) -> u32 {
+        match *self {
+            Difficulty::LeadingZeroBits(bits) => pow2_to_compact(256 - bits.min(256)),
+            Difficulty::CompactTarget(compact) => target_to_compact(&compact_to_target(compact)),
+        }
+    }
+
+    /// This difficulty as it would be committed to a block header
+    pub fn normalized(&self) -> Difficulty {
+        Difficulty::CompactTarget(self.to_compact())
+    }
+
+    /// The compact target closest to `target` without exceeding it
+    pub fn from_target(target: &[u8; 32]) -> Difficulty {
+        Difficulty::CompactTarget(target_to_compact(target))
+    }
+
+    /// Whether `hash`, read as a big-endian integer, is at or below the target
+    pub fn is_met_by(&self, hash: &[u8]) -> bool {
+        if hash.len() != 32 {
+            return false;
+        }
+        hash.cmp(&self.to_target()[..]) != Ordering::Greater
+    }
+
+    /// The compact difficulty whose target is this one's multiplied by
+    /// `numerator / denominator`, clamped to the maximum target.
+    ///
+    /// A ratio above one makes blocks easier to find. `None` if `denominator` is zero.
+    pub fn scaled(&self, numerator: u64, denominator: u64) -> Option<Difficulty> {
+        if denominator == 0 {
+            return None;
+        }
+        let scaled = U320::from_be_bytes(&self.to_target())
+            .mul_u64(numerator)
+            .div_u64(denominator);
+        if scaled.0[4] != 0 {
+            return Some(Difficulty::from_target(&MAX_TARGET));
+        }
+        Some(Difficulty::from_target(&scaled.to_be_bytes()))
+    }
+
+    /// Expected number of hashes needed to meet this difficulty, `2^256 / (target + 1)`,
+    /// saturating at `u128::MAX`
+    pub fn work(&self) -> u128 {
+        if let Difficulty::LeadingZeroBits(bits) = *self {
+            return if bits >= 128 { u128::MAX } else { 1u128 << bits };
+        }
+
+        let divisor = U320::from_be_bytes(&self.to_target()).add_one();
+        let mut remainder = U320::ZERO;
+        let mut quotient: u128 = 0;
+        for bit in (0..=256u32).rev() {
+            remainder = remainder.shl1();
+            if bit == 256 {
+                remainder.0[0] |= 1;
+            }
+            if remainder >= divisor {
+                remainder = remainder.sub(&divisor);
+                if bit >= 128 {
+                    return u128::MAX;
+                }
+                quotient |= 1u128 << bit;
+            }
+        }
+        quotient
+    }
+}
+
+/// Accumulated proof of work, saturating at `u128::MAX` instead of overflowing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Work(pub u128);
+
+impl Work {
+    pub const ZERO: Work = Work(0);
+
+    /// Work of a single block committing to `difficulty`
+    pub fn of(difficulty: Difficulty) -> Work {
+        Work(difficulty.work())
+    }
+}
+
+impl std::ops::Add for Work {
+    type Output = Work;
+
+    fn add(self, other: Work) -> Work {
+        Work(self.0.saturating_add(other.0))
+    }
+}
+
+impl std::ops::AddAssign for Work {
+    fn add_assign(&mut self, other: Work) {
+        *self = *self + other;
+    }
+}
+
+impl std::iter::Sum for Work {
+    fn sum<I: Iterator<Item = Work>>(iter: I) -> Work {
+        iter.fold(Work::ZERO, |total, work| total + work)
+    }
+}
+
+impl std::fmt::Display for Work {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Decode a compact target: a one-byte size, a sign bit, and a 23-bit mantissa
+fn compact_to_target(compact: u32) -> [u8; 32] {
+    let size = (compact >> 24) as i64;
+    let mantissa = compact & 0x007f_ffff;
+    if compact & 0x0080_0000 != 0 || mantissa == 0 {
+        return [0; 32];
+    }
+
+    // The mantissa's three bytes occupy big-endian positions 32 - size .. 35 - size
+    let mut target = [0u8; 32];
+    let bytes = [(mantissa >> 16) as u8, (mantissa >> 8) as u8, mantissa as u8];
+    for (i, byte) in bytes.iter().enumerate() {
+        let pos = 32 - size + i as i64;
+        if pos < 0 {
+            if *byte != 0 {
+                return MAX_TARGET;
+            }
+        } else if pos < 32 {
+            target[pos as usize] = *byte;
+        }
+    }
+    target
+}
+
+/// Encode a target, truncating it to the three most significant bytes
+fn target_to_compact(target: &[u8; 32]) -> u32 {
+    let first = match target.iter().position(|&b| b != 0) {
+        Some(first) => first,
+        None => return 0,
+    };
+    let mut size = (32 - first) as u32;
+    let byte = |i: usize| target.get(i).copied().unwrap_or(0) as u32;
+    let mut mantissa = (byte(first) << 16) | (byte(first + 1) << 8) | byte(first + 2);
+
+    // Keep the sign bit clear by moving one byte into the exponent
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    (size << 24) | mantissa
+}
+
+/// Encode `2^k` for `0 <= k <= 256` exactly
+fn pow2_to_compact(k: u32) -> u32 {
+    let mut size = k / 8 + 1;
+    let mut mantissa = (1u32 << (k % 8)) << 16;
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        size += 1;
+    }
+    (size << 24) | mantissa
+}
+
+/// Minimal little-endian unsigned integer wide enough for `2^256`
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct U320([u64; 5]);
+
+impl U320 {
+    const ZERO: U320 = U320([0; 5]);
+
+    fn from_be_bytes(bytes: &[u8; 32]) -> Self {
+        let mut limbs = [0u64; 5];
+        for (i, chunk) in bytes.rchunks(8).enumerate() {
+            let mut word = [0u8; 8];
+            word.copy_from_slice(chunk);
+            limbs[i] = u64::from_be_bytes(word);
+        }
+        U320(limbs)
+    }
+
+    /// The low 256 bits as big-endian bytes
+    fn to_be_bytes(self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        for (chunk, limb) in bytes.rchunks_mut(8).zip(self.0.iter()) {
+            chunk.copy_from_slice(&limb.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Multiply by `rhs`; a 256-bit value times a `u64` always fits
+    fn mul_u64(self, rhs: u64) -> Self {
+        let mut out = [0u64; 5];
+        let mut carry = 0u128;
+        for (out, limb) in out.iter_mut().zip(self.0.iter()) {
+            let product = *limb as u128 * rhs as u128 + carry;
+            *out = product as u64;
+            carry = product >> 64;
+        }
+        U320(out)
+    }
+
+    fn div_u64(self, rhs: u64) -> Self {
+        let mut out = [0u64; 5];
+        let mut remainder = 0u128;
+        for (out, limb) in out.iter_mut().zip(self.0.iter()).rev() {
+            let dividend = (remainder << 64) | *limb as u128;
+            *out = (dividend / rhs as u128) as u64;
+            remainder = dividend % rhs as u128;
+        }
+        U320(out)
+    }
+
+    fn add_one(mut self) -> Self {
+        for limb in self.0.iter_mut() {
+            let (sum, carry) = limb.overflowing_add(1);
+            *limb = sum;
+            if !carry {
+                break;
+            }
+        }
+        self
+    }
+
+    fn shl1(self) -> Self {
+        let mut out = [0u64; 5];
+        let mut carry = 0;
+        for (limb, out) in self.0.iter().zip(out.iter_mut()) {
+            *out = (limb << 1) | carry;
+            carry = limb >> 63;
+        }
+        U320(out)
+    }
+
+    fn sub(&self, rhs: &Self) -> Self {
+        let mut out = [0u64; 5];
+        let mut borrow = false;
+        for (out, (a, b)) in out.iter_mut().zip(self.0.iter().zip(rhs.0.iter())) {
+            let (d1, b1) = a.overflowing_sub(*b);
+            let (d2, b2) = d1.overflowing_sub(borrow as u64);
+            *out = d2;
+            borrow = b1 || b2;
+        }
+        U320(out)
+    }
+}
+
+impl PartialOrd for U320 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for U320 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.iter().rev().cmp(other.0.iter().rev())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::testing;
+
+    /// The hash just above `target`, if there is one
+    fn successor(target: &[u8; 32]) -> Option<[u8; 32]> {
+        let mut next = *target;
+        for byte in next.iter_mut().rev() {
+            let (sum, carry) = byte.overflowing_add(1);
+            *byte = sum;
+            if !carry {
+                return Some(next);
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn test_leading_zero_bits_boundaries() {
+        for bits in 0..=256u32 {
+            let difficulty = Difficulty::LeadingZeroBits(bits);
+            let target = difficulty.to_target();
+            assert!(difficulty.is_met_by(&target));
+            match successor(&target) {
+                // The first hash with only bits - 1 leading zeros
+                Some(next) => assert!(!difficulty.is_met_by(&next)),
+                None => assert_eq!(bits, 0),
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn test_compact_round_trip(target in testing::hash32(), shift in 0..256usize) {
+            // Spread the targets across magnitudes by zeroing a random prefix
+            let mut target = target.0;
+            for byte in target.iter_mut().take(shift / 8) {
+                *byte = 0;
+            }
+
+            let compact = Difficulty::from_target(&target);
+            let rounded = compact.to_target();
+            // Truncation never makes the target easier
+            prop_assert!(rounded <= target);
+            prop_assert_eq!(Difficulty::from_target(&rounded), compact);
+            prop_assert_eq!(Difficulty::CompactTarget(compact.to_compact()), compact);
+
+            // Boundary hashes for the compact form
+            prop_assert!(compact.is_met_by(&rounded));
+            if let Some(next) = successor(&rounded) {
+                prop_assert!(!compact.is_met_by(&next));
+            }
+        }
+
+        #[test]
+        fn test_representations_agree(bits in 0..=255u32, hash in testing::hash32()) {
+            let zero_bits = Difficulty::LeadingZeroBits(bits);
+            let compact = Difficulty::CompactTarget(zero_bits.to_compact());
+            prop_assert!(compact.to_target() >= zero_bits.to_target());
+
+            let mut hash = hash.0;
+            for i in 0..bits as usize {
+                hash[i / 8] &= !(0x80 >> (i % 8));
+            }
+            prop_assert!(zero_bits.is_met_by(&hash));
+            prop_assert!(compact.is_met_by(&hash));
+        }
+
+        #[test]
+        fn test_scaling_moves_the_target(mantissa in 0..0x80_0000u32, harder_by in 1..=16u64, easier_by in 1..=16u64) {
+            let difficulty = Difficulty::CompactTarget(0x1a00_0000 | mantissa);
+            let harder = difficulty.scaled(1, harder_by).unwrap();
+            let easier = difficulty.scaled(easier_by, 1).unwrap();
+            prop_assert!(harder.to_target() <= difficulty.to_target());
+            prop_assert!(easier.to_target() >= difficulty.to_target());
+        }
+    }
+
+    #[test]
+    fn test_known_compact_values() {
+        // Bitcoin's genesis difficulty: 0x00000000ffff0000...
+        let genesis = Difficulty::CompactTarget(0x1d00_ffff);
+        let mut expected = [0u8; 32];
+        expected[4] = 0xff;
+        expected[5] = 0xff;
+        assert_eq!(genesis.to_target(), expected);
+        assert_eq!(genesis.to_compact(), 0x1d00_ffff);
+        assert_eq!(genesis.work(), 0x1_0001_0001);
+
+        assert_eq!(Difficulty::LeadingZeroBits(0).to_compact(), 0x2101_0000);
+        assert_eq!(Difficulty::CompactTarget(0x2101_0000).to_target(), MAX_TARGET);
+        assert_eq!(Difficulty::CompactTarget(0x0180_0000).to_target(), [0; 32]);
+    }
+
+    #[test]
+    fn test_scaled() {
+        let base = Difficulty::CompactTarget(0x1d00_ffff);
+        assert_eq!(base.scaled(1, 1), Some(base));
+        assert_eq!(base.scaled(2, 1), Some(Difficulty::CompactTarget(0x1d01_fffe)));
+        assert_eq!(base.scaled(1, 4), Some(Difficulty::CompactTarget(0x1c3f_ffc0)));
+        assert_eq!(base.scaled(u64::MAX, 1), Some(Difficulty::from_target(&MAX_TARGET)));
+        assert_eq!(base.scaled(1, 0), None);
+    }
+
+    #[test]
+    fn test_work() {
+        assert_eq!(Difficulty::LeadingZeroBits(0).work(), 1);
+        assert_eq!(Difficulty::LeadingZeroBits(20).work(), 1 << 20);
+        assert_eq!(Difficulty::LeadingZeroBits(200).work(), u128::MAX);
+        assert_eq!(Difficulty::CompactTarget(0x2101_0000).work(), 1);
+
+        // One compact step above 2^(256 - n) is slightly less work than n zero bits
+        let compact = Difficulty::CompactTarget(Difficulty::LeadingZeroBits(32).to_compact());
+        assert_eq!(compact.work(), (1 << 32) - 1);
+        assert_eq!(Difficulty::CompactTarget(0).work(), u128::MAX);
+
+        let total: Work = [8, 10, 12]
+            .map(|bits| Work::of(Difficulty::LeadingZeroBits(bits)))
+            .into_iter()
+            .sum();
+        assert_eq!(total, Work(256 + 1024 + 4096));
+        assert_eq!(Work(u128::MAX) + Work(1), Work(u128::MAX));
+    }
+}
@@ -0,0 +1,302 @@
+//! Hex and base64 text for hashes, txids and raw bytes, parsed the same way
+//! wherever it is accepted.
+//!
+//! Parsing is strict: hex must have an even number of digits, with an
+//! optional `0x` or `0X` prefix, and base64 must be padded with `=` to a
+//! multiple of four characters, with zero bits in the padding, so each byte
+//! string has exactly one base64 form. Both accept either case of letter
+//! where the encoding has one, and write lowercase hex. An error names the
+//! byte offset in the input, prefix included, at which parsing failed.
+
+use thiserror::Error;
+
+use crate::hash::Hash32;
+
+/// The base64 alphabet (RFC 4648), padded with '='
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Reasons hex or base64 text is refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum EncodingError {
+    /// The hex has an odd number of digits; `position` is the last one,
+    /// which has no pair
+    #[error("odd number of hex digits, unpaired at position {position}")]
+    OddLength { position: usize },
+    /// `character`, at `position`, is not a digit of the encoding
+    #[error("invalid character {character:?} at position {position}")]
+    InvalidCharacter { position: usize, character: char },
+    /// The base64 ends in a group of fewer than four characters starting at
+    /// `position`
+    #[error("incomplete base64 group at position {position}")]
+    Truncated { position: usize },
+    /// Base64 padding at `position` is misplaced, or the character there
+    /// leaves bits set that the padding drops
+    #[error("invalid base64 padding at position {position}")]
+    InvalidPadding { position: usize },
+    /// The text decoded to `got` bytes where `expected` were needed
+    #[error("expected {expected} bytes, got {got}")]
+    WrongLength { expected: usize, got: usize },
+}
+
+/// `bytes` in lowercase hex, without a prefix
+pub fn to_hex(bytes: &[u8]) -> String {
+    hex::encode(bytes)
+}
+
+/// The bytes whose hex is `s`, which may start with `0x` or `0X`
+pub fn from_hex(s: &str) -> Result<Vec<u8>, EncodingError> {
+    let offset = if s.starts_with("0x") || s.starts_with("0X") {
+        2
+    } else {
+        0
+    };
+    let mut out = Vec::with_capacity((s.len() - offset) / 2);
+    let mut high = None;
+    for (index, character) in s[offset..].char_indices() {
+        let digit = character
+            .to_digit(16)
+            .ok_or(EncodingError::InvalidCharacter {
+                position: offset + index,
+                character,
+            })? as u8;
+        match high.take() {
+            Some(high) => out.push(high << 4 | digit),
+            None => high = Some(digit),
+        }
+    }
+    if high.is_some() {
+        // Every character was a hex digit, so each is one byte
+        return Err(EncodingError::OddLength {
+            position: s.len() - 1,
+        });
+    }
+    Ok(out)
+}
+
+/// `bytes` in padded base64
+pub fn to_base64(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut group = [0u8; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let value = u32::from_be_bytes([0, group[0], group[1], group[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(value >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The bytes [`to_base64`] made `s` from
+pub fn from_base64(s: &str) -> Result<Vec<u8>, EncodingError> {
+    let padding = s.bytes().rev().take_while(|&c| c == b'=').count();
+    let data = &s[..s.len() - padding];
+    let mut digits = Vec::with_capacity(data.len());
+    for (position, character) in data.char_indices() {
+        match BASE64_ALPHABET.iter().position(|&a| a as char == character) {
+            Some(digit) => digits.push(digit as u32),
+            None if character == '=' => return Err(EncodingError::InvalidPadding { position }),
+            None => {
+                return Err(EncodingError::InvalidCharacter {
+                    position,
+                    character,
+                })
+            }
+        }
+    }
+    if !s.len().is_multiple_of(4) {
+        return Err(EncodingError::Truncated {
+            position: s.len() - s.len() % 4,
+        });
+    }
+    if padding > 2 {
+        return Err(EncodingError::InvalidPadding {
+            position: data.len(),
+        });
+    }
+
+    let mut out = Vec::with_capacity(digits.len() / 4 * 3);
+    for chunk in digits.chunks(4) {
+        let missing = 4 - chunk.len();
+        let value = chunk.iter().fold(0, |value, digit| value << 6 | digit) << (6 * missing);
+        if value & ((1 << (8 * missing)) - 1) != 0 {
+            return Err(EncodingError::InvalidPadding {
+                position: data.len() - 1,
+            });
+        }
+        out.extend_from_slice(&value.to_be_bytes()[1..4 - missing]);
+    }
+    Ok(out)
+}
+
+/// A 32-byte hash written as 64 hex digits, now [`Hash32`]
+#[deprecated(note = "use Hash32")]
+pub type HexHash32 = Hash32;
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::testing;
+
+    proptest! {
+        #[test]
+        fn test_round_trip_random_bytes(bytes in vec(any::<u8>(), 0..80)) {
+            let hex = to_hex(&bytes);
+            prop_assert_eq!(from_hex(&hex), Ok(bytes.clone()));
+            prop_assert_eq!(from_hex(&format!("0x{}", hex)), Ok(bytes.clone()));
+            prop_assert_eq!(from_hex(&hex.to_uppercase()), Ok(bytes.clone()));
+            let base64 = to_base64(&bytes);
+            prop_assert_eq!(base64.len(), bytes.len().div_ceil(3) * 4);
+            prop_assert_eq!(from_base64(&base64), Ok(bytes));
+        }
+
+        #[test]
+        fn test_hashes_round_trip(hash in testing::hash32()) {
+            let hex = to_hex(&hash.0);
+            prop_assert_eq!(hex.parse(), Ok(hash));
+            prop_assert_eq!(hash.to_string(), hex);
+        }
+    }
+
+    #[test]
+    fn test_known_encodings() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0xff]), "00abff");
+        assert_eq!(from_hex(""), Ok(Vec::new()));
+        assert_eq!(from_hex("0x"), Ok(Vec::new()));
+        assert_eq!(to_base64(b""), "");
+        assert_eq!(to_base64(b"f"), "Zg==");
+        assert_eq!(to_base64(b"fo"), "Zm8=");
+        assert_eq!(to_base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_malformed_hex() {
+        use EncodingError::*;
+
+        let cases: &[(&str, EncodingError)] = &[
+            ("abc", OddLength { position: 2 }),
+            ("0xabc", OddLength { position: 4 }),
+            ("0", OddLength { position: 0 }),
+            (
+                "zz",
+                InvalidCharacter {
+                    position: 0,
+                    character: 'z',
+                },
+            ),
+            (
+                "0x12g4",
+                InvalidCharacter {
+                    position: 4,
+                    character: 'g',
+                },
+            ),
+            (
+                "12 34",
+                InvalidCharacter {
+                    position: 2,
+                    character: ' ',
+                },
+            ),
+            // A bad character is reported before the odd length
+            (
+                "ab-",
+                InvalidCharacter {
+                    position: 2,
+                    character: '-',
+                },
+            ),
+            // Only a leading prefix, and only one
+            (
+                "0x0x12",
+                InvalidCharacter {
+                    position: 3,
+                    character: 'x',
+                },
+            ),
+            (
+                "12é4",
+                InvalidCharacter {
+                    position: 2,
+                    character: 'é',
+                },
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(from_hex(input), Err(*expected), "{}", input);
+        }
+
+        assert_eq!(
+            "abcd".parse::<Hash32>(),
+            Err(WrongLength {
+                expected: 32,
+                got: 2
+            })
+        );
+        assert_eq!(
+            format!("0x{}", "00".repeat(33)).parse::<Hash32>(),
+            Err(WrongLength {
+                expected: 32,
+                got: 33
+            })
+        );
+    }
+
+    #[test]
+    fn test_malformed_base64() {
+        use EncodingError::*;
+
+        let cases: &[(&str, EncodingError)] = &[
+            ("Zg=", Truncated { position: 0 }),
+            ("Zm9vYm", Truncated { position: 4 }),
+            ("Zg", Truncated { position: 0 }),
+            ("Z===", InvalidPadding { position: 1 }),
+            ("====", InvalidPadding { position: 0 }),
+            ("Zg==Zg==", InvalidPadding { position: 2 }),
+            // Bits the padding drops must be zero
+            ("Zh==", InvalidPadding { position: 1 }),
+            ("Zm9=", InvalidPadding { position: 2 }),
+            (
+                "Zm9v!mFy",
+                InvalidCharacter {
+                    position: 4,
+                    character: '!',
+                },
+            ),
+            (
+                "Zm9vYmF-",
+                InvalidCharacter {
+                    position: 7,
+                    character: '-',
+                },
+            ),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(from_base64(input), Err(*expected), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_error_messages() {
+        assert_eq!(
+            from_hex("0x12g4").unwrap_err().to_string(),
+            "invalid character 'g' at position 4"
+        );
+        assert_eq!(
+            from_hex("abc").unwrap_err().to_string(),
+            "odd number of hex digits, unpaired at position 2"
+        );
+        assert_eq!(
+            from_base64("Zh==").unwrap_err().to_string(),
+            "invalid base64 padding at position 1"
+        );
+    }
+}
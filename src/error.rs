@@ -0,0 +1,154 @@
+//! The crate-wide error, for callers that drive several modules and want one
+//! error type to propagate with `?`.
+//!
+//! Each module reports its own failures with its own enum, which says
+//! exactly what can go wrong there; [`Error`] wraps any of them, and its
+//! message is that of the module error it holds.
+
+use crate::block::{BlockDecodeError, BlockError};
+use crate::chain::ChainError;
+use crate::codec::DecodeError;
+use crate::mempool::MempoolError;
+use crate::merkle_trie::MerkleError;
+use crate::net::NetError;
+use crate::state::StateError;
+use crate::store::StoreError;
+use crate::utxo::UtxoError;
+use crate::validation::ValidationError;
+#[cfg(any(test, feature = "crypto"))]
+use crate::wallet::WalletError;
+
+/// Any of the crate's module errors
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Error {
+    /// Bytes are not a valid encoding
+    #[error(transparent)]
+    Decode(DecodeError),
+    /// A Merkle tree or proof cannot be made
+    #[error(transparent)]
+    Merkle(MerkleError),
+    /// A block cannot be assembled
+    #[error(transparent)]
+    Block(BlockError),
+    /// A block cannot be read from a stream
+    #[error(transparent)]
+    BlockDecode(BlockDecodeError),
+    /// A block breaks the consensus rules
+    #[error(transparent)]
+    Validation(ValidationError),
+    /// A block's transfers do not apply to the account state
+    #[error(transparent)]
+    State(StateError),
+    /// A block's transactions do not apply to the unspent outputs
+    #[error(transparent)]
+    Utxo(UtxoError),
+    /// A block store failed
+    #[error(transparent)]
+    Store(StoreError),
+    /// A block cannot be added to the chain
+    #[error(transparent)]
+    Chain(ChainError),
+    /// A transaction is not accepted into the mempool
+    #[error(transparent)]
+    Mempool(MempoolError),
+    /// A message could not be sent or received
+    #[error(transparent)]
+    Net(NetError),
+    /// A wallet cannot build a transaction or keep up with the chain
+    #[cfg(any(test, feature = "crypto"))]
+    #[error(transparent)]
+    Wallet(WalletError),
+}
+
+macro_rules! impl_from {
+    ($($variant:ident($err:ty)),* $(,)?) => {
+        $(
+            impl From<$err> for Error {
+                fn from(err: $err) -> Self {
+                    Error::$variant(err)
+                }
+            }
+        )*
+    };
+}
+
+impl_from!(
+    Decode(DecodeError),
+    Merkle(MerkleError),
+    Block(BlockError),
+    BlockDecode(BlockDecodeError),
+    Validation(ValidationError),
+    State(StateError),
+    Utxo(UtxoError),
+    Store(StoreError),
+    Chain(ChainError),
+    Mempool(MempoolError),
+    Net(NetError),
+);
+
+#[cfg(any(test, feature = "crypto"))]
+impl_from!(Wallet(WalletError));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{Block, BlockHash};
+
+    fn fails() -> Result<Block, Error> {
+        let block = Block::new(vec![b"tx".to_vec()], BlockHash::ZERO)?;
+        let proof = block.merkle_tree().generate_proof(1)?;
+        Ok(Block::from_bytes(proof.root_hash(), &Default::default())?)
+    }
+
+    #[test]
+    fn test_errors_convert_and_keep_their_messages() {
+        assert_eq!(
+            fails(),
+            Err(Error::Merkle(MerkleError::IndexOutOfRange {
+                index: 1,
+                leaves: 1,
+            }))
+        );
+        let cases: Vec<(Error, &str)> = vec![
+            (
+                BlockError::NoTransactions.into(),
+                "a block needs at least one transaction",
+            ),
+            (
+                MerkleError::IndexOutOfRange {
+                    index: 4,
+                    leaves: 3,
+                }
+                .into(),
+                "leaf index 4 is out of range for a tree of 3 leaves",
+            ),
+            (
+                ChainError::UnknownParent(BlockHash::from_bytes([0xab; 32])).into(),
+                "parent block abababababababababababababababababababababababababababababababab is unknown",
+            ),
+            (
+                NetError::BadMagic {
+                    expected: 1,
+                    got: 2,
+                }
+                .into(),
+                "frame magic 00000002 is not 00000001",
+            ),
+            (
+                WalletError::InsufficientFunds {
+                    available: 5,
+                    required: 8,
+                }
+                .into(),
+                "8 is required but only 5 is spendable",
+            ),
+            (
+                DecodeError::UnexpectedEof.into(),
+                "unexpected end of input",
+            ),
+        ];
+        for (err, message) in cases {
+            assert_eq!(err.to_string(), message);
+        }
+    }
+}
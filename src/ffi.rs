@@ -0,0 +1,253 @@
+//! A C interface for checking merkle proofs and hashing headers from other
+//! languages, declared for C and C++ in `include/aarwyn.h`.
+//!
+//! Memory ownership is the caller's throughout. Every pointer passed in is
+//! only read or written during the call and never kept, and results are
+//! written to buffers the caller allocates and frees. No function hands out
+//! memory Rust allocated, so there is nothing to free on this side. A null
+//! pointer is accepted only with a length of zero.
+//!
+//! Every function returns an `int32_t`: [`AARWYN_OK`] or another
+//! non-negative result on success, or one of the negative `AARWYN_ERR_`
+//! codes. A panic is caught before it can unwind into the caller and is
+//! reported as [`AARWYN_ERR_PANIC`].
+
+use std::panic::{self, AssertUnwindSafe};
+use std::slice;
+
+use crate::block::BlockHeader;
+use crate::codec::DecodeLimits;
+use crate::merkle_trie::{MerkleProof, MerkleTree};
+
+/// Length of a hash, and of the output buffers that receive one
+pub const AARWYN_HASH_LEN: usize = 32;
+
+pub const AARWYN_OK: i32 = 0;
+/// A pointer was null with a non-zero length
+pub const AARWYN_ERR_NULL_POINTER: i32 = -1;
+/// Input bytes did not decode
+pub const AARWYN_ERR_DECODE: i32 = -2;
+/// The output buffer is shorter than [`AARWYN_HASH_LEN`]
+pub const AARWYN_ERR_BUFFER_TOO_SMALL: i32 = -3;
+/// A hash was given with a length other than [`AARWYN_HASH_LEN`]
+pub const AARWYN_ERR_INVALID_LENGTH: i32 = -4;
+/// A merkle root was asked for no leaves
+pub const AARWYN_ERR_EMPTY: i32 = -5;
+/// The call panicked
+pub const AARWYN_ERR_PANIC: i32 = -6;
+
+/// Check that `proof`, a proof in the format of [`MerkleProof::to_bytes`],
+/// places the leaf `data` under `root`. Returns 1 if it does and 0 if not.
+///
+/// # Safety
+///
+/// Each pointer must be valid for reads of its length, or null with a
+/// length of zero.
+#[no_mangle]
+pub unsafe extern "C" fn aarwyn_verify_proof(
+    root: *const u8,
+    root_len: usize,
+    data: *const u8,
+    data_len: usize,
+    proof: *const u8,
+    proof_len: usize,
+) -> i32 {
+    guard(|| {
+        let root = input(root, root_len)?;
+        let data = input(data, data_len)?;
+        let proof = input(proof, proof_len)?;
+        if root.len() != AARWYN_HASH_LEN {
+            return Err(AARWYN_ERR_INVALID_LENGTH);
+        }
+        let proof = MerkleProof::from_bytes(proof, &DecodeLimits::default())
+            .map_err(|_| AARWYN_ERR_DECODE)?;
+        Ok((proof.root_hash() == root && proof.verify(data)) as i32)
+    })
+}
+
+/// Write the hash of the block whose header is encoded in `header`, as
+/// [`BlockHeader::to_bytes`] writes it, to the first [`AARWYN_HASH_LEN`]
+/// bytes of `out`.
+///
+/// # Safety
+///
+/// `header` must be valid for reads of `header_len` bytes and `out` for
+/// writes of `out_len`, each or null with a length of zero.
+#[no_mangle]
+pub unsafe extern "C" fn aarwyn_block_hash(
+    header: *const u8,
+    header_len: usize,
+    out: *mut u8,
+    out_len: usize,
+) -> i32 {
+    guard(|| {
+        let header = input(header, header_len)?;
+        let out = output(out, out_len)?;
+        let header = BlockHeader::from_bytes(header).map_err(|_| AARWYN_ERR_DECODE)?;
+        out[..AARWYN_HASH_LEN].copy_from_slice(header.hash().as_bytes());
+        Ok(AARWYN_OK)
+    })
+}
+
+/// Write the merkle root of `leaf_count` leaves to the first
+/// [`AARWYN_HASH_LEN`] bytes of `out`. Leaf `i` is the `leaf_lens[i]`
+/// bytes at `leaves[i]`.
+///
+/// # Safety
+///
+/// `leaves` and `leaf_lens` must each be valid for reads of `leaf_count`
+/// elements, each leaf pointer valid for reads of its length or null with
+/// a length of zero, and `out` valid for writes of `out_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn aarwyn_merkle_root(
+    leaves: *const *const u8,
+    leaf_lens: *const usize,
+    leaf_count: usize,
+    out: *mut u8,
+    out_len: usize,
+) -> i32 {
+    guard(|| {
+        if leaf_count == 0 {
+            return Err(AARWYN_ERR_EMPTY);
+        }
+        if leaves.is_null() || leaf_lens.is_null() {
+            return Err(AARWYN_ERR_NULL_POINTER);
+        }
+        let pointers = slice::from_raw_parts(leaves, leaf_count);
+        let lens = slice::from_raw_parts(leaf_lens, leaf_count);
+        let leaves = pointers
+            .iter()
+            .zip(lens)
+            .map(|(leaf, len)| input(*leaf, *len))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tree = MerkleTree::new(&leaves).map_err(|_| AARWYN_ERR_EMPTY)?;
+        let out = output(out, out_len)?;
+        out[..AARWYN_HASH_LEN].copy_from_slice(tree.root_hash());
+        Ok(AARWYN_OK)
+    })
+}
+
+/// Run `call`, turning its error or a panic into the code returned
+fn guard(call: impl FnOnce() -> Result<i32, i32>) -> i32 {
+    match panic::catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(result)) | Ok(Err(result)) => result,
+        Err(_) => AARWYN_ERR_PANIC,
+    }
+}
+
+/// The `len` bytes at `ptr`, borrowed for the call
+unsafe fn input<'a>(ptr: *const u8, len: usize) -> Result<&'a [u8], i32> {
+    match (ptr.is_null(), len) {
+        (_, 0) => Ok(&[]),
+        (true, _) => Err(AARWYN_ERR_NULL_POINTER),
+        (false, _) => Ok(slice::from_raw_parts(ptr, len)),
+    }
+}
+
+/// The caller's buffer of `len` bytes at `ptr`, if it holds a hash
+unsafe fn output<'a>(ptr: *mut u8, len: usize) -> Result<&'a mut [u8], i32> {
+    if len < AARWYN_HASH_LEN {
+        return Err(AARWYN_ERR_BUFFER_TOO_SMALL);
+    }
+    if ptr.is_null() {
+        return Err(AARWYN_ERR_NULL_POINTER);
+    }
+    Ok(slice::from_raw_parts_mut(ptr, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+    use crate::block::{BlockBuilder, BlockHash};
+
+    #[test]
+    fn test_verify_proof() {
+        let leaves: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 3]).collect();
+        let tree = MerkleTree::new(&leaves).unwrap();
+        let root = tree.root_hash();
+        let proof = tree.generate_proof(2).unwrap().to_bytes();
+        let verify = |root: &[u8], data: &[u8], proof: &[u8]| unsafe {
+            aarwyn_verify_proof(
+                root.as_ptr(),
+                root.len(),
+                data.as_ptr(),
+                data.len(),
+                proof.as_ptr(),
+                proof.len(),
+            )
+        };
+
+        assert_eq!(verify(root, &leaves[2], &proof), 1);
+        assert_eq!(verify(root, &leaves[1], &proof), 0);
+        assert_eq!(verify(&[0; 32], &leaves[2], &proof), 0);
+        assert_eq!(
+            verify(&root[..31], &leaves[2], &proof),
+            AARWYN_ERR_INVALID_LENGTH
+        );
+        assert_eq!(verify(root, &leaves[2], &proof[..40]), AARWYN_ERR_DECODE);
+        let null = unsafe {
+            aarwyn_verify_proof(
+                root.as_ptr(),
+                32,
+                ptr::null(),
+                3,
+                proof.as_ptr(),
+                proof.len(),
+            )
+        };
+        assert_eq!(null, AARWYN_ERR_NULL_POINTER);
+    }
+
+    #[test]
+    fn test_block_hash_and_merkle_root() {
+        let block = BlockBuilder::new(BlockHash::from_bytes([0; 32]))
+            .transactions([b"a".to_vec(), b"bc".to_vec(), Vec::new()])
+            .timestamp(1_700_000_000)
+            .build()
+            .unwrap();
+        let header = block.header().to_bytes();
+        let mut out = [0u8; 40];
+        let code = unsafe {
+            aarwyn_block_hash(header.as_ptr(), header.len(), out.as_mut_ptr(), out.len())
+        };
+        assert_eq!(code, AARWYN_OK);
+        assert_eq!(&out[..32], block.hash().as_bytes());
+        let code = unsafe { aarwyn_block_hash(header.as_ptr(), 10, out.as_mut_ptr(), out.len()) };
+        assert_eq!(code, AARWYN_ERR_DECODE);
+        let code =
+            unsafe { aarwyn_block_hash(header.as_ptr(), header.len(), out.as_mut_ptr(), 31) };
+        assert_eq!(code, AARWYN_ERR_BUFFER_TOO_SMALL);
+
+        // The empty leaf may be a null pointer
+        let pointers = [b"a".as_ptr(), b"bc".as_ptr(), ptr::null()];
+        let lens = [1, 2, 0];
+        let mut root = [0u8; 32];
+        let code = unsafe {
+            aarwyn_merkle_root(pointers.as_ptr(), lens.as_ptr(), 3, root.as_mut_ptr(), 32)
+        };
+        assert_eq!(code, AARWYN_OK);
+        assert_eq!(root.as_slice(), block.header().merkle_root());
+        let code = unsafe {
+            aarwyn_merkle_root(pointers.as_ptr(), lens.as_ptr(), 0, root.as_mut_ptr(), 32)
+        };
+        assert_eq!(code, AARWYN_ERR_EMPTY);
+        let code = unsafe {
+            aarwyn_merkle_root(
+                pointers.as_ptr(),
+                [1, 2, 5].as_ptr(),
+                3,
+                root.as_mut_ptr(),
+                32,
+            )
+        };
+        assert_eq!(code, AARWYN_ERR_NULL_POINTER);
+    }
+
+    #[test]
+    fn test_panics_do_not_cross() {
+        assert_eq!(guard(|| panic!("caught")), AARWYN_ERR_PANIC);
+        assert_eq!(guard(|| Err(AARWYN_ERR_DECODE)), AARWYN_ERR_DECODE);
+    }
+}
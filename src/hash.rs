@@ -0,0 +1,253 @@
+//! The 32-byte hash every Merkle root, proof node and leaf hash is held as.
+//!
+//! A fixed-size array rather than a `Vec<u8>` means a hash of the wrong
+//! length cannot be built, comparing two hashes allocates nothing, and a
+//! header field can never be shifted by a short hash before it. Block
+//! hashes and txids keep their own types, [`BlockHash`] and [`Txid`], so they
+//! cannot be mixed up with roots; both convert to and from [`Hash32`].
+//!
+//! [`BlockHash`]: crate::block::BlockHash
+//! [`Txid`]: crate::transaction::Txid
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use sha2::digest::Output;
+use sha2::Sha256;
+
+use crate::encoding::{from_hex, to_hex, EncodingError};
+
+/// A 32-byte hash, written as 64 hex digits.
+///
+/// Human-readable serde formats such as JSON hold it as that hex string, and
+/// also read it as a list of 32 numbers, as a `Vec<u8>` was written. Binary
+/// formats hold it as a byte string, which bincode and postcard encode as a
+/// `Vec<u8>` of the same bytes would be.
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hash32(pub [u8; 32]);
+
+impl Hash32 {
+    pub const ZERO: Hash32 = Hash32([0; 32]);
+
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Hash32(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn into_bytes(self) -> [u8; 32] {
+        self.0
+    }
+
+    pub fn to_vec(&self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+impl AsRef<[u8]> for Hash32 {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; 32]> for Hash32 {
+    fn from(bytes: [u8; 32]) -> Self {
+        Hash32(bytes)
+    }
+}
+
+impl From<Hash32> for [u8; 32] {
+    fn from(hash: Hash32) -> Self {
+        hash.0
+    }
+}
+
+impl From<Output<Sha256>> for Hash32 {
+    fn from(digest: Output<Sha256>) -> Self {
+        Hash32(digest.into())
+    }
+}
+
+impl TryFrom<&[u8]> for Hash32 {
+    type Error = EncodingError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        bytes
+            .try_into()
+            .map(Hash32)
+            .map_err(|_| EncodingError::WrongLength {
+                expected: 32,
+                got: bytes.len(),
+            })
+    }
+}
+
+impl PartialEq<[u8]> for Hash32 {
+    fn eq(&self, other: &[u8]) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<Hash32> for [u8] {
+    fn eq(&self, other: &Hash32) -> bool {
+        self == other.0
+    }
+}
+
+impl fmt::Display for Hash32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&to_hex(&self.0))
+    }
+}
+
+impl fmt::Debug for Hash32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash32({})", self)
+    }
+}
+
+impl FromStr for Hash32 {
+    type Err = EncodingError;
+
+    /// The hash whose 64 hex digits are `s`, in either case and with an
+    /// optional `0x` prefix
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = from_hex(s)?;
+        Hash32::try_from(bytes.as_slice())
+    }
+}
+
+impl Serialize for Hash32 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_bytes(&self.0)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Hash32 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            deserializer.deserialize_any(Hash32Visitor)
+        } else {
+            deserializer.deserialize_bytes(Hash32Visitor)
+        }
+    }
+}
+
+struct Hash32Visitor;
+
+impl<'de> Visitor<'de> for Hash32Visitor {
+    type Value = Hash32;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a 32-byte hash")
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Hash32, E> {
+        s.parse().map_err(E::custom)
+    }
+
+    fn visit_bytes<E: de::Error>(self, bytes: &[u8]) -> Result<Hash32, E> {
+        Hash32::try_from(bytes).map_err(|_| E::invalid_length(bytes.len(), &self))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Hash32, A::Error> {
+        let mut bytes = [0; 32];
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(index, &self))?;
+        }
+        if seq.next_element::<u8>()?.is_some() {
+            return Err(de::Error::invalid_length(33, &self));
+        }
+        Ok(Hash32(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::hash32;
+    use proptest::prelude::*;
+    use sha2::Digest;
+
+    #[test]
+    fn test_hex_and_conversions() {
+        let hash = Hash32::from(Sha256::digest(b"abc"));
+        let hex = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(hash.to_string(), hex);
+        assert_eq!(hex.parse(), Ok(hash));
+        assert_eq!(format!("0x{}", hex.to_uppercase()).parse(), Ok(hash));
+        assert_eq!(
+            hex[..62].parse::<Hash32>(),
+            Err(EncodingError::WrongLength {
+                expected: 32,
+                got: 31
+            })
+        );
+        assert_eq!(Hash32::try_from(hash.as_ref()), Ok(hash));
+        assert_eq!(hash, *hash.as_ref());
+        assert_eq!(<[u8; 32]>::from(hash), hash.0);
+        assert!(Hash32::ZERO < hash);
+    }
+
+    proptest! {
+        #[test]
+        fn test_any_hash_round_trips(hash in hash32()) {
+            prop_assert_eq!(hash.to_string().parse(), Ok(hash));
+            prop_assert_eq!(Hash32::try_from(hash.as_ref()), Ok(hash));
+            let json = serde_json::to_string(&hash).unwrap();
+            prop_assert_eq!(serde_json::from_str::<Hash32>(&json).unwrap(), hash);
+            let bincode = crate::codec::to_bincode(&hash).unwrap();
+            let decoded: Hash32 = crate::codec::from_bincode(&bincode, &Default::default()).unwrap();
+            prop_assert_eq!(decoded, hash);
+        }
+    }
+
+    #[test]
+    fn test_wrong_lengths_are_refused() {
+        // A 31-byte hash cannot be made, so it can never shift the fields
+        // encoded after it
+        for len in [0, 31, 33, 64] {
+            assert_eq!(
+                Hash32::try_from(&vec![0xab; len][..]),
+                Err(EncodingError::WrongLength {
+                    expected: 32,
+                    got: len
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_serde_forms() {
+        let hash = Hash32([0xab; 32]);
+        let json = serde_json::to_string(&hash).unwrap();
+        assert_eq!(json, format!("\"{}\"", "ab".repeat(32)));
+        assert_eq!(serde_json::from_str::<Hash32>(&json).unwrap(), hash);
+        assert!(serde_json::from_str::<Hash32>("\"abab\"").is_err());
+        let list = serde_json::to_string(&hash.to_vec()).unwrap();
+        assert_eq!(serde_json::from_str::<Hash32>(&list).unwrap(), hash);
+        let short = serde_json::to_string(&vec![0xab_u8; 31]).unwrap();
+        assert!(serde_json::from_str::<Hash32>(&short).is_err());
+
+        // Binary formats see the bytes of a 32-byte `Vec<u8>`
+        let bincode = crate::codec::to_bincode(&hash).unwrap();
+        assert_eq!(bincode, crate::codec::to_bincode(&hash.to_vec()).unwrap());
+        let decoded: Hash32 = crate::codec::from_bincode(&bincode, &Default::default()).unwrap();
+        assert_eq!(decoded, hash);
+        let postcard = postcard::to_allocvec(&hash).unwrap();
+        assert_eq!(postcard, postcard::to_allocvec(&hash.to_vec()).unwrap());
+        assert_eq!(postcard::from_bytes::<Hash32>(&postcard).unwrap(), hash);
+        let short = postcard::to_allocvec(&vec![0xab_u8; 31]).unwrap();
+        assert!(postcard::from_bytes::<Hash32>(&short).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+#![deny(clippy::unwrap_used, clippy::expect_used)]
+
+pub mod address;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod bitcoin;
+pub mod block;
+#[cfg(feature = "serde")]
+pub mod canonical_json;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+pub mod chain;
+pub mod checkpoint;
+#[cfg(any(feature = "client", feature = "client-async"))]
+pub mod client;
+pub mod codec;
+pub mod crypto;
+pub mod difficulty;
+pub mod encoding;
+pub mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod hash;
+pub mod mempool;
+pub mod merkle_trie;
+pub mod net;
+#[cfg(feature = "tokio")]
+pub mod node;
+pub mod params;
+#[cfg(feature = "proto")]
+pub mod proto;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod retarget;
+#[cfg(feature = "rlp")]
+pub mod rlp;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "ssz")]
+pub mod ssz;
+pub mod state;
+pub mod store;
+#[cfg(any(test, feature = "vectors"))]
+pub mod test_vectors;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+mod trace;
+pub mod transaction;
+pub mod utxo;
+pub mod validation;
+#[cfg(any(test, feature = "crypto"))]
+pub mod wallet;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use error::Error;
+pub use hash::Hash32;
@@ -1,6 +1,3 @@
-pub mod merkle_trie;
-pub mod block;
-
 fn main() {
     println!("Hello, world!");
 }
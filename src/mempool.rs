@@ -0,0 +1,1457 @@
+//! Transactions waiting to be mined.
+//!
+//! The pool never holds two transactions spending the same output: a
+//! transaction conflicting with a pooled one is refused with
+//! [`MempoolError::Conflict`], naming the pooled transaction so the caller
+//! can decide whether to remove it in favour of the new one. A pool built
+//! with [`Mempool::with_replacement`] instead replaces the pooled
+//! transaction when the new one pays enough more.
+//!
+//! Each transaction is pooled with the fee it pays, and blocks are filled in
+//! order of fee rate, the fee per byte of the transaction's encoding. A
+//! transaction spending the outputs of pooled ones is ranked together with
+//! those unmined ancestors, by the fee rate of the whole package, so a child
+//! paying well can pull in a parent paying little.
+
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::block::{Block, BlockHash, BlockLimits};
+use crate::params::{Clock, SystemClock};
+use crate::transaction::{FeeError, Locked, OutPoint, SigError, Transaction, TxOutput, Txid};
+use crate::utxo::{UtxoSet, UtxoView};
+
+/// Default cap on the number of transactions a [`Mempool`] holds
+pub const DEFAULT_MAX_MEMPOOL_TRANSACTIONS: usize = 50_000;
+/// Default cap on the total encoded size of the transactions a [`Mempool`] holds
+pub const DEFAULT_MAX_MEMPOOL_BYTES: usize = 64 * 1024 * 1024;
+/// Default cap on the pooled ancestors of a pooled transaction, itself included
+pub const DEFAULT_MAX_ANCESTORS: usize = 25;
+/// Default cap on the pooled descendants of a pooled transaction, itself included
+pub const DEFAULT_MAX_DESCENDANTS: usize = 25;
+
+/// Reasons a transaction is refused by the [`Mempool`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MempoolError {
+    /// The transaction is already in the pool
+    #[error("transaction {0} is already pooled")]
+    AlreadyPooled(Txid),
+    /// The transaction spends the same output twice
+    #[error("transaction spends {}:{} twice", .0.txid, .0.index)]
+    DuplicateInput(OutPoint),
+    /// The transaction spends an output the pooled `existing_txid` spends
+    #[error("transaction conflicts with pooled {existing_txid}")]
+    Conflict { existing_txid: Txid },
+    /// The pool is at its limits and room cannot be made by evicting
+    /// transactions paying a lower fee rate
+    #[error("mempool is full")]
+    PoolFull,
+    /// The transaction would replace pooled ones it conflicts with, but its
+    /// fee of `offered` is below the `required` bump over their fee rates
+    #[error("replacement pays a fee of {offered} but at least {required} is required")]
+    InsufficientFeeBump { required: u64, offered: u64 },
+    /// The transaction's lock time keeps it out of the next block
+    #[error("transaction is {0}")]
+    NotFinal(Locked),
+    /// The transaction would have `count` pooled ancestors, itself included,
+    /// more than the pool's limit of `max`
+    #[error("transaction would have {count} pooled ancestors, more than {max}")]
+    TooManyAncestors { count: usize, max: usize },
+    /// The pooled `ancestor` would have `count` pooled descendants, itself
+    /// included, more than the pool's limit of `max`
+    #[error("pooled {ancestor} would have {count} descendants, more than {max}")]
+    TooManyDescendants {
+        ancestor: Txid,
+        count: usize,
+        max: usize,
+    },
+}
+
+/// A pooled transaction with its fee and encoded size
+#[derive(Clone, Debug)]
+struct Entry {
+    tx: Transaction,
+    fee: u64,
+    size: usize,
+    /// Exempt from eviction and expiry
+    pinned: bool,
+    /// Unix time the transaction was pooled at
+    inserted_at: u64,
+}
+
+impl Entry {
+    /// Compare fee rates exactly, without dividing
+    fn cmp_rate(&self, other: &Entry) -> Ordering {
+        (self.fee as u128 * other.size as u128).cmp(&(other.fee as u128 * self.size as u128))
+    }
+}
+
+/// A package of pooled transactions ranked for a block: `txid` with its
+/// ancestors not yet chosen, paying `fee` over `size` bytes in all
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Candidate {
+    fee: u128,
+    size: usize,
+    txid: Txid,
+}
+
+/// Better packages are greater: a higher fee rate, then a lower txid
+impl Ord for Candidate {
+    fn cmp(&self, other: &Candidate) -> Ordering {
+        (self.fee * other.size as u128)
+            .cmp(&(other.fee * self.size as u128))
+            .then(other.txid.cmp(&self.txid))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Candidate) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A pool of transactions by txid, with an index of the outputs they spend.
+///
+/// A transaction may spend the outputs of pooled ones, which are its
+/// ancestors, up to the limits set by [`Mempool::with_package_limits`].
+///
+/// The pool is bounded by transaction count and total encoded size. When
+/// either would be exceeded, transactions no other pooled one spends from are
+/// evicted, lowest fee rate first, to make room for one paying more.
+/// Transactions pinned on insert are never evicted or expired, and a pinned
+/// transaction may evict others whatever their fee rate.
+#[derive(Clone, Debug)]
+pub struct Mempool {
+    entries: HashMap<Txid, Entry>,
+    /// The pooled transaction spending each output
+    spenders: HashMap<OutPoint, Txid>,
+    max_count: usize,
+    max_bytes: usize,
+    total_bytes: usize,
+    clock: Arc<dyn Clock>,
+    /// Fee rate a replacement must add over what it replaces, if
+    /// replacement is allowed
+    replacement_increment: Option<u64>,
+    /// Height of the chain's tip, whose child transactions must be final in
+    tip_height: u64,
+    max_ancestors: usize,
+    max_descendants: usize,
+}
+
+/// A transaction admitted by [`Mempool::insert_with`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Inserted {
+    pub txid: Txid,
+    /// Transactions evicted to make room, lowest fee rate first
+    pub evicted: Vec<Txid>,
+    /// Transactions replaced, those the new one conflicts with and then
+    /// every pooled transaction spending from them
+    pub replaced: Vec<Txid>,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Self::with_limits(DEFAULT_MAX_MEMPOOL_TRANSACTIONS, DEFAULT_MAX_MEMPOOL_BYTES)
+    }
+}
+
+impl Mempool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty pool holding at most `max_count` transactions of at most
+    /// `max_bytes` in total
+    pub fn with_limits(max_count: usize, max_bytes: usize) -> Self {
+        Mempool {
+            entries: HashMap::new(),
+            spenders: HashMap::new(),
+            max_count,
+            max_bytes,
+            total_bytes: 0,
+            clock: Arc::new(SystemClock),
+            replacement_increment: None,
+            tip_height: 0,
+            max_ancestors: DEFAULT_MAX_ANCESTORS,
+            max_descendants: DEFAULT_MAX_DESCENDANTS,
+        }
+    }
+
+    /// Time entries by `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Let a transaction replace the pooled ones it conflicts with if its fee
+    /// rate beats each of theirs by at least `increment` per byte, and its
+    /// fee covers the fees of everything it replaces, their descendants
+    /// included, plus `increment` per byte of its own. Replacing a package
+    /// then never costs miners fees, nor relays it for free.
+    ///
+    /// Without this, conflicting transactions are refused with
+    /// [`MempoolError::Conflict`].
+    pub fn with_replacement(mut self, increment: u64) -> Self {
+        self.replacement_increment = Some(increment);
+        self
+    }
+
+    /// Refuse a transaction that would leave it, or any pooled ancestor of
+    /// it, with more than `max_ancestors` pooled ancestors or `max_descendants`
+    /// pooled descendants, counting itself
+    pub fn with_package_limits(mut self, max_ancestors: usize, max_descendants: usize) -> Self {
+        self.max_ancestors = max_ancestors;
+        self.max_descendants = max_descendants;
+        self
+    }
+
+    /// Follow the chain's tip to `height`, so transactions are admitted only
+    /// if final in the block above it; the tip is taken to be the genesis
+    /// block until set. Pooled transactions are left as they are.
+    pub fn set_tip_height(&mut self, height: u64) {
+        self.tip_height = height;
+    }
+
+    /// Add `tx`, paying `fee`, to the pool and return its txid.
+    ///
+    /// Refused if it is already pooled, is not final in the block above the
+    /// tip (see [`Mempool::set_tip_height`]) stamped at the clock's time,
+    /// spends an output twice, spends an output a pooled transaction spends
+    /// and cannot replace it (see [`Mempool::with_replacement`]), exceeds the
+    /// package limits (see [`Mempool::with_package_limits`]), or does not fit
+    /// in the pool.
+    pub fn insert(&mut self, tx: Transaction, fee: u64) -> Result<Txid, MempoolError> {
+        self.insert_with(tx, fee, false)
+            .map(|inserted| inserted.txid)
+    }
+
+    /// Like [`Mempool::insert`], also reporting the transactions evicted to
+    /// make room and those replaced; a `pinned` transaction is never evicted
+    /// or expired
+    pub fn insert_with(
+        &mut self,
+        tx: Transaction,
+        fee: u64,
+        pinned: bool,
+    ) -> Result<Inserted, MempoolError> {
+        let txid = tx.txid();
+        if self.entries.contains_key(&txid) {
+            return Err(MempoolError::AlreadyPooled(txid));
+        }
+        tx.check_final(self.tip_height + 1, self.clock.now())
+            .map_err(MempoolError::NotFinal)?;
+        let mut conflicts = Vec::new();
+        for (i, input) in tx.inputs.iter().enumerate() {
+            let out = input.prev_out;
+            if tx.inputs[..i].iter().any(|earlier| earlier.prev_out == out) {
+                return Err(MempoolError::DuplicateInput(out));
+            }
+            if let Some(&existing_txid) = self.spenders.get(&out) {
+                if self.replacement_increment.is_none() {
+                    return Err(MempoolError::Conflict { existing_txid });
+                }
+                if !conflicts.contains(&existing_txid) {
+                    conflicts.push(existing_txid);
+                }
+            }
+        }
+
+        self.check_package_limits(&tx)?;
+
+        let size = tx.encode().len();
+        let entry = Entry {
+            tx,
+            fee,
+            size,
+            pinned,
+            inserted_at: self.clock.now(),
+        };
+        let replaced = self.descendants(&conflicts);
+        if let Some(increment) = self.replacement_increment {
+            let rate = conflicts
+                .iter()
+                .map(|txid| self.replacement_fee(&self.entries[txid], size, increment))
+                .max()
+                .unwrap_or(0);
+            let total = replaced
+                .iter()
+                .map(|txid| self.entries[txid].fee as u128)
+                .sum::<u128>()
+                + increment as u128 * size as u128;
+            let required = rate.max(u64::try_from(total).unwrap_or(u64::MAX));
+            if !replaced.is_empty() && fee < required {
+                return Err(MempoolError::InsufficientFeeBump {
+                    required,
+                    offered: fee,
+                });
+            }
+        }
+        let evicted = self.make_room(&entry, &replaced)?;
+        for txid in replaced.iter().chain(&evicted) {
+            self.remove(txid);
+        }
+        for input in &entry.tx.inputs {
+            self.spenders.insert(input.prev_out, txid);
+        }
+        self.total_bytes += size;
+        self.entries.insert(txid, entry);
+        Ok(Inserted {
+            txid,
+            evicted,
+            replaced,
+        })
+    }
+
+    /// Refuse `tx` if pooling it would break the ancestor or descendant limit
+    fn check_package_limits(&self, tx: &Transaction) -> Result<(), MempoolError> {
+        let ancestors = self.ancestors(tx);
+        if ancestors.len() + 1 > self.max_ancestors {
+            return Err(MempoolError::TooManyAncestors {
+                count: ancestors.len() + 1,
+                max: self.max_ancestors,
+            });
+        }
+        let mut ancestors: Vec<Txid> = ancestors.into_iter().collect();
+        ancestors.sort_unstable();
+        for ancestor in ancestors {
+            let count = self.descendants(&[ancestor]).len() + 1;
+            if count > self.max_descendants {
+                return Err(MempoolError::TooManyDescendants {
+                    ancestor,
+                    count,
+                    max: self.max_descendants,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// The pooled transactions `tx` spends from, directly or through others
+    fn ancestors(&self, tx: &Transaction) -> HashSet<Txid> {
+        let mut found = HashSet::new();
+        let mut pending: Vec<Txid> = tx.inputs.iter().map(|input| input.prev_out.txid).collect();
+        while let Some(txid) = pending.pop() {
+            let Some(entry) = self.entries.get(&txid) else {
+                continue;
+            };
+            if found.insert(txid) {
+                pending.extend(entry.tx.inputs.iter().map(|input| input.prev_out.txid));
+            }
+        }
+        found
+    }
+
+    /// The total fee and size of the pooled transaction `txid` and its
+    /// pooled ancestors, whose fee rate ranks it for a block
+    pub fn package_fee(&self, txid: &Txid) -> Option<(u64, usize)> {
+        let entry = self.entries.get(txid)?;
+        let package = self.ancestors(&entry.tx);
+        let fee = package
+            .iter()
+            .map(|txid| self.entries[txid].fee as u128)
+            .sum::<u128>()
+            + entry.fee as u128;
+        let size = package
+            .iter()
+            .map(|txid| self.entries[txid].size)
+            .sum::<usize>()
+            + entry.size;
+        Some((u64::try_from(fee).unwrap_or(u64::MAX), size))
+    }
+
+    /// The pooled transaction `txid` and its pooled ancestors outside
+    /// `selected`, parents before children
+    fn package(&self, txid: Txid, selected: &HashSet<Txid>) -> Vec<Txid> {
+        // Depth first, with each transaction pushed again to be placed once
+        // everything it spends from is
+        let mut package = Vec::new();
+        let mut seen = HashSet::new();
+        let mut pending = vec![(txid, false)];
+        while let Some((txid, parents_placed)) = pending.pop() {
+            if parents_placed {
+                package.push(txid);
+                continue;
+            }
+            if !self.entries.contains_key(&txid) || selected.contains(&txid) || !seen.insert(txid) {
+                continue;
+            }
+            pending.push((txid, true));
+            for input in &self.entries[&txid].tx.inputs {
+                pending.push((input.prev_out.txid, false));
+            }
+        }
+        package
+    }
+
+    /// The package [`Mempool::select_for_block`] ranks `txid` by: it and its
+    /// pooled ancestors not in `selected`
+    fn candidate(&self, txid: Txid, selected: &HashSet<Txid>) -> Candidate {
+        let package = self.package(txid, selected);
+        Candidate {
+            fee: package
+                .iter()
+                .map(|txid| self.entries[txid].fee as u128)
+                .sum(),
+            size: package.iter().map(|txid| self.entries[txid].size).sum(),
+            txid,
+        }
+    }
+
+    /// The least fee a transaction of `size` bytes must pay to replace
+    /// `entry`: its fee rate plus `increment` per byte, rounded up
+    fn replacement_fee(&self, entry: &Entry, size: usize, increment: u64) -> u64 {
+        let (size, old_size) = (size as u128, entry.size as u128);
+        let matched = (entry.fee as u128 * size).div_ceil(old_size);
+        u64::try_from(matched + increment as u128 * size).unwrap_or(u64::MAX)
+    }
+
+    /// `roots` and every pooled transaction spending from them, directly or
+    /// through others, parents before children
+    fn descendants(&self, roots: &[Txid]) -> Vec<Txid> {
+        let mut found = roots.to_vec();
+        let mut seen: HashSet<Txid> = roots.iter().copied().collect();
+        let mut next = 0;
+        while let Some(&txid) = found.get(next) {
+            next += 1;
+            for index in 0..self.entries[&txid].tx.outputs.len() as u32 {
+                if let Some(child) = self.spender_of(&OutPoint { txid, index }) {
+                    if seen.insert(child) {
+                        found.push(child);
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// The transactions to evict so that `entry` fits once `replaced` are gone
+    fn make_room(&self, entry: &Entry, replaced: &[Txid]) -> Result<Vec<Txid>, MempoolError> {
+        if entry.size > self.max_bytes || self.max_count == 0 {
+            return Err(MempoolError::PoolFull);
+        }
+        let parents: HashSet<Txid> = entry
+            .tx
+            .inputs
+            .iter()
+            .map(|input| input.prev_out.txid)
+            .collect();
+        let mut candidates: Vec<(&Txid, &Entry)> = self
+            .entries
+            .iter()
+            .filter(|(txid, candidate)| {
+                !candidate.pinned
+                    && !replaced.contains(txid)
+                    && !parents.contains(*txid)
+                    && !self.has_children(txid, candidate)
+            })
+            .collect();
+        candidates.sort_by(|a, b| a.1.cmp_rate(b.1).then(a.0.cmp(b.0)));
+
+        let mut count = self.entries.len() - replaced.len();
+        let mut bytes = self.total_bytes
+            - replaced
+                .iter()
+                .map(|txid| self.entries[txid].size)
+                .sum::<usize>();
+        let mut evicted = Vec::new();
+        let mut candidates = candidates.into_iter();
+        while count >= self.max_count || bytes + entry.size > self.max_bytes {
+            match candidates.next() {
+                Some((txid, candidate))
+                    if entry.pinned || candidate.cmp_rate(entry) == Ordering::Less =>
+                {
+                    evicted.push(*txid);
+                    count -= 1;
+                    bytes -= candidate.size;
+                }
+                _ => return Err(MempoolError::PoolFull),
+            }
+        }
+        Ok(evicted)
+    }
+
+    /// Whether a pooled transaction spends an output of `entry`
+    fn has_children(&self, txid: &Txid, entry: &Entry) -> bool {
+        (0..entry.tx.outputs.len() as u32)
+            .any(|index| self.spenders.contains_key(&OutPoint { txid: *txid, index }))
+    }
+
+    /// Remove the transaction `txid`, freeing the outputs it spends.
+    ///
+    /// Pooled transactions spending its outputs stay pooled.
+    pub fn remove(&mut self, txid: &Txid) -> Option<Transaction> {
+        let entry = self.entries.remove(txid)?;
+        for input in &entry.tx.inputs {
+            self.spenders.remove(&input.prev_out);
+        }
+        self.total_bytes -= entry.size;
+        Some(entry.tx)
+    }
+
+    /// Remove the transaction `txid` and every pooled transaction spending
+    /// its outputs, directly or through others
+    fn remove_with_descendants(&mut self, txid: &Txid) {
+        for txid in self.descendants(&[*txid]) {
+            self.remove(&txid);
+        }
+    }
+
+    /// Remove the unpinned transactions pooled more than `older_than` ago
+    /// and return their txids, oldest first.
+    ///
+    /// A stale transaction is kept while a fresh or pinned one spends from
+    /// it, and expires along with the last of them.
+    pub fn expire(&mut self, older_than: Duration) -> Vec<Txid> {
+        let cutoff = self.clock.now().saturating_sub(older_than.as_secs());
+        let mut stale: Vec<(u64, Txid)> = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| !entry.pinned && entry.inserted_at < cutoff)
+            .map(|(txid, entry)| (entry.inserted_at, *txid))
+            .collect();
+
+        // Each pass takes the stale transactions nothing pooled spends from,
+        // which may free their parents for the next
+        let mut expired = Vec::new();
+        loop {
+            let before = expired.len();
+            stale.retain(|&(inserted_at, txid)| {
+                if self.has_children(&txid, &self.entries[&txid]) {
+                    return true;
+                }
+                self.remove(&txid);
+                expired.push((inserted_at, txid));
+                false
+            });
+            if expired.len() == before {
+                break;
+            }
+        }
+        expired.sort_unstable();
+        expired.into_iter().map(|(_, txid)| txid).collect()
+    }
+
+    pub fn contains(&self, txid: &Txid) -> bool {
+        self.entries.contains_key(txid)
+    }
+
+    /// The pooled transactions with their txids, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&Txid, &Transaction)> {
+        self.entries.iter().map(|(txid, entry)| (txid, &entry.tx))
+    }
+
+    pub fn get(&self, txid: &Txid) -> Option<&Transaction> {
+        self.entries.get(txid).map(|entry| &entry.tx)
+    }
+
+    /// The fee the pooled transaction `txid` pays
+    pub fn fee(&self, txid: &Txid) -> Option<u64> {
+        self.entries.get(txid).map(|entry| entry.fee)
+    }
+
+    /// The pooled transaction spending `out`, if any
+    pub fn spender_of(&self, out: &OutPoint) -> Option<Txid> {
+        self.spenders.get(out).copied()
+    }
+
+    /// Number of pooled transactions
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total encoded size of the pooled transactions
+    pub fn total_bytes(&self) -> usize {
+        self.total_bytes
+    }
+
+    /// Pooled transactions for a block, best package fee rate first, until
+    /// `limits` are filled.
+    ///
+    /// Each transaction is ranked with its pooled ancestors not yet chosen,
+    /// by the fee rate of that package, and the package is chosen whole,
+    /// parents before children. Packages too large for the room left are
+    /// skipped so smaller ones can still fit.
+    pub fn select_for_block(&self, limits: &BlockLimits) -> Vec<Transaction> {
+        let mut selected = HashSet::new();
+        let mut ranked: HashMap<Txid, Candidate> = self
+            .entries
+            .keys()
+            .map(|&txid| (txid, self.candidate(txid, &selected)))
+            .collect();
+        let mut queue: BTreeSet<Candidate> = ranked.values().copied().collect();
+
+        let mut chosen = Vec::new();
+        let mut bytes = 0;
+        while let Some(best) = queue.pop_last() {
+            ranked.remove(&best.txid);
+            let package = self.package(best.txid, &selected);
+            if bytes + best.size > limits.max_bytes
+                || chosen.len() + package.len() > limits.max_transactions
+            {
+                continue;
+            }
+            for txid in &package {
+                selected.insert(*txid);
+                if let Some(candidate) = ranked.remove(txid) {
+                    queue.remove(&candidate);
+                }
+                chosen.push(self.entries[txid].tx.clone());
+            }
+            bytes += best.size;
+
+            // The packages of their descendants shrink to what is left
+            for txid in self.descendants(&package) {
+                if let Some(stale) = ranked.get(&txid) {
+                    queue.remove(stale);
+                    let candidate = self.candidate(txid, &selected);
+                    queue.insert(candidate);
+                    ranked.insert(txid, candidate);
+                }
+            }
+        }
+        chosen
+    }
+
+    /// An unmined block on `prev_hash` holding `coinbase` and then the
+    /// transactions [`Mempool::select_for_block`] picks for the room the
+    /// coinbase leaves within `limits`
+    pub fn block_template(
+        &self,
+        prev_hash: BlockHash,
+        limits: &BlockLimits,
+        coinbase: Transaction,
+    ) -> Block {
+        let coinbase = coinbase.encode();
+        let room = BlockLimits {
+            max_bytes: limits.max_bytes.saturating_sub(coinbase.len()),
+            max_transactions: limits.max_transactions.saturating_sub(1),
+        };
+        let txs = self
+            .select_for_block(&room)
+            .iter()
+            .map(Transaction::encode)
+            .collect::<Vec<_>>();
+        Block::template(prev_hash, txs, limits, coinbase)
+    }
+
+    /// Drop the transactions `block` includes, and any pooled transaction
+    /// spending an output one of them spends, since it can no longer be mined,
+    /// along with the pooled transactions spending from it.
+    ///
+    /// Block transactions that do not decode as [`Transaction`]s are skipped.
+    pub fn remove_mined(&mut self, block: &Block) {
+        for bytes in block.transactions() {
+            let Ok(tx) = Transaction::decode_from_block(bytes) else {
+                continue;
+            };
+            self.remove(&Txid::of(bytes));
+            for input in &tx.inputs {
+                if let Some(conflict) = self.spender_of(&input.prev_out) {
+                    self.remove_with_descendants(&conflict);
+                }
+            }
+        }
+    }
+
+    /// What `tx` spends less what it pays out, with the outputs it spends
+    /// looked up in `utxos` and then among those of pooled transactions
+    pub fn fee_of(&self, tx: &Transaction, utxos: &UtxoSet) -> Result<u64, FeeError> {
+        tx.fee(&PoolView { pool: self, utxos })
+    }
+
+    /// Check that the inputs of `tx` meet the conditions of the outputs
+    /// they spend, as [`Transaction::verify_spends`] does, with the outputs
+    /// looked up as [`Mempool::fee_of`] looks them up. A transaction from a
+    /// peer or a client must pass before it is inserted.
+    pub fn verify_spends(&self, tx: &Transaction, utxos: &UtxoSet) -> Result<(), SigError> {
+        tx.verify_spends(&PoolView { pool: self, utxos })
+    }
+
+    /// Return the transactions of `block`, just disconnected by a reorg, to
+    /// the pool where they are still valid, and the number returned.
+    ///
+    /// `utxos` is the unspent output set after the reorg. A transaction is
+    /// returned if it is not a coinbase, spends only outputs in `utxos` or of
+    /// pooled transactions, pays out no more than it spends, and conflicts
+    /// with nothing pooled. Its fee is what it spends less what it pays out.
+    pub fn reinsert_disconnected(&mut self, block: &Block, utxos: &UtxoSet) -> usize {
+        let mut reinserted = 0;
+        for bytes in block.transactions() {
+            let Ok(tx) = Transaction::decode_from_block(bytes) else {
+                continue;
+            };
+            if tx.is_coinbase() {
+                continue;
+            }
+            let Ok(fee) = self.fee_of(&tx, utxos) else {
+                continue;
+            };
+            if self.insert(tx, fee).is_ok() {
+                reinserted += 1;
+            }
+        }
+        reinserted
+    }
+}
+
+/// The outputs of the unspent set and of pooled transactions, as a reorg
+/// leaves them
+struct PoolView<'a> {
+    pool: &'a Mempool,
+    utxos: &'a UtxoSet,
+}
+
+impl UtxoView for PoolView<'_> {
+    fn output(&self, out: &OutPoint) -> Option<TxOutput> {
+        self.utxos.output(out).or_else(|| {
+            let entry = self.pool.entries.get(&out.txid)?;
+            entry.tx.outputs.get(out.index as usize).cloned()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::chain::Blockchain;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::params::ChainParams;
+    use crate::testing::{address, signed_spend, unsigned_spend};
+    use crate::transaction::SigError;
+
+    fn outpoint(n: u8) -> OutPoint {
+        OutPoint {
+            txid: Txid::from_bytes([n; 32]),
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn test_rejects_conflicts_until_removed() {
+        let mut pool = Mempool::new();
+        let first = pool
+            .insert(unsigned_spend(&[outpoint(1), outpoint(2)], &[10]), 1)
+            .unwrap();
+        assert_eq!(
+            pool.insert(unsigned_spend(&[outpoint(1), outpoint(2)], &[10]), 1),
+            Err(MempoolError::AlreadyPooled(first))
+        );
+
+        // A different transaction spending one of the same outputs
+        let rival = unsigned_spend(&[outpoint(3), outpoint(2)], &[9]);
+        assert_eq!(
+            pool.insert(rival.clone(), 1),
+            Err(MempoolError::Conflict {
+                existing_txid: first
+            })
+        );
+        assert_eq!(
+            pool.insert(unsigned_spend(&[outpoint(4), outpoint(4)], &[1]), 1),
+            Err(MempoolError::DuplicateInput(outpoint(4)))
+        );
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.spender_of(&outpoint(3)), None);
+
+        // Removing the pooled transaction frees its outputs for the rival
+        assert!(pool.remove(&first).is_some());
+        assert_eq!(pool.spender_of(&outpoint(1)), None);
+        let rival_txid = pool.insert(rival, 1).unwrap();
+        assert_eq!(pool.spender_of(&outpoint(2)), Some(rival_txid));
+        assert!(pool.insert(unsigned_spend(&[outpoint(1)], &[5]), 1).is_ok());
+    }
+
+    #[test]
+    fn test_mined_transactions_free_their_outputs() {
+        let mut pool = Mempool::new();
+        let mined = unsigned_spend(&[outpoint(1)], &[10]);
+        let mined_txid = pool.insert(mined.clone(), 1).unwrap();
+        let conflicting = pool
+            .insert(unsigned_spend(&[outpoint(2)], &[7]), 1)
+            .unwrap();
+        let unrelated = pool
+            .insert(unsigned_spend(&[outpoint(3)], &[3]), 1)
+            .unwrap();
+
+        // The block also spends outpoint 2, differently than the pool does
+        let block = BlockBuilder::new(BlockHash::ZERO)
+            .transaction(Transaction::default())
+            .transaction(mined)
+            .transaction(unsigned_spend(&[outpoint(2)], &[6]))
+            .transaction(b"raw".to_vec())
+            .build()
+            .unwrap();
+        pool.remove_mined(&block);
+
+        assert!(!pool.contains(&mined_txid));
+        assert!(!pool.contains(&conflicting));
+        assert!(pool.contains(&unrelated));
+        assert_eq!(pool.len(), 1);
+        assert_eq!(pool.spender_of(&outpoint(1)), None);
+        assert_eq!(pool.spender_of(&outpoint(2)), None);
+        assert!(pool
+            .insert(unsigned_spend(&[outpoint(1), outpoint(2)], &[1]), 1)
+            .is_ok());
+    }
+
+    fn out(tx: &Transaction, index: u32) -> OutPoint {
+        OutPoint {
+            txid: tx.txid(),
+            index,
+        }
+    }
+
+    /// Size of a transaction with one input and one output
+    const SPEND_SIZE: u64 = 85;
+
+    #[test]
+    fn test_selects_by_fee_rate_within_limits() {
+        let mut pool = Mempool::new();
+        let a = unsigned_spend(&[outpoint(1)], &[10]);
+        let b = unsigned_spend(&[outpoint(2)], &[10]);
+        let c = unsigned_spend(&[outpoint(3)], &[10]);
+        let child = unsigned_spend(&[out(&b, 0)], &[10]);
+        let d = unsigned_spend(&[outpoint(4), outpoint(5)], &[10]);
+        let d_size = d.encode().len() as u64;
+        assert_eq!(a.encode().len() as u64, SPEND_SIZE);
+        pool.insert(a.clone(), SPEND_SIZE).unwrap();
+        pool.insert(c.clone(), SPEND_SIZE * 3).unwrap();
+        // The child pays the best rate but must follow its parent
+        pool.insert(child.clone(), SPEND_SIZE * 10).unwrap();
+        pool.insert(b.clone(), SPEND_SIZE * 5).unwrap();
+        pool.insert(d.clone(), d_size * 4).unwrap();
+
+        let unlimited = BlockLimits {
+            max_bytes: usize::MAX,
+            max_transactions: usize::MAX,
+        };
+        assert_eq!(
+            pool.select_for_block(&unlimited),
+            [&b, &child, &d, &c, &a].map(Clone::clone)
+        );
+        let two = BlockLimits {
+            max_transactions: 2,
+            ..unlimited
+        };
+        assert_eq!(pool.select_for_block(&two), [b.clone(), child.clone()]);
+
+        // `d` no longer fits after the first two, but `c` still does
+        let bytes = BlockLimits {
+            max_bytes: (SPEND_SIZE * 2 + d_size - 1) as usize,
+            ..unlimited
+        };
+        assert_eq!(pool.select_for_block(&bytes), [b, child, c]);
+        assert!(pool
+            .select_for_block(&BlockLimits {
+                max_bytes: 0,
+                ..unlimited
+            })
+            .is_empty());
+    }
+
+    #[test]
+    fn test_child_pays_for_parent() {
+        let mut pool = Mempool::new();
+        let parent = unsigned_spend(&[outpoint(1)], &[10]);
+        let child = unsigned_spend(&[out(&parent, 0)], &[10]);
+        let standalone = unsigned_spend(&[outpoint(2)], &[10]);
+        pool.insert(parent.clone(), SPEND_SIZE).unwrap();
+        pool.insert(standalone.clone(), SPEND_SIZE * 4).unwrap();
+        let two = BlockLimits {
+            max_bytes: usize::MAX,
+            max_transactions: 2,
+        };
+        assert_eq!(
+            pool.select_for_block(&two),
+            [standalone.clone(), parent.clone()]
+        );
+
+        // The pair pays 5 per byte, beating the standalone's 4
+        let child_txid = pool.insert(child.clone(), SPEND_SIZE * 9).unwrap();
+        assert_eq!(
+            pool.package_fee(&child_txid),
+            Some((SPEND_SIZE * 10, SPEND_SIZE as usize * 2))
+        );
+        assert_eq!(pool.select_for_block(&two), [parent.clone(), child.clone()]);
+        // A package is taken whole or not at all
+        let one = BlockLimits {
+            max_transactions: 1,
+            ..two
+        };
+        assert_eq!(pool.select_for_block(&one), [standalone]);
+    }
+
+    #[test]
+    fn test_selected_packages_are_topologically_ordered() {
+        let mut pool = Mempool::new();
+        let mut root = unsigned_spend(&[outpoint(1)], &[10]);
+        root.outputs.push(root.outputs[0].clone());
+        let left = unsigned_spend(&[out(&root, 0)], &[10]);
+        let right = unsigned_spend(&[out(&root, 1)], &[10]);
+        let join = unsigned_spend(&[out(&left, 0), out(&right, 0)], &[10]);
+        let other = unsigned_spend(&[outpoint(2)], &[10]);
+        // The root and left pay almost nothing, but the join pulls them in
+        pool.insert(root.clone(), 1).unwrap();
+        pool.insert(left.clone(), 2).unwrap();
+        pool.insert(right.clone(), SPEND_SIZE * 8).unwrap();
+        pool.insert(join.clone(), SPEND_SIZE * 40).unwrap();
+        pool.insert(other.clone(), SPEND_SIZE * 6).unwrap();
+
+        let chosen = pool.select_for_block(&BlockLimits {
+            max_bytes: usize::MAX,
+            max_transactions: usize::MAX,
+        });
+        assert_eq!(chosen.len(), 5);
+        assert_eq!(chosen[0], root);
+        assert_eq!(chosen[3], join);
+        assert_eq!(chosen[4], other);
+        for (i, tx) in chosen.iter().enumerate() {
+            for input in &tx.inputs {
+                if let Some(parent) = chosen.iter().position(|p| p.txid() == input.prev_out.txid) {
+                    assert!(parent < i);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_package_limits() {
+        let mut pool = Mempool::new().with_package_limits(3, 3);
+        let first = unsigned_spend(&[outpoint(1)], &[10]);
+        let second = unsigned_spend(&[out(&first, 0)], &[10]);
+        let third = unsigned_spend(&[out(&second, 0)], &[10]);
+        for tx in [&first, &second, &third] {
+            pool.insert(tx.clone(), 1).unwrap();
+        }
+        assert_eq!(
+            pool.insert(unsigned_spend(&[out(&third, 0)], &[10]), 1),
+            Err(MempoolError::TooManyAncestors { count: 4, max: 3 })
+        );
+
+        let mut fan = unsigned_spend(&[outpoint(2)], &[10]);
+        fan.outputs = vec![fan.outputs[0].clone(); 3];
+        let fan_txid = pool.insert(fan.clone(), 1).unwrap();
+        pool.insert(unsigned_spend(&[out(&fan, 0)], &[1]), 1)
+            .unwrap();
+        pool.insert(unsigned_spend(&[out(&fan, 1)], &[1]), 1)
+            .unwrap();
+        assert_eq!(
+            pool.insert(unsigned_spend(&[out(&fan, 2)], &[1]), 1),
+            Err(MempoolError::TooManyDescendants {
+                ancestor: fan_txid,
+                count: 4,
+                max: 3,
+            })
+        );
+        assert_eq!(pool.len(), 6);
+    }
+
+    #[test]
+    fn test_full_pool_evicts_lowest_fee_rate() {
+        let mut pool = Mempool::with_limits(2, usize::MAX);
+        let parent = pool
+            .insert(unsigned_spend(&[outpoint(1)], &[10]), SPEND_SIZE)
+            .unwrap();
+        let cheap = pool
+            .insert(unsigned_spend(&[outpoint(2)], &[10]), SPEND_SIZE * 2)
+            .unwrap();
+
+        // The parent of the new transaction is kept, so the cheap one goes
+        let child = unsigned_spend(
+            &[OutPoint {
+                txid: parent,
+                index: 0,
+            }],
+            &[10],
+        );
+        let child = pool.insert(child, SPEND_SIZE * 9).unwrap();
+        assert!(!pool.contains(&cheap));
+        assert_eq!(pool.total_bytes(), 2 * SPEND_SIZE as usize);
+
+        // Only the child could be evicted, and it pays more
+        assert_eq!(
+            pool.insert(unsigned_spend(&[outpoint(3)], &[10]), SPEND_SIZE * 3),
+            Err(MempoolError::PoolFull)
+        );
+        assert!(pool.contains(&parent) && pool.contains(&child));
+
+        let mut small = Mempool::with_limits(10, SPEND_SIZE as usize);
+        small
+            .insert(unsigned_spend(&[outpoint(1)], &[10]), 1)
+            .unwrap();
+        assert_eq!(
+            small.insert(unsigned_spend(&[outpoint(2)], &[10]), 1),
+            Err(MempoolError::PoolFull)
+        );
+        assert!(small
+            .insert(unsigned_spend(&[outpoint(2)], &[10]), 2)
+            .is_ok());
+        assert_eq!(
+            small.insert(unsigned_spend(&[outpoint(4), outpoint(5)], &[1]), 1_000),
+            Err(MempoolError::PoolFull)
+        );
+    }
+
+    #[test]
+    fn test_overflow_evicts_exactly_the_cheapest() {
+        let mut pool = Mempool::with_limits(usize::MAX, 5 * SPEND_SIZE as usize);
+        // The cheapest is pinned and must survive
+        let txids: Vec<Txid> = [3, 1, 4, 2, 5]
+            .into_iter()
+            .enumerate()
+            .map(|(i, rate)| {
+                let tx = unsigned_spend(&[outpoint(i as u8)], &[10]);
+                pool.insert_with(tx, SPEND_SIZE * rate, rate == 1)
+                    .unwrap()
+                    .txid
+            })
+            .collect();
+
+        let big = unsigned_spend(&[outpoint(10), outpoint(11)], &[10]);
+        let size = big.encode().len();
+        let inserted = pool.insert_with(big, size as u64 * 10, false).unwrap();
+        assert_eq!(inserted.evicted, [txids[3], txids[0]]);
+        assert_eq!(pool.len(), 4);
+        assert_eq!(pool.total_bytes(), 3 * SPEND_SIZE as usize + size);
+        assert!(pool.contains(&txids[1]));
+
+        // A pinned transaction may evict one paying a better rate
+        let mine = unsigned_spend(&[outpoint(12)], &[10]);
+        let inserted = pool.insert_with(mine, 0, true).unwrap();
+        assert_eq!(inserted.evicted, [txids[2]]);
+    }
+
+    #[test]
+    fn test_replace_by_fee() {
+        let mut pool = Mempool::new().with_replacement(2);
+        let original = pool
+            .insert(unsigned_spend(&[outpoint(1)], &[10]), SPEND_SIZE)
+            .unwrap();
+
+        // A bump of less than two per byte is refused
+        let required = SPEND_SIZE * 3;
+        assert_eq!(
+            pool.insert(unsigned_spend(&[outpoint(1)], &[9]), required - 1),
+            Err(MempoolError::InsufficientFeeBump {
+                required,
+                offered: required - 1
+            })
+        );
+        assert!(pool.contains(&original));
+
+        let inserted = pool
+            .insert_with(unsigned_spend(&[outpoint(1)], &[8]), required, false)
+            .unwrap();
+        assert_eq!(inserted.replaced, [original]);
+        assert!(inserted.evicted.is_empty());
+        assert!(!pool.contains(&original));
+        assert_eq!(pool.spender_of(&outpoint(1)), Some(inserted.txid));
+
+        // Without replacement enabled the conflict is reported instead
+        let mut strict = Mempool::new();
+        let first = strict
+            .insert(unsigned_spend(&[outpoint(1)], &[10]), 1)
+            .unwrap();
+        assert_eq!(
+            strict.insert(unsigned_spend(&[outpoint(1)], &[9]), 1_000),
+            Err(MempoolError::Conflict {
+                existing_txid: first
+            })
+        );
+    }
+
+    #[test]
+    fn test_replacement_pays_for_replaced_descendants() {
+        let mut pool = Mempool::new().with_replacement(1);
+        let parent = unsigned_spend(&[outpoint(1)], &[100]);
+        pool.insert(parent.clone(), SPEND_SIZE).unwrap();
+        let mut tip = parent;
+        for amount in 76..100 {
+            let child = unsigned_spend(&[out(&tip, 0)], &[amount]);
+            pool.insert(child.clone(), SPEND_SIZE * 50).unwrap();
+            tip = child;
+        }
+        assert_eq!(pool.len(), DEFAULT_MAX_DESCENDANTS);
+
+        // Beating the parent's rate is not enough to evict the package of
+        // well-paying descendants hanging from it
+        let replacement = unsigned_spend(&[outpoint(1)], &[50]);
+        let required = SPEND_SIZE + 24 * SPEND_SIZE * 50 + SPEND_SIZE;
+        assert_eq!(
+            pool.insert(replacement.clone(), SPEND_SIZE * 2),
+            Err(MempoolError::InsufficientFeeBump {
+                required,
+                offered: SPEND_SIZE * 2
+            })
+        );
+        assert_eq!(pool.len(), DEFAULT_MAX_DESCENDANTS);
+
+        let inserted = pool.insert_with(replacement, required, false).unwrap();
+        assert_eq!(inserted.replaced.len(), DEFAULT_MAX_DESCENDANTS);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_replacement_cascades_to_descendants() {
+        let mut pool = Mempool::new().with_replacement(1);
+        let parent = unsigned_spend(&[outpoint(1), outpoint(2)], &[10]);
+        let child = unsigned_spend(&[out(&parent, 0)], &[9]);
+        let grandchild = unsigned_spend(&[out(&child, 0)], &[8]);
+        let other = unsigned_spend(&[outpoint(3)], &[10]);
+        for tx in [&parent, &child, &grandchild, &other] {
+            pool.insert(tx.clone(), SPEND_SIZE).unwrap();
+        }
+
+        // Conflicting on one input of the parent takes its whole chain, so
+        // the replacement pays for all three and one more per byte
+        let replacement = unsigned_spend(&[outpoint(2)], &[5]);
+        assert_eq!(
+            pool.insert(replacement.clone(), SPEND_SIZE * 3),
+            Err(MempoolError::InsufficientFeeBump {
+                required: SPEND_SIZE * 4,
+                offered: SPEND_SIZE * 3
+            })
+        );
+        let inserted = pool
+            .insert_with(replacement, SPEND_SIZE * 4, false)
+            .unwrap();
+        assert_eq!(
+            inserted.replaced,
+            [parent.txid(), child.txid(), grandchild.txid()]
+        );
+        assert_eq!(pool.len(), 2);
+        assert!(pool.contains(&other.txid()));
+        assert_eq!(pool.spender_of(&outpoint(1)), None);
+        assert_eq!(pool.total_bytes(), 2 * SPEND_SIZE as usize);
+    }
+
+    /// A clock tests can move
+    #[derive(Debug, Default)]
+    struct ManualClock(std::sync::atomic::AtomicU64);
+
+    impl ManualClock {
+        fn set(&self, now: u64) {
+            self.0.store(now, std::sync::atomic::Ordering::Relaxed);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> u64 {
+            self.0.load(std::sync::atomic::Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn test_expiry_spares_fresh_and_pinned() {
+        let clock = Arc::new(ManualClock::default());
+        let mut pool = Mempool::new().with_clock(clock.clone());
+        clock.set(100);
+        let stale = pool
+            .insert(unsigned_spend(&[outpoint(1)], &[10]), 1)
+            .unwrap();
+        let pinned = pool
+            .insert_with(unsigned_spend(&[outpoint(2)], &[10]), 1, true)
+            .unwrap()
+            .txid;
+        let parent = pool
+            .insert(unsigned_spend(&[outpoint(3)], &[10]), 1)
+            .unwrap();
+        clock.set(150);
+        let child = unsigned_spend(
+            &[OutPoint {
+                txid: parent,
+                index: 0,
+            }],
+            &[10],
+        );
+        let child = pool.insert(child, 1).unwrap();
+        clock.set(200);
+        let fresh = pool
+            .insert(unsigned_spend(&[outpoint(4)], &[10]), 1)
+            .unwrap();
+
+        // The stale parent is kept for its fresh child
+        assert_eq!(pool.expire(Duration::from_secs(60)), [stale]);
+        assert_eq!(pool.len(), 4);
+        assert!(pool.expire(Duration::from_secs(60)).is_empty());
+
+        clock.set(300);
+        assert_eq!(pool.expire(Duration::from_secs(60)), [parent, child, fresh]);
+        assert_eq!(pool.len(), 1);
+        assert!(pool.contains(&pinned));
+        assert_eq!(pool.spender_of(&outpoint(3)), None);
+    }
+
+    #[test]
+    fn test_refuses_transactions_not_yet_final() {
+        let clock = Arc::new(ManualClock::default());
+        let mut pool = Mempool::new().with_clock(clock.clone());
+        clock.set(1_700_000_000);
+        let locked = |n: u8, lock_time: u32| Transaction {
+            lock_time,
+            ..unsigned_spend(&[outpoint(n)], &[10])
+        };
+
+        // The next block is at height 1 until the tip moves
+        assert_eq!(
+            pool.insert(locked(1, 5), 1),
+            Err(MempoolError::NotFinal(Locked::UntilHeight(5)))
+        );
+        pool.set_tip_height(4);
+        pool.insert(locked(1, 5), 1).unwrap();
+
+        assert_eq!(
+            pool.insert(locked(2, 1_700_000_000), 1),
+            Err(MempoolError::NotFinal(Locked::UntilTime(1_700_000_001)))
+        );
+        clock.set(1_700_000_001);
+        pool.insert(locked(2, 1_700_000_000), 1).unwrap();
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn test_lifecycle_across_connect_and_reorg() {
+        let genesis_tx = Transaction {
+            outputs: vec![
+                TxOutput::to_address(50, address()),
+                TxOutput::to_address(50, address()),
+            ],
+            ..Transaction::default()
+        };
+        let params = ChainParams {
+            genesis_transactions: vec![genesis_tx.encode()],
+            ..ChainParams::test_defaults()
+        };
+        let mut chain = Blockchain::new_from_params(&params)
+            .with_utxo_set()
+            .unwrap();
+        let genesis = chain.tip().clone();
+        let mine = |parent: &Block, txs: &[Vec<u8>]| {
+            let mut block = parent
+                .next_builder()
+                .transactions(txs.iter().cloned())
+                .difficulty(params.initial_difficulty)
+                .timestamp(parent.timestamp() + 10)
+                .build()
+                .unwrap();
+            block.mine(params.initial_difficulty);
+            block
+        };
+        let coinbase = |tag: u32| Transaction {
+            lock_time: tag,
+            ..Transaction::default()
+        };
+
+        let mut pool = Mempool::new();
+        let tx1 = signed_spend(&[out(&genesis_tx, 0)], &[45]);
+        let tx2 = signed_spend(&[out(&genesis_tx, 1)], &[49]);
+        let txid1 = pool.insert(tx1.clone(), 5).unwrap();
+        let txid2 = pool.insert(tx2, 1).unwrap();
+
+        // Room for one transaction besides the coinbase takes the better rate
+        let limits = BlockLimits {
+            max_bytes: 1024,
+            max_transactions: 2,
+        };
+        let template = pool.block_template(genesis.hash(), &limits, coinbase(1));
+        assert_eq!(
+            template.transactions(),
+            [coinbase(1).encode(), tx1.encode()]
+        );
+        let a1 = mine(&genesis, template.transactions());
+        chain.append(a1.clone()).unwrap();
+        pool.remove_mined(&a1);
+        assert!(!pool.contains(&txid1));
+        assert!(pool.contains(&txid2));
+
+        // A heavier branch spends the output the pooled tx2 spends
+        let tx3 = signed_spend(&[out(&genesis_tx, 1)], &[40]);
+        let b1 = mine(&genesis, &[coinbase(2).encode(), tx3.encode()]);
+        let b2 = mine(&b1, &[coinbase(3).encode()]);
+        chain.insert(b1.clone()).unwrap();
+        let reorg = chain.insert(b2.clone()).unwrap().unwrap();
+        assert_eq!(reorg.disconnected, [a1.hash()]);
+        pool.remove_mined(&b1);
+        pool.remove_mined(&b2);
+        assert!(pool.is_empty());
+
+        // tx1 is valid again on the new branch; the coinbase is not returned
+        let utxos = chain.utxo_set().unwrap();
+        assert_eq!(pool.reinsert_disconnected(&a1, utxos), 1);
+        assert_eq!(pool.fee(&txid1), Some(5));
+
+        // Once its output is spent on the active chain it is not returned
+        let mut other = Mempool::new();
+        chain.rollback_to(0).unwrap();
+        let b1 = mine(
+            &genesis,
+            &[
+                coinbase(4).encode(),
+                signed_spend(&[out(&genesis_tx, 0)], &[1]).encode(),
+            ],
+        );
+        chain.append(b1).unwrap();
+        assert_eq!(
+            other.reinsert_disconnected(&a1, chain.utxo_set().unwrap()),
+            0
+        );
+        assert!(other.is_empty());
+    }
+
+    #[test]
+    fn test_fee_of_counts_pooled_outputs() {
+        let funding = unsigned_spend(&[], &[100]);
+        let mut utxos = UtxoSet::new();
+        utxos
+            .apply_block(
+                &BlockBuilder::new(BlockHash::from_bytes([0; 32]))
+                    .transaction(funding.clone())
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+        let mut pool = Mempool::new();
+        let parent = unsigned_spend(&[out(&funding, 0)], &[90]);
+        assert_eq!(pool.fee_of(&parent, &utxos), Ok(10));
+        pool.insert(parent.clone(), 10).unwrap();
+
+        // The child spends an output only the pool holds
+        assert_eq!(
+            pool.fee_of(&unsigned_spend(&[out(&parent, 0)], &[85]), &utxos),
+            Ok(5)
+        );
+        let orphan = unsigned_spend(&[outpoint(9)], &[1]);
+        assert_eq!(
+            pool.fee_of(&orphan, &utxos),
+            Err(FeeError::MissingInput(outpoint(9)))
+        );
+        assert!(matches!(
+            pool.fee_of(&unsigned_spend(&[out(&funding, 0)], &[101]), &utxos),
+            Err(FeeError::NegativeFee { .. })
+        ));
+    }
+
+    #[test]
+    fn test_verify_spends_checks_pooled_and_unspent_outputs() {
+        let funding = signed_spend(&[], &[100]);
+        let mut utxos = UtxoSet::new();
+        utxos
+            .apply_block(
+                &BlockBuilder::new(BlockHash::ZERO)
+                    .transaction(funding.clone())
+                    .build()
+                    .unwrap(),
+            )
+            .unwrap();
+        let mut pool = Mempool::new();
+        let parent = signed_spend(&[out(&funding, 0)], &[90]);
+        assert_eq!(pool.verify_spends(&parent, &utxos), Ok(()));
+        pool.insert(parent.clone(), 10).unwrap();
+        assert_eq!(
+            pool.verify_spends(&signed_spend(&[out(&parent, 0)], &[85]), &utxos),
+            Ok(())
+        );
+
+        let mut unsigned = signed_spend(&[out(&parent, 0)], &[85]);
+        unsigned.inputs[0].signatures.clear();
+        assert_eq!(
+            pool.verify_spends(&unsigned, &utxos),
+            Err(SigError::MissingSignature { index: 0, input: 0 })
+        );
+        let mut stolen = signed_spend(&[out(&parent, 0)], &[85]);
+        stolen.sign_input(0, &SigningKey::from_bytes(&[4; 32]));
+        assert_eq!(
+            pool.verify_spends(&stolen, &utxos),
+            Err(SigError::KeyMismatch { index: 0, input: 0 })
+        );
+        let mut forged = signed_spend(&[out(&parent, 0)], &[85]);
+        forged.outputs[0].amount = 80;
+        assert_eq!(
+            pool.verify_spends(&forged, &utxos),
+            Err(SigError::InvalidSignature { index: 0, input: 0 })
+        );
+        assert_eq!(
+            pool.verify_spends(&signed_spend(&[outpoint(9)], &[1]), &utxos),
+            Err(SigError::UnknownKey { index: 0, input: 0 })
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_pool_invariants_hold(
+            txs in vec((vec((any::<Index>(), any::<bool>()), 1..3), 1..1000u64, 0..5000u64), 1..24),
+            max_count in 1..8usize,
+            max_bytes in 100..2000usize,
+            replace in any::<bool>(),
+        ) {
+            let mut pool = Mempool::with_limits(max_count, max_bytes);
+            if replace {
+                pool = pool.with_replacement(1);
+            }
+            // Each input spends one of a few outputs, or one made by an
+            // earlier transaction, so conflicts and packages are common
+            let mut made: Vec<Txid> = Vec::new();
+            for (inputs, amount, fee) in txs {
+                let outs: Vec<OutPoint> = inputs
+                    .iter()
+                    .map(|(at, chained)| match *chained && !made.is_empty() {
+                        true => OutPoint {
+                            txid: made[at.index(made.len())],
+                            index: 0,
+                        },
+                        false => outpoint(at.index(8) as u8 + 1),
+                    })
+                    .collect();
+                let tx = unsigned_spend(&outs, &[amount]);
+                made.push(tx.txid());
+                if let Ok(txid) = pool.insert(tx, fee) {
+                    prop_assert!(pool.contains(&txid));
+                }
+
+                prop_assert!(pool.len() <= max_count);
+                prop_assert!(pool.total_bytes() <= max_bytes);
+                let sizes: usize = pool.iter().map(|(_, tx)| tx.encode().len()).sum();
+                prop_assert_eq!(pool.total_bytes(), sizes);
+                // No two pooled transactions spend the same output
+                for (txid, tx) in pool.iter() {
+                    for input in &tx.inputs {
+                        prop_assert_eq!(pool.spender_of(&input.prev_out), Some(*txid));
+                    }
+                }
+            }
+
+            // A block holds pooled transactions once each, parents first
+            let chosen = pool.select_for_block(&BlockLimits {
+                max_bytes: usize::MAX,
+                max_transactions: usize::MAX,
+            });
+            prop_assert_eq!(chosen.len(), pool.len());
+            let mut seen = HashSet::new();
+            for tx in &chosen {
+                for input in &tx.inputs {
+                    let txid = input.prev_out.txid;
+                    prop_assert!(!pool.contains(&txid) || seen.contains(&txid));
+                }
+                prop_assert!(seen.insert(tx.txid()));
+            }
+
+            for txid in made {
+                pool.remove(&txid);
+            }
+            prop_assert!(pool.is_empty());
+            prop_assert_eq!(pool.total_bytes(), 0);
+        }
+    }
+}
@@ -1,75 +1,99 @@
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::codec::{self, DecodeError, DecodeLimits, Reader};
+use crate::hash::Hash32;
+use crate::trace;
+
+/// Reasons a Merkle tree or proof cannot be made
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum MerkleError {
+    /// A tree needs at least one leaf
+    #[error("cannot create a Merkle tree from no leaves")]
+    Empty,
+    /// A proof was asked for the leaf at `index` of a tree with `leaves` leaves
+    #[error("leaf index {index} is out of range for a tree of {leaves} leaves")]
+    IndexOutOfRange { index: usize, leaves: usize },
+}
 
 /// A simple Merkle Tree implementation using SHA-256 hashing
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct MerkleTree {
     /// The root hash of the Merkle tree
-    root: Vec<u8>,
-    /// All tree node hashes in level order
-    nodes: Vec<Vec<u8>>,
+    root: Hash32,
+    /// All tree node hashes, grouped by level from the leaves up to the root
+    nodes: Vec<Vec<Hash32>>,
     /// Number of leaf nodes
     leaf_count: usize,
 }
 
 impl MerkleTree {
-    /// Create a new Merkle tree from a list of data items
-    pub fn new<T: AsRef<[u8]>>(data: &[T]) -> Self {
-        if data.is_empty() {
-            panic!("Cannot create Merkle tree from empty data");
+    /// Create a new Merkle tree from a list of data items, of which there
+    /// must be at least one
+    pub fn new<T: AsRef<[u8]>>(data: &[T]) -> Result<Self, MerkleError> {
+        // Create leaf nodes (level 0)
+        Self::from_leaf_hashes(data.iter().map(|item| Self::hash(item.as_ref())).collect())
+    }
+
+    /// Create a tree over leaves already hashed with [`MerkleTree::hash`], such
+    /// as those of transactions hashed while they were read
+    pub(crate) fn from_leaf_hashes(leaves: Vec<Hash32>) -> Result<Self, MerkleError> {
+        if leaves.is_empty() {
+            return Err(MerkleError::Empty);
         }
 
         let mut nodes = Vec::new();
         
-        // Create leaf nodes (level 0)
-        let mut current_level = Vec::new();
-        for item in data {
-            let hash = Self::hash(item.as_ref());
-            current_level.push(hash);
-        }
-        
-        let leaf_count = current_level.len();
-        nodes.push(current_level);
+        let leaf_count = leaves.len();
+        let mut last_level = leaves;
         
         // Build tree upwards until we reach the root
-        while nodes.last().unwrap().len() > 1 {
-            let last_level = nodes.last().unwrap();
+        while last_level.len() > 1 {
             let mut new_level = Vec::new();
             
             // Combine pairs of nodes
             for i in (0..last_level.len()).step_by(2) {
                 if i + 1 < last_level.len() {
                     // Combine two child nodes
-                    let mut combined = last_level[i].clone();
-                    combined.extend_from_slice(&last_level[i + 1]);
-                    let parent_hash = Self::hash(&combined);
-                    new_level.push(parent_hash);
+                    new_level.push(Self::hash_pair(&last_level[i], &last_level[i + 1]));
                 } else {
                     // Odd number of nodes, promote the last one
-                    new_level.push(last_level[i].clone());
+                    new_level.push(last_level[i]);
                 }
             }
             
-            nodes.push(new_level);
+            nodes.push(std::mem::replace(&mut last_level, new_level));
         }
         
         // The root is the last node in the last level
-        let root = nodes.last().unwrap()[0].clone();
+        let root = last_level[0];
+        nodes.push(last_level);
         
-        MerkleTree {
+        Ok(MerkleTree {
             root,
-            nodes: nodes.into_iter().flatten().collect(),
+            nodes,
             leaf_count,
-        }
+        })
     }
     
     /// Get the root hash of the Merkle tree
+    pub fn root(&self) -> Hash32 {
+        self.root
+    }
+
+    /// The root hash as bytes, as [`MerkleTree::root`] holds it
     pub fn root_hash(&self) -> &[u8] {
-        &self.root
+        self.root.as_ref()
     }
     
     /// Generate a Merkle proof for a leaf at the given index
-    pub fn generate_proof(&self, leaf_index: usize) -> MerkleProof {
+    pub fn generate_proof(&self, leaf_index: usize) -> Result<MerkleProof, MerkleError> {
         if leaf_index >= self.leaf_count {
-            panic!("Leaf index out of bounds");
+            return Err(MerkleError::IndexOutOfRange {
+                index: leaf_index,
+                leaves: self.leaf_count,
+            });
         }
         
         let mut proof = Vec::new();
@@ -82,43 +106,69 @@ impl MerkleTree {
             let sibling_idx = if is_right { index - 1 } else { index + 1 };
             
             if sibling_idx < level_nodes.len() {
-                proof.push((level_nodes[sibling_idx].clone(), is_right));
+                proof.push((level_nodes[sibling_idx], !is_right));
             }
             
             // Move to parent index for next level
             index /= 2;
         }
         
-        // MerkleProof {
-        //     proof,
-        //     leaf_hash: self.nodes[0][leaf_index].clone(),
-        //     root_hash: self.root.clone(),
-        // }
-        todo!()
+        trace::event!(
+            trace,
+            index = leaf_index,
+            leaves = self.leaf_count,
+            root = %trace::short(self.root),
+            "proof generated"
+        );
+        Ok(MerkleProof {
+            proof,
+            leaf_hash: self.nodes[0][leaf_index],
+            root_hash: self.root,
+        })
     }
     
     /// Helper function to compute SHA-256 hash
-    pub fn hash(data: &[u8]) -> Vec<u8> {
+    pub fn hash(data: &[u8]) -> Hash32 {
+        Sha256::digest(data).into()
+    }
+
+    /// The hash of a parent node: its left child's hash then its right's
+    fn hash_pair(left: &Hash32, right: &Hash32) -> Hash32 {
         let mut hasher = Sha256::new();
-        hasher.update(data);
-        hasher.finalize().to_vec()
+        hasher.update(left);
+        hasher.update(right);
+        hasher.finalize().into()
     }
 }
 
 /// A proof that a leaf is included in the Merkle tree
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct MerkleProof {
     /// The proof nodes, each with a flag indicating if it's a right sibling
-    proof: Vec<(Vec<u8>, bool)>,
+    proof: Vec<(Hash32, bool)>,
     /// The hash of the leaf being proven
-    leaf_hash: Vec<u8>,
+    leaf_hash: Hash32,
     /// The root hash of the tree
-    root_hash: Vec<u8>,
+    root_hash: Hash32,
 }
 
 impl MerkleProof {
     /// Verify the Merkle proof
     pub fn verify<T: AsRef<[u8]>>(&self, data: T) -> bool {
-        let leaf_hash = MerkleTree::hash(data.as_ref());
+        let valid = self.verify_leaf(data.as_ref());
+        trace::event!(
+            trace,
+            valid,
+            leaf = %trace::short(self.leaf_hash),
+            root = %trace::short(self.root_hash),
+            "proof verified"
+        );
+        valid
+    }
+    
+    fn verify_leaf(&self, data: &[u8]) -> bool {
+        let leaf_hash = MerkleTree::hash(data);
         
         // Check if the leaf hash matches
         if leaf_hash != self.leaf_hash {
@@ -129,34 +179,104 @@ impl MerkleProof {
         
         // Traverse up the tree using the proof
         for (sibling, is_right) in &self.proof {
-            let mut combined = Vec::new();
-            
-            if *is_right {
+            current_hash = if *is_right {
                 // Current hash is left, sibling is right
-                combined.extend_from_slice(&current_hash);
-                combined.extend_from_slice(sibling);
+                MerkleTree::hash_pair(&current_hash, sibling)
             } else {
                 // Current hash is right, sibling is left
-                combined.extend_from_slice(sibling);
-                combined.extend_from_slice(&current_hash);
-            }
-            
-            current_hash = MerkleTree::hash(&combined);
+                MerkleTree::hash_pair(sibling, &current_hash)
+            };
         }
         
         // Check if we've arrived at the root
         current_hash == self.root_hash
     }
+
+    /// The root the proof claims the leaf is under; compare it against a
+    /// trusted root before relying on `verify`
+    pub fn root(&self) -> Hash32 {
+        self.root_hash
+    }
+
+    /// The claimed root as bytes, as [`MerkleProof::root`] holds it
+    pub fn root_hash(&self) -> &[u8] {
+        self.root_hash.as_ref()
+    }
+
+    /// The hash of the leaf being proven
+    #[cfg(any(test, feature = "serde", feature = "cbor", feature = "proto", feature = "test-utils"))]
+    pub(crate) fn leaf_hash(&self) -> &[u8] {
+        self.leaf_hash.as_ref()
+    }
+
+    /// The siblings from the leaf up, each with whether it is on the right
+    #[cfg(any(test, feature = "serde", feature = "cbor", feature = "proto", feature = "test-utils"))]
+    pub(crate) fn siblings(&self) -> &[(Hash32, bool)] {
+        &self.proof
+    }
+
+    /// A proof from hashes another encoding has already checked, as
+    /// [`MerkleProof::from_bytes`] would
+    #[cfg(any(test, feature = "cbor", feature = "proto", feature = "test-utils"))]
+    pub(crate) fn from_parts(proof: Vec<(Hash32, bool)>, leaf_hash: Hash32, root_hash: Hash32) -> MerkleProof {
+        MerkleProof {
+            proof,
+            leaf_hash,
+            root_hash,
+        }
+    }
+
+    /// Serialize the proof: leaf hash, root hash, then each sibling with its side flag
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buffer = Vec::with_capacity(64 + 1 + self.proof.len() * 33);
+        buffer.extend_from_slice(self.leaf_hash.as_ref());
+        buffer.extend_from_slice(self.root_hash.as_ref());
+        codec::write_varint(&mut buffer, self.proof.len() as u64);
+        for (sibling, is_right) in &self.proof {
+            buffer.push(*is_right as u8);
+            buffer.extend_from_slice(sibling.as_ref());
+        }
+        buffer
+    }
+
+    /// Decode a proof produced by `to_bytes`, enforcing `limits` on untrusted input
+    pub fn from_bytes(bytes: &[u8], limits: &DecodeLimits) -> Result<MerkleProof, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let mut reader = Reader::new(bytes);
+
+        let leaf_hash = Hash32(reader.read_array()?);
+        let root_hash = Hash32(reader.read_array()?);
+        let depth = reader.read_len("proof depth", limits.max_proof_depth)?;
+
+        let mut proof = Vec::with_capacity(depth);
+        for _ in 0..depth {
+            let is_right = match reader.read_u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(DecodeError::InvalidValue("proof side flag")),
+            };
+            proof.push((Hash32(reader.read_array()?), is_right));
+        }
+        reader.finish()?;
+
+        Ok(MerkleProof {
+            proof,
+            leaf_hash,
+            root_hash,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::testing::{corrupt_merkle_proof, merkle_proof};
+    use proptest::prelude::*;
     
     #[test]
     fn test_merkle_tree() {
         let data = vec!["a", "b", "c", "d"];
-        let tree = MerkleTree::new(&data);
+        let tree = MerkleTree::new(&data).unwrap();
         
         // Verify that the tree has the correct structure
         assert_eq!(tree.nodes.len(), 3); // 3 levels: leaves, internal, root
@@ -165,20 +285,52 @@ mod tests {
         assert_eq!(tree.nodes[2].len(), 1); // 1 root
     }
     
-    #[test]
-    fn test_merkle_proof() {
-        let data = vec!["a", "b", "c", "d"];
-        let tree = MerkleTree::new(&data);
-        
-        // Generate and verify a proof for each item
-        for (i, item) in data.iter().enumerate() {
-            let proof = tree.generate_proof(i);
-            assert!(proof.verify(item));
+    proptest! {
+        #[test]
+        fn test_merkle_proof((leaves, index, proof) in merkle_proof()) {
+            prop_assert!(proof.verify(&leaves[index]));
+            prop_assert_eq!(proof.root(), MerkleTree::new(&leaves).unwrap().root());
+            prop_assert!(proof.siblings().len() <= leaves.len().next_power_of_two().trailing_zeros() as usize);
             
             // Verify that the proof fails for different data
-            if i > 0 {
-                assert!(!proof.verify(data[i-1]));
+            for other in leaves.iter().filter(|leaf| **leaf != leaves[index]) {
+                prop_assert!(!proof.verify(other));
             }
         }
+        
+        #[test]
+        fn test_proof_bytes_round_trip((leaves, index, proof) in merkle_proof()) {
+            let decoded = MerkleProof::from_bytes(&proof.to_bytes(), &DecodeLimits::default()).unwrap();
+            prop_assert!(decoded.verify(&leaves[index]));
+            prop_assert_eq!(decoded, proof);
+        }
+        
+        #[test]
+        fn test_corrupt_proofs_fail((leaf, proof) in corrupt_merkle_proof()) {
+            prop_assert!(!proof.verify(&leaf));
+            let decoded = MerkleProof::from_bytes(&proof.to_bytes(), &DecodeLimits::default()).unwrap();
+            prop_assert!(!decoded.verify(&leaf));
+        }
+    }
+
+    #[test]
+    fn test_merkle_errors() {
+        assert_eq!(MerkleTree::new::<&str>(&[]), Err(MerkleError::Empty));
+        let tree = MerkleTree::new(&["a", "b", "c"]).unwrap();
+        let err = tree.generate_proof(3).unwrap_err();
+        assert_eq!(err, MerkleError::IndexOutOfRange { index: 3, leaves: 3 });
+        assert_eq!(err.to_string(), "leaf index 3 is out of range for a tree of 3 leaves");
+        assert_eq!(MerkleError::Empty.to_string(), "cannot create a Merkle tree from no leaves");
+    }
+
+    #[test]
+    fn test_proof_depth_limit() {
+        let mut bytes = vec![0u8; 64];
+        codec::write_varint(&mut bytes, 65);
+        bytes.extend(std::iter::repeat_n(0u8, 65 * 33));
+        assert_eq!(
+            MerkleProof::from_bytes(&bytes, &DecodeLimits::default()),
+            Err(DecodeError::LimitExceeded { what: "proof depth", value: 65, max: 64 })
+        );
     }
 }
@@ -0,0 +1,560 @@
+//! Remembering the addresses of peers, and keeping connected to enough of
+//! them.
+//!
+//! An [`AddrBook`] holds the addresses a node has connected to or been told
+//! of in an `Addr` message, with when each was last seen, when the node last
+//! connected to it and how many attempts since have failed. A
+//! [`ConnectionManager`] picks the addresses to dial to keep a target number
+//! of outbound connections, those that worked most recently first, and
+//! holds back from each that keeps failing for twice as long after every
+//! failure. The book is saved to a file of its own between runs.
+
+use std::collections::HashSet;
+use std::fs;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+
+use crate::codec::{self, DecodeError, Reader};
+use crate::params::{Clock, SystemClock};
+use crate::store::StoreError;
+
+/// Addresses an [`AddrBook`] holds unless configured otherwise
+pub const DEFAULT_MAX_ADDRESSES: usize = 2000;
+
+/// Most addresses an `Addr` message may carry
+pub const MAX_ADDR_PER_MESSAGE: usize = 1000;
+
+/// Outbound connections a node keeps unless configured otherwise
+pub const DEFAULT_TARGET_OUTBOUND: usize = 8;
+
+/// How long an address is held back after its first failure; each failure
+/// after doubles it
+pub const BASE_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+/// Longest an address is held back, however often it has failed
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+const MAGIC: &[u8; 4] = b"AWAB";
+const VERSION: u32 = 1;
+
+/// Bytes of an address on the wire: an IPv6 address, IPv4 ones mapped into
+/// it, and the port
+pub(super) const ADDR_LEN: usize = 16 + 2;
+
+/// What an [`AddrBook`] knows of one address
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AddrEntry {
+    pub addr: SocketAddr,
+    /// When the address was last seen working, by the node or a peer, in
+    /// seconds since the Unix epoch
+    pub last_seen: u64,
+    /// When the node last connected to the address, if ever
+    pub last_success: Option<u64>,
+    /// When the node last tried to connect to the address, if ever
+    pub last_attempt: Option<u64>,
+    /// Attempts failed since the last that worked
+    pub failures: u32,
+}
+
+impl AddrEntry {
+    fn new(addr: SocketAddr, last_seen: u64) -> Self {
+        AddrEntry {
+            addr,
+            last_seen,
+            last_success: None,
+            last_attempt: None,
+            failures: 0,
+        }
+    }
+
+    /// When the address may be tried again, after [`BASE_RETRY_DELAY`]
+    /// doubled for each failure after the first, up to [`MAX_RETRY_DELAY`]
+    pub fn retry_at(&self) -> u64 {
+        match (self.failures, self.last_attempt) {
+            (0, _) | (_, None) => 0,
+            (failures, Some(attempt)) => {
+                let delay = BASE_RETRY_DELAY
+                    .as_secs()
+                    .saturating_mul(1 << (failures - 1).min(32))
+                    .min(MAX_RETRY_DELAY.as_secs());
+                attempt.saturating_add(delay)
+            }
+        }
+    }
+
+    /// Which of two entries to keep over the other: one the node has
+    /// connected to, then one failing less, then one seen more recently
+    fn standing(&self) -> (bool, std::cmp::Reverse<u32>, u64) {
+        (
+            self.last_success.is_some(),
+            std::cmp::Reverse(self.failures),
+            self.last_seen,
+        )
+    }
+}
+
+/// The addresses of known peers, bounded in number, timed by a [`Clock`]
+#[derive(Clone, Debug)]
+pub struct AddrBook {
+    entries: Vec<AddrEntry>,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for AddrBook {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ADDRESSES)
+    }
+}
+
+impl AddrBook {
+    /// An empty book holding up to `capacity` addresses
+    pub fn new(capacity: usize) -> Self {
+        AddrBook {
+            entries: Vec::new(),
+            capacity,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Time the book by `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, addr: &SocketAddr) -> Option<&AddrEntry> {
+        self.entries.iter().find(|entry| entry.addr == *addr)
+    }
+
+    /// Every entry, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = &AddrEntry> {
+        self.entries.iter()
+    }
+
+    /// Learn of `addr`, seen working at `last_seen`, or update when it was
+    /// last seen. A time in the future counts as now. A full book drops its
+    /// worst entry to make room, or the new one if that is worst. Returns
+    /// whether the address was not held before and now is.
+    pub fn add(&mut self, addr: SocketAddr, last_seen: u64) -> bool {
+        let last_seen = last_seen.min(self.clock.now());
+        if let Some(entry) = self.entry_mut(&addr) {
+            entry.last_seen = entry.last_seen.max(last_seen);
+            return false;
+        }
+        self.entries.push(AddrEntry::new(addr, last_seen));
+        self.evict();
+        self.get(&addr).is_some()
+    }
+
+    /// Note that connecting to `addr` worked just now, adding it if new
+    pub fn mark_success(&mut self, addr: SocketAddr) {
+        let now = self.clock.now();
+        self.add(addr, now);
+        if let Some(entry) = self.entry_mut(&addr) {
+            entry.last_seen = now;
+            entry.last_success = Some(now);
+            entry.last_attempt = Some(now);
+            entry.failures = 0;
+        }
+    }
+
+    /// Note that connecting to `addr` failed just now, holding it back for
+    /// longer than after its last failure
+    pub fn mark_failure(&mut self, addr: &SocketAddr) {
+        let now = self.clock.now();
+        if let Some(entry) = self.entry_mut(addr) {
+            entry.last_attempt = Some(now);
+            entry.failures = entry.failures.saturating_add(1);
+        }
+    }
+
+    /// Up to `count` addresses to connect to that are not held back and
+    /// that `skip` lets through, those connected to most recently first,
+    /// then those seen most recently
+    pub fn candidates(&self, count: usize, skip: impl Fn(&SocketAddr) -> bool) -> Vec<SocketAddr> {
+        let now = self.clock.now();
+        let mut ready: Vec<&AddrEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.retry_at() <= now && !skip(&entry.addr))
+            .collect();
+        ready.sort_by_key(|entry| std::cmp::Reverse((entry.last_success, entry.last_seen)));
+        ready
+            .into_iter()
+            .take(count)
+            .map(|entry| entry.addr)
+            .collect()
+    }
+
+    /// The addresses to tell a peer of in an `Addr`: up to
+    /// [`MAX_ADDR_PER_MESSAGE`] that are not failing, seen most recently
+    /// first, with when each was last seen
+    pub fn sample(&self) -> Vec<(SocketAddr, u64)> {
+        let mut working: Vec<&AddrEntry> = self
+            .entries
+            .iter()
+            .filter(|entry| entry.failures == 0)
+            .collect();
+        working.sort_by_key(|entry| std::cmp::Reverse(entry.last_seen));
+        working
+            .into_iter()
+            .take(MAX_ADDR_PER_MESSAGE)
+            .map(|entry| (entry.addr, entry.last_seen))
+            .collect()
+    }
+
+    /// Write the book to `path`, through a temporary file renamed over it
+    /// so that a crash leaves either the old book or the new one
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), StoreError> {
+        let mut file = MAGIC.to_vec();
+        file.extend_from_slice(&VERSION.to_le_bytes());
+        codec::write_varint(&mut file, self.entries.len() as u64);
+        for entry in &self.entries {
+            write_addr(&mut file, &entry.addr);
+            file.extend_from_slice(&entry.last_seen.to_le_bytes());
+            for time in [entry.last_success, entry.last_attempt] {
+                file.extend_from_slice(&time.unwrap_or(0).to_le_bytes());
+            }
+            file.extend_from_slice(&entry.failures.to_le_bytes());
+        }
+        let checksum = Sha256::digest(&file);
+        file.extend_from_slice(&checksum[..4]);
+
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &file)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Read back a book [`AddrBook::save`] wrote, into one holding up to
+    /// `capacity` addresses, or an empty one if there is no file at `path`
+    pub fn load(path: impl AsRef<Path>, capacity: usize) -> Result<Self, StoreError> {
+        let mut book = AddrBook::new(capacity);
+        let file = match fs::read(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(book),
+            Err(err) => return Err(err.into()),
+        };
+        let Some(body_len) = file.len().checked_sub(4) else {
+            return Err(DecodeError::UnexpectedEof.into());
+        };
+        let (body, checksum) = file.split_at(body_len);
+        if Sha256::digest(body)[..4] != *checksum {
+            return Err(DecodeError::InvalidValue("address book checksum").into());
+        }
+        let mut reader = Reader::new(body);
+        if reader.read_array::<4>()? != *MAGIC || reader.read_u32()? != VERSION {
+            return Err(DecodeError::InvalidValue("address book header").into());
+        }
+        let count = reader.read_len("address count", reader.remaining() / ADDR_LEN)?;
+        for _ in 0..count {
+            let addr = read_addr(&mut reader)?;
+            let last_seen = reader.read_u64()?;
+            let [last_success, last_attempt] =
+                [reader.read_u64()?, reader.read_u64()?].map(|time| (time != 0).then_some(time));
+            let failures = reader.read_u32()?;
+            if book.get(&addr).is_none() {
+                book.entries.push(AddrEntry {
+                    addr,
+                    last_seen,
+                    last_success,
+                    last_attempt,
+                    failures,
+                });
+            }
+        }
+        reader.finish()?;
+        while book.entries.len() > book.capacity {
+            book.evict();
+        }
+        Ok(book)
+    }
+
+    /// Note that the node is trying `addr` now
+    fn mark_attempt(&mut self, addr: &SocketAddr) {
+        let now = self.clock.now();
+        if let Some(entry) = self.entry_mut(addr) {
+            entry.last_attempt = Some(now);
+        }
+    }
+
+    fn entry_mut(&mut self, addr: &SocketAddr) -> Option<&mut AddrEntry> {
+        self.entries.iter_mut().find(|entry| entry.addr == *addr)
+    }
+
+    /// Drop the worst entry if the book is over its capacity
+    fn evict(&mut self) {
+        if self.entries.len() <= self.capacity {
+            return;
+        }
+        let worst = (0..self.entries.len()).min_by_key(|index| self.entries[*index].standing());
+        if let Some(worst) = worst {
+            self.entries.swap_remove(worst);
+        }
+    }
+}
+
+/// Which outbound connections a node has and is making, and the
+/// [`AddrBook`] it makes them from.
+///
+/// Like a [`Relay`](super::Relay), it leaves the connections themselves to
+/// the caller: it says which addresses to dial, and is told how each dial
+/// went and when each connection closes.
+#[derive(Clone, Debug)]
+pub struct ConnectionManager {
+    book: AddrBook,
+    target: usize,
+    /// Addresses dialled and not yet connected or failed
+    pending: HashSet<SocketAddr>,
+    /// Addresses connected to
+    outbound: HashSet<SocketAddr>,
+}
+
+impl ConnectionManager {
+    /// A manager keeping `target` outbound connections to addresses from
+    /// `book`
+    pub fn new(book: AddrBook, target: usize) -> Self {
+        ConnectionManager {
+            book,
+            target,
+            pending: HashSet::new(),
+            outbound: HashSet::new(),
+        }
+    }
+
+    pub fn book(&self) -> &AddrBook {
+        &self.book
+    }
+
+    pub fn book_mut(&mut self) -> &mut AddrBook {
+        &mut self.book
+    }
+
+    /// Addresses connected to, in no particular order
+    pub fn outbound(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.outbound.iter().copied()
+    }
+
+    /// The addresses to dial now to reach the target, counting the dials
+    /// under way, skipping those `skip` rules out, such as banned ones or
+    /// peers already connected. Each is under way until
+    /// [`ConnectionManager::connected`] or [`ConnectionManager::failed`].
+    pub fn to_dial(&mut self, skip: impl Fn(&SocketAddr) -> bool) -> Vec<SocketAddr> {
+        let wanted = self
+            .target
+            .saturating_sub(self.outbound.len() + self.pending.len());
+        if wanted == 0 {
+            return Vec::new();
+        }
+        let (pending, outbound) = (&self.pending, &self.outbound);
+        let dial = self.book.candidates(wanted, |addr| {
+            pending.contains(addr) || outbound.contains(addr) || skip(addr)
+        });
+        for addr in &dial {
+            self.book.mark_attempt(addr);
+            self.pending.insert(*addr);
+        }
+        dial
+    }
+
+    /// A dial to `addr`, by this manager or another, ended in a connection
+    pub fn connected(&mut self, addr: SocketAddr) {
+        self.pending.remove(&addr);
+        self.outbound.insert(addr);
+        self.book.mark_success(addr);
+    }
+
+    /// A dial to `addr` failed
+    pub fn failed(&mut self, addr: &SocketAddr) {
+        self.pending.remove(addr);
+        self.book.mark_failure(addr);
+    }
+
+    /// The connection to `addr` closed, freeing its place if outbound.
+    /// Returns whether it was.
+    pub fn disconnected(&mut self, addr: &SocketAddr) -> bool {
+        self.outbound.remove(addr)
+    }
+
+    /// Whether `addr` is being dialled
+    pub fn is_pending(&self, addr: &SocketAddr) -> bool {
+        self.pending.contains(addr)
+    }
+}
+
+/// Append `addr` as [`ADDR_LEN`] bytes: the IP address as IPv6, with an
+/// IPv4 one mapped into it, then the port
+pub(super) fn write_addr(buf: &mut Vec<u8>, addr: &SocketAddr) {
+    let ip = match addr.ip() {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    buf.extend_from_slice(&ip.octets());
+    buf.extend_from_slice(&addr.port().to_le_bytes());
+}
+
+/// Read an address [`write_addr`] wrote, IPv4 ones as IPv4
+pub(super) fn read_addr(reader: &mut Reader<'_>) -> Result<SocketAddr, DecodeError> {
+    let ip = Ipv6Addr::from(reader.read_array::<16>()?).to_canonical();
+    let port = u16::from_le_bytes(reader.read_array()?);
+    Ok(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[derive(Debug)]
+    struct ManualClock(AtomicU64);
+
+    impl ManualClock {
+        fn advance(&self, secs: u64) {
+            self.0.fetch_add(secs, Ordering::Relaxed);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_book_evicts_worst_and_persists() {
+        let clock = Arc::new(ManualClock(AtomicU64::new(1000)));
+        let mut book = AddrBook::new(3).with_clock(clock.clone());
+        assert!(book.add(addr(1), 900));
+        assert!(book.add(addr(2), 950));
+        assert!(!book.add(addr(2), 990));
+        assert_eq!(book.get(&addr(2)).unwrap().last_seen, 990);
+        // A time in the future counts as now
+        assert!(book.add(addr(3), 5000));
+        assert_eq!(book.get(&addr(3)).unwrap().last_seen, 1000);
+
+        // Full, the book drops the least recently seen, unless the new
+        // address is older still
+        book.mark_success(addr(1));
+        assert!(book.add(addr(4), 999));
+        assert!(book.get(&addr(2)).is_none());
+        assert!(!book.add(addr(5), 10));
+        assert_eq!(book.len(), 3);
+        // One failing goes before one seen long ago
+        book.mark_failure(&addr(3));
+        assert!(book.add(addr(6), 500));
+        assert!(book.get(&addr(3)).is_none());
+
+        let dir = std::env::temp_dir().join(format!("aarwyn-addrbook-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("peers.dat");
+        assert!(book.add(SocketAddr::from((Ipv6Addr::LOCALHOST, 8333)), 1000));
+        book.mark_failure(&addr(4));
+        book.save(&path).unwrap();
+        let loaded = AddrBook::load(&path, 3).unwrap();
+        let sorted = |book: &AddrBook| {
+            let mut entries: Vec<AddrEntry> = book.iter().cloned().collect();
+            entries.sort_by_key(|entry| entry.addr);
+            entries
+        };
+        assert_eq!(sorted(&loaded), sorted(&book));
+        assert!(loaded.iter().any(|entry| entry.addr.is_ipv6()));
+        // Loaded into a smaller book, the best are kept
+        assert_eq!(
+            AddrBook::load(&path, 1).unwrap().get(&addr(1)),
+            book.get(&addr(1))
+        );
+
+        // A missing file is an empty book, and a damaged one an error
+        assert!(AddrBook::load(dir.join("missing.dat"), 3)
+            .unwrap()
+            .is_empty());
+        let mut damaged = fs::read(&path).unwrap();
+        damaged[10] ^= 1;
+        fs::write(&path, damaged).unwrap();
+        assert!(matches!(
+            AddrBook::load(&path, 3),
+            Err(StoreError::Corrupt(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_manager_backs_off_failing_addresses() {
+        let clock = Arc::new(ManualClock(AtomicU64::new(1000)));
+        let mut book = AddrBook::new(10).with_clock(clock.clone());
+        book.add(addr(1), 901);
+        book.add(addr(2), 902);
+        let mut manager = ConnectionManager::new(book, 1);
+        // A dialer that fails the addresses in `down` and reaches the rest
+        let dial = |manager: &mut ConnectionManager, down: &[u16]| {
+            let dialled = manager.to_dial(|_| false);
+            for addr in &dialled {
+                if down.contains(&addr.port()) {
+                    manager.failed(addr);
+                } else {
+                    manager.connected(*addr);
+                }
+            }
+            dialled.iter().map(SocketAddr::port).collect::<Vec<_>>()
+        };
+        let held_back = |manager: &ConnectionManager, port| {
+            manager.book().get(&addr(port)).unwrap().retry_at() - clock.now()
+        };
+
+        // The most recently seen first, then the other once it fails
+        assert_eq!(dial(&mut manager, &[2]), [2]);
+        assert_eq!(held_back(&manager, 2), 60);
+        assert_eq!(dial(&mut manager, &[]), [1]);
+        assert_eq!(dial(&mut manager, &[]), Vec::<u16>::new());
+
+        // Once it drops, the address that worked is tried first
+        assert!(manager.disconnected(&addr(1)));
+        assert_eq!(dial(&mut manager, &[1]), [1]);
+        assert_eq!(dial(&mut manager, &[]), Vec::<u16>::new());
+
+        // Each failure doubles the delay
+        clock.advance(60);
+        assert_eq!(dial(&mut manager, &[1]), [1]);
+        assert_eq!(dial(&mut manager, &[2]), [2]);
+        assert_eq!(held_back(&manager, 1), 120);
+        assert_eq!(held_back(&manager, 2), 120);
+        clock.advance(119);
+        assert_eq!(dial(&mut manager, &[]), Vec::<u16>::new());
+        clock.advance(1);
+        assert_eq!(dial(&mut manager, &[]), [1]);
+        assert_eq!(manager.book().get(&addr(1)).unwrap().failures, 0);
+        assert_eq!(manager.book().get(&addr(2)).unwrap().failures, 2);
+        assert_eq!(manager.outbound().collect::<Vec<_>>(), [addr(1)]);
+
+        // Up to a limit
+        let entry = AddrEntry {
+            failures: 3,
+            last_attempt: Some(0),
+            ..manager.book().get(&addr(2)).unwrap().clone()
+        };
+        assert_eq!(entry.retry_at(), 240);
+        let entry = AddrEntry {
+            failures: 100,
+            ..entry
+        };
+        assert_eq!(entry.retry_at(), MAX_RETRY_DELAY.as_secs());
+    }
+}
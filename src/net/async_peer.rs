@@ -0,0 +1,319 @@
+//! Peers on tokio: the frame codec as a `tokio_util` codec, and the
+//! connection it frames.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures_util::{SinkExt, StreamExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::time::{timeout_at, Instant};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+use super::handshake::check_version;
+use super::message::{decode_payload, encode_frame_to, parse_header};
+use super::{
+    HandshakeError, Message, NetError, PeerInfo, Version, FRAME_HEADER_LEN, HANDSHAKE_TIMEOUT,
+};
+use crate::codec::{DecodeError, DecodeLimits};
+
+/// The frames of [`super::write_frame`] and [`super::read_frame`] as a
+/// codec, for framing an async stream.
+///
+/// A header is checked as soon as it has arrived, so a frame that is too
+/// large fails before its payload is buffered.
+#[derive(Clone, Copy, Debug)]
+pub struct FrameCodec {
+    magic: u32,
+    limits: DecodeLimits,
+    /// Bytes of the frames decoded so far
+    decoded: u64,
+}
+
+impl FrameCodec {
+    /// A codec for the network named by `magic`, decoding under `limits`
+    pub fn new(magic: u32, limits: DecodeLimits) -> Self {
+        FrameCodec {
+            magic,
+            limits,
+            decoded: 0,
+        }
+    }
+
+    /// Bytes of the frames decoded so far, headers included
+    pub fn bytes_decoded(&self) -> u64 {
+        self.decoded
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Message;
+    type Error = NetError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Message>, NetError> {
+        let Some(&header) = src.first_chunk::<FRAME_HEADER_LEN>() else {
+            src.reserve(FRAME_HEADER_LEN - src.len());
+            return Ok(None);
+        };
+        let (command, len) = parse_header(&header, self.magic, &self.limits)?;
+        if src.len() < FRAME_HEADER_LEN + len {
+            src.reserve(FRAME_HEADER_LEN + len - src.len());
+            return Ok(None);
+        }
+        src.advance(FRAME_HEADER_LEN);
+        let payload = src.split_to(len);
+        let message = decode_payload(&header, command, &payload, &self.limits)?;
+        self.decoded += (FRAME_HEADER_LEN + len) as u64;
+        Ok(Some(message))
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Message>, NetError> {
+        match self.decode(src)? {
+            Some(message) => Ok(Some(message)),
+            None if src.is_empty() => Ok(None),
+            // The stream ended part way through a frame
+            None => Err(DecodeError::UnexpectedEof.into()),
+        }
+    }
+}
+
+impl Encoder<&Message> for FrameCodec {
+    type Error = NetError;
+
+    fn encode(&mut self, message: &Message, dst: &mut BytesMut) -> Result<(), NetError> {
+        encode_frame_to(&mut dst.writer(), self.magic, message)?;
+        Ok(())
+    }
+}
+
+/// A connection to another node over tokio, the async counterpart of
+/// [`super::Peer`]
+#[derive(Debug)]
+pub struct AsyncPeer {
+    framed: Framed<TcpStream, FrameCodec>,
+    handshake_timeout: Duration,
+    /// Set once the handshake is done
+    info: Option<PeerInfo>,
+}
+
+impl AsyncPeer {
+    /// Wrap an open connection to a node on the network named by `magic`
+    pub fn new(stream: TcpStream, magic: u32) -> Self {
+        AsyncPeer {
+            framed: Framed::new(stream, FrameCodec::new(magic, DecodeLimits::default())),
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            info: None,
+        }
+    }
+
+    /// Connect to the node at `addr`
+    pub async fn connect(addr: impl ToSocketAddrs, magic: u32) -> Result<Self, NetError> {
+        Ok(AsyncPeer::new(TcpStream::connect(addr).await?, magic))
+    }
+
+    /// Decode received blocks under `limits` rather than the defaults
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.framed.codec_mut().limits = limits;
+        self
+    }
+
+    /// Give the handshake `timeout` instead of [`HANDSHAKE_TIMEOUT`]
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// The address of the other end
+    pub fn peer_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.framed.get_ref().peer_addr()?)
+    }
+
+    /// The address of this end
+    pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.framed.get_ref().local_addr()?)
+    }
+
+    /// What the peer said about itself, once the handshake is done
+    pub fn info(&self) -> Option<&PeerInfo> {
+        self.info.as_ref()
+    }
+
+    /// Bytes of the frames received so far, headers included
+    pub fn bytes_received(&self) -> u64 {
+        self.framed.codec().bytes_decoded()
+    }
+
+    /// Exchange `Version` messages as [`super::Peer::handshake`] does
+    pub async fn handshake(&mut self, local: &Version) -> Result<PeerInfo, HandshakeError> {
+        if self.info.is_some() {
+            return Err(HandshakeError::UnexpectedMessage("version"));
+        }
+        let deadline = Instant::now() + self.handshake_timeout;
+        match timeout_at(deadline, self.exchange_versions(local)).await {
+            Ok(info) => {
+                let info = info?;
+                self.info = Some(info.clone());
+                Ok(info)
+            }
+            Err(_) => Err(NetError::TimedOut.into()),
+        }
+    }
+
+    async fn exchange_versions(&mut self, local: &Version) -> Result<PeerInfo, HandshakeError> {
+        self.framed.send(&Message::Version(local.clone())).await?;
+        let mut info = None;
+        let mut acknowledged = false;
+        loop {
+            match info {
+                Some(info) if acknowledged => return Ok(info),
+                _ => {}
+            }
+            match self.next_frame().await? {
+                Message::Version(remote) if info.is_none() => {
+                    info = Some(check_version(local, remote)?);
+                    self.framed.send(&Message::VerAck).await?;
+                }
+                Message::VerAck if !acknowledged => acknowledged = true,
+                other => return Err(HandshakeError::UnexpectedMessage(other.command())),
+            }
+        }
+    }
+
+    pub async fn send(&mut self, message: &Message) -> Result<(), NetError> {
+        if self.info.is_none() {
+            return Err(NetError::HandshakeRequired);
+        }
+        self.framed.send(message).await
+    }
+
+    /// Wait for the next message. Dropping the future before it completes
+    /// loses nothing: a frame part way through arriving stays buffered for
+    /// the next call. After an error other than [`NetError::Io`] or
+    /// [`NetError::HandshakeRequired`], the connection should be dropped.
+    pub async fn recv(&mut self) -> Result<Message, NetError> {
+        if self.info.is_none() {
+            return Err(NetError::HandshakeRequired);
+        }
+        self.next_frame().await
+    }
+
+    async fn next_frame(&mut self) -> Result<Message, NetError> {
+        self.framed.next().await.unwrap_or(Err(NetError::Closed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{version, MAGIC};
+    use super::super::{write_frame, InvItem};
+    use super::*;
+    use crate::params::ChainParams;
+    use tokio::net::TcpListener;
+
+    async fn connected_pair() -> (AsyncPeer, AsyncPeer) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let dialer = AsyncPeer::connect(listener.local_addr().unwrap(), MAGIC)
+            .await
+            .unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        (dialer, AsyncPeer::new(stream, MAGIC))
+    }
+
+    #[test]
+    fn test_codec_decodes_frames_as_they_arrive() {
+        let block = ChainParams::test_defaults().genesis_block();
+        let messages = [
+            Message::Ping(4),
+            Message::BlockMsg(Box::new(block.clone())),
+            Message::Inv(vec![InvItem::Block(block.hash())]),
+        ];
+        let mut bytes = Vec::new();
+        for message in &messages {
+            write_frame(&mut bytes, MAGIC, message).unwrap();
+        }
+        let len = bytes.len();
+
+        // Fed a byte at a time, each message comes out once its last byte is in
+        let mut codec = FrameCodec::new(MAGIC, DecodeLimits::default());
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in bytes {
+            buf.extend_from_slice(&[byte]);
+            if let Some(message) = codec.decode(&mut buf).unwrap() {
+                decoded.push(message);
+            }
+        }
+        assert_eq!(decoded, messages);
+        assert_eq!(codec.bytes_decoded(), len as u64);
+        assert!(buf.is_empty());
+        assert_eq!(codec.decode_eof(&mut buf), Ok(None));
+
+        let mut encoded = BytesMut::new();
+        codec.encode(&messages[0], &mut encoded).unwrap();
+        encoded.truncate(encoded.len() - 1);
+        assert_eq!(
+            codec.decode_eof(&mut encoded),
+            Err(NetError::Decode(DecodeError::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_codec_checks_header_before_payload() {
+        let mut frame = Vec::new();
+        write_frame(&mut frame, MAGIC, &Message::Ping(1)).unwrap();
+        // Claim a payload far over a ping's, sending only the header
+        frame[16..20].copy_from_slice(&1_000_000u32.to_le_bytes());
+        let mut buf = BytesMut::from(&frame[..FRAME_HEADER_LEN]);
+        assert_eq!(
+            FrameCodec::new(MAGIC, DecodeLimits::default()).decode(&mut buf),
+            Err(NetError::PayloadTooLarge {
+                command: "ping".to_string(),
+                len: 1_000_000,
+                max: 8,
+            })
+        );
+        assert_eq!(
+            FrameCodec::new(MAGIC ^ 1, DecodeLimits::default()).decode(&mut buf),
+            Err(NetError::BadMagic {
+                expected: MAGIC ^ 1,
+                got: MAGIC,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_async_peers_handshake_and_exchange() {
+        let (mut a, mut b) = connected_pair().await;
+        assert_eq!(
+            a.send(&Message::Ping(1)).await,
+            Err(NetError::HandshakeRequired)
+        );
+
+        let (local, remote) = (version(1), version(2));
+        let (info_a, info_b) = tokio::join!(a.handshake(&local), b.handshake(&remote));
+        assert_eq!(info_a.unwrap().best_height, 7);
+        assert_eq!(info_b.unwrap(), *a.info().unwrap());
+
+        a.send(&Message::Ping(9)).await.unwrap();
+        assert_eq!(b.recv().await, Ok(Message::Ping(9)));
+        drop(a);
+        assert_eq!(b.recv().await, Err(NetError::Closed));
+    }
+
+    #[tokio::test]
+    async fn test_async_handshake_times_out_and_detects_self() {
+        let (a, _silent) = connected_pair().await;
+        let mut a = a.with_handshake_timeout(Duration::from_millis(100));
+        assert_eq!(
+            a.handshake(&version(1)).await,
+            Err(HandshakeError::Net(NetError::TimedOut))
+        );
+        assert_eq!(a.info(), None);
+
+        let (mut a, mut b) = connected_pair().await;
+        let local = version(1);
+        let (result_a, result_b) = tokio::join!(a.handshake(&local), b.handshake(&local));
+        assert_eq!(result_a, Err(HandshakeError::SelfConnection));
+        assert_eq!(result_b, Err(HandshakeError::SelfConnection));
+    }
+}
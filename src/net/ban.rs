@@ -0,0 +1,194 @@
+//! Scoring peers for what they send, and banning the worst for a while.
+//!
+//! Each [`Offense`] carries a penalty by how much harm it could do, from a
+//! block without its proof of work down to a message nobody asked for. A
+//! peer's penalties add up to its [`MisbehaviorScore`]; one whose score
+//! reaches the threshold is disconnected and its IP address put on a
+//! [`BanList`] until the ban expires.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::params::{Clock, SystemClock};
+
+/// Misbehavior score at which a peer is banned
+pub const BAN_THRESHOLD: u32 = 100;
+
+/// How long a ban lasts unless configured otherwise
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Ways a peer can misbehave, each with its own penalty
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Offense {
+    /// A block or header without the work its difficulty demands, which
+    /// costs the sender nothing to make
+    InvalidProofOfWork,
+    /// A block or header breaking another of the chain's rules
+    InvalidBlock,
+    /// A frame that could not be parsed: a bad checksum, an unknown command,
+    /// an oversized or malformed payload
+    Malformed,
+    /// A transaction paying out more than it spends
+    InvalidTransaction,
+    /// Data sent without being asked for, in answer to a request no longer
+    /// outstanding, or building on nothing the node has
+    Unsolicited,
+}
+
+impl Offense {
+    /// What the offense adds to the misbehavior score
+    pub fn penalty(self) -> u32 {
+        match self {
+            Offense::InvalidProofOfWork => BAN_THRESHOLD,
+            Offense::InvalidBlock => 50,
+            Offense::Malformed => 20,
+            Offense::InvalidTransaction => 10,
+            Offense::Unsolicited => 5,
+        }
+    }
+}
+
+impl fmt::Display for Offense {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Offense::InvalidProofOfWork => write!(f, "invalid proof of work"),
+            Offense::InvalidBlock => write!(f, "invalid block"),
+            Offense::Malformed => write!(f, "malformed message"),
+            Offense::InvalidTransaction => write!(f, "invalid transaction"),
+            Offense::Unsolicited => write!(f, "unsolicited message"),
+        }
+    }
+}
+
+/// The sum of the penalties a peer has earned
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MisbehaviorScore(u32);
+
+impl MisbehaviorScore {
+    pub fn value(self) -> u32 {
+        self.0
+    }
+
+    /// Add the penalty for `offense`, returning the new score
+    pub fn add(&mut self, offense: Offense) -> u32 {
+        self.0 = self.0.saturating_add(offense.penalty());
+        self.0
+    }
+}
+
+/// IP addresses banned until a time on a [`Clock`]
+#[derive(Clone, Debug)]
+pub struct BanList {
+    /// When each ban ends, in seconds since the Unix epoch
+    until: HashMap<IpAddr, u64>,
+    duration: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for BanList {
+    fn default() -> Self {
+        Self::new(DEFAULT_BAN_DURATION)
+    }
+}
+
+impl BanList {
+    /// A ban list whose bans last `duration`
+    pub fn new(duration: Duration) -> Self {
+        BanList {
+            until: HashMap::new(),
+            duration,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Time bans by `clock` rather than the system clock
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Ban `ip` from now for the list's duration, extending any ban it is
+    /// under. Returns when the ban ends.
+    pub fn ban(&mut self, ip: IpAddr) -> u64 {
+        let until = self.clock.now().saturating_add(self.duration.as_secs());
+        let entry = self.until.entry(ip).or_insert(until);
+        *entry = (*entry).max(until);
+        *entry
+    }
+
+    /// Lift any ban on `ip`, returning whether there was one in force
+    pub fn unban(&mut self, ip: &IpAddr) -> bool {
+        let banned = self.is_banned(ip);
+        self.until.remove(ip);
+        banned
+    }
+
+    /// When the ban on `ip` ends, if it is banned now
+    pub fn banned_until(&self, ip: &IpAddr) -> Option<u64> {
+        let until = *self.until.get(ip)?;
+        (until > self.clock.now()).then_some(until)
+    }
+
+    pub fn is_banned(&self, ip: &IpAddr) -> bool {
+        self.banned_until(ip).is_some()
+    }
+
+    /// The bans in force and when each ends, forgetting those expired
+    pub fn banned(&mut self) -> Vec<(IpAddr, u64)> {
+        let now = self.clock.now();
+        self.until.retain(|_, until| *until > now);
+        let mut banned: Vec<_> = self.until.iter().map(|(ip, until)| (*ip, *until)).collect();
+        banned.sort();
+        banned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::FixedClock;
+
+    #[test]
+    fn test_score_reaches_threshold_by_offense() {
+        let mut score = MisbehaviorScore::default();
+        assert_eq!(score.add(Offense::Unsolicited), 5);
+        assert_eq!(score.add(Offense::Malformed), 25);
+        assert!(score.value() < BAN_THRESHOLD);
+        assert!(score.add(Offense::InvalidProofOfWork) >= BAN_THRESHOLD);
+
+        // Each offense is worse than the next
+        let offenses = [
+            Offense::InvalidProofOfWork,
+            Offense::InvalidBlock,
+            Offense::Malformed,
+            Offense::InvalidTransaction,
+            Offense::Unsolicited,
+        ];
+        for pair in offenses.windows(2) {
+            assert!(pair[0].penalty() > pair[1].penalty());
+        }
+    }
+
+    #[test]
+    fn test_bans_last_their_duration() {
+        let ip: IpAddr = [10, 0, 0, 1].into();
+        let other: IpAddr = [10, 0, 0, 2].into();
+        let mut bans = BanList::new(Duration::from_secs(60)).with_clock(Arc::new(FixedClock(1000)));
+        assert_eq!(bans.ban(ip), 1060);
+        assert_eq!(bans.banned_until(&ip), Some(1060));
+        assert!(!bans.is_banned(&other));
+
+        // Seen from a minute later, the ban is over
+        let mut later = bans.clone().with_clock(Arc::new(FixedClock(1060)));
+        assert!(!later.is_banned(&ip));
+        assert_eq!(later.banned(), Vec::new());
+        assert_eq!(bans.banned(), vec![(ip, 1060)]);
+
+        assert!(bans.unban(&ip));
+        assert!(!bans.unban(&ip));
+        assert!(!bans.is_banned(&ip));
+    }
+}
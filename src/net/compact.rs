@@ -0,0 +1,328 @@
+//! Compact blocks: a block's header with a short id for each transaction in
+//! place of the transaction itself.
+//!
+//! A peer that has most of a new block's transactions in its mempool
+//! already rebuilds the block from a [`CompactBlock`], asking with a
+//! `GetBlockTxn` for only those it lacks. Short ids are salted with the
+//! block's hash, which costs a block's proof of work to change, so nobody
+//! can cheaply make transactions whose ids collide in the blocks of others.
+
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::codec::{self, DecodeError, DecodeLimits, Reader};
+use crate::crypto::{Signature, SignatureScheme};
+use crate::mempool::Mempool;
+use crate::transaction::Txid;
+
+/// Length of a short transaction id, in bytes
+pub const SHORT_ID_LEN: usize = 6;
+
+/// A transaction id cut short, salted with the hash of the block holding
+/// the transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShortTxId([u8; SHORT_ID_LEN]);
+
+impl ShortTxId {
+    /// The short id of `txid` in the block with hash `block`: the first
+    /// [`SHORT_ID_LEN`] bytes of the SHA-256 of the two
+    pub fn new(block: &BlockHash, txid: &Txid) -> Self {
+        let digest = Sha256::new()
+            .chain_update(block.as_bytes())
+            .chain_update(txid.as_bytes())
+            .finalize();
+        ShortTxId(std::array::from_fn(|i| digest[i]))
+    }
+
+    pub fn from_bytes(bytes: [u8; SHORT_ID_LEN]) -> Self {
+        ShortTxId(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; SHORT_ID_LEN] {
+        &self.0
+    }
+}
+
+/// A block as its header and signature, the transactions sent whole, and a
+/// short id for each of the rest
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactBlock {
+    pub header: BlockHeader,
+    pub signature: Option<Signature>,
+    /// Short ids of the transactions not sent whole, in block order
+    pub short_ids: Vec<ShortTxId>,
+    /// Transactions sent whole, with their index in the block, in order
+    pub prefilled: Vec<(usize, Vec<u8>)>,
+}
+
+/// Reasons a block could not be rebuilt from its compact form
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CompactError {
+    /// A prefilled index is out of order or past the end of the block
+    #[error("prefilled transaction index {0} out of order")]
+    BadPrefilledIndex(usize),
+    /// The transactions sent for the missing ones are not as many
+    #[error("sent {got} transactions for {expected} missing")]
+    WrongTransactionCount { expected: usize, got: usize },
+    /// Transactions are still missing
+    #[error("{0} transactions still missing")]
+    Incomplete(usize),
+    /// The transactions found do not hash to the header's merkle root, so a
+    /// short id matched the wrong transaction
+    #[error("transactions do not match the merkle root")]
+    MerkleRootMismatch,
+}
+
+impl CompactBlock {
+    /// `block` in compact form, with its first transaction, the coinbase no
+    /// mempool holds, sent whole
+    pub fn new(block: &Block) -> Self {
+        let hash = block.hash();
+        let transactions = block.transactions();
+        CompactBlock {
+            header: block.header().clone(),
+            signature: block.signature().cloned(),
+            short_ids: transactions
+                .iter()
+                .skip(1)
+                .map(|tx| ShortTxId::new(&hash, &Txid::of(tx)))
+                .collect(),
+            prefilled: transactions
+                .first()
+                .map(|tx| (0, tx.clone()))
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    pub fn hash(&self) -> BlockHash {
+        self.header.hash()
+    }
+
+    /// Number of transactions in the block
+    pub fn tx_count(&self) -> usize {
+        self.short_ids.len() + self.prefilled.len()
+    }
+
+    /// Start rebuilding the block with the prefilled transactions and those
+    /// `mempool` holds. A short id two pooled transactions share is left
+    /// missing, to be asked for.
+    pub fn reconstruct(&self, mempool: &Mempool) -> Result<PartialBlock, CompactError> {
+        let hash = self.hash();
+        let mut transactions = vec![None; self.tx_count()];
+        let mut last = None;
+        for (index, tx) in &self.prefilled {
+            if *index >= transactions.len() || last.is_some_and(|last| *index <= last) {
+                return Err(CompactError::BadPrefilledIndex(*index));
+            }
+            transactions[*index] = Some(tx.clone());
+            last = Some(*index);
+        }
+
+        // Each short id maps to the one pooled transaction it names, or to
+        // none if several share it
+        let mut pooled = HashMap::new();
+        for (txid, tx) in mempool.iter() {
+            pooled
+                .entry(ShortTxId::new(&hash, txid))
+                .and_modify(|found| *found = None)
+                .or_insert(Some(tx));
+        }
+        let empty = transactions.iter_mut().filter(|tx| tx.is_none());
+        for (slot, short_id) in empty.zip(&self.short_ids) {
+            if let Some(Some(tx)) = pooled.get(short_id) {
+                *slot = Some(tx.encode());
+            }
+        }
+        Ok(PartialBlock {
+            header: self.header.clone(),
+            signature: self.signature,
+            transactions,
+        })
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.header.to_bytes();
+        match &self.signature {
+            Some(signature) => {
+                buf.push(signature.scheme().tag());
+                buf.extend_from_slice(&signature.to_bytes());
+            }
+            None => buf.push(0),
+        }
+        codec::write_varint(&mut buf, self.short_ids.len() as u64);
+        for short_id in &self.short_ids {
+            buf.extend_from_slice(short_id.as_bytes());
+        }
+        codec::write_varint(&mut buf, self.prefilled.len() as u64);
+        for (index, tx) in &self.prefilled {
+            codec::write_varint(&mut buf, *index as u64);
+            codec::write_bytes(&mut buf, tx);
+        }
+        buf
+    }
+
+    /// Decode what [`CompactBlock::encode`] wrote, holding it to the
+    /// transaction limits a block has
+    pub fn decode(reader: &mut Reader<'_>, limits: &DecodeLimits) -> Result<Self, DecodeError> {
+        let header = BlockHeader::decode(reader)?;
+        let signature = match reader.read_u8()? {
+            0 => None,
+            tag => {
+                let scheme = SignatureScheme::from_tag(tag)
+                    .ok_or(DecodeError::InvalidValue("signature flag"))?;
+                Some(Signature::from_bytes(scheme, &reader.read_array()?))
+            }
+        };
+        let count = reader.read_len("short id count", limits.max_transactions)?;
+        let mut short_ids = Vec::with_capacity(count);
+        for _ in 0..count {
+            short_ids.push(ShortTxId(reader.read_array()?));
+        }
+        let count = reader.read_len("prefilled count", limits.max_transactions)?;
+        let mut prefilled = Vec::with_capacity(count.min(reader.remaining()));
+        for _ in 0..count {
+            let index = reader.read_len("prefilled index", limits.max_transactions)?;
+            let tx = reader.read_var_bytes("transaction size", limits.max_transaction_bytes)?;
+            prefilled.push((index, tx.to_vec()));
+        }
+        if short_ids.len() + prefilled.len() > limits.max_transactions {
+            return Err(DecodeError::InvalidValue("transaction count"));
+        }
+        Ok(CompactBlock {
+            header,
+            signature,
+            short_ids,
+            prefilled,
+        })
+    }
+}
+
+/// A block being rebuilt from its compact form
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialBlock {
+    header: BlockHeader,
+    signature: Option<Signature>,
+    transactions: Vec<Option<Vec<u8>>>,
+}
+
+impl PartialBlock {
+    pub fn hash(&self) -> BlockHash {
+        self.header.hash()
+    }
+
+    /// Indexes of the transactions still missing, in order
+    pub fn missing(&self) -> Vec<usize> {
+        (0..self.transactions.len())
+            .filter(|index| self.transactions[*index].is_none())
+            .collect()
+    }
+
+    /// Fill the missing transactions with `transactions`, one for each in
+    /// order of index
+    pub fn fill(&mut self, transactions: Vec<Vec<u8>>) -> Result<(), CompactError> {
+        let missing = self.missing();
+        if transactions.len() != missing.len() {
+            return Err(CompactError::WrongTransactionCount {
+                expected: missing.len(),
+                got: transactions.len(),
+            });
+        }
+        for (index, tx) in missing.into_iter().zip(transactions) {
+            self.transactions[index] = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// The block, once every transaction is in and they match the
+    /// header's merkle root
+    pub fn into_block(self) -> Result<Block, CompactError> {
+        let missing = self.missing().len();
+        if missing > 0 {
+            return Err(CompactError::Incomplete(missing));
+        }
+        let transactions = self.transactions.into_iter().flatten().collect();
+        // A block with no transactions has no root to match
+        match Block::from_parts(self.header, self.signature, transactions) {
+            Ok(block) if block.verify_merkle_root() => Ok(block),
+            _ => Err(CompactError::MerkleRootMismatch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::mined;
+    use super::*;
+    use crate::params::ChainParams;
+    use crate::transaction::Transaction;
+
+    fn tx(lock_time: u32) -> Transaction {
+        Transaction {
+            lock_time,
+            ..Transaction::default()
+        }
+    }
+
+    #[test]
+    fn test_rebuilds_block_from_mempool_and_missing_transactions() {
+        let genesis = ChainParams::test_defaults().genesis_block();
+        let txs: Vec<Transaction> = (1..=4).map(tx).collect();
+        let mut block = genesis
+            .next_builder()
+            .transaction(b"coinbase".to_vec())
+            .transactions(txs.iter().map(Transaction::encode))
+            .build()
+            .unwrap();
+        block.mine(ChainParams::test_defaults().initial_difficulty);
+        let compact = CompactBlock::new(&block);
+        assert_eq!(compact.hash(), block.hash());
+        assert_eq!(compact.tx_count(), 5);
+        assert_eq!(compact.prefilled, vec![(0, b"coinbase".to_vec())]);
+        let encoded = compact.encode();
+        let mut reader = Reader::new(&encoded);
+        assert_eq!(
+            CompactBlock::decode(&mut reader, &DecodeLimits::default()),
+            Ok(compact.clone())
+        );
+        reader.finish().unwrap();
+
+        // The pool holds all but the second and fourth, and something else
+        let mut mempool = Mempool::new();
+        for tx in [&txs[0], &txs[2], &tx(9)] {
+            mempool.insert(tx.clone(), 1).unwrap();
+        }
+        let mut partial = compact.reconstruct(&mempool).unwrap();
+        assert_eq!(partial.missing(), vec![2, 4]);
+        assert_eq!(
+            partial.clone().into_block(),
+            Err(CompactError::Incomplete(2))
+        );
+        assert_eq!(
+            partial.fill(vec![txs[1].encode()]),
+            Err(CompactError::WrongTransactionCount {
+                expected: 2,
+                got: 1
+            })
+        );
+
+        // The wrong transaction for a slot breaks the merkle root
+        let mut wrong = partial.clone();
+        wrong.fill(vec![txs[1].encode(), tx(9).encode()]).unwrap();
+        assert_eq!(wrong.into_block(), Err(CompactError::MerkleRootMismatch));
+        partial
+            .fill(vec![txs[1].encode(), txs[3].encode()])
+            .unwrap();
+        assert_eq!(partial.into_block(), Ok(block));
+
+        // A block of just a coinbase needs nothing from the pool
+        let empty = mined(&genesis, b"empty");
+        let partial = CompactBlock::new(&empty)
+            .reconstruct(&Mempool::new())
+            .unwrap();
+        assert_eq!(partial.into_block(), Ok(empty));
+    }
+}
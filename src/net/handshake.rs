@@ -0,0 +1,257 @@
+//! The exchange of `Version` messages that opens every connection.
+
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+
+use super::{write_frame, Message, NetError, Peer, Version};
+use crate::block::BlockHash;
+
+/// Protocol version this build speaks
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Oldest protocol version a peer may speak
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// How long a handshake may take before the peer is given up on
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What a peer told us about itself in the handshake
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerInfo {
+    /// The lower of the two sides' protocol versions, which both speak
+    pub protocol_version: u32,
+    pub services: u64,
+    /// Height of the peer's best chain when it connected
+    pub best_height: u64,
+    pub user_agent: String,
+}
+
+/// Reasons a handshake failed; the connection should be dropped after any
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum HandshakeError {
+    /// Sending or receiving failed, or the peer was silent for too long
+    #[error("handshake failed: {0}")]
+    Net(NetError),
+    /// The peer is on a network with another genesis block
+    #[error("peer has genesis {got} but expected {expected}")]
+    GenesisMismatch { expected: BlockHash, got: BlockHash },
+    /// The peer speaks a protocol version older than the minimum
+    #[error("peer protocol version {got} is older than {min}")]
+    ObsoleteVersion { min: u32, got: u32 },
+    /// The peer sent our own nonce back, so it is this node
+    #[error("connected to self")]
+    SelfConnection,
+    /// The peer sent something other than `Version` and `VerAck`, or sent
+    /// one of them twice or out of order
+    #[error("unexpected {0} message during handshake")]
+    UnexpectedMessage(&'static str),
+}
+
+impl From<NetError> for HandshakeError {
+    fn from(err: NetError) -> Self {
+        HandshakeError::Net(err)
+    }
+}
+
+impl Peer {
+    /// Exchange `Version` messages, `local` describing this node, and
+    /// acknowledge the peer's with a `VerAck`.
+    ///
+    /// The peer's version is refused if its genesis is not `local`'s, if it
+    /// speaks a protocol older than [`MIN_PROTOCOL_VERSION`], or if it carries
+    /// `local`'s nonce. Unless both sides finish within the handshake
+    /// timeout, it fails with [`NetError::TimedOut`]. Until it succeeds,
+    /// [`Peer::send`] and [`Peer::recv`] refuse to run.
+    pub fn handshake(&mut self, local: &Version) -> Result<PeerInfo, HandshakeError> {
+        if self.info.is_some() {
+            return Err(HandshakeError::UnexpectedMessage("version"));
+        }
+        let deadline = Instant::now() + self.handshake_timeout;
+        write_frame(
+            &mut self.stream,
+            self.magic,
+            &Message::Version(local.clone()),
+        )?;
+
+        let mut info: Option<PeerInfo> = None;
+        let mut acknowledged = false;
+        loop {
+            match info {
+                Some(info) if acknowledged => {
+                    self.info = Some(info.clone());
+                    return Ok(info);
+                }
+                _ => {}
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(NetError::TimedOut.into());
+            }
+            match self.read_within(remaining)? {
+                Message::Version(remote) if info.is_none() => {
+                    info = Some(check_version(local, remote)?);
+                    write_frame(&mut self.stream, self.magic, &Message::VerAck)?;
+                }
+                Message::VerAck if !acknowledged => acknowledged = true,
+                other => return Err(HandshakeError::UnexpectedMessage(other.command())),
+            }
+        }
+    }
+
+    /// What the peer said about itself, once the handshake is done
+    pub fn info(&self) -> Option<&PeerInfo> {
+        self.info.as_ref()
+    }
+
+    /// Give the handshake `timeout` instead of [`HANDSHAKE_TIMEOUT`]
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+}
+
+pub(super) fn check_version(local: &Version, remote: Version) -> Result<PeerInfo, HandshakeError> {
+    if remote.nonce == local.nonce {
+        return Err(HandshakeError::SelfConnection);
+    }
+    if remote.genesis_hash != local.genesis_hash {
+        return Err(HandshakeError::GenesisMismatch {
+            expected: local.genesis_hash,
+            got: remote.genesis_hash,
+        });
+    }
+    if remote.protocol_version < MIN_PROTOCOL_VERSION {
+        return Err(HandshakeError::ObsoleteVersion {
+            min: MIN_PROTOCOL_VERSION,
+            got: remote.protocol_version,
+        });
+    }
+    Ok(PeerInfo {
+        protocol_version: remote.protocol_version.min(local.protocol_version),
+        services: remote.services,
+        best_height: remote.best_height,
+        user_agent: remote.user_agent,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{connected_pair, version, MAGIC};
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_handshake() {
+        let (mut a, mut b) = connected_pair();
+        let local = version(1);
+        let remote = Version {
+            best_height: 40,
+            protocol_version: PROTOCOL_VERSION + 1,
+            user_agent: "/other:2.0/".to_string(),
+            ..version(2)
+        };
+
+        // Before the handshake, nothing else goes through
+        assert_eq!(a.send(&Message::Ping(1)), Err(NetError::HandshakeRequired));
+        assert_eq!(a.recv(), Err(NetError::HandshakeRequired));
+
+        let other = thread::spawn(move || {
+            let info = b.handshake(&remote).unwrap();
+            assert_eq!(b.recv(), Ok(Message::Ping(1)));
+            info
+        });
+        let info = a.handshake(&local).unwrap();
+        assert_eq!(
+            info,
+            PeerInfo {
+                protocol_version: PROTOCOL_VERSION,
+                services: 1,
+                best_height: 40,
+                user_agent: "/other:2.0/".to_string(),
+            }
+        );
+        assert_eq!(a.info(), Some(&info));
+        a.send(&Message::Ping(1)).unwrap();
+        assert_eq!(other.join().unwrap().best_height, 7);
+
+        // Handshakes happen once
+        assert_eq!(
+            a.handshake(&local),
+            Err(HandshakeError::UnexpectedMessage("version"))
+        );
+    }
+
+    #[test]
+    fn test_handshake_rejects_other_genesis_and_old_versions() {
+        let (mut a, mut b) = connected_pair();
+        let other = thread::spawn(move || {
+            b.handshake(&Version {
+                genesis_hash: BlockHash::from_bytes([9; 32]),
+                ..version(2)
+            })
+        });
+        assert_eq!(
+            a.handshake(&version(1)),
+            Err(HandshakeError::GenesisMismatch {
+                expected: version(1).genesis_hash,
+                got: BlockHash::from_bytes([9; 32]),
+            })
+        );
+        assert!(other.join().unwrap().is_err());
+        assert_eq!(a.info(), None);
+
+        let (mut a, mut b) = connected_pair();
+        let other = thread::spawn(move || {
+            b.handshake(&Version {
+                protocol_version: MIN_PROTOCOL_VERSION - 1,
+                ..version(2)
+            })
+        });
+        assert_eq!(
+            a.handshake(&version(1)),
+            Err(HandshakeError::ObsoleteVersion {
+                min: MIN_PROTOCOL_VERSION,
+                got: MIN_PROTOCOL_VERSION - 1,
+            })
+        );
+        drop(a);
+        assert!(other.join().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_handshake_detects_self_connection() {
+        // Both ends of a node dialling itself send the same version
+        let (mut a, mut b) = connected_pair();
+        let other = thread::spawn(move || b.handshake(&version(1)));
+        assert_eq!(
+            a.handshake(&version(1)),
+            Err(HandshakeError::SelfConnection)
+        );
+        assert_eq!(other.join().unwrap(), Err(HandshakeError::SelfConnection));
+    }
+
+    #[test]
+    fn test_handshake_times_out_on_silent_peer() {
+        let (a, _silent) = connected_pair();
+        let mut a = a.with_handshake_timeout(Duration::from_millis(100));
+        let started = Instant::now();
+        assert_eq!(
+            a.handshake(&version(1)),
+            Err(HandshakeError::Net(NetError::TimedOut))
+        );
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!(a.info(), None);
+    }
+
+    #[test]
+    fn test_handshake_refuses_other_messages() {
+        let (mut a, b) = connected_pair();
+        let mut raw = b.stream.try_clone().unwrap();
+        write_frame(&mut raw, MAGIC, &Message::Ping(3)).unwrap();
+        assert_eq!(
+            a.handshake(&version(1)),
+            Err(HandshakeError::UnexpectedMessage("ping"))
+        );
+    }
+}
@@ -0,0 +1,846 @@
+//! Protocol messages and the frames that carry them.
+
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::SocketAddr;
+
+use sha2::{Digest, Sha256};
+
+use super::addr_book::{read_addr, write_addr, ADDR_LEN};
+use super::{CompactBlock, NetError, MAX_ADDR_PER_MESSAGE};
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::codec::{self, Checksummed, DecodeError, DecodeLimits, Reader};
+use crate::transaction::{Transaction, Txid};
+
+/// Size of a frame header: magic, command, payload length and checksum
+pub const FRAME_HEADER_LEN: usize = 4 + 12 + 4 + 4;
+
+/// Most items an `Inv` or `GetData` message may list
+pub const MAX_INV_ITEMS: usize = 50_000;
+
+/// Most headers a `Headers` message may carry
+pub const MAX_HEADERS: usize = 2000;
+
+/// Most hashes a `GetHeaders` locator may list
+pub const MAX_LOCATOR_HASHES: usize = 500;
+
+/// Longest user agent a `Version` message may carry, in bytes
+pub const MAX_USER_AGENT_LEN: usize = 256;
+
+/// Service bit of a node that serves full blocks
+pub const SERVICE_FULL_BLOCKS: u64 = 1;
+
+/// Service bit of a node that takes new blocks in compact form, and
+/// answers `GetBlockTxn` for the blocks it sends so
+pub const SERVICE_COMPACT_BLOCKS: u64 = 2;
+
+/// Something a peer can announce or ask for by hash
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InvItem {
+    Block(BlockHash),
+    Tx(Txid),
+}
+
+impl InvItem {
+    fn tag(&self) -> u8 {
+        match self {
+            InvItem::Tx(_) => 1,
+            InvItem::Block(_) => 2,
+        }
+    }
+}
+
+/// The first message each side sends on a new connection; see
+/// [`Peer::handshake`](super::Peer::handshake)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    pub protocol_version: u32,
+    /// Bits naming what the sender offers, such as [`SERVICE_FULL_BLOCKS`]
+    pub services: u64,
+    /// Height of the sender's best chain
+    pub best_height: u64,
+    /// The sender's genesis block, which names its network
+    pub genesis_hash: BlockHash,
+    /// Random per node, so a node can notice it dialled itself
+    pub nonce: u64,
+    pub user_agent: String,
+}
+
+/// A message between peers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Version(Version),
+    /// Acknowledges the peer's `Version`
+    VerAck,
+    /// Asks for a `Pong` echoing the nonce
+    Ping(u64),
+    Pong(u64),
+    /// Announces blocks or transactions the sender has
+    Inv(Vec<InvItem>),
+    /// Asks for the announced blocks or transactions
+    GetData(Vec<InvItem>),
+    BlockMsg(Box<Block>),
+    Tx(Transaction),
+    /// Headers following the fork point of a `GetHeaders` locator, in order
+    Headers(Vec<BlockHeader>),
+    /// Asks for headers after the first locator hash the peer knows, up to
+    /// `stop` or [`MAX_HEADERS`] of them
+    GetHeaders {
+        locator: Vec<BlockHash>,
+        stop: Option<BlockHash>,
+    },
+    /// A new block in compact form, sent unasked to a peer offering
+    /// [`SERVICE_COMPACT_BLOCKS`]
+    CmpctBlock(Box<CompactBlock>),
+    /// Asks for the transactions at `indexes` in `block`, those a compact
+    /// block left missing, in order
+    GetBlockTxn {
+        block: BlockHash,
+        indexes: Vec<usize>,
+    },
+    /// The transactions a `GetBlockTxn` asked for, in its order
+    BlockTxn {
+        block: BlockHash,
+        transactions: Vec<Vec<u8>>,
+    },
+    /// Addresses of peers the sender knows, with when each was last seen
+    /// working, in seconds since the Unix epoch
+    Addr(Vec<(SocketAddr, u64)>),
+    /// Asks for an `Addr` of the peers the receiver knows
+    GetAddr,
+}
+
+impl Message {
+    /// The frame command naming the message, at most twelve ASCII bytes
+    pub fn command(&self) -> &'static str {
+        match self {
+            Message::Version(_) => "version",
+            Message::VerAck => "verack",
+            Message::Ping(_) => "ping",
+            Message::Pong(_) => "pong",
+            Message::Inv(_) => "inv",
+            Message::GetData(_) => "getdata",
+            Message::BlockMsg(_) => "block",
+            Message::Tx(_) => "tx",
+            Message::Headers(_) => "headers",
+            Message::GetHeaders { .. } => "getheaders",
+            Message::CmpctBlock(_) => "cmpctblock",
+            Message::GetBlockTxn { .. } => "getblocktxn",
+            Message::BlockTxn { .. } => "blocktxn",
+            Message::Addr(_) => "addr",
+            Message::GetAddr => "getaddr",
+        }
+    }
+
+    /// The payload a frame carries for the message. Integers are little
+    /// endian and lists follow a varint count; blocks, transactions and
+    /// headers use their own encodings.
+    pub fn encode_payload(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        match self {
+            Message::Version(version) => {
+                buf.extend_from_slice(&version.protocol_version.to_le_bytes());
+                buf.extend_from_slice(&version.services.to_le_bytes());
+                buf.extend_from_slice(&version.best_height.to_le_bytes());
+                buf.extend_from_slice(version.genesis_hash.as_bytes());
+                buf.extend_from_slice(&version.nonce.to_le_bytes());
+                codec::write_bytes(&mut buf, version.user_agent.as_bytes());
+            }
+            Message::VerAck => {}
+            Message::Ping(nonce) | Message::Pong(nonce) => {
+                buf.extend_from_slice(&nonce.to_le_bytes());
+            }
+            Message::Inv(items) | Message::GetData(items) => {
+                codec::write_varint(&mut buf, items.len() as u64);
+                for item in items {
+                    buf.push(item.tag());
+                    buf.extend_from_slice(match item {
+                        InvItem::Block(hash) => hash.as_bytes(),
+                        InvItem::Tx(txid) => txid.as_bytes(),
+                    });
+                }
+            }
+            Message::BlockMsg(block) => buf = block.to_bytes(),
+            Message::Tx(tx) => buf = tx.encode(),
+            Message::Headers(headers) => {
+                codec::write_varint(&mut buf, headers.len() as u64);
+                for header in headers {
+                    buf.extend_from_slice(&header.to_bytes());
+                }
+            }
+            Message::GetHeaders { locator, stop } => {
+                codec::write_varint(&mut buf, locator.len() as u64);
+                for hash in locator {
+                    buf.extend_from_slice(hash.as_bytes());
+                }
+                match stop {
+                    Some(hash) => {
+                        buf.push(1);
+                        buf.extend_from_slice(hash.as_bytes());
+                    }
+                    None => buf.push(0),
+                }
+            }
+            Message::CmpctBlock(compact) => buf = compact.encode(),
+            Message::GetBlockTxn { block, indexes } => {
+                buf.extend_from_slice(block.as_bytes());
+                codec::write_varint(&mut buf, indexes.len() as u64);
+                for index in indexes {
+                    codec::write_varint(&mut buf, *index as u64);
+                }
+            }
+            Message::BlockTxn {
+                block,
+                transactions,
+            } => {
+                buf.extend_from_slice(block.as_bytes());
+                codec::write_varint(&mut buf, transactions.len() as u64);
+                for tx in transactions {
+                    codec::write_bytes(&mut buf, tx);
+                }
+            }
+            Message::Addr(addrs) => {
+                codec::write_varint(&mut buf, addrs.len() as u64);
+                for (addr, last_seen) in addrs {
+                    write_addr(&mut buf, addr);
+                    buf.extend_from_slice(&last_seen.to_le_bytes());
+                }
+            }
+            Message::GetAddr => {}
+        }
+        buf
+    }
+
+    /// Decode the payload of a frame with `command`, enforcing `limits` on
+    /// blocks and transactions
+    pub fn decode(
+        command: &str,
+        payload: &[u8],
+        limits: &DecodeLimits,
+    ) -> Result<Message, NetError> {
+        if command == "block" {
+            return Ok(Message::BlockMsg(Box::new(Block::from_bytes(
+                payload, limits,
+            )?)));
+        }
+        if command == "tx" {
+            return Ok(Message::Tx(Transaction::decode(payload, limits)?));
+        }
+        let mut reader = Reader::new(payload);
+        let message = match command {
+            "version" => {
+                let protocol_version = reader.read_u32()?;
+                let services = reader.read_u64()?;
+                let best_height = reader.read_u64()?;
+                let genesis_hash = BlockHash::from_bytes(reader.read_array()?);
+                let nonce = reader.read_u64()?;
+                let user_agent = reader.read_var_bytes("user agent", MAX_USER_AGENT_LEN)?;
+                let user_agent = String::from_utf8(user_agent.to_vec())
+                    .map_err(|_| DecodeError::InvalidValue("user agent"))?;
+                Message::Version(Version {
+                    protocol_version,
+                    services,
+                    best_height,
+                    genesis_hash,
+                    nonce,
+                    user_agent,
+                })
+            }
+            "verack" => Message::VerAck,
+            "ping" => Message::Ping(reader.read_u64()?),
+            "pong" => Message::Pong(reader.read_u64()?),
+            "inv" => Message::Inv(read_inv(&mut reader)?),
+            "getdata" => Message::GetData(read_inv(&mut reader)?),
+            "headers" => {
+                let count = reader.read_len("header count", MAX_HEADERS)?;
+                let mut headers = Vec::with_capacity(count);
+                for _ in 0..count {
+                    headers.push(BlockHeader::decode(&mut reader)?);
+                }
+                Message::Headers(headers)
+            }
+            "getheaders" => {
+                let count = reader.read_len("locator length", MAX_LOCATOR_HASHES)?;
+                let mut locator = Vec::with_capacity(count);
+                for _ in 0..count {
+                    locator.push(BlockHash::from_bytes(reader.read_array()?));
+                }
+                let stop = match reader.read_u8()? {
+                    0 => None,
+                    1 => Some(BlockHash::from_bytes(reader.read_array()?)),
+                    _ => return Err(DecodeError::InvalidValue("stop hash flag").into()),
+                };
+                Message::GetHeaders { locator, stop }
+            }
+            "cmpctblock" => {
+                Message::CmpctBlock(Box::new(CompactBlock::decode(&mut reader, limits)?))
+            }
+            "getblocktxn" => {
+                let block = BlockHash::from_bytes(reader.read_array()?);
+                let count = reader.read_len("index count", limits.max_transactions)?;
+                let mut indexes = Vec::with_capacity(count);
+                for _ in 0..count {
+                    indexes.push(reader.read_len("transaction index", limits.max_transactions)?);
+                }
+                Message::GetBlockTxn { block, indexes }
+            }
+            "blocktxn" => {
+                let block = BlockHash::from_bytes(reader.read_array()?);
+                let count = reader.read_len("transaction count", limits.max_transactions)?;
+                let mut transactions = Vec::with_capacity(count.min(reader.remaining()));
+                for _ in 0..count {
+                    let tx =
+                        reader.read_var_bytes("transaction size", limits.max_transaction_bytes)?;
+                    transactions.push(tx.to_vec());
+                }
+                Message::BlockTxn {
+                    block,
+                    transactions,
+                }
+            }
+            "addr" => {
+                let count = reader.read_len("address count", MAX_ADDR_PER_MESSAGE)?;
+                let mut addrs = Vec::with_capacity(count);
+                for _ in 0..count {
+                    addrs.push((read_addr(&mut reader)?, reader.read_u64()?));
+                }
+                Message::Addr(addrs)
+            }
+            "getaddr" => Message::GetAddr,
+            _ => return Err(NetError::UnknownCommand(command.to_string())),
+        };
+        reader.finish()?;
+        Ok(message)
+    }
+}
+
+fn read_inv(reader: &mut Reader<'_>) -> Result<Vec<InvItem>, DecodeError> {
+    let count = reader.read_len("inventory size", MAX_INV_ITEMS)?;
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        let tag = reader.read_u8()?;
+        let hash = reader.read_array()?;
+        items.push(match tag {
+            1 => InvItem::Tx(Txid::from_bytes(hash)),
+            2 => InvItem::Block(BlockHash::from_bytes(hash)),
+            _ => return Err(DecodeError::InvalidValue("inventory type")),
+        });
+    }
+    Ok(items)
+}
+
+/// Largest payload a frame with `command` may carry, checked before the
+/// payload is read, or `None` for an unknown command. Blocks and
+/// transactions are bounded by `limits`.
+fn max_payload_len(command: &str, limits: &DecodeLimits) -> Option<usize> {
+    Some(match command {
+        "version" => 4 + 8 + 8 + 32 + 8 + 2 + MAX_USER_AGENT_LEN,
+        "verack" => 0,
+        "ping" | "pong" => 8,
+        "inv" | "getdata" => 3 + MAX_INV_ITEMS * 33,
+        "block" => limits.max_decode_bytes,
+        "tx" => limits.max_transaction_bytes,
+        "headers" => 2 + MAX_HEADERS * BlockHeader::MAX_ENCODED_LEN,
+        "getheaders" => 2 + MAX_LOCATOR_HASHES * 32 + 33,
+        "cmpctblock" | "blocktxn" => limits.max_decode_bytes,
+        "getblocktxn" => 32 + 9 + limits.max_transactions * 9,
+        "addr" => 3 + MAX_ADDR_PER_MESSAGE * (ADDR_LEN + 8),
+        "getaddr" => 0,
+        _ => return None,
+    })
+}
+
+/// The first four bytes of the SHA-256 of `payload`
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let mut checksum = [0u8; 4];
+    checksum.copy_from_slice(&Sha256::digest(payload)[..4]);
+    checksum
+}
+
+/// Write `message` in a frame: `magic`, the command padded with zeros to
+/// twelve bytes, the payload length, the payload checksum, then the payload
+pub fn write_frame(writer: &mut impl Write, magic: u32, message: &Message) -> Result<(), NetError> {
+    let mut writer = BufWriter::new(writer);
+    encode_frame_to(&mut writer, magic, message)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write `message` in a frame to `writer`, as [`write_frame`] does but
+/// without flushing. A block is streamed a transaction at a time rather than
+/// encoded whole, once to size and checksum it and again to write it.
+pub(super) fn encode_frame_to(
+    writer: &mut impl Write,
+    magic: u32,
+    message: &Message,
+) -> io::Result<()> {
+    if let Message::BlockMsg(block) = message {
+        let mut payload = Checksummed::new(io::sink());
+        block.write_to(&mut payload)?;
+        writer.write_all(&frame_header(
+            magic,
+            message.command(),
+            payload.len() as usize,
+            payload.checksum(),
+        ))?;
+        block.write_to(writer)?;
+        return Ok(());
+    }
+    let payload = message.encode_payload();
+    writer.write_all(&frame_header(
+        magic,
+        message.command(),
+        payload.len(),
+        checksum(&payload),
+    ))?;
+    writer.write_all(&payload)
+}
+
+fn frame_header(
+    magic: u32,
+    command: &str,
+    len: usize,
+    checksum: [u8; 4],
+) -> [u8; FRAME_HEADER_LEN] {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    header[..4].copy_from_slice(&magic.to_le_bytes());
+    header[4..4 + command.len()].copy_from_slice(command.as_bytes());
+    header[16..20].copy_from_slice(&(len as u32).to_le_bytes());
+    header[20..].copy_from_slice(&checksum);
+    header
+}
+
+/// Read one frame written by [`write_frame`] and decode its message.
+///
+/// The frame must start with `magic`, name a known command and carry no
+/// more than that command's largest payload, which is checked before the
+/// payload is read. A stream that ends before the first byte of the frame
+/// gives [`NetError::Closed`].
+pub fn read_frame(
+    reader: &mut impl Read,
+    magic: u32,
+    limits: &DecodeLimits,
+) -> Result<Message, NetError> {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    let mut read = 0;
+    while read < header.len() {
+        match reader.read(&mut header[read..]) {
+            Ok(0) if read == 0 => return Err(NetError::Closed),
+            Ok(0) => return Err(DecodeError::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    let (command, len) = parse_header(&header, magic, limits)?;
+    if command == "block" {
+        return read_block_payload(reader, &header, len, limits);
+    }
+    let mut payload = vec![0u8; len];
+    reader
+        .read_exact(&mut payload)
+        .map_err(|err| match err.kind() {
+            std::io::ErrorKind::UnexpectedEof => DecodeError::UnexpectedEof.into(),
+            _ => NetError::from(err),
+        })?;
+    decode_payload(&header, command, &payload, limits)
+}
+
+/// The block in the `len` byte payload of a frame with `header`, decoded as
+/// it is read so the payload is never held whole. Failures are reported as
+/// [`decode_payload`] would report them: a short payload before a bad
+/// checksum, and a bad checksum before anything it garbled.
+fn read_block_payload(
+    reader: &mut impl Read,
+    header: &[u8; FRAME_HEADER_LEN],
+    len: usize,
+    limits: &DecodeLimits,
+) -> Result<Message, NetError> {
+    let mut payload = Checksummed::new(BufReader::new(reader.take(len as u64)));
+    let block = Block::read_from(&mut payload, limits);
+    let decoded = payload.len();
+    // Read what the block left, so the checksum covers the whole payload
+    if let Err(err) = io::copy(&mut payload, &mut io::sink()) {
+        return Err(payload.take_error().unwrap_or(err).into());
+    }
+    if let Some(err) = payload.take_error() {
+        return Err(err.into());
+    }
+    if payload.len() < len as u64 {
+        return Err(DecodeError::UnexpectedEof.into());
+    }
+    if payload.checksum() != header[20..24] {
+        return Err(NetError::BadChecksum);
+    }
+    let block = block?;
+    if decoded < len as u64 {
+        return Err(DecodeError::TrailingBytes((len as u64 - decoded) as usize).into());
+    }
+    Ok(Message::BlockMsg(Box::new(block)))
+}
+
+/// The command a frame header names and the length of the payload after
+/// it, once the magic, the command and the length have been checked
+pub(super) fn parse_header<'a>(
+    header: &'a [u8; FRAME_HEADER_LEN],
+    magic: u32,
+    limits: &DecodeLimits,
+) -> Result<(&'a str, usize), NetError> {
+    let [m0, m1, m2, m3, command @ .., l0, l1, l2, l3, _, _, _, _] = header;
+    let got = u32::from_le_bytes([*m0, *m1, *m2, *m3]);
+    if got != magic {
+        return Err(NetError::BadMagic {
+            expected: magic,
+            got,
+        });
+    }
+    let name_len = command
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(command.len());
+    // Padding must be zeros, so each command has one encoding
+    let command = match std::str::from_utf8(&command[..name_len]) {
+        Ok(name) if command[name_len..].iter().all(|&b| b == 0) => name,
+        _ => return Err(NetError::UnknownCommand(hex::encode(command))),
+    };
+    let max = max_payload_len(command, limits)
+        .ok_or_else(|| NetError::UnknownCommand(command.to_string()))?;
+    let len = u32::from_le_bytes([*l0, *l1, *l2, *l3]) as usize;
+    if len > max {
+        return Err(NetError::PayloadTooLarge {
+            command: command.to_string(),
+            len,
+            max,
+        });
+    }
+    Ok((command, len))
+}
+
+/// The message in `payload`, which followed `header` naming `command`
+pub(super) fn decode_payload(
+    header: &[u8; FRAME_HEADER_LEN],
+    command: &str,
+    payload: &[u8],
+    limits: &DecodeLimits,
+) -> Result<Message, NetError> {
+    if checksum(payload) != header[20..24] {
+        return Err(NetError::BadChecksum);
+    }
+    Message::decode(command, payload, limits)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::codec::ChunkedReader;
+    use crate::testing;
+
+    const MAGIC: u32 = 0xa12b_c34d;
+
+    fn block() -> Block {
+        crate::params::ChainParams::test_defaults().genesis_block()
+    }
+
+    fn messages() -> Vec<Message> {
+        let block = block();
+        vec![
+            Message::Version(Version {
+                protocol_version: 1,
+                services: SERVICE_FULL_BLOCKS,
+                best_height: 12,
+                genesis_hash: block.hash(),
+                nonce: 0x0102_0304_0506_0708,
+                user_agent: "/aarwyn:0.1.0/".to_string(),
+            }),
+            Message::VerAck,
+            Message::Ping(7),
+            Message::Pong(7),
+            Message::Inv(vec![
+                InvItem::Block(block.hash()),
+                InvItem::Tx(Txid::of(b"tx")),
+            ]),
+            Message::GetData(vec![InvItem::Tx(Txid::of(b"tx"))]),
+            Message::Headers(vec![block.header().clone(), block.header().clone()]),
+            Message::GetHeaders {
+                locator: vec![block.hash(), BlockHash::from_bytes([1; 32])],
+                stop: Some(block.hash()),
+            },
+            Message::GetHeaders {
+                locator: Vec::new(),
+                stop: None,
+            },
+            Message::CmpctBlock(Box::new(CompactBlock::new(&block))),
+            Message::GetBlockTxn {
+                block: block.hash(),
+                indexes: vec![1, 4, 5],
+            },
+            Message::BlockTxn {
+                block: block.hash(),
+                transactions: vec![b"one".to_vec(), Vec::new()],
+            },
+            Message::Addr(vec![
+                ("10.0.0.1:8333".parse().unwrap(), 1_700_000_000),
+                ("[2001:db8::1]:18333".parse().unwrap(), 0),
+            ]),
+            Message::GetAddr,
+            Message::BlockMsg(Box::new(block)),
+            Message::Tx(Transaction {
+                lock_time: 5,
+                ..Transaction::default()
+            }),
+        ]
+    }
+
+    fn frame(message: &Message) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_frame(&mut buf, MAGIC, message).unwrap();
+        buf
+    }
+
+    #[test]
+    fn test_frames_round_trip() {
+        let limits = DecodeLimits::default();
+        let mut stream = Vec::new();
+        for message in messages() {
+            write_frame(&mut stream, MAGIC, &message).unwrap();
+        }
+        let mut reader = stream.as_slice();
+        for message in messages() {
+            assert_eq!(read_frame(&mut reader, MAGIC, &limits), Ok(message));
+        }
+        assert_eq!(
+            read_frame(&mut reader, MAGIC, &limits),
+            Err(NetError::Closed)
+        );
+
+        let ping = frame(&Message::Ping(7));
+        assert_eq!(&ping[4..16], b"ping\0\0\0\0\0\0\0\0");
+        assert_eq!(ping.len(), FRAME_HEADER_LEN + 8);
+    }
+
+    #[test]
+    fn test_rejects_bad_frames() {
+        let limits = DecodeLimits::default();
+        let good = frame(&Message::Ping(7));
+        let read = |bytes: &[u8]| read_frame(&mut &bytes[..], MAGIC, &limits);
+
+        assert_eq!(
+            read_frame(&mut good.as_slice(), MAGIC + 1, &limits),
+            Err(NetError::BadMagic {
+                expected: MAGIC + 1,
+                got: MAGIC
+            })
+        );
+
+        let mut flipped = good.clone();
+        *flipped.last_mut().unwrap() ^= 1;
+        assert_eq!(read(&flipped), Err(NetError::BadChecksum));
+
+        let mut unknown = good.clone();
+        unknown[4..8].copy_from_slice(b"pong");
+        unknown[5] = b'x';
+        assert!(matches!(read(&unknown), Err(NetError::UnknownCommand(_))));
+
+        // Garbage after the command's zero padding
+        let mut padded = good.clone();
+        padded[15] = b'x';
+        assert!(matches!(read(&padded), Err(NetError::UnknownCommand(_))));
+
+        assert_eq!(
+            read(&good[..good.len() - 1]),
+            Err(NetError::Decode(DecodeError::UnexpectedEof))
+        );
+        assert_eq!(
+            read(&good[..10]),
+            Err(NetError::Decode(DecodeError::UnexpectedEof))
+        );
+    }
+
+    #[test]
+    fn test_block_frames_stream() {
+        let limits = DecodeLimits::default();
+        let mut block = block()
+            .next_builder()
+            .transactions((0..40u8).map(|i| vec![i; i as usize * 7]))
+            .build()
+            .unwrap();
+        block.sign(&crate::crypto::ed25519::SigningKey::from_bytes(&[1; 32]));
+        let message = Message::BlockMsg(Box::new(block));
+        let good = frame(&message);
+        assert_eq!(good[FRAME_HEADER_LEN..], message.encode_payload());
+
+        // The whole payload read at once and decoded, as before streaming
+        let buffered = |bytes: &[u8]| {
+            let header: [u8; FRAME_HEADER_LEN] = bytes[..FRAME_HEADER_LEN].try_into().unwrap();
+            let (command, len) = parse_header(&header, MAGIC, &limits)?;
+            match bytes[FRAME_HEADER_LEN..].get(..len) {
+                Some(payload) => decode_payload(&header, command, payload, &limits),
+                None => Err(DecodeError::UnexpectedEof.into()),
+            }
+        };
+        let streamed = |bytes: &[u8], chunk: usize| {
+            read_frame(&mut ChunkedReader::new(bytes, chunk), MAGIC, &limits)
+        };
+        for chunk in [1, 3, 64] {
+            assert_eq!(streamed(&good, chunk), Ok(message.clone()));
+        }
+
+        // With its checksum fixed, the payload's contents decide the error
+        let reframe = |payload: &[u8]| {
+            let mut frame = good[..FRAME_HEADER_LEN].to_vec();
+            frame[16..20].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+            frame[20..24].copy_from_slice(&checksum(payload));
+            frame.extend_from_slice(payload);
+            frame
+        };
+        let payload = &good[FRAME_HEADER_LEN..];
+        let mut flipped = good.clone();
+        flipped[FRAME_HEADER_LEN + 90] ^= 0xff;
+        let mut no_transactions = payload.to_vec();
+        no_transactions[BlockHeader::ENCODED_LEN + 1 + 64] = 0;
+        let cases = [
+            flipped,
+            good[..good.len() - 1].to_vec(),
+            good[..FRAME_HEADER_LEN + 10].to_vec(),
+            reframe(&[payload, &[0]].concat()),
+            reframe(&payload[..payload.len() - 1]),
+            reframe(&no_transactions),
+        ];
+        for (i, frame) in cases.iter().enumerate() {
+            let expected = buffered(frame);
+            assert!(expected.is_err(), "case {}", i);
+            for chunk in [1, 5] {
+                assert_eq!(streamed(frame, chunk), expected, "case {}", i);
+            }
+        }
+        assert_eq!(
+            streamed(&cases[3], 4),
+            Err(NetError::Decode(DecodeError::TrailingBytes(1)))
+        );
+    }
+
+    #[test]
+    fn test_enforces_payload_limits() {
+        let limits = DecodeLimits::default();
+
+        // A ping claiming a longer payload is refused before it is read
+        let mut long = frame(&Message::Ping(7));
+        long[16..20].copy_from_slice(&9u32.to_le_bytes());
+        assert_eq!(
+            read_frame(&mut long.as_slice(), MAGIC, &limits),
+            Err(NetError::PayloadTooLarge {
+                command: "ping".to_string(),
+                len: 9,
+                max: 8
+            })
+        );
+
+        // A block over the decode limit
+        let block = frame(&Message::BlockMsg(Box::new(block())));
+        let small = DecodeLimits {
+            max_decode_bytes: block.len() - FRAME_HEADER_LEN - 1,
+            ..limits
+        };
+        assert!(matches!(
+            read_frame(&mut block.as_slice(), MAGIC, &small),
+            Err(NetError::PayloadTooLarge { .. })
+        ));
+
+        // Too many inventory items, even within the payload limit
+        let mut payload = Vec::new();
+        codec::write_varint(&mut payload, MAX_INV_ITEMS as u64 + 1);
+        assert_eq!(
+            Message::decode("inv", &payload, &limits),
+            Err(NetError::Decode(DecodeError::LimitExceeded {
+                what: "inventory size",
+                value: MAX_INV_ITEMS as u64 + 1,
+                max: MAX_INV_ITEMS as u64
+            }))
+        );
+
+        // Trailing bytes after a message
+        let mut payload = Message::Pong(1).encode_payload();
+        payload.push(0);
+        assert_eq!(
+            Message::decode("pong", &payload, &limits),
+            Err(NetError::Decode(DecodeError::TrailingBytes(1)))
+        );
+    }
+
+    /// Any message, with addresses as they read back: IPv4 ones as IPv4 and
+    /// no IPv6 flow or scope
+    fn message() -> impl Strategy<Value = Message> {
+        let hash = || testing::hash32().prop_map(|hash| BlockHash::from_bytes(hash.0));
+        let inv = vec(
+            prop_oneof![
+                hash().prop_map(InvItem::Block),
+                any::<[u8; 32]>().prop_map(|txid| InvItem::Tx(Txid::from_bytes(txid))),
+            ],
+            0..8,
+        );
+        let addr = prop_oneof![
+            any::<(Ipv4Addr, u16)>().prop_map(|(ip, port)| SocketAddr::new(ip.into(), port)),
+            any::<([u8; 16], u16)>().prop_map(|(ip, port)| {
+                SocketAddr::new(Ipv6Addr::from(ip).to_canonical(), port)
+            }),
+        ];
+        let version = (any::<(u32, u64, u64, u64)>(), hash(), "[ -~]{0,40}").prop_map(
+            |((protocol_version, services, best_height, nonce), genesis_hash, user_agent)| {
+                Message::Version(Version {
+                    protocol_version,
+                    services,
+                    best_height,
+                    genesis_hash,
+                    nonce,
+                    user_agent,
+                })
+            },
+        );
+        prop_oneof![
+            version,
+            Just(Message::VerAck),
+            any::<u64>().prop_map(Message::Ping),
+            any::<u64>().prop_map(Message::Pong),
+            inv.clone().prop_map(Message::Inv),
+            inv.prop_map(Message::GetData),
+            testing::block().prop_map(|block| Message::BlockMsg(Box::new(block))),
+            testing::transaction().prop_map(Message::Tx),
+            vec(testing::block_header(), 0..4).prop_map(Message::Headers),
+            (vec(hash(), 0..8), proptest::option::of(hash()))
+                .prop_map(|(locator, stop)| Message::GetHeaders { locator, stop }),
+            testing::block()
+                .prop_map(|block| Message::CmpctBlock(Box::new(CompactBlock::new(&block)))),
+            (hash(), vec(0..1000usize, 0..8))
+                .prop_map(|(block, indexes)| Message::GetBlockTxn { block, indexes }),
+            (hash(), vec(vec(any::<u8>(), 0..40), 0..4)).prop_map(|(block, transactions)| {
+                Message::BlockTxn {
+                    block,
+                    transactions,
+                }
+            }),
+            vec((addr, any::<u64>()), 0..8).prop_map(Message::Addr),
+            Just(Message::GetAddr),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_messages_round_trip(message in message(), chunk in 1..64usize) {
+            let limits = DecodeLimits::default();
+            prop_assert!(message.command().len() <= 12);
+            let payload = message.encode_payload();
+            prop_assert_eq!(&Message::decode(message.command(), &payload, &limits).unwrap(), &message);
+
+            let bytes = frame(&message);
+            prop_assert_eq!(bytes.len(), FRAME_HEADER_LEN + payload.len());
+            let mut reader = ChunkedReader::new(&bytes, chunk);
+            prop_assert_eq!(read_frame(&mut reader, MAGIC, &limits), Ok(message));
+            prop_assert_eq!(read_frame(&mut reader, MAGIC, &limits), Err(NetError::Closed));
+        }
+    }
+}
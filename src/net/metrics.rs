@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Hooks through which a node reports the traffic of its peers, for export
+/// to whatever metrics system it uses, the counterpart of
+/// [`ChainMetrics`](crate::chain::ChainMetrics) for the network.
+///
+/// Every hook does nothing by default. Hooks are called on the tasks
+/// handling each peer, so they should return quickly.
+pub trait NetMetrics: Send + Sync + fmt::Debug {
+    /// The node took in a `command` message, received in a frame of
+    /// `bytes`, from the peer at `peer`
+    fn message_processed(&self, peer: SocketAddr, command: &'static str, bytes: usize) {
+        let _ = (peer, command, bytes);
+    }
+
+    /// A `command` message from `peer` was over the peer's rate limit, so
+    /// the node stops reading from it until the budget refills
+    fn message_throttled(&self, peer: SocketAddr, command: &'static str) {
+        let _ = (peer, command);
+    }
+
+    /// The queue of messages from `peer` waiting for the node is full, so
+    /// the node stops reading from it until the queue drains
+    fn inbound_queue_full(&self, peer: SocketAddr) {
+        let _ = peer;
+    }
+}
+
+/// What a [`CountingNetMetrics`] has seen of one peer so far
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PeerMessageCounts {
+    pub processed: u64,
+    /// Bytes of the frames of the processed messages
+    pub bytes: u64,
+    pub throttled: u64,
+    pub queue_full: u64,
+}
+
+/// Network metrics kept in memory by counting every hook call for each
+/// peer.
+///
+/// Clones share their counts, so one clone can be installed on a node and
+/// another kept to read them.
+#[derive(Clone, Debug, Default)]
+pub struct CountingNetMetrics {
+    counts: Arc<Mutex<HashMap<SocketAddr, PeerMessageCounts>>>,
+}
+
+impl CountingNetMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The counts so far for the peer at `addr`, all zero if it has not
+    /// been seen
+    pub fn peer(&self, addr: &SocketAddr) -> PeerMessageCounts {
+        self.lock().get(addr).cloned().unwrap_or_default()
+    }
+
+    /// The counts so far for every peer seen
+    pub fn counts(&self) -> HashMap<SocketAddr, PeerMessageCounts> {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SocketAddr, PeerMessageCounts>> {
+        // Counting never panics part way, so a poisoned lock holds sound counts
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl NetMetrics for CountingNetMetrics {
+    fn message_processed(&self, peer: SocketAddr, _: &'static str, bytes: usize) {
+        let mut counts = self.lock();
+        let counts = counts.entry(peer).or_default();
+        counts.processed += 1;
+        counts.bytes += bytes as u64;
+    }
+
+    fn message_throttled(&self, peer: SocketAddr, _: &'static str) {
+        self.lock().entry(peer).or_default().throttled += 1;
+    }
+
+    fn inbound_queue_full(&self, peer: SocketAddr) {
+        self.lock().entry(peer).or_default().queue_full += 1;
+    }
+}
@@ -0,0 +1,358 @@
+//! Talking to other nodes over TCP.
+//!
+//! Every [`Message`] travels in a frame: four magic bytes naming the network,
+//! as [`ChainParams::magic`](crate::params::ChainParams::magic) derives them
+//! from its genesis block, a twelve byte command, the payload length, a checksum of the payload, then
+//! the payload itself. A [`Peer`] sends and receives frames over one
+//! connection, blocking until each is through, once both sides have
+//! introduced themselves with a [`Peer::handshake`]. A [`Synchronizer`]
+//! catches a chain up with a peer's, and a [`Relay`] passes new blocks on
+//! between peers. An [`AddrBook`] remembers the addresses of peers to
+//! connect to.
+//!
+//! With the `tokio` feature, an [`AsyncPeer`] does what a [`Peer`] does
+//! without blocking, and a [`Node`] drives connections to many peers at
+//! once.
+
+use std::io;
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use thiserror::Error;
+
+use crate::block::BlockDecodeError;
+use crate::codec::{DecodeError, DecodeLimits};
+use crate::trace;
+
+mod addr_book;
+#[cfg(feature = "tokio")]
+mod async_peer;
+mod ban;
+mod compact;
+mod handshake;
+mod message;
+mod metrics;
+#[cfg(feature = "tokio")]
+mod node;
+mod rate;
+mod relay;
+mod sync;
+
+pub use addr_book::{
+    AddrBook, AddrEntry, ConnectionManager, BASE_RETRY_DELAY, DEFAULT_MAX_ADDRESSES,
+    DEFAULT_TARGET_OUTBOUND, MAX_ADDR_PER_MESSAGE, MAX_RETRY_DELAY,
+};
+#[cfg(feature = "tokio")]
+pub use async_peer::{AsyncPeer, FrameCodec};
+pub use ban::{BanList, MisbehaviorScore, Offense, BAN_THRESHOLD, DEFAULT_BAN_DURATION};
+pub use compact::{CompactBlock, CompactError, PartialBlock, ShortTxId, SHORT_ID_LEN};
+pub use handshake::{
+    HandshakeError, PeerInfo, HANDSHAKE_TIMEOUT, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION,
+};
+pub use message::{
+    read_frame, write_frame, InvItem, Message, Version, FRAME_HEADER_LEN, MAX_HEADERS,
+    MAX_INV_ITEMS, MAX_LOCATOR_HASHES, MAX_USER_AGENT_LEN, SERVICE_COMPACT_BLOCKS,
+    SERVICE_FULL_BLOCKS,
+};
+pub use metrics::{CountingNetMetrics, NetMetrics, PeerMessageCounts};
+#[cfg(feature = "tokio")]
+pub(crate) use node::load_addr_book;
+#[cfg(feature = "tokio")]
+pub use node::{
+    Node, NodeConfig, NodeError, PeerStatus, DEFAULT_CONNECT_INTERVAL, INBOUND_QUEUE_LEN,
+    MAX_HANDSHAKE_FAILURES, PEER_QUEUE_LEN, USER_AGENT,
+};
+pub use rate::{RateLimit, RateLimiter, RateLimits, DEFAULT_BLOCK_RATE, DEFAULT_GOSSIP_RATE};
+pub use relay::{
+    RecentItems, Relay, DEFAULT_MAX_RECENT_ITEMS, MAX_KNOWN_PER_PEER, MAX_PARTIAL_BLOCKS,
+};
+pub use sync::{serve, SyncError, SyncProgress, Synchronizer, BLOCKS_PER_REQUEST, SYNC_TIMEOUT};
+
+/// Reasons a message could not be sent or received
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum NetError {
+    /// The connection failed
+    #[error("connection error: {0}")]
+    Io(String),
+    /// The peer sent nothing for longer than allowed
+    #[error("timed out waiting for peer")]
+    TimedOut,
+    /// The peer closed the connection between frames
+    #[error("connection closed by peer")]
+    Closed,
+    /// Messages other than the handshake's wait until it is done
+    #[error("handshake not done")]
+    HandshakeRequired,
+    /// The frame is for another network
+    #[error("frame magic {got:08x} is not {expected:08x}")]
+    BadMagic { expected: u32, got: u32 },
+    /// The frame names a command this build does not know
+    #[error("unknown command {0:?}")]
+    UnknownCommand(String),
+    /// The frame's payload is larger than its command allows
+    #[error("{command} payload of {len} bytes exceeds limit of {max}")]
+    PayloadTooLarge {
+        command: String,
+        len: usize,
+        max: usize,
+    },
+    /// The payload does not match the frame's checksum
+    #[error("payload checksum mismatch")]
+    BadChecksum,
+    /// The payload is not a valid message of the frame's command
+    #[error("malformed message: {0}")]
+    Decode(DecodeError),
+}
+
+impl NetError {
+    /// The offense of a peer that sent a frame failing this way, if the
+    /// failure is the peer's doing
+    pub fn offense(&self) -> Option<Offense> {
+        match self {
+            NetError::BadMagic { .. }
+            | NetError::UnknownCommand(_)
+            | NetError::PayloadTooLarge { .. }
+            | NetError::BadChecksum
+            | NetError::Decode(_) => Some(Offense::Malformed),
+            NetError::Io(_)
+            | NetError::TimedOut
+            | NetError::Closed
+            | NetError::HandshakeRequired => None,
+        }
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            // What a read past its timeout fails with, depending on the platform
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => NetError::TimedOut,
+            _ => NetError::Io(err.to_string()),
+        }
+    }
+}
+
+impl From<DecodeError> for NetError {
+    fn from(err: DecodeError) -> Self {
+        NetError::Decode(err)
+    }
+}
+
+impl From<BlockDecodeError> for NetError {
+    fn from(err: BlockDecodeError) -> Self {
+        match err {
+            BlockDecodeError::Io(err) => NetError::Io(err),
+            BlockDecodeError::Decode(err) => NetError::Decode(err),
+        }
+    }
+}
+
+/// A connection to another node, exchanging framed messages
+#[derive(Debug)]
+pub struct Peer {
+    stream: TcpStream,
+    magic: u32,
+    limits: DecodeLimits,
+    handshake_timeout: Duration,
+    /// Set once the handshake is done
+    info: Option<PeerInfo>,
+}
+
+impl Peer {
+    /// Wrap an open connection to a node on the network named by `magic`
+    pub fn new(stream: TcpStream, magic: u32) -> Self {
+        Peer {
+            stream,
+            magic,
+            limits: DecodeLimits::default(),
+            handshake_timeout: HANDSHAKE_TIMEOUT,
+            info: None,
+        }
+    }
+
+    /// Connect to the node at `addr`
+    pub fn connect(addr: impl ToSocketAddrs, magic: u32) -> Result<Self, NetError> {
+        Ok(Peer::new(TcpStream::connect(addr)?, magic))
+    }
+
+    /// Decode received blocks under `limits` rather than the defaults
+    pub fn with_limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Another handle on the same connection, for receiving on one thread
+    /// while sending on another
+    pub fn try_clone(&self) -> Result<Peer, NetError> {
+        Ok(Peer {
+            stream: self.stream.try_clone()?,
+            magic: self.magic,
+            limits: self.limits,
+            handshake_timeout: self.handshake_timeout,
+            info: self.info.clone(),
+        })
+    }
+
+    /// The address of the other end
+    pub fn peer_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.stream.peer_addr()?)
+    }
+
+    pub fn send(&mut self, message: &Message) -> Result<(), NetError> {
+        if self.info.is_none() {
+            return Err(NetError::HandshakeRequired);
+        }
+        trace::event!(
+            trace,
+            peer = self.stream.peer_addr().ok().map(tracing::field::display),
+            command = message.command(),
+            "message sent"
+        );
+        write_frame(&mut self.stream, self.magic, message)
+    }
+
+    /// Wait for the next message. After an error other than
+    /// [`NetError::Io`] or [`NetError::HandshakeRequired`], the stream may be
+    /// part way through a frame, so the connection should be dropped.
+    pub fn recv(&mut self) -> Result<Message, NetError> {
+        if self.info.is_none() {
+            return Err(NetError::HandshakeRequired);
+        }
+        let message = read_frame(&mut self.stream, self.magic, &self.limits)?;
+        self.trace_received(&message);
+        Ok(message)
+    }
+
+    /// Like [`Peer::recv`], but failing with [`NetError::TimedOut`] once the
+    /// peer has been silent for `timeout`, which must not be zero
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Result<Message, NetError> {
+        if self.info.is_none() {
+            return Err(NetError::HandshakeRequired);
+        }
+        self.read_within(timeout)
+    }
+
+    fn read_within(&mut self, timeout: Duration) -> Result<Message, NetError> {
+        self.stream.set_read_timeout(Some(timeout))?;
+        let message = read_frame(&mut self.stream, self.magic, &self.limits);
+        self.stream.set_read_timeout(None)?;
+        let message = message?;
+        self.trace_received(&message);
+        Ok(message)
+    }
+
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    fn trace_received(&self, message: &Message) {
+        trace::event!(
+            trace,
+            peer = self.stream.peer_addr().ok().map(tracing::field::display),
+            command = message.command(),
+            "message received"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::params::ChainParams;
+    use std::net::TcpListener;
+    use std::thread;
+
+    pub(super) const MAGIC: u32 = 0xa12b_c34d;
+
+    /// This node's version on the test network, under `nonce`
+    pub(super) fn version(nonce: u64) -> Version {
+        Version {
+            protocol_version: PROTOCOL_VERSION,
+            services: SERVICE_FULL_BLOCKS,
+            best_height: 7,
+            genesis_hash: ChainParams::test_defaults().genesis_hash(),
+            nonce,
+            user_agent: "/aarwyn:0.1.0/".to_string(),
+        }
+    }
+
+    /// A child of `parent` told apart by `tag`, mined at the test difficulty
+    pub(super) fn mined(parent: &Block, tag: &[u8]) -> Block {
+        let difficulty = ChainParams::test_defaults().initial_difficulty;
+        let mut block = parent
+            .next_builder()
+            .transaction(tag.to_vec())
+            .difficulty(difficulty)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(difficulty);
+        block
+    }
+
+    /// Both ends of a localhost connection, not yet through a handshake
+    pub(super) fn connected_pair() -> (Peer, Peer) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dialer = Peer::connect(listener.local_addr().unwrap(), MAGIC).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        (dialer, Peer::new(stream, MAGIC))
+    }
+
+    #[test]
+    fn test_peers_exchange_block() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let block = ChainParams::test_defaults().genesis_block();
+        let hash = block.hash();
+
+        // The listening side serves the one block it has
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut peer = Peer::new(stream, MAGIC);
+            peer.handshake(&version(2)).unwrap();
+            assert_eq!(peer.recv(), Ok(Message::Ping(1)));
+            peer.send(&Message::Pong(1)).unwrap();
+            assert_eq!(
+                peer.recv(),
+                Ok(Message::GetData(vec![InvItem::Block(block.hash())]))
+            );
+            peer.send(&Message::BlockMsg(Box::new(block))).unwrap();
+            assert_eq!(peer.recv(), Err(NetError::Closed));
+        });
+
+        let mut peer = Peer::connect(addr, MAGIC).unwrap();
+        assert_eq!(peer.peer_addr(), Ok(addr));
+        peer.handshake(&version(1)).unwrap();
+        peer.send(&Message::Ping(1)).unwrap();
+        assert_eq!(peer.recv(), Ok(Message::Pong(1)));
+        peer.send(&Message::GetData(vec![InvItem::Block(hash)]))
+            .unwrap();
+        match peer.recv() {
+            Ok(Message::BlockMsg(block)) => {
+                assert_eq!(block.hash(), hash);
+                assert!(block.verify_merkle_root());
+            }
+            other => panic!("expected a block, got {:?}", other),
+        }
+        drop(peer);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_peer_rejects_other_network() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            Peer::new(stream, MAGIC).handshake(&version(2))
+        });
+
+        let mut peer = Peer::connect(addr, MAGIC ^ 1).unwrap();
+        assert!(peer.handshake(&version(1)).is_err());
+        assert_eq!(
+            server.join().unwrap(),
+            Err(HandshakeError::Net(NetError::BadMagic {
+                expected: MAGIC,
+                got: MAGIC ^ 1
+            }))
+        );
+    }
+}
@@ -0,0 +1,1399 @@
+//! A node on tokio, talking to many peers at once.
+//!
+//! [`Node::start`] listens for peers and spawns the task that owns the
+//! chain, the mempool and the set of peers. Each connection runs in a task
+//! of its own: it passes what the peer sends to the node's task through one
+//! channel shared by all connections, and sends what the node's task queues
+//! for it on a channel of its own. The node's task waits on the listener,
+//! the connections and the [`Node`] handle at once, relays blocks and
+//! transactions with a [`Relay`], and catches up with any peer whose chain
+//! is longer, headers first, until [`Node::shutdown`].
+//!
+//! Frames carry the magic of the chain's parameters, and handshakes its
+//! genesis hash, so a node never talks to one on another network: a frame
+//! with another magic fails before its payload is read, and a version with
+//! another genesis ends the handshake. Each failed handshake is kept, with
+//! the reason, in a log [`Node::handshake_failures`] reads back.
+//!
+//! What a peer sends is held to the budgets of a
+//! [`RateLimiter`](super::RateLimiter), and to a queue of its own for
+//! the node's task to take in. A peer over its budget, or whose queue is
+//! full, is not read from until there is room again, so a peer sending
+//! faster than the node keeps up is slowed to the node's pace without
+//! holding up the others.
+//!
+//! Every [`Offense`](super::Offense) a peer commits adds to its misbehavior score. A peer
+//! whose score reaches the configured threshold is disconnected and its IP
+//! address banned: the node neither accepts connections from it nor
+//! connects to it until the ban expires.
+//!
+//! The node keeps an [`AddrBook`] of the peers it has connected to and
+//! those they tell it of, asking each peer it connects to for the
+//! addresses it knows. Every so often it dials addresses from the book
+//! until it has the configured number of outbound connections, as a
+//! [`ConnectionManager`] picks them. The book can be kept in a file, loaded
+//! when the node starts and saved when it shuts down.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::net::{TcpListener, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinHandle, JoinSet};
+use tokio::time::{interval, sleep};
+use tokio_util::sync::CancellationToken;
+
+use super::relay::update_mempool;
+use super::sync::known_header;
+use super::{
+    AddrBook, AddrEntry, AsyncPeer, BanList, ConnectionManager, HandshakeError, InvItem, Message,
+    NetError, NetMetrics, PeerInfo, RateLimiter, RateLimits, Relay, SyncError, Version,
+    BAN_THRESHOLD, DEFAULT_BAN_DURATION, DEFAULT_MAX_ADDRESSES, DEFAULT_TARGET_OUTBOUND,
+    MAX_HEADERS, MAX_LOCATOR_HASHES, PROTOCOL_VERSION, SERVICE_COMPACT_BLOCKS, SERVICE_FULL_BLOCKS,
+};
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::chain::{check_header, BlockStatus, Blockchain, ChainError};
+use crate::mempool::Mempool;
+use crate::params::{Clock, SystemClock};
+use crate::store::{ChainStore, MemoryStore};
+use crate::trace;
+use crate::transaction::{Transaction, Txid};
+
+/// Messages queued for a peer before it is dropped as too slow to take them
+pub const PEER_QUEUE_LEN: usize = 1024;
+
+/// Messages from all peers queued for the node's task before connections
+/// wait to pass on more
+const EVENT_QUEUE_LEN: usize = 1024;
+
+/// How a [`Node`] introduces itself to peers
+pub const USER_AGENT: &str = concat!("/aarwyn:", env!("CARGO_PKG_VERSION"), "/");
+
+/// Failed handshakes a [`Node`] keeps in its log, dropping the oldest
+pub const MAX_HANDSHAKE_FAILURES: usize = 64;
+
+/// Messages from one peer waiting for the node's task, unless configured
+/// otherwise, before the peer is no longer read from
+pub const INBOUND_QUEUE_LEN: usize = 64;
+
+/// How often a connection over its rate limit checks whether its budget
+/// has refilled
+const THROTTLE_POLL: Duration = Duration::from_millis(50);
+
+/// How often a node tops up its outbound connections unless configured
+/// otherwise
+pub const DEFAULT_CONNECT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How a [`Node`] runs
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    /// Misbehavior score at which a peer is disconnected and banned
+    pub ban_threshold: u32,
+    /// How long a ban lasts
+    pub ban_duration: Duration,
+    /// Tells the time that bans expire and rate limits refill by
+    pub clock: Arc<dyn Clock>,
+    /// How much each peer may send
+    pub rate_limits: RateLimits,
+    /// Messages from one peer waiting for the node's task before the peer
+    /// is no longer read from
+    pub inbound_queue_len: usize,
+    /// Where to report the traffic of each peer, if anywhere
+    pub metrics: Option<Arc<dyn NetMetrics>>,
+    /// Outbound connections to keep, dialling addresses from the book
+    pub target_outbound: usize,
+    /// How often to dial more addresses while short of the target; must
+    /// not be zero
+    pub connect_interval: Duration,
+    /// The file to load the address book from on starting and save it to
+    /// on shutting down, if any
+    pub addr_book: Option<PathBuf>,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            ban_threshold: BAN_THRESHOLD,
+            ban_duration: DEFAULT_BAN_DURATION,
+            clock: Arc::new(SystemClock),
+            rate_limits: RateLimits::default(),
+            inbound_queue_len: INBOUND_QUEUE_LEN,
+            metrics: None,
+            target_outbound: DEFAULT_TARGET_OUTBOUND,
+            connect_interval: DEFAULT_CONNECT_INTERVAL,
+            addr_book: None,
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Report the traffic of each peer to `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<dyn NetMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+}
+
+/// A connected peer, as [`Node::peer_info`] lists it
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerStatus {
+    pub addr: SocketAddr,
+    /// What the peer said about itself in the handshake
+    pub info: PeerInfo,
+    /// The sum of the penalties for the peer's offenses so far
+    pub misbehavior: u32,
+}
+
+/// Reasons a [`Node`] could not do what it was asked
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum NodeError {
+    /// The node has shut down
+    #[error("node has shut down")]
+    Stopped,
+    /// Connecting to a peer failed, or the peer failed the handshake
+    #[error("could not connect: {0}")]
+    Handshake(HandshakeError),
+    /// The chain refused a block
+    #[error("block refused: {0}")]
+    Chain(ChainError),
+    /// The mempool refused a transaction, for the reason given
+    #[error("transaction refused: {0}")]
+    Rejected(String),
+    /// The address is banned until the given Unix time
+    #[error("{ip} is banned until {until}")]
+    Banned { ip: IpAddr, until: u64 },
+}
+
+type Reply<T> = oneshot::Sender<Result<T, NodeError>>;
+
+type Query<S> = Box<dyn FnOnce(&Blockchain<S>, &Mempool) + Send>;
+
+/// What the [`Node`] handle asks of the node's task
+enum Command<S: ChainStore> {
+    Connect(SocketAddr, Reply<PeerInfo>),
+    SubmitBlock(Box<Block>, Reply<()>),
+    SubmitTransaction(Transaction, Reply<Txid>),
+    PeerInfo(oneshot::Sender<Vec<PeerStatus>>),
+    HandshakeFailures(oneshot::Sender<Vec<(SocketAddr, HandshakeError)>>),
+    Banned(oneshot::Sender<Vec<(IpAddr, u64)>>),
+    AddrBook(oneshot::Sender<Vec<AddrEntry>>),
+    Query(Query<S>),
+}
+
+/// What connections tell the node's task
+enum Event {
+    /// The handshake is done, and the connection sends what is queued on
+    /// `commands`
+    Connected {
+        addr: SocketAddr,
+        info: PeerInfo,
+        commands: mpsc::Sender<Message>,
+        reply: Option<Reply<PeerInfo>>,
+    },
+    /// The handshake failed, and the connection is closed
+    HandshakeFailed(SocketAddr, HandshakeError),
+    /// Dialling the address failed before a handshake could begin
+    DialFailed(SocketAddr),
+    /// A message from the peer, received in a frame of `bytes` and
+    /// holding a place in its inbound queue until handled
+    Received {
+        from: SocketAddr,
+        message: Message,
+        bytes: usize,
+        _queued: OwnedSemaphorePermit,
+    },
+    /// The connection is closed, having failed to receive with the error if
+    /// any
+    Closed(SocketAddr, Option<NetError>),
+}
+
+/// A running node, and the handle for asking things of it.
+///
+/// Dropping the handle shuts the node down as [`Node::shutdown`] does,
+/// without waiting for it.
+pub struct Node<S: ChainStore = MemoryStore> {
+    commands: mpsc::Sender<Command<S>>,
+    local_addr: SocketAddr,
+    task: JoinHandle<(Blockchain<S>, Mempool)>,
+}
+
+impl<S: ChainStore + Send + 'static> Node<S> {
+    /// Listen on `addr` for peers, and start relaying for `chain` and
+    /// `mempool` as `config` says, on the network of the chain's parameters.
+    /// Fails if the address book file cannot be read.
+    pub async fn start(
+        addr: impl ToSocketAddrs,
+        chain: Blockchain<S>,
+        mempool: Mempool,
+        config: NodeConfig,
+    ) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let book = load_addr_book(&config)?;
+        Self::spawn(listener, book, chain, mempool, config)
+    }
+
+    /// [`Node::start`] on `listener`, bound already, with `book` as the
+    /// address book. Fails only if the listener's address cannot be read.
+    pub(crate) fn spawn(
+        listener: TcpListener,
+        book: AddrBook,
+        chain: Blockchain<S>,
+        mempool: Mempool,
+        config: NodeConfig,
+    ) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (commands, command_rx) = mpsc::channel(EVENT_QUEUE_LEN);
+        let (events, event_rx) = mpsc::channel(EVENT_QUEUE_LEN);
+        let magic = chain.params().magic();
+        let state = NodeState {
+            chain,
+            mempool,
+            relay: Relay::new(),
+            peers: HashMap::new(),
+            magic,
+            nonce: RandomState::new().build_hasher().finish(),
+            bans: BanList::new(config.ban_duration).with_clock(config.clock.clone()),
+            ban_threshold: config.ban_threshold,
+            clock: config.clock,
+            rate_limits: config.rate_limits,
+            inbound_queue_len: config.inbound_queue_len,
+            metrics: config.metrics,
+            handshake_failures: VecDeque::new(),
+            outbound: ConnectionManager::new(book, config.target_outbound),
+            connect_interval: config.connect_interval,
+            addr_book: config.addr_book,
+            local_addr,
+            events,
+            connections: JoinSet::new(),
+            shutdown: CancellationToken::new(),
+        };
+        let task = tokio::spawn(state.run(listener, command_rx, event_rx));
+        Ok(Node {
+            commands,
+            local_addr,
+            task,
+        })
+    }
+
+    /// The address the node listens on, with the port picked if `addr`
+    /// asked for port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Connect to the node at `addr` and relay to it once the handshake is
+    /// done, catching up first if its chain is longer. Fails with
+    /// [`NodeError::Banned`] while `addr`'s IP address is banned.
+    pub async fn connect(&self, addr: SocketAddr) -> Result<PeerInfo, NodeError> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::Connect(addr, reply)).await?;
+        answer.await.map_err(|_| NodeError::Stopped)?
+    }
+
+    /// Insert `block` into the chain, bring the mempool in line with any
+    /// change to the active chain, and announce the block to every peer
+    pub async fn submit_block(&self, block: Block) -> Result<(), NodeError> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::SubmitBlock(Box::new(block), reply))
+            .await?;
+        answer.await.map_err(|_| NodeError::Stopped)?
+    }
+
+    /// Add `tx` to the mempool, paying the fee its inputs leave in the
+    /// chain's unspent outputs and the pool's, and announce it to every
+    /// peer. Fails with [`NodeError::Rejected`] if the chain does not track
+    /// unspent outputs, an output it spends is missing, it pays out more
+    /// than it spends, or the pool refuses it.
+    pub async fn submit_transaction(&self, tx: Transaction) -> Result<Txid, NodeError> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::SubmitTransaction(tx, reply)).await?;
+        answer.await.map_err(|_| NodeError::Stopped)?
+    }
+
+    /// The connected peers, what each said about itself and its
+    /// misbehavior score, in order of address
+    pub async fn peer_info(&self) -> Result<Vec<PeerStatus>, NodeError> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::PeerInfo(reply)).await?;
+        answer.await.map_err(|_| NodeError::Stopped)
+    }
+
+    /// The last [`MAX_HANDSHAKE_FAILURES`] handshakes that failed, in
+    /// either direction, oldest first: the address of each peer and why,
+    /// such as being on another network
+    pub async fn handshake_failures(&self) -> Result<Vec<(SocketAddr, HandshakeError)>, NodeError> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::HandshakeFailures(reply)).await?;
+        answer.await.map_err(|_| NodeError::Stopped)
+    }
+
+    /// The banned IP addresses and when each ban ends, in Unix time
+    pub async fn banned(&self) -> Result<Vec<(IpAddr, u64)>, NodeError> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::Banned(reply)).await?;
+        answer.await.map_err(|_| NodeError::Stopped)
+    }
+
+    /// What the node's address book holds, in order of address
+    pub async fn addr_book(&self) -> Result<Vec<AddrEntry>, NodeError> {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::AddrBook(reply)).await?;
+        answer.await.map_err(|_| NodeError::Stopped)
+    }
+
+    /// Run `f` on the chain and the mempool between the node's other work,
+    /// and return what it does
+    pub async fn query<R, F>(&self, f: F) -> Result<R, NodeError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Blockchain<S>, &Mempool) -> R + Send + 'static,
+    {
+        let (reply, answer) = oneshot::channel();
+        self.send(Command::Query(Box::new(move |chain, mempool| {
+            // The caller may have given up waiting
+            let _ = reply.send(f(chain, mempool));
+        })))
+        .await?;
+        answer.await.map_err(|_| NodeError::Stopped)
+    }
+
+    /// Stop listening, close every connection and wait for all of the
+    /// node's tasks to finish, then save the address book if configured
+    /// to and hand back the chain and the mempool
+    pub async fn shutdown(self) -> (Blockchain<S>, Mempool) {
+        drop(self.commands);
+        match self.task.await {
+            Ok(state) => state,
+            Err(err) => std::panic::resume_unwind(err.into_panic()),
+        }
+    }
+
+    async fn send(&self, command: Command<S>) -> Result<(), NodeError> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| NodeError::Stopped)
+    }
+}
+
+/// The address book `config` names, loaded from its file if any and timed
+/// by its clock
+pub(crate) fn load_addr_book(config: &NodeConfig) -> io::Result<AddrBook> {
+    let book = match &config.addr_book {
+        Some(path) => AddrBook::load(path, DEFAULT_MAX_ADDRESSES).map_err(io::Error::other)?,
+        None => AddrBook::new(DEFAULT_MAX_ADDRESSES),
+    };
+    Ok(book.with_clock(config.clock.clone()))
+}
+
+/// A peer through its handshake
+struct Connection {
+    info: PeerInfo,
+    commands: mpsc::Sender<Message>,
+}
+
+/// What the node's task owns
+struct NodeState<S: ChainStore> {
+    chain: Blockchain<S>,
+    mempool: Mempool,
+    relay: Relay,
+    peers: HashMap<SocketAddr, Connection>,
+    /// The magic of the chain's parameters
+    magic: u32,
+    /// Sent in every handshake, to spot connections to this node itself
+    nonce: u64,
+    bans: BanList,
+    ban_threshold: u32,
+    /// The log of failed handshakes, oldest first
+    handshake_failures: VecDeque<(SocketAddr, HandshakeError)>,
+    clock: Arc<dyn Clock>,
+    rate_limits: RateLimits,
+    inbound_queue_len: usize,
+    metrics: Option<Arc<dyn NetMetrics>>,
+    /// The address book, and the outbound connections made from it
+    outbound: ConnectionManager,
+    connect_interval: Duration,
+    /// Where to save the address book on shutting down
+    addr_book: Option<PathBuf>,
+    /// The address the node listens on, never to be dialled
+    local_addr: SocketAddr,
+    /// Handed to each connection, to tell the node's task what happens
+    events: mpsc::Sender<Event>,
+    connections: JoinSet<()>,
+    shutdown: CancellationToken,
+}
+
+impl<S: ChainStore> NodeState<S> {
+    async fn run(
+        mut self,
+        listener: TcpListener,
+        mut commands: mpsc::Receiver<Command<S>>,
+        mut events: mpsc::Receiver<Event>,
+    ) -> (Blockchain<S>, Mempool) {
+        let mut top_up = interval(self.connect_interval);
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    // A failed accept concerns only the connection it was for,
+                    // and one from a banned address is closed at once
+                    if let Some((stream, addr)) = accepted
+                        .ok()
+                        .filter(|(_, addr)| !self.bans.is_banned(&addr.ip()))
+                    {
+                        let peer = AsyncPeer::new(stream, self.magic);
+                        let task = connection(peer, addr, self.version(), self.events.clone(), self.inbound(), None);
+                        self.spawn(task);
+                    }
+                }
+                command = commands.recv() => match command {
+                    Some(command) => self.on_command(command),
+                    // The handle is gone
+                    None => break,
+                },
+                // The node's task holds a sender, so this never runs dry
+                Some(event) = events.recv() => self.on_event(event),
+                Some(_) = self.connections.join_next() => {}
+                _ = top_up.tick() => self.top_up(),
+            }
+        }
+
+        drop(listener);
+        self.shutdown.cancel();
+        events.close();
+        while self.connections.join_next().await.is_some() {}
+        if let Some(path) = &self.addr_book {
+            // There is no one left to tell, and peers rebuild a lost book
+            let _ = self.outbound.book().save(path);
+        }
+        (self.chain, self.mempool)
+    }
+
+    fn on_command(&mut self, command: Command<S>) {
+        match command {
+            Command::Connect(addr, reply) => {
+                if let Some(until) = self.bans.banned_until(&addr.ip()) {
+                    let ip = addr.ip();
+                    let _ = reply.send(Err(NodeError::Banned { ip, until }));
+                    return;
+                }
+                self.dial(addr, Some(reply));
+            }
+            Command::SubmitBlock(block, reply) => {
+                let result = match self.chain.insert((*block).clone()) {
+                    Ok(reorg) => {
+                        if let Some(reorg) = reorg {
+                            update_mempool(&self.chain, &mut self.mempool, &reorg);
+                        }
+                        for (to, message) in self.relay.announce_block(&block, None) {
+                            self.send(to, message);
+                        }
+                        Ok(())
+                    }
+                    Err(err) => Err(NodeError::Chain(err)),
+                };
+                let _ = reply.send(result);
+            }
+            Command::SubmitTransaction(tx, reply) => {
+                let result = self.accept_transaction(tx);
+                if let Ok(txid) = &result {
+                    for (to, message) in self.relay.announce(InvItem::Tx(*txid), None) {
+                        self.send(to, message);
+                    }
+                }
+                let _ = reply.send(result);
+            }
+            Command::PeerInfo(reply) => {
+                let mut peers: Vec<_> = self
+                    .peers
+                    .iter()
+                    .map(|(addr, peer)| PeerStatus {
+                        addr: *addr,
+                        info: peer.info.clone(),
+                        misbehavior: self.relay.misbehavior(addr).unwrap_or(0),
+                    })
+                    .collect();
+                peers.sort_by_key(|peer| peer.addr);
+                let _ = reply.send(peers);
+            }
+            Command::HandshakeFailures(reply) => {
+                let _ = reply.send(self.handshake_failures.iter().cloned().collect());
+            }
+            Command::Banned(reply) => {
+                let _ = reply.send(self.bans.banned());
+            }
+            Command::AddrBook(reply) => {
+                let mut entries: Vec<_> = self.outbound.book().iter().cloned().collect();
+                entries.sort_by_key(|entry| entry.addr);
+                let _ = reply.send(entries);
+            }
+            Command::Query(f) => f(&self.chain, &self.mempool),
+        }
+    }
+
+    fn on_event(&mut self, event: Event) {
+        match event {
+            Event::Connected {
+                addr,
+                info,
+                commands,
+                reply,
+            } => {
+                // Banned while its handshake was under way
+                if let Some(until) = self.bans.banned_until(&addr.ip()) {
+                    if self.outbound.is_pending(&addr) {
+                        self.outbound.failed(&addr);
+                    }
+                    if let Some(reply) = reply {
+                        let ip = addr.ip();
+                        let _ = reply.send(Err(NodeError::Banned { ip, until }));
+                    }
+                    return;
+                }
+                // Dialled by the node, so listening at `addr` for others
+                let dialled = reply.is_some() || self.outbound.is_pending(&addr);
+                if dialled {
+                    self.outbound.connected(addr);
+                }
+                let behind = info.best_height > self.chain.height();
+                self.relay.add_peer(addr);
+                if info.services & SERVICE_COMPACT_BLOCKS != 0 {
+                    self.relay.enable_compact_blocks(&addr);
+                }
+                self.peers.insert(
+                    addr,
+                    Connection {
+                        info: info.clone(),
+                        commands,
+                    },
+                );
+                if let Some(reply) = reply {
+                    let _ = reply.send(Ok(info));
+                }
+                if dialled {
+                    self.send(addr, Message::GetAddr);
+                }
+                if behind {
+                    let request = self.get_headers(None);
+                    self.send(addr, request);
+                }
+            }
+            Event::DialFailed(addr) => self.outbound.failed(&addr),
+            Event::HandshakeFailed(addr, err) => {
+                if self.outbound.is_pending(&addr) {
+                    self.outbound.failed(&addr);
+                }
+                if self.handshake_failures.len() == MAX_HANDSHAKE_FAILURES {
+                    self.handshake_failures.pop_front();
+                }
+                self.handshake_failures.push_back((addr, err));
+            }
+            Event::Received {
+                from,
+                message,
+                bytes,
+                ..
+            } => {
+                // Messages may still arrive from a peer just dropped
+                if !self.peers.contains_key(&from) {
+                    return;
+                }
+                let _span = trace::span!(
+                    "message",
+                    peer = %from,
+                    command = message.command(),
+                    size = bytes
+                );
+                trace::event!(trace, "message received");
+                if let Some(metrics) = &self.metrics {
+                    metrics.message_processed(from, message.command(), bytes);
+                }
+                match self.on_message(from, message) {
+                    Ok(replies) => {
+                        for (to, reply) in replies {
+                            self.send(to, reply);
+                        }
+                    }
+                    Err(err) => {
+                        trace::event!(debug, error = %err, "message refused");
+                        if let Some(offense) = err.offense() {
+                            self.relay.penalize(&from, offense);
+                        }
+                    }
+                }
+                self.enforce_ban(&from);
+            }
+            Event::Closed(addr, err) => {
+                if let Some(offense) = err.as_ref().and_then(NetError::offense) {
+                    self.relay.penalize(&addr, offense);
+                    self.enforce_ban(&addr);
+                }
+                self.disconnect(&addr);
+            }
+        }
+    }
+
+    fn on_message(
+        &mut self,
+        from: SocketAddr,
+        message: Message,
+    ) -> Result<Vec<(SocketAddr, Message)>, SyncError> {
+        match message {
+            Message::Headers(headers) => self.on_headers(from, headers),
+            Message::GetAddr => Ok(vec![(from, Message::Addr(self.outbound.book().sample()))]),
+            Message::Addr(addrs) => {
+                for (addr, last_seen) in addrs {
+                    self.outbound.book_mut().add(addr, last_seen);
+                }
+                Ok(Vec::new())
+            }
+            message => self
+                .relay
+                .handle(from, message, &mut self.chain, &mut self.mempool),
+        }
+    }
+
+    /// Check headers sent in answer to a `GetHeaders` against their parents
+    /// and ask for the blocks behind those the chain lacks, then for the
+    /// headers after a full batch
+    fn on_headers(
+        &mut self,
+        from: SocketAddr,
+        headers: Vec<BlockHeader>,
+    ) -> Result<Vec<(SocketAddr, Message)>, SyncError> {
+        let Some(first) = headers.first() else {
+            return Ok(Vec::new());
+        };
+        let prev = first.prev_block_hash();
+        let mut parent = (
+            prev,
+            known_header(&self.chain, &prev).ok_or(SyncError::UnconnectedHeaders(prev))?,
+        );
+        let mut wanted = Vec::new();
+        for header in &headers {
+            let hash = check_header(self.chain.params(), &parent.1, parent.0, header)
+                .map_err(SyncError::InvalidHeader)?;
+            match self.chain.status_of(hash.as_ref()) {
+                None => wanted.push(InvItem::Block(hash)),
+                Some(BlockStatus::Invalid) => {
+                    return Err(SyncError::InvalidHeader(ChainError::MarkedInvalid(hash)))
+                }
+                Some(_) => {}
+            }
+            parent = (hash, header.clone());
+        }
+
+        // The peer answers in order, so the blocks are in by the time the
+        // next batch of headers, building on them, arrives
+        let mut replies = self.relay.handle(
+            from,
+            Message::Inv(wanted),
+            &mut self.chain,
+            &mut self.mempool,
+        )?;
+        if headers.len() == MAX_HEADERS {
+            replies.push((from, self.get_headers(Some(parent.0))));
+        }
+        Ok(replies)
+    }
+
+    /// Add `tx` to the mempool, paying what its inputs leave, if its inputs
+    /// are signed for the outputs they spend
+    fn accept_transaction(&mut self, tx: Transaction) -> Result<Txid, NodeError> {
+        let utxos = self.chain.utxo_set().ok_or_else(|| {
+            NodeError::Rejected("the chain does not track unspent outputs".to_string())
+        })?;
+        let fee = self
+            .mempool
+            .fee_of(&tx, utxos)
+            .map_err(|err| NodeError::Rejected(err.to_string()))?;
+        self.mempool
+            .verify_spends(&tx, utxos)
+            .map_err(|err| NodeError::Rejected(err.to_string()))?;
+        self.mempool
+            .insert(tx, fee)
+            .map_err(|err| NodeError::Rejected(err.to_string()))
+    }
+
+    /// Queue `message` for the peer at `to`, dropping the peer if its queue
+    /// is full or its connection gone
+    fn send(&mut self, to: SocketAddr, message: Message) {
+        trace::event!(trace, peer = %to, command = message.command(), "message sent");
+        if let Some(peer) = self.peers.get(&to) {
+            if peer.commands.try_send(message).is_err() {
+                self.disconnect(&to);
+            }
+        }
+    }
+
+    /// Disconnect and ban the peer at `addr` if its misbehavior score has
+    /// reached the threshold
+    fn enforce_ban(&mut self, addr: &SocketAddr) {
+        if self
+            .relay
+            .misbehavior(addr)
+            .is_some_and(|score| score >= self.ban_threshold)
+        {
+            self.bans.ban(addr.ip());
+            self.disconnect(addr);
+        }
+    }
+
+    /// Forget the peer at `addr`, which closes its connection
+    fn disconnect(&mut self, addr: &SocketAddr) {
+        self.peers.remove(addr);
+        self.relay.remove_peer(addr);
+        self.outbound.disconnected(addr);
+    }
+
+    /// Dial the addresses the connection manager picks to make up the
+    /// target number of outbound connections, skipping banned ones, peers
+    /// connected already and the node itself
+    fn top_up(&mut self) {
+        let (bans, peers, local_addr) = (&self.bans, &self.peers, self.local_addr);
+        let dial = self.outbound.to_dial(|addr| {
+            *addr == local_addr || peers.contains_key(addr) || bans.is_banned(&addr.ip())
+        });
+        for addr in dial {
+            self.dial(addr, None);
+        }
+    }
+
+    /// Connect to `addr` and take the connection through its handshake,
+    /// telling `reply` how it went if given
+    fn dial(&mut self, addr: SocketAddr, reply: Option<Reply<PeerInfo>>) {
+        let (magic, version, events) = (self.magic, self.version(), self.events.clone());
+        let inbound = self.inbound();
+        self.spawn(async move {
+            match AsyncPeer::connect(addr, magic).await {
+                Ok(peer) => connection(peer, addr, version, events, inbound, reply).await,
+                Err(err) => {
+                    let _ = events.send(Event::DialFailed(addr)).await;
+                    if let Some(reply) = reply {
+                        let _ = reply.send(Err(NodeError::Handshake(err.into())));
+                    }
+                }
+            }
+        });
+    }
+
+    /// A request for the headers after the chain's tip, or after `last` if
+    /// given, the last header of a batch
+    fn get_headers(&self, last: Option<BlockHash>) -> Message {
+        let mut locator = self.chain.locator();
+        if let Some(last) = last {
+            locator.insert(0, last);
+        }
+        locator.truncate(MAX_LOCATOR_HASHES);
+        Message::GetHeaders {
+            locator,
+            stop: None,
+        }
+    }
+
+    /// The budgets and queue for a new connection's inbound messages
+    fn inbound(&self) -> Inbound {
+        Inbound {
+            limiter: RateLimiter::new(self.rate_limits, self.clock.clone()),
+            queue: Arc::new(Semaphore::new(self.inbound_queue_len)),
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    fn version(&self) -> Version {
+        Version {
+            protocol_version: PROTOCOL_VERSION,
+            services: SERVICE_FULL_BLOCKS | SERVICE_COMPACT_BLOCKS,
+            best_height: self.chain.height(),
+            genesis_hash: self.chain.params().genesis_hash(),
+            nonce: self.nonce,
+            user_agent: USER_AGENT.to_string(),
+        }
+    }
+
+    /// Run `task` until it ends or the node shuts down
+    fn spawn(&mut self, task: impl Future<Output = ()> + Send + 'static) {
+        let shutdown = self.shutdown.clone();
+        self.connections.spawn(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = shutdown.cancelled() => {}
+            }
+        });
+    }
+}
+
+/// What holds a connection's inbound messages back from the node's task
+struct Inbound {
+    limiter: RateLimiter,
+    /// A permit for each message from the peer the node's task is yet to
+    /// handle
+    queue: Arc<Semaphore>,
+    metrics: Option<Arc<dyn NetMetrics>>,
+}
+
+impl Inbound {
+    /// Take `message`, a frame of `bytes` from `addr`, out of its budget,
+    /// returning whether there was room. A message over budget is reported
+    /// only the first time it is offered, not on each `retry`.
+    fn admit(&mut self, addr: SocketAddr, message: &Message, bytes: usize, retry: bool) -> bool {
+        let admitted = self.limiter.admit(message, bytes);
+        if let Some(metrics) = &self.metrics {
+            if !admitted && !retry {
+                metrics.message_throttled(addr, message.command());
+            } else if admitted && self.queue.available_permits() == 0 {
+                metrics.inbound_queue_full(addr);
+            }
+        }
+        admitted
+    }
+}
+
+/// Take a connection through its handshake, then pass on what the peer
+/// sends and send what is queued for it until either side closes it.
+///
+/// Only one message from the peer is held at a time: while it is over
+/// budget or waits for room in the inbound queue, the peer is not read
+/// from, though what is queued for it is still sent.
+async fn connection(
+    mut peer: AsyncPeer,
+    addr: SocketAddr,
+    local: Version,
+    events: mpsc::Sender<Event>,
+    mut inbound: Inbound,
+    reply: Option<Reply<PeerInfo>>,
+) {
+    let info = match peer.handshake(&local).await {
+        Ok(info) => info,
+        Err(err) => {
+            // Dropping the peer on returning closes the connection
+            let _ = events.send(Event::HandshakeFailed(addr, err.clone())).await;
+            if let Some(reply) = reply {
+                let _ = reply.send(Err(NodeError::Handshake(err)));
+            }
+            return;
+        }
+    };
+    let (commands, mut queued) = mpsc::channel(PEER_QUEUE_LEN);
+    let connected = Event::Connected {
+        addr,
+        info,
+        commands,
+        reply,
+    };
+    if events.send(connected).await.is_err() {
+        return;
+    }
+    let mut received = peer.bytes_received();
+    // A message received and not yet passed on, with its frame's length and
+    // whether its budget had room for it
+    let mut held: Option<(Message, usize, bool)> = None;
+    loop {
+        if let Some((message, bytes, admitted)) = &mut held {
+            if !*admitted {
+                *admitted = inbound.admit(addr, message, *bytes, true);
+            }
+        }
+        let throttled = held.as_ref().is_some_and(|(_, _, admitted)| !admitted);
+        let admitted = held.as_ref().is_some_and(|(_, _, admitted)| *admitted);
+        tokio::select! {
+            message = queued.recv() => match message {
+                Some(message) => {
+                    if peer.send(&message).await.is_err() {
+                        break;
+                    }
+                }
+                // The node dropped the peer
+                None => return,
+            },
+            message = peer.recv(), if held.is_none() => match message {
+                Ok(message) => {
+                    let bytes = (peer.bytes_received() - received) as usize;
+                    received = peer.bytes_received();
+                    let admitted = inbound.admit(addr, &message, bytes, false);
+                    held = Some((message, bytes, admitted));
+                }
+                Err(err) => {
+                    let _ = events.send(Event::Closed(addr, Some(err))).await;
+                    return;
+                }
+            },
+            _ = sleep(THROTTLE_POLL), if throttled => {}
+            permit = inbound.queue.clone().acquire_owned(), if admitted => {
+                // A message is held whenever one is admitted, and the inbound
+                // queue is never closed
+                let (Some((message, bytes, _)), Ok(permit)) = (held.take(), permit) else {
+                    return;
+                };
+                let received = Event::Received {
+                    from: addr,
+                    message,
+                    bytes,
+                    _queued: permit,
+                };
+                if events.send(received).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    let _ = events.send(Event::Closed(addr, None)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{mined, version};
+    use super::super::{CountingNetMetrics, Offense, RateLimit, FRAME_HEADER_LEN};
+    use super::*;
+    use crate::params::ChainParams;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{sleep, timeout};
+
+    #[derive(Debug, Default)]
+    struct ManualClock(AtomicU64);
+
+    impl ManualClock {
+        fn set(&self, now: u64) {
+            self.0.store(now, Ordering::Relaxed);
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    /// Wait until `check` holds for `node`'s chain and mempool
+    async fn wait_for<F>(node: &Node, what: &str, check: F)
+    where
+        F: Fn(&Blockchain, &Mempool) -> bool + Clone + Send + 'static,
+    {
+        let wait = async {
+            while !node.query(check.clone()).await.unwrap() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        timeout(Duration::from_secs(10), wait)
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for {}", what));
+    }
+
+    #[tokio::test]
+    async fn test_nodes_sync_relay_and_shut_down() {
+        let params = ChainParams::test_defaults();
+        let mut chain = Blockchain::new_from_params(&params);
+        for n in 0..5u8 {
+            let block = mined(chain.tip(), &[n]);
+            chain.insert(block).unwrap();
+        }
+        let tip = chain.tip().hash();
+        let a = Node::start("127.0.0.1:0", chain, Mempool::new(), NodeConfig::default())
+            .await
+            .unwrap();
+        let b = Node::start(
+            "127.0.0.1:0",
+            Blockchain::new_from_params(&params),
+            Mempool::new(),
+            NodeConfig::default(),
+        )
+        .await
+        .unwrap();
+
+        // A node will not connect to itself
+        assert_eq!(
+            a.connect(a.local_addr()).await,
+            Err(NodeError::Handshake(HandshakeError::SelfConnection))
+        );
+
+        // B catches up on connecting, then hears of A's next block
+        let info = b.connect(a.local_addr()).await.unwrap();
+        assert_eq!(info.best_height, 5);
+        assert_eq!(info.user_agent, USER_AGENT);
+        wait_for(&b, "sync", move |chain, _| chain.tip().hash() == tip).await;
+        let block = a
+            .query(|chain, _| mined(chain.tip(), b"next"))
+            .await
+            .unwrap();
+        let next = block.hash();
+        a.submit_block(block).await.unwrap();
+        wait_for(&b, "relay", move |chain, _| chain.tip().hash() == next).await;
+        assert_eq!(a.peer_info().await.unwrap().len(), 1);
+        assert_eq!(b.peer_info().await.unwrap()[0].addr, a.local_addr());
+
+        // Shutting A down closes its listener and its connection to B
+        let addr = a.local_addr();
+        let (chain, _) = timeout(Duration::from_secs(5), a.shutdown())
+            .await
+            .expect("shutdown hung");
+        assert_eq!(chain.height(), 6);
+        assert!(TcpStream::connect(addr).await.is_err());
+        let wait = async {
+            while !b.peer_info().await.unwrap().is_empty() {
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        timeout(Duration::from_secs(5), wait)
+            .await
+            .expect("B kept its connection to A");
+        let (chain, _) = timeout(Duration::from_secs(5), b.shutdown())
+            .await
+            .expect("shutdown hung");
+        assert_eq!(chain.tip().hash(), next);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_closes_connections_mid_handshake() {
+        let node = Node::start(
+            "127.0.0.1:0",
+            Blockchain::new_from_params(&ChainParams::test_defaults()),
+            Mempool::new(),
+            NodeConfig::default(),
+        )
+        .await
+        .unwrap();
+        // A peer that connects and never says a word holds up nothing
+        let mut silent = std::net::TcpStream::connect(node.local_addr()).unwrap();
+        sleep(Duration::from_millis(50)).await;
+        timeout(Duration::from_secs(5), node.shutdown())
+            .await
+            .expect("shutdown hung");
+
+        // The node sent its version, then closed the connection
+        let mut received = Vec::new();
+        std::io::Read::read_to_end(&mut silent, &mut received).unwrap();
+        assert!(!received.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_nodes_refuse_other_networks() {
+        let (test, mainnet) = (
+            ChainParams::test_defaults(),
+            ChainParams::mainnet_defaults(),
+        );
+        let start = |params: &ChainParams| {
+            let chain = Blockchain::new_from_params(params);
+            Node::start("127.0.0.1:0", chain, Mempool::new(), NodeConfig::default())
+        };
+        let (a, b) = (start(&test).await.unwrap(), start(&mainnet).await.unwrap());
+
+        // Each node's frames carry a magic the other does not read past
+        assert!(matches!(
+            b.connect(a.local_addr()).await,
+            Err(NodeError::Handshake(HandshakeError::Net(
+                NetError::BadMagic { .. }
+            )))
+        ));
+        let bad_magic = NetError::BadMagic {
+            expected: test.magic(),
+            got: mainnet.magic(),
+        };
+        let failures = async {
+            loop {
+                let failures = a.handshake_failures().await.unwrap();
+                if !failures.is_empty() {
+                    return failures;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let failures = timeout(Duration::from_secs(5), failures).await.unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].1, HandshakeError::Net(bad_magic));
+
+        // A peer framing with the test magic but holding the main genesis
+        // is refused at the handshake, and the connection closed
+        let mut peer = AsyncPeer::connect(a.local_addr(), test.magic())
+            .await
+            .unwrap();
+        let local = Version {
+            genesis_hash: mainnet.genesis_hash(),
+            ..version(1)
+        };
+        let mismatch = HandshakeError::GenesisMismatch {
+            expected: mainnet.genesis_hash(),
+            got: test.genesis_hash(),
+        };
+        assert_eq!(peer.handshake(&local).await, Err(mismatch));
+        let failures = async {
+            loop {
+                let failures = a.handshake_failures().await.unwrap();
+                if failures.len() == 2 {
+                    return failures;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let failures = timeout(Duration::from_secs(5), failures).await.unwrap();
+        assert_eq!(failures[1].0.ip(), IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(
+            failures[1].1,
+            HandshakeError::GenesisMismatch {
+                expected: test.genesis_hash(),
+                got: mainnet.genesis_hash(),
+            }
+        );
+        assert_eq!(a.peer_info().await.unwrap(), Vec::new());
+        a.shutdown().await;
+        b.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn test_flooding_peer_is_throttled_alone() {
+        let clock = Arc::new(ManualClock::default());
+        clock.set(1000);
+        let metrics = CountingNetMetrics::new();
+        let config = NodeConfig {
+            clock: clock.clone(),
+            rate_limits: RateLimits {
+                gossip: RateLimit {
+                    messages_per_sec: 10,
+                    bytes_per_sec: 1024 * 1024,
+                },
+                ..RateLimits::default()
+            },
+            ..NodeConfig::default()
+        }
+        .with_metrics(Arc::new(metrics.clone()));
+        let params = ChainParams::test_defaults();
+        let node = Node::start(
+            "127.0.0.1:0",
+            Blockchain::new_from_params(&params),
+            Mempool::new(),
+            config,
+        )
+        .await
+        .unwrap();
+        async fn connect(addr: SocketAddr, magic: u32, nonce: u64) -> AsyncPeer {
+            // Claiming no blocks, so the node does not ask for any
+            let local = Version {
+                best_height: 0,
+                ..version(nonce)
+            };
+            let mut peer = AsyncPeer::connect(addr, magic).await.unwrap();
+            peer.handshake(&local).await.unwrap();
+            peer
+        }
+        async fn pongs(peer: &mut AsyncPeer, range: std::ops::Range<u64>) {
+            let wait = async {
+                for n in range {
+                    assert_eq!(peer.recv().await, Ok(Message::Pong(n)));
+                }
+            };
+            timeout(Duration::from_secs(5), wait).await.expect("pongs")
+        }
+        let mut flooder = connect(node.local_addr(), params.magic(), 1).await;
+        let mut polite = connect(node.local_addr(), params.magic(), 2).await;
+
+        // Ten times a second's budget in pings gets a second's worth
+        // answered, while a peer within its budget is answered in full
+        for n in 0..100 {
+            flooder.send(&Message::Ping(n)).await.unwrap();
+        }
+        for n in 0..5 {
+            polite.send(&Message::Ping(n)).await.unwrap();
+        }
+        pongs(&mut polite, 0..5).await;
+        pongs(&mut flooder, 0..10).await;
+        assert!(timeout(Duration::from_millis(200), flooder.recv())
+            .await
+            .is_err());
+        let (flooder_addr, polite_addr) =
+            (flooder.local_addr().unwrap(), polite.local_addr().unwrap());
+        let counts = metrics.peer(&flooder_addr);
+        assert_eq!((counts.processed, counts.throttled), (10, 1));
+        let counts = metrics.peer(&polite_addr);
+        assert_eq!((counts.processed, counts.throttled), (5, 0));
+        assert_eq!(counts.bytes, 5 * (FRAME_HEADER_LEN as u64 + 8));
+
+        // Each second lets another second's worth through
+        clock.set(1001);
+        pongs(&mut flooder, 10..20).await;
+        assert!(timeout(Duration::from_millis(200), flooder.recv())
+            .await
+            .is_err());
+        let counts = metrics.peer(&flooder_addr);
+        assert_eq!((counts.processed, counts.throttled), (20, 2));
+        assert_eq!(node.peer_info().await.unwrap().len(), 2);
+        timeout(Duration::from_secs(5), node.shutdown())
+            .await
+            .expect("shutdown hung");
+    }
+
+    #[tokio::test]
+    async fn test_misbehaving_peer_is_banned_until_expiry() {
+        let clock = Arc::new(ManualClock::default());
+        clock.set(1000);
+        let config = NodeConfig {
+            ban_duration: Duration::from_secs(600),
+            clock: clock.clone(),
+            ..NodeConfig::default()
+        };
+        let params = ChainParams::test_defaults();
+        let node = Node::start(
+            "127.0.0.1:0",
+            Blockchain::new_from_params(&params),
+            Mempool::new(),
+            config,
+        )
+        .await
+        .unwrap();
+        let mut peer = AsyncPeer::connect(node.local_addr(), params.magic())
+            .await
+            .unwrap();
+        peer.handshake(&version(1)).await.unwrap();
+        // The node lists the peer once it has taken in the handshake
+        let score = || async { Some(node.peer_info().await.unwrap().first()?.misbehavior) };
+
+        // Blocks nobody asked for cost a little each
+        let genesis = params.genesis_block();
+        for n in 0..3u8 {
+            let block = mined(&genesis, &[n]);
+            peer.send(&Message::BlockMsg(Box::new(block)))
+                .await
+                .unwrap();
+        }
+        while score().await < Some(3 * Offense::Unsolicited.penalty()) {
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(score().await, Some(15));
+
+        // A header without the work it claims costs the rest
+        let difficulty = params.initial_difficulty;
+        let weak = (0u8..)
+            .map(|n| {
+                genesis
+                    .next_builder()
+                    .transaction(vec![n])
+                    .difficulty(difficulty)
+                    .timestamp(genesis.timestamp() + 10)
+                    .build()
+                    .unwrap()
+            })
+            .find(|block| !difficulty.is_met_by(block.hash().as_bytes()))
+            .unwrap();
+        peer.send(&Message::Headers(vec![weak.header().clone()]))
+            .await
+            .unwrap();
+        let dropped = async { while peer.recv().await.is_ok() {} };
+        timeout(Duration::from_secs(5), dropped)
+            .await
+            .expect("banned peer was not disconnected");
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        assert_eq!(node.banned().await.unwrap(), vec![(localhost, 1600)]);
+        assert_eq!(node.peer_info().await.unwrap(), Vec::new());
+
+        // The node neither takes the peer back nor dials it while banned
+        let mut again = AsyncPeer::connect(node.local_addr(), params.magic())
+            .await
+            .unwrap();
+        assert!(again.handshake(&version(1)).await.is_err());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(
+            node.connect(addr).await,
+            Err(NodeError::Banned {
+                ip: localhost,
+                until: 1600
+            })
+        );
+
+        // Once the ban is over, it does both
+        clock.set(1600);
+        assert_eq!(node.banned().await.unwrap(), Vec::new());
+        let accept = async {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut peer = AsyncPeer::new(stream, params.magic());
+            peer.handshake(&version(2)).await.unwrap();
+            peer
+        };
+        let (connected, _peer) = tokio::join!(node.connect(addr), accept);
+        assert_eq!(connected.unwrap().best_height, 7);
+        let mut again = AsyncPeer::connect(node.local_addr(), params.magic())
+            .await
+            .unwrap();
+        again.handshake(&version(3)).await.unwrap();
+        timeout(Duration::from_secs(5), node.shutdown())
+            .await
+            .expect("shutdown hung");
+    }
+
+    #[tokio::test]
+    async fn test_nodes_exchange_addresses_and_remember_them() {
+        let params = ChainParams::test_defaults();
+        let dir = std::env::temp_dir().join(format!("aarwyn-node-addrs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("peers.dat");
+        let config = |addr_book: Option<PathBuf>| NodeConfig {
+            connect_interval: Duration::from_millis(50),
+            addr_book,
+            ..NodeConfig::default()
+        };
+        let start = |config: NodeConfig| {
+            let chain = Blockchain::new_from_params(&params);
+            Node::start("127.0.0.1:0", chain, Mempool::new(), config)
+        };
+        /// Wait until `node` has an outbound connection to each of `addrs`
+        async fn connected_to(node: &Node, addrs: &[SocketAddr]) {
+            let wait = async {
+                loop {
+                    let peers = node.peer_info().await.unwrap();
+                    if addrs
+                        .iter()
+                        .all(|addr| peers.iter().any(|peer| peer.addr == *addr))
+                    {
+                        return;
+                    }
+                    sleep(Duration::from_millis(10)).await;
+                }
+            };
+            timeout(Duration::from_secs(10), wait)
+                .await
+                .expect("timed out connecting");
+        }
+        let b = start(config(None)).await.unwrap();
+        let c = start(config(None)).await.unwrap();
+        b.connect(c.local_addr()).await.unwrap();
+
+        // A hears of C from B, and dials it of its own accord
+        let a = start(config(Some(path.clone()))).await.unwrap();
+        a.connect(b.local_addr()).await.unwrap();
+        connected_to(&a, &[b.local_addr(), c.local_addr()]).await;
+        let book = a.addr_book().await.unwrap();
+        assert_eq!(book.len(), 2);
+        assert!(book.iter().all(|entry| entry.last_success.is_some()));
+        // Only dialled addresses go in the book, not those of peers that
+        // dialled in
+        let addrs = |book: Vec<AddrEntry>| -> Vec<SocketAddr> {
+            book.into_iter().map(|entry| entry.addr).collect()
+        };
+        assert_eq!(addrs(b.addr_book().await.unwrap()), [c.local_addr()]);
+        assert_eq!(addrs(c.addr_book().await.unwrap()), []);
+
+        // Saved on shutting down, the book has a new node dial both
+        timeout(Duration::from_secs(5), a.shutdown())
+            .await
+            .expect("shutdown hung");
+        assert_eq!(
+            AddrBook::load(&path, DEFAULT_MAX_ADDRESSES).unwrap().len(),
+            2
+        );
+        let again = start(config(Some(path))).await.unwrap();
+        connected_to(&again, &[b.local_addr(), c.local_addr()]).await;
+        for node in [again, b, c] {
+            timeout(Duration::from_secs(5), node.shutdown())
+                .await
+                .expect("shutdown hung");
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,169 @@
+//! Budgets for how much a peer may send, refilled as time passes.
+//!
+//! A [`RateLimiter`] keeps two budgets for a peer, one for blocks and
+//! headers and a smaller one for everything else, transaction gossip above
+//! all. Each allows a number of messages and of bytes a second, and holds
+//! at most a second's worth, so a quiet peer can send a burst that size.
+
+use std::sync::Arc;
+
+use super::Message;
+use crate::params::Clock;
+
+/// How much of one kind of traffic a peer may send each second
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub messages_per_sec: u32,
+    /// Counting whole frames, headers included
+    pub bytes_per_sec: u64,
+}
+
+/// Budget for blocks and headers unless configured otherwise
+pub const DEFAULT_BLOCK_RATE: RateLimit = RateLimit {
+    messages_per_sec: 500,
+    bytes_per_sec: 32 * 1024 * 1024,
+};
+
+/// Budget for every other message unless configured otherwise
+pub const DEFAULT_GOSSIP_RATE: RateLimit = RateLimit {
+    messages_per_sec: 200,
+    bytes_per_sec: 1024 * 1024,
+};
+
+/// The budgets a [`RateLimiter`] keeps for a peer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RateLimits {
+    /// For `block` and `headers` messages, which a node catching up
+    /// receives in bulk, and for compact blocks and their transactions
+    pub blocks: RateLimit,
+    /// For transactions, inventories, requests and pings
+    pub gossip: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            blocks: DEFAULT_BLOCK_RATE,
+            gossip: DEFAULT_GOSSIP_RATE,
+        }
+    }
+}
+
+/// A budget refilled at its rate each second, up to a second's worth
+#[derive(Clone, Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    messages: u64,
+    bytes: u64,
+    /// When the bucket was last refilled, in seconds since the Unix epoch
+    refilled: u64,
+}
+
+impl TokenBucket {
+    fn full(limit: RateLimit, now: u64) -> Self {
+        TokenBucket {
+            limit,
+            messages: limit.messages_per_sec.into(),
+            bytes: limit.bytes_per_sec,
+            refilled: now,
+        }
+    }
+
+    /// Take a message of `bytes` out of the bucket if it holds enough. A
+    /// message larger than a second's worth is taken from a full bucket,
+    /// emptying it.
+    fn take(&mut self, bytes: u64, now: u64) -> bool {
+        let elapsed = now.saturating_sub(self.refilled);
+        if elapsed > 0 {
+            let refill =
+                |held: u64, rate: u64| held.saturating_add(elapsed.saturating_mul(rate)).min(rate);
+            self.messages = refill(self.messages, self.limit.messages_per_sec.into());
+            self.bytes = refill(self.bytes, self.limit.bytes_per_sec);
+            self.refilled = now;
+        }
+        let room = self.bytes >= bytes || self.bytes == self.limit.bytes_per_sec;
+        if self.messages == 0 || !room {
+            return false;
+        }
+        self.messages -= 1;
+        self.bytes = self.bytes.saturating_sub(bytes);
+        true
+    }
+}
+
+/// The budgets of one peer, timed by a [`Clock`]
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    blocks: TokenBucket,
+    gossip: TokenBucket,
+    clock: Arc<dyn Clock>,
+}
+
+impl RateLimiter {
+    /// A limiter whose budgets start full
+    pub fn new(limits: RateLimits, clock: Arc<dyn Clock>) -> Self {
+        let now = clock.now();
+        RateLimiter {
+            blocks: TokenBucket::full(limits.blocks, now),
+            gossip: TokenBucket::full(limits.gossip, now),
+            clock,
+        }
+    }
+
+    /// Take `message`, received in a frame of `bytes`, out of its budget,
+    /// returning whether there was room. A message there was no room for
+    /// takes nothing, and should be offered again once time has passed.
+    pub fn admit(&mut self, message: &Message, bytes: usize) -> bool {
+        let now = self.clock.now();
+        let bucket = match message {
+            Message::BlockMsg(_)
+            | Message::Headers(_)
+            | Message::CmpctBlock(_)
+            | Message::BlockTxn { .. } => &mut self.blocks,
+            _ => &mut self.gossip,
+        };
+        bucket.take(bytes as u64, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::params::{ChainParams, FixedClock};
+
+    #[test]
+    fn test_budgets_refill_each_second() {
+        let limits = RateLimits {
+            blocks: RateLimit {
+                messages_per_sec: 2,
+                bytes_per_sec: 1000,
+            },
+            gossip: RateLimit {
+                messages_per_sec: 3,
+                bytes_per_sec: 100,
+            },
+        };
+        let mut limiter = RateLimiter::new(limits, Arc::new(FixedClock(1000)));
+        let ping = Message::Ping(1);
+        let block = Message::BlockMsg(Box::new(ChainParams::test_defaults().genesis_block()));
+
+        // Three pings use up the gossip budget, leaving blocks theirs
+        assert!((0..3).all(|_| limiter.admit(&ping, 10)));
+        assert!(!limiter.admit(&ping, 10));
+        assert!(limiter.admit(&block, 400));
+        assert!(limiter.admit(&block, 400));
+        assert!(!limiter.admit(&block, 100));
+
+        // A second later both are full again, though no fuller
+        let mut later = RateLimiter {
+            clock: Arc::new(FixedClock(1005)),
+            ..limiter
+        };
+        assert!((0..3).all(|_| later.admit(&ping, 10)));
+        assert!(!later.admit(&ping, 10));
+
+        // A frame over a second's bytes passes only on a full budget
+        assert!(later.admit(&block, 5000));
+        assert!(!later.admit(&block, 1));
+    }
+}
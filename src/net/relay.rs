@@ -0,0 +1,1082 @@
+//! Announcing new blocks and transactions to peers.
+//!
+//! A node that mines, or is sent, a block it did not have announces the
+//! block's hash in an `Inv` to every peer but the one it came from. A peer
+//! that lacks the block asks for it with `GetData`, takes it in and announces
+//! it onward in turn. Transactions travel the same way, checked against the
+//! chain's unspent outputs before they enter the mempool and go on. [`Relay`]
+//! holds this policy, leaving the connections to the caller: it is told of
+//! each message and says what to send and to whom.
+//!
+//! A peer offering [`SERVICE_COMPACT_BLOCKS`](super::SERVICE_COMPACT_BLOCKS)
+//! is sent each new block straight away as a [`CompactBlock`] instead of an
+//! `Inv`. It rebuilds the block from its mempool, asks with a `GetBlockTxn`
+//! for the transactions it lacks, and falls back to asking for the whole
+//! block if what it rebuilds does not match the header's merkle root.
+
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+
+use super::sync::find_block;
+use super::{
+    serve, CompactBlock, InvItem, Message, MisbehaviorScore, Offense, PartialBlock, SyncError,
+};
+use crate::block::{Block, BlockHash};
+use crate::chain::{Blockchain, ChainError, Reorg};
+use crate::mempool::Mempool;
+use crate::store::ChainStore;
+use crate::transaction::FeeError;
+
+/// Default number of items a [`Relay`] remembers announcing, and of those it
+/// remembers asking for
+pub const DEFAULT_MAX_RECENT_ITEMS: usize = 50_000;
+
+/// Number of items a [`Relay`] remembers each peer having
+pub const MAX_KNOWN_PER_PEER: usize = 5_000;
+
+/// Number of blocks a [`Relay`] rebuilds from compact form at once; a
+/// compact block beyond them is asked for in full
+pub const MAX_PARTIAL_BLOCKS: usize = 16;
+
+/// A bounded set of inventory items that forgets the least recently seen
+/// first
+#[derive(Clone, Debug)]
+pub struct RecentItems {
+    /// When each held item was last seen
+    last_seen: HashMap<InvItem, u64>,
+    /// Sightings, oldest first; one is stale once its item is seen again or
+    /// removed
+    order: VecDeque<(InvItem, u64)>,
+    clock: u64,
+    capacity: usize,
+}
+
+impl RecentItems {
+    pub fn new(capacity: usize) -> Self {
+        RecentItems {
+            last_seen: HashMap::new(),
+            order: VecDeque::new(),
+            clock: 0,
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.last_seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.last_seen.is_empty()
+    }
+
+    pub fn contains(&self, item: &InvItem) -> bool {
+        self.last_seen.contains_key(item)
+    }
+
+    /// Record a sighting of `item`, forgetting the least recently seen items
+    /// beyond the capacity. Returns whether `item` was not held before.
+    pub fn insert(&mut self, item: InvItem) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+        self.clock += 1;
+        let new = self.last_seen.insert(item, self.clock).is_none();
+        self.order.push_back((item, self.clock));
+        while self.last_seen.len() > self.capacity {
+            // Every held item has an entry in the order
+            let Some((oldest, seen)) = self.order.pop_front() else {
+                break;
+            };
+            if self.last_seen.get(&oldest) == Some(&seen) {
+                self.last_seen.remove(&oldest);
+            }
+        }
+        // Stale sightings of items seen over and over would otherwise pile up
+        if self.order.len() > 2 * self.capacity {
+            let last_seen = &self.last_seen;
+            self.order
+                .retain(|(item, seen)| last_seen.get(item) == Some(seen));
+        }
+        new
+    }
+
+    /// Forget `item`, returning whether it was held
+    pub fn remove(&mut self, item: &InvItem) -> bool {
+        self.last_seen.remove(item).is_some()
+    }
+}
+
+/// What a [`Relay`] keeps about one peer
+#[derive(Clone, Debug)]
+struct PeerState {
+    addr: SocketAddr,
+    /// Items the peer announced or sent, or was told of, so as not to tell
+    /// it again
+    known: RecentItems,
+    misbehavior: MisbehaviorScore,
+    /// Whether to send the peer new blocks in compact form
+    compact: bool,
+}
+
+/// The relay policy of a node and the peers it relays to.
+///
+/// Each item is announced once, however many peers send it, and asked for
+/// from the first peer to announce it while the request is outstanding. An
+/// announcement skips the peers known to have the item already.
+#[derive(Clone, Debug)]
+pub struct Relay {
+    peers: Vec<PeerState>,
+    /// Items held and announced to the peers
+    announced: RecentItems,
+    /// Items asked for and not yet received
+    requested: RecentItems,
+    /// Blocks being rebuilt from compact form, with the peer asked for the
+    /// missing transactions
+    partial: HashMap<BlockHash, (SocketAddr, PartialBlock)>,
+}
+
+impl Default for Relay {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_MAX_RECENT_ITEMS)
+    }
+}
+
+impl Relay {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A relay remembering up to `capacity` announced items and as many
+    /// requested ones
+    pub fn with_capacity(capacity: usize) -> Self {
+        Relay {
+            peers: Vec::new(),
+            announced: RecentItems::new(capacity),
+            requested: RecentItems::new(capacity),
+            partial: HashMap::new(),
+        }
+    }
+
+    /// Relay to the peer at `addr` from now on
+    pub fn add_peer(&mut self, addr: SocketAddr) {
+        if self.peer(&addr).is_none() {
+            self.peers.push(PeerState {
+                addr,
+                known: RecentItems::new(MAX_KNOWN_PER_PEER),
+                misbehavior: MisbehaviorScore::default(),
+                compact: false,
+            });
+        }
+    }
+
+    /// Send new blocks to the peer at `addr`, if connected, in compact form
+    /// from now on
+    pub fn enable_compact_blocks(&mut self, addr: &SocketAddr) {
+        if let Some(peer) = self.peers.iter_mut().find(|peer| peer.addr == *addr) {
+            peer.compact = true;
+        }
+    }
+
+    /// Stop relaying to the peer at `addr`, forgetting the blocks it was
+    /// sending the missing transactions of so that they can be asked for
+    /// from others
+    pub fn remove_peer(&mut self, addr: &SocketAddr) {
+        self.peers.retain(|peer| peer.addr != *addr);
+        let requested = &mut self.requested;
+        self.partial.retain(|hash, (peer, _)| {
+            let keep = peer != addr;
+            if !keep {
+                requested.remove(&InvItem::Block(*hash));
+            }
+            keep
+        });
+    }
+
+    /// Connected peers, in the order they were added
+    pub fn peers(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.peers.iter().map(|peer| peer.addr)
+    }
+
+    /// The misbehavior score of the peer at `addr`, if connected: the sum of
+    /// the penalties for the offenses it committed
+    pub fn misbehavior(&self, addr: &SocketAddr) -> Option<u32> {
+        Some(self.peer(addr)?.misbehavior.value())
+    }
+
+    /// Add the penalty for `offense` to the score of the peer at `addr`,
+    /// returning the new score if the peer is connected
+    pub fn penalize(&mut self, addr: &SocketAddr, offense: Offense) -> Option<u32> {
+        let peer = self.peers.iter_mut().find(|peer| peer.addr == *addr)?;
+        Some(peer.misbehavior.add(offense))
+    }
+
+    /// Announce `item`, which the node now holds, to every peer but
+    /// `source`, the one it came from if any, and those known to have it.
+    /// Returns the `Inv` for each peer, or nothing if the item was announced
+    /// already.
+    pub fn announce(
+        &mut self,
+        item: InvItem,
+        source: Option<SocketAddr>,
+    ) -> Vec<(SocketAddr, Message)> {
+        self.requested.remove(&item);
+        if !self.announced.insert(item) {
+            return Vec::new();
+        }
+        self.peers
+            .iter_mut()
+            .filter_map(|peer| {
+                let tell = Some(peer.addr) != source && peer.known.insert(item);
+                tell.then(|| (peer.addr, Message::Inv(vec![item])))
+            })
+            .collect()
+    }
+
+    /// Announce `block`, which the chain now holds, like [`Relay::announce`],
+    /// sending it in compact form to the peers that take it so
+    pub fn announce_block(
+        &mut self,
+        block: &Block,
+        source: Option<SocketAddr>,
+    ) -> Vec<(SocketAddr, Message)> {
+        let compact = self.compact(block);
+        self.announce_compact(block.hash(), compact, source)
+    }
+
+    /// Handle `message` from the peer at `from`, returning what to send and
+    /// to whom.
+    ///
+    /// Announced items that are neither held nor asked for already are asked
+    /// for. A `GetData` is answered with the blocks the chain has and the
+    /// transactions the mempool has. A block that was asked for is inserted
+    /// into the chain and, if it is new, announced onward with
+    /// [`Relay::announce_block`]. Other requests are answered with [`serve`].
+    ///
+    /// A compact block that is neither held nor asked for already is
+    /// rebuilt from the mempool. The transactions still missing are asked
+    /// for with a `GetBlockTxn`, and the block is inserted and announced
+    /// once the `BlockTxn` answering it arrives. If the block cannot be
+    /// rebuilt, its transactions do not match its merkle root, or
+    /// [`MAX_PARTIAL_BLOCKS`] are being rebuilt already, the whole block is
+    /// asked for instead.
+    ///
+    /// A transaction that was asked for goes into the mempool, paying the
+    /// fee its inputs leave in the chain's unspent outputs and the pool's,
+    /// and is announced onward. It is dropped if the chain does not track
+    /// unspent outputs, if an output it spends is missing, or if the pool
+    /// refuses it. If it pays out more than it spends, the sender's
+    /// misbehavior score also rises by [`Offense::InvalidTransaction`]'s
+    /// penalty.
+    pub fn handle<S: ChainStore>(
+        &mut self,
+        from: SocketAddr,
+        message: Message,
+        chain: &mut Blockchain<S>,
+        mempool: &mut Mempool,
+    ) -> Result<Vec<(SocketAddr, Message)>, SyncError> {
+        match message {
+            Message::Inv(items) => {
+                let mut wanted = Vec::new();
+                for item in items {
+                    self.learn(&from, item);
+                    let held = match &item {
+                        InvItem::Block(hash) => chain.status_of(hash.as_ref()).is_some(),
+                        InvItem::Tx(txid) => mempool.contains(txid),
+                    };
+                    if !held && !self.announced.contains(&item) && self.requested.insert(item) {
+                        wanted.push(item);
+                    }
+                }
+                if wanted.is_empty() {
+                    return Ok(Vec::new());
+                }
+                Ok(vec![(from, Message::GetData(wanted))])
+            }
+            Message::GetData(items) => {
+                let mut replies = Vec::new();
+                for item in items {
+                    let reply = match &item {
+                        InvItem::Block(hash) => find_block(chain, hash)
+                            .map(|block| Message::BlockMsg(Box::new(block.clone()))),
+                        InvItem::Tx(txid) => mempool.get(txid).cloned().map(Message::Tx),
+                    };
+                    if let Some(reply) = reply {
+                        self.learn(&from, item);
+                        replies.push((from, reply));
+                    }
+                }
+                Ok(replies)
+            }
+            Message::BlockMsg(block) => {
+                let hash = block.hash();
+                self.learn(&from, InvItem::Block(hash));
+                if !self.requested.remove(&InvItem::Block(hash)) {
+                    return Err(SyncError::UnrequestedBlock(hash));
+                }
+                self.accept_block(from, *block, chain, mempool)
+            }
+            Message::CmpctBlock(compact) => {
+                let hash = compact.hash();
+                let item = InvItem::Block(hash);
+                self.learn(&from, item);
+                if chain.status_of(hash.as_ref()).is_some()
+                    || self.announced.contains(&item)
+                    || self.requested.contains(&item)
+                {
+                    return Ok(Vec::new());
+                }
+                let Ok(partial) = compact.reconstruct(mempool) else {
+                    return Ok(self.request_block(from, hash));
+                };
+                let missing = partial.missing();
+                if missing.is_empty() {
+                    return match partial.into_block() {
+                        Ok(block) => self.accept_block(from, block, chain, mempool),
+                        Err(_) => Ok(self.request_block(from, hash)),
+                    };
+                }
+                if self.partial.len() >= MAX_PARTIAL_BLOCKS {
+                    return Ok(self.request_block(from, hash));
+                }
+                self.requested.insert(item);
+                self.partial.insert(hash, (from, partial));
+                Ok(vec![(
+                    from,
+                    Message::GetBlockTxn {
+                        block: hash,
+                        indexes: missing,
+                    },
+                )])
+            }
+            Message::BlockTxn {
+                block: hash,
+                transactions,
+            } => {
+                let mut partial = match self.partial.entry(hash) {
+                    Entry::Occupied(entry) if entry.get().0 == from => entry.remove().1,
+                    _ => return Err(SyncError::UnrequestedBlock(hash)),
+                };
+                match partial
+                    .fill(transactions)
+                    .and_then(|()| partial.into_block())
+                {
+                    Ok(block) => {
+                        self.requested.remove(&InvItem::Block(hash));
+                        self.accept_block(from, block, chain, mempool)
+                    }
+                    // A short id matched the wrong pooled transaction, or
+                    // the peer sent the wrong ones
+                    Err(_) => Ok(self.request_block(from, hash)),
+                }
+            }
+            Message::Tx(tx) => {
+                let item = InvItem::Tx(tx.txid());
+                self.learn(&from, item);
+                if !self.requested.remove(&item) {
+                    return Ok(Vec::new());
+                }
+                let Some(utxos) = chain.utxo_set() else {
+                    return Ok(Vec::new());
+                };
+                let fee = match mempool.fee_of(&tx, utxos) {
+                    Ok(fee) => fee,
+                    // Perhaps spending a transaction yet to arrive
+                    Err(FeeError::MissingInput(_)) => return Ok(Vec::new()),
+                    Err(_) => {
+                        self.penalize(&from, Offense::InvalidTransaction);
+                        return Ok(Vec::new());
+                    }
+                };
+                if mempool.verify_spends(&tx, utxos).is_err() {
+                    self.penalize(&from, Offense::InvalidTransaction);
+                    return Ok(Vec::new());
+                }
+                match mempool.insert(tx, fee) {
+                    Ok(txid) => Ok(self.announce(InvItem::Tx(txid), Some(from))),
+                    Err(_) => Ok(Vec::new()),
+                }
+            }
+            request => Ok(serve(chain, &request)
+                .into_iter()
+                .map(|reply| (from, reply))
+                .collect()),
+        }
+    }
+
+    fn peer(&self, addr: &SocketAddr) -> Option<&PeerState> {
+        self.peers.iter().find(|peer| peer.addr == *addr)
+    }
+
+    /// Insert `block`, received from `from`, into the chain, bring the
+    /// mempool in line with any change to the active chain, and announce the
+    /// block onward if it is new
+    fn accept_block<S: ChainStore>(
+        &mut self,
+        from: SocketAddr,
+        block: Block,
+        chain: &mut Blockchain<S>,
+        mempool: &mut Mempool,
+    ) -> Result<Vec<(SocketAddr, Message)>, SyncError> {
+        let hash = block.hash();
+        // Made before the chain takes the block, and only if someone wants it
+        let compact = self.compact(&block);
+        match chain.insert(block) {
+            Ok(reorg) => {
+                if let Some(reorg) = reorg {
+                    update_mempool(chain, mempool, &reorg);
+                }
+                Ok(self.announce_compact(hash, compact, Some(from)))
+            }
+            // Already held, or held back as an orphan and not announced
+            Err(ChainError::DuplicateBlock(_) | ChainError::UnknownParent(_)) => Ok(Vec::new()),
+            Err(err @ (ChainError::Store(_) | ChainError::ReorgTooDeep { .. })) => {
+                Err(SyncError::Chain(err))
+            }
+            Err(err) => Err(SyncError::InvalidBlock(err)),
+        }
+    }
+
+    /// `block` in compact form, if any peer takes blocks so
+    fn compact(&self, block: &Block) -> Option<CompactBlock> {
+        self.peers
+            .iter()
+            .any(|peer| peer.compact)
+            .then(|| CompactBlock::new(block))
+    }
+
+    /// Announce the block with `hash`, sending `compact` in place of the
+    /// `Inv` to the peers that take compact blocks
+    fn announce_compact(
+        &mut self,
+        hash: BlockHash,
+        compact: Option<CompactBlock>,
+        source: Option<SocketAddr>,
+    ) -> Vec<(SocketAddr, Message)> {
+        let mut announcements = self.announce(InvItem::Block(hash), source);
+        if let Some(compact) = compact {
+            for (to, message) in &mut announcements {
+                if self.peer(to).is_some_and(|peer| peer.compact) {
+                    *message = Message::CmpctBlock(Box::new(compact.clone()));
+                }
+            }
+        }
+        announcements
+    }
+
+    /// Ask the peer at `from` for the whole block with `hash`
+    fn request_block(&mut self, from: SocketAddr, hash: BlockHash) -> Vec<(SocketAddr, Message)> {
+        let item = InvItem::Block(hash);
+        self.requested.insert(item);
+        vec![(from, Message::GetData(vec![item]))]
+    }
+
+    /// Note that the peer at `addr` has `item`
+    fn learn(&mut self, addr: &SocketAddr, item: InvItem) {
+        if let Some(peer) = self.peers.iter_mut().find(|peer| peer.addr == *addr) {
+            peer.known.insert(item);
+        }
+    }
+}
+
+/// Bring `mempool` in line with the active chain after `reorg`: return the
+/// transactions of the disconnected blocks that are still valid, lowest
+/// block first, then drop those the connected blocks mined and any they
+/// conflict with
+pub(super) fn update_mempool<S: ChainStore>(
+    chain: &Blockchain<S>,
+    mempool: &mut Mempool,
+    reorg: &Reorg,
+) {
+    if let Some(utxos) = chain.utxo_set() {
+        for hash in reorg.disconnected.iter().rev() {
+            if let Some(block) = find_block(chain, hash) {
+                mempool.reinsert_disconnected(block, utxos);
+            }
+        }
+    }
+    for hash in &reorg.connected {
+        if let Some(block) = find_block(chain, hash) {
+            mempool.remove_mined(block);
+        }
+    }
+    mempool.set_tip_height(chain.height());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{connected_pair, mined, version};
+    use super::super::Peer;
+    use super::*;
+    use crate::address::Address;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::crypto::Signer;
+    use crate::params::ChainParams;
+    use crate::transaction::{OutPoint, Transaction, TxInput, TxOutput, Txid};
+    use std::sync::mpsc::{self, Sender};
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    fn addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn test_recent_items_forget_least_recently_seen() {
+        let item = |n: u8| InvItem::Tx(Txid::from_bytes([n; 32]));
+        let mut recent = RecentItems::new(3);
+        assert!(recent.insert(item(1)));
+        assert!(recent.insert(item(2)));
+        assert!(recent.insert(item(3)));
+        // Seeing the first again makes the second the least recent
+        assert!(!recent.insert(item(1)));
+        assert!(recent.insert(item(4)));
+        assert_eq!(recent.len(), 3);
+        assert!(!recent.contains(&item(2)));
+        assert!(recent.contains(&item(1)));
+
+        for _ in 0..10 {
+            recent.insert(item(4));
+        }
+        assert!(recent.order.len() <= 6);
+        assert!(recent.remove(&item(3)));
+        assert!(!recent.remove(&item(3)));
+        assert!(recent.insert(item(5)));
+        assert!(recent.insert(item(6)));
+        assert_eq!(recent.len(), 3);
+        assert!(!recent.contains(&item(1)));
+    }
+
+    #[test]
+    fn test_relay_asks_once_and_serves_pool() {
+        let mut chain = Blockchain::new_from_params(&ChainParams::test_defaults());
+        let mut mempool = Mempool::new();
+        let tx = Transaction {
+            inputs: vec![TxInput {
+                prev_out: OutPoint {
+                    txid: Txid::of(b"funding"),
+                    index: 0,
+                },
+                signatures: Vec::new(),
+                public_key: None,
+                recovery_id: None,
+            }],
+            outputs: vec![TxOutput::to_address(10, Address::from_bytes([0; 32]))],
+            lock_time: 0,
+        };
+        let txid = mempool.insert(tx.clone(), 1).unwrap();
+        let block = mined(chain.tip(), b"new");
+        let mut relay = Relay::new();
+        relay.add_peer(addr(1));
+        relay.add_peer(addr(2));
+        relay.add_peer(addr(3));
+
+        // Only what is neither held nor already asked for is asked for
+        let genesis = InvItem::Block(chain.tip().hash());
+        let new = InvItem::Block(block.hash());
+        let inv = Message::Inv(vec![genesis, InvItem::Tx(txid), new]);
+        assert_eq!(
+            relay.handle(addr(1), inv.clone(), &mut chain, &mut mempool),
+            Ok(vec![(addr(1), Message::GetData(vec![new]))])
+        );
+        assert_eq!(
+            relay.handle(addr(2), inv, &mut chain, &mut mempool),
+            Ok(Vec::new())
+        );
+
+        // The block is announced once, to the peer that did not announce it
+        assert_eq!(
+            relay.handle(
+                addr(1),
+                Message::BlockMsg(Box::new(block.clone())),
+                &mut chain,
+                &mut mempool
+            ),
+            Ok(vec![(addr(3), Message::Inv(vec![new]))])
+        );
+        assert_eq!(chain.tip().hash(), block.hash());
+        assert_eq!(relay.announce(new, None), Vec::new());
+        assert_eq!(
+            relay.handle(
+                addr(2),
+                Message::BlockMsg(Box::new(block.clone())),
+                &mut chain,
+                &mut mempool
+            ),
+            Err(SyncError::UnrequestedBlock(block.hash()))
+        );
+
+        let missing = InvItem::Tx(Txid::of(b"missing"));
+        assert_eq!(
+            relay.handle(
+                addr(2),
+                Message::GetData(vec![new, InvItem::Tx(txid), missing]),
+                &mut chain,
+                &mut mempool
+            ),
+            Ok(vec![
+                (addr(2), Message::BlockMsg(Box::new(block))),
+                (addr(2), Message::Tx(tx)),
+            ])
+        );
+    }
+
+    /// What a test node keeps, and what it hands back once stopped
+    struct Node {
+        chain: Blockchain,
+        mempool: Mempool,
+        relay: Relay,
+        /// Messages received, with the peer each came from
+        received: Vec<(SocketAddr, Message)>,
+    }
+
+    enum Event {
+        Peer(SocketAddr, Message),
+        Mined(Block),
+        /// Pool a transaction unchecked, paying the fee, and announce it
+        Submitted(Transaction, u64),
+        /// Pool a transaction unchecked, without announcing it
+        Pooled(Transaction),
+        /// Send blocks to every peer in compact form from now on
+        CompactBlocks,
+        Inspect(Box<dyn FnOnce(&Node) + Send>),
+        Stop,
+    }
+
+    /// A node relaying between `peers` on its own thread, over `chain`
+    fn spawn_node(peers: Vec<Peer>, chain: Blockchain) -> (Sender<Event>, JoinHandle<Node>) {
+        let (events, inbox) = mpsc::channel();
+        let mut node = Node {
+            chain,
+            mempool: Mempool::new(),
+            relay: Relay::new(),
+            received: Vec::new(),
+        };
+        let mut writers = HashMap::new();
+        for peer in peers {
+            let from = peer.peer_addr().unwrap();
+            let mut reader = peer.try_clone().unwrap();
+            let events = events.clone();
+            thread::spawn(move || {
+                while let Ok(message) = reader.recv() {
+                    if events.send(Event::Peer(from, message)).is_err() {
+                        break;
+                    }
+                }
+            });
+            node.relay.add_peer(from);
+            writers.insert(from, peer);
+        }
+
+        let thread = thread::spawn(move || {
+            for event in inbox {
+                let Node {
+                    chain,
+                    mempool,
+                    relay,
+                    received,
+                } = &mut node;
+                let outgoing = match event {
+                    Event::Mined(block) => {
+                        chain.insert(block.clone()).unwrap();
+                        relay.announce_block(&block, None)
+                    }
+                    Event::Submitted(tx, fee) => {
+                        let txid = mempool.insert(tx, fee).unwrap();
+                        relay.announce(InvItem::Tx(txid), None)
+                    }
+                    Event::Pooled(tx) => {
+                        mempool.insert(tx, 0).unwrap();
+                        Vec::new()
+                    }
+                    Event::CompactBlocks => {
+                        let peers: Vec<_> = relay.peers().collect();
+                        for peer in peers {
+                            relay.enable_compact_blocks(&peer);
+                        }
+                        Vec::new()
+                    }
+                    Event::Peer(from, message) => {
+                        received.push((from, message.clone()));
+                        relay.handle(from, message, chain, mempool).unwrap()
+                    }
+                    Event::Inspect(f) => {
+                        f(&node);
+                        Vec::new()
+                    }
+                    Event::Stop => break,
+                };
+                for (to, message) in outgoing {
+                    writers.get_mut(&to).unwrap().send(&message).unwrap();
+                }
+            }
+            node
+        });
+        (events, thread)
+    }
+
+    /// Wait for `check` to hold of the node, failing after ten seconds
+    fn wait_for(node: &Sender<Event>, check: fn(&Node) -> bool, what: &str) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let (reply, answer) = mpsc::channel();
+            let inspect = move |node: &Node| reply.send(check(node)).unwrap();
+            node.send(Event::Inspect(Box::new(inspect))).unwrap();
+            if answer.recv().unwrap() {
+                return;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Let stray duplicates and echoes arrive, then stop every node
+    fn stop_all<const N: usize>(nodes: [(Sender<Event>, JoinHandle<Node>); N]) -> [Node; N] {
+        thread::sleep(Duration::from_millis(200));
+        for (events, _) in &nodes {
+            events.send(Event::Stop).unwrap();
+        }
+        nodes.map(|(_, thread)| thread.join().unwrap())
+    }
+
+    /// Both ends of a connection that has been through its handshake
+    fn linked() -> (Peer, Peer) {
+        let (mut a, mut b) = connected_pair();
+        let other = thread::spawn(move || {
+            b.handshake(&version(2)).unwrap();
+            b
+        });
+        a.handshake(&version(1)).unwrap();
+        (a, other.join().unwrap())
+    }
+
+    fn commands(received: &[(SocketAddr, Message)]) -> Vec<&'static str> {
+        received
+            .iter()
+            .map(|(_, message)| message.command())
+            .collect()
+    }
+
+    fn blocks_in(received: &[(SocketAddr, Message)]) -> Vec<(SocketAddr, BlockHash)> {
+        received
+            .iter()
+            .filter_map(|(from, message)| match message {
+                Message::BlockMsg(block) => Some((*from, block.hash())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_relays_along_line_once() {
+        // A - B - C, with no connection between A and C
+        let (a_to_b, b_to_a) = linked();
+        let (b_to_c, c_to_b) = linked();
+        // Each side knows the other by the address of its end
+        let a_at_b = b_to_a.peer_addr().unwrap();
+        let b_at_a = a_to_b.peer_addr().unwrap();
+        let b_at_c = c_to_b.peer_addr().unwrap();
+        let chain = || Blockchain::new_from_params(&ChainParams::test_defaults());
+        let a = spawn_node(vec![a_to_b], chain());
+        let b = spawn_node(vec![b_to_a, b_to_c], chain());
+        let c = spawn_node(vec![c_to_b], chain());
+
+        let genesis = ChainParams::test_defaults().genesis_block();
+        let block = mined(&genesis, b"mined at A");
+        a.0.send(Event::Mined(block.clone())).unwrap();
+        wait_for(&c.0, |node| node.chain.height() == 1, "the block at C");
+        let [a, b, c] = stop_all([a, b, c]);
+
+        for node in [&a, &b, &c] {
+            assert_eq!(node.chain.tip().hash(), block.hash());
+        }
+        assert_eq!(blocks_in(&b.received), vec![(a_at_b, block.hash())]);
+        assert_eq!(blocks_in(&c.received), vec![(b_at_c, block.hash())]);
+        // B does not announce the block back to A, and C has no one else to
+        // announce it to
+        assert_eq!(
+            a.received,
+            vec![(b_at_a, Message::GetData(vec![InvItem::Block(block.hash())]))]
+        );
+        assert_eq!(commands(&b.received), ["inv", "block", "getdata"]);
+        assert_eq!(commands(&c.received), ["inv", "block"]);
+    }
+
+    fn txs_in(received: &[(SocketAddr, Message)]) -> Vec<(SocketAddr, Txid)> {
+        received
+            .iter()
+            .filter_map(|(from, message)| match message {
+                Message::Tx(tx) => Some((*from, tx.txid())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_transactions_gossip_around_triangle_once() {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let owner = Address::from_public_key(&key.public_key());
+        let funding = Transaction {
+            outputs: vec![TxOutput::to_address(500, owner); 3],
+            ..Transaction::default()
+        };
+        let unsigned = |index: u32, amount: u64| Transaction {
+            inputs: vec![TxInput {
+                prev_out: OutPoint {
+                    txid: funding.txid(),
+                    index,
+                },
+                signatures: Vec::new(),
+                public_key: None,
+                recovery_id: None,
+            }],
+            outputs: vec![TxOutput::to_address(amount, Address::from_bytes([3; 32]))],
+            lock_time: 0,
+        };
+        let spend = |index: u32, amount: u64| {
+            let mut tx = unsigned(index, amount);
+            tx.sign_input(0, &key);
+            tx
+        };
+        let params = ChainParams {
+            genesis_transactions: vec![funding.encode()],
+            ..ChainParams::test_defaults()
+        };
+        let chain = || {
+            Blockchain::new_from_params(&params)
+                .with_utxo_set()
+                .unwrap()
+        };
+
+        // Every node connects to both others
+        let (a_to_b, b_to_a) = linked();
+        let (b_to_c, c_to_b) = linked();
+        let (c_to_a, a_to_c) = linked();
+        let a_at_b = b_to_a.peer_addr().unwrap();
+        let a_at_c = c_to_a.peer_addr().unwrap();
+        let a = spawn_node(vec![a_to_b, a_to_c], chain());
+        let b = spawn_node(vec![b_to_a, b_to_c], chain());
+        let c = spawn_node(vec![c_to_a, c_to_b], chain());
+
+        let valid = spend(0, 450);
+        a.0.send(Event::Submitted(valid.clone(), 50)).unwrap();
+        for node in [&b.0, &c.0] {
+            wait_for(node, |node| node.mempool.len() == 1, "the transaction");
+        }
+        // A pays out more than it spends, then spends without a signature;
+        // only A takes them in unchecked
+        let invalid = spend(1, 600);
+        let unsigned = unsigned(2, 450);
+        a.0.send(Event::Submitted(invalid.clone(), 0)).unwrap();
+        a.0.send(Event::Submitted(unsigned.clone(), 50)).unwrap();
+        for node in [&b.0, &c.0] {
+            let penalized = |node: &Node| {
+                node.relay.peers().any(|peer| {
+                    node.relay.misbehavior(&peer) == Some(2 * Offense::InvalidTransaction.penalty())
+                })
+            };
+            wait_for(node, penalized, "the invalid transactions");
+        }
+        let [a, b, c] = stop_all([a, b, c]);
+
+        for node in [&a, &b, &c] {
+            assert!(node.mempool.contains(&valid.txid()));
+        }
+        for node in [&b, &c] {
+            assert!(!node.mempool.contains(&invalid.txid()));
+            assert!(!node.mempool.contains(&unsigned.txid()));
+        }
+        // Each transaction reaches each node once, the valid one from A or
+        // from whichever other node took it in first, and nothing comes back
+        // to A
+        assert_eq!(txs_in(&a.received), Vec::new());
+        for (node, a_addr) in [(&b, a_at_b), (&c, a_at_c)] {
+            let txs = txs_in(&node.received);
+            assert_eq!(txs.len(), 3);
+            assert_eq!(txs[0].1, valid.txid());
+            assert_eq!(txs[1], (a_addr, invalid.txid()));
+            assert_eq!(txs[2], (a_addr, unsigned.txid()));
+        }
+        assert_eq!(
+            b.relay.misbehavior(&a_at_b),
+            Some(2 * Offense::InvalidTransaction.penalty())
+        );
+        for (node, a_addr) in [(&b, a_at_b), (&c, a_at_c)] {
+            for peer in node.relay.peers().filter(|peer| *peer != a_addr) {
+                assert_eq!(node.relay.misbehavior(&peer), Some(0));
+            }
+        }
+    }
+
+    /// Transactions paying `n` to distinct addresses, for blocks to hold
+    fn payments(n: u8) -> Vec<Transaction> {
+        (0..n)
+            .map(|n| Transaction {
+                outputs: vec![TxOutput::to_address(
+                    100 + u64::from(n),
+                    Address::from_bytes([n; 32]),
+                )],
+                ..Transaction::default()
+            })
+            .collect()
+    }
+
+    /// A block on `parent` holding a coinbase and `txs`
+    fn mined_with(parent: &Block, txs: &[Transaction]) -> Block {
+        let difficulty = ChainParams::test_defaults().initial_difficulty;
+        let mut block = parent
+            .next_builder()
+            .transaction(b"coinbase".to_vec())
+            .transactions(txs.iter().map(Transaction::encode))
+            .difficulty(difficulty)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(difficulty);
+        block
+    }
+
+    #[test]
+    fn test_compact_block_carries_only_missing_transactions() {
+        let (a_to_b, b_to_a) = linked();
+        let b_at_a = a_to_b.peer_addr().unwrap();
+        let chain = || Blockchain::new_from_params(&ChainParams::test_defaults());
+        let a = spawn_node(vec![a_to_b], chain());
+        let b = spawn_node(vec![b_to_a], chain());
+        for node in [&a.0, &b.0] {
+            node.send(Event::CompactBlocks).unwrap();
+        }
+
+        // B pools all but the last two of the block's transactions
+        let txs = payments(20);
+        for (i, tx) in txs.iter().enumerate() {
+            a.0.send(Event::Pooled(tx.clone())).unwrap();
+            if i < 18 {
+                b.0.send(Event::Pooled(tx.clone())).unwrap();
+            }
+        }
+        let genesis = ChainParams::test_defaults().genesis_block();
+        let block = mined_with(&genesis, &txs);
+        a.0.send(Event::Mined(block.clone())).unwrap();
+        wait_for(&b.0, |node| node.chain.height() == 1, "the block at B");
+        let [a, b] = stop_all([a, b]);
+
+        assert_eq!(b.chain.tip(), &block);
+        // The block's transactions leave the pool once mined
+        assert!(b.mempool.is_empty());
+        assert_eq!(commands(&b.received), ["cmpctblock", "blocktxn"]);
+        assert_eq!(
+            a.received,
+            vec![(
+                b_at_a,
+                Message::GetBlockTxn {
+                    block: block.hash(),
+                    indexes: vec![19, 20],
+                }
+            )]
+        );
+        let Message::BlockTxn { transactions, .. } = &b.received[1].1 else {
+            panic!("expected the missing transactions");
+        };
+        assert_eq!(transactions, &[txs[18].encode(), txs[19].encode()]);
+
+        // The three payloads together are well under half the full block
+        let sent: usize = a
+            .received
+            .iter()
+            .chain(&b.received)
+            .map(|(_, message)| message.encode_payload().len())
+            .sum();
+        let full = Message::BlockMsg(Box::new(block)).encode_payload().len();
+        assert!(
+            2 * sent < full,
+            "sent {} bytes of a {} byte block",
+            sent,
+            full
+        );
+    }
+
+    #[test]
+    fn test_compact_block_falls_back_to_full_block() {
+        let mut chain = Blockchain::new_from_params(&ChainParams::test_defaults());
+        let mut mempool = Mempool::new();
+        let txs = payments(3);
+        mempool.insert(txs[0].clone(), 0).unwrap();
+        let block = mined_with(chain.tip(), &txs);
+        let hash = block.hash();
+        let mut relay = Relay::new();
+        for port in 1..=3 {
+            relay.add_peer(addr(port));
+        }
+        relay.enable_compact_blocks(&addr(2));
+
+        // The pool lacks two transactions, and the wrong ones come back
+        let compact = CompactBlock::new(&block);
+        assert_eq!(
+            relay.handle(
+                addr(1),
+                Message::CmpctBlock(Box::new(compact.clone())),
+                &mut chain,
+                &mut mempool
+            ),
+            Ok(vec![(
+                addr(1),
+                Message::GetBlockTxn {
+                    block: hash,
+                    indexes: vec![2, 3],
+                }
+            )])
+        );
+        let wrong = Message::BlockTxn {
+            block: hash,
+            transactions: vec![txs[2].encode(), txs[1].encode()],
+        };
+        assert_eq!(
+            relay.handle(addr(3), wrong.clone(), &mut chain, &mut mempool),
+            Err(SyncError::UnrequestedBlock(hash))
+        );
+        assert_eq!(
+            relay.handle(addr(1), wrong, &mut chain, &mut mempool),
+            Ok(vec![(
+                addr(1),
+                Message::GetData(vec![InvItem::Block(hash)])
+            )])
+        );
+
+        // The full block is taken, and sent on compact where it is wanted
+        assert_eq!(
+            relay.handle(
+                addr(1),
+                Message::BlockMsg(Box::new(block.clone())),
+                &mut chain,
+                &mut mempool
+            ),
+            Ok(vec![
+                (addr(2), Message::CmpctBlock(Box::new(compact.clone()))),
+                (addr(3), Message::Inv(vec![InvItem::Block(hash)])),
+            ])
+        );
+        assert_eq!(chain.tip().hash(), hash);
+        // Mined, so no longer pooled
+        assert!(mempool.is_empty());
+
+        // A block rebuilt whole from the pool that misses its merkle root
+        mempool.insert(txs[0].clone(), 0).unwrap();
+        let next = mined_with(&block, &txs[..1]);
+        let mut tampered = CompactBlock::new(&next);
+        tampered.prefilled[0].1 = b"another coinbase".to_vec();
+        assert_eq!(
+            relay.handle(
+                addr(2),
+                Message::CmpctBlock(Box::new(tampered)),
+                &mut chain,
+                &mut mempool
+            ),
+            Ok(vec![(
+                addr(2),
+                Message::GetData(vec![InvItem::Block(next.hash())])
+            )])
+        );
+        assert_eq!(chain.height(), 1);
+    }
+}
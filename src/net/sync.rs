@@ -0,0 +1,481 @@
+//! Headers-first synchronization of a chain from a peer.
+//!
+//! A [`Synchronizer`] asks the peer for the headers after a locator of the
+//! local chain, a batch at a time, and checks each against its parent until
+//! the peer has no more. It then downloads the blocks behind the headers the
+//! chain does not have and inserts them, leaving the chain's fork choice to
+//! decide whether they take over. [`serve`] is the other side, answering a
+//! peer's requests from a local chain.
+
+use std::time::Duration;
+
+use thiserror::Error;
+
+use super::{InvItem, Message, NetError, Offense, Peer, MAX_HEADERS, MAX_LOCATOR_HASHES};
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::chain::{check_header, BlockStatus, Blockchain, ChainError};
+use crate::store::ChainStore;
+use crate::trace;
+use crate::validation::ValidationError;
+
+/// Most blocks asked for in one `GetData`
+pub const BLOCKS_PER_REQUEST: usize = 16;
+
+/// How long the peer may take to answer a request
+pub const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a synchronization fetched
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SyncProgress {
+    /// Headers received and checked, including any the chain already had
+    pub headers: usize,
+    /// Blocks downloaded and inserted
+    pub blocks: usize,
+}
+
+/// Reasons a synchronization stopped short
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SyncError {
+    /// Talking to the peer failed
+    #[error("sync failed: {0}")]
+    Net(NetError),
+    /// The peer answered a request with the wrong message
+    #[error("peer answered with an unexpected {0} message")]
+    UnexpectedMessage(&'static str),
+    /// The peer's headers build on a block neither the chain nor the headers
+    /// before them have
+    #[error("peer sent headers building on unknown block {0}")]
+    UnconnectedHeaders(BlockHash),
+    /// A header breaks the chain's rules
+    #[error("peer sent invalid header: {0}")]
+    InvalidHeader(ChainError),
+    /// The peer sent a block that was not asked for
+    #[error("peer sent block {0} that was not requested")]
+    UnrequestedBlock(BlockHash),
+    /// The chain refused a block
+    #[error("peer sent invalid block: {0}")]
+    InvalidBlock(ChainError),
+    /// The local chain could not take a block through no fault of the peer's
+    #[error("could not store synced block: {0}")]
+    Chain(ChainError),
+}
+
+impl SyncError {
+    /// Whether the peer broke the protocol or sent invalid data, rather than
+    /// the connection or the local chain failing; such a peer should be
+    /// dropped
+    pub fn is_misbehavior(&self) -> bool {
+        self.offense().is_some()
+    }
+
+    /// The peer's offense, if the failure is the peer's doing
+    pub fn offense(&self) -> Option<Offense> {
+        match self {
+            SyncError::Net(err) => err.offense(),
+            SyncError::UnexpectedMessage(_)
+            | SyncError::UnconnectedHeaders(_)
+            | SyncError::UnrequestedBlock(_) => Some(Offense::Unsolicited),
+            SyncError::InvalidHeader(err) | SyncError::InvalidBlock(err) => Some(match err {
+                ChainError::InvalidBlock(
+                    ValidationError::InsufficientProofOfWork
+                    | ValidationError::DifficultyBelowMinimum,
+                ) => Offense::InvalidProofOfWork,
+                _ => Offense::InvalidBlock,
+            }),
+            SyncError::Chain(_) => None,
+        }
+    }
+}
+
+impl From<NetError> for SyncError {
+    fn from(err: NetError) -> Self {
+        SyncError::Net(err)
+    }
+}
+
+/// Brings a local chain up to a peer's, over a connection that has been
+/// through its handshake
+pub struct Synchronizer<'a, S: ChainStore> {
+    peer: &'a mut Peer,
+    chain: &'a mut Blockchain<S>,
+    timeout: Duration,
+}
+
+impl<'a, S: ChainStore> Synchronizer<'a, S> {
+    pub fn new(peer: &'a mut Peer, chain: &'a mut Blockchain<S>) -> Self {
+        Synchronizer {
+            peer,
+            chain,
+            timeout: SYNC_TIMEOUT,
+        }
+    }
+
+    /// Wait `timeout` for each answer instead of [`SYNC_TIMEOUT`]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Fetch the peer's headers until it has no more, then the blocks behind
+    /// those the chain lacks.
+    ///
+    /// Headers on a branch off the local chain are fetched from the fork
+    /// point the peer finds in the locator. Requests the peer makes in the
+    /// meantime are answered with [`serve`]. After an error for which
+    /// [`SyncError::is_misbehavior`] holds, the blocks inserted so far stay
+    /// in the chain, but the peer should be dropped.
+    pub fn run(&mut self) -> Result<SyncProgress, SyncError> {
+        let _span = trace::span!(
+            "sync",
+            peer = self.peer.peer_addr().ok().map(tracing::field::display)
+        );
+        let mut progress = SyncProgress::default();
+        let wanted = self.fetch_headers(&mut progress)?;
+        trace::event!(
+            debug,
+            headers = progress.headers,
+            wanted = wanted.len(),
+            "headers fetched"
+        );
+        self.fetch_blocks(&wanted, &mut progress)?;
+        trace::event!(
+            debug,
+            headers = progress.headers,
+            blocks = progress.blocks,
+            "synchronized"
+        );
+        Ok(progress)
+    }
+
+    /// The checked headers whose blocks the chain does not have, in order
+    fn fetch_headers(
+        &mut self,
+        progress: &mut SyncProgress,
+    ) -> Result<Vec<(BlockHash, BlockHeader)>, SyncError> {
+        let mut wanted: Vec<(BlockHash, BlockHeader)> = Vec::new();
+        let mut last: Option<(BlockHash, BlockHeader)> = None;
+        loop {
+            // Lead with the last header received, so the peer carries on from it
+            let mut locator = self.chain.locator();
+            if let Some((hash, _)) = &last {
+                locator.insert(0, *hash);
+            }
+            locator.truncate(MAX_LOCATOR_HASHES);
+            self.peer.send(&Message::GetHeaders {
+                locator,
+                stop: None,
+            })?;
+            let headers = match self.await_reply()? {
+                Message::Headers(headers) => headers,
+                other => return Err(SyncError::UnexpectedMessage(other.command())),
+            };
+            let Some(first) = headers.first() else {
+                return Ok(wanted);
+            };
+
+            let prev = first.prev_block_hash();
+            let mut parent = match last.take() {
+                Some(last) if last.0 == prev => last,
+                _ => {
+                    // The peer's chain leaves ours, or the headers it sent
+                    // before, lower down: drop what it no longer builds on
+                    match wanted.iter().position(|(hash, _)| *hash == prev) {
+                        Some(index) => wanted.truncate(index + 1),
+                        None => wanted.clear(),
+                    }
+                    let header = match wanted.last() {
+                        Some((_, header)) => Some(header.clone()),
+                        None => known_header(self.chain, &prev),
+                    };
+                    (prev, header.ok_or(SyncError::UnconnectedHeaders(prev))?)
+                }
+            };
+
+            let full = headers.len() == MAX_HEADERS;
+            progress.headers += headers.len();
+            for header in headers {
+                let hash = check_header(self.chain.params(), &parent.1, parent.0, &header)
+                    .map_err(SyncError::InvalidHeader)?;
+                match self.chain.status_of(hash.as_ref()) {
+                    None => wanted.push((hash, header.clone())),
+                    Some(BlockStatus::Invalid) => {
+                        return Err(SyncError::InvalidHeader(ChainError::MarkedInvalid(hash)))
+                    }
+                    Some(_) => {}
+                }
+                parent = (hash, header);
+            }
+            if !full {
+                return Ok(wanted);
+            }
+            last = Some(parent);
+        }
+    }
+
+    fn fetch_blocks(
+        &mut self,
+        wanted: &[(BlockHash, BlockHeader)],
+        progress: &mut SyncProgress,
+    ) -> Result<(), SyncError> {
+        for batch in wanted.chunks(BLOCKS_PER_REQUEST) {
+            let mut missing: Vec<BlockHash> = batch.iter().map(|(hash, _)| *hash).collect();
+            self.peer.send(&Message::GetData(
+                missing.iter().copied().map(InvItem::Block).collect(),
+            ))?;
+            while !missing.is_empty() {
+                let block = match self.await_reply()? {
+                    Message::BlockMsg(block) => *block,
+                    other => return Err(SyncError::UnexpectedMessage(other.command())),
+                };
+                let hash = block.hash();
+                let Some(index) = missing.iter().position(|wanted| *wanted == hash) else {
+                    return Err(SyncError::UnrequestedBlock(hash));
+                };
+                missing.remove(index);
+                match self.chain.insert(block) {
+                    // An orphan connects once its parent arrives
+                    Ok(_) | Err(ChainError::DuplicateBlock(_) | ChainError::UnknownParent(_)) => {
+                        progress.blocks += 1
+                    }
+                    Err(err @ (ChainError::Store(_) | ChainError::ReorgTooDeep { .. })) => {
+                        return Err(SyncError::Chain(err))
+                    }
+                    Err(err) => return Err(SyncError::InvalidBlock(err)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The next message that is not a request of the peer's own, answering
+    /// those along the way
+    fn await_reply(&mut self) -> Result<Message, SyncError> {
+        loop {
+            let message = self.peer.recv_timeout(self.timeout)?;
+            match message {
+                Message::Ping(_) | Message::GetHeaders { .. } | Message::GetData(_) => {
+                    for reply in serve(self.chain, &message) {
+                        self.peer.send(&reply)?;
+                    }
+                }
+                Message::Inv(_) | Message::Pong(_) => {}
+                message => return Ok(message),
+            }
+        }
+    }
+}
+
+/// The header of the block `hash` if `chain` has it, on any branch
+pub(super) fn known_header<S: ChainStore>(
+    chain: &Blockchain<S>,
+    hash: &BlockHash,
+) -> Option<BlockHeader> {
+    match chain.height_of(hash.as_ref()) {
+        Some(height) => chain.header(height).cloned(),
+        None => Some(chain.tree().get(hash)?.block().header().clone()),
+    }
+}
+
+/// The replies to `request` from `chain`: the headers after a `GetHeaders`
+/// locator, each block a `GetData` asks for that the chain has, on any
+/// branch, the transactions a `GetBlockTxn` asks for if the chain has the
+/// block and all of them, and a `Pong` for a `Ping`. Other messages get none.
+pub fn serve<S: ChainStore>(chain: &Blockchain<S>, request: &Message) -> Vec<Message> {
+    match request {
+        Message::GetHeaders { locator, stop } => {
+            vec![Message::Headers(chain.headers_after(
+                locator,
+                *stop,
+                MAX_HEADERS,
+            ))]
+        }
+        Message::GetData(items) => items
+            .iter()
+            .filter_map(|item| match item {
+                InvItem::Block(hash) => find_block(chain, hash),
+                InvItem::Tx(_) => None,
+            })
+            .map(|block| Message::BlockMsg(Box::new(block.clone())))
+            .collect(),
+        Message::GetBlockTxn { block, indexes } => {
+            let Some(found) = find_block(chain, block) else {
+                return Vec::new();
+            };
+            let transactions: Option<Vec<Vec<u8>>> = indexes
+                .iter()
+                .map(|index| found.transactions().get(*index).cloned())
+                .collect();
+            transactions
+                .map(|transactions| Message::BlockTxn {
+                    block: *block,
+                    transactions,
+                })
+                .into_iter()
+                .collect()
+        }
+        Message::Ping(nonce) => vec![Message::Pong(*nonce)],
+        _ => Vec::new(),
+    }
+}
+
+pub(super) fn find_block<'a, S: ChainStore>(
+    chain: &'a Blockchain<S>,
+    hash: &BlockHash,
+) -> Option<&'a Block> {
+    match chain.get_by_hash(hash.as_ref()) {
+        Some(block) => Some(block),
+        None => Some(chain.tree().get(hash)?.block()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{connected_pair, version};
+    use super::*;
+    use crate::params::ChainParams;
+    use crate::validation::ValidationError;
+    use std::thread;
+
+    fn child(parent: &Block, tag: &[u8]) -> Block {
+        let difficulty = ChainParams::test_defaults().initial_difficulty;
+        let mut block = parent
+            .next_builder()
+            .transaction(tag.to_vec())
+            .difficulty(difficulty)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(difficulty);
+        block
+    }
+
+    fn chain_of(len: usize) -> Blockchain {
+        let mut chain = Blockchain::new_from_params(&ChainParams::test_defaults());
+        for i in 1..len {
+            let block = child(chain.tip(), &(i as u64).to_le_bytes());
+            chain.append(block).unwrap();
+        }
+        chain
+    }
+
+    /// Serve `chain` to the other end of `peer` until it hangs up
+    fn serve_until_closed(mut peer: Peer, chain: Blockchain) -> Blockchain {
+        peer.handshake(&version(2)).unwrap();
+        loop {
+            match peer.recv() {
+                Ok(request) => {
+                    for reply in serve(&chain, &request) {
+                        peer.send(&reply).unwrap();
+                    }
+                }
+                Err(NetError::Closed) => return chain,
+                Err(err) => panic!("serving failed: {}", err),
+            }
+        }
+    }
+
+    #[test]
+    fn test_syncs_empty_chain_from_peer() {
+        let (mut peer, remote) = connected_pair();
+        let server = thread::spawn(move || serve_until_closed(remote, chain_of(501)));
+
+        let mut chain = Blockchain::new_from_params(&ChainParams::test_defaults());
+        peer.handshake(&version(1)).unwrap();
+        let progress = Synchronizer::new(&mut peer, &mut chain).run().unwrap();
+        assert_eq!(
+            progress,
+            SyncProgress {
+                headers: 500,
+                blocks: 500
+            }
+        );
+
+        // Caught up, the peer has nothing more to send
+        let progress = Synchronizer::new(&mut peer, &mut chain).run().unwrap();
+        assert_eq!(progress, SyncProgress::default());
+        drop(peer);
+        let served = server.join().unwrap();
+        assert_eq!(chain.height(), 500);
+        assert_eq!(chain.tip().hash(), served.tip().hash());
+    }
+
+    #[test]
+    fn test_syncs_across_batches_and_onto_peer_fork() {
+        // We share 100 blocks with the peer, then hold 20 of our own against
+        // its 2100, which take more than one batch of headers
+        let shared = chain_of(101);
+        let mut ours = Blockchain::new_from_params(&ChainParams::test_defaults());
+        let mut theirs = Blockchain::new_from_params(&ChainParams::test_defaults());
+        for block in shared.range(1..).unwrap() {
+            ours.append(block.clone()).unwrap();
+            theirs.append(block.clone()).unwrap();
+        }
+        for i in 0..20u8 {
+            ours.append(child(ours.tip(), &[b'o', i])).unwrap();
+        }
+        for i in 0..2100u64 {
+            theirs
+                .append(child(theirs.tip(), &i.to_be_bytes()))
+                .unwrap();
+        }
+        let (mut peer, remote) = connected_pair();
+        let server = thread::spawn(move || serve_until_closed(remote, theirs));
+
+        peer.handshake(&version(1)).unwrap();
+        let progress = Synchronizer::new(&mut peer, &mut ours).run().unwrap();
+        assert!(progress.headers > MAX_HEADERS);
+        assert_eq!(progress.blocks, 2100);
+        drop(peer);
+        let theirs = server.join().unwrap();
+        assert_eq!(ours.height(), 2200);
+        assert_eq!(ours.tip().hash(), theirs.tip().hash());
+        assert_eq!(
+            ours.status_of(shared.tip().hash().as_ref()),
+            Some(BlockStatus::Active)
+        );
+    }
+
+    #[test]
+    fn test_aborts_on_invalid_headers() {
+        let (mut peer, mut remote) = connected_pair();
+        let genesis = ChainParams::test_defaults().genesis_block();
+        let good = child(&genesis, b"good");
+        let bad = good
+            .next_builder()
+            .version(99)
+            .transaction(b"bad".to_vec())
+            .timestamp(good.timestamp() + 10)
+            .build()
+            .unwrap();
+        let (good, bad) = (good.header().clone(), bad.header().clone());
+        let server = thread::spawn(move || {
+            remote.handshake(&version(2)).unwrap();
+            assert!(matches!(remote.recv(), Ok(Message::GetHeaders { .. })));
+            remote.send(&Message::Headers(vec![good, bad])).unwrap();
+            assert!(matches!(remote.recv(), Ok(Message::GetHeaders { .. })));
+            // Headers that build on nothing we have
+            remote
+                .send(&Message::Headers(vec![genesis.header().clone()]))
+                .unwrap();
+        });
+
+        let mut chain = Blockchain::new_from_params(&ChainParams::test_defaults());
+        peer.handshake(&version(1)).unwrap();
+        let err = Synchronizer::new(&mut peer, &mut chain).run().unwrap_err();
+        assert_eq!(
+            err,
+            SyncError::InvalidHeader(ChainError::InvalidBlock(
+                ValidationError::UnsupportedVersion(99)
+            ))
+        );
+        assert_eq!(err.offense(), Some(Offense::InvalidBlock));
+        assert_eq!(chain.height(), 0);
+
+        let err = Synchronizer::new(&mut peer, &mut chain).run().unwrap_err();
+        assert_eq!(err, SyncError::UnconnectedHeaders(BlockHash::ZERO));
+        assert_eq!(err.offense(), Some(Offense::Unsolicited));
+        server.join().unwrap();
+
+        assert!(!SyncError::Net(NetError::TimedOut).is_misbehavior());
+    }
+}
@@ -0,0 +1,485 @@
+//! A whole node: the chain, the mempool, the network and, if configured, a
+//! miner, started and stopped together.
+//!
+//! [`Node::new`] opens the chain with its unspent outputs and an empty
+//! mempool. [`Node::start`] hands both to a [`net::Node`] listening for
+//! peers, connects to the configured ones and sets the miner going;
+//! [`Node::stop`] stops them all and takes the chain and the mempool back,
+//! so the node can be started again.
+//!
+//! The miner builds a block on the tip from the transactions
+//! [`Mempool::select_for_block`] picks, with a coinbase paying the subsidy
+//! and their fees, and tries nonces a round at a time. Between rounds it
+//! checks the tip, starting over on the new one if another block arrived.
+//! A block it finds goes into the chain and out to every peer as one passed
+//! to [`net::Node::submit_block`] does. It never stamps a block ahead of the
+//! clock, so at a trivial difficulty it waits for the clock to pass the
+//! median time past rather than racing ahead of it.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+
+use crate::address::Address;
+use crate::block::{Block, BlockHash, BlockLimits};
+use crate::chain::{Blockchain, ChainError};
+use crate::mempool::Mempool;
+use crate::net::{self, NodeError};
+use crate::params::ChainParams;
+use crate::store::{ChainStore, MemoryStore};
+use crate::transaction::{Transaction, TxOutput, Txid};
+
+/// Nonces a miner tries between checks of the tip unless configured
+/// otherwise
+pub const DEFAULT_NONCES_PER_ROUND: u64 = 100_000;
+
+/// How long a miner waits to try again when the clock has not yet passed
+/// the median time past
+const MINER_RETRY: Duration = Duration::from_millis(100);
+
+/// How a [`Node`] mines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MinerConfig {
+    /// Paid the subsidy and the fees of each block mined
+    pub payout: Address,
+    /// Nonces tried between checks of the tip; must not be zero
+    pub nonces_per_round: u64,
+}
+
+impl MinerConfig {
+    /// Mine paying `payout`, checking the tip every
+    /// [`DEFAULT_NONCES_PER_ROUND`] nonces
+    pub fn new(payout: Address) -> Self {
+        MinerConfig {
+            payout,
+            nonces_per_round: DEFAULT_NONCES_PER_ROUND,
+        }
+    }
+}
+
+/// How a [`Node`] runs
+#[derive(Clone, Debug)]
+pub struct NodeConfig {
+    /// Where to listen for peers; port 0 picks a free one
+    pub listen: SocketAddr,
+    /// Peers to connect to on starting
+    pub peers: Vec<SocketAddr>,
+    /// How to mine, if at all
+    pub miner: Option<MinerConfig>,
+    /// How the network side runs
+    pub net: net::NodeConfig,
+}
+
+impl NodeConfig {
+    /// Listen on `listen`, connecting to no one and not mining
+    pub fn new(listen: SocketAddr) -> Self {
+        NodeConfig {
+            listen,
+            peers: Vec::new(),
+            miner: None,
+            net: net::NodeConfig::default(),
+        }
+    }
+
+    /// Connect to `peers` on starting
+    pub fn with_peers(mut self, peers: Vec<SocketAddr>) -> Self {
+        self.peers = peers;
+        self
+    }
+
+    /// Mine as `miner` says
+    pub fn with_miner(mut self, miner: MinerConfig) -> Self {
+        self.miner = Some(miner);
+        self
+    }
+}
+
+/// The miner's task and the token that stops it
+struct Miner {
+    stop: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Miner {
+    fn drop(&mut self) {
+        self.stop.cancel();
+    }
+}
+
+/// A node and what it runs once started
+struct Running<S: ChainStore> {
+    /// Shared with the miner, which lets go of it on stopping
+    net: Arc<net::Node<S>>,
+    miner: Option<Miner>,
+}
+
+/// A node, running or stopped, and the handle for asking things of it.
+///
+/// Dropping a running node stops the miner and shuts the network down
+/// without waiting for either.
+pub struct Node<S: ChainStore = MemoryStore> {
+    config: NodeConfig,
+    /// The chain and the mempool while stopped
+    parts: Option<(Blockchain<S>, Mempool)>,
+    running: Option<Running<S>>,
+}
+
+impl Node {
+    /// A stopped node with a chain in memory for `params`
+    pub fn new(params: ChainParams, config: NodeConfig) -> Result<Self, ChainError> {
+        Self::with_store(params, MemoryStore::new(), config)
+    }
+}
+
+impl<S: ChainStore + Send + 'static> Node<S> {
+    /// A stopped node with the chain for `params` kept in `store`, picking
+    /// up where the store left off, and tracking its unspent outputs
+    pub fn with_store(
+        params: ChainParams,
+        store: S,
+        config: NodeConfig,
+    ) -> Result<Self, ChainError> {
+        let chain = Blockchain::open(&params, store)?.with_utxo_set()?;
+        let mut mempool = Mempool::new().with_clock(params.clock.clone());
+        mempool.set_tip_height(chain.height());
+        Ok(Node {
+            config,
+            parts: Some((chain, mempool)),
+            running: None,
+        })
+    }
+
+    /// Listen for peers, connect to the configured ones and start mining if
+    /// configured to. A peer that cannot be reached is skipped. Fails,
+    /// leaving the node stopped, if the listener cannot be bound or the
+    /// address book file cannot be read; does nothing if running already.
+    #[allow(clippy::expect_used)]
+    pub async fn start(&mut self) -> io::Result<()> {
+        if self.running.is_some() {
+            return Ok(());
+        }
+        let listener = TcpListener::bind(self.config.listen).await?;
+        let book = net::load_addr_book(&self.config.net)?;
+        let (chain, mempool) = self.parts.take().expect("a stopped node holds its parts");
+        let net = Arc::new(net::Node::spawn(
+            listener,
+            book,
+            chain,
+            mempool,
+            self.config.net.clone(),
+        )?);
+        for peer in &self.config.peers {
+            let _ = net.connect(*peer).await;
+        }
+        let miner = self.config.miner.map(|config| {
+            let stop = CancellationToken::new();
+            let task = tokio::spawn(mine(net.clone(), config, stop.clone()));
+            Miner { stop, task }
+        });
+        self.running = Some(Running { net, miner });
+        Ok(())
+    }
+
+    /// Stop the miner, then close every connection and take the chain and
+    /// the mempool back, as [`net::Node::shutdown`] does. Does nothing if
+    /// stopped already.
+    pub async fn stop(&mut self) {
+        let Some(Running { net, miner }) = self.running.take() else {
+            return;
+        };
+        if let Some(mut miner) = miner {
+            miner.stop.cancel();
+            if let Err(err) = (&mut miner.task).await {
+                if err.is_panic() {
+                    std::panic::resume_unwind(err.into_panic());
+                }
+            }
+        }
+        let Ok(net) = Arc::try_unwrap(net) else {
+            unreachable!("the miner has let go of the node");
+        };
+        self.parts = Some(net.shutdown().await);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// The network side while running, for connecting to peers and asking
+    /// about them
+    pub fn net(&self) -> Option<&net::Node<S>> {
+        Some(&self.running.as_ref()?.net)
+    }
+
+    /// The address the node listens on while running
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        Some(self.net()?.local_addr())
+    }
+
+    /// Add `tx` to the mempool and announce it, as
+    /// [`net::Node::submit_transaction`] does. Fails with
+    /// [`NodeError::Stopped`] while stopped.
+    pub async fn submit_transaction(&self, tx: Transaction) -> Result<Txid, NodeError> {
+        self.net()
+            .ok_or(NodeError::Stopped)?
+            .submit_transaction(tx)
+            .await
+    }
+
+    /// Run `f` on the chain and the mempool, between the network's other
+    /// work while running
+    pub async fn query<R, F>(&self, f: F) -> Result<R, NodeError>
+    where
+        R: Send + 'static,
+        F: FnOnce(&Blockchain<S>, &Mempool) -> R + Send + 'static,
+    {
+        match (&self.running, &self.parts) {
+            (Some(running), _) => running.net.query(f).await,
+            (None, Some((chain, mempool))) => Ok(f(chain, mempool)),
+            (None, None) => Err(NodeError::Stopped),
+        }
+    }
+
+    /// The height and hash of the tip
+    pub async fn tip(&self) -> Result<(u64, BlockHash), NodeError> {
+        self.query(|chain, _| (chain.height(), chain.tip().hash()))
+            .await
+    }
+}
+
+/// Mine on `node`'s tip as `config` says until `stop` is cancelled or the
+/// node shuts down
+async fn mine<S: ChainStore + Send + 'static>(
+    node: Arc<net::Node<S>>,
+    config: MinerConfig,
+    stop: CancellationToken,
+) {
+    loop {
+        let template = tokio::select! {
+            _ = stop.cancelled() => return,
+            template = node.query(move |chain, mempool| template(chain, mempool, config.payout)) => template,
+        };
+        let mut block = match template {
+            Ok(Some(block)) => block,
+            Ok(None) => {
+                tokio::select! {
+                    _ = stop.cancelled() => return,
+                    _ = sleep(MINER_RETRY) => continue,
+                }
+            }
+            Err(_) => return,
+        };
+        let (tip, difficulty) = (block.prev_block_hash(), block.difficulty());
+        loop {
+            let round = tokio::task::spawn_blocking(move || {
+                let found = block.mine_up_to(difficulty, config.nonces_per_round);
+                (block, found)
+            });
+            let found;
+            (block, found) = match round.await {
+                Ok(round) => round,
+                Err(err) => std::panic::resume_unwind(err.into_panic()),
+            };
+            if stop.is_cancelled() {
+                return;
+            }
+            if found {
+                // Refused only if stale, and the next template is on the
+                // tip that beat it
+                let _ = node.submit_block(block).await;
+                break;
+            }
+            match node.query(|chain, _| chain.tip().hash()).await {
+                Ok(hash) if hash == tip => {}
+                Ok(_) => break,
+                Err(_) => return,
+            }
+        }
+    }
+}
+
+/// An unmined block on `chain`'s tip stamped with the clock's time,
+/// holding a coinbase paying `payout` the subsidy and the fees of the
+/// transactions `mempool` picks for it, then those transactions. None if
+/// the chain would not take a block stamped now.
+fn template<S: ChainStore>(
+    chain: &Blockchain<S>,
+    mempool: &Mempool,
+    payout: Address,
+) -> Option<Block> {
+    let params = chain.params();
+    let timestamp = params.clock.now();
+    // A chain whose store fails to read the headers cannot be built on
+    let median_time_past = chain.median_time_past().ok()?;
+    let difficulty = chain.next_block_difficulty().ok()?;
+    if timestamp <= median_time_past
+        || !params
+            .timestamp_rule
+            .allows(chain.tip().timestamp(), timestamp)
+    {
+        return None;
+    }
+    let height = chain.height() + 1;
+    // The lock time tells coinbases paying the same apart, and is not
+    // enforced on them
+    let coinbase = |amount| Transaction {
+        outputs: vec![TxOutput::to_address(amount, payout)],
+        lock_time: height as u32,
+        ..Transaction::default()
+    };
+    // Leave room for the coinbase at its largest
+    let limits = params.block_limits;
+    let room = BlockLimits {
+        max_bytes: limits
+            .max_bytes
+            .saturating_sub(coinbase(u64::MAX).encode().len()),
+        max_transactions: limits.max_transactions.saturating_sub(1),
+    };
+    let txs = mempool.select_for_block(&room);
+    let fees = txs
+        .iter()
+        .filter_map(|tx| mempool.fee(&tx.txid()))
+        .fold(0u64, u64::saturating_add);
+    let coinbase = coinbase(params.subsidy_at(height).saturating_add(fees));
+    // The coinbase leads every template, so building never fails
+    chain
+        .tip()
+        .next_builder()
+        .transactions(std::iter::once(coinbase).chain(txs).map(|tx| tx.encode()))
+        .difficulty(difficulty)
+        .timestamp(timestamp)
+        .build()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::testing::signed_spend;
+    use crate::transaction::OutPoint;
+    use std::collections::BTreeSet;
+    use tokio::time::{sleep, timeout};
+
+    /// The height of the active block holding `txid`, if any
+    fn mined_at(chain: &Blockchain, txid: &Txid) -> Option<u64> {
+        let mut blocks = chain.iter();
+        let found =
+            blocks.position(|block| block.transactions().iter().any(|tx| Txid::of(tx) == *txid))?;
+        Some(found as u64)
+    }
+
+    /// The tip and the pooled txids of `node`
+    async fn state(node: &Node) -> (BlockHash, BTreeSet<Txid>) {
+        node.query(|chain, mempool| {
+            let pooled = mempool.iter().map(|(txid, _)| *txid).collect();
+            (chain.tip().hash(), pooled)
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mined_blocks_reach_the_other_node() {
+        let funding = signed_spend(&[], &[1000]);
+        let params = ChainParams {
+            genesis_transactions: vec![funding.encode()],
+            ..ChainParams::test_defaults()
+        };
+        let listen: SocketAddr = "127.0.0.1:0".parse().unwrap();
+        let mut b = Node::new(params.clone(), NodeConfig::new(listen)).unwrap();
+        b.start().await.unwrap();
+        let payout = Address::from_bytes([9; 32]);
+        let miner = MinerConfig {
+            payout,
+            nonces_per_round: 4,
+        };
+        let config = NodeConfig::new(listen)
+            .with_peers(vec![b.local_addr().unwrap()])
+            .with_miner(miner);
+        let mut a = Node::new(params.clone(), config).unwrap();
+        a.start().await.unwrap();
+        assert_eq!(a.net().unwrap().peer_info().await.unwrap().len(), 1);
+
+        // A spend sent to B reaches A's pool and a block A mines, which
+        // comes back to B and takes it out of B's pool
+        let spend = signed_spend(
+            &[OutPoint {
+                txid: funding.txid(),
+                index: 0,
+            }],
+            &[900],
+        );
+        // Neither without a signature nor signed by another key
+        let mut unsigned = spend.clone();
+        unsigned.inputs[0].signatures.clear();
+        let mut stolen = spend.clone();
+        stolen.sign_input(0, &SigningKey::from_bytes(&[8; 32]));
+        for tx in [unsigned, stolen] {
+            assert!(matches!(
+                b.submit_transaction(tx).await,
+                Err(NodeError::Rejected(_))
+            ));
+        }
+        let txid = b.submit_transaction(spend).await.unwrap();
+        let unfunded = signed_spend(
+            &[OutPoint {
+                txid: Txid::of(b"nothing"),
+                index: 0,
+            }],
+            &[1],
+        );
+        assert!(matches!(
+            b.submit_transaction(unfunded).await,
+            Err(NodeError::Rejected(_))
+        ));
+        let converged = async {
+            loop {
+                // Mined at B by the time the states are taken
+                let mined = b
+                    .query(move |chain, _| mined_at(chain, &txid).is_some())
+                    .await
+                    .unwrap();
+                let (a_state, b_state) = (state(&a).await, state(&b).await);
+                if mined && a_state == b_state {
+                    return b_state;
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+        };
+        let (_, pooled) = timeout(Duration::from_secs(10), converged)
+            .await
+            .expect("B caught up with A");
+        assert!(pooled.is_empty());
+
+        a.stop().await;
+        assert!(!a.is_running());
+        assert_eq!(a.local_addr(), None);
+        b.stop().await;
+
+        // The block holding the spend pays its fee to the miner
+        let (height, coinbase) = b
+            .query(move |chain, _| {
+                let height = mined_at(chain, &txid).unwrap();
+                let coinbase = &chain.get(height).unwrap().transactions()[0];
+                (height, Transaction::decode_from_block(coinbase).unwrap())
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            coinbase.outputs,
+            vec![TxOutput::to_address(
+                params.subsidy_at(height) + 100,
+                payout
+            )]
+        );
+        let (tip, _) = a.tip().await.unwrap();
+        assert!(tip >= height);
+    }
+}
@@ -0,0 +1,526 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::block::{Block, BlockBuilder, BlockHash, BlockLimits};
+use crate::crypto::PublicKey;
+use crate::difficulty::Difficulty;
+
+/// How blocks on a chain are sealed
+#[derive(Clone, Debug)]
+pub enum ConsensusMode {
+    /// Blocks must be mined to meet `difficulty`
+    ProofOfWork { difficulty: Difficulty },
+    /// Blocks must be signed by one of the listed authorities.
+    ///
+    /// With a nonzero `step_duration`, time is cut into slots of that many
+    /// seconds, and the authorities take turns in listed order: the block
+    /// stamped in a slot is expected from its
+    /// [`expected_signer`](ConsensusMode::expected_signer). An in-turn
+    /// block commits to twice the work of an out-of-turn one, and each block
+    /// must be stamped in a later slot than its parent, so no authority signs
+    /// more than one block per slot and a chain signed in turn wins fork
+    /// choice. Up to `skew_tolerance` seconds of
+    /// clock skew either side of a slot still count as in turn. Without a
+    /// rotation every block commits to the work of an in-turn one.
+    ProofOfAuthority {
+        authorities: Vec<PublicKey>,
+        step_duration: u64,
+        skew_tolerance: u64,
+    },
+}
+
+impl ConsensusMode {
+    /// The slot `timestamp` falls in, on a proof-of-authority chain with a
+    /// signing rotation
+    pub fn slot(&self, timestamp: u64) -> Option<u64> {
+        match self {
+            ConsensusMode::ProofOfAuthority {
+                authorities,
+                step_duration,
+                ..
+            } if *step_duration != 0 && !authorities.is_empty() => Some(timestamp / step_duration),
+            _ => None,
+        }
+    }
+
+    /// The authority whose turn it is at `timestamp`, on a proof-of-authority
+    /// chain with a signing rotation
+    pub fn expected_signer(&self, timestamp: u64) -> Option<&PublicKey> {
+        let ConsensusMode::ProofOfAuthority { authorities, .. } = self else {
+            return None;
+        };
+        let slot = self.slot(timestamp)?;
+        Some(&authorities[(slot % authorities.len() as u64) as usize])
+    }
+
+    /// Whether `block` is signed by an authority whose turn it is at the
+    /// block's timestamp, give or take the skew tolerance; `None` without a
+    /// signing rotation
+    pub fn is_in_turn(&self, block: &Block) -> Option<bool> {
+        let ConsensusMode::ProofOfAuthority {
+            authorities,
+            step_duration,
+            skew_tolerance,
+        } = self
+        else {
+            return None;
+        };
+        if *step_duration == 0 || authorities.is_empty() {
+            return None;
+        }
+        let first = block.timestamp().saturating_sub(*skew_tolerance) / step_duration;
+        let last = block.timestamp().saturating_add(*skew_tolerance) / step_duration;
+        // A window spanning every slot lets any authority sign in turn
+        let slots = (last - first).min(authorities.len() as u64 - 1);
+        let signers: Vec<PublicKey> = (first..=first + slots)
+            .filter_map(|slot| self.expected_signer(slot * step_duration).cloned())
+            .collect();
+        Some(block.verify_signature(&signers))
+    }
+
+    /// The difficulty a block on a chain with a signing rotation commits to:
+    /// work 2 in turn and 1 out of turn
+    pub fn authority_difficulty(in_turn: bool) -> Difficulty {
+        let bits = if in_turn { 1 } else { 0 };
+        Difficulty::from_target(&Difficulty::LeadingZeroBits(bits).to_target())
+    }
+
+    /// The difficulty a block on a proof-of-authority chain must commit to,
+    /// since fork choice weighs it by that: as
+    /// [`authority_difficulty`](ConsensusMode::authority_difficulty) gives
+    /// for whether `block` is in turn, or that of an in-turn block without a
+    /// signing rotation. `None` on a proof-of-work chain, and under a
+    /// rotation when the block's body, and so its signature, is unknown.
+    pub fn required_authority_difficulty(&self, block: Option<&Block>) -> Option<Difficulty> {
+        if let ConsensusMode::ProofOfWork { .. } = self {
+            return None;
+        }
+        if self.expected_signer(0).is_none() {
+            return Some(Self::authority_difficulty(true));
+        }
+        let in_turn = self.is_in_turn(block?)?;
+        Some(Self::authority_difficulty(in_turn))
+    }
+}
+
+/// Constraints on a block's timestamp relative to its parent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimestampRule {
+    /// Timestamps are not checked
+    Any,
+    /// A block may not be older than its parent
+    NonDecreasing,
+    /// A block must be strictly newer than its parent
+    StrictlyIncreasing,
+}
+
+impl TimestampRule {
+    /// Whether a block stamped `timestamp` may follow one stamped `parent`
+    pub fn allows(&self, parent: u64, timestamp: u64) -> bool {
+        match self {
+            TimestampRule::Any => true,
+            TimestampRule::NonDecreasing => timestamp >= parent,
+            TimestampRule::StrictlyIncreasing => timestamp > parent,
+        }
+    }
+}
+
+/// A source of the current time, against which new blocks' timestamps are checked
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// Seconds since the Unix epoch
+    fn now(&self) -> u64;
+}
+
+/// The system's wall clock, reading 0 while it is set before the Unix epoch
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+}
+
+/// A clock stopped at the given Unix time, for deterministic tests
+#[derive(Clone, Copy, Debug)]
+pub struct FixedClock(pub u64);
+
+impl Clock for FixedClock {
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Consensus parameters shared by every node on a chain.
+///
+/// Everything needed to derive the genesis block and validate the blocks
+/// after it lives here, so two nodes with equal params agree on the chain.
+#[derive(Clone, Debug)]
+pub struct ChainParams {
+    /// Transactions carried by the genesis block
+    pub genesis_transactions: Vec<Vec<u8>>,
+    /// Timestamp committed in the genesis header
+    pub genesis_timestamp: u64,
+    /// Nonce the genesis block is mined from; a nonce that already meets
+    /// `initial_difficulty` makes deriving the genesis block instant
+    pub genesis_nonce: u64,
+    /// Difficulty committed in the genesis header, where retargeting starts
+    pub initial_difficulty: Difficulty,
+    /// The sealing rule blocks must satisfy
+    pub consensus_mode: ConsensusMode,
+    /// How each block's timestamp must relate to its parent's
+    pub timestamp_rule: TimestampRule,
+    /// Number of recent blocks whose median timestamp a new block must be
+    /// newer than; zero disables the check
+    pub median_time_span: usize,
+    /// How far past `clock`'s time a new block's timestamp may be; `None`
+    /// disables the check
+    pub max_future_drift: Option<Duration>,
+    /// The node's view of the current time. Unlike the other fields it is
+    /// local to each node, and only checked when a block arrives
+    pub clock: Arc<dyn Clock>,
+    /// Intended time between blocks
+    pub target_block_time: Duration,
+    /// Number of blocks between difficulty adjustments; zero disables retargeting
+    pub retarget_interval: u64,
+    /// Largest factor the target may move by in a single adjustment, either way
+    pub max_adjustment: f64,
+    /// Size limits every block must respect
+    pub block_limits: BlockLimits,
+    /// Header versions blocks may use; the genesis block uses the lowest
+    pub allowed_versions: RangeInclusive<u32>,
+    /// Known-good block hashes by height; no chain contradicting them is accepted
+    pub checkpoints: BTreeMap<u64, BlockHash>,
+    /// Skip proof-of-work and signature checks up to the highest checkpoint
+    /// during full-chain validation
+    pub trust_checkpoints: bool,
+    /// Keys that may co-sign further checkpoints at runtime; see
+    /// [`Blockchain::add_certified_checkpoint`](crate::chain::Blockchain::add_certified_checkpoint)
+    pub checkpoint_committee: Vec<PublicKey>,
+    /// Number of committee signatures a certified checkpoint needs; zero
+    /// refuses every certificate
+    pub checkpoint_threshold: usize,
+    /// New coins a block's coinbase may create before the first halving
+    pub initial_subsidy: u64,
+    /// Number of blocks between halvings of the subsidy; zero disables halving
+    pub halving_interval: u64,
+    /// Number of blocks after a coinbase before its outputs may be spent, on
+    /// chains tracking unspent outputs
+    pub coinbase_maturity: u64,
+    /// Human-readable part of the chain's bech32 addresses, telling them
+    /// apart from other networks'; see [`Address::to_bech32`](crate::address::Address::to_bech32)
+    pub address_hrp: String,
+}
+
+impl ChainParams {
+    /// Parameters for the main network: ten-minute blocks retargeted every
+    /// 2016 blocks, starting at 20 leading zero bits
+    pub fn mainnet_defaults() -> Self {
+        ChainParams {
+            genesis_transactions: vec![b"Aarwyn chain genesis".to_vec()],
+            genesis_timestamp: 1_700_000_000,
+            genesis_nonce: MAINNET_GENESIS_NONCE,
+            initial_difficulty: Difficulty::LeadingZeroBits(20),
+            consensus_mode: ConsensusMode::ProofOfWork {
+                difficulty: Difficulty::LeadingZeroBits(20),
+            },
+            timestamp_rule: TimestampRule::NonDecreasing,
+            median_time_span: 11,
+            max_future_drift: Some(Duration::from_secs(2 * 60 * 60)),
+            clock: Arc::new(SystemClock),
+            target_block_time: Duration::from_secs(600),
+            retarget_interval: 2016,
+            max_adjustment: 4.0,
+            block_limits: BlockLimits {
+                max_bytes: 1024 * 1024,
+                max_transactions: 10_000,
+            },
+            allowed_versions: 1..=1,
+            checkpoints: BTreeMap::new(),
+            trust_checkpoints: false,
+            checkpoint_committee: Vec::new(),
+            checkpoint_threshold: 0,
+            initial_subsidy: 50 * COIN,
+            halving_interval: 210_000,
+            coinbase_maturity: 100,
+            address_hrp: "arw".to_string(),
+        }
+    }
+
+    /// Parameters for tests: a minimum difficulty of four leading zero bits
+    /// so blocks mine in a handful of hashes, no retargeting, and a subsidy
+    /// halving every 150 blocks
+    pub fn test_defaults() -> Self {
+        ChainParams {
+            genesis_transactions: vec![b"Aarwyn test genesis".to_vec()],
+            genesis_timestamp: 1_700_000_000,
+            genesis_nonce: 0,
+            initial_difficulty: Difficulty::LeadingZeroBits(4),
+            consensus_mode: ConsensusMode::ProofOfWork {
+                difficulty: Difficulty::LeadingZeroBits(4),
+            },
+            timestamp_rule: TimestampRule::NonDecreasing,
+            median_time_span: 11,
+            max_future_drift: Some(Duration::from_secs(2 * 60 * 60)),
+            clock: Arc::new(SystemClock),
+            target_block_time: Duration::from_secs(1),
+            retarget_interval: 0,
+            max_adjustment: 4.0,
+            block_limits: BlockLimits {
+                max_bytes: 1024 * 1024,
+                max_transactions: 10_000,
+            },
+            allowed_versions: 1..=1,
+            checkpoints: BTreeMap::new(),
+            trust_checkpoints: false,
+            checkpoint_committee: Vec::new(),
+            checkpoint_threshold: 0,
+            initial_subsidy: 50 * COIN,
+            halving_interval: 150,
+            coinbase_maturity: 100,
+            address_hrp: "tarw".to_string(),
+        }
+    }
+
+    /// Pin the block at each height to the given hash, replacing any earlier checkpoints
+    pub fn with_checkpoints(mut self, checkpoints: Vec<(u64, BlockHash)>) -> Self {
+        self.checkpoints = checkpoints.into_iter().collect();
+        self
+    }
+
+    /// Let `threshold` of `committee` certify checkpoints beyond the fixed ones
+    pub fn with_checkpoint_committee(
+        mut self,
+        committee: Vec<PublicKey>,
+        threshold: usize,
+    ) -> Self {
+        self.checkpoint_committee = committee;
+        self.checkpoint_threshold = threshold;
+        self
+    }
+
+    /// Seal blocks by `authorities` taking turns in slots of `step_duration`
+    /// seconds, with no tolerance for clock skew
+    pub fn with_authorities(mut self, authorities: Vec<PublicKey>, step_duration: u64) -> Self {
+        self.consensus_mode = ConsensusMode::ProofOfAuthority {
+            authorities,
+            step_duration,
+            skew_tolerance: 0,
+        };
+        self
+    }
+
+    /// Derive the genesis block, mining it from `genesis_nonce` if that nonce
+    /// does not already meet `initial_difficulty`. A block commits to at
+    /// least one transaction, so empty `genesis_transactions` are taken as a
+    /// single empty transaction.
+    pub fn genesis_block(&self) -> Block {
+        let transactions = match &self.genesis_transactions[..] {
+            [] => vec![Vec::new()],
+            transactions => transactions.to_vec(),
+        };
+        #[allow(clippy::expect_used)]
+        let mut genesis = BlockBuilder::new(BlockHash::ZERO)
+            .version(*self.allowed_versions.start())
+            .transactions(transactions)
+            .timestamp(self.genesis_timestamp)
+            .difficulty(self.initial_difficulty)
+            .nonce(self.genesis_nonce)
+            .build()
+            .expect("the genesis block has a transaction");
+        genesis.mine(self.initial_difficulty);
+        genesis
+    }
+
+    /// Hash of the block every valid chain starts from
+    pub fn genesis_hash(&self) -> BlockHash {
+        self.genesis_block().hash()
+    }
+
+    /// The magic naming the network at the start of every frame between
+    /// peers: the last four bytes of the genesis hash, little endian. The
+    /// first bytes are the genesis block's proof of work, zero on every
+    /// network, so networks are told apart by the last.
+    pub fn magic(&self) -> u32 {
+        let hash = self.genesis_hash();
+        let [.., a, b, c, d] = *hash.as_bytes();
+        u32::from_le_bytes([a, b, c, d])
+    }
+
+    /// New coins the coinbase of the block at `height` may create: the
+    /// initial subsidy halved once per completed halving interval, rounding
+    /// down, and zero from the 64th halving on
+    pub fn subsidy_at(&self, height: u64) -> u64 {
+        if self.halving_interval == 0 {
+            return self.initial_subsidy;
+        }
+        let halvings = height / self.halving_interval;
+        if halvings >= u64::BITS as u64 {
+            0
+        } else {
+            self.initial_subsidy >> halvings
+        }
+    }
+
+    /// Most coins the blocks after genesis up to and including `height` may
+    /// have created, saturating at `u64::MAX`. The genesis block's outputs
+    /// are fixed by `genesis_transactions` rather than the schedule.
+    pub fn total_supply_at(&self, height: u64) -> u64 {
+        let subsidy = self.initial_subsidy as u128;
+        let blocks = height as u128;
+        if self.halving_interval == 0 {
+            return saturate(subsidy * blocks);
+        }
+        // Counting heights 0..=height, then leaving out the genesis block
+        let interval = self.halving_interval as u128;
+        let counted = blocks + 1;
+        let whole = (counted / interval).min(u64::BITS as u128) as u32;
+        let partial = if whole < u64::BITS {
+            (counted % interval) * (subsidy >> whole)
+        } else {
+            0
+        };
+        let total = interval * halved_sum(self.initial_subsidy, whole) + partial - subsidy;
+        saturate(total)
+    }
+}
+
+/// Base units in one coin
+pub const COIN: u64 = 100_000_000;
+
+/// `subsidy >> k` summed over `k` in `0..halvings`, without a loop.
+///
+/// Summed over every `k`, the shifts of `n` total `2n - popcount(n)`: each
+/// set bit `2^b` contributes `2^(b+1) - 1`. The shifts from `halvings` on
+/// are the full sum for `subsidy >> halvings`.
+fn halved_sum(subsidy: u64, halvings: u32) -> u128 {
+    let full = |n: u64| 2 * n as u128 - n.count_ones() as u128;
+    let rest = subsidy.checked_shr(halvings).unwrap_or(0);
+    full(subsidy) - full(rest)
+}
+
+fn saturate(value: u128) -> u64 {
+    u64::try_from(value).unwrap_or(u64::MAX)
+}
+
+/// A nonce meeting the mainnet genesis difficulty, so nodes don't have to mine it
+const MAINNET_GENESIS_NONCE: u64 = 642_511;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_genesis_is_deterministic() {
+        let params = ChainParams::test_defaults();
+        assert_eq!(params.genesis_block(), params.genesis_block());
+        assert!(params.genesis_block().verify_pow(params.initial_difficulty));
+
+        let mut other = params.clone();
+        other.genesis_timestamp += 1;
+        assert_ne!(other.genesis_hash(), params.genesis_hash());
+        assert_ne!(other.magic(), params.magic());
+    }
+
+    #[test]
+    fn test_networks_have_their_own_magic() {
+        let mainnet = ChainParams::mainnet_defaults();
+        let test = ChainParams::test_defaults();
+        assert_eq!(mainnet.magic(), mainnet.magic());
+        assert_ne!(mainnet.magic(), test.magic());
+        assert_eq!(
+            test.magic().to_le_bytes(),
+            test.genesis_hash().as_bytes()[28..]
+        );
+    }
+
+    #[test]
+    fn test_subsidy_halves_at_interval_boundaries() {
+        let params = ChainParams::mainnet_defaults();
+        let interval = params.halving_interval;
+        assert_eq!(params.subsidy_at(0), 50 * COIN);
+        for halvings in 1..70u64 {
+            let boundary = halvings * interval;
+            assert_eq!(
+                params.subsidy_at(boundary - 1),
+                (50 * COIN).checked_shr(halvings as u32 - 1).unwrap_or(0)
+            );
+            assert_eq!(
+                params.subsidy_at(boundary),
+                (50 * COIN).checked_shr(halvings as u32).unwrap_or(0)
+            );
+        }
+        assert_eq!(params.subsidy_at(interval), 25 * COIN);
+        assert_eq!(params.subsidy_at(64 * interval - 1), 0);
+
+        // A subsidy with the top bit set lasts all 64 halvings
+        let wide = ChainParams {
+            initial_subsidy: u64::MAX,
+            halving_interval: 1,
+            ..ChainParams::test_defaults()
+        };
+        assert_eq!(wide.subsidy_at(63), 1);
+        assert_eq!(wide.subsidy_at(64), 0);
+        for height in [u64::MAX, u64::MAX - 1, 1 << 63] {
+            assert_eq!(wide.subsidy_at(height), 0);
+            assert_eq!(params.subsidy_at(height), 0);
+        }
+        let flat = ChainParams {
+            halving_interval: 0,
+            ..ChainParams::test_defaults()
+        };
+        assert_eq!(flat.subsidy_at(u64::MAX), 50 * COIN);
+    }
+
+    #[test]
+    fn test_total_supply_matches_summed_subsidies() {
+        let params = ChainParams {
+            initial_subsidy: 1000,
+            halving_interval: 7,
+            ..ChainParams::test_defaults()
+        };
+        let mut total = 0;
+        for height in 0..7 * 12 {
+            if height > 0 {
+                total += params.subsidy_at(height);
+            }
+            assert_eq!(params.total_supply_at(height), total, "height {}", height);
+        }
+        // 1000 halves to zero after ten halvings
+        assert_eq!(params.total_supply_at(u64::MAX), total);
+
+        let mainnet = ChainParams::mainnet_defaults();
+        assert_eq!(mainnet.total_supply_at(0), 0);
+        assert_eq!(mainnet.total_supply_at(209_999), 209_999 * 50 * COIN);
+        assert_eq!(
+            mainnet.total_supply_at(210_000),
+            209_999 * 50 * COIN + 25 * COIN
+        );
+        assert_eq!(
+            mainnet.total_supply_at(u64::MAX),
+            2_099_999_997_690_000 - 50 * COIN
+        );
+
+        let wide = ChainParams {
+            initial_subsidy: u64::MAX,
+            halving_interval: u64::MAX,
+            ..ChainParams::test_defaults()
+        };
+        assert_eq!(wide.total_supply_at(1), u64::MAX);
+        assert_eq!(wide.total_supply_at(u64::MAX), u64::MAX);
+    }
+
+    #[test]
+    fn test_mainnet_genesis_nonce_is_mined() {
+        let params = ChainParams::mainnet_defaults();
+        let genesis = params.genesis_block();
+        assert_eq!(genesis.nonce(), MAINNET_GENESIS_NONCE);
+        assert!(genesis.verify_pow(params.initial_difficulty));
+    }
+}
@@ -0,0 +1,541 @@
+//! Protocol Buffers messages for blocks, headers, transactions and merkle
+//! proofs, for services that speak gRPC.
+//!
+//! The messages in [`pb`] are generated from `proto/aarwyn.proto` by the
+//! build script. Native values convert into them with `From`, and messages
+//! convert back with `TryFrom`, which holds them to everything the crate's
+//! binary format would: hash and key lengths, the counts and sizes of
+//! [`DecodeLimits::default`], and for transactions the canonical encoding
+//! itself, so a message that converts names the same transaction, with the
+//! same txid, as its binary form.
+
+use thiserror::Error;
+
+use crate::address::Address;
+use crate::block::{Block, BlockHash, BlockHeader, HeaderFields};
+use crate::codec::{DecodeError, DecodeLimits};
+use crate::crypto::secp256k1::RecoveryId;
+use crate::crypto::{ed25519, secp256k1, PublicKey, Signature, SignatureScheme, SIGNATURE_LENGTH};
+use crate::hash::Hash32;
+use crate::merkle_trie::MerkleProof;
+use crate::transaction::{OutPoint, SpendCondition, Transaction, TxInput, TxOutput, Txid};
+
+/// The generated messages of the `aarwyn.v1` package
+#[allow(clippy::all)]
+pub mod pb {
+    include!(concat!(env!("OUT_DIR"), "/aarwyn.v1.rs"));
+}
+
+/// Reasons a message does not convert to a native value
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ProtoError {
+    /// A message field that must be set is not
+    #[error("{0} is not set")]
+    MissingField(&'static str),
+    /// A hash, key or signature field holds the wrong number of bytes
+    #[error("{field} is {got} bytes, expected {expected}")]
+    InvalidLength {
+        field: &'static str,
+        expected: usize,
+        got: usize,
+    },
+    /// The message holds a value the binary format of `message` refuses
+    #[error("invalid {message}: {err}")]
+    Invalid {
+        message: &'static str,
+        err: DecodeError,
+    },
+}
+
+impl From<&BlockHeader> for pb::BlockHeader {
+    fn from(header: &BlockHeader) -> Self {
+        pb::BlockHeader {
+            version: header.version(),
+            prev_block_hash: header.prev_block_hash().as_bytes().to_vec(),
+            merkle_root: header.merkle_root().to_vec(),
+            state_root: header.state_root().map(|root| root.to_vec()),
+            timestamp: header.timestamp(),
+            bits: header.bits(),
+            nonce: header.nonce(),
+        }
+    }
+}
+
+impl TryFrom<pb::BlockHeader> for BlockHeader {
+    type Error = ProtoError;
+
+    fn try_from(header: pb::BlockHeader) -> Result<Self, Self::Error> {
+        let fields = HeaderFields {
+            version: header.version,
+            prev_block_hash: BlockHash::from_bytes(fixed(
+                &header.prev_block_hash,
+                "BlockHeader.prev_block_hash",
+            )?),
+            merkle_root: Hash32(fixed(&header.merkle_root, "BlockHeader.merkle_root")?),
+            state_root: header
+                .state_root
+                .map(|root| fixed(&root, "BlockHeader.state_root"))
+                .transpose()?,
+            timestamp: header.timestamp,
+            bits: header.bits,
+            nonce: header.nonce,
+        };
+        BlockHeader::try_from(fields).map_err(|err| ProtoError::Invalid {
+            message: "BlockHeader",
+            err,
+        })
+    }
+}
+
+impl From<&Signature> for pb::Signature {
+    fn from(signature: &Signature) -> Self {
+        let bytes = signature.to_bytes().to_vec();
+        pb::Signature {
+            scheme: Some(match signature.scheme() {
+                SignatureScheme::Ed25519 => pb::signature::Scheme::Ed25519(bytes),
+                SignatureScheme::Secp256k1 => pb::signature::Scheme::Secp256k1(bytes),
+            }),
+        }
+    }
+}
+
+impl TryFrom<pb::Signature> for Signature {
+    type Error = ProtoError;
+
+    fn try_from(signature: pb::Signature) -> Result<Self, Self::Error> {
+        let (scheme, bytes) = match signature.scheme {
+            Some(pb::signature::Scheme::Ed25519(bytes)) => (SignatureScheme::Ed25519, bytes),
+            Some(pb::signature::Scheme::Secp256k1(bytes)) => (SignatureScheme::Secp256k1, bytes),
+            None => return Err(ProtoError::MissingField("Signature.scheme")),
+        };
+        let bytes = fixed::<SIGNATURE_LENGTH>(&bytes, "Signature.scheme")?;
+        Ok(Signature::from_bytes(scheme, &bytes))
+    }
+}
+
+impl From<&PublicKey> for pb::PublicKey {
+    fn from(key: &PublicKey) -> Self {
+        let bytes = key.as_bytes().to_vec();
+        pb::PublicKey {
+            scheme: Some(match key.scheme() {
+                SignatureScheme::Ed25519 => pb::public_key::Scheme::Ed25519(bytes),
+                SignatureScheme::Secp256k1 => pb::public_key::Scheme::Secp256k1(bytes),
+            }),
+        }
+    }
+}
+
+impl TryFrom<pb::PublicKey> for PublicKey {
+    type Error = ProtoError;
+
+    fn try_from(key: pb::PublicKey) -> Result<Self, Self::Error> {
+        let (scheme, bytes, len) = match key.scheme {
+            Some(pb::public_key::Scheme::Ed25519(bytes)) => {
+                (SignatureScheme::Ed25519, bytes, ed25519::PUBLIC_KEY_LENGTH)
+            }
+            Some(pb::public_key::Scheme::Secp256k1(bytes)) => (
+                SignatureScheme::Secp256k1,
+                bytes,
+                secp256k1::PUBLIC_KEY_LENGTH,
+            ),
+            None => return Err(ProtoError::MissingField("PublicKey.scheme")),
+        };
+        if bytes.len() != len {
+            return Err(ProtoError::InvalidLength {
+                field: "PublicKey.scheme",
+                expected: len,
+                got: bytes.len(),
+            });
+        }
+        PublicKey::from_bytes(scheme, &bytes).map_err(|_| ProtoError::Invalid {
+            message: "PublicKey",
+            err: DecodeError::InvalidValue("public key"),
+        })
+    }
+}
+
+impl From<&Block> for pb::Block {
+    fn from(block: &Block) -> Self {
+        pb::Block {
+            header: Some(block.header().into()),
+            signature: block.signature().map(Into::into),
+            transactions: block.transactions().to_vec(),
+        }
+    }
+}
+
+impl TryFrom<pb::Block> for Block {
+    type Error = ProtoError;
+
+    fn try_from(block: pb::Block) -> Result<Self, Self::Error> {
+        let limits = DecodeLimits::default();
+        let invalid = |err| ProtoError::Invalid {
+            message: "Block",
+            err,
+        };
+        let header = block
+            .header
+            .ok_or(ProtoError::MissingField("Block.header"))?
+            .try_into()?;
+        let signature = block.signature.map(Signature::try_from).transpose()?;
+        if block.transactions.is_empty() {
+            return Err(invalid(DecodeError::InvalidValue(
+                "block has no transactions",
+            )));
+        }
+        check_limit(
+            "transaction count",
+            block.transactions.len(),
+            limits.max_transactions,
+        )
+        .map_err(invalid)?;
+        for tx in &block.transactions {
+            check_limit("transaction size", tx.len(), limits.max_transaction_bytes)
+                .map_err(invalid)?;
+        }
+        Block::from_parts(header, signature, block.transactions).map_err(invalid)
+    }
+}
+
+impl From<&Transaction> for pb::Transaction {
+    fn from(tx: &Transaction) -> Self {
+        pb::Transaction {
+            inputs: tx
+                .inputs
+                .iter()
+                .map(|input| pb::TxInput {
+                    prev_out: Some(pb::OutPoint {
+                        txid: input.prev_out.txid.as_bytes().to_vec(),
+                        index: input.prev_out.index,
+                    }),
+                    signatures: input.signatures.iter().map(Into::into).collect(),
+                    public_key: input.public_key.as_ref().map(Into::into),
+                    recovery_id: input.recovery_id.map(|id| id.to_u8().into()),
+                })
+                .collect(),
+            outputs: tx
+                .outputs
+                .iter()
+                .map(|output| pb::TxOutput {
+                    amount: output.amount,
+                    condition: Some(match &output.condition {
+                        SpendCondition::SingleKey(address) => {
+                            pb::tx_output::Condition::SingleKey(address.as_bytes().to_vec())
+                        }
+                        SpendCondition::MultiSig { m, keys } => {
+                            pb::tx_output::Condition::MultiSig(pb::MultiSig {
+                                m: (*m).into(),
+                                keys: keys.iter().map(Into::into).collect(),
+                            })
+                        }
+                    }),
+                })
+                .collect(),
+            lock_time: tx.lock_time,
+        }
+    }
+}
+
+/// Refused unless the transaction decodes from its own canonical encoding
+/// under [`DecodeLimits::default`] to the same transaction, as
+/// [`Transaction::decode`] would have it
+impl TryFrom<pb::Transaction> for Transaction {
+    type Error = ProtoError;
+
+    fn try_from(tx: pb::Transaction) -> Result<Self, Self::Error> {
+        let limits = DecodeLimits::default();
+        let invalid = |err| ProtoError::Invalid {
+            message: "Transaction",
+            err,
+        };
+        check_limit("input count", tx.inputs.len(), limits.max_tx_inputs).map_err(invalid)?;
+        check_limit("output count", tx.outputs.len(), limits.max_tx_outputs).map_err(invalid)?;
+
+        let inputs = tx
+            .inputs
+            .into_iter()
+            .map(|input| {
+                let prev_out = input
+                    .prev_out
+                    .ok_or(ProtoError::MissingField("TxInput.prev_out"))?;
+                let recovery_id = input
+                    .recovery_id
+                    .map(|id| {
+                        u8::try_from(id)
+                            .ok()
+                            .and_then(RecoveryId::from_u8)
+                            .ok_or(invalid(DecodeError::InvalidValue("recovery id")))
+                    })
+                    .transpose()?;
+                Ok(TxInput {
+                    prev_out: OutPoint {
+                        txid: Txid::from_bytes(fixed(&prev_out.txid, "OutPoint.txid")?),
+                        index: prev_out.index,
+                    },
+                    signatures: input
+                        .signatures
+                        .into_iter()
+                        .map(Signature::try_from)
+                        .collect::<Result<_, _>>()?,
+                    public_key: input.public_key.map(PublicKey::try_from).transpose()?,
+                    recovery_id,
+                })
+            })
+            .collect::<Result<_, ProtoError>>()?;
+
+        let outputs = tx
+            .outputs
+            .into_iter()
+            .map(|output| {
+                let condition = match output.condition {
+                    Some(pb::tx_output::Condition::SingleKey(address)) => {
+                        SpendCondition::SingleKey(Address::from_bytes(fixed(
+                            &address,
+                            "TxOutput.single_key",
+                        )?))
+                    }
+                    Some(pb::tx_output::Condition::MultiSig(multisig)) => {
+                        SpendCondition::MultiSig {
+                            m: u8::try_from(multisig.m)
+                                .map_err(|_| invalid(DecodeError::InvalidValue("multisig m")))?,
+                            keys: multisig
+                                .keys
+                                .into_iter()
+                                .map(PublicKey::try_from)
+                                .collect::<Result<_, _>>()?,
+                        }
+                    }
+                    None => return Err(ProtoError::MissingField("TxOutput.condition")),
+                };
+                Ok(TxOutput {
+                    amount: output.amount,
+                    condition,
+                })
+            })
+            .collect::<Result<_, ProtoError>>()?;
+
+        let tx = Transaction {
+            inputs,
+            outputs,
+            lock_time: tx.lock_time,
+        };
+        if Transaction::decode(&tx.encode(), &limits).map_err(invalid)? != tx {
+            return Err(invalid(DecodeError::NonCanonicalEncoding));
+        }
+        Ok(tx)
+    }
+}
+
+impl From<&MerkleProof> for pb::MerkleProof {
+    fn from(proof: &MerkleProof) -> Self {
+        pb::MerkleProof {
+            leaf_hash: proof.leaf_hash().to_vec(),
+            root_hash: proof.root_hash().to_vec(),
+            proof: proof
+                .siblings()
+                .iter()
+                .map(|(sibling, is_right)| pb::ProofStep {
+                    sibling: sibling.to_vec(),
+                    is_right: *is_right,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<pb::MerkleProof> for MerkleProof {
+    type Error = ProtoError;
+
+    fn try_from(proof: pb::MerkleProof) -> Result<Self, Self::Error> {
+        let limits = DecodeLimits::default();
+        check_limit("proof depth", proof.proof.len(), limits.max_proof_depth).map_err(|err| {
+            ProtoError::Invalid {
+                message: "MerkleProof",
+                err,
+            }
+        })?;
+        let siblings = proof
+            .proof
+            .into_iter()
+            .map(|step| {
+                Ok((
+                    Hash32(fixed(&step.sibling, "ProofStep.sibling")?),
+                    step.is_right,
+                ))
+            })
+            .collect::<Result<_, ProtoError>>()?;
+        let leaf_hash = fixed(&proof.leaf_hash, "MerkleProof.leaf_hash")?;
+        let root_hash = fixed(&proof.root_hash, "MerkleProof.root_hash")?;
+        Ok(MerkleProof::from_parts(
+            siblings,
+            Hash32(leaf_hash),
+            Hash32(root_hash),
+        ))
+    }
+}
+
+/// `bytes` as an array, if it is `N` long
+fn fixed<const N: usize>(bytes: &[u8], field: &'static str) -> Result<[u8; N], ProtoError> {
+    bytes.try_into().map_err(|_| ProtoError::InvalidLength {
+        field,
+        expected: N,
+        got: bytes.len(),
+    })
+}
+
+fn check_limit(what: &'static str, value: usize, max: usize) -> Result<(), DecodeError> {
+    if value > max {
+        return Err(DecodeError::LimitExceeded {
+            what,
+            value: value as u64,
+            max: max as u64,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use prost::Message;
+
+    use super::*;
+    use crate::crypto::ed25519;
+    use crate::test_vectors::SerdeFixtures;
+    use crate::testing;
+
+    // Through the message and its wire encoding and back
+    fn round_trip<T, M>(value: &T) -> Result<T, ProtoError>
+    where
+        for<'a> M: From<&'a T> + Message + Default,
+        T: TryFrom<M, Error = ProtoError>,
+    {
+        let bytes = M::from(value).encode_to_vec();
+        T::try_from(M::decode(bytes.as_slice()).unwrap())
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let fixtures = SerdeFixtures::new();
+        for header in &fixtures.headers {
+            assert_eq!(
+                round_trip::<_, pb::BlockHeader>(header).as_ref(),
+                Ok(header)
+            );
+        }
+        assert_eq!(
+            round_trip::<_, pb::Block>(&fixtures.block).as_ref(),
+            Ok(&fixtures.block)
+        );
+        assert_eq!(
+            round_trip::<_, pb::Transaction>(&fixtures.transaction).as_ref(),
+            Ok(&fixtures.transaction)
+        );
+        let proof = round_trip::<_, pb::MerkleProof>(&fixtures.proof).unwrap();
+        assert_eq!(proof, fixtures.proof);
+        assert!(proof.verify(b"leaf-3"));
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_values_round_trip(
+            mut block in testing::block(),
+            signed in any::<bool>(),
+            tx in testing::transaction(),
+        ) {
+            if signed {
+                block.sign(&ed25519::SigningKey::from_bytes(&[1; 32]));
+            }
+            let header = block.header().clone();
+            prop_assert_eq!(round_trip::<_, pb::BlockHeader>(&header), Ok(header));
+            prop_assert_eq!(round_trip::<_, pb::Block>(&block), Ok(block));
+            prop_assert_eq!(round_trip::<_, pb::Transaction>(&tx), Ok(tx));
+        }
+
+        #[test]
+        fn test_generated_proofs_round_trip((leaves, index, proof) in testing::merkle_proof()) {
+            let decoded = round_trip::<_, pb::MerkleProof>(&proof).unwrap();
+            prop_assert!(decoded.verify(&leaves[index]));
+            prop_assert_eq!(decoded, proof);
+        }
+    }
+
+    #[test]
+    fn test_refuses_invalid_messages() {
+        let fixtures = SerdeFixtures::new();
+
+        // A 31-byte merkle root never makes a block
+        let mut block = pb::Block::from(&fixtures.block);
+        block.header.as_mut().unwrap().merkle_root.pop();
+        let err = Block::try_from(block.clone()).unwrap_err();
+        assert_eq!(
+            err,
+            ProtoError::InvalidLength {
+                field: "BlockHeader.merkle_root",
+                expected: 32,
+                got: 31
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "BlockHeader.merkle_root is 31 bytes, expected 32"
+        );
+        block.header = None;
+        assert_eq!(
+            Block::try_from(block),
+            Err(ProtoError::MissingField("Block.header"))
+        );
+
+        // A state root the header's version does not have
+        let mut header = pb::BlockHeader::from(&fixtures.headers[0]);
+        header.state_root = Some(vec![0; 32]);
+        assert!(matches!(
+            BlockHeader::try_from(header),
+            Err(ProtoError::Invalid {
+                message: "BlockHeader",
+                ..
+            })
+        ));
+
+        // An input with both its key and a recovery id has no canonical
+        // encoding, and a multisig output needs at least one signature
+        let mut tx = pb::Transaction::from(&fixtures.transaction);
+        tx.inputs[1].public_key =
+            tx.outputs[1]
+                .condition
+                .as_ref()
+                .and_then(|condition| match condition {
+                    pb::tx_output::Condition::MultiSig(multisig) => multisig.keys.last().cloned(),
+                    _ => None,
+                });
+        assert_eq!(
+            Transaction::try_from(tx.clone()),
+            Err(ProtoError::Invalid {
+                message: "Transaction",
+                err: DecodeError::NonCanonicalEncoding
+            })
+        );
+        tx.inputs[1].public_key = None;
+        if let Some(pb::tx_output::Condition::MultiSig(multisig)) = &mut tx.outputs[1].condition {
+            multisig.m = 0;
+        }
+        assert!(matches!(
+            Transaction::try_from(tx),
+            Err(ProtoError::Invalid {
+                message: "Transaction",
+                ..
+            })
+        ));
+
+        let mut proof = pb::MerkleProof::from(&fixtures.proof);
+        proof.proof[0].sibling.push(0);
+        assert_eq!(
+            MerkleProof::try_from(proof),
+            Err(ProtoError::InvalidLength {
+                field: "ProofStep.sibling",
+                expected: 32,
+                got: 33
+            })
+        );
+    }
+}
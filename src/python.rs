@@ -0,0 +1,244 @@
+//! Python classes for reading blocks and checking merkle proofs against a
+//! chain of headers, so scripts share the chain's hashing rules rather than
+//! reimplementing them.
+//!
+//! Build the shared library with the `python` feature and import it as
+//! `aarwyn_chain`, for example after copying `libaarwyn_chain.so` to
+//! `aarwyn_chain.so` on the module path:
+//!
+//! ```text
+//! import aarwyn_chain
+//!
+//! block = aarwyn_chain.Block.from_bytes(raw)
+//! proof = aarwyn_chain.MerkleProof.from_bytes(proof_bytes)
+//! assert proof.root_hex() == block.merkle_root()
+//! assert proof.verify(block.transactions()[0])
+//! ```
+//!
+//! Hashes are returned as lowercase hex and everything else as `bytes`.
+//! Input that does not decode, and headers a chain refuses, raise
+//! `ValueError` with the reason; nothing here panics on bad input.
+
+use std::fmt;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+use crate::block::{Block, BlockHeader};
+use crate::chain::HeaderChain;
+use crate::codec::DecodeLimits;
+use crate::encoding;
+use crate::merkle_trie::MerkleProof;
+use crate::params::ChainParams;
+
+/// A proof that a transaction is under a block's merkle root
+#[pyclass(name = "MerkleProof", module = "aarwyn_chain", frozen)]
+pub struct PyMerkleProof(MerkleProof);
+
+#[pymethods]
+impl PyMerkleProof {
+    /// Decode a proof in the chain's binary format
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        MerkleProof::from_bytes(data, &DecodeLimits::default())
+            .map(PyMerkleProof)
+            .map_err(value_error)
+    }
+
+    fn to_bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.to_bytes())
+    }
+
+    /// Whether the proof places `data` under its root; compare `root_hex`
+    /// against a trusted root first
+    fn verify(&self, data: &[u8]) -> bool {
+        self.0.verify(data)
+    }
+
+    fn root_hex(&self) -> String {
+        encoding::to_hex(self.0.root_hash())
+    }
+}
+
+/// A block with its header and transactions
+#[pyclass(name = "Block", module = "aarwyn_chain", frozen)]
+pub struct PyBlock(Block);
+
+#[pymethods]
+impl PyBlock {
+    /// Decode a block in the chain's binary format
+    #[staticmethod]
+    fn from_bytes(data: &[u8]) -> PyResult<Self> {
+        Block::from_bytes(data, &DecodeLimits::default())
+            .map(PyBlock)
+            .map_err(value_error)
+    }
+
+    fn hash(&self) -> String {
+        self.0.hash().to_string()
+    }
+
+    fn merkle_root(&self) -> String {
+        encoding::to_hex(self.0.header().merkle_root())
+    }
+
+    /// The encoded header, as `HeaderChain.append` takes it
+    fn header<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.0.header().to_bytes())
+    }
+
+    fn transactions<'py>(&self, py: Python<'py>) -> Vec<Bound<'py, PyBytes>> {
+        self.0
+            .transactions()
+            .iter()
+            .map(|tx| PyBytes::new(py, tx))
+            .collect()
+    }
+}
+
+/// The headers of a chain from its genesis, checked as they are appended
+#[pyclass(name = "HeaderChain", module = "aarwyn_chain")]
+pub struct PyHeaderChain(HeaderChain);
+
+#[pymethods]
+impl PyHeaderChain {
+    /// Start from the genesis of `network`: "mainnet" or "test"
+    #[new]
+    #[pyo3(signature = (network = "mainnet"))]
+    fn new(network: &str) -> PyResult<Self> {
+        let params = match network {
+            "mainnet" => ChainParams::mainnet_defaults(),
+            "test" => ChainParams::test_defaults(),
+            _ => return Err(value_error(format!("unknown network {:?}", network))),
+        };
+        Ok(PyHeaderChain(HeaderChain::new(&params)))
+    }
+
+    /// Decode `header` and add it on top of the tip
+    fn append(&mut self, header: &[u8]) -> PyResult<()> {
+        let header = BlockHeader::from_bytes(header).map_err(value_error)?;
+        self.0.append(header).map_err(value_error)
+    }
+
+    /// Height of the tip, where the genesis header is at height 0
+    fn height(&self) -> u64 {
+        self.0.height()
+    }
+
+    /// Whether `proof` shows `tx` is in the block at `height`
+    fn verify_inclusion(&self, height: u64, tx: &[u8], proof: &PyMerkleProof) -> bool {
+        self.0.verify_inclusion(height, tx, &proof.0)
+    }
+}
+
+/// The `aarwyn_chain` Python module
+#[pymodule]
+fn aarwyn_chain(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyMerkleProof>()?;
+    module.add_class::<PyBlock>()?;
+    module.add_class::<PyHeaderChain>()?;
+    Ok(())
+}
+
+fn value_error(err: impl fmt::Display) -> PyErr {
+    PyValueError::new_err(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CStr;
+
+    use pyo3::types::PyDict;
+
+    use super::*;
+    use crate::merkle_trie::MerkleTree;
+
+    /// Run `script` in a fresh namespace holding the module and `values`
+    fn run(script: &CStr, values: impl FnOnce(&Bound<'_, PyDict>) -> PyResult<()>) {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let namespace = PyDict::new(py);
+            namespace.set_item("aarwyn_chain", pyo3::wrap_pymodule!(aarwyn_chain)(py))?;
+            values(&namespace)?;
+            py.run(script, Some(&namespace), None)
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn test_verify_proof() {
+        let leaves: Vec<Vec<u8>> = (0..5u8).map(|i| vec![i; 3]).collect();
+        let tree = MerkleTree::new(&leaves).unwrap();
+        run(
+            cr#"
+proof = aarwyn_chain.MerkleProof.from_bytes(proof_bytes)
+assert proof.verify(leaf)
+assert not proof.verify(b"other")
+assert proof.root_hex() == root
+assert proof.to_bytes() == proof_bytes
+try:
+    aarwyn_chain.MerkleProof.from_bytes(proof_bytes[:40])
+    raise AssertionError("a truncated proof decoded")
+except ValueError as err:
+    assert str(err) == "unexpected end of input", err
+"#,
+            |values| {
+                values.set_item(
+                    "proof_bytes",
+                    PyBytes::new(values.py(), &tree.generate_proof(2).unwrap().to_bytes()),
+                )?;
+                values.set_item("leaf", PyBytes::new(values.py(), &leaves[2]))?;
+                values.set_item("root", hex::encode(tree.root_hash()))
+            },
+        );
+    }
+
+    #[test]
+    fn test_block_and_header_chain() {
+        let params = ChainParams::test_defaults();
+        let genesis = params.genesis_block();
+        let mut block = genesis
+            .next_builder()
+            .transactions([b"one".to_vec(), b"two".to_vec(), b"three".to_vec()])
+            .timestamp(genesis.timestamp() + 10)
+            .difficulty(genesis.header().difficulty())
+            .build()
+            .unwrap();
+        block.mine(genesis.header().difficulty());
+        run(
+            cr#"
+block = aarwyn_chain.Block.from_bytes(raw)
+assert block.hash() == expected_hash
+assert block.transactions() == [b"one", b"two", b"three"]
+
+chain = aarwyn_chain.HeaderChain("test")
+assert chain.height() == 0
+chain.append(block.header())
+assert chain.height() == 1
+proof = aarwyn_chain.MerkleProof.from_bytes(proof_bytes)
+assert proof.root_hex() == block.merkle_root()
+assert chain.verify_inclusion(1, b"two", proof)
+assert not chain.verify_inclusion(1, b"one", proof)
+assert not chain.verify_inclusion(2, b"two", proof)
+
+for bad, call in [
+    ("a header not on the tip", lambda: chain.append(block.header())),
+    ("a truncated block", lambda: aarwyn_chain.Block.from_bytes(raw[:-1])),
+    ("an unknown network", lambda: aarwyn_chain.HeaderChain("other")),
+]:
+    try:
+        call()
+        raise AssertionError(bad + " was accepted")
+    except ValueError:
+        pass
+"#,
+            |values| {
+                values.set_item("raw", PyBytes::new(values.py(), &block.to_bytes()))?;
+                values.set_item("expected_hash", block.hash().to_string())?;
+                let proof = block.merkle_tree().generate_proof(1).unwrap().to_bytes();
+                values.set_item("proof_bytes", PyBytes::new(values.py(), &proof))
+            },
+        );
+    }
+}
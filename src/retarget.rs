@@ -0,0 +1,159 @@
+//! Difficulty adjustment.
+//!
+//! Every `retarget_interval` blocks the target is scaled by the ratio of the
+//! time the previous interval actually took to the time it should have taken,
+//! so block production tracks `target_block_time` as hash rate changes. All
+//! other blocks must commit to the same difficulty as their parent.
+
+use crate::block::BlockHeader;
+use crate::difficulty::Difficulty;
+use crate::params::{ChainParams, ConsensusMode};
+
+/// Whether the block at `height` starts a new difficulty interval
+pub fn is_retarget_height(height: u64, params: &ChainParams) -> bool {
+    params.retarget_interval != 0 && height != 0 && height.is_multiple_of(params.retarget_interval)
+}
+
+/// The difficulty for the block after `recent_headers`.
+///
+/// `recent_headers` is the interval just finished, oldest first, ending with
+/// the new block's parent. The elapsed time between the first and last header
+/// is compared against `target_block_time` for each gap between them, clamped
+/// to `max_adjustment` either way, and the parent's target is scaled by the
+/// ratio. In proof-of-work mode the result is never easier than the chain's
+/// minimum difficulty. `None` if `recent_headers` is empty.
+pub fn next_difficulty(recent_headers: &[BlockHeader], params: &ChainParams) -> Option<Difficulty> {
+    let (first, last) = (recent_headers.first()?, recent_headers.last()?);
+
+    let gaps = recent_headers.len() as u64 - 1;
+    let expected = params.target_block_time.as_secs().saturating_mul(gaps);
+    if expected == 0 {
+        return Some(last.difficulty().normalized());
+    }
+
+    let max_adjustment = params.max_adjustment.max(1.0);
+    let shortest = ((expected as f64 / max_adjustment) as u64).max(1);
+    let longest = (expected as f64 * max_adjustment) as u64;
+    let actual = last
+        .timestamp()
+        .saturating_sub(first.timestamp())
+        .clamp(shortest, longest);
+
+    let next = last.difficulty().scaled(actual, expected)?;
+    let next = match &params.consensus_mode {
+        ConsensusMode::ProofOfWork { difficulty } => {
+            let minimum = difficulty.normalized();
+            if next.to_target() > minimum.to_target() {
+                minimum
+            } else {
+                next
+            }
+        }
+        ConsensusMode::ProofOfAuthority { .. } => next,
+    };
+    Some(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BlockBuilder, BlockHash};
+    use std::time::Duration;
+
+    const START: Difficulty = Difficulty::CompactTarget(0x1d00_ffff);
+
+    fn params(minimum: Difficulty) -> ChainParams {
+        ChainParams {
+            consensus_mode: ConsensusMode::ProofOfWork {
+                difficulty: minimum,
+            },
+            target_block_time: Duration::from_secs(60),
+            retarget_interval: 10,
+            max_adjustment: 4.0,
+            ..ChainParams::test_defaults()
+        }
+    }
+
+    /// An interval of ten headers committing to `START`, `spacing` seconds apart
+    fn interval(spacing: u64) -> Vec<BlockHeader> {
+        (0..10)
+            .map(|i| {
+                BlockBuilder::new(BlockHash::ZERO)
+                    .transaction(vec![i as u8])
+                    .difficulty(START)
+                    .timestamp(1_700_000_000 + i * spacing)
+                    .build()
+                    .unwrap()
+                    .header()
+                    .clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_on_schedule_keeps_difficulty() {
+        let params = params(Difficulty::LeadingZeroBits(0));
+        assert_eq!(next_difficulty(&interval(60), &params).unwrap(), START);
+        assert_eq!(next_difficulty(&[], &params), None);
+    }
+
+    #[test]
+    fn test_fast_blocks_raise_difficulty() {
+        let params = params(Difficulty::LeadingZeroBits(0));
+        let next = next_difficulty(&interval(30), &params).unwrap();
+        assert_eq!(next, START.scaled(1, 2).unwrap());
+        assert!(next.work() > START.work());
+    }
+
+    #[test]
+    fn test_slow_blocks_lower_difficulty() {
+        let params = params(Difficulty::LeadingZeroBits(0));
+        let next = next_difficulty(&interval(90), &params).unwrap();
+        assert_eq!(next, START.scaled(3, 2).unwrap());
+        assert!(next.work() < START.work());
+    }
+
+    #[test]
+    fn test_adjustment_is_clamped() {
+        let params = params(Difficulty::LeadingZeroBits(0));
+        assert_eq!(
+            next_difficulty(&interval(1), &params).unwrap(),
+            START.scaled(1, 4).unwrap()
+        );
+        assert_eq!(
+            next_difficulty(&interval(0), &params).unwrap(),
+            START.scaled(1, 4).unwrap()
+        );
+        assert_eq!(
+            next_difficulty(&interval(6000), &params).unwrap(),
+            START.scaled(4, 1).unwrap()
+        );
+
+        // Timestamps going backwards count as the fastest allowed interval
+        let mut headers = interval(60);
+        headers.reverse();
+        assert_eq!(
+            next_difficulty(&headers, &params).unwrap(),
+            START.scaled(1, 4).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_never_easier_than_minimum() {
+        let minimum = Difficulty::CompactTarget(0x1d01_0000);
+        let next = next_difficulty(&interval(6000), &params(minimum));
+        assert_eq!(next, Some(minimum.normalized()));
+    }
+
+    #[test]
+    fn test_retarget_heights() {
+        let mut params = params(Difficulty::LeadingZeroBits(0));
+        let heights: Vec<u64> = (0..35)
+            .filter(|&h| is_retarget_height(h, &params))
+            .collect();
+        assert_eq!(heights, vec![10, 20, 30]);
+
+        params.retarget_interval = 0;
+        assert!((0..35).all(|h| !is_retarget_height(h, &params)));
+    }
+}
@@ -0,0 +1,720 @@
+//! Recursive Length Prefix encoding of block headers and transactions, for
+//! tooling that speaks Ethereum's serialization.
+//!
+//! An [`Item`] is a byte string or a list of items. Integers are byte
+//! strings holding the big-endian value without leading zeros, so zero is
+//! the empty string; hashes, addresses, keys and signatures are byte
+//! strings of their usual bytes. A value that may be absent is a list of
+//! none or one item. The types are lists of their fields, in this order:
+//!
+//! - [`BlockHeader`]: `[version, prev_block_hash, merkle_root, [state_root?],
+//!   timestamp, bits, nonce]`, the state root present from
+//!   [`STATE_ROOT_VERSION`](crate::block::STATE_ROOT_VERSION) on
+//! - [`Transaction`]: `[[input...], [output...], lock_time]`
+//! - an input: `[[txid, index], [signature...], [public_key?],
+//!   [recovery_id?]]`
+//! - an output: `[amount, condition]`, where the condition is
+//!   `[0, address]` for a single key or `[1, m, [key...]]` for a multisig
+//! - a signature or public key: `[scheme_tag, bytes]`, with the tag of
+//!   [`SignatureScheme::tag`]
+//!
+//! Decoding is strict: lengths must take their shortest form, a single byte
+//! below `0x80` must be encoded as itself, integers may not have leading
+//! zeros, and nothing may follow the item, so every value has exactly one
+//! encoding.
+
+use crate::address::Address;
+use crate::block::{BlockHash, BlockHeader, HeaderFields};
+use crate::codec::{self, DecodeError, DecodeLimits};
+use crate::crypto::secp256k1::RecoveryId;
+use crate::crypto::{PublicKey, Signature, SignatureScheme, SIGNATURE_LENGTH};
+use crate::hash::Hash32;
+use crate::transaction::{OutPoint, SpendCondition, Transaction, TxInput, TxOutput, Txid};
+
+/// How deeply lists may nest in input; a transaction needs five levels
+const MAX_NESTING: usize = 16;
+
+/// An RLP item
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Item {
+    Bytes(Vec<u8>),
+    List(Vec<Item>),
+}
+
+impl Item {
+    /// The item holding `value` as a big-endian integer without leading
+    /// zeros
+    pub fn uint(value: u64) -> Item {
+        let bytes = value.to_be_bytes();
+        let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+        Item::Bytes(bytes[zeros..].to_vec())
+    }
+
+    /// The encoding of the item
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            Item::Bytes(bytes) if bytes.len() == 1 && bytes[0] < 0x80 => out.push(bytes[0]),
+            Item::Bytes(bytes) => {
+                write_length(out, 0x80, bytes.len());
+                out.extend_from_slice(bytes);
+            }
+            Item::List(items) => {
+                let mut payload = Vec::new();
+                for item in items {
+                    item.encode_into(&mut payload);
+                }
+                write_length(out, 0xc0, payload.len());
+                out.extend_from_slice(&payload);
+            }
+        }
+    }
+
+    /// Decode one item making up all of `bytes`, enforcing `limits` on
+    /// untrusted input
+    pub fn decode(bytes: &[u8], limits: &DecodeLimits) -> Result<Item, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let (item, rest) = read_item(bytes, 0)?;
+        if !rest.is_empty() {
+            return Err(DecodeError::TrailingBytes(rest.len()));
+        }
+        Ok(item)
+    }
+}
+
+/// A type with an RLP encoding, through its conversions to and from
+/// [`Item`]
+pub trait Rlp: Sized {
+    /// The encoding of the value
+    fn to_rlp(&self) -> Vec<u8>;
+
+    /// Decode what [`Rlp::to_rlp`] wrote, enforcing `limits` on the size of
+    /// untrusted input
+    fn from_rlp(bytes: &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError>;
+}
+
+impl<T> Rlp for T
+where
+    for<'a> Item: From<&'a T>,
+    T: TryFrom<Item, Error = DecodeError>,
+{
+    fn to_rlp(&self) -> Vec<u8> {
+        Item::from(self).encode()
+    }
+
+    fn from_rlp(bytes: &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+        T::try_from(Item::decode(bytes, limits)?)
+    }
+}
+
+impl From<&BlockHeader> for Item {
+    fn from(header: &BlockHeader) -> Self {
+        Item::List(vec![
+            Item::uint(header.version().into()),
+            Item::Bytes(header.prev_block_hash().to_vec()),
+            Item::Bytes(header.merkle_root().to_vec()),
+            optional(header.state_root().map(|root| Item::Bytes(root.to_vec()))),
+            Item::uint(header.timestamp()),
+            Item::uint(header.bits().into()),
+            Item::uint(header.nonce()),
+        ])
+    }
+}
+
+impl TryFrom<Item> for BlockHeader {
+    type Error = DecodeError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        let [version, prev_block_hash, merkle_root, state_root, timestamp, bits, nonce] =
+            fields(item, "header")?;
+        BlockHeader::try_from(HeaderFields {
+            version: uint(version, "version")?,
+            prev_block_hash: BlockHash::from_bytes(fixed(prev_block_hash, "prev block hash")?),
+            merkle_root: Hash32(fixed(merkle_root, "merkle root")?),
+            state_root: take_optional(state_root, "state root")?
+                .map(|root| fixed(root, "state root"))
+                .transpose()?,
+            timestamp: uint(timestamp, "timestamp")?,
+            bits: uint(bits, "bits")?,
+            nonce: uint(nonce, "nonce")?,
+        })
+    }
+}
+
+impl From<&Transaction> for Item {
+    fn from(tx: &Transaction) -> Self {
+        let inputs = tx
+            .inputs
+            .iter()
+            .map(|input| {
+                Item::List(vec![
+                    Item::List(vec![
+                        Item::Bytes(input.prev_out.txid.as_bytes().to_vec()),
+                        Item::uint(input.prev_out.index.into()),
+                    ]),
+                    Item::List(
+                        input
+                            .signatures
+                            .iter()
+                            .map(|signature| {
+                                keyed(signature.scheme(), signature.to_bytes().to_vec())
+                            })
+                            .collect(),
+                    ),
+                    optional(input.public_key.as_ref().map(public_key)),
+                    optional(input.recovery_id.map(|id| Item::uint(id.to_u8().into()))),
+                ])
+            })
+            .collect();
+        let outputs = tx
+            .outputs
+            .iter()
+            .map(|output| {
+                let condition = match &output.condition {
+                    SpendCondition::SingleKey(address) => Item::List(vec![
+                        Item::uint(0),
+                        Item::Bytes(address.as_bytes().to_vec()),
+                    ]),
+                    SpendCondition::MultiSig { m, keys } => Item::List(vec![
+                        Item::uint(1),
+                        Item::uint((*m).into()),
+                        Item::List(keys.iter().map(public_key).collect()),
+                    ]),
+                };
+                Item::List(vec![Item::uint(output.amount), condition])
+            })
+            .collect();
+        Item::List(vec![
+            Item::List(inputs),
+            Item::List(outputs),
+            Item::uint(tx.lock_time.into()),
+        ])
+    }
+}
+
+/// Accepts exactly the transactions whose canonical encoding decodes under
+/// [`DecodeLimits::default`] to the same transaction, as
+/// [`Transaction::decode`] would have it
+impl TryFrom<Item> for Transaction {
+    type Error = DecodeError;
+
+    fn try_from(item: Item) -> Result<Self, Self::Error> {
+        let limits = DecodeLimits::default();
+        let [inputs, outputs, lock_time] = fields(item, "transaction")?;
+        let inputs = list(inputs, "inputs", limits.max_tx_inputs)?
+            .into_iter()
+            .map(|input| {
+                let [prev_out, signatures, key, recovery_id] = fields(input, "input")?;
+                let [txid, index] = fields(prev_out, "prev out")?;
+                Ok(TxInput {
+                    prev_out: OutPoint {
+                        txid: Txid::from_bytes(fixed(txid, "txid")?),
+                        index: uint(index, "index")?,
+                    },
+                    signatures: list(signatures, "signatures", usize::MAX)?
+                        .into_iter()
+                        .map(|signature| {
+                            let (scheme, bytes) = unkeyed(signature, "signature")?;
+                            let bytes = fixed::<SIGNATURE_LENGTH>(bytes, "signature")?;
+                            Ok(Signature::from_bytes(scheme, &bytes))
+                        })
+                        .collect::<Result<_, DecodeError>>()?,
+                    public_key: take_optional(key, "public key")?
+                        .map(read_public_key)
+                        .transpose()?,
+                    recovery_id: take_optional(recovery_id, "recovery id")?
+                        .map(|id| {
+                            RecoveryId::from_u8(uint(id, "recovery id")?)
+                                .ok_or(DecodeError::InvalidValue("recovery id"))
+                        })
+                        .transpose()?,
+                })
+            })
+            .collect::<Result<_, DecodeError>>()?;
+        let outputs = list(outputs, "outputs", limits.max_tx_outputs)?
+            .into_iter()
+            .map(|output| {
+                let [amount, condition] = fields(output, "output")?;
+                let mut condition = list(condition, "condition", 3)?.into_iter();
+                let tag = condition
+                    .next()
+                    .ok_or(DecodeError::InvalidValue("condition"))?;
+                let condition = match (
+                    uint::<u8>(tag, "condition")?,
+                    condition.next(),
+                    condition.next(),
+                ) {
+                    (0, Some(address), None) => {
+                        SpendCondition::SingleKey(Address::from_bytes(fixed(address, "address")?))
+                    }
+                    (1, Some(m), Some(keys)) => SpendCondition::MultiSig {
+                        m: uint(m, "multisig m")?,
+                        keys: list(keys, "multisig keys", usize::MAX)?
+                            .into_iter()
+                            .map(read_public_key)
+                            .collect::<Result<_, _>>()?,
+                    },
+                    _ => return Err(DecodeError::InvalidValue("condition")),
+                };
+                Ok(TxOutput {
+                    amount: uint(amount, "amount")?,
+                    condition,
+                })
+            })
+            .collect::<Result<_, DecodeError>>()?;
+
+        let tx = Transaction {
+            inputs,
+            outputs,
+            lock_time: uint(lock_time, "lock time")?,
+        };
+        if Transaction::decode(&tx.encode(), &limits)? != tx {
+            return Err(DecodeError::NonCanonicalEncoding);
+        }
+        Ok(tx)
+    }
+}
+
+/// Append the prefix of a string (`offset` 0x80) or list (0xc0) whose
+/// payload is `len` bytes
+fn write_length(out: &mut Vec<u8>, offset: u8, len: usize) {
+    if len <= 55 {
+        out.push(offset + len as u8);
+    } else {
+        let bytes = (len as u64).to_be_bytes();
+        let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(offset + 55 + (8 - zeros) as u8);
+        out.extend_from_slice(&bytes[zeros..]);
+    }
+}
+
+/// Read the item at the start of `bytes`, `depth` lists deep, returning it
+/// and the input after it
+fn read_item(bytes: &[u8], depth: usize) -> Result<(Item, &[u8]), DecodeError> {
+    let (&prefix, rest) = bytes.split_first().ok_or(DecodeError::UnexpectedEof)?;
+    if prefix < 0x80 {
+        return Ok((Item::Bytes(vec![prefix]), rest));
+    }
+    let is_list = prefix >= 0xc0;
+    let short = if is_list {
+        prefix - 0xc0
+    } else {
+        prefix - 0x80
+    };
+    let (len, rest) = if short <= 55 {
+        (short as u64, rest)
+    } else {
+        let size = (short - 55) as usize;
+        if rest.len() < size {
+            return Err(DecodeError::UnexpectedEof);
+        }
+        let (len_bytes, rest) = rest.split_at(size);
+        // The long form is only for payloads of 56 bytes and more, and its
+        // length has no leading zeros
+        if len_bytes[0] == 0 {
+            return Err(DecodeError::NonCanonicalEncoding);
+        }
+        let len = len_bytes.iter().fold(0u64, |len, &b| len << 8 | b as u64);
+        if len <= 55 {
+            return Err(DecodeError::NonCanonicalEncoding);
+        }
+        (len, rest)
+    };
+    if (rest.len() as u64) < len {
+        return Err(DecodeError::UnexpectedEof);
+    }
+    let (payload, rest) = rest.split_at(len as usize);
+
+    if !is_list {
+        if payload.len() == 1 && payload[0] < 0x80 {
+            return Err(DecodeError::NonCanonicalEncoding);
+        }
+        return Ok((Item::Bytes(payload.to_vec()), rest));
+    }
+    if depth == MAX_NESTING {
+        return Err(DecodeError::InvalidValue("rlp nesting"));
+    }
+    let mut items = Vec::new();
+    let mut payload = payload;
+    while !payload.is_empty() {
+        let (item, remaining) = read_item(payload, depth + 1)?;
+        items.push(item);
+        payload = remaining;
+    }
+    Ok((Item::List(items), rest))
+}
+
+/// A list of none or one item
+fn optional(item: Option<Item>) -> Item {
+    Item::List(item.into_iter().collect())
+}
+
+fn take_optional(item: Item, what: &'static str) -> Result<Option<Item>, DecodeError> {
+    let mut items = list(item, what, 1)?;
+    Ok(items.pop())
+}
+
+/// `[scheme_tag, bytes]`
+fn keyed(scheme: SignatureScheme, bytes: Vec<u8>) -> Item {
+    Item::List(vec![Item::uint(scheme.tag().into()), Item::Bytes(bytes)])
+}
+
+fn public_key(key: &PublicKey) -> Item {
+    keyed(key.scheme(), key.as_bytes().to_vec())
+}
+
+fn unkeyed(item: Item, what: &'static str) -> Result<(SignatureScheme, Item), DecodeError> {
+    let [tag, bytes] = fields(item, what)?;
+    let scheme =
+        SignatureScheme::from_tag(uint(tag, what)?).ok_or(DecodeError::InvalidValue(what))?;
+    Ok((scheme, bytes))
+}
+
+fn read_public_key(item: Item) -> Result<PublicKey, DecodeError> {
+    let (scheme, bytes) = unkeyed(item, "public key")?;
+    let Item::Bytes(bytes) = bytes else {
+        return Err(DecodeError::InvalidValue("public key"));
+    };
+    PublicKey::from_bytes(scheme, &bytes).map_err(|_| DecodeError::InvalidValue("public key"))
+}
+
+/// The items of a list of at most `max`
+fn list(item: Item, what: &'static str, max: usize) -> Result<Vec<Item>, DecodeError> {
+    match item {
+        Item::List(items) if items.len() > max => Err(DecodeError::LimitExceeded {
+            what,
+            value: items.len() as u64,
+            max: max as u64,
+        }),
+        Item::List(items) => Ok(items),
+        Item::Bytes(_) => Err(DecodeError::InvalidValue(what)),
+    }
+}
+
+/// The items of a list of exactly `N`
+fn fields<const N: usize>(item: Item, what: &'static str) -> Result<[Item; N], DecodeError> {
+    match item {
+        Item::List(items) => items
+            .try_into()
+            .map_err(|_| DecodeError::InvalidValue(what)),
+        Item::Bytes(_) => Err(DecodeError::InvalidValue(what)),
+    }
+}
+
+fn fixed<const N: usize>(item: Item, what: &'static str) -> Result<[u8; N], DecodeError> {
+    match item {
+        Item::Bytes(bytes) => bytes
+            .try_into()
+            .map_err(|_| DecodeError::InvalidValue(what)),
+        Item::List(_) => Err(DecodeError::InvalidValue(what)),
+    }
+}
+
+fn uint<T: TryFrom<u64>>(item: Item, what: &'static str) -> Result<T, DecodeError> {
+    let Item::Bytes(bytes) = item else {
+        return Err(DecodeError::InvalidValue(what));
+    };
+    if bytes.first() == Some(&0) {
+        return Err(DecodeError::NonCanonicalEncoding);
+    }
+    if bytes.len() > 8 {
+        return Err(DecodeError::InvalidValue(what));
+    }
+    let value = bytes.iter().fold(0u64, |value, &b| value << 8 | b as u64);
+    T::try_from(value).map_err(|_| DecodeError::InvalidValue(what))
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::difficulty::Difficulty;
+    use crate::test_vectors::SerdeFixtures;
+    use crate::testing;
+
+    fn bytes(s: &str) -> Item {
+        Item::Bytes(s.as_bytes().to_vec())
+    }
+
+    /// Byte strings of up to 70 bytes, past the short form, nested in lists
+    /// up to four deep
+    fn item() -> impl Strategy<Value = Item> {
+        vec(any::<u8>(), 0..70)
+            .prop_map(Item::Bytes)
+            .prop_recursive(4, 32, 6, |inner| vec(inner, 0..6).prop_map(Item::List))
+    }
+
+    #[test]
+    fn test_spec_vectors() {
+        let lorem = "Lorem ipsum dolor sit amet, consectetur adipisicing elit";
+        let set = |items: Vec<Item>| Item::List(items);
+        let cases = [
+            (bytes("dog"), "83646f67".to_string()),
+            (
+                set(vec![bytes("cat"), bytes("dog")]),
+                "c88363617483646f67".to_string(),
+            ),
+            (bytes(""), "80".to_string()),
+            (set(vec![]), "c0".to_string()),
+            (Item::uint(0), "80".to_string()),
+            (Item::Bytes(vec![0]), "00".to_string()),
+            (Item::uint(15), "0f".to_string()),
+            (Item::uint(1024), "820400".to_string()),
+            (Item::uint(u64::MAX), "88ffffffffffffffff".to_string()),
+            // The set theoretical representation of three
+            (
+                set(vec![
+                    set(vec![]),
+                    set(vec![set(vec![])]),
+                    set(vec![set(vec![]), set(vec![set(vec![])])]),
+                ]),
+                "c7c0c1c0c3c0c1c0".to_string(),
+            ),
+            (bytes(lorem), format!("b838{}", hex::encode(lorem))),
+            (
+                set(vec![bytes(lorem)]),
+                format!("f83ab838{}", hex::encode(lorem)),
+            ),
+        ];
+        for (item, expected) in cases {
+            assert_eq!(hex::encode(item.encode()), expected);
+            let decoded = Item::decode(&item.encode(), &DecodeLimits::default());
+            assert_eq!(decoded, Ok(item));
+        }
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let fixtures = SerdeFixtures::new();
+        let limits = DecodeLimits::default();
+        for header in &fixtures.headers {
+            assert_eq!(
+                BlockHeader::from_rlp(&header.to_rlp(), &limits).as_ref(),
+                Ok(header)
+            );
+        }
+        let tx = &fixtures.transaction;
+        assert_eq!(
+            Transaction::from_rlp(&tx.to_rlp(), &limits).as_ref(),
+            Ok(tx)
+        );
+        let coinbase = Transaction::default();
+        assert_eq!(hex::encode(coinbase.to_rlp()), "c3c0c080");
+        assert_eq!(
+            Transaction::from_rlp(&coinbase.to_rlp(), &limits),
+            Ok(coinbase)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_items_round_trip(item in item()) {
+            let limits = DecodeLimits::default();
+            let bytes = item.encode();
+            prop_assert_eq!(Item::decode(&bytes, &limits), Ok(item));
+            // A strict prefix is never a whole item
+            prop_assert!(Item::decode(&bytes[..bytes.len() - 1], &limits).is_err());
+        }
+
+        #[test]
+        fn test_generated_values_round_trip(
+            header in testing::block_header(),
+            tx in testing::transaction(),
+        ) {
+            let limits = DecodeLimits::default();
+            prop_assert_eq!(BlockHeader::from_rlp(&header.to_rlp(), &limits), Ok(header));
+            prop_assert_eq!(Transaction::from_rlp(&tx.to_rlp(), &limits), Ok(tx));
+        }
+    }
+
+    #[test]
+    fn test_encoding_pinned() {
+        let header = BlockBuilder::new(BlockHash::from_bytes([0; 32]))
+            .transaction(b"genesis".to_vec())
+            .timestamp(1_700_000_000)
+            .difficulty(Difficulty::LeadingZeroBits(8))
+            .nonce(42)
+            .build()
+            .unwrap();
+        assert_eq!(
+            hex::encode(header.header().to_rlp()),
+            concat!(
+                "f84f",
+                "01",
+                "a0",
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "a0",
+                "aeebad4a796fcc2e15dc4c6061b45ed9b373f26adfc798ca7d2d8cc58182718e",
+                "c0",
+                "846553f100",
+                "8420010000",
+                "2a",
+            )
+        );
+
+        let tx = Transaction {
+            inputs: vec![TxInput {
+                prev_out: OutPoint {
+                    txid: Txid::from_bytes([0x11; 32]),
+                    index: 1,
+                },
+                signatures: Vec::new(),
+                public_key: None,
+                recovery_id: None,
+            }],
+            outputs: vec![TxOutput::to_address(300, Address::from_bytes([0x44; 32]))],
+            lock_time: 0,
+        };
+        assert_eq!(
+            hex::encode(tx.to_rlp()),
+            concat!(
+                "f851",
+                "e7",
+                "e6",
+                "e2",
+                "a0",
+                "1111111111111111111111111111111111111111111111111111111111111111",
+                "01",
+                "c0",
+                "c0",
+                "c0",
+                "e7",
+                "e6",
+                "82012c",
+                "e2",
+                "80",
+                "a0",
+                "4444444444444444444444444444444444444444444444444444444444444444",
+                "80",
+            )
+        );
+    }
+
+    #[test]
+    fn test_malformed_items() {
+        use DecodeError::*;
+
+        let long = format!("b838{}", "61".repeat(56));
+        // An empty list inside `depth` more
+        let nested =
+            |depth| (0..depth).fold(Item::List(vec![]), |inner, _| Item::List(vec![inner]));
+        let cases: Vec<(String, DecodeError)> = vec![
+            (String::new(), UnexpectedEof),
+            ("83646f".to_string(), UnexpectedEof),
+            ("c88363617483646f".to_string(), UnexpectedEof),
+            // A list whose last item runs past the list's end
+            ("c28201".to_string() + "02", UnexpectedEof),
+            ("b9".to_string(), UnexpectedEof),
+            ("bf".to_string() + "ffffffffffffffff", UnexpectedEof),
+            ("8000".to_string(), TrailingBytes(1)),
+            (format!("{}61", long), TrailingBytes(1)),
+            // A single low byte behind a prefix
+            ("8100".to_string(), NonCanonicalEncoding),
+            ("817f".to_string(), NonCanonicalEncoding),
+            // The long form for a short payload, or with a leading zero
+            (format!("b803{}", "616263"), NonCanonicalEncoding),
+            (format!("b90038{}", "61".repeat(56)), NonCanonicalEncoding),
+            ("f800".to_string(), NonCanonicalEncoding),
+            (
+                hex::encode(nested(MAX_NESTING).encode()),
+                InvalidValue("rlp nesting"),
+            ),
+        ];
+        let limits = DecodeLimits::default();
+        for (input, expected) in cases {
+            let bytes = hex::decode(&input).unwrap();
+            assert_eq!(Item::decode(&bytes, &limits), Err(expected), "{}", input);
+        }
+        assert!(Item::decode(&hex::decode(long).unwrap(), &limits).is_ok());
+        assert!(Item::decode(&nested(MAX_NESTING - 1).encode(), &limits).is_ok());
+
+        let small = DecodeLimits {
+            max_decode_bytes: 2,
+            ..DecodeLimits::default()
+        };
+        assert!(matches!(
+            Item::decode(&[0x83, 0x64, 0x6f, 0x67], &small),
+            Err(LimitExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_malformed_values() {
+        let limits = DecodeLimits::default();
+        let header = SerdeFixtures::new().headers[0].clone();
+        let Item::List(fields) = Item::from(&header) else {
+            unreachable!()
+        };
+        let with = |index: usize, item: Item| {
+            let mut fields = fields.clone();
+            fields[index] = item;
+            BlockHeader::from_rlp(&Item::List(fields).encode(), &limits)
+        };
+
+        assert_eq!(
+            BlockHeader::from_rlp(&Item::List(fields[..6].to_vec()).encode(), &limits),
+            Err(DecodeError::InvalidValue("header"))
+        );
+        assert_eq!(
+            with(0, Item::Bytes(vec![0, 1])),
+            Err(DecodeError::NonCanonicalEncoding)
+        );
+        assert_eq!(
+            with(0, Item::uint(1 << 32)),
+            Err(DecodeError::InvalidValue("version"))
+        );
+        assert_eq!(
+            with(2, Item::Bytes(vec![1; 31])),
+            Err(DecodeError::InvalidValue("merkle root"))
+        );
+        // A state root on a version without one
+        assert_eq!(
+            with(3, Item::List(vec![Item::Bytes(vec![1; 32])])),
+            Err(DecodeError::InvalidValue("state root for header version"))
+        );
+        assert_eq!(
+            with(3, Item::List(vec![Item::List(vec![]), Item::List(vec![])])),
+            Err(DecodeError::LimitExceeded {
+                what: "state root",
+                value: 2,
+                max: 1
+            })
+        );
+        assert_eq!(
+            with(6, Item::List(vec![])),
+            Err(DecodeError::InvalidValue("nonce"))
+        );
+
+        let tx = SerdeFixtures::new().transaction;
+        let Item::List(fields) = Item::from(&tx) else {
+            unreachable!()
+        };
+        let with = |index: usize, item: Item| {
+            let mut fields = fields.clone();
+            fields[index] = item;
+            Transaction::from_rlp(&Item::List(fields).encode(), &limits)
+        };
+        let unknown_condition = Item::List(vec![Item::List(vec![
+            Item::uint(1),
+            Item::List(vec![Item::uint(2), Item::Bytes(vec![0x44; 32])]),
+        ])]);
+        assert_eq!(
+            with(1, unknown_condition),
+            Err(DecodeError::InvalidValue("condition"))
+        );
+        // Decodes, but is not a transaction the canonical decoder accepts
+        let zero_of_zero = Item::List(vec![Item::List(vec![
+            Item::uint(1),
+            Item::List(vec![Item::uint(1), Item::uint(0), Item::List(vec![])]),
+        ])]);
+        assert!(with(1, zero_of_zero).is_err());
+    }
+}
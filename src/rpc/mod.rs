@@ -0,0 +1,1001 @@
+//! JSON-RPC 2.0 queries over a shared chain, with transactions taken into a
+//! mempool, and read-only REST routes over the same for block explorers.
+//!
+//! [`Rpc`] answers request bodies and REST paths; [`RpcServer`] serves it
+//! over HTTP, a JSON-RPC request per `POST` and a REST query per `GET`.
+//! Parameters are positional. Hashes and txids are
+//! written as hex, and so are blocks, transactions and proofs in their
+//! encodings. Errors carry the codes of [`RpcError::code`]: the JSON-RPC
+//! ones for malformed requests and the conventional node ones for requests
+//! the chain or mempool cannot satisfy.
+//!
+//! A JSON-RPC response may instead be MessagePack, in which hashes, txids
+//! and encodings are bin rather than hex, for roughly half the size. A
+//! request picks it with an `"encoding": "msgpack"` member, or else with an
+//! `Accept` header naming `application/msgpack`; see [`Encoding`].
+//! [`parse_response`] reads a response in either encoding as the same
+//! [`Value`].
+
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use serde_json::{json, Value};
+use thiserror::Error;
+
+use crate::block::{Block, BlockHash};
+use crate::chain::{Blockchain, SharedChain};
+use crate::codec::DecodeLimits;
+use crate::encoding;
+use crate::mempool::Mempool;
+use crate::store::{ChainStore, MemoryStore};
+use crate::transaction::{Transaction, Txid};
+
+mod msgpack;
+mod rest;
+mod server;
+
+pub use rest::{DEFAULT_PAGE_BLOCKS, MAX_PAGE_BLOCKS, STATS_WINDOW};
+pub use server::{RpcServer, MAX_REQUEST_BYTES};
+
+/// Reasons a call failed, each with its code in the error response
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum RpcError {
+    /// The body is not valid JSON; code -32700
+    #[error("parse error: {0}")]
+    Parse(String),
+    /// The body is not a JSON-RPC 2.0 request; code -32600
+    #[error("invalid request: {0}")]
+    InvalidRequest(&'static str),
+    /// No method has the name; code -32601
+    #[error("method not found: {0}")]
+    MethodNotFound(String),
+    /// A parameter is missing, of the wrong type or out of range; code -32602
+    #[error("invalid params: {0}")]
+    InvalidParams(String),
+    /// No block or transaction has the hash, height or txid; code -5
+    #[error("{0} not found")]
+    NotFound(String),
+    /// Hex, or the bytes it holds, does not decode; code -22
+    #[error("decode failed: {0}")]
+    Decode(String),
+    /// The mempool refused the transaction; code -26
+    #[error("transaction rejected: {0}")]
+    Rejected(String),
+    /// The node cannot answer at all; code -32603
+    #[error("internal error: {0}")]
+    Internal(String),
+}
+
+impl RpcError {
+    pub fn code(&self) -> i64 {
+        match self {
+            RpcError::Parse(_) => -32700,
+            RpcError::InvalidRequest(_) => -32600,
+            RpcError::MethodNotFound(_) => -32601,
+            RpcError::InvalidParams(_) => -32602,
+            RpcError::NotFound(_) => -5,
+            RpcError::Decode(_) => -22,
+            RpcError::Rejected(_) => -26,
+            RpcError::Internal(_) => -32603,
+        }
+    }
+
+    /// The HTTP status of a REST query failing this way
+    pub fn http_status(&self) -> u16 {
+        match self {
+            RpcError::NotFound(_) | RpcError::MethodNotFound(_) => 404,
+            RpcError::Parse(_)
+            | RpcError::InvalidRequest(_)
+            | RpcError::InvalidParams(_)
+            | RpcError::Decode(_)
+            | RpcError::Rejected(_) => 400,
+            RpcError::Internal(_) => 500,
+        }
+    }
+
+    /// The `error` member of a response
+    pub fn to_value(&self) -> Value {
+        json!({
+            "code": self.code(),
+            "message": self.to_string(),
+        })
+    }
+}
+
+/// How a JSON-RPC response body is encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    /// MessagePack, with byte strings as bin
+    MessagePack,
+}
+
+impl Encoding {
+    /// The media type of a body in the encoding
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+        }
+    }
+
+    /// The encoding an `Accept` header asks for: MessagePack if it names
+    /// `application/msgpack` or `application/x-msgpack` without `q=0`, and
+    /// JSON otherwise
+    pub fn from_accept(accept: &str) -> Encoding {
+        let wants_msgpack = accept.split(',').any(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next().unwrap_or_default();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f64>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            !refused
+                && (media_type.eq_ignore_ascii_case("application/msgpack")
+                    || media_type.eq_ignore_ascii_case("application/x-msgpack"))
+        });
+        if wants_msgpack {
+            Encoding::MessagePack
+        } else {
+            Encoding::Json
+        }
+    }
+
+    /// The name a request's `encoding` member gives the encoding
+    fn from_name(name: &str) -> Option<Encoding> {
+        match name {
+            "json" => Some(Encoding::Json),
+            "msgpack" => Some(Encoding::MessagePack),
+            _ => None,
+        }
+    }
+
+    fn encode(self, payload: Payload) -> Vec<u8> {
+        match self {
+            Encoding::Json => payload.into_json().to_string().into_bytes(),
+            Encoding::MessagePack => msgpack::to_vec(&payload),
+        }
+    }
+}
+
+/// Read a response `body` in `encoding`, as a client of the server would,
+/// with MessagePack bin read as the hex JSON would have held
+pub fn parse_response(body: &[u8], encoding: Encoding) -> Result<Value, RpcError> {
+    match encoding {
+        Encoding::Json => {
+            serde_json::from_slice(body).map_err(|err| RpcError::Parse(err.to_string()))
+        }
+        Encoding::MessagePack => {
+            msgpack::to_json(body).map_err(|err| RpcError::Parse(err.to_string()))
+        }
+    }
+}
+
+/// A result as the methods build it: JSON values, with byte strings kept as
+/// bytes until the response's encoding writes them, as hex in JSON and as
+/// bin in MessagePack
+#[derive(Clone, Debug, PartialEq)]
+enum Payload {
+    Value(Value),
+    Bytes(Vec<u8>),
+    Array(Vec<Payload>),
+    Object(Vec<(&'static str, Payload)>),
+}
+
+impl Payload {
+    fn into_json(self) -> Value {
+        match self {
+            Payload::Value(value) => value,
+            Payload::Bytes(bytes) => hex::encode(bytes).into(),
+            Payload::Array(items) => {
+                Value::Array(items.into_iter().map(Payload::into_json).collect())
+            }
+            Payload::Object(fields) => Value::Object(
+                fields
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), value.into_json()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// The methods, over a chain and a mempool shared with the rest of the node
+pub struct Rpc<S: ChainStore = MemoryStore> {
+    chain: Arc<SharedChain<S>>,
+    mempool: Arc<Mutex<Mempool>>,
+}
+
+impl<S: ChainStore> Clone for Rpc<S> {
+    fn clone(&self) -> Self {
+        Rpc {
+            chain: Arc::clone(&self.chain),
+            mempool: Arc::clone(&self.mempool),
+        }
+    }
+}
+
+impl<S: ChainStore> Rpc<S> {
+    pub fn new(chain: Arc<SharedChain<S>>, mempool: Arc<Mutex<Mempool>>) -> Self {
+        Rpc { chain, mempool }
+    }
+
+    /// Answer the request in `body` with the response body, or with `None`
+    /// for a notification, a request without an `id`. The response is JSON
+    /// whatever encoding the request asks for.
+    pub fn handle(&self, body: &[u8]) -> Option<String> {
+        let (_, response) = self.answer(body)?;
+        Some(response.into_json().to_string())
+    }
+
+    /// Answer the request in `body` like [`Rpc::handle`], in the encoding
+    /// the request's `encoding` member names or else in `accept`, returning
+    /// the encoding with the body
+    pub fn handle_encoded(&self, body: &[u8], accept: Encoding) -> Option<(Encoding, Vec<u8>)> {
+        let (requested, response) = self.answer(body)?;
+        let encoding = requested.unwrap_or(accept);
+        Some((encoding, encoding.encode(response)))
+    }
+
+    /// The response to the request in `body`, with the encoding it asks
+    /// for, if any
+    fn answer(&self, body: &[u8]) -> Option<(Option<Encoding>, Payload)> {
+        match parse_request(body) {
+            Ok(request) => {
+                let result = self.dispatch(&request.method, &request.params);
+                Some((request.encoding, response(request.id?, result)))
+            }
+            Err(err) => Some((None, response(Value::Null, Err(err)))),
+        }
+    }
+
+    /// Run `method` with positional `params`:
+    ///
+    /// - `getblockcount`: the height of the active tip
+    /// - `getbestblockhash`: the hash of the active tip
+    /// - `getblockhash(height)`: the hash of the active block at `height`
+    /// - `getblock(hash, verbosity = 1)`: the block in hex for verbosity 0,
+    ///   or its header fields and txids as an object for 1; blocks off the
+    ///   active chain are found too, with -1 confirmations
+    /// - `getrawtransaction(txid)`: the transaction in hex, from the mempool
+    ///   or else from the chain's transaction index
+    /// - `sendrawtransaction(hex)`: takes the transaction into the mempool,
+    ///   paying the fee its inputs leave in the chain's unspent outputs, and
+    ///   returns its txid
+    /// - `getmerkleproof(txid)`: the indexed transaction bundled with its
+    ///   block header and merkle proof, as [`crate::chain::TxWithProof`] in
+    ///   hex
+    pub fn call(&self, method: &str, params: &[Value]) -> Result<Value, RpcError> {
+        self.dispatch(method, params).map(Payload::into_json)
+    }
+
+    fn dispatch(&self, method: &str, params: &[Value]) -> Result<Payload, RpcError> {
+        match method {
+            "getblockcount" => {
+                arity(params, 0)?;
+                Ok(Payload::Value(self.chain.height().into()))
+            }
+            "getbestblockhash" => {
+                arity(params, 0)?;
+                Ok(Payload::Bytes(self.chain.tip_hash().to_vec()))
+            }
+            "getblockhash" => {
+                arity(params, 1)?;
+                let height = u64_param(params, 0, "height")?;
+                self.chain
+                    .with_read(|chain| Some(chain.get(height)?.hash()))
+                    .map(|hash| Payload::Bytes(hash.to_vec()))
+                    .ok_or_else(|| RpcError::NotFound(format!("block at height {}", height)))
+            }
+            "getblock" => {
+                arity(params, 2)?;
+                let hash = hash_param(params, 0, "hash")?;
+                let verbosity = match params.get(1) {
+                    Some(_) => u64_param(params, 1, "verbosity")?,
+                    None => 1,
+                };
+                if verbosity > 1 {
+                    return Err(RpcError::InvalidParams(format!(
+                        "verbosity {} is not 0 or 1",
+                        verbosity
+                    )));
+                }
+                self.chain
+                    .with_read(|chain| {
+                        let block = find_block(chain, &hash)?;
+                        Some(match verbosity {
+                            0 => Payload::Bytes(block.to_bytes()),
+                            _ => block_payload(chain, block),
+                        })
+                    })
+                    .ok_or_else(|| RpcError::NotFound(format!("block {}", hash)))
+            }
+            "getrawtransaction" => {
+                arity(params, 1)?;
+                let txid = txid_param(params, 0)?;
+                self.find_transaction(&txid)
+                    .map(|found| Payload::Bytes(found.tx))
+                    .ok_or_else(|| RpcError::NotFound(format!("transaction {}", txid)))
+            }
+            "sendrawtransaction" => {
+                arity(params, 1)?;
+                let bytes = encoding::from_hex(str_param(params, 0, "hex")?)
+                    .map_err(|err| RpcError::Decode(format!("transaction hex: {}", err)))?;
+                let tx = Transaction::decode(&bytes, &DecodeLimits::default())
+                    .map_err(|err| RpcError::Decode(err.to_string()))?;
+                let fee = self.chain.with_read(|chain| {
+                    let utxos = chain.utxo_set().ok_or_else(|| {
+                        RpcError::Internal("chain does not track unspent outputs".to_string())
+                    })?;
+                    tx.verify_spends(utxos)
+                        .map_err(|err| RpcError::Rejected(err.to_string()))?;
+                    tx.fee(utxos)
+                        .map_err(|err| RpcError::Rejected(err.to_string()))
+                })?;
+                let txid = self
+                    .mempool()
+                    .insert(tx, fee)
+                    .map_err(|err| RpcError::Rejected(err.to_string()))?;
+                Ok(Payload::Bytes(txid.as_bytes().to_vec()))
+            }
+            "getmerkleproof" => {
+                arity(params, 1)?;
+                let txid = txid_param(params, 0)?;
+                self.chain
+                    .with_read(|chain| chain.get_transaction_with_proof(txid.as_bytes()))
+                    .map(|found| Payload::Bytes(found.to_bytes()))
+                    .ok_or_else(|| RpcError::NotFound(format!("transaction {}", txid)))
+            }
+            _ => Err(RpcError::MethodNotFound(method.to_string())),
+        }
+    }
+
+    /// The transaction `txid` from the mempool, or else from the chain's
+    /// transaction index
+    fn find_transaction(&self, txid: &Txid) -> Option<FoundTx> {
+        if let Some(tx) = self.mempool().get(txid) {
+            return Some(FoundTx {
+                tx: tx.encode(),
+                block: None,
+            });
+        }
+        self.chain.with_read(|chain| {
+            let found = chain.get_transaction_with_proof(txid.as_bytes())?;
+            let confirmations = chain.height() - found.height + 1;
+            Some(FoundTx {
+                tx: found.tx,
+                block: Some((found.block_header.hash(), found.height, confirmations)),
+            })
+        })
+    }
+
+    /// A lock that panicked while held may have left the pool half-updated,
+    /// so poisoning is passed on rather than ignored
+    #[allow(clippy::expect_used)]
+    fn mempool(&self) -> MutexGuard<'_, Mempool> {
+        self.mempool
+            .lock()
+            .expect("a writer panicked while updating the mempool")
+    }
+}
+
+/// A transaction as [`Rpc::find_transaction`] finds it
+struct FoundTx {
+    tx: Vec<u8>,
+    /// The hash and height of the block holding it, and its confirmations;
+    /// `None` while it is pooled
+    block: Option<(BlockHash, u64, u64)>,
+}
+
+struct Request {
+    /// `None` for a notification
+    id: Option<Value>,
+    method: String,
+    params: Vec<Value>,
+    /// The encoding the `encoding` member asks the response to be in
+    encoding: Option<Encoding>,
+}
+
+fn parse_request(body: &[u8]) -> Result<Request, RpcError> {
+    let request: Value =
+        serde_json::from_slice(body).map_err(|err| RpcError::Parse(err.to_string()))?;
+    if request.as_object().is_none() {
+        return Err(RpcError::InvalidRequest("request is not an object"));
+    }
+    if request.get("jsonrpc").and_then(Value::as_str) != Some("2.0") {
+        return Err(RpcError::InvalidRequest("jsonrpc is not \"2.0\""));
+    }
+    let id = match request.get("id") {
+        Some(id @ (Value::Null | Value::Number(_) | Value::String(_))) => Some(id.clone()),
+        Some(_) => return Err(RpcError::InvalidRequest("id is not a string or number")),
+        None => None,
+    };
+    let method = request
+        .get("method")
+        .and_then(Value::as_str)
+        .ok_or(RpcError::InvalidRequest("method is not a string"))?
+        .to_string();
+    let params = match request.get("params") {
+        None => Vec::new(),
+        Some(Value::Array(params)) => params.clone(),
+        Some(_) => return Err(RpcError::InvalidRequest("params is not an array")),
+    };
+    let encoding = match request.get("encoding") {
+        None => None,
+        Some(name) => Some(name.as_str().and_then(Encoding::from_name).ok_or(
+            RpcError::InvalidRequest("encoding is not \"json\" or \"msgpack\""),
+        )?),
+    };
+    Ok(Request {
+        id,
+        method,
+        params,
+        encoding,
+    })
+}
+
+/// The response carrying `result` for the request `id`
+fn response(id: Value, result: Result<Payload, RpcError>) -> Payload {
+    let outcome = match result {
+        Ok(payload) => ("result", payload),
+        Err(err) => ("error", Payload::Value(err.to_value())),
+    };
+    Payload::Object(vec![
+        ("jsonrpc", Payload::Value("2.0".into())),
+        outcome,
+        ("id", Payload::Value(id)),
+    ])
+}
+
+fn arity(params: &[Value], max: usize) -> Result<(), RpcError> {
+    if params.len() > max {
+        return Err(RpcError::InvalidParams(format!(
+            "expected at most {} params, got {}",
+            max,
+            params.len()
+        )));
+    }
+    Ok(())
+}
+
+fn param<'a>(params: &'a [Value], index: usize, name: &str) -> Result<&'a Value, RpcError> {
+    params
+        .get(index)
+        .ok_or_else(|| RpcError::InvalidParams(format!("missing {}", name)))
+}
+
+fn u64_param(params: &[Value], index: usize, name: &str) -> Result<u64, RpcError> {
+    param(params, index, name)?
+        .as_u64()
+        .ok_or_else(|| RpcError::InvalidParams(format!("{} is not an unsigned integer", name)))
+}
+
+fn str_param<'a>(params: &'a [Value], index: usize, name: &str) -> Result<&'a str, RpcError> {
+    param(params, index, name)?
+        .as_str()
+        .ok_or_else(|| RpcError::InvalidParams(format!("{} is not a string", name)))
+}
+
+fn hash_param(params: &[Value], index: usize, name: &str) -> Result<BlockHash, RpcError> {
+    str_param(params, index, name)?
+        .parse()
+        .map_err(|err| RpcError::InvalidParams(format!("{}: {}", name, err)))
+}
+
+fn txid_param(params: &[Value], index: usize) -> Result<Txid, RpcError> {
+    parse_txid(str_param(params, index, "txid")?)
+}
+
+fn parse_txid(hex: &str) -> Result<Txid, RpcError> {
+    hex.parse()
+        .map_err(|err| RpcError::InvalidParams(format!("txid: {}", err)))
+}
+
+/// The block with `hash` on the active chain or any branch of the tree
+fn find_block<'a, S: ChainStore>(chain: &'a Blockchain<S>, hash: &BlockHash) -> Option<&'a Block> {
+    match chain.get_by_hash(hash.as_ref()) {
+        Some(block) => Some(block),
+        None => Some(chain.tree().get(hash)?.block()),
+    }
+}
+
+fn block_value<S: ChainStore>(chain: &Blockchain<S>, block: &Block) -> Value {
+    block_payload(chain, block).into_json()
+}
+
+fn block_payload<S: ChainStore>(chain: &Blockchain<S>, block: &Block) -> Payload {
+    let hash = block.hash();
+    let header = block.header();
+    let (height, confirmations) = match chain.height_of(hash.as_ref()) {
+        Some(height) => (height, (chain.height() - height + 1) as i64),
+        None => (
+            chain.tree().get(&hash).map_or(0, |stored| stored.height()),
+            -1,
+        ),
+    };
+    let txids = block
+        .transactions()
+        .iter()
+        .map(|tx| Payload::Bytes(Txid::of(tx).as_bytes().to_vec()))
+        .collect();
+    Payload::Object(vec![
+        ("hash", Payload::Bytes(hash.to_vec())),
+        ("confirmations", Payload::Value(confirmations.into())),
+        ("height", Payload::Value(height.into())),
+        ("version", Payload::Value(header.version().into())),
+        (
+            "previousblockhash",
+            Payload::Bytes(header.prev_block_hash().to_vec()),
+        ),
+        ("merkleroot", Payload::Bytes(header.merkle_root().to_vec())),
+        (
+            "stateroot",
+            header
+                .state_root()
+                .map_or(Payload::Value(Value::Null), |root| {
+                    Payload::Bytes(root.to_vec())
+                }),
+        ),
+        ("time", Payload::Value(header.timestamp().into())),
+        ("bits", Payload::Value(header.bits().into())),
+        ("nonce", Payload::Value(header.nonce().into())),
+        ("tx", Payload::Array(txids)),
+    ])
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::chain::TxWithProof;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::params::ChainParams;
+    use crate::testing::signed_spend;
+    use crate::transaction::OutPoint;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    /// A server over a chain whose genesis pays out `funding` and whose
+    /// block at height 1 holds `coinbase`, both to [`testing::key`], and the
+    /// URL to reach it at
+    ///
+    /// [`testing::key`]: crate::testing::key
+    pub(crate) struct Fixture {
+        pub(crate) server: RpcServer,
+        pub(crate) url: String,
+        pub(crate) funding: Transaction,
+        pub(crate) coinbase: Transaction,
+        pub(crate) genesis: Block,
+        pub(crate) block: Block,
+    }
+
+    pub(crate) fn start() -> Fixture {
+        let funding = signed_spend(&[], &[1000]);
+        let params = ChainParams {
+            genesis_transactions: vec![funding.encode()],
+            ..ChainParams::test_defaults()
+        };
+        let mut chain = Blockchain::new_from_params(&params)
+            .with_utxo_set()
+            .unwrap()
+            .with_tx_index()
+            .unwrap();
+        let genesis = chain.tip().clone();
+        let coinbase = signed_spend(&[], &[50]);
+        let mut block = genesis
+            .next_builder()
+            .transaction(coinbase.clone())
+            .difficulty(params.initial_difficulty)
+            .timestamp(genesis.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(params.initial_difficulty);
+        chain.append(block.clone()).unwrap();
+
+        let rpc = Rpc::new(
+            Arc::new(SharedChain::new(chain)),
+            Arc::new(Mutex::new(Mempool::new())),
+        );
+        let server = RpcServer::start("127.0.0.1:0", rpc).unwrap();
+        Fixture {
+            url: format!("http://{}/", server.local_addr()),
+            server,
+            funding,
+            coinbase,
+            genesis,
+            block,
+        }
+    }
+
+    /// Send `body` to the server with `method`, returning the status and the
+    /// response body
+    fn request(server: &RpcServer, method: &str, body: &str) -> (u16, String) {
+        send(server, method, "/", body)
+    }
+
+    /// Like `request`, for `path` rather than the root
+    pub(super) fn send(server: &RpcServer, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            method,
+            path,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        let status = reply[9..12].parse().unwrap();
+        let (_, body) = reply.split_once("\r\n\r\n").unwrap();
+        (status, body.to_string())
+    }
+
+    /// Post `body` with the extra header lines `headers`, returning the
+    /// head of the response and its body
+    fn post_raw(server: &RpcServer, headers: &str, body: &str) -> (String, Vec<u8>) {
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            headers,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).unwrap();
+        let end = reply.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8(reply[..end].to_vec()).unwrap();
+        (head, reply[end + 4..].to_vec())
+    }
+
+    /// The parsed response to `body`, which must come with status 200
+    fn post(server: &RpcServer, body: &str) -> Value {
+        let (status, body) = request(server, "POST", body);
+        assert_eq!(status, 200);
+        serde_json::from_str(&body).unwrap()
+    }
+
+    /// Call `method`, returning its result or the code of its error
+    pub(super) fn call(server: &RpcServer, method: &str, params: Vec<Value>) -> Result<Value, i64> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": Value::Array(params),
+        });
+        let response = post(server, &request.to_string());
+        assert_eq!(response.get("jsonrpc"), Some(&"2.0".into()));
+        assert_eq!(response.get("id"), Some(&1.into()));
+        match response.get("error") {
+            Some(error) => Err(error.get("code").unwrap().as_i64().unwrap()),
+            None => Ok(response.get("result").unwrap().clone()),
+        }
+    }
+
+    fn hex_of(value: Result<Value, i64>) -> Vec<u8> {
+        hex::decode(value.unwrap().as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_block_queries() {
+        let Fixture {
+            server,
+            coinbase,
+            genesis,
+            block,
+            ..
+        } = start();
+        let hash = block.hash().to_string();
+
+        assert_eq!(call(&server, "getblockcount", vec![]), Ok(1.into()));
+        assert_eq!(
+            call(&server, "getbestblockhash", vec![]),
+            Ok(hash.clone().into())
+        );
+        assert_eq!(
+            call(&server, "getblockhash", vec![0.into()]),
+            Ok(genesis.hash().to_string().into())
+        );
+        assert_eq!(
+            call(&server, "getblockhash", vec![1.into()]),
+            Ok(hash.clone().into())
+        );
+        assert_eq!(call(&server, "getblockhash", vec![2.into()]), Err(-5));
+        assert_eq!(call(&server, "getblockhash", vec!["1".into()]), Err(-32602));
+        assert_eq!(call(&server, "getblockhash", vec![]), Err(-32602));
+
+        assert_eq!(
+            hex_of(call(
+                &server,
+                "getblock",
+                vec![hash.clone().into(), 0.into()]
+            )),
+            block.to_bytes()
+        );
+        let object = call(&server, "getblock", vec![hash.clone().into()]).unwrap();
+        assert_eq!(object.get("hash"), Some(&hash.clone().into()));
+        assert_eq!(object.get("height"), Some(&1.into()));
+        assert_eq!(object.get("confirmations"), Some(&1.into()));
+        assert_eq!(
+            object.get("previousblockhash"),
+            Some(&genesis.hash().to_string().into())
+        );
+        assert_eq!(
+            object.get("tx"),
+            Some(&vec![coinbase.txid().to_string()].into())
+        );
+
+        let unknown = BlockHash::from_bytes([9; 32]).to_string();
+        assert_eq!(call(&server, "getblock", vec![unknown.into()]), Err(-5));
+        assert_eq!(call(&server, "getblock", vec!["zz".into()]), Err(-32602));
+        assert_eq!(
+            call(&server, "getblock", vec![hash.into(), 2.into()]),
+            Err(-32602)
+        );
+        server.stop();
+    }
+
+    #[test]
+    fn test_getblock_in_messagepack() {
+        let Fixture { server, block, .. } = start();
+        for verbosity in [0, 1] {
+            let request = format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"getblock","params":["{}",{}]}}"#,
+                block.hash(),
+                verbosity
+            );
+            let (head, json) = post_raw(&server, "", &request);
+            assert!(head.contains("Content-Type: application/json"), "{}", head);
+            let (head, msgpack) = post_raw(&server, "Accept: application/msgpack\r\n", &request);
+            assert!(
+                head.contains("Content-Type: application/msgpack"),
+                "{}",
+                head
+            );
+
+            let expected = parse_response(&json, Encoding::Json).unwrap();
+            assert!(expected.get("result").is_some());
+            assert_eq!(
+                parse_response(&msgpack, Encoding::MessagePack),
+                Ok(expected)
+            );
+            // Hashes and the block are bin, half the size of their hex
+            let bin = match verbosity {
+                0 => block.to_bytes(),
+                _ => [&[0xc4, 0x20][..], block.hash().as_bytes()].concat(),
+            };
+            assert!(msgpack.windows(bin.len()).any(|w| w == bin));
+            assert!(
+                msgpack.len() * 3 < json.len() * 2,
+                "{} bytes of MessagePack against {} of JSON",
+                msgpack.len(),
+                json.len()
+            );
+        }
+        server.stop();
+    }
+
+    #[test]
+    fn test_encoding_negotiation() {
+        let cases = [
+            ("application/json", Encoding::Json),
+            ("*/*", Encoding::Json),
+            ("Application/MsgPack", Encoding::MessagePack),
+            (
+                "text/html, application/x-msgpack;q=0.9",
+                Encoding::MessagePack,
+            ),
+            ("application/msgpack;q=0, application/json", Encoding::Json),
+        ];
+        for (accept, expected) in cases {
+            assert_eq!(Encoding::from_accept(accept), expected, "{}", accept);
+        }
+
+        let Fixture { server, .. } = start();
+        let request = |encoding: &str| {
+            format!(
+                r#"{{"jsonrpc":"2.0","id":1,"method":"getblockcount","encoding":"{}"}}"#,
+                encoding
+            )
+        };
+        let expected = json!({
+            "jsonrpc": "2.0",
+            "result": 1,
+            "id": 1,
+        });
+        // The request's member wins over the header
+        for (headers, encoding, content_type) in [
+            ("", "msgpack", Encoding::MessagePack),
+            ("Accept: application/msgpack\r\n", "json", Encoding::Json),
+        ] {
+            let (head, body) = post_raw(&server, headers, &request(encoding));
+            assert!(head.contains(content_type.content_type()), "{}", head);
+            assert_eq!(parse_response(&body, content_type), Ok(expected.clone()));
+        }
+        let (_, body) = post_raw(&server, "Accept: application/msgpack\r\n", &request("cbor"));
+        let response = parse_response(&body, Encoding::MessagePack).unwrap();
+        assert_eq!(
+            response.get("error").and_then(|error| error.get("code")),
+            Some(&(-32600).into())
+        );
+        assert_eq!(
+            parse_response(b"\xc1", Encoding::MessagePack).map_err(|err| err.code()),
+            Err(-32700)
+        );
+        server.stop();
+    }
+
+    #[test]
+    fn test_transaction_methods() {
+        let Fixture {
+            server,
+            funding,
+            coinbase,
+            block,
+            ..
+        } = start();
+        let mined = coinbase.txid().to_string();
+        assert_eq!(
+            hex_of(call(
+                &server,
+                "getrawtransaction",
+                vec![mined.clone().into()]
+            )),
+            coinbase.encode()
+        );
+        let proof = hex_of(call(&server, "getmerkleproof", vec![mined.into()]));
+        let proof = TxWithProof::from_bytes(&proof, &DecodeLimits::default()).unwrap();
+        assert!(proof.verify(block.hash().as_bytes()));
+        assert_eq!(proof.tx, coinbase.encode());
+
+        let spend = signed_spend(
+            &[OutPoint {
+                txid: funding.txid(),
+                index: 0,
+            }],
+            &[900],
+        );
+        // Unsigned, and signed by a key the spent output does not pay to
+        let mut unsigned = spend.clone();
+        unsigned.inputs[0].signatures.clear();
+        let mut stolen = spend.clone();
+        stolen.sign_input(0, &SigningKey::from_bytes(&[8; 32]));
+        for tx in [unsigned, stolen] {
+            assert_eq!(
+                call(
+                    &server,
+                    "sendrawtransaction",
+                    vec![hex::encode(tx.encode()).into()]
+                ),
+                Err(-26)
+            );
+        }
+        let raw = hex::encode(spend.encode());
+        assert_eq!(
+            call(&server, "sendrawtransaction", vec![raw.clone().into()]),
+            Ok(spend.txid().to_string().into())
+        );
+        let pooled = spend.txid().to_string();
+        assert_eq!(
+            hex_of(call(
+                &server,
+                "getrawtransaction",
+                vec![pooled.clone().into()]
+            )),
+            spend.encode()
+        );
+        // Pooled but not mined, so there is nothing to prove
+        assert_eq!(
+            call(&server, "getmerkleproof", vec![pooled.into()]),
+            Err(-5)
+        );
+
+        // Already pooled, and spending an output that does not exist
+        assert_eq!(
+            call(&server, "sendrawtransaction", vec![raw.into()]),
+            Err(-26)
+        );
+        let missing = signed_spend(
+            &[OutPoint {
+                txid: Txid::of(b"nothing"),
+                index: 0,
+            }],
+            &[1],
+        );
+        assert_eq!(
+            call(
+                &server,
+                "sendrawtransaction",
+                vec![hex::encode(missing.encode()).into()]
+            ),
+            Err(-26)
+        );
+        // Malformed hex, and hex that holds no transaction
+        assert_eq!(
+            call(&server, "sendrawtransaction", vec!["0xzz".into()]),
+            Err(-22)
+        );
+        assert_eq!(
+            call(&server, "sendrawtransaction", vec!["00ff".into()]),
+            Err(-22)
+        );
+
+        let unknown = Txid::of(b"unknown").to_string();
+        assert_eq!(
+            call(&server, "getrawtransaction", vec![unknown.into()]),
+            Err(-5)
+        );
+        assert_eq!(
+            call(&server, "getrawtransaction", vec!["abcd".into()]),
+            Err(-32602)
+        );
+        server.stop();
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        let Fixture { server, .. } = start();
+        let code = |response: &Value| {
+            assert_eq!(response.get("id"), Some(&Value::Null));
+            response.get("error")?.get("code")?.as_i64()
+        };
+        assert_eq!(code(&post(&server, "{")), Some(-32700));
+        assert_eq!(code(&post(&server, "[]")), Some(-32600));
+        assert_eq!(
+            code(&post(&server, r#"{"id":1,"method":"getblockcount"}"#)),
+            Some(-32600)
+        );
+        assert_eq!(
+            code(&post(
+                &server,
+                r#"{"jsonrpc":"2.0","id":1,"method":"getblockcount","params":{}}"#
+            )),
+            Some(-32600)
+        );
+        assert_eq!(call(&server, "getblocks", vec![]), Err(-32601));
+        assert_eq!(call(&server, "getblockcount", vec![1.into()]), Err(-32602));
+        assert_eq!(
+            call(&server, "getrawtransaction", vec!["0x1z".into()]),
+            Err(-32602)
+        );
+
+        // String ids are echoed, and notifications get no response
+        let response = post(
+            &server,
+            r#"{"jsonrpc":"2.0","id":"a","method":"getblockcount"}"#,
+        );
+        assert_eq!(response.get("id"), Some(&"a".into()));
+        assert_eq!(
+            request(
+                &server,
+                "POST",
+                r#"{"jsonrpc":"2.0","method":"getblockcount"}"#
+            ),
+            (204, String::new())
+        );
+
+        assert_eq!(request(&server, "PUT", "").0, 405);
+
+        // An oversized body is refused on its declared length alone
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        write!(
+            stream,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n",
+            MAX_REQUEST_BYTES + 1
+        )
+        .unwrap();
+        stream.shutdown(std::net::Shutdown::Write).unwrap();
+        let mut reply = String::new();
+        stream.read_to_string(&mut reply).unwrap();
+        assert!(reply.starts_with("HTTP/1.1 413"));
+        let (_, body) = reply.split_once("\r\n\r\n").unwrap();
+        assert_eq!(code(&serde_json::from_str(body).unwrap()), Some(-32600));
+        server.stop();
+    }
+}
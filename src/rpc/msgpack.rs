@@ -0,0 +1,159 @@
+//! Writing responses as MessagePack, and reading them back as JSON values.
+//!
+//! A response is written as JSON would have it, with objects as maps from
+//! text keys, except that byte strings are bin rather than hex text and
+//! numbers are integers or floats rather than their digits. Reading maps
+//! bin back to hex, so both encodings of a response read as the same
+//! [`Value`].
+
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+use serde_json::{Map, Value};
+
+use super::Payload;
+
+/// The MessagePack encoding of `payload`
+#[allow(clippy::expect_used)]
+pub(super) fn to_vec(payload: &Payload) -> Vec<u8> {
+    rmp_serde::to_vec(payload).expect("payloads have only text keys")
+}
+
+/// The response MessagePack `body` holds, with bin read as hex
+pub(super) fn to_json(body: &[u8]) -> Result<Value, rmp_serde::decode::Error> {
+    rmp_serde::from_slice::<Json>(body).map(|json| json.0)
+}
+
+impl Serialize for Payload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Payload::Value(value) => JsonRef(value).serialize(serializer),
+            Payload::Bytes(bytes) => serializer.serialize_bytes(bytes),
+            Payload::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(item)?;
+                }
+                seq.end()
+            }
+            Payload::Object(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// A JSON value written through serde, its numbers as the narrowest of
+/// `u64`, `i64` and `f64` holding them. `Value`'s own `Serialize` keeps the
+/// digits of a number by writing it as a map only serde_json understands.
+struct JsonRef<'a>(&'a Value);
+
+impl Serialize for JsonRef<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self.0 {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Number(number) => {
+                if let Some(n) = number.as_u64() {
+                    serializer.serialize_u64(n)
+                } else if let Some(n) = number.as_i64() {
+                    serializer.serialize_i64(n)
+                } else if let Some(n) = number.as_f64() {
+                    serializer.serialize_f64(n)
+                } else {
+                    Err(serde::ser::Error::custom(format!(
+                        "number {} has no MessagePack form",
+                        number
+                    )))
+                }
+            }
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(&JsonRef(item))?;
+                }
+                seq.end()
+            }
+            Value::Object(fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields {
+                    map.serialize_entry(key, &JsonRef(value))?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// A JSON value read through serde, byte strings as hex
+struct Json(Value);
+
+impl<'de> Deserialize<'de> for Json {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(JsonVisitor).map(Json)
+    }
+}
+
+struct JsonVisitor;
+
+impl<'de> Visitor<'de> for JsonVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("a value with a JSON form")
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Value, E> {
+        Ok(value.into())
+    }
+
+    fn visit_bytes<E: de::Error>(self, value: &[u8]) -> Result<Value, E> {
+        Ok(hex::encode(value).into())
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut items = Vec::new();
+        while let Some(Json(item)) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Value::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut fields = Map::new();
+        while let Some((key, Json(value))) = map.next_entry::<String, Json>()? {
+            fields.insert(key, value);
+        }
+        Ok(Value::Object(fields))
+    }
+}
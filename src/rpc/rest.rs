@@ -0,0 +1,289 @@
+//! Read-only REST routes for block explorers.
+//!
+//! Each route answers a `GET` with a JSON body: the same block objects as
+//! `getblock` at verbosity 1, and plain objects for the rest. A query for an
+//! item that does not exist fails with status 404, and one with a malformed
+//! height, hash or parameter with 400, the body holding the error as a
+//! JSON-RPC response would.
+
+use serde_json::{json, Value};
+
+use super::{block_value, find_block, parse_txid, Rpc, RpcError};
+use crate::block::BlockHash;
+use crate::chain::ChainStats;
+use crate::store::ChainStore;
+
+/// Blocks a `/blocks` page lists when the query does not say
+pub const DEFAULT_PAGE_BLOCKS: u64 = 20;
+
+/// Most blocks a `/blocks` page lists, however many the query asks for
+pub const MAX_PAGE_BLOCKS: u64 = 100;
+
+/// Number of blocks up to the tip that `/stats` covers
+pub const STATS_WINDOW: usize = 100;
+
+impl<S: ChainStore> Rpc<S> {
+    /// Answer a `GET` of `url`, a path with an optional query string:
+    ///
+    /// - `/tip`: the height, hash, time and total work of the active tip
+    /// - `/block/height/{n}`: the active block at height `n`
+    /// - `/block/hash/{hash}`: the block with `hash`, on any branch
+    /// - `/tx/{txid}`: the transaction in hex, with the block holding it
+    ///   and its confirmations, or null and 0 while it is pooled
+    /// - `/stats`: [`ChainStats`] over the last [`STATS_WINDOW`] blocks,
+    ///   and the size of the mempool
+    /// - `/blocks?from=&count=`: the active blocks from height `from`,
+    ///   default 0, at most `count` of them, default
+    ///   [`DEFAULT_PAGE_BLOCKS`] and capped at [`MAX_PAGE_BLOCKS`], with the
+    ///   height to ask for the next page from, or null past the tip
+    pub fn get(&self, url: &str) -> Result<Value, RpcError> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["tip"] => {
+                let view = self.chain.view();
+                Ok(json!({
+                    "height": view.height,
+                    "hash": view.tip_hash.to_string(),
+                    "time": view.tip.timestamp(),
+                    "totalwork": view.total_work.0,
+                }))
+            }
+            ["block", "height", height] => {
+                let height: u64 = height.parse().map_err(|_| {
+                    RpcError::InvalidParams(format!(
+                        "height {:?} is not an unsigned integer",
+                        height
+                    ))
+                })?;
+                self.chain
+                    .with_read(|chain| Some(block_value(chain, chain.get(height)?)))
+                    .ok_or_else(|| RpcError::NotFound(format!("block at height {}", height)))
+            }
+            ["block", "hash", hash] => {
+                let hash: BlockHash = hash
+                    .parse()
+                    .map_err(|err| RpcError::InvalidParams(format!("hash: {}", err)))?;
+                self.chain
+                    .with_read(|chain| Some(block_value(chain, find_block(chain, &hash)?)))
+                    .ok_or_else(|| RpcError::NotFound(format!("block {}", hash)))
+            }
+            ["tx", txid] => {
+                let txid = parse_txid(txid)?;
+                let found = self
+                    .find_transaction(&txid)
+                    .ok_or_else(|| RpcError::NotFound(format!("transaction {}", txid)))?;
+                let (block, height, confirmations) = match found.block {
+                    Some((hash, height, confirmations)) => {
+                        (hash.to_string().into(), height.into(), confirmations)
+                    }
+                    None => (Value::Null, Value::Null, 0),
+                };
+                Ok(json!({
+                    "txid": txid.to_string(),
+                    "size": found.tx.len(),
+                    "hex": hex::encode(&found.tx),
+                    "blockhash": block,
+                    "height": height,
+                    "confirmations": confirmations,
+                }))
+            }
+            ["stats"] => {
+                let stats = self
+                    .chain
+                    .with_read(|chain| chain.stats(STATS_WINDOW))
+                    .map_err(|err| RpcError::Internal(err.to_string()))?;
+                Ok(stats_value(&stats, self.mempool().len()))
+            }
+            ["blocks"] => {
+                let from = query_u64(query, "from")?.unwrap_or(0);
+                let count = query_u64(query, "count")?
+                    .unwrap_or(DEFAULT_PAGE_BLOCKS)
+                    .min(MAX_PAGE_BLOCKS);
+                Ok(self.chain.with_read(|chain| {
+                    let blocks: Vec<Value> = (from..from.saturating_add(count))
+                        .map_while(|height| chain.get(height))
+                        .map(|block| block_value(chain, block))
+                        .collect();
+                    let next = from + blocks.len() as u64;
+                    json!({
+                        "from": from,
+                        "blocks": blocks,
+                        "next": (next <= chain.height()).then_some(next),
+                    })
+                }))
+            }
+            _ => Err(RpcError::NotFound(format!("route {}", path))),
+        }
+    }
+}
+
+/// The value of `key` in a query string as an unsigned integer, if given
+fn query_u64(query: &str, key: &str) -> Result<Option<u64>, RpcError> {
+    let Some((_, value)) = query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == key)
+    else {
+        return Ok(None);
+    };
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| RpcError::InvalidParams(format!("{} is not an unsigned integer", key)))
+}
+
+fn stats_value(stats: &ChainStats, mempool_transactions: usize) -> Value {
+    json!({
+        "blocks": stats.blocks,
+        "averageblockinterval": stats.average_block_interval,
+        "transactions": stats.transactions,
+        "meantransactionsperblock": stats.mean_transactions_per_block,
+        "bits": stats.difficulty.to_compact(),
+        "totalwork": stats.total_work.0,
+        "tipage": stats.tip_age,
+        "mempooltransactions": mempool_transactions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::{call, send, start, Fixture};
+    use super::super::RpcServer;
+    use super::*;
+    use crate::testing::signed_spend;
+    use crate::transaction::OutPoint;
+
+    /// The status and parsed body of a `GET` of `path`
+    fn get(server: &RpcServer, path: &str) -> (u16, Value) {
+        let (status, body) = send(server, "GET", path, "");
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    /// The status a `GET` of `path` fails with, checking the body holds the
+    /// error
+    fn status(server: &RpcServer, path: &str) -> u16 {
+        let (status, body) = get(server, path);
+        assert!(body.get("error").and_then(|err| err.get("code")).is_some());
+        status
+    }
+
+    #[test]
+    fn test_rest_routes() {
+        let Fixture {
+            server,
+            funding,
+            coinbase,
+            genesis,
+            block,
+            ..
+        } = start();
+        let hash = block.hash().to_string();
+
+        let (code, tip) = get(&server, "/tip");
+        assert_eq!(code, 200);
+        assert_eq!(tip.get("height"), Some(&1.into()));
+        assert_eq!(tip.get("hash"), Some(&hash.clone().into()));
+        assert_eq!(tip.get("time"), Some(&block.timestamp().into()));
+
+        let (code, by_height) = get(&server, "/block/height/1");
+        assert_eq!(code, 200);
+        assert_eq!(by_height.get("hash"), Some(&hash.clone().into()));
+        assert_eq!(
+            by_height.get("tx"),
+            Some(&vec![coinbase.txid().to_string()].into())
+        );
+        assert_eq!(
+            get(&server, &format!("/block/hash/{}", hash)),
+            (200, by_height)
+        );
+        let (_, first) = get(&server, "/block/height/0");
+        assert_eq!(first.get("hash"), Some(&genesis.hash().to_string().into()));
+
+        let (code, mined) = get(&server, &format!("/tx/{}", coinbase.txid()));
+        assert_eq!(code, 200);
+        assert_eq!(
+            mined.get("hex"),
+            Some(&hex::encode(coinbase.encode()).into())
+        );
+        assert_eq!(mined.get("blockhash"), Some(&hash.into()));
+        assert_eq!(mined.get("height"), Some(&1.into()));
+        assert_eq!(mined.get("confirmations"), Some(&1.into()));
+
+        // A pooled transaction has no block yet
+        let spend = signed_spend(
+            &[OutPoint {
+                txid: funding.txid(),
+                index: 0,
+            }],
+            &[900],
+        );
+        let raw = hex::encode(spend.encode());
+        assert!(call(&server, "sendrawtransaction", vec![raw.clone().into()]).is_ok());
+        let (code, pooled) = get(&server, &format!("/tx/{}", spend.txid()));
+        assert_eq!(code, 200);
+        assert_eq!(pooled.get("hex"), Some(&raw.into()));
+        assert_eq!(pooled.get("blockhash"), Some(&Value::Null));
+        assert_eq!(pooled.get("confirmations"), Some(&0.into()));
+
+        let (code, stats) = get(&server, "/stats");
+        assert_eq!(code, 200);
+        assert_eq!(stats.get("blocks"), Some(&2.into()));
+        assert_eq!(stats.get("transactions"), Some(&2.into()));
+        assert_eq!(stats.get("averageblockinterval"), Some(&10.0.into()));
+        assert_eq!(stats.get("mempooltransactions"), Some(&1.into()));
+        server.stop();
+    }
+
+    #[test]
+    fn test_rest_errors() {
+        let Fixture { server, .. } = start();
+        assert_eq!(status(&server, "/block/height/2"), 404);
+        assert_eq!(status(&server, "/block/height/-1"), 400);
+        assert_eq!(status(&server, "/block/height/one"), 400);
+        let unknown = BlockHash::from_bytes([9; 32]);
+        assert_eq!(status(&server, &format!("/block/hash/{}", unknown)), 404);
+        assert_eq!(status(&server, "/block/hash/zz"), 400);
+        assert_eq!(status(&server, "/block/hash/abcd"), 400);
+        let unknown = crate::transaction::Txid::of(b"unknown");
+        assert_eq!(status(&server, &format!("/tx/{}", unknown)), 404);
+        assert_eq!(status(&server, "/tx/xyz"), 400);
+        assert_eq!(status(&server, "/blocks?count=many"), 400);
+        assert_eq!(status(&server, "/"), 404);
+        assert_eq!(status(&server, "/block"), 404);
+        assert_eq!(status(&server, "/tip/extra"), 404);
+        server.stop();
+    }
+
+    #[test]
+    fn test_rest_pagination() {
+        let Fixture { server, .. } = start();
+        let page = |path: &str| {
+            let (code, page) = get(&server, path);
+            assert_eq!(code, 200);
+            let heights: Vec<u64> = page
+                .get("blocks")
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|block| block.get("height").unwrap().as_u64().unwrap())
+                .collect();
+            (heights, page.get("next").unwrap().as_u64())
+        };
+
+        assert_eq!(page("/blocks"), (vec![0, 1], None));
+        assert_eq!(page("/blocks?from=0&count=1"), (vec![0], Some(1)));
+        assert_eq!(page("/blocks?count=1&from=1"), (vec![1], None));
+        // An empty page still says where to carry on
+        assert_eq!(page("/blocks?from=1&count=0"), (Vec::new(), Some(1)));
+        // Pages reaching past the tip stop at it, or are empty beyond it
+        assert_eq!(page("/blocks?from=1&count=50"), (vec![1], None));
+        assert_eq!(page("/blocks?from=2&count=5"), (Vec::new(), None));
+        assert_eq!(
+            page(&format!("/blocks?from={}&count={}", u64::MAX, u64::MAX)),
+            (Vec::new(), None)
+        );
+        server.stop();
+    }
+}
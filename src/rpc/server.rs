@@ -0,0 +1,158 @@
+//! Serving [`Rpc`] over HTTP.
+
+use std::io::{self, Read};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use super::{response, Encoding, Rpc, RpcError};
+use crate::store::ChainStore;
+
+/// Largest request body the server reads; a larger one is refused with
+/// status 413 before any of it is parsed
+pub const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// An HTTP server answering JSON-RPC and REST requests on a thread of its
+/// own until stopped or dropped.
+///
+/// A JSON-RPC request must `POST` its body to any path. The response has
+/// status 200 with the JSON-RPC response as its body, even for a failed
+/// call, or 204 with no body for a notification. The body is JSON unless
+/// the request asks for MessagePack, by its `encoding` member or its
+/// `Accept` header, and its `Content-Type` says which. A `GET` is answered by
+/// [`Rpc::get`], with status 200 and the result as the body, or the
+/// status of the error with `{"error": ...}` holding it.
+pub struct RpcServer {
+    server: Arc<Server>,
+    addr: SocketAddr,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RpcServer {
+    /// Listen on `addr` and start answering requests with `rpc`
+    pub fn start<S>(addr: impl ToSocketAddrs, rpc: Rpc<S>) -> io::Result<RpcServer>
+    where
+        S: ChainStore + Send + Sync + 'static,
+    {
+        let server = Arc::new(Server::http(addr).map_err(io::Error::other)?);
+        let addr = server
+            .server_addr()
+            .to_ip()
+            .ok_or_else(|| io::Error::other("not listening on an IP address"))?;
+        let thread = thread::spawn({
+            let server = Arc::clone(&server);
+            move || {
+                for request in server.incoming_requests() {
+                    answer(&rpc, request);
+                }
+            }
+        });
+        Ok(RpcServer {
+            server,
+            addr,
+            thread: Some(thread),
+        })
+    }
+
+    /// The address the server listens on, with the port picked if `addr`
+    /// asked for port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stop accepting requests and wait for the one in hand, if any
+    pub fn stop(mut self) {
+        self.shut_down();
+    }
+
+    fn shut_down(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            // A panic in a handler has already been reported on its thread
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.shut_down();
+    }
+}
+
+fn answer<S: ChainStore>(rpc: &Rpc<S>, mut request: Request) {
+    let reply = match request.method() {
+        Method::Get => match rpc.get(request.url()) {
+            Ok(value) => json(value.to_string(), 200),
+            Err(err) => json(
+                json!({ "error": err.to_value() }).to_string(),
+                err.http_status(),
+            ),
+        },
+        Method::Post => {
+            let accept = request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv("Accept"))
+                .map_or(Encoding::Json, |header| {
+                    Encoding::from_accept(header.value.as_str())
+                });
+            match read_body(&mut request) {
+                // The connection failed part way through the body
+                Err(_) => return,
+                Ok(None) => {
+                    let error = response(
+                        Value::Null,
+                        Err(RpcError::InvalidRequest("request body too large")),
+                    );
+                    encoded(accept, accept.encode(error), 413)
+                }
+                Ok(Some(body)) => match rpc.handle_encoded(&body, accept) {
+                    Some((encoding, body)) => encoded(encoding, body, 200),
+                    None => Response::from_data(Vec::new()).with_status_code(204),
+                },
+            }
+        }
+        _ => Response::from_data(Vec::new())
+            .with_status_code(405)
+            .with_header(header("Allow", "GET, POST")),
+    };
+    // The client may have hung up, leaving no one to tell
+    let _ = request.respond(reply);
+}
+
+/// The request's body, or `None` if it is over [`MAX_REQUEST_BYTES`]
+fn read_body(request: &mut Request) -> io::Result<Option<Vec<u8>>> {
+    if request
+        .body_length()
+        .is_some_and(|len| len > MAX_REQUEST_BYTES)
+    {
+        return Ok(None);
+    }
+    let mut body = Vec::new();
+    request
+        .as_reader()
+        .take(MAX_REQUEST_BYTES as u64 + 1)
+        .read_to_end(&mut body)?;
+    Ok((body.len() <= MAX_REQUEST_BYTES).then_some(body))
+}
+
+fn json(body: String, status: u16) -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_string(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+}
+
+fn encoded(encoding: Encoding, body: Vec<u8>, status: u16) -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", encoding.content_type()))
+}
+
+#[allow(clippy::expect_used)]
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name, value).expect("header names and values are ASCII")
+}
@@ -0,0 +1,658 @@
+//! SSZ-style encoding and hash tree roots of block headers and blocks, for
+//! consensus-layer tooling that speaks Ethereum's Simple Serialize.
+//!
+//! Each type is a container of fields. Fixed-size fields are written in
+//! order; a variable-size field is written in its place as a four-byte
+//! little-endian offset, counted from the start of the container, to its
+//! bytes, which follow the fixed part in field order. Integers are
+//! little-endian at their width. The types are containers of these fields,
+//! in this order:
+//!
+//! - [`BlockHeader`]: `{version: uint32, prev_block_hash: Bytes32,
+//!   merkle_root: Bytes32, state_root: Bytes32, timestamp: uint64, bits:
+//!   uint32, nonce: uint64}`, always [`HEADER_LENGTH`] bytes, with a zero
+//!   state root before [`STATE_ROOT_VERSION`]
+//! - [`Block`]: `{header: BlockHeader, signature: Union[None, Bytes64,
+//!   Bytes64], transactions: List[ByteList[MAX_TRANSACTION_BYTES],
+//!   MAX_TRANSACTIONS]}`, where the signature is a selector byte, 0 for none
+//!   or the [`SignatureScheme::tag`] of its scheme, followed by its bytes
+//!
+//! A list of variable-size items is the offsets of its items, counted from
+//! the start of the list, followed by their bytes, so the first offset also
+//! gives the number of items.
+//!
+//! # Hash tree roots
+//!
+//! [`Ssz::hash_tree_root`] merkleizes 32-byte chunks with SHA-256:
+//!
+//! - an integer is one chunk holding its little-endian bytes, and a
+//!   `Bytes32` is its own chunk
+//! - longer byte vectors and byte lists are cut into 32-byte chunks, the last
+//!   padded with zeros
+//! - a container's chunks are the hash tree roots of its fields, in order
+//! - the chunks are padded with zero chunks to a power of two, or for a list
+//!   to the number of chunks its limit allows, and hashed in pairs up to a
+//!   single root, as [`MerkleTree`] hashes the nodes above its leaves
+//! - a list's root is then hashed with its number of items as a chunk, and
+//!   a union's with its selector
+//!
+//! Decoding is strict: offsets must point in order within the input, the
+//! first straight after the fixed part, no signature may follow the none
+//! selector, and a header before [`STATE_ROOT_VERSION`] must have a zero
+//! state root, so every value has exactly one encoding.
+
+use crate::block::{Block, BlockHash, BlockHeader, HeaderFields, STATE_ROOT_VERSION};
+use crate::codec::{self, DecodeError, DecodeLimits, Reader};
+use crate::crypto::{Signature, SignatureScheme};
+use crate::hash::Hash32;
+use crate::merkle_trie::MerkleTree;
+
+/// Size of an encoded header in bytes
+pub const HEADER_LENGTH: usize = 4 + 32 + 32 + 32 + 8 + 4 + 8;
+
+/// Limit of a block's transaction list, as in an Ethereum execution payload
+pub const MAX_TRANSACTIONS: usize = 1 << 20;
+
+/// Limit of a transaction's byte list, as in an Ethereum execution payload
+pub const MAX_TRANSACTION_BYTES: usize = 1 << 30;
+
+/// Size of an offset in bytes
+const OFFSET_LENGTH: usize = 4;
+
+/// Size of a block's fixed part: the header and the offsets of the
+/// signature and the transactions
+const BLOCK_FIXED_LENGTH: usize = HEADER_LENGTH + 2 * OFFSET_LENGTH;
+
+/// A type with an SSZ encoding and hash tree root
+pub trait Ssz: Sized {
+    /// The encoding of the value
+    fn to_ssz(&self) -> Vec<u8>;
+
+    /// Decode what [`Ssz::to_ssz`] wrote, enforcing `limits` on untrusted
+    /// input
+    fn from_ssz(bytes: &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError>;
+
+    /// The root of the merkleized fields of the value
+    fn hash_tree_root(&self) -> [u8; 32];
+}
+
+/// The encoding of `value`
+pub fn encode<T: Ssz>(value: &T) -> Vec<u8> {
+    value.to_ssz()
+}
+
+/// Decode a `T` making up all of `bytes`, enforcing `limits` on untrusted
+/// input
+pub fn decode<T: Ssz>(bytes: &[u8], limits: &DecodeLimits) -> Result<T, DecodeError> {
+    T::from_ssz(bytes, limits)
+}
+
+impl Ssz for BlockHeader {
+    fn to_ssz(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LENGTH);
+        out.extend_from_slice(&self.version().to_le_bytes());
+        out.extend_from_slice(self.prev_block_hash().as_bytes());
+        out.extend_from_slice(self.merkle_root());
+        out.extend_from_slice(self.state_root().unwrap_or(&[0; 32]));
+        out.extend_from_slice(&self.timestamp().to_le_bytes());
+        out.extend_from_slice(&self.bits().to_le_bytes());
+        out.extend_from_slice(&self.nonce().to_le_bytes());
+        out
+    }
+
+    fn from_ssz(bytes: &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let mut reader = Reader::new(bytes);
+        let header = read_header(&mut reader)?;
+        reader.finish()?;
+        Ok(header)
+    }
+
+    fn hash_tree_root(&self) -> [u8; 32] {
+        let fields = vec![
+            uint_chunk(self.version().into()),
+            *self.prev_block_hash().as_bytes(),
+            chunk(self.merkle_root()),
+            *self.state_root().unwrap_or(&[0; 32]),
+            uint_chunk(self.timestamp()),
+            uint_chunk(self.bits().into()),
+            uint_chunk(self.nonce()),
+        ];
+        let count = fields.len();
+        merkleize(fields, count)
+    }
+}
+
+impl Ssz for Block {
+    fn to_ssz(&self) -> Vec<u8> {
+        let signature = match self.signature() {
+            Some(signature) => {
+                [&[signature.scheme().tag()][..], &signature.to_bytes()[..]].concat()
+            }
+            None => vec![0],
+        };
+        let transactions = self.transactions();
+
+        let mut out = self.header().to_ssz();
+        out.extend_from_slice(&offset(BLOCK_FIXED_LENGTH));
+        out.extend_from_slice(&offset(BLOCK_FIXED_LENGTH + signature.len()));
+        out.extend_from_slice(&signature);
+        let mut position = OFFSET_LENGTH * transactions.len();
+        for tx in transactions {
+            out.extend_from_slice(&offset(position));
+            position += tx.len();
+        }
+        for tx in transactions {
+            out.extend_from_slice(tx);
+        }
+        out
+    }
+
+    fn from_ssz(bytes: &[u8], limits: &DecodeLimits) -> Result<Self, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let mut reader = Reader::new(bytes);
+        let header = read_header(&mut reader)?;
+        let signature_offset = reader.read_u32()? as usize;
+        let transactions_offset = reader.read_u32()? as usize;
+        if signature_offset != BLOCK_FIXED_LENGTH {
+            return Err(DecodeError::InvalidValue("signature offset"));
+        }
+        if transactions_offset < signature_offset || transactions_offset > bytes.len() {
+            return Err(DecodeError::InvalidValue("transactions offset"));
+        }
+        let signature = read_signature(&bytes[signature_offset..transactions_offset])?;
+        let transactions = read_transactions(&bytes[transactions_offset..], limits)?;
+        Block::from_parts(header, signature, transactions)
+    }
+
+    fn hash_tree_root(&self) -> [u8; 32] {
+        let signature = match self.signature() {
+            Some(signature) => mix_in(
+                merkleize(pack(&signature.to_bytes()), 2),
+                signature.scheme().tag().into(),
+            ),
+            None => mix_in([0; 32], 0),
+        };
+        let transactions = self.transactions();
+        let roots = transactions
+            .iter()
+            .map(|tx| {
+                let root = merkleize(pack(tx), MAX_TRANSACTION_BYTES / 32);
+                mix_in(root, tx.len() as u64)
+            })
+            .collect();
+        let transactions = mix_in(
+            merkleize(roots, MAX_TRANSACTIONS),
+            transactions.len() as u64,
+        );
+        merkleize(
+            vec![self.header().hash_tree_root(), signature, transactions],
+            3,
+        )
+    }
+}
+
+fn read_header(reader: &mut Reader<'_>) -> Result<BlockHeader, DecodeError> {
+    let version = reader.read_u32()?;
+    let prev_block_hash = BlockHash::from_bytes(reader.read_array()?);
+    let merkle_root = Hash32(reader.read_array()?);
+    let state_root: [u8; 32] = reader.read_array()?;
+    let state_root = if version >= STATE_ROOT_VERSION {
+        Some(state_root)
+    } else if state_root == [0; 32] {
+        None
+    } else {
+        return Err(DecodeError::InvalidValue("state root for header version"));
+    };
+    BlockHeader::try_from(HeaderFields {
+        version,
+        prev_block_hash,
+        merkle_root,
+        state_root,
+        timestamp: reader.read_u64()?,
+        bits: reader.read_u32()?,
+        nonce: reader.read_u64()?,
+    })
+}
+
+/// The signature union: a selector byte and, unless it is 0, the signature
+fn read_signature(bytes: &[u8]) -> Result<Option<Signature>, DecodeError> {
+    let (&selector, value) = bytes
+        .split_first()
+        .ok_or(DecodeError::InvalidValue("signature"))?;
+    if selector == 0 {
+        return match value {
+            [] => Ok(None),
+            _ => Err(DecodeError::InvalidValue("signature")),
+        };
+    }
+    let scheme = SignatureScheme::from_tag(selector)
+        .ok_or(DecodeError::InvalidValue("signature selector"))?;
+    let value = value
+        .try_into()
+        .map_err(|_| DecodeError::InvalidValue("signature"))?;
+    Ok(Some(Signature::from_bytes(scheme, &value)))
+}
+
+/// The transaction list: the offsets of the transactions, then their bytes
+fn read_transactions(bytes: &[u8], limits: &DecodeLimits) -> Result<Vec<Vec<u8>>, DecodeError> {
+    if bytes.is_empty() {
+        return Err(DecodeError::InvalidValue("block has no transactions"));
+    }
+    let mut reader = Reader::new(bytes);
+    let first = reader.read_u32()? as usize;
+    if first == 0 || !first.is_multiple_of(OFFSET_LENGTH) || first > bytes.len() {
+        return Err(DecodeError::InvalidValue("transaction offset"));
+    }
+    let count = first / OFFSET_LENGTH;
+    let max_count = limits.max_transactions.min(MAX_TRANSACTIONS);
+    if count > max_count {
+        return Err(DecodeError::LimitExceeded {
+            what: "transaction count",
+            value: count as u64,
+            max: max_count as u64,
+        });
+    }
+    let mut offsets = vec![first];
+    for _ in 1..count {
+        offsets.push(reader.read_u32()? as usize);
+    }
+    offsets.push(bytes.len());
+
+    let max_size = limits.max_transaction_bytes.min(MAX_TRANSACTION_BYTES);
+    offsets
+        .windows(2)
+        .map(|pair| {
+            let (start, end) = (pair[0], pair[1]);
+            if end < start || end > bytes.len() {
+                return Err(DecodeError::InvalidValue("transaction offset"));
+            }
+            if end - start > max_size {
+                return Err(DecodeError::LimitExceeded {
+                    what: "transaction size",
+                    value: (end - start) as u64,
+                    max: max_size as u64,
+                });
+            }
+            Ok(bytes[start..end].to_vec())
+        })
+        .collect()
+}
+
+/// Block limits keep encodings far below the 4 GiB offsets can reach
+#[allow(clippy::expect_used)]
+fn offset(position: usize) -> [u8; OFFSET_LENGTH] {
+    u32::try_from(position)
+        .expect("an encoding of at most 4 GiB")
+        .to_le_bytes()
+}
+
+/// `bytes` padded with zeros to a chunk
+fn chunk(bytes: &[u8]) -> [u8; 32] {
+    let mut chunk = [0; 32];
+    chunk[..bytes.len()].copy_from_slice(bytes);
+    chunk
+}
+
+fn uint_chunk(value: u64) -> [u8; 32] {
+    chunk(&value.to_le_bytes())
+}
+
+/// `bytes` cut into chunks, the last padded with zeros
+fn pack(bytes: &[u8]) -> Vec<[u8; 32]> {
+    bytes.chunks(32).map(chunk).collect()
+}
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    MerkleTree::hash(&[&left[..], &right[..]].concat()).into_bytes()
+}
+
+/// `root` hashed with `value`, a list's length or a union's selector
+fn mix_in(root: [u8; 32], value: u64) -> [u8; 32] {
+    hash_pair(&root, &uint_chunk(value))
+}
+
+/// The root of `chunks` padded with zero chunks to the power of two at or
+/// above `limit`
+fn merkleize(chunks: Vec<[u8; 32]>, limit: usize) -> [u8; 32] {
+    assert!(chunks.len() <= limit, "more chunks than the limit");
+    let depth = limit.next_power_of_two().trailing_zeros();
+    // Padding the leaves to a power of two leaves the tree no odd node to
+    // promote, so it hashes every pair as SSZ does
+    let width = chunks.len().next_power_of_two();
+    let mut root = match chunks.len() {
+        0 => [0; 32],
+        _ => {
+            let mut leaves: Vec<Hash32> = chunks.into_iter().map(Hash32).collect();
+            leaves.resize(width, Hash32::ZERO);
+            MerkleTree::from_leaf_hashes(leaves).map_or([0; 32], |tree| tree.root().into_bytes())
+        }
+    };
+    // The rest of the padding is subtrees of zero chunks, of a known root at
+    // each level
+    let mut zero = [0; 32];
+    for _ in 0..width.trailing_zeros() {
+        zero = hash_pair(&zero, &zero);
+    }
+    for _ in width.trailing_zeros()..depth {
+        root = hash_pair(&root, &zero);
+        zero = hash_pair(&zero, &zero);
+    }
+    root
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::crypto::ed25519;
+    use crate::difficulty::Difficulty;
+    use crate::test_vectors::SerdeFixtures;
+    use crate::testing;
+
+    fn pinned_block() -> Block {
+        BlockBuilder::new(BlockHash::from_bytes([0; 32]))
+            .transactions([b"genesis".to_vec(), b"tx".to_vec()])
+            .timestamp(1_700_000_000)
+            .difficulty(Difficulty::LeadingZeroBits(8))
+            .nonce(42)
+            .build()
+            .unwrap()
+    }
+
+    fn header_fields(header: &BlockHeader) -> HeaderFields {
+        HeaderFields {
+            version: header.version(),
+            prev_block_hash: header.prev_block_hash(),
+            merkle_root: header.merkle_root_hash(),
+            state_root: header.state_root().copied(),
+            timestamp: header.timestamp(),
+            bits: header.bits(),
+            nonce: header.nonce(),
+        }
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let fixtures = SerdeFixtures::new();
+        let limits = DecodeLimits::default();
+        for header in &fixtures.headers {
+            assert_eq!(header.to_ssz().len(), HEADER_LENGTH);
+            assert_eq!(
+                decode::<BlockHeader>(&encode(header), &limits).as_ref(),
+                Ok(header)
+            );
+        }
+        let mut block = pinned_block();
+        let unsigned = block.to_ssz();
+        assert_eq!(Block::from_ssz(&unsigned, &limits).as_ref(), Ok(&block));
+        block.sign(&ed25519::SigningKey::from_bytes(&[1; 32]));
+        assert_eq!(block.to_ssz().len(), unsigned.len() + 64);
+        for block in [&block, &fixtures.block] {
+            assert_eq!(decode::<Block>(&encode(block), &limits).as_ref(), Ok(block));
+        }
+    }
+
+    #[test]
+    fn test_encoding_pinned() {
+        let block = pinned_block();
+        let header = concat!(
+            "01000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "23acfc690b02377123520fff7b37ae1188f1365e7a956aeec2899eb30e85090c",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "00f1536500000000",
+            "00000120",
+            "2a00000000000000",
+        );
+        assert_eq!(hex::encode(block.header().to_ssz()), header);
+        assert_eq!(
+            hex::encode(block.to_ssz()),
+            [
+                header,
+                // Offsets of the signature and the transactions
+                "80000000",
+                "81000000",
+                // No signature
+                "00",
+                // Offsets of the two transactions, then their bytes
+                "08000000",
+                "0f000000",
+                "67656e65736973",
+                "7478",
+            ]
+            .concat()
+        );
+        assert_eq!(
+            hex::encode(block.header().hash_tree_root()),
+            "db399a8941a1bc27beda563970804c4dbd16da0a8e096247558ba6b299dfb621"
+        );
+        assert_eq!(
+            hex::encode(block.hash_tree_root()),
+            "776bb6fe6681b1b763f19b0b77481f61b291b2be2b2d5735e56c4a47111a9d5c"
+        );
+    }
+
+    #[test]
+    fn test_merkleize() {
+        let zero = [0; 32];
+        let z1 = hash_pair(&zero, &zero);
+        let z2 = hash_pair(&z1, &z1);
+        let (a, b, c) = ([1; 32], [2; 32], [3; 32]);
+        assert_eq!(merkleize(vec![], 1), zero);
+        assert_eq!(merkleize(vec![], 4), z2);
+        assert_eq!(merkleize(vec![a], 1), a);
+        assert_eq!(merkleize(vec![a], 4), hash_pair(&hash_pair(&a, &zero), &z1));
+        assert_eq!(
+            merkleize(vec![a, b, c], 3),
+            hash_pair(&hash_pair(&a, &b), &hash_pair(&c, &zero))
+        );
+        assert_eq!(
+            merkleize(vec![a, b, c], 8),
+            hash_pair(&hash_pair(&hash_pair(&a, &b), &hash_pair(&c, &zero)), &z2)
+        );
+    }
+
+    #[test]
+    fn test_hash_tree_root_follows_every_field() {
+        let fixtures = SerdeFixtures::new();
+        let header = fixtures.block.header();
+        let root = header.hash_tree_root();
+        assert_eq!(header.clone().hash_tree_root(), root);
+        let decoded = BlockHeader::from_ssz(&header.to_ssz(), &DecodeLimits::default()).unwrap();
+        assert_eq!(decoded.hash_tree_root(), root);
+
+        let changes: [fn(&mut HeaderFields); 7] = [
+            |fields| fields.version ^= 1,
+            |fields| fields.prev_block_hash = BlockHash::from_bytes([9; 32]),
+            |fields| fields.merkle_root.0[31] ^= 1,
+            |fields| fields.state_root = Some([7; 32]),
+            |fields| fields.timestamp ^= 1,
+            |fields| fields.bits ^= 1,
+            |fields| fields.nonce ^= 1,
+        ];
+        let mut roots = vec![root];
+        for change in changes {
+            let mut fields = header_fields(header);
+            change(&mut fields);
+            let changed = BlockHeader::try_from(fields).unwrap();
+            roots.push(changed.hash_tree_root());
+        }
+        roots.sort();
+        roots.dedup();
+        assert_eq!(roots.len(), 8);
+
+        let block = &fixtures.block;
+        let root = block.hash_tree_root();
+        let header = BlockHeader::try_from(HeaderFields {
+            nonce: block.header().nonce() ^ 1,
+            ..header_fields(block.header())
+        })
+        .unwrap();
+        let signature = block.signature().copied();
+        let transactions = block.transactions().to_vec();
+        let mut flipped = transactions.clone();
+        flipped[0][0] ^= 1;
+        let mut longer = transactions.clone();
+        longer[0].push(0);
+        let mut more = transactions.clone();
+        more.push(Vec::new());
+        let other_scheme = signature.map(|signature| {
+            Signature::from_bytes(SignatureScheme::Secp256k1, &signature.to_bytes())
+        });
+        let blocks = [
+            Block::from_parts(header, signature, transactions.clone()).unwrap(),
+            Block::from_parts(block.header().clone(), None, transactions.clone()).unwrap(),
+            Block::from_parts(block.header().clone(), other_scheme, transactions).unwrap(),
+            Block::from_parts(block.header().clone(), signature, flipped).unwrap(),
+            Block::from_parts(block.header().clone(), signature, longer).unwrap(),
+            Block::from_parts(block.header().clone(), signature, more).unwrap(),
+        ];
+        let mut roots = vec![root];
+        roots.extend(blocks.iter().map(Ssz::hash_tree_root));
+        roots.sort();
+        roots.dedup();
+        assert_eq!(roots.len(), 7);
+        assert_eq!(block.clone().hash_tree_root(), root);
+    }
+
+    #[test]
+    fn test_refuses_malformed_input() {
+        let limits = DecodeLimits::default();
+        let block = pinned_block();
+        let bytes = block.to_ssz();
+        let with = |at: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[at..at + value.len()].copy_from_slice(value);
+            bytes
+        };
+        let cases = [
+            (
+                bytes[..HEADER_LENGTH + 3].to_vec(),
+                DecodeError::UnexpectedEof,
+            ),
+            (
+                bytes[..BLOCK_FIXED_LENGTH + 1].to_vec(),
+                DecodeError::InvalidValue("block has no transactions"),
+            ),
+            (
+                with(120, &[0x81]),
+                DecodeError::InvalidValue("signature offset"),
+            ),
+            (
+                with(124, &[0x7f]),
+                DecodeError::InvalidValue("transactions offset"),
+            ),
+            (
+                with(124, &[0xff]),
+                DecodeError::InvalidValue("transactions offset"),
+            ),
+            (with(124, &[0x80]), DecodeError::InvalidValue("signature")),
+            (
+                with(128, &[3]),
+                DecodeError::InvalidValue("signature selector"),
+            ),
+            (with(128, &[1]), DecodeError::InvalidValue("signature")),
+            (
+                with(129, &[6]),
+                DecodeError::InvalidValue("transaction offset"),
+            ),
+            (
+                with(129, &[0]),
+                DecodeError::InvalidValue("transaction offset"),
+            ),
+            (
+                with(133, &[7]),
+                DecodeError::InvalidValue("transaction offset"),
+            ),
+            (
+                with(133, &[0x20]),
+                DecodeError::InvalidValue("transaction offset"),
+            ),
+            (
+                with(68, &[1]),
+                DecodeError::InvalidValue("state root for header version"),
+            ),
+        ];
+        for (bytes, expected) in cases {
+            assert_eq!(Block::from_ssz(&bytes, &limits), Err(expected));
+        }
+        let mut trailing = block.header().to_ssz();
+        trailing.push(0);
+        assert_eq!(
+            BlockHeader::from_ssz(&trailing, &limits),
+            Err(DecodeError::TrailingBytes(1))
+        );
+        let small = DecodeLimits {
+            max_transactions: 1,
+            max_transaction_bytes: 4,
+            ..DecodeLimits::default()
+        };
+        assert_eq!(
+            Block::from_ssz(&bytes, &small),
+            Err(DecodeError::LimitExceeded {
+                what: "transaction count",
+                value: 2,
+                max: 1
+            })
+        );
+        let small = DecodeLimits {
+            max_transaction_bytes: 4,
+            ..DecodeLimits::default()
+        };
+        assert_eq!(
+            Block::from_ssz(&bytes, &small),
+            Err(DecodeError::LimitExceeded {
+                what: "transaction size",
+                value: 7,
+                max: 4
+            })
+        );
+    }
+
+    /// `block`, signed if `signed`
+    fn maybe_signed(mut block: Block, signed: bool) -> Block {
+        if signed {
+            block.sign(&ed25519::SigningKey::from_bytes(&[1; 32]));
+        }
+        block
+    }
+
+    proptest! {
+        #[test]
+        fn test_generated_blocks_round_trip(block in testing::block(), signed in any::<bool>()) {
+            let limits = DecodeLimits::default();
+            let block = maybe_signed(block, signed);
+            let header = block.header();
+            let bytes = header.to_ssz();
+            prop_assert_eq!(bytes.len(), HEADER_LENGTH);
+            let decoded = BlockHeader::from_ssz(&bytes, &limits).unwrap();
+            prop_assert_eq!(&decoded, header);
+            prop_assert_eq!(decoded.hash_tree_root(), header.hash_tree_root());
+
+            let decoded = Block::from_ssz(&block.to_ssz(), &limits).unwrap();
+            prop_assert_eq!(decoded.hash_tree_root(), block.hash_tree_root());
+            prop_assert_eq!(decoded, block);
+        }
+
+        #[test]
+        fn test_corrupted_blocks_decode_canonically(
+            block in testing::block(),
+            signed in any::<bool>(),
+            at in any::<Index>(),
+            bit in 0..8u8,
+        ) {
+            // Whatever corrupted input decodes is the encoding of what it
+            // decodes to
+            let mut bytes = maybe_signed(block, signed).to_ssz();
+            let at = at.index(bytes.len());
+            bytes[at] ^= 1 << bit;
+            if let Ok(block) = Block::from_ssz(&bytes, &DecodeLimits::default()) {
+                prop_assert_eq!(block.to_ssz(), bytes);
+            }
+        }
+    }
+}
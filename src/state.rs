@@ -0,0 +1,494 @@
+//! Account balances, a simpler ledger than the unspent outputs of
+//! [`crate::utxo`].
+//!
+//! Each address holds a balance and the nonce of the last transfer it sent.
+//! A block carries [`Transfer`]s, each signed by the sender and naming a nonce
+//! above the sender's last, so no transfer applies twice. The whole state
+//! commits to a single [`StateMachine::state_root`].
+
+use std::collections::BTreeMap;
+
+use thiserror::Error;
+
+use crate::block::Block;
+use crate::codec::{DecodeError, Reader};
+#[cfg(any(test, feature = "crypto"))]
+use crate::crypto::ed25519::SigningKey;
+use crate::crypto::ed25519::{Signature, VerifyingKey, SIGNATURE_LENGTH};
+use crate::merkle_trie::MerkleTree;
+use crate::transaction::{pubkey_hash, BlockTransaction};
+
+/// Encoded size of a transfer: sender key, recipient, amount, nonce and signature
+pub const TRANSFER_SIZE: usize = 32 + 32 + 8 + 8 + SIGNATURE_LENGTH;
+
+/// The state root of a state without accounts
+pub const EMPTY_STATE_ROOT: [u8; 32] = [0; 32];
+
+/// A ledger state a block's header can commit to the root of; see
+/// [`crate::block::BlockBuilder::build_with_state`]
+pub trait StateView {
+    /// Why a block's transactions do not apply to the state
+    type Error;
+
+    /// The root committing to the state as it is
+    fn state_root(&self) -> [u8; 32];
+
+    /// The root the state would have after `block`'s transactions, leaving
+    /// the state itself as it is
+    fn root_after(&self, block: &Block) -> Result<[u8; 32], Self::Error>;
+}
+
+impl StateView for StateMachine {
+    type Error = StateError;
+
+    fn state_root(&self) -> [u8; 32] {
+        StateMachine::state_root(self)
+    }
+
+    fn root_after(&self, block: &Block) -> Result<[u8; 32], StateError> {
+        let mut after = self.clone();
+        after.apply_block(block)?;
+        Ok(after.state_root())
+    }
+}
+
+/// What an address holds
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Account {
+    pub balance: u64,
+    /// Nonce of the last transfer the account sent; zero before its first
+    pub nonce: u64,
+}
+
+/// A payment of `amount` from the holder of `from` to the address `to`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    /// The sender's key; the debited address is its [`pubkey_hash`]
+    pub from: VerifyingKey,
+    /// Address credited, as [`pubkey_hash`] of the recipient's key
+    pub to: [u8; 32],
+    pub amount: u64,
+    /// Must exceed the sender's [`Account::nonce`]
+    pub nonce: u64,
+    /// The sender's signature over [`Transfer::signing_bytes`]
+    pub signature: Signature,
+}
+
+impl Transfer {
+    /// A transfer from `key`'s address, signed with it
+    #[cfg(any(test, feature = "crypto"))]
+    pub fn signed(key: &SigningKey, to: [u8; 32], amount: u64, nonce: u64) -> Self {
+        let mut transfer = Transfer {
+            from: key.verifying_key(),
+            to,
+            amount,
+            nonce,
+            signature: Signature::from_bytes(&[0; SIGNATURE_LENGTH]),
+        };
+        transfer.signature = key.sign(&transfer.signing_bytes());
+        transfer
+    }
+
+    /// The encoding without the signature, which is what the sender signs
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(TRANSFER_SIZE);
+        buf.extend_from_slice(self.from.as_bytes());
+        buf.extend_from_slice(&self.to);
+        buf.extend_from_slice(&self.amount.to_le_bytes());
+        buf.extend_from_slice(&self.nonce.to_le_bytes());
+        buf
+    }
+
+    /// The canonical encoding: [`Transfer::signing_bytes`] followed by the
+    /// signature, [`TRANSFER_SIZE`] bytes in all
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = self.signing_bytes();
+        buf.extend_from_slice(&self.signature.to_bytes());
+        buf
+    }
+
+    /// Decode a transfer produced by [`Transfer::encode`]
+    pub fn decode(bytes: &[u8]) -> Result<Transfer, DecodeError> {
+        let mut reader = Reader::new(bytes);
+        let from = VerifyingKey::from_bytes(&reader.read_array()?)
+            .map_err(|_| DecodeError::InvalidValue("sender key"))?;
+        let transfer = Transfer {
+            from,
+            to: reader.read_array()?,
+            amount: reader.read_u64()?,
+            nonce: reader.read_u64()?,
+            signature: Signature::from_bytes(&reader.read_array()?),
+        };
+        reader.finish()?;
+        Ok(transfer)
+    }
+
+    /// The address debited
+    pub fn sender(&self) -> [u8; 32] {
+        pubkey_hash(&self.from)
+    }
+}
+
+impl BlockTransaction for Transfer {
+    fn encode(&self) -> Vec<u8> {
+        Transfer::encode(self)
+    }
+}
+
+/// Reasons a transfer cannot be applied to a [`StateMachine`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum StateError {
+    /// The transaction at `index` in the block is not a valid [`Transfer`]
+    #[error("transaction {index} does not decode: {err}")]
+    Decode { index: usize, err: DecodeError },
+    /// The signature is not the sender's over the transfer
+    #[error("transfer signature is invalid")]
+    InvalidSignature,
+    /// The nonce does not exceed the sender's last
+    #[error("nonce {got} does not exceed the last nonce {last}")]
+    StaleNonce { last: u64, got: u64 },
+    /// The sender holds less than the amount
+    #[error("transfer of {amount} exceeds the balance {balance}")]
+    InsufficientBalance { balance: u64, amount: u64 },
+    /// Crediting the recipient would overflow its balance
+    #[error("recipient balance would overflow")]
+    BalanceOverflow,
+}
+
+/// What [`StateMachine::apply_block`] changed, for
+/// [`StateMachine::undo_block`] to reverse
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateUndo {
+    /// Each account as it was before each change, in change order; `None`
+    /// for an account the change created
+    previous: Vec<([u8; 32], Option<Account>)>,
+}
+
+/// Accounts by address
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StateMachine {
+    accounts: BTreeMap<[u8; 32], Account>,
+}
+
+impl StateMachine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A state holding the given balances, as a genesis allocation does.
+    /// Repeated addresses are summed, saturating.
+    pub fn from_allocations(allocations: impl IntoIterator<Item = ([u8; 32], u64)>) -> Self {
+        let mut state = Self::new();
+        for (address, amount) in allocations {
+            let account = state.accounts.entry(address).or_default();
+            account.balance = account.balance.saturating_add(amount);
+        }
+        state
+    }
+
+    /// The account at `address`, if it has ever held anything
+    pub fn account(&self, address: &[u8; 32]) -> Option<&Account> {
+        self.accounts.get(address)
+    }
+
+    /// The balance at `address`, zero for an unknown address
+    pub fn balance(&self, address: &[u8; 32]) -> u64 {
+        self.account(address).map_or(0, |account| account.balance)
+    }
+
+    /// Number of accounts
+    pub fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.accounts.is_empty()
+    }
+
+    /// Check `tx` against the state and apply it, or leave the state as it
+    /// was if it does not apply
+    pub fn apply_transaction(&mut self, tx: &Transfer) -> Result<(), StateError> {
+        self.apply_transfer(tx, &mut StateUndo::default())
+    }
+
+    fn apply_transfer(&mut self, tx: &Transfer, undo: &mut StateUndo) -> Result<(), StateError> {
+        tx.from
+            .verify(&tx.signing_bytes(), &tx.signature)
+            .map_err(|_| StateError::InvalidSignature)?;
+        let from = tx.sender();
+        let sender = self.accounts.get(&from).copied().unwrap_or_default();
+        if tx.nonce <= sender.nonce {
+            return Err(StateError::StaleNonce {
+                last: sender.nonce,
+                got: tx.nonce,
+            });
+        }
+        let balance =
+            sender
+                .balance
+                .checked_sub(tx.amount)
+                .ok_or(StateError::InsufficientBalance {
+                    balance: sender.balance,
+                    amount: tx.amount,
+                })?;
+        // A transfer to the sender's own address leaves its balance alone
+        if tx.to != from {
+            let recipient = self.balance(&tx.to);
+            let credited = recipient
+                .checked_add(tx.amount)
+                .ok_or(StateError::BalanceOverflow)?;
+            self.set(tx.to, |account| account.balance = credited, undo);
+        }
+        self.set(
+            from,
+            |account| {
+                account.nonce = tx.nonce;
+                if tx.to != from {
+                    account.balance = balance;
+                }
+            },
+            undo,
+        );
+        Ok(())
+    }
+
+    /// Change the account at `address`, recording its old value in `undo`
+    fn set(&mut self, address: [u8; 32], change: impl FnOnce(&mut Account), undo: &mut StateUndo) {
+        let previous = self.accounts.get(&address).copied();
+        undo.previous.push((address, previous));
+        change(self.accounts.entry(address).or_default());
+    }
+
+    /// Apply `block`'s transactions in order, each of which must decode as a
+    /// [`Transfer`]. On error the state is left as it was.
+    pub fn apply_block(&mut self, block: &Block) -> Result<StateUndo, StateError> {
+        let mut undo = StateUndo::default();
+        for (index, tx) in block.transactions().iter().enumerate() {
+            let applied = Transfer::decode(tx)
+                .map_err(|err| StateError::Decode { index, err })
+                .and_then(|tx| self.apply_transfer(&tx, &mut undo));
+            if let Err(err) = applied {
+                self.undo_block(undo);
+                return Err(err);
+            }
+        }
+        Ok(undo)
+    }
+
+    /// Reverse the [`StateMachine::apply_block`] that returned `undo`, which
+    /// must be the last block applied and not yet undone
+    pub fn undo_block(&mut self, undo: StateUndo) {
+        for (address, previous) in undo.previous.into_iter().rev() {
+            match previous {
+                Some(account) => self.accounts.insert(address, account),
+                None => self.accounts.remove(&address),
+            };
+        }
+    }
+
+    /// The merkle root over every account in address order, each leaf being
+    /// the address, balance and nonce, integers little endian; equal states
+    /// have equal roots however they were reached. [`EMPTY_STATE_ROOT`] for
+    /// a state without accounts.
+    #[allow(clippy::expect_used)]
+    pub fn state_root(&self) -> [u8; 32] {
+        let leaves: Vec<Vec<u8>> = self
+            .accounts
+            .iter()
+            .map(|(address, account)| {
+                let mut leaf = Vec::with_capacity(32 + 8 + 8);
+                leaf.extend_from_slice(address);
+                leaf.extend_from_slice(&account.balance.to_le_bytes());
+                leaf.extend_from_slice(&account.nonce.to_le_bytes());
+                leaf
+            })
+            .collect();
+        MerkleTree::new(&leaves).map_or(EMPTY_STATE_ROOT, |tree| {
+            tree.root_hash()
+                .try_into()
+                .expect("SHA-256 digests are 32 bytes")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::block::{BlockBuilder, BlockHash};
+
+    fn key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn address(seed: u8) -> [u8; 32] {
+        pubkey_hash(&key(seed).verifying_key())
+    }
+
+    fn block(txs: &[&Transfer]) -> Block {
+        BlockBuilder::new(BlockHash::ZERO)
+            .transactions(txs.iter().map(|&tx| tx.clone()))
+            .timestamp(0)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_transfers_check_signature_nonce_and_balance() {
+        let mut state = StateMachine::from_allocations([(address(1), 100)]);
+        let pay = Transfer::signed(&key(1), address(2), 60, 1);
+        assert_eq!(Transfer::decode(&pay.encode()), Ok(pay.clone()));
+        state.apply_transaction(&pay).unwrap();
+        assert_eq!(state.balance(&address(1)), 40);
+        assert_eq!(state.balance(&address(2)), 60);
+        let before = state.clone();
+
+        // The same nonce, or an older one, cannot be used again
+        assert_eq!(
+            state.apply_transaction(&pay),
+            Err(StateError::StaleNonce { last: 1, got: 1 })
+        );
+        assert_eq!(
+            state.apply_transaction(&Transfer::signed(&key(1), address(2), 1, 0)),
+            Err(StateError::StaleNonce { last: 1, got: 0 })
+        );
+        assert_eq!(
+            state.apply_transaction(&Transfer::signed(&key(1), address(2), 41, 2)),
+            Err(StateError::InsufficientBalance {
+                balance: 40,
+                amount: 41
+            })
+        );
+        let mut forged = Transfer::signed(&key(1), address(2), 40, 2);
+        forged.to = address(3);
+        assert_eq!(
+            state.apply_transaction(&forged),
+            Err(StateError::InvalidSignature)
+        );
+        assert_eq!(state, before);
+
+        // Nonces may skip ahead, and a self transfer only moves the nonce
+        let skip = Transfer::signed(&key(1), address(1), 40, 7);
+        state.apply_transaction(&skip).unwrap();
+        assert_eq!(
+            state.account(&address(1)),
+            Some(&Account {
+                balance: 40,
+                nonce: 7
+            })
+        );
+    }
+
+    #[test]
+    fn test_block_round_trips_through_undo() {
+        let mut state = StateMachine::from_allocations([(address(1), 100), (address(2), 5)]);
+        let root = state.state_root();
+        let first = block(&[
+            &Transfer::signed(&key(1), address(2), 30, 1),
+            &Transfer::signed(&key(2), address(3), 35, 1),
+            &Transfer::signed(&key(1), address(3), 10, 2),
+        ]);
+        let undo = state.apply_block(&first).unwrap();
+        assert_eq!(state.balance(&address(3)), 45);
+        let applied = state.clone();
+
+        // A failing block leaves the state where it was
+        let failing = block(&[
+            &Transfer::signed(&key(3), address(4), 45, 1),
+            &Transfer::signed(&key(2), address(4), 1, 1),
+        ]);
+        assert_eq!(
+            state.apply_block(&failing),
+            Err(StateError::StaleNonce { last: 1, got: 1 })
+        );
+        assert_eq!(state, applied);
+        let raw = BlockBuilder::new(BlockHash::ZERO)
+            .transaction(b"raw".to_vec())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            state.apply_block(&raw),
+            Err(StateError::Decode { index: 0, .. })
+        ));
+
+        // Disconnecting removes the accounts the block created
+        state.undo_block(undo);
+        assert_eq!(state.len(), 2);
+        assert_eq!(state.state_root(), root);
+        let undo = state.apply_block(&first).unwrap();
+        assert_eq!(state, applied);
+        state.undo_block(undo);
+        assert_eq!(state.state_root(), root);
+    }
+
+    #[test]
+    fn test_state_root_is_independent_of_history() {
+        // The same accounts reached through allocations in either order, and
+        // through transfers
+        let a = StateMachine::from_allocations([(address(1), 10), (address(2), 20)]);
+        let b = StateMachine::from_allocations([(address(2), 20), (address(1), 10)]);
+        assert_eq!(a.state_root(), b.state_root());
+
+        let mut c = StateMachine::from_allocations([(address(2), 30)]);
+        let mut d = c.clone();
+        c.apply_transaction(&Transfer::signed(&key(2), address(1), 4, 1))
+            .unwrap();
+        c.apply_transaction(&Transfer::signed(&key(2), address(1), 6, 2))
+            .unwrap();
+        d.apply_transaction(&Transfer::signed(&key(2), address(1), 10, 2))
+            .unwrap();
+        assert_eq!(c.state_root(), d.state_root());
+        assert_ne!(c.state_root(), a.state_root(), "the nonce is committed");
+
+        assert_eq!(StateMachine::new().state_root(), EMPTY_STATE_ROOT);
+        let mut moved = a.clone();
+        moved
+            .apply_transaction(&Transfer::signed(&key(1), address(2), 1, 1))
+            .unwrap();
+        assert_ne!(moved.state_root(), a.state_root());
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn test_blocks_conserve_balances_and_undo(
+            allocations in vec(0..1_000u64, 4),
+            blocks in vec(vec((1..=4u8, 1..=4u8, 0..600u64, 0..4u64), 1..5), 1..5),
+        ) {
+            let addresses: Vec<[u8; 32]> = (1..=4).map(address).collect();
+            let mut state = StateMachine::from_allocations(addresses.iter().copied().zip(allocations));
+            let supply = |state: &StateMachine| -> u64 {
+                addresses.iter().map(|address| state.balance(address)).sum()
+            };
+            let total = supply(&state);
+            let mut history = Vec::new();
+            for transfers in blocks {
+                let transfers: Vec<Transfer> = transfers
+                    .into_iter()
+                    .map(|(from, to, amount, nonce)| {
+                        Transfer::signed(&key(from), address(to), amount, nonce)
+                    })
+                    .collect();
+                for transfer in &transfers {
+                    prop_assert_eq!(&Transfer::decode(&transfer.encode()).unwrap(), transfer);
+                }
+                let before = state.clone();
+                match state.apply_block(&block(&transfers.iter().collect::<Vec<_>>())) {
+                    Ok(undo) => history.push((before, undo)),
+                    // A block with any bad transfer changes nothing
+                    Err(_) => prop_assert_eq!(&state, &before),
+                }
+                prop_assert_eq!(supply(&state), total);
+            }
+
+            for (before, undo) in history.into_iter().rev() {
+                state.undo_block(undo);
+                prop_assert_eq!(state.state_root(), before.state_root());
+                prop_assert_eq!(&state, &before);
+            }
+        }
+    }
+}
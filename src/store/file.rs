@@ -0,0 +1,472 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use super::{ChainStore, ChainTip, StoreError};
+use crate::block::{Block, BlockHash, BlockHeader};
+use crate::codec::{write_varint, DecodeError, DecodeLimits, Reader};
+
+const LOG_FILE: &str = "chain.log";
+
+const BLOCK_RECORD: u8 = 1;
+const TIP_RECORD: u8 = 2;
+/// The header of a block whose body was pruned
+const HEADER_RECORD: u8 = 3;
+const PRUNE_RECORD: u8 = 4;
+
+/// Bytes before a record's payload: kind and payload length
+const RECORD_HEADER_LEN: usize = 1 + 4;
+const CHECKSUM_LEN: usize = 4;
+
+/// A store backed by a single append-only log file in a directory.
+///
+/// Every block and every tip update is one checksummed record, so a tip
+/// update lands completely or not at all. On open the log is replayed to
+/// rebuild the in-memory index, and a torn final record left by a crash is
+/// cut off; any other damage fails the open and leaves the log as it is.
+/// Block bodies stay on disk and are read back on demand.
+///
+/// Pruning rewrites the log with only headers for the pruned blocks and
+/// swaps it in with a rename, so a crash leaves either the old log or the
+/// new one.
+#[derive(Debug)]
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    offsets: HashMap<BlockHash, (u64, usize)>,
+    /// Offsets of the headers of pruned blocks
+    headers: HashMap<BlockHash, u64>,
+    heights: Vec<BlockHash>,
+    cumulative_work: u128,
+    pruned_height: u64,
+}
+
+impl FileStore {
+    /// Open the store in `dir`, creating the directory and log if needed.
+    ///
+    /// Fails with [`StoreError::Corrupt`] if an intact record cannot be
+    /// applied.
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        fs::create_dir_all(dir.as_ref())?;
+        let path = dir.as_ref().join(LOG_FILE);
+        // Left behind by a prune that crashed before swapping its log in
+        let _ = fs::remove_file(path.with_extension("tmp"));
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&path)?;
+        let log = file.try_clone()?;
+
+        let mut store = FileStore {
+            path,
+            file,
+            offsets: HashMap::new(),
+            headers: HashMap::new(),
+            heights: Vec::new(),
+            cumulative_work: 0,
+            pruned_height: 0,
+        };
+        let good = store.replay(BufReader::new(log))?;
+        if good < store.file.metadata()?.len() {
+            store.file.set_len(good)?;
+        }
+        Ok(store)
+    }
+
+    /// Apply the records of `log` in order up to a torn tail, returning the
+    /// length of the intact prefix
+    fn replay(&mut self, mut log: impl Read) -> Result<u64, StoreError> {
+        let mut offset = 0;
+        while let Some((kind, payload)) = read_record(&mut log)? {
+            let payload_offset = offset + RECORD_HEADER_LEN as u64;
+            self.apply(kind, &payload, payload_offset)?;
+            offset = payload_offset + (payload.len() + CHECKSUM_LEN) as u64;
+        }
+        Ok(offset)
+    }
+
+    fn apply(&mut self, kind: u8, payload: &[u8], offset: u64) -> Result<(), StoreError> {
+        let mut reader = Reader::new(payload);
+        match kind {
+            BLOCK_RECORD => {
+                let hash = BlockHash::from_bytes(reader.read_array()?);
+                let len = reader.remaining();
+                self.offsets.insert(hash, (offset + 32, len));
+            }
+            TIP_RECORD => {
+                let from_height = reader.read_u64()?;
+                let cumulative_work = u128::from_le_bytes(reader.read_array()?);
+                let count = reader.read_len("tip hashes", reader.remaining() / 32)?;
+                let mut hashes = Vec::with_capacity(count);
+                for _ in 0..count {
+                    hashes.push(BlockHash::from_bytes(reader.read_array()?));
+                }
+                reader.finish()?;
+                self.set_tip(from_height, &hashes, cumulative_work);
+            }
+            HEADER_RECORD => {
+                let hash = BlockHash::from_bytes(reader.read_array()?);
+                BlockHeader::decode(&mut reader)?;
+                reader.finish()?;
+                self.offsets.remove(&hash);
+                self.headers.insert(hash, offset + 32);
+            }
+            PRUNE_RECORD => {
+                self.pruned_height = reader.read_u64()?;
+                reader.finish()?;
+            }
+            _ => {
+                return Err(StoreError::Corrupt(DecodeError::InvalidValue(
+                    "record kind",
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    fn set_tip(&mut self, from_height: u64, hashes: &[BlockHash], cumulative_work: u128) {
+        self.heights.truncate(from_height as usize);
+        self.heights.extend_from_slice(hashes);
+        self.cumulative_work = cumulative_work;
+    }
+
+    /// Append one record and return the file offset of its payload
+    fn append(&mut self, kind: u8, payload: &[u8]) -> Result<u64, StoreError> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&encode_record(kind, payload))?;
+        Ok(offset + RECORD_HEADER_LEN as u64)
+    }
+}
+
+impl ChainStore for FileStore {
+    fn put_block(&mut self, block: &Block) -> Result<(), StoreError> {
+        let hash = block.hash();
+        if self.offsets.contains_key(&hash) {
+            return Ok(());
+        }
+        let bytes = block.to_bytes();
+        let mut payload = Vec::with_capacity(32 + bytes.len());
+        payload.extend_from_slice(hash.as_bytes());
+        payload.extend_from_slice(&bytes);
+        let offset = self.append(BLOCK_RECORD, &payload)?;
+        self.offsets.insert(hash, (offset + 32, bytes.len()));
+        Ok(())
+    }
+
+    fn get_block(&self, hash: &BlockHash) -> Result<Option<Block>, StoreError> {
+        let Some(&(offset, len)) = self.offsets.get(hash) else {
+            return Ok(None);
+        };
+        let bytes = read_at(&mut File::open(&self.path)?, offset, len)?;
+        Ok(Some(Block::from_bytes(&bytes, &DecodeLimits::default())?))
+    }
+
+    fn get_hash_at_height(&self, height: u64) -> Result<Option<BlockHash>, StoreError> {
+        Ok(usize::try_from(height)
+            .ok()
+            .and_then(|height| self.heights.get(height))
+            .copied())
+    }
+
+    fn put_tip(
+        &mut self,
+        from_height: u64,
+        hashes: &[BlockHash],
+        cumulative_work: u128,
+    ) -> Result<(), StoreError> {
+        let payload = tip_payload(from_height, hashes, cumulative_work);
+        self.append(TIP_RECORD, &payload)?;
+        self.file.sync_data()?;
+        self.set_tip(from_height, hashes, cumulative_work);
+        Ok(())
+    }
+
+    fn get_tip(&self) -> Result<Option<ChainTip>, StoreError> {
+        Ok(self.heights.last().map(|&hash| ChainTip {
+            height: self.heights.len() as u64 - 1,
+            hash,
+            cumulative_work: self.cumulative_work,
+        }))
+    }
+
+    fn get_header(&self, hash: &BlockHash) -> Result<Option<BlockHeader>, StoreError> {
+        // A block record starts with the block's header
+        let offset = match self.offsets.get(hash) {
+            Some(&(offset, _)) => offset,
+            None => match self.headers.get(hash) {
+                Some(&offset) => offset,
+                None => return Ok(None),
+            },
+        };
+        let bytes = read_header_at(&mut File::open(&self.path)?, offset)?;
+        Ok(Some(BlockHeader::from_bytes(&bytes)?))
+    }
+
+    fn prune_below(&mut self, height: u64) -> Result<(), StoreError> {
+        let height = height.min(self.heights.len() as u64);
+        if height <= self.pruned_height {
+            return Ok(());
+        }
+        let pruned: HashSet<&BlockHash> = self.heights[..height as usize].iter().collect();
+
+        // Copy every record still needed into a fresh log, in their original
+        // order; a header is copied without knowing its length up front
+        let mut records: Vec<(u64, &BlockHash, u8, Option<usize>)> = self
+            .offsets
+            .iter()
+            .map(|(hash, &(offset, len))| {
+                if pruned.contains(hash) {
+                    (offset, hash, HEADER_RECORD, None)
+                } else {
+                    (offset, hash, BLOCK_RECORD, Some(len))
+                }
+            })
+            .chain(
+                self.headers
+                    .iter()
+                    .map(|(hash, &offset)| (offset, hash, HEADER_RECORD, None)),
+            )
+            .collect();
+        records.sort_unstable();
+
+        let tmp = self.path.with_extension("tmp");
+        let mut source = File::open(&self.path)?;
+        let mut out = BufWriter::new(File::create(&tmp)?);
+        for (offset, hash, kind, len) in records {
+            let mut payload = hash.to_vec();
+            let bytes = match len {
+                Some(len) => read_at(&mut source, offset, len)?,
+                None => read_header_at(&mut source, offset)?,
+            };
+            payload.extend_from_slice(&bytes);
+            out.write_all(&encode_record(kind, &payload))?;
+        }
+        let tip = tip_payload(0, &self.heights, self.cumulative_work);
+        out.write_all(&encode_record(TIP_RECORD, &tip))?;
+        out.write_all(&encode_record(PRUNE_RECORD, &height.to_le_bytes()))?;
+        out.into_inner()
+            .map_err(|err| err.into_error())?
+            .sync_all()?;
+
+        fs::rename(&tmp, &self.path)?;
+        let dir = self
+            .path
+            .parent()
+            .ok_or_else(|| StoreError::Io("the log is not inside a directory".to_string()))?
+            .to_path_buf();
+        *self = FileStore::open(dir)?;
+        Ok(())
+    }
+
+    fn pruned_height(&self) -> Result<u64, StoreError> {
+        Ok(self.pruned_height)
+    }
+}
+
+/// Frame `payload` as a record of `kind`: kind, length, payload, checksum
+fn encode_record(kind: u8, payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(RECORD_HEADER_LEN + payload.len() + CHECKSUM_LEN);
+    record.push(kind);
+    record.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    record.extend_from_slice(payload);
+    let checksum = checksum(&record);
+    record.extend_from_slice(&checksum);
+    record
+}
+
+fn tip_payload(from_height: u64, hashes: &[BlockHash], cumulative_work: u128) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(8 + 16 + 10 + 32 * hashes.len());
+    payload.extend_from_slice(&from_height.to_le_bytes());
+    payload.extend_from_slice(&cumulative_work.to_le_bytes());
+    write_varint(&mut payload, hashes.len() as u64);
+    for hash in hashes {
+        payload.extend_from_slice(hash.as_bytes());
+    }
+    payload
+}
+
+fn read_at(file: &mut File, offset: u64, len: usize) -> Result<Vec<u8>, StoreError> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut bytes = vec![0; len];
+    file.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// The encoded header starting at `offset`, whose version gives its length
+fn read_header_at(file: &mut File, offset: u64) -> Result<Vec<u8>, StoreError> {
+    let mut version = [0; 4];
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    read_at(file, offset, BlockHeader::encoded_len_of(version))
+}
+
+fn checksum(bytes: &[u8]) -> [u8; CHECKSUM_LEN] {
+    let digest = Sha256::digest(bytes);
+    let mut checksum = [0; CHECKSUM_LEN];
+    checksum.copy_from_slice(&digest[..CHECKSUM_LEN]);
+    checksum
+}
+
+/// Read the next record of `log` as its kind and payload, or `None` at the
+/// end of the log or at a torn tail: a record cut short or failing its
+/// checksum, as a crash part-way through an append leaves
+fn read_record(log: &mut impl Read) -> Result<Option<(u8, Vec<u8>)>, StoreError> {
+    let mut header = [0; RECORD_HEADER_LEN];
+    if !read_full(log, &mut header)? {
+        return Ok(None);
+    }
+    let [kind, len @ ..] = header;
+    let len = u32::from_le_bytes(len) as usize;
+    // Read through `take`, so that a torn length allocates no more than the
+    // bytes actually there
+    let mut body = header.to_vec();
+    log.take(len as u64).read_to_end(&mut body)?;
+    let mut stored = [0; CHECKSUM_LEN];
+    if body.len() < RECORD_HEADER_LEN + len || !read_full(log, &mut stored)? {
+        return Ok(None);
+    }
+    if checksum(&body) != stored {
+        return Ok(None);
+    }
+    body.drain(..RECORD_HEADER_LEN);
+    Ok(Some((kind, body)))
+}
+
+/// Fill `buf` from `reader`, returning false if it ends first
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> Result<bool, StoreError> {
+    match reader.read_exact(buf) {
+        Ok(()) => Ok(true),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::store::TempDir;
+
+    fn block(tx: &[u8]) -> Block {
+        Block::new(vec![tx.to_vec()], BlockHash::ZERO).unwrap()
+    }
+
+    #[test]
+    fn test_prune_keeps_headers_across_reopen() {
+        let dir = TempDir::new("file-store-prune");
+        let [a, _, c, side] = [1u8, 2, 3, 4].map(|byte| block(&[byte; 1000]));
+        // A header committing to a state root is longer
+        let b = BlockBuilder::new(BlockHash::ZERO)
+            .transaction(vec![2; 1000])
+            .state_root([9; 32])
+            .build()
+            .unwrap();
+        let mut store = FileStore::open(dir.path()).unwrap();
+        for block in [&a, &b, &c, &side] {
+            store.put_block(block).unwrap();
+        }
+        store
+            .put_tip(0, &[a.hash(), b.hash(), c.hash()], 3)
+            .unwrap();
+        let log = dir.path().join(LOG_FILE);
+        let full_len = fs::metadata(&log).unwrap().len();
+
+        store.prune_below(2).unwrap();
+        assert!(fs::metadata(&log).unwrap().len() < full_len);
+        for store in [store, FileStore::open(dir.path()).unwrap()] {
+            assert_eq!(store.pruned_height(), Ok(2));
+            assert_eq!(store.get_block(&a.hash()), Ok(None));
+            assert_eq!(store.get_header(&b.hash()), Ok(Some(b.header().clone())));
+            assert_eq!(store.get_block(&c.hash()), Ok(Some(c.clone())));
+            assert_eq!(store.get_block(&side.hash()), Ok(Some(side.clone())));
+            assert_eq!(store.get_hash_at_height(0), Ok(Some(a.hash())));
+            assert_eq!(store.get_tip().unwrap().unwrap().cumulative_work, 3);
+        }
+    }
+
+    #[test]
+    fn test_reopen_and_torn_tail() {
+        let dir = TempDir::new("file-store");
+        let (a, b, c) = (block(b"a"), block(b"b"), block(b"c"));
+        {
+            let mut store = FileStore::open(dir.path()).unwrap();
+            store.put_block(&a).unwrap();
+            store.put_block(&b).unwrap();
+            store.put_tip(0, &[a.hash(), b.hash()], 2).unwrap();
+        }
+
+        let mut store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.get_block(&b.hash()), Ok(Some(b.clone())));
+        assert_eq!(store.get_hash_at_height(1), Ok(Some(b.hash())));
+        let good_len = fs::metadata(dir.path().join(LOG_FILE)).unwrap().len();
+
+        // A crash part-way through appending the next block and tip
+        store.put_block(&c).unwrap();
+        store.put_tip(1, &[c.hash()], 3).unwrap();
+        drop(store);
+        let log = dir.path().join(LOG_FILE);
+        let full_len = fs::metadata(&log).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&log)
+            .unwrap()
+            .set_len(full_len - 3)
+            .unwrap();
+
+        let store = FileStore::open(dir.path()).unwrap();
+        // The block record survived; the torn tip record did not
+        assert_eq!(store.get_block(&c.hash()), Ok(Some(c)));
+        assert_eq!(store.get_tip().unwrap().unwrap().hash, b.hash());
+        assert!(fs::metadata(&log).unwrap().len() > good_len);
+        assert!(fs::metadata(&log).unwrap().len() < full_len - 3);
+    }
+
+    #[test]
+    fn test_checksum_failure_is_a_torn_tail() {
+        let dir = TempDir::new("file-store");
+        let (a, b) = (block(b"a"), block(b"b"));
+        let mut store = FileStore::open(dir.path()).unwrap();
+        store.put_block(&a).unwrap();
+        let good_len = fs::metadata(dir.path().join(LOG_FILE)).unwrap().len();
+        store.put_block(&b).unwrap();
+        drop(store);
+
+        // The length of the last record landed but not all its bytes did
+        let log = dir.path().join(LOG_FILE);
+        let mut bytes = fs::read(&log).unwrap();
+        let last = bytes.len() - CHECKSUM_LEN - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&log, bytes).unwrap();
+
+        let store = FileStore::open(dir.path()).unwrap();
+        assert_eq!(store.get_block(&a.hash()), Ok(Some(a)));
+        assert_eq!(store.get_block(&b.hash()), Ok(None));
+        assert_eq!(fs::metadata(&log).unwrap().len(), good_len);
+    }
+
+    #[test]
+    fn test_corrupt_record_fails_open() {
+        let dir = TempDir::new("file-store");
+        let (a, b) = (block(b"a"), block(b"b"));
+        let mut store = FileStore::open(dir.path()).unwrap();
+        store.put_block(&a).unwrap();
+        // Intact and checksummed, but of no kind the store writes
+        store.append(9, b"unknown").unwrap();
+        store.put_block(&b).unwrap();
+        drop(store);
+
+        let log = dir.path().join(LOG_FILE);
+        let len = fs::metadata(&log).unwrap().len();
+        assert!(matches!(
+            FileStore::open(dir.path()),
+            Err(StoreError::Corrupt(_))
+        ));
+        // Nothing after the bad record was cut off
+        assert_eq!(fs::metadata(&log).unwrap().len(), len);
+    }
+}
@@ -0,0 +1,438 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use super::StoreError;
+use crate::block::{Block, BlockHash};
+use crate::codec::{Checksummed, DecodeError, DecodeLimits};
+
+const BLOCKS_FILE: &str = "blocks.dat";
+const INDEX_FILE: &str = "index.dat";
+
+/// Bytes before each block in `blocks.dat`: length and checksum
+const RECORD_HEADER_LEN: u64 = 4 + 4;
+/// Size of one `index.dat` entry: height, hash, offset and length
+const INDEX_ENTRY_LEN: usize = 8 + 32 + 8 + 4;
+
+/// Where a block's record sits in `blocks.dat`
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    hash: BlockHash,
+    offset: u64,
+    len: u32,
+}
+
+/// Append-only archival storage: blocks in height order in `blocks.dat`, and
+/// a fixed-size `(height, hash, offset, len)` entry per block in `index.dat`.
+///
+/// Each block record is its length, a checksum, and the block's binary
+/// encoding. Blocks are written before their index entry, so after a crash
+/// [`FlatFileStore::open`] re-indexes intact records the index missed and cuts
+/// off a torn final record in either file.
+#[derive(Debug)]
+pub struct FlatFileStore {
+    blocks_path: PathBuf,
+    blocks: File,
+    index: File,
+    entries: Vec<Entry>,
+    heights: HashMap<BlockHash, u64>,
+}
+
+impl FlatFileStore {
+    /// Open the store in `dir`, creating it if needed and recovering from a torn write
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, StoreError> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        let blocks_path = dir.join(BLOCKS_FILE);
+        let open = |path: PathBuf| {
+            OpenOptions::new()
+                .read(true)
+                .append(true)
+                .create(true)
+                .open(path)
+        };
+        let blocks = open(blocks_path.clone())?;
+        let mut index = open(dir.join(INDEX_FILE))?;
+
+        let mut bytes = Vec::new();
+        index.read_to_end(&mut bytes)?;
+        let mut entries = Vec::with_capacity(bytes.len() / INDEX_ENTRY_LEN);
+        for (height, chunk) in bytes.chunks_exact(INDEX_ENTRY_LEN).enumerate() {
+            match decode_entry(chunk) {
+                Some((entry_height, entry)) if entry_height == height as u64 => entries.push(entry),
+                _ => break,
+            }
+        }
+
+        let mut store = FlatFileStore {
+            blocks_path,
+            blocks,
+            index,
+            entries,
+            heights: HashMap::new(),
+        };
+        store.recover()?;
+        store.heights = store
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(height, entry)| (entry.hash, height as u64))
+            .collect();
+        Ok(store)
+    }
+
+    /// Make both files end exactly at the last intact, indexed block
+    fn recover(&mut self) -> Result<(), StoreError> {
+        let blocks_len = self.blocks.metadata()?.len();
+
+        // Drop entries whose record did not fully reach the disk
+        while let Some(last) = self.entries.last() {
+            if self.read_record(last).is_ok() {
+                break;
+            }
+            self.entries.pop();
+        }
+
+        // Index any intact records written after the last good entry
+        let mut offset = self
+            .entries
+            .last()
+            .map_or(0, |e| e.offset + RECORD_HEADER_LEN + e.len as u64);
+        let mut reader = BufReader::new(File::open(&self.blocks_path)?);
+        reader.seek(SeekFrom::Start(offset))?;
+        while let Ok((block, len)) = read_next(&mut reader) {
+            self.entries.push(Entry {
+                hash: block.hash(),
+                offset,
+                len,
+            });
+            offset += RECORD_HEADER_LEN + len as u64;
+        }
+
+        if offset < blocks_len {
+            self.blocks.set_len(offset)?;
+        }
+        self.index.set_len(0)?;
+        let mut index = Vec::with_capacity(self.entries.len() * INDEX_ENTRY_LEN);
+        for (height, entry) in self.entries.iter().enumerate() {
+            encode_entry(&mut index, height as u64, entry);
+        }
+        self.index.write_all(&index)?;
+        self.index.sync_data()?;
+        Ok(())
+    }
+
+    /// Number of stored blocks; the next appended block gets this height
+    pub fn len(&self) -> u64 {
+        self.entries.len() as u64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Append `block` at the next height and return that height
+    pub fn append(&mut self, block: &Block) -> Result<u64, StoreError> {
+        // The record header comes first, so size and checksum the block in
+        // one pass and stream it to the file in a second
+        let mut encoded = Checksummed::new(io::sink());
+        block.write_to(&mut encoded)?;
+        let len = u32::try_from(encoded.len())
+            .map_err(|_| DecodeError::InvalidValue("block too large"))?;
+        let offset = self.blocks.seek(SeekFrom::End(0))?;
+
+        let mut writer = BufWriter::new(&mut self.blocks);
+        writer.write_all(&len.to_le_bytes())?;
+        writer.write_all(&encoded.checksum())?;
+        block.write_to(&mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        self.blocks.sync_data()?;
+
+        let height = self.len();
+        let entry = Entry {
+            hash: block.hash(),
+            offset,
+            len,
+        };
+        let mut index = Vec::with_capacity(INDEX_ENTRY_LEN);
+        encode_entry(&mut index, height, &entry);
+        self.index.write_all(&index)?;
+
+        self.entries.push(entry);
+        self.heights.insert(entry.hash, height);
+        Ok(height)
+    }
+
+    pub fn get_by_height(&self, height: u64) -> Result<Option<Block>, StoreError> {
+        match usize::try_from(height)
+            .ok()
+            .and_then(|h| self.entries.get(h))
+        {
+            Some(entry) => self.read_record(entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_by_hash(&self, hash: &BlockHash) -> Result<Option<Block>, StoreError> {
+        match self.heights.get(hash) {
+            Some(&height) => self.get_by_height(height),
+            None => Ok(None),
+        }
+    }
+
+    pub fn height_of(&self, hash: &BlockHash) -> Option<u64> {
+        self.heights.get(hash).copied()
+    }
+
+    /// Stream every stored block in height order, reading one at a time
+    pub fn iter(&self) -> Result<FlatFileIter, StoreError> {
+        Ok(FlatFileIter {
+            reader: BufReader::new(File::open(&self.blocks_path)?),
+            remaining: self.entries.len(),
+        })
+    }
+
+    fn read_record(&self, entry: &Entry) -> Result<Block, StoreError> {
+        let mut file = File::open(&self.blocks_path)?;
+        file.seek(SeekFrom::Start(entry.offset))?;
+        let (block, len) = read_next(&mut file)?;
+        if len != entry.len || block.hash() != entry.hash {
+            return Err(StoreError::Corrupt(DecodeError::InvalidValue(
+                "index entry",
+            )));
+        }
+        Ok(block)
+    }
+}
+
+/// Streaming iterator over a [`FlatFileStore`], created by [`FlatFileStore::iter`]
+pub struct FlatFileIter {
+    reader: BufReader<File>,
+    remaining: usize,
+}
+
+impl Iterator for FlatFileIter {
+    type Item = Result<Block, StoreError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let result = read_next(&mut self.reader).map(|(block, _)| block);
+        if result.is_err() {
+            self.remaining = 0;
+        }
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.remaining))
+    }
+}
+
+fn encode_entry(buf: &mut Vec<u8>, height: u64, entry: &Entry) {
+    buf.extend_from_slice(&height.to_le_bytes());
+    buf.extend_from_slice(entry.hash.as_bytes());
+    buf.extend_from_slice(&entry.offset.to_le_bytes());
+    buf.extend_from_slice(&entry.len.to_le_bytes());
+}
+
+/// The height and entry [`encode_entry`] wrote at the start of `bytes`
+fn decode_entry(bytes: &[u8]) -> Option<(u64, Entry)> {
+    let (height, rest) = bytes.split_first_chunk()?;
+    let (hash, rest) = rest.split_first_chunk()?;
+    let (offset, rest) = rest.split_first_chunk()?;
+    let len = rest.first_chunk()?;
+    let entry = Entry {
+        hash: BlockHash::from_bytes(*hash),
+        offset: u64::from_le_bytes(*offset),
+        len: u32::from_le_bytes(*len),
+    };
+    Some((u64::from_le_bytes(*height), entry))
+}
+
+/// Read and check the record at the reader's position, returning the block and its encoded length
+fn read_next(reader: &mut impl Read) -> Result<(Block, u32), StoreError> {
+    let mut header = [0u8; RECORD_HEADER_LEN as usize];
+    reader.read_exact(&mut header)?;
+    let [l0, l1, l2, l3, ..] = header;
+    let len = u32::from_le_bytes([l0, l1, l2, l3]);
+    let limits = DecodeLimits::default();
+    if len as usize > limits.max_decode_bytes {
+        return Err(StoreError::Corrupt(DecodeError::LimitExceeded {
+            what: "stored block size",
+            value: len as u64,
+            max: limits.max_decode_bytes as u64,
+        }));
+    }
+
+    // Decode the block as it streams in, then read whatever it left of the
+    // record, so a bad checksum is reported ahead of what it garbled
+    let mut record = Checksummed::new(BufReader::new(reader.take(len as u64)));
+    let block = Block::read_from(&mut record, &limits);
+    let decoded = record.len();
+    if let Err(err) = io::copy(&mut record, &mut io::sink()) {
+        return Err(record.take_error().unwrap_or(err).into());
+    }
+    if let Some(err) = record.take_error() {
+        return Err(err.into());
+    }
+    if record.len() < len as u64 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    if record.checksum() != header[4..] {
+        return Err(StoreError::Corrupt(DecodeError::InvalidValue("checksum")));
+    }
+    let block = block?;
+    if decoded < len as u64 {
+        return Err(StoreError::Corrupt(DecodeError::TrailingBytes(
+            (len as u64 - decoded) as usize,
+        )));
+    }
+    Ok((block, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockBuilder;
+    use crate::store::TempDir;
+
+    fn blocks(count: usize) -> Vec<Block> {
+        let mut blocks: Vec<Block> = Vec::with_capacity(count);
+        for i in 0..count {
+            let prev = blocks.last().map_or(BlockHash::ZERO, Block::hash);
+            let block = BlockBuilder::new(prev)
+                .transactions((0..i % 5 + 1).map(|j| format!("tx {} {}", i, j).into_bytes()))
+                .timestamp(1_700_000_000 + i as u64)
+                .build()
+                .unwrap();
+            blocks.push(block);
+        }
+        blocks
+    }
+
+    fn file_len(dir: &TempDir, name: &str) -> u64 {
+        fs::metadata(dir.path().join(name)).unwrap().len()
+    }
+
+    fn truncate(dir: &TempDir, name: &str, by: u64) {
+        let file = OpenOptions::new()
+            .write(true)
+            .open(dir.path().join(name))
+            .unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - by).unwrap();
+    }
+
+    #[test]
+    fn test_round_trip_many_blocks() {
+        let dir = TempDir::new("flat-round-trip");
+        let blocks = blocks(300);
+        {
+            let mut store = FlatFileStore::open(dir.path()).unwrap();
+            for (height, block) in blocks.iter().enumerate() {
+                assert_eq!(store.append(block).unwrap(), height as u64);
+            }
+        }
+
+        let store = FlatFileStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 300);
+        assert_eq!(file_len(&dir, INDEX_FILE), 300 * INDEX_ENTRY_LEN as u64);
+        for height in [0, 1, 150, 299] {
+            let block = &blocks[height];
+            assert_eq!(
+                store.get_by_height(height as u64).unwrap().as_ref(),
+                Some(block)
+            );
+            assert_eq!(
+                store.get_by_hash(&block.hash()).unwrap().as_ref(),
+                Some(block)
+            );
+            assert_eq!(store.height_of(&block.hash()), Some(height as u64));
+        }
+        assert_eq!(store.get_by_height(300), Ok(None));
+        assert_eq!(store.get_by_hash(&BlockHash::ZERO), Ok(None));
+
+        let streamed: Vec<Block> = store.iter().unwrap().collect::<Result<_, _>>().unwrap();
+        assert_eq!(streamed, blocks);
+    }
+
+    #[test]
+    fn test_recovers_from_torn_block_write() {
+        let dir = TempDir::new("flat-torn-block");
+        let blocks = blocks(11);
+        {
+            let mut store = FlatFileStore::open(dir.path()).unwrap();
+            for block in &blocks[..10] {
+                store.append(block).unwrap();
+            }
+        }
+        let good_len = file_len(&dir, BLOCKS_FILE);
+        {
+            let mut store = FlatFileStore::open(dir.path()).unwrap();
+            store.append(&blocks[10]).unwrap();
+        }
+        // The process died half-way through writing the last block
+        truncate(&dir, BLOCKS_FILE, 20);
+
+        let mut store = FlatFileStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 10);
+        assert_eq!(file_len(&dir, BLOCKS_FILE), good_len);
+        assert_eq!(file_len(&dir, INDEX_FILE), 10 * INDEX_ENTRY_LEN as u64);
+        assert_eq!(store.get_by_hash(&blocks[10].hash()), Ok(None));
+
+        // Writing resumes cleanly at the recovered height
+        assert_eq!(store.append(&blocks[10]).unwrap(), 10);
+        drop(store);
+        let store = FlatFileStore::open(dir.path()).unwrap();
+        assert_eq!(store.get_by_height(10).unwrap().as_ref(), Some(&blocks[10]));
+    }
+
+    #[test]
+    fn test_reports_corrupt_records() {
+        let dir = TempDir::new("flat-corrupt");
+        let blocks = blocks(3);
+        let mut store = FlatFileStore::open(dir.path()).unwrap();
+        for block in &blocks {
+            store.append(block).unwrap();
+        }
+        assert_eq!(store.get_by_height(1).unwrap().as_ref(), Some(&blocks[1]));
+
+        // Flip a byte of the middle block's header, which still decodes
+        let offset = store.entries[1].offset + RECORD_HEADER_LEN + 10;
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(dir.path().join(BLOCKS_FILE))
+            .unwrap();
+        file.seek(SeekFrom::Start(offset)).unwrap();
+        file.write_all(&[0xff]).unwrap();
+        assert_eq!(
+            store.get_by_height(1),
+            Err(StoreError::Corrupt(DecodeError::InvalidValue("checksum")))
+        );
+        assert_eq!(store.get_by_height(2).unwrap().as_ref(), Some(&blocks[2]));
+    }
+
+    #[test]
+    fn test_reindexes_after_torn_index_write() {
+        let dir = TempDir::new("flat-torn-index");
+        let blocks = blocks(5);
+        {
+            let mut store = FlatFileStore::open(dir.path()).unwrap();
+            for block in &blocks {
+                store.append(block).unwrap();
+            }
+        }
+        // The last block reached the disk but its index entry only partly did
+        truncate(&dir, INDEX_FILE, 7);
+
+        let store = FlatFileStore::open(dir.path()).unwrap();
+        assert_eq!(store.len(), 5);
+        assert_eq!(store.get_by_height(4).unwrap().as_ref(), Some(&blocks[4]));
+        assert_eq!(file_len(&dir, INDEX_FILE), 5 * INDEX_ENTRY_LEN as u64);
+    }
+}
@@ -0,0 +1,272 @@
+//! Storage backends for [`crate::chain::Blockchain`].
+//!
+//! A store keeps blocks by hash, an index from active-chain height to hash,
+//! and a pointer to the tip. The chain writes every accepted block and moves
+//! the index and tip together in one [`ChainStore::put_tip`] call, so a store
+//! that makes that call atomic never exposes a half-applied reorg.
+//!
+//! A chain that prunes old blocks asks its store to drop their bodies with
+//! [`ChainStore::prune_below`]; their headers and index entries stay.
+
+use std::collections::HashMap;
+#[cfg(test)]
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+use crate::block::{Block, BlockDecodeError, BlockHash, BlockHeader};
+use crate::codec::DecodeError;
+
+mod file;
+mod flat;
+
+pub use file::FileStore;
+pub use flat::{FlatFileIter, FlatFileStore};
+
+/// Errors raised by a storage backend
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum StoreError {
+    /// The underlying storage could not be read or written
+    #[error("storage error: {0}")]
+    Io(String),
+    /// Stored bytes could not be decoded
+    #[error("corrupt stored data: {0}")]
+    Corrupt(DecodeError),
+    /// The height index has no entry for a height at or below the tip
+    #[error("no block indexed at height {0}")]
+    MissingHeight(u64),
+    /// A block referenced by the index is not stored
+    #[error("block {0} is not stored")]
+    MissingBlock(BlockHash),
+}
+
+impl From<std::io::Error> for StoreError {
+    fn from(err: std::io::Error) -> Self {
+        StoreError::Io(err.to_string())
+    }
+}
+
+impl From<DecodeError> for StoreError {
+    fn from(err: DecodeError) -> Self {
+        StoreError::Corrupt(err)
+    }
+}
+
+impl From<BlockDecodeError> for StoreError {
+    fn from(err: BlockDecodeError) -> Self {
+        match err {
+            BlockDecodeError::Io(err) => StoreError::Io(err),
+            BlockDecodeError::Decode(err) => StoreError::Corrupt(err),
+        }
+    }
+}
+
+/// The tip of the active chain as recorded by a store
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainTip {
+    pub height: u64,
+    pub hash: BlockHash,
+    /// Total work from genesis to the tip, needed to resume fork choice
+    pub cumulative_work: u128,
+}
+
+/// A persistent home for a chain's blocks and active-chain index
+pub trait ChainStore {
+    /// Store `block` under its hash; storing a block twice is not an error
+    fn put_block(&mut self, block: &Block) -> Result<(), StoreError>;
+
+    fn get_block(&self, hash: &BlockHash) -> Result<Option<Block>, StoreError>;
+
+    /// The hash at `height` on the active chain
+    fn get_hash_at_height(&self, height: u64) -> Result<Option<BlockHash>, StoreError>;
+
+    /// Atomically point heights `from_height..` at `hashes`, drop any index
+    /// entries above them, and record the last hash as the tip.
+    ///
+    /// `hashes` is never empty.
+    fn put_tip(
+        &mut self,
+        from_height: u64,
+        hashes: &[BlockHash],
+        cumulative_work: u128,
+    ) -> Result<(), StoreError>;
+
+    /// The recorded tip, or `None` for an empty store
+    fn get_tip(&self) -> Result<Option<ChainTip>, StoreError>;
+
+    /// The header of the block with `hash`, including one whose body was pruned
+    fn get_header(&self, hash: &BlockHash) -> Result<Option<BlockHeader>, StoreError>;
+
+    /// Drop the bodies of the active blocks below `height`, keeping their
+    /// headers and index entries, and record `height` as the pruned height.
+    ///
+    /// Pruned blocks are no longer returned by `get_block`. A height at or
+    /// below the current pruned height changes nothing.
+    fn prune_below(&mut self, height: u64) -> Result<(), StoreError>;
+
+    /// Height below which the active blocks have been pruned; 0 if none have
+    fn pruned_height(&self) -> Result<u64, StoreError>;
+}
+
+/// A store whose reads cannot fail, so that a chain over it can iterate
+/// its blocks without reporting errors; see
+/// [`Blockchain::iter`](crate::chain::Blockchain::iter)
+pub trait InfallibleStore: ChainStore {}
+
+/// A store that keeps everything in memory and forgets it when dropped
+#[derive(Clone, Debug, Default)]
+pub struct MemoryStore {
+    blocks: HashMap<BlockHash, Block>,
+    /// Headers of the pruned blocks
+    headers: HashMap<BlockHash, BlockHeader>,
+    heights: Vec<BlockHash>,
+    cumulative_work: u128,
+    pruned_height: u64,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keep `header` for a block whose body is not held, as if it had been pruned
+    pub(crate) fn put_header(&mut self, header: &BlockHeader) {
+        self.headers.insert(header.hash(), header.clone());
+    }
+}
+
+impl InfallibleStore for MemoryStore {}
+
+impl ChainStore for MemoryStore {
+    fn put_block(&mut self, block: &Block) -> Result<(), StoreError> {
+        self.blocks
+            .entry(block.hash())
+            .or_insert_with(|| block.clone());
+        Ok(())
+    }
+
+    fn get_block(&self, hash: &BlockHash) -> Result<Option<Block>, StoreError> {
+        Ok(self.blocks.get(hash).cloned())
+    }
+
+    fn get_hash_at_height(&self, height: u64) -> Result<Option<BlockHash>, StoreError> {
+        Ok(usize::try_from(height)
+            .ok()
+            .and_then(|height| self.heights.get(height))
+            .copied())
+    }
+
+    fn put_tip(
+        &mut self,
+        from_height: u64,
+        hashes: &[BlockHash],
+        cumulative_work: u128,
+    ) -> Result<(), StoreError> {
+        self.heights.truncate(from_height as usize);
+        self.heights.extend_from_slice(hashes);
+        self.cumulative_work = cumulative_work;
+        Ok(())
+    }
+
+    fn get_tip(&self) -> Result<Option<ChainTip>, StoreError> {
+        Ok(self.heights.last().map(|&hash| ChainTip {
+            height: self.heights.len() as u64 - 1,
+            hash,
+            cumulative_work: self.cumulative_work,
+        }))
+    }
+
+    fn get_header(&self, hash: &BlockHash) -> Result<Option<BlockHeader>, StoreError> {
+        Ok(self
+            .blocks
+            .get(hash)
+            .map(Block::header)
+            .or_else(|| self.headers.get(hash))
+            .cloned())
+    }
+
+    fn prune_below(&mut self, height: u64) -> Result<(), StoreError> {
+        let height = height.min(self.heights.len() as u64);
+        if height <= self.pruned_height {
+            return Ok(());
+        }
+        for hash in &self.heights[self.pruned_height as usize..height as usize] {
+            if let Some(block) = self.blocks.remove(hash) {
+                self.headers.insert(*hash, block.header().clone());
+            }
+        }
+        self.pruned_height = height;
+        Ok(())
+    }
+
+    fn pruned_height(&self) -> Result<u64, StoreError> {
+        Ok(self.pruned_height)
+    }
+}
+
+/// A fresh directory under the system temp dir, removed when dropped
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("aarwyn-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        TempDir(path)
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(tx: &[u8]) -> Block {
+        Block::new(vec![tx.to_vec()], BlockHash::ZERO).unwrap()
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let mut store = MemoryStore::new();
+        assert_eq!(store.get_tip(), Ok(None));
+
+        let (a, b, c) = (block(b"a"), block(b"b"), block(b"c"));
+        for block in [&a, &b, &c] {
+            store.put_block(block).unwrap();
+        }
+        assert_eq!(store.get_block(&b.hash()), Ok(Some(b.clone())));
+        assert_eq!(store.get_block(&BlockHash::ZERO), Ok(None));
+
+        store.put_tip(0, &[a.hash(), b.hash()], 2).unwrap();
+        store.put_tip(1, &[c.hash()], 5).unwrap();
+        assert_eq!(store.get_hash_at_height(1), Ok(Some(c.hash())));
+        assert_eq!(store.get_hash_at_height(2), Ok(None));
+        assert_eq!(
+            store.get_tip(),
+            Ok(Some(ChainTip {
+                height: 1,
+                hash: c.hash(),
+                cumulative_work: 5
+            }))
+        );
+
+        store.prune_below(1).unwrap();
+        assert_eq!(store.pruned_height(), Ok(1));
+        assert_eq!(store.get_block(&a.hash()), Ok(None));
+        assert_eq!(store.get_header(&a.hash()), Ok(Some(a.header().clone())));
+        assert_eq!(store.get_block(&c.hash()), Ok(Some(c.clone())));
+        store.prune_below(0).unwrap();
+        assert_eq!(store.pruned_height(), Ok(1));
+    }
+}
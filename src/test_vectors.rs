@@ -0,0 +1,483 @@
+//! Frozen vectors for other implementations of the chain's formats.
+//!
+//! Each generator here derives its vectors from fixed inputs only: leaf sets,
+//! header fields, key seeds and messages. The output is checked in under
+//! `vectors/` at the root of the repository, and the tests below compare it
+//! byte for byte against a fresh run, so a change to hashing or encoding fails
+//! loudly instead of quietly parting ways with verifiers written elsewhere.
+//! After an intended change, rewrite the files with
+//!
+//! ```text
+//! cargo run --example gen_vectors --features vectors
+//! ```
+//!
+//! Byte strings are written as lower-case hex. A file is a JSON array with
+//! one vector per line, to keep diffs readable.
+//!
+//! `serde.json` differs from the rest in freezing this crate's serde forms
+//! rather than a format of the chain: the bincode and postcard bytes of
+//! blocks, transactions, proofs and snapshots, for programs that pass them
+//! between processes.
+//!
+//! The generators build their fixtures from constant inputs and only run in
+//! tests and `vectors` builds, so a failure to build one is a bug to surface
+//! at once rather than an error to pass on.
+
+#![allow(clippy::unwrap_used, clippy::expect_used)]
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::address::Address;
+use crate::block::{Block, BlockBuilder, BlockHash, BlockHeader, STATE_ROOT_VERSION};
+use crate::chain::{Blockchain, Snapshot};
+use crate::codec;
+use crate::crypto::{ed25519, secp256k1, PublicKey, SecretKey, Signer};
+use crate::difficulty::Difficulty;
+use crate::merkle_trie::{MerkleProof, MerkleTree};
+use crate::params::ChainParams;
+use crate::transaction::{OutPoint, SpendCondition, Transaction, TxInput, TxOutput, Txid};
+
+/// The message each key signs in the signature vectors
+pub const MESSAGE: &[u8] = b"aarwyn-chain test vector";
+
+/// Every vector file: its name under `vectors/` and its contents
+pub fn files() -> Vec<(&'static str, String)> {
+    vec![
+        ("merkle.json", render(merkle())),
+        ("headers.json", render(headers())),
+        ("signatures.json", render(signatures())),
+        ("addresses.json", render(addresses())),
+        ("serde.json", render(serde_formats())),
+    ]
+}
+
+/// Merkle roots of leaf sets of one to nine leaves, each with the serialized
+/// inclusion proof of every leaf.
+///
+/// The chain has a single tree construction, with an odd node promoted to the
+/// next level unpaired; the sizes cover every shape of that up to four levels.
+pub fn merkle() -> Vec<Value> {
+    (1..=9)
+        .map(|size| {
+            let leaves: Vec<Vec<u8>> = (0..size)
+                .map(|i| format!("leaf-{}", i).into_bytes())
+                .collect();
+            let tree = MerkleTree::new(&leaves).unwrap();
+            let proofs: Vec<String> = (0..size)
+                .map(|i| hex::encode(tree.generate_proof(i).unwrap().to_bytes()))
+                .collect();
+            json!({
+                "leaves": hex_list(&leaves),
+                "root": hex::encode(tree.root_hash()),
+                "proofs": proofs,
+            })
+        })
+        .collect()
+}
+
+/// Headers built from fixed fields, with their encodings and hashes: one
+/// before and one after [`STATE_ROOT_VERSION`]
+pub fn headers() -> Vec<Value> {
+    header_builders()
+        .into_iter()
+        .map(|builder| {
+            let block = builder.build().unwrap();
+            let header = block.header();
+            json!({
+                "version": header.version(),
+                "prev_block_hash": hex::encode(header.prev_block_hash().as_bytes()),
+                "transactions": hex_list(block.transactions()),
+                "state_root": header.state_root().map(hex::encode),
+                "timestamp": header.timestamp(),
+                "bits": header.bits(),
+                "nonce": header.nonce(),
+                "merkle_root": hex::encode(header.merkle_root()),
+                "header": hex::encode(header.to_bytes()),
+                "hash": hex::encode(header.hash().as_bytes()),
+            })
+        })
+        .collect()
+}
+
+/// The blocks behind [`headers`], one before and one after
+/// [`STATE_ROOT_VERSION`]
+fn header_builders() -> [BlockBuilder; 2] {
+    let genesis = BlockBuilder::new(BlockHash::from_bytes([0; 32]))
+        .transaction(b"genesis".to_vec())
+        .timestamp(1_700_000_000)
+        .difficulty(Difficulty::LeadingZeroBits(8))
+        .nonce(42);
+    let with_state = BlockBuilder::new(BlockHash::from_bytes([0xab; 32]))
+        .version(STATE_ROOT_VERSION)
+        .transactions([b"first".to_vec(), b"second".to_vec(), b"third".to_vec()])
+        .timestamp(1_700_000_600)
+        .difficulty(Difficulty::LeadingZeroBits(20))
+        .nonce(u64::MAX)
+        .state_root([0x5a; 32]);
+    [genesis, with_state]
+}
+
+/// Keys of both schemes from fixed seeds, their signatures over
+/// [`MESSAGE`], and a transaction they spend from together: its sighashes,
+/// the signatures over them and the signed encoding.
+///
+/// The ed25519 input reveals its key; the secp256k1 one carries a recovery
+/// id instead.
+pub fn signatures() -> Vec<Value> {
+    let secp256k1_key = || secp256k1::SigningKey::from_bytes(&[2; 32]).unwrap();
+    let keys: Vec<SecretKey> = vec![
+        ed25519::SigningKey::from_bytes(&[1; 32]).into(),
+        secp256k1_key().into(),
+    ];
+
+    let mut vectors: Vec<Value> = keys
+        .iter()
+        .map(|key| {
+            json!({
+                "scheme": format!("{:?}", key.scheme()),
+                "secret_key": hex::encode(*key.to_bytes()),
+                "public_key": hex::encode(key.public_key().as_bytes()),
+                "message": hex::encode(MESSAGE),
+                "signature": hex::encode(key.sign_message(MESSAGE).to_bytes()),
+            })
+        })
+        .collect();
+
+    let mut tx = Transaction {
+        inputs: (0..2)
+            .map(|index| TxInput {
+                prev_out: OutPoint {
+                    txid: Txid::of(b"funding"),
+                    index,
+                },
+                signatures: Vec::new(),
+                public_key: None,
+                recovery_id: None,
+            })
+            .collect(),
+        outputs: keys
+            .iter()
+            .zip([5_000, 12_345])
+            .map(|(key, amount)| {
+                TxOutput::to_address(amount, Address::from_public_key(&key.public_key()))
+            })
+            .collect(),
+        lock_time: 100,
+    };
+    let unsigned = tx.encode();
+    let sighashes: Vec<[u8; 32]> = (0..tx.inputs.len()).map(|i| tx.sighash(i)).collect();
+    tx.sign_input(0, &keys[0]);
+    tx.sign_input_recoverable(1, &secp256k1_key());
+    let signatures: Vec<_> = tx
+        .inputs
+        .iter()
+        .map(|input| input.signatures[0].to_bytes())
+        .collect();
+
+    vectors.push(json!({
+        "unsigned": hex::encode(unsigned),
+        "sighashes": hex_list(&sighashes),
+        "signatures": hex_list(&signatures),
+        "recovery_id": tx.inputs[1].recovery_id.unwrap().to_u8(),
+        "signed": hex::encode(tx.encode()),
+        "txid": hex::encode(tx.txid().as_bytes()),
+    }));
+    vectors
+}
+
+/// Addresses of keys of both schemes from fixed seeds, in base58check under
+/// two version bytes and in bech32m under the main and test network
+/// human-readable parts
+pub fn addresses() -> Vec<Value> {
+    let keys: Vec<PublicKey> = (1..=3u8)
+        .flat_map(|seed| {
+            [
+                ed25519::SigningKey::from_bytes(&[seed; 32]).public_key(),
+                secp256k1::SigningKey::from_bytes(&[seed; 32])
+                    .unwrap()
+                    .public_key(),
+            ]
+        })
+        .collect();
+
+    keys.iter()
+        .map(|key| {
+            let address = Address::from_public_key(key);
+            json!({
+                "public_key": hex::encode(key.as_bytes()),
+                "address": hex::encode(address.as_bytes()),
+                "base58check_0": address.to_base58check(0),
+                "base58check_23": address.to_base58check(23),
+                "bech32_arw": address.to_bech32("arw").unwrap(),
+                "bech32_tarw": address.to_bech32("tarw").unwrap(),
+            })
+        })
+        .collect()
+}
+
+/// Values of each type with a frozen serde form, built from fixed inputs
+pub struct SerdeFixtures {
+    /// The headers of [`headers`]
+    pub headers: Vec<BlockHeader>,
+    /// The block with a state root from [`headers`], signed by an ed25519
+    /// key
+    pub block: Block,
+    /// A transaction spending with a revealed ed25519 key and a recovered
+    /// secp256k1 one, paying a single-key and a multisig output
+    pub transaction: Transaction,
+    pub proof: MerkleProof,
+    /// A snapshot at height two of a chain of the test parameters
+    pub snapshot: Snapshot,
+}
+
+impl SerdeFixtures {
+    pub fn new() -> Self {
+        let ed25519_key = ed25519::SigningKey::from_bytes(&[1; 32]);
+        let secp256k1_key = secp256k1::SigningKey::from_bytes(&[2; 32]).unwrap();
+        let headers = header_builders().map(|builder| builder.build().unwrap());
+
+        let [_, mut block] = headers.clone();
+        block.sign(&ed25519_key);
+
+        let mut transaction = Transaction {
+            inputs: (0..2)
+                .map(|index| TxInput {
+                    prev_out: OutPoint {
+                        txid: Txid::of(b"funding"),
+                        index,
+                    },
+                    signatures: Vec::new(),
+                    public_key: None,
+                    recovery_id: None,
+                })
+                .collect(),
+            outputs: vec![
+                TxOutput::to_address(5_000, Address::from_public_key(&ed25519_key.public_key())),
+                TxOutput {
+                    amount: 12_345,
+                    condition: SpendCondition::MultiSig {
+                        m: 1,
+                        keys: vec![ed25519_key.public_key(), secp256k1_key.public_key()],
+                    },
+                },
+            ],
+            lock_time: 100,
+        };
+        transaction.sign_input(0, &SecretKey::from(ed25519_key));
+        transaction.sign_input_recoverable(1, &secp256k1_key);
+
+        let leaves: Vec<Vec<u8>> = (0..5).map(|i| format!("leaf-{}", i).into_bytes()).collect();
+        let proof = MerkleTree::new(&leaves).unwrap().generate_proof(3).unwrap();
+
+        let params = ChainParams::test_defaults();
+        let mut chain = Blockchain::new_from_params(&params);
+        for tag in [b"first", b"other"] {
+            let difficulty = params.initial_difficulty;
+            let mut next = chain
+                .tip()
+                .next_builder()
+                .transaction(tag.to_vec())
+                .difficulty(difficulty)
+                .timestamp(chain.tip().timestamp() + 10)
+                .build()
+                .unwrap();
+            next.mine(difficulty);
+            chain.append(next).unwrap();
+        }
+        let snapshot = chain.snapshot_at(2).unwrap();
+
+        SerdeFixtures {
+            headers: headers.iter().map(|block| block.header().clone()).collect(),
+            block,
+            transaction,
+            proof,
+            snapshot,
+        }
+    }
+}
+
+impl Default for SerdeFixtures {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The bincode, through [`codec::to_bincode`], and postcard encodings of
+/// each of the [`SerdeFixtures`], named by type
+pub fn serde_formats() -> Vec<Value> {
+    let fixtures = SerdeFixtures::new();
+    let mut vectors: Vec<Value> = fixtures
+        .headers
+        .iter()
+        .map(|header| serde_vector("BlockHeader", header))
+        .collect();
+    vectors.extend([
+        serde_vector("Block", &fixtures.block),
+        serde_vector("Transaction", &fixtures.transaction),
+        serde_vector("MerkleProof", &fixtures.proof),
+        serde_vector("Snapshot", &fixtures.snapshot),
+    ]);
+    vectors
+}
+
+fn serde_vector<T: Serialize>(name: &str, value: &T) -> Value {
+    json!({
+        "type": name,
+        "bincode": hex::encode(codec::to_bincode(value).unwrap()),
+        "postcard": hex::encode(postcard::to_allocvec(value).unwrap()),
+    })
+}
+
+fn hex_list<T: AsRef<[u8]>>(items: &[T]) -> Value {
+    Value::Array(items.iter().map(|item| hex::encode(item).into()).collect())
+}
+
+/// A JSON array with each vector on its own line
+fn render(vectors: Vec<Value>) -> String {
+    let lines: Vec<String> = vectors.iter().map(Value::to_string).collect();
+    format!("[\n{}\n]\n", lines.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use serde::de::DeserializeOwned;
+
+    use super::*;
+    use crate::codec::DecodeLimits;
+    use crate::crypto::{Signature, SignatureScheme, Verifier};
+    use crate::merkle_trie::MerkleProof;
+
+    fn assert_frozen(name: &str, checked_in: &str) {
+        let (_, generated) = files().into_iter().find(|(file, _)| *file == name).unwrap();
+        assert!(
+            generated == checked_in,
+            "vectors/{} no longer matches the code; if the change is intended, \
+             run `cargo run --example gen_vectors --features vectors`",
+            name
+        );
+    }
+
+    #[test]
+    fn test_merkle_vectors_frozen() {
+        assert_frozen("merkle.json", include_str!("../vectors/merkle.json"));
+    }
+
+    #[test]
+    fn test_header_vectors_frozen() {
+        assert_frozen("headers.json", include_str!("../vectors/headers.json"));
+    }
+
+    #[test]
+    fn test_signature_vectors_frozen() {
+        assert_frozen(
+            "signatures.json",
+            include_str!("../vectors/signatures.json"),
+        );
+    }
+
+    #[test]
+    fn test_address_vectors_frozen() {
+        assert_frozen("addresses.json", include_str!("../vectors/addresses.json"));
+    }
+
+    #[test]
+    fn test_serde_vectors_frozen() {
+        assert_frozen("serde.json", include_str!("../vectors/serde.json"));
+    }
+
+    fn bytes(value: &Value) -> Vec<u8> {
+        hex::decode(value.as_str().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_vectors_check_out() {
+        // The checked-in files parse, and what they claim holds
+        let merkle = serde_json::from_str::<Value>(include_str!("../vectors/merkle.json")).unwrap();
+        for vector in merkle.as_array().unwrap() {
+            let leaves = vector.get("leaves").unwrap().as_array().unwrap();
+            let proofs = vector.get("proofs").unwrap().as_array().unwrap();
+            for (leaf, proof) in leaves.iter().zip(proofs) {
+                let proof =
+                    MerkleProof::from_bytes(&bytes(proof), &DecodeLimits::default()).unwrap();
+                assert_eq!(proof.root_hash(), bytes(vector.get("root").unwrap()));
+                assert!(proof.verify(bytes(leaf)));
+            }
+        }
+
+        let signatures =
+            serde_json::from_str::<Value>(include_str!("../vectors/signatures.json")).unwrap();
+        for vector in &signatures.as_array().unwrap()[..2] {
+            let scheme = match vector.get("scheme").unwrap().as_str().unwrap() {
+                "Ed25519" => SignatureScheme::Ed25519,
+                _ => SignatureScheme::Secp256k1,
+            };
+            let key =
+                PublicKey::from_bytes(scheme, &bytes(vector.get("public_key").unwrap())).unwrap();
+            let signature = bytes(vector.get("signature").unwrap());
+            let signature = Signature::from_bytes(scheme, signature.as_slice().try_into().unwrap());
+            assert_eq!(key.verify_message(MESSAGE, &signature), Ok(()));
+        }
+    }
+
+    // Both checked-in blobs of `vector` decode to `expected` and encode back
+    // to themselves
+    fn assert_serde<T>(vector: &Value, expected: &T)
+    where
+        T: Serialize + DeserializeOwned + PartialEq + Debug,
+    {
+        let bincode = bytes(vector.get("bincode").unwrap());
+        let decoded: T = codec::from_bincode(&bincode, &DecodeLimits::default()).unwrap();
+        assert_eq!(&decoded, expected);
+        assert_eq!(codec::to_bincode(&decoded).unwrap(), bincode);
+
+        let postcard = bytes(vector.get("postcard").unwrap());
+        let decoded: T = postcard::from_bytes(&postcard).unwrap();
+        assert_eq!(&decoded, expected);
+        assert_eq!(postcard::to_allocvec(&decoded).unwrap(), postcard);
+    }
+
+    #[test]
+    fn test_serde_vectors_decode() {
+        let fixtures = SerdeFixtures::new();
+        let serde = serde_json::from_str::<Value>(include_str!("../vectors/serde.json")).unwrap();
+        let vectors = serde.as_array().unwrap();
+        assert_eq!(vectors.len(), 6);
+        for (vector, header) in vectors.iter().zip(&fixtures.headers) {
+            assert_serde(vector, header);
+        }
+        assert_serde(&vectors[2], &fixtures.block);
+        assert_serde(&vectors[3], &fixtures.transaction);
+        assert_serde(&vectors[4], &fixtures.proof);
+        assert_serde(&vectors[5], &fixtures.snapshot);
+        assert!(fixtures.block.verify_merkle_root());
+    }
+
+    #[test]
+    fn test_serde_refuses_inconsistent_headers() {
+        let [old, new] = header_builders().map(|builder| builder.build().unwrap());
+        let limits = DecodeLimits::default();
+        let old = codec::to_bincode(old.header()).unwrap();
+        let new = codec::to_bincode(new.header()).unwrap();
+
+        // A version 2 header without a state root, or version 1 with one
+        let mut missing = new[..new.len() - 8 - 4 - 8 - 33].to_vec();
+        missing.push(0);
+        missing.extend_from_slice(&new[new.len() - 20..]);
+        let err = codec::from_bincode::<BlockHeader>(&missing, &limits).unwrap_err();
+        assert!(err.to_string().contains("state root"), "{}", err);
+        let mut downgraded = new.clone();
+        downgraded[..4].copy_from_slice(&old[..4]);
+        let err = codec::from_bincode::<BlockHeader>(&downgraded, &limits).unwrap_err();
+        assert!(err.to_string().contains("state root"), "{}", err);
+
+        // A merkle root of the wrong length is not a `Hash32`
+        let mut short_root = old.clone();
+        short_root[4 + 32] = 31;
+        short_root.remove(4 + 32 + 8);
+        let err = codec::from_bincode::<BlockHeader>(&short_root, &limits).unwrap_err();
+        assert!(err.to_string().contains("a 32-byte hash"), "{}", err);
+    }
+}
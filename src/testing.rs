@@ -0,0 +1,317 @@
+//! Proptest strategies for property tests of this crate and of crates built
+//! on it.
+//!
+//! Each strategy produces values the crate accepts: transactions that decode
+//! to themselves, blocks mined at the lowest difficulty, chains that append
+//! block by block, and Merkle proofs that verify. They shrink toward fewer and
+//! smaller parts, so a failing case is reported as small as it will go. The
+//! `corrupt_*` strategies take a valid value and break exactly one thing
+//! about it. Alongside the strategies, [`signed_spend`] builds a fixed
+//! transaction signed by the one test [`key`], for tests that need spends to
+//! pass signature checks.
+//!
+//! Outside this crate's own tests the module needs the `test-utils` feature:
+//!
+//! ```toml
+//! [dev-dependencies]
+//! aarwyn-chain = { version = "0.1", features = ["test-utils"] }
+//! ```
+//!
+//! The strategies only build from inputs they have already constrained to be
+//! valid, so a failure to build one is a bug in the strategy to surface at
+//! once rather than an error to pass on.
+
+#![allow(clippy::expect_used)]
+
+use std::ops::Range;
+
+use proptest::collection::{vec, SizeRange};
+use proptest::option;
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+use crate::address::Address;
+use crate::block::{Block, BlockBuilder, BlockHash, BlockHeader};
+use crate::chain::Blockchain;
+use crate::codec::DecodeLimits;
+use crate::crypto::secp256k1::RecoveryId;
+use crate::crypto::{ed25519, secp256k1, PublicKey, Signature, SignatureScheme, Signer};
+use crate::difficulty::Difficulty;
+use crate::hash::Hash32;
+use crate::merkle_trie::{MerkleProof, MerkleTree};
+use crate::params::{ChainParams, ConsensusMode};
+use crate::transaction::{OutPoint, SpendCondition, Transaction, TxInput, TxOutput, Txid};
+
+/// The difficulty generated blocks are mined at, which every hash meets
+pub const DIFFICULTY: Difficulty = Difficulty::LeadingZeroBits(0);
+
+/// Parameters the generated chains follow: [`ChainParams::test_defaults`]
+/// without proof of work
+pub fn params() -> ChainParams {
+    ChainParams {
+        initial_difficulty: DIFFICULTY,
+        consensus_mode: ConsensusMode::ProofOfWork {
+            difficulty: DIFFICULTY,
+        },
+        ..ChainParams::test_defaults()
+    }
+}
+
+/// Any 32-byte hash
+pub fn hash32() -> impl Strategy<Value = Hash32> {
+    any::<[u8; 32]>().prop_map(Hash32)
+}
+
+/// A public key of either scheme
+pub fn public_key() -> impl Strategy<Value = PublicKey> {
+    prop_oneof![
+        any::<[u8; 32]>().prop_map(|seed| ed25519::SigningKey::from_bytes(&seed).public_key()),
+        any::<[u8; 32]>().prop_filter_map("scalar out of range", |seed| {
+            secp256k1::SigningKey::from_bytes(&seed)
+                .ok()
+                .map(|key| key.public_key())
+        }),
+    ]
+}
+
+/// A signature of either scheme over nothing in particular; the encodings
+/// carry signatures without checking them
+pub fn signature() -> impl Strategy<Value = Signature> {
+    (any::<bool>(), any::<[u8; 64]>()).prop_map(|(secp256k1, bytes)| {
+        let scheme = match secp256k1 {
+            false => SignatureScheme::Ed25519,
+            true => SignatureScheme::Secp256k1,
+        };
+        Signature::from_bytes(scheme, &bytes)
+    })
+}
+
+/// An input with up to two signatures and a key, a recovery id in its
+/// place, or neither
+pub fn tx_input() -> impl Strategy<Value = TxInput> {
+    let key = prop_oneof![
+        Just((None, None)),
+        public_key().prop_map(|key| (Some(key), None)),
+        (0..4u8).prop_map(|id| (None, RecoveryId::from_u8(id))),
+    ];
+    (any::<[u8; 32]>(), any::<u32>(), vec(signature(), 0..3), key).prop_map(
+        |(txid, index, signatures, (public_key, recovery_id))| TxInput {
+            prev_out: OutPoint {
+                txid: Txid::from_bytes(txid),
+                index,
+            },
+            signatures,
+            public_key,
+            recovery_id,
+        },
+    )
+}
+
+/// A spend condition: a single address, or one to three distinct keys of
+/// which any number from one up must sign
+pub fn spend_condition() -> impl Strategy<Value = SpendCondition> {
+    let multisig = (vec(public_key(), 1..4), any::<Index>()).prop_map(|(keys, m)| {
+        let mut distinct: Vec<PublicKey> = Vec::with_capacity(keys.len());
+        for key in keys {
+            if !distinct.contains(&key) {
+                distinct.push(key);
+            }
+        }
+        SpendCondition::MultiSig {
+            m: m.index(distinct.len()) as u8 + 1,
+            keys: distinct,
+        }
+    });
+    let single =
+        any::<[u8; 32]>().prop_map(|bytes| SpendCondition::SingleKey(Address::from_bytes(bytes)));
+    prop_oneof![3 => single, 1 => multisig]
+}
+
+/// An output of a non-zero amount, so no transaction refuses it as dust
+pub fn tx_output() -> impl Strategy<Value = TxOutput> {
+    (1..=u64::MAX, spend_condition()).prop_map(|(amount, condition)| TxOutput { amount, condition })
+}
+
+/// A transaction that decodes to itself under the default limits: up to
+/// three inputs and outputs, with at least one output when there are inputs.
+/// Inputs are not signed for any outputs they spend.
+pub fn transaction() -> impl Strategy<Value = Transaction> {
+    (vec(tx_input(), 0..4), vec(tx_output(), 0..4), any::<u32>()).prop_map(
+        |(inputs, mut outputs, lock_time)| {
+            if outputs.is_empty() && !inputs.is_empty() {
+                outputs.push(TxOutput::to_address(1, Address::from_bytes([0; 32])));
+            }
+            Transaction {
+                inputs,
+                outputs,
+                lock_time,
+            }
+        },
+    )
+}
+
+/// The encoding of a [`transaction`], as blocks hold it
+pub fn transaction_bytes() -> impl Strategy<Value = Vec<u8>> {
+    transaction().prop_map(|tx| tx.encode())
+}
+
+/// A block of one to seven transactions on any parent, committing to a
+/// state root in one case out of four, mined at [`DIFFICULTY`]
+pub fn block() -> impl Strategy<Value = Block> {
+    (
+        any::<[u8; 32]>(),
+        vec(transaction_bytes(), 1..8),
+        1_700_000_000..1_800_000_000u64,
+        option::weighted(0.25, any::<[u8; 32]>()),
+    )
+        .prop_map(|(parent, transactions, timestamp, state_root)| {
+            let mut builder = BlockBuilder::new(BlockHash::from_bytes(parent));
+            if let Some(root) = state_root {
+                builder = builder.state_root(root);
+            }
+            mined(builder, transactions, timestamp)
+        })
+}
+
+/// The header of a [`block`]
+pub fn block_header() -> impl Strategy<Value = BlockHeader> {
+    block().prop_map(|block| block.header().clone())
+}
+
+/// The blocks of a chain from [`params`], genesis excluded, numbering within
+/// `blocks`; append them in order, or pass them to [`blockchain`]
+pub fn chain(blocks: impl Into<SizeRange>) -> impl Strategy<Value = Vec<Block>> {
+    vec((vec(transaction_bytes(), 1..4), 1..=600u64), blocks).prop_map(|bodies| {
+        let mut parent = params().genesis_block();
+        let mut blocks = Vec::with_capacity(bodies.len());
+        for (transactions, gap) in bodies {
+            let block = mined(
+                parent.next_builder(),
+                transactions,
+                parent.timestamp() + gap,
+            );
+            parent = block.clone();
+            blocks.push(block);
+        }
+        blocks
+    })
+}
+
+/// A chain from [`params`] with `blocks` appended
+pub fn blockchain(blocks: impl IntoIterator<Item = Block>) -> Blockchain {
+    let mut chain = Blockchain::new_from_params(&params());
+    for block in blocks {
+        chain
+            .append(block)
+            .expect("generated blocks extend the chain");
+    }
+    chain
+}
+
+/// Leaves of a Merkle tree of one to 63 leaves, the index of one of them,
+/// and the proof of that leaf
+pub fn merkle_proof() -> impl Strategy<Value = (Vec<Vec<u8>>, usize, MerkleProof)> {
+    merkle_proof_with(1..64)
+}
+
+/// As [`merkle_proof`], for a tree of a number of leaves within `leaves`,
+/// which must not include zero
+pub fn merkle_proof_with(
+    leaves: Range<usize>,
+) -> impl Strategy<Value = (Vec<Vec<u8>>, usize, MerkleProof)> {
+    (vec(vec(any::<u8>(), 0..16), leaves), any::<Index>()).prop_map(|(leaves, index)| {
+        let index = index.index(leaves.len());
+        let proof = MerkleTree::new(&leaves)
+            .and_then(|tree| tree.generate_proof(index))
+            .expect("the tree has the leaf");
+        (leaves, index, proof)
+    })
+}
+
+/// A leaf with a proof for it that has one bit flipped in its leaf hash,
+/// its root or one of its siblings, and so does not verify
+pub fn corrupt_merkle_proof() -> impl Strategy<Value = (Vec<u8>, MerkleProof)> {
+    (merkle_proof(), any::<Index>(), 0..256usize).prop_map(|((leaves, index, proof), hash, bit)| {
+        let mut siblings = proof.siblings().to_vec();
+        let mut leaf_hash = Hash32::try_from(proof.leaf_hash()).expect("a leaf hash is 32 bytes");
+        let mut root = proof.root();
+        match hash.index(siblings.len() + 2) {
+            0 => flip(&mut leaf_hash, bit),
+            1 => flip(&mut root, bit),
+            sibling => flip(&mut siblings[sibling - 2].0, bit),
+        }
+        let proof = MerkleProof::from_parts(siblings, leaf_hash, root);
+        (leaves[index].clone(), proof)
+    })
+}
+
+/// A [`block`] with one bit of the merkle root in its header flipped, so
+/// its transactions no longer match it
+pub fn corrupt_merkle_block() -> impl Strategy<Value = Block> {
+    (block(), 0..256usize).prop_map(|(block, bit)| flip_merkle_root_bit(&block, bit))
+}
+
+/// `block` with bit `bit` of its header's merkle root flipped; the new hash
+/// still meets [`DIFFICULTY`]
+pub fn flip_merkle_root_bit(block: &Block, bit: usize) -> Block {
+    let mut bytes = block.to_bytes();
+    // The merkle root follows the version and the previous block's hash
+    bytes[4 + 32 + bit / 8] ^= 1 << (bit % 8);
+    Block::from_bytes(&bytes, &DecodeLimits::default()).expect("the merkle root is not checked")
+}
+
+/// The key [`signed_spend`] signs with, and whose [`address`] every spend
+/// pays to
+pub fn key() -> ed25519::SigningKey {
+    ed25519::SigningKey::from_bytes(&[7; 32])
+}
+
+/// The address of [`key`]
+pub fn address() -> Address {
+    Address::from_public_key(&key().public_key())
+}
+
+/// A transaction spending `inputs`, with an output to [`address`] of each
+/// of `amounts`, and no signatures
+pub fn unsigned_spend(inputs: &[OutPoint], amounts: &[u64]) -> Transaction {
+    Transaction {
+        inputs: inputs
+            .iter()
+            .map(|&prev_out| TxInput {
+                prev_out,
+                signatures: Vec::new(),
+                public_key: None,
+                recovery_id: None,
+            })
+            .collect(),
+        outputs: amounts
+            .iter()
+            .map(|&amount| TxOutput::to_address(amount, address()))
+            .collect(),
+        lock_time: 0,
+    }
+}
+
+/// [`unsigned_spend`] with each input signed by [`key`]
+pub fn signed_spend(inputs: &[OutPoint], amounts: &[u64]) -> Transaction {
+    let mut tx = unsigned_spend(inputs, amounts);
+    for input in 0..tx.inputs.len() {
+        tx.sign_input(input, &key());
+    }
+    tx
+}
+
+fn flip(hash: &mut Hash32, bit: usize) {
+    hash.0[bit / 8] ^= 1 << (bit % 8);
+}
+
+fn mined(builder: BlockBuilder, transactions: Vec<Vec<u8>>, timestamp: u64) -> Block {
+    let mut block = builder
+        .transactions(transactions)
+        .timestamp(timestamp)
+        .difficulty(DIFFICULTY)
+        .build()
+        .expect("a generated block has a transaction");
+    block.mine(DIFFICULTY);
+    block
+}
@@ -0,0 +1,158 @@
+//! Instrumentation of mining, chain updates, Merkle proofs and network
+//! messages through the `tracing` crate, under the `tracing` feature.
+//!
+//! The macros here are the crate's only way to emit spans and events. With
+//! the feature off they expand to nothing, so neither the calls nor the
+//! values they would record are compiled; a value needed only for tracing
+//! is computed under `#[cfg(feature = "tracing")]` too. Hashes are recorded
+//! as [`short`] hex.
+//!
+//! Chain updates and mining are traced at `debug` level, each block's
+//! messages and proofs at `trace`, and rejected blocks at `info`.
+
+/// Emit an event at the given level, as `tracing::$level!` does
+macro_rules! event {
+    ($level:ident, $($arg:tt)+) => {
+        #[cfg(feature = "tracing")]
+        {
+            tracing::$level!($($arg)+);
+        }
+    };
+}
+
+/// Enter a `debug` span for the rest of the scope, as
+/// `tracing::debug_span!` does, returning its guard
+macro_rules! span {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        let guard = tracing::debug_span!($($arg)+).entered();
+        #[cfg(not(feature = "tracing"))]
+        let guard = $crate::trace::Disabled;
+        guard
+    }};
+}
+
+pub(crate) use {event, span};
+
+/// The guard of a span compiled away
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Disabled;
+
+/// The first six bytes of `hash` as twelve hex digits, which tell apart the
+/// hashes in any one trace and are quick to search a full hash for
+#[cfg(feature = "tracing")]
+pub(crate) fn short(hash: impl AsRef<[u8]>) -> String {
+    let hash = hash.as_ref();
+    hex::encode(&hash[..hash.len().min(6)])
+}
+
+#[cfg(test)]
+#[cfg(feature = "tracing")]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::fmt::MakeWriter;
+
+    use super::*;
+    use crate::block::Block;
+    use crate::chain::Blockchain;
+    use crate::testing::{self, DIFFICULTY};
+
+    /// Everything the subscriber writes, for the test to read back
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Captured {
+            self.clone()
+        }
+    }
+
+    fn child(parent: &Block, tx: &[u8]) -> Block {
+        let mut block = parent
+            .next_builder()
+            .transaction(tx.to_vec())
+            .timestamp(parent.timestamp() + 10)
+            .difficulty(DIFFICULTY)
+            .build()
+            .unwrap();
+        block.mine(DIFFICULTY);
+        block
+    }
+
+    #[test]
+    fn test_append_and_reorg_are_traced() {
+        let captured = Captured::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(captured.clone())
+            .with_ansi(false)
+            .finish();
+
+        let (a2, b3) = tracing::subscriber::with_default(subscriber, || {
+            let mut chain = Blockchain::new_from_params(&testing::params());
+            let genesis = chain.tip().clone();
+            let a1 = child(&genesis, b"a1");
+            let a2 = child(&a1, b"a2");
+            chain.append(a1).unwrap();
+            chain.append(a2.clone()).unwrap();
+
+            // A longer branch from genesis takes over
+            let b1 = child(&genesis, b"b1");
+            let b2 = child(&b1, b"b2");
+            let b3 = child(&b2, b"b3");
+            for block in [b1, b2, b3.clone()] {
+                chain.insert(block).unwrap();
+            }
+            assert_eq!(chain.tip(), &b3);
+            chain.append(a2.clone()).unwrap_err();
+
+            let proof = chain.tip().merkle_tree().generate_proof(0).unwrap();
+            assert!(proof.verify(b"b3"));
+            (a2, b3)
+        });
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        let has = |pattern: &[&str]| {
+            lines
+                .iter()
+                .any(|line| pattern.iter().all(|part| line.contains(part)))
+        };
+        assert!(has(&["mine", "mined", "attempts=1"]), "{}", output);
+        let a2_hash = format!("hash={}", short(a2.hash()));
+        assert!(has(&[
+            "append",
+            &a2_hash,
+            "block connected",
+            "height=2",
+            "elapsed="
+        ]));
+        assert!(has(&[
+            "reorg",
+            "depth=2",
+            &format!("old_tip={}", short(a2.hash())),
+            &format!("new_tip={}", short(b3.hash())),
+            "fork_height=0",
+        ]));
+        assert!(has(&["block rejected", &a2_hash, "error="]), "{}", output);
+        assert!(has(&["proof generated", "index=0", "leaves=1"]));
+        assert!(has(&["proof verified", "valid=true"]));
+        // Fields hold hashes short; only error messages spell them out
+        assert!(!output.contains(&a2.hash().to_string()));
+    }
+}
@@ -0,0 +1,1178 @@
+//! Structured transactions spending earlier outputs.
+//!
+//! Blocks carry transactions as opaque bytes and build their merkle tree over
+//! the hash of those bytes. A [`Transaction`] goes into a block as its
+//! canonical encoding, so its [`Txid`] is exactly the merkle leaf committing
+//! to it.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::address::Address;
+use crate::codec::{self, DecodeError, DecodeLimits, Reader};
+use crate::crypto::ed25519::VerifyingKey;
+use crate::crypto::secp256k1::RecoveryId;
+#[cfg(any(test, feature = "crypto"))]
+use crate::crypto::Signer;
+use crate::crypto::{
+    ed25519, secp256k1, PublicKey, Signature, SignatureScheme, Verifier, SIGNATURE_LENGTH,
+};
+use crate::encoding::EncodingError;
+use crate::hash::Hash32;
+use crate::merkle_trie::MerkleTree;
+use crate::utxo::UtxoView;
+
+/// Lock times below this are block heights, the rest Unix timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+/// Most keys a [`SpendCondition::MultiSig`] lists, and most signatures an
+/// input carries
+pub const MAX_MULTISIG_KEYS: usize = 16;
+
+/// Encoded size of an input without signatures or key: txid, index, the
+/// signature count and the key flag
+pub(crate) const MIN_INPUT_SIZE: usize = 32 + 4 + 1 + 1;
+/// Encoded size of a single-key output: amount, condition tag and address
+pub(crate) const OUTPUT_SIZE: usize = 8 + 1 + 32;
+
+/// Key flag of an input whose key is recovered from its signature; the low
+/// two bits hold the [`RecoveryId`]
+const RECOVERED_KEY_FLAG: u8 = 0x80;
+
+/// The SHA-256 hash of a transaction's encoding
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Txid([u8; 32]);
+
+impl Txid {
+    pub const fn from_bytes(bytes: [u8; 32]) -> Self {
+        Txid(bytes)
+    }
+
+    /// The txid of a transaction encoded as `bytes`
+    pub fn of(bytes: &[u8]) -> Self {
+        MerkleTree::hash(bytes).into()
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for Txid {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Hash32> for Txid {
+    fn from(hash: Hash32) -> Self {
+        Txid(hash.0)
+    }
+}
+
+impl From<Txid> for Hash32 {
+    fn from(txid: Txid) -> Self {
+        Hash32(txid.0)
+    }
+}
+
+impl fmt::Display for Txid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Txid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Txid({})", self)
+    }
+}
+
+impl FromStr for Txid {
+    type Err = EncodingError;
+
+    /// The txid whose 64 hex digits are `s`, as [`Hash32`] parses them
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse::<Hash32>()?.into())
+    }
+}
+
+/// Reasons the fees of a transaction or block cannot be worked out
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum FeeError {
+    /// The transaction at `index` in the block is not a valid [`Transaction`]
+    #[error("transaction {index} does not decode: {err}")]
+    Decode { index: usize, err: DecodeError },
+    /// An input spends an output the view does not hold
+    #[error("output {}:{} is not unspent", .0.txid, .0.index)]
+    MissingInput(OutPoint),
+    /// The transaction `txid` pays out more than it spends
+    #[error("transaction {txid} pays out {paid} but spends only {spent}")]
+    NegativeFee { txid: Txid, spent: u64, paid: u64 },
+    /// Amounts add up to more than a `u64` holds
+    #[error("amounts overflow")]
+    AmountOverflow,
+    /// The coinbase pays out more than the subsidy and fees allow
+    #[error("coinbase pays out {paid} but at most {allowed} is allowed")]
+    CoinbaseOverpays { paid: u64, allowed: u64 },
+}
+
+/// Reasons the input signatures of a block's transactions do not check out
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SigError {
+    /// The transaction at `index` in the block is not a valid [`Transaction`]
+    #[error("transaction {index} does not decode: {err}")]
+    Decode { index: usize, err: DecodeError },
+    /// Input `input` of the transaction at `index` is not signed
+    #[error("input {input} of transaction {index} is not signed")]
+    MissingSignature { index: usize, input: usize },
+    /// Input `input` of the transaction at `index` spends a single-key
+    /// output but carries more than one signature
+    #[error("input {input} of transaction {index} has more than one signature")]
+    ExtraSignatures { index: usize, input: usize },
+    /// Only `valid` of the signatures on input `input` of the transaction at
+    /// `index` verify against distinct keys of the multisig output it
+    /// spends, fewer than the `required` number
+    #[error(
+        "input {input} of transaction {index} has {valid} valid signatures but needs {required}"
+    )]
+    NotEnoughSignatures {
+        index: usize,
+        input: usize,
+        valid: usize,
+        required: u8,
+    },
+    /// The key input `input` of the transaction at `index` reveals does not
+    /// hash to the address of the output it spends
+    #[error("the key of input {input} of transaction {index} is not the one its output pays to")]
+    KeyMismatch { index: usize, input: usize },
+    /// The key input `input` of the transaction at `index` must be signed
+    /// with is not known
+    #[error("no key is known for input {input} of transaction {index}")]
+    UnknownKey { index: usize, input: usize },
+    /// The signature on input `input` of the transaction at `index` does not
+    /// match its [`Transaction::sighash`] and key
+    #[error("input {input} of transaction {index} has an invalid signature")]
+    InvalidSignature { index: usize, input: usize },
+}
+
+/// The earliest block a transaction whose lock time has not passed may join
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Locked {
+    /// A block at this height or above
+    UntilHeight(u64),
+    /// A block stamped at this time or later
+    UntilTime(u64),
+}
+
+impl fmt::Display for Locked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Locked::UntilHeight(height) => write!(f, "locked until height {}", height),
+            Locked::UntilTime(timestamp) => write!(f, "locked until time {}", timestamp),
+        }
+    }
+}
+
+/// Anything a block can carry as a transaction.
+///
+/// The block stores [`BlockTransaction::encode`] and hashes it into the
+/// merkle tree, so the leaf for a transaction is its [`BlockTransaction::txid`].
+pub trait BlockTransaction {
+    /// The bytes the block stores for this transaction
+    fn encode(&self) -> Vec<u8>;
+
+    /// The hash of [`BlockTransaction::encode`]
+    fn txid(&self) -> Txid {
+        Txid::of(&self.encode())
+    }
+}
+
+/// Raw transactions are stored as they are
+impl BlockTransaction for Vec<u8> {
+    fn encode(&self) -> Vec<u8> {
+        self.clone()
+    }
+}
+
+impl BlockTransaction for Transaction {
+    fn encode(&self) -> Vec<u8> {
+        Transaction::encode(self)
+    }
+}
+
+/// A reference to output `index` of the transaction `txid`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutPoint {
+    pub txid: Txid,
+    pub index: u32,
+}
+
+/// An input spending an earlier output
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TxInput {
+    pub prev_out: OutPoint,
+    /// Signatures over the input's sighash, of either scheme: one by the
+    /// key of a single-key output, or at least `m` by distinct keys of a
+    /// multisig output, in any order; empty until signed
+    pub signatures: Vec<Signature>,
+    /// The key a single-key output pays to, whose [`Address`] must be the
+    /// output's; `None` until signed, and for multisig outputs, which list
+    /// their keys
+    pub public_key: Option<PublicKey>,
+    /// Set in place of `public_key` when the input's lone signature is a
+    /// secp256k1 one the key can be recovered from; see
+    /// [`TxInput::signing_key`]
+    pub recovery_id: Option<RecoveryId>,
+}
+
+impl TxInput {
+    /// The key the input's signature must verify under, for a single-key
+    /// output: the revealed key, or else the key recovered from its one
+    /// secp256k1 signature over `sighash` with its recovery id
+    pub fn signing_key(&self, sighash: &[u8; 32]) -> Option<PublicKey> {
+        match (
+            self.public_key,
+            self.recovery_id,
+            self.signatures.as_slice(),
+        ) {
+            (Some(key), _, _) => Some(key),
+            (None, Some(id), [Signature::Secp256k1(signature)]) => {
+                secp256k1::VerifyingKey::recover(sighash, signature, id)
+                    .ok()
+                    .map(PublicKey::Secp256k1)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What it takes to spend an output
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum SpendCondition {
+    /// A signature by the key whose [`Address`] this is
+    SingleKey(Address),
+    /// Signatures by at least `m` of `keys`, which are distinct and at most
+    /// [`MAX_MULTISIG_KEYS`], with `m` at least one
+    MultiSig { m: u8, keys: Vec<PublicKey> },
+}
+
+impl SpendCondition {
+    /// The address a single-key output pays to
+    pub fn address(&self) -> Option<&Address> {
+        match self {
+            SpendCondition::SingleKey(address) => Some(address),
+            SpendCondition::MultiSig { .. } => None,
+        }
+    }
+
+    /// Append the encoding: a 0 tag and the address, or a 1 tag, `m`, the
+    /// key count and each key's [`SignatureScheme::tag`] and bytes
+    fn encode_into(&self, buf: &mut Vec<u8>) {
+        match self {
+            SpendCondition::SingleKey(address) => {
+                buf.push(0);
+                buf.extend_from_slice(address.as_bytes());
+            }
+            SpendCondition::MultiSig { m, keys } => {
+                buf.push(1);
+                buf.push(*m);
+                buf.push(keys.len() as u8);
+                for key in keys {
+                    buf.push(key.scheme().tag());
+                    buf.extend_from_slice(key.as_bytes());
+                }
+            }
+        }
+    }
+
+    fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        match reader.read_u8()? {
+            0 => Ok(SpendCondition::SingleKey(Address::from_bytes(
+                reader.read_array()?,
+            ))),
+            1 => {
+                let m = reader.read_u8()?;
+                let n = reader.read_u8()? as usize;
+                if n > MAX_MULTISIG_KEYS {
+                    return Err(DecodeError::LimitExceeded {
+                        what: "multisig key count",
+                        value: n as u64,
+                        max: MAX_MULTISIG_KEYS as u64,
+                    });
+                }
+                if m == 0 || m as usize > n {
+                    return Err(DecodeError::InvalidValue("multisig threshold"));
+                }
+                let mut keys = Vec::with_capacity(n);
+                for _ in 0..n {
+                    let tag = reader.read_u8()?;
+                    let key = read_public_key(reader, tag)?;
+                    if keys.contains(&key) {
+                        return Err(DecodeError::InvalidValue("duplicate multisig key"));
+                    }
+                    keys.push(key);
+                }
+                Ok(SpendCondition::MultiSig { m, keys })
+            }
+            _ => Err(DecodeError::InvalidValue("spend condition")),
+        }
+    }
+}
+
+/// An output paying `amount` to whoever meets `condition`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TxOutput {
+    pub amount: u64,
+    pub condition: SpendCondition,
+}
+
+impl TxOutput {
+    /// An output paying `amount` to the key whose address is `address`
+    pub fn to_address(amount: u64, address: Address) -> Self {
+        TxOutput {
+            amount,
+            condition: SpendCondition::SingleKey(address),
+        }
+    }
+
+    /// Append the encoding: the amount, then the condition
+    pub(crate) fn encode_into(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.amount.to_le_bytes());
+        self.condition.encode_into(buf);
+    }
+
+    /// Read an output as [`TxOutput::encode_into`] writes it
+    pub(crate) fn decode_from(reader: &mut Reader<'_>) -> Result<Self, DecodeError> {
+        Ok(TxOutput {
+            amount: reader.read_u64()?,
+            condition: SpendCondition::decode_from(reader)?,
+        })
+    }
+}
+
+/// Read a key of the scheme whose [`SignatureScheme::tag`] is `tag`
+fn read_public_key(reader: &mut Reader<'_>, tag: u8) -> Result<PublicKey, DecodeError> {
+    let scheme =
+        SignatureScheme::from_tag(tag).ok_or(DecodeError::InvalidValue("public key flag"))?;
+    let bytes = match scheme {
+        SignatureScheme::Ed25519 => reader.read_bytes(ed25519::PUBLIC_KEY_LENGTH)?,
+        SignatureScheme::Secp256k1 => reader.read_bytes(secp256k1::PUBLIC_KEY_LENGTH)?,
+    };
+    PublicKey::from_bytes(scheme, bytes).map_err(|_| DecodeError::InvalidValue("public key"))
+}
+
+/// How many of `signatures` over `message` verify against distinct `keys`,
+/// each key counting for at most one signature
+pub fn count_valid_signatures(
+    message: &[u8],
+    signatures: &[Signature],
+    keys: &[PublicKey],
+) -> usize {
+    let mut used = vec![false; keys.len()];
+    signatures
+        .iter()
+        .filter(|signature| {
+            let found = keys
+                .iter()
+                .enumerate()
+                .find(|(i, key)| !used[*i] && key.verify_message(message, signature).is_ok());
+            if let Some((i, _)) = found {
+                used[i] = true;
+            }
+            found.is_some()
+        })
+        .count()
+}
+
+/// The hash outputs use to name the holder of the ed25519 key `key`, the
+/// bytes of its [`Address`]
+pub fn pubkey_hash(key: &VerifyingKey) -> [u8; 32] {
+    *Address::from_public_key(&PublicKey::Ed25519(*key)).as_bytes()
+}
+
+/// A transaction moving value from earlier outputs to new ones.
+///
+/// A transaction without inputs creates its outputs from nothing, as a
+/// coinbase does.
+///
+/// The serde forms of transactions and their parts keep every field, in the
+/// order declared, so reordering fields changes the bincode and postcard
+/// formats; see [`codec::to_bincode`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Transaction {
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+    /// The height, below [`LOCKTIME_THRESHOLD`], the transaction may not be
+    /// included below, or else the time its block must be stamped after;
+    /// zero for none. A coinbase's lock time is not enforced, leaving it
+    /// free to tell coinbases apart; see [`Transaction::check_final`]
+    pub lock_time: u32,
+}
+
+impl Transaction {
+    /// The canonical encoding: a varint input count and the inputs, a varint
+    /// output count and the outputs, then the lock time. Integers are little
+    /// endian. Each input's signatures follow a varint count, each signature
+    /// after its [`SignatureScheme::tag`], and its public key follows a flag:
+    /// 0 for none, the key's tag, or `0x80` plus the recovery id for a key
+    /// to recover, without the key. Outputs are encoded as
+    /// [`SpendCondition`] describes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(
+            2 + self.inputs.len()
+                * (MIN_INPUT_SIZE + 1 + SIGNATURE_LENGTH + secp256k1::PUBLIC_KEY_LENGTH)
+                + self.outputs.len() * OUTPUT_SIZE
+                + 4,
+        );
+        codec::write_varint(&mut buf, self.inputs.len() as u64);
+        for input in &self.inputs {
+            buf.extend_from_slice(input.prev_out.txid.as_bytes());
+            buf.extend_from_slice(&input.prev_out.index.to_le_bytes());
+            codec::write_varint(&mut buf, input.signatures.len() as u64);
+            for signature in &input.signatures {
+                buf.push(signature.scheme().tag());
+                buf.extend_from_slice(&signature.to_bytes());
+            }
+            match (&input.public_key, input.recovery_id) {
+                (Some(key), _) => {
+                    buf.push(key.scheme().tag());
+                    buf.extend_from_slice(key.as_bytes());
+                }
+                (None, Some(id)) => buf.push(RECOVERED_KEY_FLAG | id.to_u8()),
+                (None, None) => buf.push(0),
+            }
+        }
+        codec::write_varint(&mut buf, self.outputs.len() as u64);
+        for output in &self.outputs {
+            output.encode_into(&mut buf);
+        }
+        buf.extend_from_slice(&self.lock_time.to_le_bytes());
+        buf
+    }
+
+    /// Decode a transaction produced by [`Transaction::encode`], enforcing
+    /// `limits` on untrusted input.
+    ///
+    /// A transaction with inputs must have at least one output, none below
+    /// [`DecodeLimits::min_output_amount`]. Only the canonical encoding of a
+    /// transaction is accepted, since its txid is the hash of the bytes:
+    /// anything that would not re-encode to exactly `bytes` is refused with
+    /// [`DecodeError::NonCanonicalEncoding`].
+    pub fn decode(bytes: &[u8], limits: &DecodeLimits) -> Result<Transaction, DecodeError> {
+        if bytes.len() > limits.max_transaction_bytes {
+            return Err(DecodeError::LimitExceeded {
+                what: "transaction size",
+                value: bytes.len() as u64,
+                max: limits.max_transaction_bytes as u64,
+            });
+        }
+        let mut reader = Reader::new(bytes);
+
+        let count = reader.read_len("input count", limits.max_tx_inputs)?;
+        // Never reserve more entries than the input can hold
+        let mut inputs = Vec::with_capacity(count.min(reader.remaining() / MIN_INPUT_SIZE));
+        for _ in 0..count {
+            let txid = Txid(reader.read_array()?);
+            let index = reader.read_u32()?;
+            let count = reader.read_len("signature count", MAX_MULTISIG_KEYS)?;
+            let mut signatures = Vec::with_capacity(count);
+            for _ in 0..count {
+                let scheme = SignatureScheme::from_tag(reader.read_u8()?)
+                    .ok_or(DecodeError::InvalidValue("signature flag"))?;
+                signatures.push(Signature::from_bytes(scheme, &reader.read_array()?));
+            }
+            let (public_key, recovery_id) = match reader.read_u8()? {
+                0 => (None, None),
+                flag if flag & !3 == RECOVERED_KEY_FLAG => (None, RecoveryId::from_u8(flag & 3)),
+                tag => (Some(read_public_key(&mut reader, tag)?), None),
+            };
+            inputs.push(TxInput {
+                prev_out: OutPoint { txid, index },
+                signatures,
+                public_key,
+                recovery_id,
+            });
+        }
+
+        let count = reader.read_len("output count", limits.max_tx_outputs)?;
+        if count == 0 && !inputs.is_empty() {
+            return Err(DecodeError::InvalidValue("no outputs"));
+        }
+        let mut outputs = Vec::with_capacity(count.min(reader.remaining() / OUTPUT_SIZE));
+        for _ in 0..count {
+            let amount = reader.read_u64()?;
+            if amount < limits.min_output_amount && !inputs.is_empty() {
+                return Err(DecodeError::InvalidValue("dust output"));
+            }
+            outputs.push(TxOutput {
+                amount,
+                condition: SpendCondition::decode_from(&mut reader)?,
+            });
+        }
+
+        let lock_time = reader.read_u32()?;
+        reader.finish()?;
+        let tx = Transaction {
+            inputs,
+            outputs,
+            lock_time,
+        };
+        if tx.encode() != bytes {
+            return Err(DecodeError::NonCanonicalEncoding);
+        }
+        Ok(tx)
+    }
+
+    /// Decode a transaction taken from a block, whose size the block's own
+    /// limits already bound
+    pub(crate) fn decode_from_block(bytes: &[u8]) -> Result<Transaction, DecodeError> {
+        let limits = DecodeLimits {
+            max_transaction_bytes: usize::MAX,
+            ..DecodeLimits::default()
+        };
+        Transaction::decode(bytes, &limits)
+    }
+
+    /// The hash of the canonical encoding
+    pub fn txid(&self) -> Txid {
+        Txid::of(&self.encode())
+    }
+
+    /// The message the signature on input `input_index` covers: the SHA-256
+    /// of [`SIGHASH_TAG`], the canonical encoding with every input unsigned
+    /// and without its key or recovery id, and `input_index` as a
+    /// little-endian `u32`.
+    ///
+    /// Signatures are left out because they cannot sign themselves, and keys
+    /// so that inputs can be signed in any order; a key is bound by having
+    /// to match both the signature and the spent output's condition. Anything
+    /// else that changes, any output or any other input, changes the sighash
+    /// of every input. This is the only message input signatures are made or
+    /// checked over.
+    pub fn sighash(&self, input_index: usize) -> [u8; 32] {
+        sighash(&self.unsigned_encoding(), input_index)
+    }
+
+    /// The encoding [`Transaction::sighash`] hashes, shared by every input
+    pub(crate) fn unsigned_encoding(&self) -> Vec<u8> {
+        let unsigned = Transaction {
+            inputs: self
+                .inputs
+                .iter()
+                .map(|input| TxInput {
+                    prev_out: input.prev_out,
+                    signatures: Vec::new(),
+                    public_key: None,
+                    recovery_id: None,
+                })
+                .collect(),
+            outputs: self.outputs.clone(),
+            lock_time: self.lock_time,
+        };
+        unsigned.encode()
+    }
+
+    /// Sign input `input` with `key`, the key of the single-key output it
+    /// spends, revealing the public key alongside the signature.
+    ///
+    /// Panics if there is no such input.
+    #[cfg(any(test, feature = "crypto"))]
+    pub fn sign_input(&mut self, input: usize, key: &(impl Signer + ?Sized)) {
+        self.inputs[input].public_key = Some(key.public_key());
+        self.inputs[input].recovery_id = None;
+        let signature = key.sign_message(&self.sighash(input));
+        self.inputs[input].signatures = vec![signature];
+    }
+
+    /// Sign input `input` with `key` like [`Transaction::sign_input`], but
+    /// with a recovery id in place of the public key, which validation
+    /// recovers from the signature.
+    ///
+    /// Panics if there is no such input.
+    #[cfg(any(test, feature = "secp256k1"))]
+    pub fn sign_input_recoverable(&mut self, input: usize, key: &secp256k1::SigningKey) {
+        self.inputs[input].public_key = None;
+        let (signature, id) = key.sign_recoverable(&self.sighash(input));
+        self.inputs[input].recovery_id = Some(id);
+        self.inputs[input].signatures = vec![signature.into()];
+    }
+
+    /// Add a signature by `key`, one of the keys of the multisig output
+    /// input `input` spends. Signers may add theirs in any order, since
+    /// signatures are not part of the sighash.
+    ///
+    /// Panics if there is no such input.
+    #[cfg(any(test, feature = "crypto"))]
+    pub fn add_multisig_signature(&mut self, input: usize, key: &(impl Signer + ?Sized)) {
+        let signature = key.sign_message(&self.sighash(input));
+        self.inputs[input].signatures.push(signature);
+    }
+
+    /// What the outputs this transaction spends hold, less what it pays
+    /// out, with the spent outputs looked up in `utxos`.
+    ///
+    /// A coinbase spends nothing, so unless it pays nothing its fee is
+    /// negative.
+    pub fn fee(&self, utxos: &impl UtxoView) -> Result<u64, FeeError> {
+        let mut spent = 0u64;
+        for input in &self.inputs {
+            let output = utxos
+                .output(&input.prev_out)
+                .ok_or(FeeError::MissingInput(input.prev_out))?;
+            spent = spent
+                .checked_add(output.amount)
+                .ok_or(FeeError::AmountOverflow)?;
+        }
+        let paid = self.value_out()?;
+        spent.checked_sub(paid).ok_or(FeeError::NegativeFee {
+            txid: self.txid(),
+            spent,
+            paid,
+        })
+    }
+
+    /// Check every input against the condition of the output it spends,
+    /// looked up in `utxos`, as [`Block::verify_spends`] checks a block's
+    /// inputs, and check its signatures over [`Transaction::sighash`].
+    ///
+    /// This is the check a transaction passes before it joins a mempool.
+    /// Errors name the transaction as index 0. A coinbase spends nothing and
+    /// passes.
+    ///
+    /// [`Block::verify_spends`]: crate::block::Block::verify_spends
+    pub fn verify_spends(&self, utxos: &impl UtxoView) -> Result<(), SigError> {
+        let unsigned = self.unsigned_encoding();
+        for (input, tx_input) in self.inputs.iter().enumerate() {
+            let output = utxos
+                .output(&tx_input.prev_out)
+                .ok_or(SigError::UnknownKey { index: 0, input })?;
+            let Some(key) = check_spend(0, input, tx_input, &unsigned, &output)? else {
+                continue;
+            };
+            let signature = match tx_input.signatures.as_slice() {
+                [signature] => signature,
+                [] => return Err(SigError::MissingSignature { index: 0, input }),
+                _ => return Err(SigError::ExtraSignatures { index: 0, input }),
+            };
+            key.verify_message(&sighash(&unsigned, input), signature)
+                .map_err(|_| SigError::InvalidSignature { index: 0, input })?;
+        }
+        Ok(())
+    }
+
+    /// The total of the outputs
+    pub fn value_out(&self) -> Result<u64, FeeError> {
+        self.outputs.iter().try_fold(0u64, |sum, output| {
+            sum.checked_add(output.amount)
+                .ok_or(FeeError::AmountOverflow)
+        })
+    }
+
+    /// Whether the transaction may join the block at `height` stamped
+    /// `timestamp`, or else the earliest block it may join. Coinbases are
+    /// always final.
+    pub fn check_final(&self, height: u64, timestamp: u64) -> Result<(), Locked> {
+        if self.is_coinbase() || self.lock_time == 0 {
+            return Ok(());
+        }
+        let lock_time = u64::from(self.lock_time);
+        if self.lock_time < LOCKTIME_THRESHOLD {
+            if height < lock_time {
+                return Err(Locked::UntilHeight(lock_time));
+            }
+        } else if timestamp <= lock_time {
+            return Err(Locked::UntilTime(lock_time + 1));
+        }
+        Ok(())
+    }
+
+    /// Whether this transaction creates value rather than spending outputs
+    pub fn is_coinbase(&self) -> bool {
+        self.inputs.is_empty()
+    }
+}
+
+/// Domain tag that starts every [`Transaction::sighash`], keeping input
+/// signatures apart from anything else the same key signs
+pub const SIGHASH_TAG: &[u8] = b"aarwyn-chain/sighash/v1";
+
+/// [`Transaction::sighash`] of input `input` given the unsigned encoding
+pub(crate) fn sighash(unsigned: &[u8], input: usize) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(SIGHASH_TAG);
+    hasher.update(unsigned);
+    hasher.update((input as u32).to_le_bytes());
+    hasher.finalize().into()
+}
+
+/// Check input `input` of the transaction at `index`, whose
+/// [`Transaction::unsigned_encoding`] is `unsigned`, against the condition of
+/// `output`, the output it spends.
+///
+/// A single-key input must reveal the key its output's address names, or
+/// recover it from its signature; the key is returned for the caller to
+/// check the input's one signature under. A multisig input's signatures
+/// must all verify against distinct keys of the output, at least `m` of
+/// them, and are checked here, returning `None`. Every signature on a
+/// multisig input must count, so that nobody but the signers can change the
+/// transaction's encoding.
+pub(crate) fn check_spend(
+    index: usize,
+    input: usize,
+    tx_input: &TxInput,
+    unsigned: &[u8],
+    output: &TxOutput,
+) -> Result<Option<PublicKey>, SigError> {
+    let message = sighash(unsigned, input);
+    match &output.condition {
+        SpendCondition::SingleKey(address) => {
+            let key = tx_input
+                .signing_key(&message)
+                .ok_or(SigError::UnknownKey { index, input })?;
+            if Address::from_public_key(&key) != *address {
+                return Err(SigError::KeyMismatch { index, input });
+            }
+            Ok(Some(key))
+        }
+        SpendCondition::MultiSig { m, keys } => {
+            if tx_input.public_key.is_some() || tx_input.recovery_id.is_some() {
+                return Err(SigError::KeyMismatch { index, input });
+            }
+            let valid = count_valid_signatures(&message, &tx_input.signatures, keys);
+            if valid < *m as usize {
+                return Err(SigError::NotEnoughSignatures {
+                    index,
+                    input,
+                    valid,
+                    required: *m,
+                });
+            }
+            if valid < tx_input.signatures.len() {
+                return Err(SigError::InvalidSignature { index, input });
+            }
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::{BlockBuilder, BlockHash};
+    use crate::crypto::ed25519::SigningKey;
+    use crate::testing::{self, transaction};
+    use proptest::collection::{hash_set, vec};
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    fn sample() -> Transaction {
+        Transaction {
+            inputs: vec![
+                TxInput {
+                    prev_out: OutPoint {
+                        txid: Txid([0x11; 32]),
+                        index: 1,
+                    },
+                    signatures: Vec::new(),
+                    public_key: None,
+                    recovery_id: None,
+                },
+                TxInput {
+                    prev_out: OutPoint {
+                        txid: Txid([0x22; 32]),
+                        index: 0x0102_0304,
+                    },
+                    signatures: vec![Signature::from_bytes(SignatureScheme::Ed25519, &[0x33; 64])],
+                    public_key: Some(SigningKey::from_bytes(&[0x55; 32]).public_key()),
+                    recovery_id: None,
+                },
+            ],
+            outputs: vec![TxOutput::to_address(300, Address::from_bytes([0x44; 32]))],
+            lock_time: 7,
+        }
+    }
+
+    #[test]
+    fn test_encoding_golden_bytes() {
+        let expected = [
+            "02",
+            &"11".repeat(32),
+            "01000000",
+            "00",
+            "00",
+            &"22".repeat(32),
+            "04030201",
+            "01",
+            "01",
+            &"33".repeat(64),
+            "01",
+            "c6822637c7d310ec57627be00ba259d253749f4aaf644470cffbe53a35f73242",
+            "01",
+            "2c01000000000000",
+            "00",
+            &"44".repeat(32),
+            "07000000",
+        ]
+        .concat();
+        let tx = sample();
+        assert_eq!(hex::encode(tx.encode()), expected);
+        assert_eq!(tx.txid(), Txid::of(&hex::decode(&expected).unwrap()));
+        assert_eq!(tx.txid().to_string().parse(), Ok(tx.txid()));
+        assert_eq!(
+            "0x12".parse::<Txid>(),
+            Err(EncodingError::WrongLength {
+                expected: 32,
+                got: 1
+            })
+        );
+
+        // A coinbase with no outputs is the two counts and the lock time
+        assert_eq!(Transaction::default().encode(), [0, 0, 0, 0, 0, 0]);
+        assert!(Transaction::default().is_coinbase());
+        assert!(!tx.is_coinbase());
+    }
+
+    #[test]
+    fn test_sighash() {
+        let tx = sample();
+        let sighashes = [tx.sighash(0), tx.sighash(1)];
+        assert_eq!(
+            hex::encode(sighashes[0]),
+            "e13ead8de5711d36457f4bd8c05c43f6047d48631b0129207289f6b66bb8fbc4"
+        );
+        assert_eq!(
+            hex::encode(sighashes[1]),
+            "7cb09cd0f1af73a2d7a7bbce06b312070fda2308f9d8bb7ac82f3823dfa58459"
+        );
+
+        // Signatures and keys are not covered
+        let mut unsigned = tx.clone();
+        unsigned.inputs[1].signatures.clear();
+        unsigned.inputs[1].public_key = None;
+        assert_eq!([unsigned.sighash(0), unsigned.sighash(1)], sighashes);
+
+        // Everything else is, for every input
+        let mut changed = [tx.clone(), tx.clone(), tx.clone(), tx.clone()];
+        changed[0].inputs[0].prev_out.index += 1;
+        changed[1].inputs[1].prev_out.txid = Txid([0x23; 32]);
+        changed[2].outputs[0].amount -= 1;
+        changed[3].lock_time += 1;
+        for tx in &changed {
+            assert_ne!(tx.sighash(0), sighashes[0]);
+            assert_ne!(tx.sighash(1), sighashes[1]);
+        }
+
+        // A signature over the sighash passes block validation
+        let key = SigningKey::from_bytes(&[9; 32]);
+        let mut tx = tx;
+        tx.sign_input(0, &key);
+        tx.sign_input(1, &key);
+        let block = BlockBuilder::new(BlockHash::ZERO)
+            .transactions([Transaction::default(), tx])
+            .build()
+            .unwrap();
+        assert_eq!(
+            block.verify_signatures_batch(|_| Some(key.verifying_key().into())),
+            Ok(())
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_random_transactions_round_trip(
+            tx in transaction(),
+            at in any::<Index>(),
+            bit in 0..8u8,
+            cut in 0..3usize,
+        ) {
+            let limits = DecodeLimits::default();
+            let bytes = tx.encode();
+            prop_assert_eq!(Transaction::decode(&bytes, &limits), Ok(tx.clone()));
+            prop_assert_eq!(tx.txid(), Txid::of(&bytes));
+            prop_assert_eq!(tx.txid().to_string().parse(), Ok(tx.txid()));
+
+            // Whatever a corrupted encoding decodes to encodes back to it
+            let mut mutated = bytes.clone();
+            mutated[at.index(bytes.len())] ^= 1 << bit;
+            mutated.truncate(bytes.len() - cut);
+            if let Ok(decoded) = Transaction::decode(&mutated, &limits) {
+                prop_assert_eq!(decoded.encode(), mutated);
+            }
+        }
+
+        #[test]
+        fn test_block_merkle_leaves_are_txids(txs in vec(transaction(), 1..6)) {
+            let block = BlockBuilder::new(BlockHash::ZERO)
+                .transactions(txs.clone())
+                .build()
+                .unwrap();
+
+            let decoded = block.decode_transactions(&DecodeLimits::default()).unwrap();
+            prop_assert_eq!(&decoded, &txs);
+            prop_assert_eq!(block.txids(), txs.iter().map(Transaction::txid).collect::<Vec<_>>());
+            for (index, tx) in txs.iter().enumerate() {
+                let proof = block.merkle_tree().generate_proof(index).unwrap();
+                prop_assert!(proof.verify(tx.encode()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_decode_corpus() {
+        let limits = DecodeLimits::default();
+        let bytes = sample().encode();
+        let with = |at: usize, patch: &[u8]| [&bytes[..at], patch, &bytes[at + 1..]].concat();
+        let outputs_at = 1 + 2 * (32 + 4 + 1 + 1) + 1 + 64 + 32;
+        let zero_outputs = [&bytes[..outputs_at], &[0], &bytes[bytes.len() - 4..]].concat();
+        let corpus: [(&str, Vec<u8>, DecodeError); 6] = [
+            (
+                "overlong input count",
+                with(0, &[0x82, 0x00]),
+                DecodeError::InvalidVarint,
+            ),
+            (
+                "overlong output count",
+                with(outputs_at, &[0x81, 0x00]),
+                DecodeError::InvalidVarint,
+            ),
+            (
+                "spending transaction without outputs",
+                zero_outputs,
+                DecodeError::InvalidValue("no outputs"),
+            ),
+            (
+                "zero-value output",
+                [&bytes[..outputs_at + 1], &[0; 8], &bytes[outputs_at + 9..]].concat(),
+                DecodeError::InvalidValue("dust output"),
+            ),
+            (
+                "input count over the limit",
+                [&[0x91, 0x4e][..], &bytes[1..]].concat(),
+                DecodeError::LimitExceeded {
+                    what: "input count",
+                    value: 10_001,
+                    max: 10_000,
+                },
+            ),
+            (
+                "output count over the limit",
+                with(outputs_at, &[0x91, 0x4e]),
+                DecodeError::LimitExceeded {
+                    what: "output count",
+                    value: 10_001,
+                    max: 10_000,
+                },
+            ),
+        ];
+        for (what, bytes, err) in corpus {
+            assert_eq!(Transaction::decode(&bytes, &limits), Err(err), "{}", what);
+        }
+
+        // The dust threshold is configurable
+        let strict = DecodeLimits {
+            min_output_amount: 301,
+            ..limits
+        };
+        assert_eq!(
+            Transaction::decode(&bytes, &strict),
+            Err(DecodeError::InvalidValue("dust output"))
+        );
+        let lax = DecodeLimits {
+            min_output_amount: 300,
+            ..limits
+        };
+        assert_eq!(Transaction::decode(&bytes, &lax), Ok(sample()));
+
+        // Coinbases may pay nothing, since the subsidy eventually runs out
+        let coinbase = Transaction {
+            outputs: vec![TxOutput::to_address(0, Address::from_bytes([1; 32]))],
+            ..Transaction::default()
+        };
+        for tx in [Transaction::default(), coinbase] {
+            assert_eq!(Transaction::decode(&tx.encode(), &limits), Ok(tx));
+        }
+    }
+
+    #[test]
+    fn test_lock_time_boundaries() {
+        let spend = |lock_time| Transaction {
+            inputs: vec![TxInput {
+                prev_out: OutPoint {
+                    txid: Txid([1; 32]),
+                    index: 0,
+                },
+                signatures: Vec::new(),
+                public_key: None,
+                recovery_id: None,
+            }],
+            lock_time,
+            ..Transaction::default()
+        };
+
+        // Final from exactly the lock height, whatever the time
+        assert_eq!(
+            spend(10).check_final(9, u64::MAX),
+            Err(Locked::UntilHeight(10))
+        );
+        assert_eq!(spend(10).check_final(10, 0), Ok(()));
+        let last_height = LOCKTIME_THRESHOLD - 1;
+        assert_eq!(
+            spend(last_height).check_final(0, u64::MAX),
+            Err(Locked::UntilHeight(last_height as u64))
+        );
+
+        // From the threshold on, final once a block is stamped after it
+        let time = LOCKTIME_THRESHOLD;
+        assert_eq!(
+            spend(time).check_final(u64::MAX, time as u64),
+            Err(Locked::UntilTime(time as u64 + 1))
+        );
+        assert_eq!(spend(time).check_final(0, time as u64 + 1), Ok(()));
+        assert_eq!(spend(u32::MAX).check_final(0, u32::MAX as u64 + 1), Ok(()));
+
+        assert_eq!(spend(0).check_final(0, 0), Ok(()));
+        let coinbase = Transaction {
+            lock_time: 10,
+            ..Transaction::default()
+        };
+        assert_eq!(coinbase.check_final(0, 0), Ok(()));
+    }
+
+    #[test]
+    fn test_decode_rejects_malformed_input() {
+        let limits = DecodeLimits::default();
+        let bytes = sample().encode();
+
+        assert_eq!(
+            Transaction::decode(&bytes[..bytes.len() - 1], &limits),
+            Err(DecodeError::UnexpectedEof)
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            Transaction::decode(&trailing, &limits),
+            Err(DecodeError::TrailingBytes(1))
+        );
+        let mut bad_flag = bytes.clone();
+        bad_flag[1 + (32 + 4 + 1 + 1) + 32 + 4 + 1] = 3;
+        assert_eq!(
+            Transaction::decode(&bad_flag, &limits),
+            Err(DecodeError::InvalidValue("signature flag"))
+        );
+        let mut bad_key_flag = bytes.clone();
+        bad_key_flag[1 + 32 + 4 + 1] = 3;
+        assert_eq!(
+            Transaction::decode(&bad_key_flag, &limits),
+            Err(DecodeError::InvalidValue("public key flag"))
+        );
+
+        let tight = DecodeLimits {
+            max_transaction_bytes: bytes.len() - 1,
+            ..limits
+        };
+        assert!(matches!(
+            Transaction::decode(&bytes, &tight),
+            Err(DecodeError::LimitExceeded {
+                what: "transaction size",
+                ..
+            })
+        ));
+        // A huge count is refused by the input running out, not by allocating
+        assert_eq!(
+            Transaction::decode(&[0x90, 0x4e], &limits),
+            Err(DecodeError::UnexpectedEof)
+        );
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_multisig_encoding(
+            keys in hash_set(testing::public_key(), MAX_MULTISIG_KEYS + 1),
+            signatures in vec(testing::signature(), 17),
+        ) {
+            let limits = DecodeLimits::default();
+            let too_many: Vec<PublicKey> = keys.into_iter().collect();
+            let keys = &too_many[..MAX_MULTISIG_KEYS];
+            let mut tx = sample();
+            tx.outputs[0].condition = SpendCondition::MultiSig {
+                m: 2,
+                keys: keys[..3].to_vec(),
+            };
+            tx.inputs[1].signatures = signatures[..3].to_vec();
+            let bytes = tx.encode();
+            prop_assert_eq!(Transaction::decode(&bytes, &limits), Ok(tx.clone()));
+            prop_assert_eq!(tx.outputs[0].condition.address(), None);
+
+            // Up to the limit in keys and signatures, each key listed once
+            let condition = |m, keys: &[PublicKey]| {
+                let mut tx = tx.clone();
+                tx.outputs[0].condition = SpendCondition::MultiSig {
+                    m,
+                    keys: keys.to_vec(),
+                };
+                Transaction::decode(&tx.encode(), &limits)
+            };
+            prop_assert!(condition(16, keys).is_ok());
+            prop_assert!(condition(1, &keys[..1]).is_ok());
+            prop_assert_eq!(
+                condition(1, &too_many),
+                Err(DecodeError::LimitExceeded {
+                    what: "multisig key count",
+                    value: 17,
+                    max: 16,
+                })
+            );
+            let threshold = DecodeError::InvalidValue("multisig threshold");
+            prop_assert_eq!(condition(0, &keys[..3]), Err(threshold.clone()));
+            prop_assert_eq!(condition(4, &keys[..3]), Err(threshold));
+            prop_assert_eq!(
+                condition(2, &[keys[0], keys[1], keys[0]]),
+                Err(DecodeError::InvalidValue("duplicate multisig key"))
+            );
+            let mut signed = tx.clone();
+            signed.inputs[1].signatures = signatures;
+            let decoded = Transaction::decode(&signed.encode(), &limits);
+            prop_assert!(
+                matches!(
+                    decoded,
+                    Err(DecodeError::LimitExceeded {
+                        what: "signature count",
+                        ..
+                    })
+                ),
+                "{:?}",
+                decoded
+            );
+            let mut bad_tag = bytes.clone();
+            bad_tag[1 + (32 + 4 + 1 + 1) + (32 + 4 + 1 + 3 * 65 + 1 + 32) + 1 + 8] = 2;
+            prop_assert_eq!(
+                Transaction::decode(&bad_tag, &limits),
+                Err(DecodeError::InvalidValue("spend condition"))
+            );
+        }
+    }
+
+    #[test]
+    fn test_raw_transactions_mix_with_structured_ones() {
+        let tx = sample();
+        let mixed = BlockBuilder::new(BlockHash::ZERO)
+            .transaction(b"raw".to_vec())
+            .transaction(tx.clone())
+            .build()
+            .unwrap();
+        assert_eq!(mixed.transactions()[1], tx.encode());
+    }
+}
@@ -0,0 +1,661 @@
+//! The set of unspent transaction outputs.
+//!
+//! Applying a block spends the outputs its inputs name and adds the outputs
+//! it creates, returning the [`UndoData`] needed to put the set back exactly
+//! as it was when the block is disconnected. The set counts the blocks
+//! applied to it, so the first block applied is taken to be at height 0.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::de::{self, Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use thiserror::Error;
+
+use crate::block::Block;
+use crate::codec::{self, DecodeError, DecodeLimits, Reader};
+use crate::merkle_trie::MerkleTree;
+use crate::state::{StateView, EMPTY_STATE_ROOT};
+use crate::transaction::{Locked, OutPoint, Transaction, TxOutput, Txid, OUTPUT_SIZE};
+
+/// Reasons a block's transactions cannot be applied to a [`UtxoSet`]
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum UtxoError {
+    /// The transaction at `index` in the block is not a valid [`Transaction`]
+    #[error("transaction {index} does not decode: {err}")]
+    Decode { index: usize, err: DecodeError },
+    /// An input spends an output that is not unspent: it never existed, or
+    /// an earlier block spent it
+    #[error("output {}:{} is not unspent", .0.txid, .0.index)]
+    MissingInput(OutPoint),
+    /// An input spends an output already spent earlier in the same block
+    #[error("output {}:{} is spent twice in the block", .0.txid, .0.index)]
+    DoubleSpend(OutPoint),
+    /// A transaction creates an output that is already unspent, as a repeat
+    /// of an earlier transaction does
+    #[error("output {}:{} already exists", .0.txid, .0.index)]
+    DuplicateOutput(OutPoint),
+    /// The transaction `txid`'s lock time has not passed
+    #[error("transaction {txid} is {locked}")]
+    NotFinal { txid: Txid, locked: Locked },
+    /// An input spends a coinbase output before the block at `spendable_at`
+    #[error("coinbase output {}:{} cannot be spent before height {spendable_at}", .out.txid, .out.index)]
+    ImmatureCoinbase { out: OutPoint, spendable_at: u64 },
+}
+
+/// Somewhere to look up unspent outputs
+pub trait UtxoView {
+    /// The unspent output at `out`
+    fn output(&self, out: &OutPoint) -> Option<TxOutput>;
+}
+
+impl UtxoView for UtxoSet {
+    fn output(&self, out: &OutPoint) -> Option<TxOutput> {
+        self.get(out).cloned()
+    }
+}
+
+impl UtxoView for HashMap<OutPoint, TxOutput> {
+    fn output(&self, out: &OutPoint) -> Option<TxOutput> {
+        self.get(out).cloned()
+    }
+}
+
+impl StateView for UtxoSet {
+    type Error = UtxoError;
+
+    fn state_root(&self) -> [u8; 32] {
+        UtxoSet::state_root(self)
+    }
+
+    fn root_after(&self, block: &Block) -> Result<[u8; 32], UtxoError> {
+        let mut after = self.clone();
+        after.apply_block(block)?;
+        Ok(after.state_root())
+    }
+}
+
+/// What [`UtxoSet::apply_block`] changed, for [`UtxoSet::undo_block`] to reverse
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UndoData {
+    /// Outputs the block spent, with their contents, in spending order
+    spent: Vec<(OutPoint, Coin)>,
+    /// Outputs the block created, in creation order
+    created: Vec<OutPoint>,
+}
+
+impl UndoData {
+    /// Number of outputs the block spent
+    pub fn spent_count(&self) -> usize {
+        self.spent.len()
+    }
+
+    /// Outputs the block spent, with their contents, in spending order
+    pub fn spent(&self) -> impl Iterator<Item = (&OutPoint, &TxOutput)> {
+        self.spent.iter().map(|(out, coin)| (out, &coin.output))
+    }
+
+    /// Number of outputs the block created
+    pub fn created_count(&self) -> usize {
+        self.created.len()
+    }
+}
+
+/// An unspent output and the block that created it
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Coin {
+    output: TxOutput,
+    height: u64,
+    /// Created by a coinbase after the genesis block, so subject to maturity
+    coinbase: bool,
+}
+
+/// Unspent outputs by the outpoint naming them
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UtxoSet {
+    outputs: BTreeMap<OutPoint, Coin>,
+    /// Height of the next block to apply: the number applied so far
+    next_height: u64,
+    coinbase_maturity: u64,
+}
+
+impl UtxoSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let coinbase outputs be spent only `depth` blocks after the block
+    /// creating them. Outputs of the genesis block, the first block applied,
+    /// are spendable at once.
+    pub fn with_coinbase_maturity(mut self, depth: u64) -> Self {
+        self.coinbase_maturity = depth;
+        self
+    }
+
+    /// Height of the next block to apply, the number of blocks applied
+    pub fn next_height(&self) -> u64 {
+        self.next_height
+    }
+
+    /// The unspent output at `out`
+    pub fn get(&self, out: &OutPoint) -> Option<&TxOutput> {
+        self.outputs.get(out).map(|coin| &coin.output)
+    }
+
+    /// Height of the block that created the unspent output at `out`
+    pub fn created_at(&self, out: &OutPoint) -> Option<u64> {
+        self.outputs.get(out).map(|coin| coin.height)
+    }
+
+    pub fn contains(&self, out: &OutPoint) -> bool {
+        self.outputs.contains_key(out)
+    }
+
+    /// Number of unspent outputs
+    pub fn len(&self) -> usize {
+        self.outputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outputs.is_empty()
+    }
+
+    /// Unspent outputs in outpoint order
+    pub fn iter(&self) -> impl Iterator<Item = (&OutPoint, &TxOutput)> {
+        self.outputs.iter().map(|(out, coin)| (out, &coin.output))
+    }
+
+    /// A canonical encoding of the unspent outputs: a varint count, then each
+    /// outpoint and output in outpoint order, followed by the height that
+    /// created it and a coinbase flag. Two sets holding the same outputs
+    /// encode identically, however they were built.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        codec::write_varint(&mut buf, self.outputs.len() as u64);
+        for (out, coin) in &self.outputs {
+            encode_coin(&mut buf, out, coin);
+        }
+        buf
+    }
+
+    /// Decode a set encoded by [`UtxoSet::to_bytes`], enforcing `limits` on
+    /// untrusted input.
+    ///
+    /// Outpoints must come in increasing order, so only the canonical
+    /// encoding is accepted. The set has no coinbase maturity and counts no
+    /// blocks applied, neither being part of the encoding.
+    pub fn from_bytes(bytes: &[u8], limits: &DecodeLimits) -> Result<UtxoSet, DecodeError> {
+        codec::check_input_size(bytes.len(), limits)?;
+        let mut reader = Reader::new(bytes);
+        let set = UtxoSet::decode_from(&mut reader)?;
+        reader.finish()?;
+        Ok(set)
+    }
+
+    /// Read a set as [`UtxoSet::to_bytes`] writes it
+    pub(crate) fn decode_from(reader: &mut Reader<'_>) -> Result<UtxoSet, DecodeError> {
+        let max = reader.remaining() / COIN_SIZE;
+        let count = reader.read_len("unspent output count", max)?;
+        let mut outputs = BTreeMap::new();
+        let mut last = None;
+        for _ in 0..count {
+            let out = OutPoint {
+                txid: Txid::from_bytes(reader.read_array()?),
+                index: reader.read_u32()?,
+            };
+            if last.is_some_and(|last| out <= last) {
+                return Err(DecodeError::NonCanonicalEncoding);
+            }
+            let output = TxOutput::decode_from(reader)?;
+            let height = reader.read_u64()?;
+            let coinbase = match reader.read_u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(DecodeError::InvalidValue("coinbase flag")),
+            };
+            outputs.insert(
+                out,
+                Coin {
+                    output,
+                    height,
+                    coinbase,
+                },
+            );
+            last = Some(out);
+        }
+        Ok(UtxoSet {
+            outputs,
+            ..UtxoSet::default()
+        })
+    }
+
+    /// Count `next_height` blocks as applied, for a set taken after the block
+    /// below that height rather than built by applying every block
+    pub(crate) fn resume_at(mut self, next_height: u64) -> Self {
+        self.next_height = next_height;
+        self
+    }
+
+    /// The merkle root over every unspent output in outpoint order, each leaf
+    /// being the outpoint and output as [`UtxoSet::to_bytes`] encodes them;
+    /// [`EMPTY_STATE_ROOT`] for an empty set
+    #[allow(clippy::expect_used)]
+    pub fn state_root(&self) -> [u8; 32] {
+        let leaves: Vec<Vec<u8>> = self
+            .outputs
+            .iter()
+            .map(|(out, coin)| {
+                let mut leaf = Vec::with_capacity(COIN_SIZE);
+                encode_coin(&mut leaf, out, coin);
+                leaf
+            })
+            .collect();
+        MerkleTree::new(&leaves).map_or(EMPTY_STATE_ROOT, |tree| {
+            tree.root_hash()
+                .try_into()
+                .expect("SHA-256 digests are 32 bytes")
+        })
+    }
+
+    /// Spend the outputs `block`'s transactions consume and add the ones they
+    /// create, in block order, so a transaction may spend an output created
+    /// earlier in the same block.
+    ///
+    /// Transactions without inputs create outputs from nothing, as a
+    /// coinbase does. Every transaction must decode as a [`Transaction`] final
+    /// at the block's height and timestamp (see [`Transaction::check_final`]),
+    /// and spend only mature coinbase outputs. On error the set is left as it
+    /// was.
+    pub fn apply_block(&mut self, block: &Block) -> Result<UndoData, UtxoError> {
+        let mut undo = UndoData::default();
+        for (index, tx) in block.transactions().iter().enumerate() {
+            let applied = Transaction::decode_from_block(tx)
+                .map_err(|err| UtxoError::Decode { index, err })
+                .and_then(|tx| self.apply_transaction(&tx, block.timestamp(), &mut undo));
+            if let Err(err) = applied {
+                self.revert(undo);
+                return Err(err);
+            }
+        }
+        self.next_height += 1;
+        Ok(undo)
+    }
+
+    fn apply_transaction(
+        &mut self,
+        tx: &Transaction,
+        timestamp: u64,
+        undo: &mut UndoData,
+    ) -> Result<(), UtxoError> {
+        let height = self.next_height;
+        let txid = tx.txid();
+        tx.check_final(height, timestamp)
+            .map_err(|locked| UtxoError::NotFinal { txid, locked })?;
+        for input in &tx.inputs {
+            let out = input.prev_out;
+            let coin = match self.outputs.get(&out) {
+                Some(coin) => coin,
+                None if undo.spent.iter().any(|(spent, _)| *spent == out) => {
+                    return Err(UtxoError::DoubleSpend(out))
+                }
+                None => return Err(UtxoError::MissingInput(out)),
+            };
+            let spendable_at = coin.height.saturating_add(self.coinbase_maturity);
+            if coin.coinbase && height < spendable_at {
+                return Err(UtxoError::ImmatureCoinbase { out, spendable_at });
+            }
+            if let Some(coin) = self.outputs.remove(&out) {
+                undo.spent.push((out, coin));
+            }
+        }
+        for (index, output) in tx.outputs.iter().enumerate() {
+            let out = OutPoint {
+                txid,
+                index: index as u32,
+            };
+            if self.outputs.contains_key(&out) {
+                return Err(UtxoError::DuplicateOutput(out));
+            }
+            let coin = Coin {
+                output: output.clone(),
+                height,
+                coinbase: tx.is_coinbase() && height > 0,
+            };
+            self.outputs.insert(out, coin);
+            undo.created.push(out);
+        }
+        Ok(())
+    }
+
+    /// Reverse the [`UtxoSet::apply_block`] that returned `undo`, which must
+    /// be the last block applied and not yet undone
+    pub fn undo_block(&mut self, undo: UndoData) {
+        self.revert(undo);
+        self.next_height -= 1;
+    }
+
+    fn revert(&mut self, undo: UndoData) {
+        // Outputs spent within the block are restored and then removed again
+        for (out, coin) in undo.spent.into_iter().rev() {
+            self.outputs.insert(out, coin);
+        }
+        for out in undo.created.into_iter().rev() {
+            self.outputs.remove(&out);
+        }
+    }
+}
+
+/// Serde writes the set as the sequence of its unspent outputs in outpoint
+/// order, each an `(outpoint, output, height, coinbase)` tuple, and reads only
+/// such a sequence in that order. As with [`UtxoSet::to_bytes`], the coinbase
+/// maturity and the count of blocks applied are left out.
+impl Serialize for UtxoSet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(
+            self.outputs
+                .iter()
+                .map(|(out, coin)| (out, &coin.output, coin.height, coin.coinbase)),
+        )
+    }
+}
+
+impl<'de> Deserialize<'de> for UtxoSet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<(OutPoint, TxOutput, u64, bool)>::deserialize(deserializer)?;
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(de::Error::custom("unspent outputs out of outpoint order"));
+        }
+        let outputs = entries
+            .into_iter()
+            .map(|(out, output, height, coinbase)| {
+                let coin = Coin {
+                    output,
+                    height,
+                    coinbase,
+                };
+                (out, coin)
+            })
+            .collect();
+        Ok(UtxoSet {
+            outputs,
+            ..UtxoSet::default()
+        })
+    }
+}
+
+/// Encoded size of an unspent single-key output, the smallest kind: outpoint,
+/// output, height and coinbase flag
+const COIN_SIZE: usize = 32 + 4 + OUTPUT_SIZE + 8 + 1;
+
+fn encode_coin(buf: &mut Vec<u8>, out: &OutPoint, coin: &Coin) {
+    buf.extend_from_slice(out.txid.as_bytes());
+    buf.extend_from_slice(&out.index.to_le_bytes());
+    coin.output.encode_into(buf);
+    buf.extend_from_slice(&coin.height.to_le_bytes());
+    buf.push(coin.coinbase as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use proptest::sample::Index;
+
+    use super::*;
+    use crate::address::Address;
+    use crate::block::{BlockBuilder, BlockHash};
+    use crate::transaction::{TxInput, Txid};
+
+    fn pay(inputs: &[OutPoint], amounts: &[u64], lock_time: u32) -> Transaction {
+        Transaction {
+            inputs: inputs
+                .iter()
+                .map(|&prev_out| TxInput {
+                    prev_out,
+                    signatures: Vec::new(),
+                    public_key: None,
+                    recovery_id: None,
+                })
+                .collect(),
+            outputs: amounts
+                .iter()
+                .map(|&amount| {
+                    TxOutput::to_address(amount, Address::from_bytes([amount as u8; 32]))
+                })
+                .collect(),
+            lock_time,
+        }
+    }
+
+    fn out(tx: &Transaction, index: u32) -> OutPoint {
+        OutPoint {
+            txid: tx.txid(),
+            index,
+        }
+    }
+
+    fn block(txs: &[&Transaction]) -> Block {
+        BlockBuilder::new(BlockHash::ZERO)
+            .transactions(txs.iter().map(|&tx| tx.clone()))
+            .timestamp(0)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_spend_chain_within_block() {
+        let mut set = UtxoSet::new();
+        let coinbase = pay(&[], &[50], 0);
+        let first = pay(&[out(&coinbase, 0)], &[20, 30], 0);
+        let second = pay(&[out(&first, 1)], &[30], 0);
+        let undo = set
+            .apply_block(&block(&[&coinbase, &first, &second]))
+            .unwrap();
+
+        assert_eq!(undo.spent_count(), 2);
+        assert_eq!(undo.created_count(), 4);
+        let unspent: Vec<OutPoint> = set.iter().map(|(out, _)| *out).collect();
+        let mut expected = vec![out(&first, 0), out(&second, 0)];
+        expected.sort();
+        assert_eq!(unspent, expected);
+        assert_eq!(set.get(&out(&second, 0)).unwrap().amount, 30);
+
+        set.undo_block(undo);
+        assert_eq!(set, UtxoSet::new());
+    }
+
+    #[test]
+    fn test_rejects_double_and_missing_spends() {
+        let mut set = UtxoSet::new();
+        let coinbase = pay(&[], &[50], 0);
+        let undo = set.apply_block(&block(&[&coinbase])).unwrap();
+        let before = set.clone();
+
+        // Two spends of one output in the same block
+        let a = pay(&[out(&coinbase, 0)], &[50], 0);
+        let b = pay(&[out(&coinbase, 0)], &[49], 0);
+        assert_eq!(
+            set.apply_block(&block(&[&a, &b])),
+            Err(UtxoError::DoubleSpend(out(&coinbase, 0)))
+        );
+        assert_eq!(set, before);
+
+        // A spend in a later block of an output already spent
+        let spent = set.apply_block(&block(&[&a])).unwrap();
+        assert_eq!(
+            set.apply_block(&block(&[&b])),
+            Err(UtxoError::MissingInput(out(&coinbase, 0)))
+        );
+        set.undo_block(spent);
+        assert_eq!(set, before);
+
+        let unknown = OutPoint {
+            txid: Txid::from_bytes([9; 32]),
+            index: 0,
+        };
+        assert_eq!(
+            set.apply_block(&block(&[&pay(&[unknown], &[1], 0)])),
+            Err(UtxoError::MissingInput(unknown))
+        );
+        assert_eq!(
+            set.apply_block(&block(&[&coinbase])),
+            Err(UtxoError::DuplicateOutput(out(&coinbase, 0)))
+        );
+        let raw = BlockBuilder::new(BlockHash::ZERO)
+            .transaction(b"raw".to_vec())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            set.apply_block(&raw),
+            Err(UtxoError::Decode { index: 0, .. })
+        ));
+        assert_eq!(set, before);
+
+        set.undo_block(undo);
+        assert!(set.is_empty());
+        assert_eq!(set.to_bytes(), [0]);
+    }
+
+    #[test]
+    fn test_lock_times_and_coinbase_maturity() {
+        let mut set = UtxoSet::new().with_coinbase_maturity(3);
+        let premine = pay(&[], &[100], 0);
+        let reward = pay(&[], &[50], 0);
+        set.apply_block(&block(&[&premine])).unwrap();
+        set.apply_block(&block(&[&reward])).unwrap();
+        assert_eq!(set.next_height(), 2);
+        assert_eq!(set.created_at(&out(&reward, 0)), Some(1));
+
+        // The genesis outputs are spendable at once, the coinbase at height 1
+        // only from height 4
+        let early = pay(&[out(&reward, 0)], &[50], 0);
+        assert_eq!(
+            set.apply_block(&block(&[&early])),
+            Err(UtxoError::ImmatureCoinbase {
+                out: out(&reward, 0),
+                spendable_at: 4
+            })
+        );
+        let before = set.clone();
+
+        // Locked until height 3, so not final in the block at height 2
+        let locked = pay(&[out(&premine, 0)], &[100], 3);
+        assert_eq!(
+            set.apply_block(&block(&[&locked])),
+            Err(UtxoError::NotFinal {
+                txid: locked.txid(),
+                locked: Locked::UntilHeight(3)
+            })
+        );
+        assert_eq!(set, before);
+        let filler = |tag| pay(&[], &[1], tag);
+        set.apply_block(&block(&[&filler(2)])).unwrap();
+        set.apply_block(&block(&[&filler(3), &locked])).unwrap();
+        set.apply_block(&block(&[&filler(4), &early])).unwrap();
+        assert_eq!(set.next_height(), 5);
+
+        // A time lock is judged against the block's own timestamp
+        let timed = pay(&[out(&locked, 0)], &[100], 600_000_000);
+        let stamped = |timestamp| {
+            BlockBuilder::new(BlockHash::ZERO)
+                .transaction(timed.clone())
+                .timestamp(timestamp)
+                .build()
+                .unwrap()
+        };
+        assert_eq!(
+            set.apply_block(&stamped(600_000_000)),
+            Err(UtxoError::NotFinal {
+                txid: timed.txid(),
+                locked: Locked::UntilTime(600_000_001)
+            })
+        );
+        let timed_undo = set.apply_block(&stamped(600_000_001)).unwrap();
+        set.undo_block(timed_undo);
+        assert_eq!(set.next_height(), 5);
+        assert!(set.contains(&out(&locked, 0)));
+    }
+
+    #[test]
+    fn test_encoding_round_trips() {
+        let mut set = UtxoSet::new();
+        let premine = pay(&[], &[100], 0);
+        let split = pay(&[out(&premine, 0)], &[60, 40], 0);
+        set.apply_block(&block(&[&premine])).unwrap();
+        set.apply_block(&block(&[&pay(&[], &[50], 0), &split]))
+            .unwrap();
+
+        let limits = DecodeLimits::default();
+        let bytes = set.to_bytes();
+        let decoded = UtxoSet::from_bytes(&bytes, &limits).unwrap();
+        assert_eq!(decoded.next_height(), 0);
+        assert_eq!(decoded.resume_at(2), set);
+        let bincode = codec::to_bincode(&set).unwrap();
+        let decoded: UtxoSet = codec::from_bincode(&bincode, &limits).unwrap();
+        assert_eq!(decoded.to_bytes(), bytes);
+
+        // One coin, then the same coin twice, out of order
+        let mut single = UtxoSet::new();
+        single.apply_block(&block(&[&premine])).unwrap();
+        let coin = &single.to_bytes()[1..];
+        let repeated = [&[2], coin, coin].concat();
+        assert_eq!(
+            UtxoSet::from_bytes(&repeated, &limits),
+            Err(DecodeError::NonCanonicalEncoding)
+        );
+        let mut flag = single.to_bytes();
+        *flag.last_mut().unwrap() = 2;
+        assert_eq!(
+            UtxoSet::from_bytes(&flag, &limits),
+            Err(DecodeError::InvalidValue("coinbase flag"))
+        );
+        assert!(UtxoSet::from_bytes(&bytes[..bytes.len() - 1], &limits).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn test_undo_reverses_apply(
+            blocks in vec(vec((vec(any::<Index>(), 1..3), vec(1..100u64, 1..3)), 0..4), 1..8),
+        ) {
+            let limits = DecodeLimits::default();
+            let mut set = UtxoSet::new();
+            let mut history = Vec::new();
+            for (height, spends) in blocks.into_iter().enumerate() {
+                // Spends of outputs unspent before the block, which may
+                // collide with each other
+                let unspent: Vec<OutPoint> = set.iter().map(|(out, _)| *out).collect();
+                let mut txs = vec![pay(&[], &[1_000 + height as u64], 0)];
+                for (inputs, amounts) in spends {
+                    if unspent.is_empty() {
+                        break;
+                    }
+                    let inputs: Vec<OutPoint> = inputs
+                        .iter()
+                        .map(|at| unspent[at.index(unspent.len())])
+                        .collect();
+                    txs.push(pay(&inputs, &amounts, 0));
+                }
+                let before = set.clone();
+                match set.apply_block(&block(&txs.iter().collect::<Vec<_>>())) {
+                    Ok(undo) => {
+                        prop_assert_eq!(
+                            set.len(),
+                            before.len() + undo.created_count() - undo.spent_count()
+                        );
+                        history.push((before, undo));
+                    }
+                    Err(_) => prop_assert_eq!(&set, &before),
+                }
+
+                let decoded = UtxoSet::from_bytes(&set.to_bytes(), &limits).unwrap();
+                prop_assert_eq!(decoded.state_root(), set.state_root());
+                prop_assert_eq!(&decoded.resume_at(set.next_height()), &set);
+            }
+
+            for (before, undo) in history.into_iter().rev() {
+                set.undo_block(undo);
+                prop_assert_eq!(&set, &before);
+            }
+            prop_assert_eq!(set, UtxoSet::new());
+        }
+    }
+}
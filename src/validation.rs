@@ -0,0 +1,385 @@
+use std::ops::RangeInclusive;
+
+use thiserror::Error;
+
+use crate::block::{Block, BlockLimits, TxValidationError};
+use crate::difficulty::Difficulty;
+use crate::params::{ChainParams, ConsensusMode};
+
+/// Reasons a block can fail validation
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ValidationError {
+    /// The header's merkle root does not match the block's transactions
+    #[error("merkle root does not match transactions")]
+    MerkleRootMismatch,
+    /// The block hash does not meet the required difficulty
+    #[error("block hash does not meet difficulty")]
+    InsufficientProofOfWork,
+    /// The difficulty committed in the header is easier than the chain's minimum
+    #[error("committed difficulty is below the minimum")]
+    DifficultyBelowMinimum,
+    /// The block is not signed by any of the chain's authorities
+    #[error("block is not signed by an authority")]
+    InvalidAuthoritySignature,
+    /// One or more transactions failed the application's checks
+    #[error("{0}")]
+    InvalidTransactions(TxValidationError),
+    /// The header version is not one the chain accepts
+    #[error("block version {0} is not allowed")]
+    UnsupportedVersion(u32),
+    /// The block carries more transactions than the chain allows
+    #[error("block has {count} transactions but at most {max} are allowed")]
+    TooManyTransactions { count: usize, max: usize },
+    /// The block's transactions add up to more bytes than the chain allows
+    #[error("block carries {bytes} bytes of transactions but at most {max} are allowed")]
+    BlockTooLarge { bytes: usize, max: usize },
+}
+
+/// A single check applied to a block
+pub trait Rule: Send + Sync {
+    fn check(&self, block: &Block) -> Result<(), ValidationError>;
+}
+
+/// Requires the merkle root to commit to the block's transactions
+pub struct MerkleRootRule;
+
+impl Rule for MerkleRootRule {
+    fn check(&self, block: &Block) -> Result<(), ValidationError> {
+        if block.verify_merkle_root() {
+            Ok(())
+        } else {
+            Err(ValidationError::MerkleRootMismatch)
+        }
+    }
+}
+
+/// Requires the header version to be in an allowed range
+pub struct VersionRule {
+    allowed: RangeInclusive<u32>,
+}
+
+impl VersionRule {
+    pub fn new(allowed: RangeInclusive<u32>) -> Self {
+        VersionRule { allowed }
+    }
+}
+
+impl Rule for VersionRule {
+    fn check(&self, block: &Block) -> Result<(), ValidationError> {
+        let version = block.header().version();
+        if self.allowed.contains(&version) {
+            Ok(())
+        } else {
+            Err(ValidationError::UnsupportedVersion(version))
+        }
+    }
+}
+
+/// Requires the block to fit within [`BlockLimits`], measured the same way
+/// as [`Block::fill_template`]
+pub struct BlockLimitsRule {
+    limits: BlockLimits,
+}
+
+impl BlockLimitsRule {
+    pub fn new(limits: BlockLimits) -> Self {
+        BlockLimitsRule { limits }
+    }
+}
+
+impl Rule for BlockLimitsRule {
+    fn check(&self, block: &Block) -> Result<(), ValidationError> {
+        let count = block.transactions().len();
+        if count > self.limits.max_transactions {
+            return Err(ValidationError::TooManyTransactions {
+                count,
+                max: self.limits.max_transactions,
+            });
+        }
+        let bytes = block.transactions().iter().map(Vec::len).sum();
+        if bytes > self.limits.max_bytes {
+            return Err(ValidationError::BlockTooLarge {
+                bytes,
+                max: self.limits.max_bytes,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Requires the block to be sealed according to the chain's consensus mode
+pub struct ConsensusRule {
+    mode: ConsensusMode,
+}
+
+impl ConsensusRule {
+    pub fn new(mode: ConsensusMode) -> Self {
+        ConsensusRule { mode }
+    }
+}
+
+impl Rule for ConsensusRule {
+    fn check(&self, block: &Block) -> Result<(), ValidationError> {
+        match &self.mode {
+            ConsensusMode::ProofOfWork { difficulty } => {
+                if block.verify_pow(*difficulty) {
+                    Ok(())
+                } else {
+                    Err(ValidationError::InsufficientProofOfWork)
+                }
+            }
+            ConsensusMode::ProofOfAuthority { authorities, .. } => {
+                if block.verify_signature(authorities) {
+                    Ok(())
+                } else {
+                    Err(ValidationError::InvalidAuthoritySignature)
+                }
+            }
+        }
+    }
+}
+
+/// Requires the block to meet the difficulty its header commits to, which
+/// must be at least `minimum`.
+///
+/// Fork choice weighs blocks by their committed difficulty, so a chain that
+/// compares branches needs this rather than a plain proof-of-work check.
+pub struct CommittedDifficultyRule {
+    minimum: Difficulty,
+}
+
+impl CommittedDifficultyRule {
+    pub fn new(minimum: Difficulty) -> Self {
+        CommittedDifficultyRule { minimum }
+    }
+}
+
+impl Rule for CommittedDifficultyRule {
+    fn check(&self, block: &Block) -> Result<(), ValidationError> {
+        if !block.verify_pow(self.minimum) {
+            return Err(ValidationError::InsufficientProofOfWork);
+        }
+        if block.difficulty().to_target() > self.minimum.normalized().to_target() {
+            return Err(ValidationError::DifficultyBelowMinimum);
+        }
+        if !block.verify_pow(block.difficulty()) {
+            return Err(ValidationError::InsufficientProofOfWork);
+        }
+        Ok(())
+    }
+}
+
+/// Runs an application-specific check over every transaction in the block
+pub struct TransactionRule<F> {
+    check: F,
+    all_errors: bool,
+}
+
+impl<F> TransactionRule<F>
+where
+    F: Fn(usize, &[u8]) -> Result<(), String> + Send + Sync,
+{
+    /// Create a rule that stops at the first failing transaction
+    pub fn new(check: F) -> Self {
+        TransactionRule {
+            check,
+            all_errors: false,
+        }
+    }
+
+    /// Collect every failing transaction instead of stopping at the first
+    pub fn all_errors(mut self, all_errors: bool) -> Self {
+        self.all_errors = all_errors;
+        self
+    }
+}
+
+impl<F> Rule for TransactionRule<F>
+where
+    F: Fn(usize, &[u8]) -> Result<(), String> + Send + Sync,
+{
+    fn check(&self, block: &Block) -> Result<(), ValidationError> {
+        let result = if self.all_errors {
+            block.validate_all_transactions(&self.check)
+        } else {
+            block.validate_transactions(&self.check)
+        };
+        result.map_err(ValidationError::InvalidTransactions)
+    }
+}
+
+/// An ordered pipeline of rules; validation stops at the first failure
+#[derive(Default)]
+pub struct Validator {
+    rules: Vec<Box<dyn Rule>>,
+}
+
+impl Validator {
+    /// Create a validator with no rules
+    pub fn new() -> Self {
+        Validator { rules: Vec::new() }
+    }
+
+    /// Create the standard validator for a chain: version, size limits,
+    /// merkle root, then consensus
+    pub fn from_params(params: &ChainParams) -> Self {
+        Validator::new()
+            .with_rule(VersionRule::new(params.allowed_versions.clone()))
+            .with_rule(BlockLimitsRule::new(params.block_limits))
+            .with_rule(MerkleRootRule)
+            .with_rule(ConsensusRule::new(params.consensus_mode.clone()))
+    }
+
+    /// Append a rule to the pipeline
+    pub fn with_rule<R: Rule + 'static>(mut self, rule: R) -> Self {
+        self.rules.push(Box::new(rule));
+        self
+    }
+
+    /// Run every rule in order against the block
+    pub fn validate(&self, block: &Block) -> Result<(), ValidationError> {
+        for rule in &self.rules {
+            rule.check(block)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::BlockHash;
+    use crate::crypto::ed25519::SigningKey;
+    use crate::difficulty::Difficulty;
+
+    fn block() -> Block {
+        Block::new(vec![b"tx".to_vec()], BlockHash::ZERO).unwrap()
+    }
+
+    #[test]
+    fn test_proof_of_work_mode() {
+        let pow = |difficulty| ChainParams {
+            consensus_mode: ConsensusMode::ProofOfWork { difficulty },
+            ..ChainParams::test_defaults()
+        };
+
+        let mut block = block();
+        block.mine(Difficulty::LeadingZeroBits(8));
+        assert_eq!(Validator::from_params(&pow(Difficulty::LeadingZeroBits(8))).validate(&block), Ok(()));
+        let compact = Difficulty::CompactTarget(Difficulty::LeadingZeroBits(8).to_compact());
+        assert_eq!(Validator::from_params(&pow(compact)).validate(&block), Ok(()));
+
+        // 64 leading zero bits will not happen by accident
+        assert_eq!(
+            Validator::from_params(&pow(Difficulty::LeadingZeroBits(64))).validate(&block),
+            Err(ValidationError::InsufficientProofOfWork)
+        );
+    }
+
+    #[test]
+    fn test_committed_difficulty_rule() {
+        let rule = CommittedDifficultyRule::new(Difficulty::LeadingZeroBits(4));
+
+        // Mined to the minimum but committing to nothing
+        let mut lazy = block();
+        lazy.mine(Difficulty::LeadingZeroBits(4));
+        assert_eq!(rule.check(&lazy), Err(ValidationError::DifficultyBelowMinimum));
+
+        let mut honest = crate::block::BlockBuilder::new(BlockHash::ZERO)
+            .transaction(b"tx".to_vec())
+            .difficulty(Difficulty::LeadingZeroBits(12))
+            .build()
+            .unwrap();
+        honest.mine(Difficulty::LeadingZeroBits(12));
+        assert_eq!(rule.check(&honest), Ok(()));
+    }
+
+    #[test]
+    fn test_version_and_limit_rules() {
+        let mut params = ChainParams::test_defaults();
+        params.consensus_mode = ConsensusMode::ProofOfWork {
+            difficulty: Difficulty::LeadingZeroBits(0),
+        };
+        params.allowed_versions = 1..=2;
+        params.block_limits = BlockLimits {
+            max_bytes: 4,
+            max_transactions: 2,
+        };
+        let validator = Validator::from_params(&params);
+        let build = |version: u32, txs: &[&[u8]]| {
+            crate::block::BlockBuilder::new(BlockHash::ZERO)
+                .version(version)
+                .transactions(txs.iter().map(|tx| tx.to_vec()))
+                .build()
+                .unwrap()
+        };
+
+        assert_eq!(validator.validate(&build(2, &[b"ab", b"cd"])), Ok(()));
+        assert_eq!(
+            validator.validate(&build(3, &[b"ab"])),
+            Err(ValidationError::UnsupportedVersion(3))
+        );
+        assert_eq!(
+            validator.validate(&build(1, &[b"a", b"b", b"c"])),
+            Err(ValidationError::TooManyTransactions { count: 3, max: 2 })
+        );
+        assert_eq!(
+            validator.validate(&build(1, &[b"abc", b"de"])),
+            Err(ValidationError::BlockTooLarge { bytes: 5, max: 4 })
+        );
+    }
+
+    #[test]
+    fn test_transaction_rule() {
+        let block = Block::new(vec![b"a".to_vec(), vec![0xff], vec![0xff, 1]], BlockHash::ZERO).unwrap();
+        let no_ff = |_: usize, tx: &[u8]| {
+            if tx.contains(&0xff) {
+                Err("forbidden byte".to_string())
+            } else {
+                Ok(())
+            }
+        };
+
+        let first = Validator::new().with_rule(TransactionRule::new(no_ff));
+        match first.validate(&block) {
+            Err(ValidationError::InvalidTransactions(err)) => {
+                assert_eq!(err.failures().len(), 1);
+                assert_eq!(err.index(), 1);
+                assert_eq!(err.message(), "forbidden byte");
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let all = Validator::new().with_rule(TransactionRule::new(no_ff).all_errors(true));
+        match all.validate(&block) {
+            Err(ValidationError::InvalidTransactions(err)) => {
+                let indexes: Vec<usize> = err.failures().iter().map(|f| f.index).collect();
+                assert_eq!(indexes, vec![1, 2]);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_proof_of_authority_mode() {
+        let authority = SigningKey::from_bytes(&[1; 32]);
+        let params = ChainParams {
+            consensus_mode: ConsensusMode::ProofOfAuthority {
+                authorities: vec![authority.verifying_key().into()],
+                step_duration: 0,
+                skew_tolerance: 0,
+            },
+            ..ChainParams::test_defaults()
+        };
+        let validator = Validator::from_params(&params);
+
+        let mut block = block();
+        assert_eq!(validator.validate(&block), Err(ValidationError::InvalidAuthoritySignature));
+
+        block.sign(&SigningKey::from_bytes(&[2; 32]));
+        assert_eq!(validator.validate(&block), Err(ValidationError::InvalidAuthoritySignature));
+
+        block.sign(&authority);
+        assert_eq!(validator.validate(&block), Ok(()));
+    }
+}
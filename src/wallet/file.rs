@@ -0,0 +1,419 @@
+//! Saving a wallet's keys to disk, encrypted under a passphrase.
+//!
+//! The file starts with a header: magic, format version, the scrypt
+//! parameters, salt and nonce. A 32 byte key is derived from the passphrase
+//! with scrypt, and the key material is sealed with ChaCha20-Poly1305 under
+//! that key, with the header as associated data, so a file whose header has
+//! been altered fails to open just like one opened with the wrong passphrase.
+//!
+//! Only keys are saved: the single keys, and the HD account with the number
+//! of its addresses handed out. Outputs are found again by following the
+//! chain.
+
+use std::fs;
+use std::path::Path;
+
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+use super::{HdKeys, Wallet};
+use crate::codec::{write_varint, Reader};
+use crate::crypto::hd::{ChildNumber, ExtendedPrivKey};
+use crate::crypto::{SecretKey, SignatureScheme};
+
+const MAGIC: &[u8; 4] = b"AWWL";
+const VERSION: u32 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// Magic, version, scrypt parameters, salt and nonce
+const HEADER_LEN: usize = 4 + 4 + (1 + 4 + 4) + SALT_LEN + NONCE_LEN;
+
+/// Largest scrypt cost accepted, as the bytes each lane fills times the
+/// number of lanes, so that a crafted header cannot exhaust memory or time
+const MAX_SCRYPT_COST: u64 = 1 << 30;
+
+/// Reasons a wallet file cannot be written or opened
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum WalletFileError {
+    /// The file could not be read or written
+    #[error("wallet file error: {0}")]
+    Io(String),
+    /// The file does not start with a wallet header
+    #[error("not a wallet file")]
+    NotAWallet,
+    /// The file is in a format version this build does not know
+    #[error("unsupported wallet file version {0}")]
+    UnsupportedVersion(u32),
+    /// The scrypt parameters are invalid, or cost more than allowed
+    #[error("invalid key derivation parameters")]
+    InvalidKdfParams,
+    /// The passphrase is wrong, or the file has been altered
+    #[error("wrong passphrase or altered wallet file")]
+    Decryption,
+    /// The decrypted key material is malformed
+    #[error("corrupt wallet file")]
+    Corrupt,
+}
+
+impl From<std::io::Error> for WalletFileError {
+    fn from(err: std::io::Error) -> Self {
+        WalletFileError::Io(err.to_string())
+    }
+}
+
+/// Cost parameters of the scrypt derivation: `2^log_n` iterations over
+/// blocks of `r`, in `p` parallel lanes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScryptParams {
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+}
+
+impl Default for ScryptParams {
+    /// `2^15` iterations over 1 KiB blocks: 32 MiB of memory
+    fn default() -> Self {
+        ScryptParams {
+            log_n: 15,
+            r: 8,
+            p: 1,
+        }
+    }
+}
+
+impl ScryptParams {
+    /// Derive the file key from `passphrase` and `salt`
+    fn derive_key(
+        &self,
+        passphrase: &str,
+        salt: &[u8],
+    ) -> Result<Zeroizing<[u8; 32]>, WalletFileError> {
+        // 2^log_n * 128 * r * p overflows even u128 for the largest
+        // parameters, so every step is checked
+        let cost = 1u128
+            .checked_shl(self.log_n.into())
+            .and_then(|n| n.checked_mul(128 * u128::from(self.r) * u128::from(self.p)));
+        if cost.is_none_or(|cost| cost > u128::from(MAX_SCRYPT_COST)) {
+            return Err(WalletFileError::InvalidKdfParams);
+        }
+        let params = scrypt::Params::new(self.log_n, self.r, self.p, 32)
+            .map_err(|_| WalletFileError::InvalidKdfParams)?;
+        let mut key = Zeroizing::new([0; 32]);
+        scrypt::scrypt(passphrase.as_bytes(), salt, &params, key.as_mut())
+            .map_err(|_| WalletFileError::InvalidKdfParams)?;
+        Ok(key)
+    }
+}
+
+impl Wallet {
+    /// Write the wallet's keys to `path`, encrypted under `passphrase` with
+    /// the default [`ScryptParams`]
+    pub fn save_encrypted(
+        &self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<(), WalletFileError> {
+        self.save_encrypted_with(path, passphrase, ScryptParams::default())
+    }
+
+    /// Like [`Wallet::save_encrypted`], deriving the file key with `params`.
+    ///
+    /// The file is written beside `path` and renamed over it, so a crash
+    /// leaves either the old file or the new one.
+    #[allow(clippy::expect_used)]
+    pub fn save_encrypted_with(
+        &self,
+        path: impl AsRef<Path>,
+        passphrase: &str,
+        params: ScryptParams,
+    ) -> Result<(), WalletFileError> {
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let key = params.derive_key(passphrase, &salt)?;
+
+        let mut file = Vec::with_capacity(HEADER_LEN);
+        file.extend_from_slice(MAGIC);
+        file.extend_from_slice(&VERSION.to_le_bytes());
+        file.push(params.log_n);
+        file.extend_from_slice(&params.r.to_le_bytes());
+        file.extend_from_slice(&params.p.to_le_bytes());
+        file.extend_from_slice(&salt);
+        file.extend_from_slice(&nonce);
+
+        let plaintext = self.encode_keys();
+        let sealed = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: &file,
+                },
+            )
+            .expect("a wallet is far below the cipher's length limit");
+        file.extend_from_slice(&sealed);
+
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, &file)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Open a wallet file written by [`Wallet::save_encrypted`]. The wallet
+    /// holds the saved keys and no outputs until it follows a chain.
+    pub fn load_encrypted(
+        path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> Result<Wallet, WalletFileError> {
+        let file = fs::read(path)?;
+        let version = match file.split_first_chunk::<4>() {
+            Some((magic, rest)) if magic == MAGIC => rest.first_chunk::<4>(),
+            _ => None,
+        }
+        .ok_or(WalletFileError::NotAWallet)?;
+        let version = u32::from_le_bytes(*version);
+        if version != VERSION {
+            return Err(WalletFileError::UnsupportedVersion(version));
+        }
+        let (header, sealed) = file
+            .split_first_chunk::<HEADER_LEN>()
+            .ok_or(WalletFileError::NotAWallet)?;
+        // The magic and version, then the scrypt parameters, salt and nonce
+        let [_, _, _, _, _, _, _, _, log_n, r0, r1, r2, r3, p0, p1, p2, p3, ref salt_nonce @ ..] =
+            *header;
+        let params = ScryptParams {
+            log_n,
+            r: u32::from_le_bytes([r0, r1, r2, r3]),
+            p: u32::from_le_bytes([p0, p1, p2, p3]),
+        };
+        let (salt, nonce) = salt_nonce.split_at(SALT_LEN);
+
+        let key = params.derive_key(passphrase, salt)?;
+        let plaintext = ChaCha20Poly1305::new(Key::from_slice(key.as_ref()))
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: sealed,
+                    aad: header.as_slice(),
+                },
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| WalletFileError::Decryption)?;
+        Wallet::decode_keys(&plaintext).ok_or(WalletFileError::Corrupt)
+    }
+
+    /// The single keys, then the HD account if there is one
+    fn encode_keys(&self) -> Zeroizing<Vec<u8>> {
+        let mut buf = Zeroizing::new(Vec::new());
+        write_varint(&mut buf, self.single.len() as u64);
+        for address in &self.single {
+            let key = &self.keys[address];
+            buf.push(key.scheme().tag());
+            buf.extend_from_slice(key.to_bytes().as_ref());
+        }
+        match &self.hd {
+            None => buf.push(0),
+            Some(hd) => {
+                buf.push(1);
+                buf.push(hd.account.scheme().tag());
+                buf.extend_from_slice(hd.account.secret_bytes().as_ref());
+                buf.extend_from_slice(hd.account.chain_code());
+                buf.push(hd.account.depth());
+                buf.extend_from_slice(&hd.account.child_number().to_u32().to_le_bytes());
+                write_varint(&mut buf, hd.used as u64);
+                buf.extend_from_slice(&hd.gap_limit.to_le_bytes());
+            }
+        }
+        buf
+    }
+
+    fn decode_keys(bytes: &[u8]) -> Option<Wallet> {
+        fn read_key(reader: &mut Reader) -> Option<SecretKey> {
+            let scheme = SignatureScheme::from_tag(reader.read_u8().ok()?)?;
+            let secret = Zeroizing::new(reader.read_array::<32>().ok()?);
+            SecretKey::from_bytes(scheme, &secret).ok()
+        }
+
+        let mut reader = Reader::new(bytes);
+        let mut wallet = Wallet::new();
+        for _ in 0..reader.read_varint().ok()? {
+            wallet.add_key(read_key(&mut reader)?);
+        }
+        if reader.read_u8().ok()? == 1 {
+            let key = read_key(&mut reader)?;
+            let chain_code = Zeroizing::new(reader.read_array::<32>().ok()?);
+            let depth = reader.read_u8().ok()?;
+            let child_number = ChildNumber::from_u32(reader.read_u32().ok()?);
+            let used = reader.read_varint().ok()?;
+            let gap_limit = reader.read_u32().ok()?;
+            wallet.hd = Some(HdKeys {
+                account: ExtendedPrivKey::from_parts(key, *chain_code, depth, child_number),
+                addresses: Vec::new(),
+                next_index: 0,
+                used: used.try_into().ok()?,
+                gap_limit,
+            });
+            wallet.top_up().ok()?;
+        }
+        reader.finish().ok()?;
+        Some(wallet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::address::Address;
+    use crate::crypto::{ed25519, secp256k1};
+    use crate::store::TempDir;
+
+    /// Cheap enough for tests
+    const TEST_PARAMS: ScryptParams = ScryptParams {
+        log_n: 4,
+        r: 8,
+        p: 1,
+    };
+
+    fn wallet() -> Wallet {
+        let master = ExtendedPrivKey::from_seed(SignatureScheme::Secp256k1, &[1; 32]).unwrap();
+        let account = master
+            .derive_path(&"m/44'/0'/0'/0".parse().unwrap())
+            .unwrap();
+        let mut wallet = Wallet::from_account_with_gap_limit(account, 3).unwrap();
+        wallet.add_key(ed25519::SigningKey::from_bytes(&[7; 32]));
+        wallet.add_key(secp256k1::SigningKey::from_bytes(&[8; 32]).unwrap());
+        for _ in 0..4 {
+            wallet.receive_address().unwrap();
+        }
+        wallet
+    }
+
+    fn saved(name: &str, passphrase: &str) -> (TempDir, std::path::PathBuf) {
+        let dir = TempDir::new(name);
+        fs::create_dir_all(dir.path()).unwrap();
+        let path = dir.path().join("wallet.dat");
+        wallet()
+            .save_encrypted_with(&path, passphrase, TEST_PARAMS)
+            .unwrap();
+        (dir, path)
+    }
+
+    fn addresses(wallet: &Wallet) -> Vec<Address> {
+        let mut addresses: Vec<Address> = wallet.keys.keys().copied().collect();
+        addresses.sort();
+        addresses
+    }
+
+    #[test]
+    fn test_encrypted_round_trip() {
+        let (_dir, path) = saved("wallet-round-trip", "correct horse");
+        let mut loaded = Wallet::load_encrypted(&path, "correct horse").unwrap();
+        let mut original = wallet();
+        assert_eq!(addresses(&loaded), addresses(&original));
+        assert_eq!(loaded.single, original.single);
+        // The next fresh address carries on from the saved one
+        assert_eq!(loaded.receive_address(), original.receive_address());
+
+        // The key material is not in the file in the clear
+        let file = fs::read(&path).unwrap();
+        let secret = original.keys[&original.single[0]].to_bytes();
+        assert!(!file.windows(32).any(|window| window == secret.as_ref()));
+
+        // A wallet without keys round trips too
+        Wallet::new()
+            .save_encrypted_with(&path, "", TEST_PARAMS)
+            .unwrap();
+        assert_eq!(Wallet::load_encrypted(&path, "").unwrap().keys.len(), 0);
+    }
+
+    #[test]
+    fn test_wrong_passphrase_fails() {
+        let (_dir, path) = saved("wallet-wrong-passphrase", "correct horse");
+        let err = Wallet::load_encrypted(&path, "correct horse ").unwrap_err();
+        assert_eq!(err, WalletFileError::Decryption);
+        assert!(!err.to_string().contains("horse"));
+    }
+
+    #[test]
+    fn test_corrupted_file_fails() {
+        let (_dir, path) = saved("wallet-corrupted", "pass");
+        let file = fs::read(&path).unwrap();
+        let load_with = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            Wallet::load_encrypted(&path, "pass").map(|_| ())
+        };
+
+        let mut flipped = file.clone();
+        flipped[HEADER_LEN + 5] ^= 1;
+        assert_eq!(load_with(&flipped), Err(WalletFileError::Decryption));
+        assert_eq!(
+            load_with(&file[..file.len() - 1]),
+            Err(WalletFileError::Decryption)
+        );
+        assert_eq!(
+            load_with(&file[..HEADER_LEN - 1]),
+            Err(WalletFileError::NotAWallet)
+        );
+        assert_eq!(load_with(b"not a wallet"), Err(WalletFileError::NotAWallet));
+
+        let mut future = file.clone();
+        future[4..8].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(
+            load_with(&future),
+            Err(WalletFileError::UnsupportedVersion(2))
+        );
+        assert_eq!(load_with(&file), Ok(()));
+    }
+
+    #[test]
+    fn test_header_is_authenticated() {
+        let (_dir, path) = saved("wallet-header", "pass");
+        let file = fs::read(&path).unwrap();
+        let load_with = |bytes: &[u8]| {
+            fs::write(&path, bytes).unwrap();
+            Wallet::load_encrypted(&path, "pass").map(|_| ())
+        };
+
+        // The salt and nonce are covered
+        for i in HEADER_LEN - SALT_LEN - NONCE_LEN..HEADER_LEN {
+            let mut altered = file.clone();
+            altered[i] ^= 1;
+            assert_eq!(
+                load_with(&altered),
+                Err(WalletFileError::Decryption),
+                "byte {}",
+                i
+            );
+        }
+        // So are the scrypt parameters: log_n, r and p
+        for (i, value) in [(8, TEST_PARAMS.log_n - 1), (9, 4), (13, 2)] {
+            let mut altered = file.clone();
+            altered[i] = value;
+            assert_eq!(
+                load_with(&altered),
+                Err(WalletFileError::Decryption),
+                "byte {}",
+                i
+            );
+        }
+        // Parameters too costly to try are refused before deriving
+        let mut costly = file.clone();
+        costly[8] = 40;
+        assert_eq!(load_with(&costly), Err(WalletFileError::InvalidKdfParams));
+        // Including those whose cost overflows a u64
+        let mut overflowing = file.clone();
+        overflowing[8] = 30;
+        overflowing[9..13].copy_from_slice(&(1u32 << 20).to_le_bytes());
+        overflowing[13..17].copy_from_slice(&(1u32 << 7).to_le_bytes());
+        assert_eq!(
+            load_with(&overflowing),
+            Err(WalletFileError::InvalidKdfParams)
+        );
+    }
+}
@@ -0,0 +1,635 @@
+//! A wallet: keys, the outputs paying them, and transactions spending those.
+//!
+//! A [`Wallet`] holds single keys, an HD account whose keys it derives as
+//! needed, or both. It learns of outputs by scanning the blocks of the
+//! active chain: [`Wallet::follow`] replays the chain and subscribes to its
+//! [`ChainEvent`]s, and [`Wallet::sync`] applies the events received since,
+//! so outputs created by a block a reorg disconnects leave the balance and
+//! outputs it spent return to it.
+//!
+//! This module needs the `crypto` feature, whose signing is not constant
+//! time; see [`crypto`](crate::crypto#secret-keys).
+
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::mpsc::{Receiver, TryRecvError};
+
+use thiserror::Error;
+
+use crate::address::Address;
+use crate::block::{Block, BlockHash};
+use crate::chain::{Blockchain, ChainEvent, StoredBlock};
+use crate::codec::DecodeLimits;
+use crate::crypto::hd::{DerivationError, ExtendedPrivKey};
+use crate::crypto::{SecretKey, SignatureScheme, Signer, SIGNATURE_LENGTH};
+use crate::store::ChainStore;
+use crate::transaction::{OutPoint, Transaction, TxInput, TxOutput, MIN_INPUT_SIZE, OUTPUT_SIZE};
+
+mod file;
+mod select;
+
+pub use file::{ScryptParams, WalletFileError};
+pub use select::{
+    BranchAndBound, Candidate, CoinSelector, LargestFirst, Selection, SmallestFirst, Target,
+};
+
+/// Addresses an HD wallet derives beyond the last one used, so that
+/// payments to addresses it handed out are found in blocks
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// Reasons a wallet cannot build a transaction or keep up with the chain
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum WalletError {
+    /// The spendable outputs hold `available`, less than the `required`
+    /// amount plus the fee of spending them all
+    #[error("{required} is required but only {available} is spendable")]
+    InsufficientFunds { available: u64, required: u64 },
+    /// The amount is below the smallest output transactions may carry
+    #[error("amount {0} is below the dust limit")]
+    Dust(u64),
+    /// Amounts or fees add up to more than a `u64` holds
+    #[error("amounts overflow")]
+    AmountOverflow,
+    /// The wallet has no key to receive with
+    #[error("the wallet has no keys")]
+    NoKeys,
+    /// The wallet is not receiving chain events: it never followed a chain,
+    /// or fell behind and was cut off. [`Wallet::follow`] resynchronises it.
+    #[error("the wallet is not following a chain")]
+    Unsubscribed,
+    /// A connected block is not in the chain, as when it has been pruned
+    /// since; the wallet must follow the chain again
+    #[error("connected block {0} is unknown")]
+    UnknownBlock(BlockHash),
+    /// The HD account could not derive the next address
+    #[error("cannot derive the next address: {0}")]
+    Derivation(DerivationError),
+}
+
+impl From<DerivationError> for WalletError {
+    fn from(err: DerivationError) -> Self {
+        WalletError::Derivation(err)
+    }
+}
+
+/// An output paying one of the wallet's keys, and the block that created it
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Coin {
+    output: TxOutput,
+    height: u64,
+    /// Created by a coinbase after the genesis block, so subject to maturity
+    coinbase: bool,
+}
+
+impl Coin {
+    /// The address the output pays, which the wallet only tracks for
+    /// single-key outputs
+    #[allow(clippy::expect_used)]
+    fn address(&self) -> &Address {
+        self.output
+            .condition
+            .address()
+            .expect("the wallet tracks single-key outputs only")
+    }
+}
+
+/// What a connected block changed, for its disconnection to reverse
+#[derive(Clone, Debug, Default)]
+struct BlockChanges {
+    created: Vec<OutPoint>,
+    spent: Vec<(OutPoint, Coin)>,
+}
+
+/// The children of an HD account key, derived in index order
+struct HdKeys {
+    account: ExtendedPrivKey,
+    /// Addresses of the children derived so far, in index order
+    addresses: Vec<Address>,
+    /// Index the next child derives from
+    next_index: u32,
+    /// Number of the derived addresses handed out or seen in a block
+    used: usize,
+    gap_limit: u32,
+}
+
+impl HdKeys {
+    /// Derive the next child, skipping the indices BIP-32 gives no key for
+    fn derive_next(&mut self) -> Result<ExtendedPrivKey, DerivationError> {
+        // ed25519 keys derive only hardened children
+        let hardened = self.account.scheme() == SignatureScheme::Ed25519;
+        loop {
+            let index = self.next_index;
+            self.next_index += 1;
+            match self.account.derive_child(index, hardened) {
+                Err(DerivationError::InvalidKey) => continue,
+                derived => return derived,
+            }
+        }
+    }
+}
+
+/// Keys, the unspent outputs paying them, and the chain position they are
+/// known at.
+///
+/// The wallet does not mark the outputs a transaction it creates spends
+/// until a block spending them connects: creating another transaction
+/// before then may select the same outputs.
+pub struct Wallet {
+    keys: HashMap<Address, SecretKey>,
+    /// Addresses of the single keys, in the order they were added
+    single: Vec<Address>,
+    hd: Option<HdKeys>,
+    coins: BTreeMap<OutPoint, Coin>,
+    /// Changes made by the connected blocks that changed anything
+    blocks: HashMap<BlockHash, BlockChanges>,
+    /// Height of the last block connected
+    height: u64,
+    coinbase_maturity: u64,
+    events: Option<Receiver<ChainEvent>>,
+}
+
+impl Wallet {
+    /// A wallet without keys
+    pub fn new() -> Self {
+        Wallet {
+            keys: HashMap::new(),
+            single: Vec::new(),
+            hd: None,
+            coins: BTreeMap::new(),
+            blocks: HashMap::new(),
+            height: 0,
+            coinbase_maturity: 0,
+            events: None,
+        }
+    }
+
+    /// A wallet receiving with the children of `account`, such as the key
+    /// at `m/44'/0'/0'/0`, deriving [`DEFAULT_GAP_LIMIT`] ahead of the last
+    /// one used. ed25519 accounts derive hardened children.
+    pub fn from_account(account: ExtendedPrivKey) -> Result<Self, DerivationError> {
+        Wallet::from_account_with_gap_limit(account, DEFAULT_GAP_LIMIT)
+    }
+
+    /// Like [`Wallet::from_account`], deriving `gap_limit` addresses ahead
+    pub fn from_account_with_gap_limit(
+        account: ExtendedPrivKey,
+        gap_limit: u32,
+    ) -> Result<Self, DerivationError> {
+        let mut wallet = Wallet::new();
+        wallet.hd = Some(HdKeys {
+            account,
+            addresses: Vec::new(),
+            next_index: 0,
+            used: 0,
+            gap_limit,
+        });
+        wallet.top_up()?;
+        Ok(wallet)
+    }
+
+    /// Add a single key, returning its address
+    pub fn add_key(&mut self, key: impl Into<SecretKey>) -> Address {
+        let key = key.into();
+        let address = Address::from_public_key(&key.public_key());
+        if let Entry::Vacant(entry) = self.keys.entry(address) {
+            entry.insert(key);
+            self.single.push(address);
+        }
+        address
+    }
+
+    /// An address to be paid at: a fresh one from the HD account if there
+    /// is one, else the first single key's
+    pub fn receive_address(&mut self) -> Result<Address, WalletError> {
+        if let Some(hd) = &mut self.hd {
+            let address = hd.addresses[hd.used];
+            hd.used += 1;
+            self.top_up()?;
+            return Ok(address);
+        }
+        self.single.first().copied().ok_or(WalletError::NoKeys)
+    }
+
+    /// Whether `address` is one of the wallet's, including HD addresses
+    /// derived ahead but not yet handed out
+    pub fn is_mine(&self, address: &Address) -> bool {
+        self.keys.contains_key(address)
+    }
+
+    /// Derive HD addresses until the gap limit lies beyond the last one used
+    fn top_up(&mut self) -> Result<(), DerivationError> {
+        let Some(hd) = &mut self.hd else {
+            return Ok(());
+        };
+        while hd.addresses.len() < hd.used + hd.gap_limit.max(1) as usize {
+            let key = hd.derive_next()?;
+            let address = Address::from_public_key(&key.public_key());
+            hd.addresses.push(address);
+            self.keys.insert(address, key.secret_key().duplicate());
+        }
+        Ok(())
+    }
+
+    /// Mark the HD address `address` and those before it used, deriving
+    /// further ahead
+    #[allow(clippy::expect_used)]
+    fn mark_used(&mut self, address: &Address) {
+        let Some(hd) = &mut self.hd else {
+            return;
+        };
+        if let Some(position) = hd.addresses[hd.used..].iter().position(|a| a == address) {
+            hd.used += position + 1;
+            self.top_up()
+                .expect("the account derived its first child, so derives the rest");
+        }
+    }
+
+    /// Height of the last block the wallet has seen connect
+    pub fn height(&self) -> u64 {
+        self.height
+    }
+
+    /// The total of the outputs paying the wallet, mature or not
+    pub fn balance(&self) -> u64 {
+        self.coins.values().map(|coin| coin.output.amount).sum()
+    }
+
+    /// The total of the outputs a transaction in the next block may spend,
+    /// leaving out immature coinbase outputs
+    pub fn spendable_balance(&self) -> u64 {
+        self.spendable().map(|(_, coin)| coin.output.amount).sum()
+    }
+
+    /// The outputs paying the wallet, in outpoint order
+    pub fn outputs(&self) -> impl Iterator<Item = (&OutPoint, &TxOutput)> {
+        self.coins.iter().map(|(out, coin)| (out, &coin.output))
+    }
+
+    fn spendable(&self) -> impl Iterator<Item = (&OutPoint, &Coin)> {
+        let next_height = self.height + 1;
+        self.coins.iter().filter(move |(_, coin)| {
+            !coin.coinbase || next_height >= coin.height.saturating_add(self.coinbase_maturity)
+        })
+    }
+
+    /// Take in the outputs `block`, connected at `height`, pays the wallet
+    /// and drop those it spends. Transactions that do not decode are
+    /// skipped.
+    pub fn connect_block(&mut self, block: &Block, height: u64) {
+        let mut changes = BlockChanges::default();
+        for bytes in block.transactions() {
+            let Ok(tx) = Transaction::decode_from_block(bytes) else {
+                continue;
+            };
+            for input in &tx.inputs {
+                if let Some(coin) = self.coins.remove(&input.prev_out) {
+                    changes.spent.push((input.prev_out, coin));
+                }
+            }
+            let txid = tx.txid();
+            for (index, output) in tx.outputs.iter().enumerate() {
+                let Some(address) = output.condition.address() else {
+                    continue;
+                };
+                if !self.keys.contains_key(address) {
+                    continue;
+                }
+                self.mark_used(address);
+                let out = OutPoint {
+                    txid,
+                    index: index as u32,
+                };
+                let coin = Coin {
+                    output: output.clone(),
+                    height,
+                    coinbase: tx.is_coinbase() && height > 0,
+                };
+                self.coins.insert(out, coin);
+                changes.created.push(out);
+            }
+        }
+        if !changes.created.is_empty() || !changes.spent.is_empty() {
+            self.blocks.insert(block.hash(), changes);
+        }
+        self.height = height;
+    }
+
+    /// Reverse [`Wallet::connect_block`] for the block `hash` at `height`,
+    /// which must be the last block connected
+    pub fn disconnect_block(&mut self, hash: &BlockHash, height: u64) {
+        if let Some(changes) = self.blocks.remove(hash) {
+            for out in changes.created.iter().rev() {
+                self.coins.remove(out);
+            }
+            for (out, coin) in changes.spent.into_iter().rev() {
+                self.coins.insert(out, coin);
+            }
+        }
+        self.height = height.saturating_sub(1);
+    }
+
+    /// Forget every output and scan the active chain of `chain` from
+    /// genesis, then subscribe to its events for [`Wallet::sync`]. Pruned
+    /// blocks are skipped, so outputs they created are missed.
+    pub fn follow<S: ChainStore>(&mut self, chain: &mut Blockchain<S>) {
+        self.coins.clear();
+        self.blocks.clear();
+        self.height = 0;
+        self.coinbase_maturity = chain.params().coinbase_maturity;
+        for height in 0..=chain.height() {
+            if let Some(block) = chain.get(height) {
+                self.connect_block(block, height);
+            }
+        }
+        self.events = Some(chain.subscribe());
+    }
+
+    /// Apply the chain events received since the last sync, looking the
+    /// connected blocks up in `chain`, the chain being followed.
+    ///
+    /// Fails with [`WalletError::Unsubscribed`] if the subscription was cut
+    /// off, and with [`WalletError::UnknownBlock`] if a connected block is
+    /// gone; either way the wallet stops following and must
+    /// [`Wallet::follow`] the chain again.
+    pub fn sync<S: ChainStore>(&mut self, chain: &Blockchain<S>) -> Result<(), WalletError> {
+        let events = self.events.take().ok_or(WalletError::Unsubscribed)?;
+        loop {
+            match events.try_recv() {
+                Ok(ChainEvent::Connected { hash, height }) => {
+                    let block = chain
+                        .tree()
+                        .get(&hash)
+                        .map(StoredBlock::block)
+                        .or_else(|| chain.get_by_hash(hash.as_ref()))
+                        .ok_or(WalletError::UnknownBlock(hash))?;
+                    self.connect_block(block, height);
+                }
+                Ok(ChainEvent::Disconnected { hash, height }) => {
+                    self.disconnect_block(&hash, height)
+                }
+                Ok(ChainEvent::ReorgCompleted { .. }) => {}
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(WalletError::Unsubscribed),
+            }
+        }
+        self.events = Some(events);
+        Ok(())
+    }
+
+    /// A signed transaction paying `amount` to `to`, at a fee of `fee_rate`
+    /// per byte of its encoding, spending the outputs `selector` chooses.
+    ///
+    /// Only spendable outputs are offered to the selector. Change returns to
+    /// a [`Wallet::receive_address`], and every input is signed over its
+    /// sighash by the key its output pays.
+    pub fn create_transaction(
+        &mut self,
+        to: Address,
+        amount: u64,
+        fee_rate: u64,
+        selector: &impl CoinSelector,
+    ) -> Result<Transaction, WalletError> {
+        let dust = DecodeLimits::default().min_output_amount;
+        if amount < dust {
+            return Err(WalletError::Dust(amount));
+        }
+        let mut tx = Transaction {
+            outputs: vec![TxOutput::to_address(amount, to)],
+            ..Transaction::default()
+        };
+        let candidates: Vec<Candidate> = self
+            .spendable()
+            .map(|(out, coin)| Candidate {
+                out: *out,
+                amount: coin.output.amount,
+                input_size: MIN_INPUT_SIZE
+                    + 1
+                    + SIGNATURE_LENGTH
+                    + self.keys[coin.address()].public_key().as_bytes().len(),
+            })
+            .collect();
+        let target = Target {
+            amount,
+            fee_rate,
+            base_size: tx.encode().len(),
+            change_size: OUTPUT_SIZE,
+            dust,
+        };
+        let selection = selector.select(&candidates, &target)?;
+
+        tx.inputs = selection
+            .inputs
+            .iter()
+            .map(|&prev_out| TxInput {
+                prev_out,
+                signatures: Vec::new(),
+                public_key: None,
+                recovery_id: None,
+            })
+            .collect();
+        if let Some(amount) = selection.change {
+            tx.outputs
+                .push(TxOutput::to_address(amount, self.receive_address()?));
+        }
+        for index in 0..tx.inputs.len() {
+            let address = self.coins[&tx.inputs[index].prev_out].address();
+            tx.sign_input(index, &self.keys[address]);
+        }
+        Ok(tx)
+    }
+}
+
+impl Default for Wallet {
+    fn default() -> Self {
+        Wallet::new()
+    }
+}
+
+impl fmt::Debug for Wallet {
+    /// The number of keys and the outputs, keeping the keys out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Wallet")
+            .field("keys", &self.keys.len())
+            .field("coins", &self.coins)
+            .field("height", &self.height)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::ed25519;
+    use crate::crypto::mnemonic::Mnemonic;
+    use crate::params::{ChainParams, COIN};
+
+    fn params() -> ChainParams {
+        ChainParams {
+            genesis_transactions: vec![Transaction::default().encode()],
+            coinbase_maturity: 2,
+            ..ChainParams::test_defaults()
+        }
+    }
+
+    fn hd_wallet() -> Wallet {
+        let mnemonic: Mnemonic =
+            "legal winner thank year wave sausage worth useful legal winner thank yellow"
+                .parse()
+                .unwrap();
+        let master =
+            ExtendedPrivKey::from_seed(SignatureScheme::Secp256k1, &mnemonic.to_seed("")[..])
+                .unwrap();
+        let account = master
+            .derive_path(&"m/44'/0'/0'/0".parse().unwrap())
+            .unwrap();
+        Wallet::from_account_with_gap_limit(account, 3).unwrap()
+    }
+
+    /// A mined child of `parent` whose coinbase, told apart by `tag`, pays
+    /// the subsidy plus `fees` to `miner`
+    fn mine(parent: &Block, miner: Address, tag: u32, fees: u64, txs: &[&Transaction]) -> Block {
+        let params = params();
+        let coinbase = Transaction {
+            outputs: vec![TxOutput::to_address(params.initial_subsidy + fees, miner)],
+            lock_time: tag,
+            ..Transaction::default()
+        };
+        let mut block = parent
+            .next_builder()
+            .transactions(
+                std::iter::once(coinbase.encode()).chain(txs.iter().map(|tx| tx.encode())),
+            )
+            .difficulty(params.initial_difficulty)
+            .timestamp(parent.timestamp() + 10)
+            .build()
+            .unwrap();
+        block.mine(params.initial_difficulty);
+        block
+    }
+
+    #[test]
+    fn test_mine_spend_and_reorg() {
+        let mut chain = Blockchain::new_from_params(&params())
+            .with_utxo_set()
+            .unwrap();
+        let mut alice = hd_wallet();
+        let mut bob = Wallet::new();
+        let bob_address = bob.add_key(ed25519::SigningKey::from_bytes(&[7; 32]));
+        let stranger = Address::from_bytes([9; 32]);
+        alice.follow(&mut chain);
+        bob.follow(&mut chain);
+
+        // A coinbase paying alice matures two blocks later
+        let miner = alice.receive_address().unwrap();
+        let a1 = mine(chain.tip(), miner, 1, 0, &[]);
+        chain.append(a1.clone()).unwrap();
+        alice.sync(&chain).unwrap();
+        assert_eq!(alice.balance(), 50 * COIN);
+        assert_eq!(alice.spendable_balance(), 0);
+        assert!(matches!(
+            alice.create_transaction(bob_address, COIN, 1, &LargestFirst),
+            Err(WalletError::InsufficientFunds { available: 0, .. })
+        ));
+        let a2 = mine(&a1, stranger, 2, 0, &[]);
+        chain.append(a2.clone()).unwrap();
+        alice.sync(&chain).unwrap();
+        assert_eq!(alice.spendable_balance(), 50 * COIN);
+
+        // Alice pays bob, with change back to a fresh address of hers
+        let tx = alice
+            .create_transaction(bob_address, 10 * COIN, 3, &LargestFirst)
+            .unwrap();
+        let fee = tx.encode().len() as u64 * 3;
+        assert_eq!(tx.inputs.len(), 1);
+        assert_eq!(tx.outputs[0].amount, 10 * COIN);
+        assert_eq!(tx.outputs[1].amount, 40 * COIN - fee);
+        let change = tx.outputs[1].condition.address().unwrap();
+        assert!(alice.is_mine(change));
+        assert_ne!(*change, miner);
+
+        let a3 = mine(&a2, stranger, 3, fee, &[&tx]);
+        a3.verify_spends(chain.utxo_set().unwrap()).unwrap();
+        chain.append(a3.clone()).unwrap();
+        alice.sync(&chain).unwrap();
+        bob.sync(&chain).unwrap();
+        assert_eq!(alice.balance(), 40 * COIN - fee);
+        assert_eq!(bob.balance(), 10 * COIN);
+        assert_eq!(alice.height(), 3);
+
+        // A heavier branch without the payment takes it back out
+        let b3 = mine(&a2, stranger, 4, 0, &[]);
+        let b4 = mine(&b3, stranger, 5, 0, &[]);
+        chain.insert(b3).unwrap();
+        chain.insert(b4.clone()).unwrap().unwrap();
+        alice.sync(&chain).unwrap();
+        bob.sync(&chain).unwrap();
+        assert_eq!(alice.balance(), 50 * COIN);
+        assert_eq!(alice.spendable_balance(), 50 * COIN);
+        assert_eq!(bob.balance(), 0);
+        assert_eq!(alice.height(), 4);
+
+        // The payment is still valid on the new branch and confirms again
+        let b5 = mine(&b4, stranger, 6, fee, &[&tx]);
+        b5.verify_spends(chain.utxo_set().unwrap()).unwrap();
+        chain.append(b5).unwrap();
+        alice.sync(&chain).unwrap();
+        bob.sync(&chain).unwrap();
+        assert_eq!(alice.balance(), 40 * COIN - fee);
+        assert_eq!(bob.balance(), 10 * COIN);
+
+        // Too little is left over to pay for a change output, so it goes to the fee
+        let all = bob
+            .create_transaction(stranger, 10 * COIN - 200, 1, &LargestFirst)
+            .unwrap();
+        assert_eq!(all.outputs.len(), 1);
+        assert!(all.fee(chain.utxo_set().unwrap()).unwrap() >= all.encode().len() as u64);
+    }
+
+    #[test]
+    fn test_selection_and_lookahead() {
+        let mut alice = hd_wallet();
+        let genesis = params().genesis_block();
+        alice.connect_block(&genesis, 0);
+
+        // Addresses not yet handed out are recognised, up to the gap limit
+        let mut fresh = hd_wallet();
+        let addresses: Vec<Address> = (0..4).map(|_| fresh.receive_address().unwrap()).collect();
+        assert!(alice.is_mine(&addresses[2]));
+        assert!(!alice.is_mine(&addresses[3]));
+        let paid = mine(&genesis, addresses[2], 1, 0, &[]);
+        alice.connect_block(&paid, 0);
+        assert!(alice.is_mine(&addresses[3]));
+        // Receiving resumes after the address seen in the block
+        assert_eq!(alice.receive_address().unwrap(), addresses[3]);
+
+        let recipient = Address::from_bytes([9; 32]);
+        assert_eq!(
+            alice.create_transaction(recipient, 0, 1, &LargestFirst),
+            Err(WalletError::Dust(0))
+        );
+        let tx = alice
+            .create_transaction(recipient, 50 * COIN, 0, &LargestFirst)
+            .unwrap();
+        assert_eq!(tx.outputs.len(), 1);
+        assert_eq!(
+            alice.create_transaction(recipient, 50 * COIN, 1, &LargestFirst),
+            Err(WalletError::InsufficientFunds {
+                available: 50 * COIN,
+                required: 50 * COIN + tx.encode().len() as u64,
+            })
+        );
+        assert_eq!(
+            Wallet::new().create_transaction(recipient, COIN, 1, &LargestFirst),
+            Err(WalletError::InsufficientFunds {
+                available: 0,
+                required: COIN + 47,
+            })
+        );
+        assert_eq!(Wallet::new().receive_address(), Err(WalletError::NoKeys));
+        assert_eq!(
+            Wallet::new().sync(&Blockchain::new_from_params(&params())),
+            Err(WalletError::Unsubscribed)
+        );
+    }
+}
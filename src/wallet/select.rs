@@ -0,0 +1,440 @@
+//! Choosing which outputs fund a transaction.
+//!
+//! Every strategy judges a choice by what the signed transaction would
+//! cost: each input adds its encoded size times the fee rate, so an output
+//! worth no more than the fee of spending it is never chosen.
+
+use crate::codec;
+use crate::transaction::OutPoint;
+
+use super::WalletError;
+
+/// Branch-and-bound gives up after exploring this many choices
+const BNB_MAX_TRIES: usize = 100_000;
+
+/// An output a selector may spend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    pub out: OutPoint,
+    pub amount: u64,
+    /// Bytes the signed input spending it adds to the transaction
+    pub input_size: usize,
+}
+
+/// What the selected outputs must pay for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Target {
+    /// The amount paid to the recipient
+    pub amount: u64,
+    /// Fee per byte of the signed transaction's encoding
+    pub fee_rate: u64,
+    /// Encoded size of the transaction without inputs, paying only the
+    /// recipient
+    pub base_size: usize,
+    /// Bytes a change output adds
+    pub change_size: usize,
+    /// The smallest change worth an output; less goes to the fee
+    pub dust: u64,
+}
+
+/// The outputs to spend, and where the excess over the amount goes
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Selection {
+    pub inputs: Vec<OutPoint>,
+    /// The amount of the change output, if there is one
+    pub change: Option<u64>,
+    /// The fee the transaction pays, including any excess too small for
+    /// change
+    pub fee: u64,
+}
+
+impl Target {
+    /// Encoded size of the signed transaction spending `inputs`, with a
+    /// change output if `change`
+    pub fn size(&self, inputs: &[&Candidate], change: bool) -> usize {
+        let mut count = Vec::new();
+        codec::write_varint(&mut count, inputs.len() as u64);
+        // The base size counts a one byte input count
+        self.base_size + count.len() - 1
+            + inputs.iter().map(|c| c.input_size).sum::<usize>()
+            + if change { self.change_size } else { 0 }
+    }
+
+    fn fee(&self, size: usize) -> u64 {
+        (size as u64).saturating_mul(self.fee_rate)
+    }
+
+    /// What `candidate` adds towards the target once the fee of spending it
+    /// is paid, or `None` if it adds nothing
+    pub fn effective_value(&self, candidate: &Candidate) -> Option<u64> {
+        candidate
+            .amount
+            .checked_sub(self.fee(candidate.input_size))
+            .filter(|&value| value > 0)
+    }
+
+    /// The selection spending `inputs`, with change if the excess pays for
+    /// a change output and is not dust, or `None` if they fall short
+    pub fn settle(&self, inputs: &[&Candidate]) -> Option<Selection> {
+        let total = inputs
+            .iter()
+            .fold(0u64, |sum, c| sum.saturating_add(c.amount));
+        let with_change = self
+            .amount
+            .saturating_add(self.fee(self.size(inputs, true)));
+        let change = total
+            .checked_sub(with_change)
+            .filter(|&change| change >= self.dust);
+        let fee = match change {
+            Some(change) => total - self.amount - change,
+            None => total.checked_sub(self.amount)?,
+        };
+        if change.is_none() && fee < self.fee(self.size(inputs, false)) {
+            return None;
+        }
+        Some(Selection {
+            inputs: inputs.iter().map(|c| c.out).collect(),
+            change,
+            fee,
+        })
+    }
+
+    /// The error for candidates that cannot meet the target even spending
+    /// every one worth spending; `required - available` is the shortfall
+    pub fn insufficient(&self, candidates: &[Candidate]) -> WalletError {
+        let usable: Vec<&Candidate> = candidates
+            .iter()
+            .filter(|c| self.effective_value(c).is_some())
+            .collect();
+        WalletError::InsufficientFunds {
+            available: usable.iter().map(|c| c.amount).sum(),
+            required: self
+                .amount
+                .saturating_add(self.fee(self.size(&usable, false))),
+        }
+    }
+}
+
+/// A strategy choosing which outputs fund a transaction
+pub trait CoinSelector {
+    /// Choose from `candidates` outputs paying `target` and the fee of
+    /// spending them, failing with [`WalletError::InsufficientFunds`] if
+    /// they cannot
+    fn select(&self, candidates: &[Candidate], target: &Target) -> Result<Selection, WalletError>;
+}
+
+/// Add the candidates worth spending in `order` until they meet `target`
+fn accumulate<'a>(
+    order: impl IntoIterator<Item = &'a Candidate>,
+    candidates: &[Candidate],
+    target: &Target,
+) -> Result<Selection, WalletError> {
+    let mut inputs = Vec::new();
+    for candidate in order {
+        if target.effective_value(candidate).is_none() {
+            continue;
+        }
+        inputs.push(candidate);
+        if let Some(selection) = target.settle(&inputs) {
+            return Ok(selection);
+        }
+    }
+    Err(target.insufficient(candidates))
+}
+
+/// The largest outputs first: few inputs and a low fee, but usually change
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LargestFirst;
+
+impl CoinSelector for LargestFirst {
+    fn select(&self, candidates: &[Candidate], target: &Target) -> Result<Selection, WalletError> {
+        let mut order: Vec<&Candidate> = candidates.iter().collect();
+        order.sort_by(|a, b| b.amount.cmp(&a.amount).then(a.out.cmp(&b.out)));
+        accumulate(order, candidates, target)
+    }
+}
+
+/// The smallest outputs first, consolidating them into fewer while fees
+/// are low
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SmallestFirst;
+
+impl CoinSelector for SmallestFirst {
+    fn select(&self, candidates: &[Candidate], target: &Target) -> Result<Selection, WalletError> {
+        let mut order: Vec<&Candidate> = candidates.iter().collect();
+        order.sort_by(|a, b| a.amount.cmp(&b.amount).then(a.out.cmp(&b.out)));
+        accumulate(order, candidates, target)
+    }
+}
+
+/// A depth-first search for outputs meeting the target without change,
+/// overshooting by at most `tolerance`, which goes to the fee. Of the
+/// matches found, the one overshooting least is chosen. Without a match it
+/// falls back to [`LargestFirst`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BranchAndBound {
+    /// How far a match may overshoot; by default what a change output would
+    /// cost at the fee rate
+    pub tolerance: Option<u64>,
+}
+
+/// The state of a branch-and-bound search
+struct Search<'a> {
+    /// Candidates worth spending with their effective values, largest first
+    pool: Vec<(u64, &'a Candidate)>,
+    target: u64,
+    tolerance: u64,
+    chosen: Vec<usize>,
+    best: Option<(u64, Vec<usize>)>,
+    tries: usize,
+}
+
+impl Search<'_> {
+    /// Explore the choices from candidate `i` on, given `value` chosen so
+    /// far and `remaining` available from `i` on
+    fn explore(&mut self, i: usize, value: u64, remaining: u64) {
+        if self.tries == 0 || value > self.target.saturating_add(self.tolerance) {
+            return;
+        }
+        self.tries -= 1;
+        if value >= self.target {
+            // Adding more would only overshoot further
+            let waste = value - self.target;
+            if self.best.as_ref().is_none_or(|(best, _)| waste < *best) {
+                self.best = Some((waste, self.chosen.clone()));
+            }
+            return;
+        }
+        if i == self.pool.len() || value + remaining < self.target {
+            return;
+        }
+        let (effective, _) = self.pool[i];
+        self.chosen.push(i);
+        self.explore(i + 1, value + effective, remaining - effective);
+        self.chosen.pop();
+        if self.best.as_ref().is_some_and(|(waste, _)| *waste == 0) {
+            return;
+        }
+        self.explore(i + 1, value, remaining - effective);
+    }
+}
+
+impl CoinSelector for BranchAndBound {
+    fn select(&self, candidates: &[Candidate], target: &Target) -> Result<Selection, WalletError> {
+        let mut pool: Vec<(u64, &Candidate)> = candidates
+            .iter()
+            .filter_map(|c| Some((target.effective_value(c)?, c)))
+            .collect();
+        pool.sort_by(|(a, a_c), (b, b_c)| b.cmp(a).then(a_c.out.cmp(&b_c.out)));
+        let remaining = pool.iter().map(|(value, _)| value).sum();
+        let mut search = Search {
+            pool,
+            target: target
+                .amount
+                .saturating_add(target.fee(target.size(&[], false))),
+            tolerance: self
+                .tolerance
+                .unwrap_or_else(|| target.fee(target.change_size)),
+            chosen: Vec::new(),
+            best: None,
+            tries: BNB_MAX_TRIES,
+        };
+        search.explore(0, 0, remaining);
+
+        let Some((_, chosen)) = search.best else {
+            return LargestFirst.select(candidates, target);
+        };
+        let inputs: Vec<&Candidate> = chosen.iter().map(|&i| search.pool[i].1).collect();
+        let total: u64 = inputs.iter().map(|c| c.amount).sum();
+        Ok(Selection {
+            inputs: inputs.iter().map(|c| c.out).collect(),
+            change: None,
+            fee: total - target.amount,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::transaction::Txid;
+
+    /// Outputs of 1000, 5000, 20000, 30000 and 100000, each 100 bytes to
+    /// spend
+    fn candidates() -> Vec<Candidate> {
+        [5_000, 100_000, 1_000, 30_000, 20_000]
+            .iter()
+            .enumerate()
+            .map(|(i, &amount)| Candidate {
+                out: OutPoint {
+                    txid: Txid::from_bytes([i as u8; 32]),
+                    index: 0,
+                },
+                amount,
+                input_size: 100,
+            })
+            .collect()
+    }
+
+    fn target(amount: u64, fee_rate: u64) -> Target {
+        Target {
+            amount,
+            fee_rate,
+            base_size: 46,
+            change_size: 40,
+            dust: 300,
+        }
+    }
+
+    /// The amounts of the selected outputs, in selection order
+    fn amounts(selection: &Selection) -> Vec<u64> {
+        let candidates = candidates();
+        selection
+            .inputs
+            .iter()
+            .map(|out| candidates.iter().find(|c| c.out == *out).unwrap().amount)
+            .collect()
+    }
+
+    #[test]
+    fn test_strategies_on_fixed_outputs() {
+        let candidates = candidates();
+        let goal = target(25_000, 1);
+
+        let largest = LargestFirst.select(&candidates, &goal).unwrap();
+        assert_eq!(amounts(&largest), [100_000]);
+        // 46 + 100 + 40 bytes at 1 per byte
+        assert_eq!(largest.fee, 186);
+        assert_eq!(largest.change, Some(100_000 - 25_000 - 186));
+
+        let smallest = SmallestFirst.select(&candidates, &goal).unwrap();
+        assert_eq!(amounts(&smallest), [1_000, 5_000, 20_000]);
+        assert_eq!(smallest.fee, 386);
+        assert_eq!(smallest.change, Some(26_000 - 25_000 - 386));
+
+        // No subset lands within a change output's cost, so branch and bound
+        // falls back to the largest output
+        assert_eq!(
+            BranchAndBound::default().select(&candidates, &goal),
+            Ok(largest)
+        );
+
+        // At 20 per byte the 1000 output costs 2000 to spend and is skipped
+        let smallest = SmallestFirst
+            .select(&candidates, &target(3_000, 20))
+            .unwrap();
+        assert_eq!(amounts(&smallest), [5_000, 20_000]);
+    }
+
+    #[test]
+    fn test_branch_and_bound_exact_match() {
+        let candidates = candidates();
+        // 20000 and 5000 pay 24754 and a fee of 246 for their 246 bytes
+        // exactly
+        let exact = BranchAndBound::default()
+            .select(&candidates, &target(24_754, 1))
+            .unwrap();
+        assert_eq!(amounts(&exact), [20_000, 5_000]);
+        assert_eq!(exact.change, None);
+        assert_eq!(exact.fee, 246);
+
+        // An overshoot within the tolerance goes to the fee
+        let close = BranchAndBound::default()
+            .select(&candidates, &target(24_730, 1))
+            .unwrap();
+        assert_eq!(amounts(&close), [20_000, 5_000]);
+        assert_eq!(close.fee, 270);
+        let strict = BranchAndBound {
+            tolerance: Some(10),
+        };
+        assert_eq!(
+            strict.select(&candidates, &target(24_730, 1)),
+            LargestFirst.select(&candidates, &target(24_730, 1))
+        );
+    }
+
+    #[test]
+    fn test_insufficient_funds_counts_fees() {
+        let candidates = candidates();
+        // Spending all five outputs costs 546 bytes of fees on top
+        let expected = WalletError::InsufficientFunds {
+            available: 156_000,
+            required: 156_000 + 546,
+        };
+        let goal = target(156_000, 1);
+        assert_eq!(
+            LargestFirst.select(&candidates, &goal),
+            Err(expected.clone())
+        );
+        assert_eq!(
+            SmallestFirst.select(&candidates, &goal),
+            Err(expected.clone())
+        );
+        assert_eq!(
+            BranchAndBound::default().select(&candidates, &goal),
+            Err(expected)
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn test_selections_pay_target_and_fee(
+            outputs in vec((1..200_000u64, 60..200usize), 0..12),
+            amount in 1..300_000u64,
+            fee_rate in 0..20u64,
+        ) {
+            let candidates: Vec<Candidate> = outputs
+                .iter()
+                .enumerate()
+                .map(|(i, &(amount, input_size))| Candidate {
+                    out: OutPoint {
+                        txid: Txid::from_bytes([i as u8; 32]),
+                        index: 0,
+                    },
+                    amount,
+                    input_size,
+                })
+                .collect();
+            let goal = target(amount, fee_rate);
+            let selectors: [&dyn CoinSelector; 3] =
+                [&LargestFirst, &SmallestFirst, &BranchAndBound::default()];
+            let results: Vec<_> = selectors
+                .iter()
+                .map(|selector| selector.select(&candidates, &goal))
+                .collect();
+            // Every strategy falls back to spending all it can
+            prop_assert!(results.iter().all(Result::is_ok) || results.iter().all(Result::is_err));
+
+            for result in results {
+                match result {
+                    Ok(selection) => {
+                        let inputs: Vec<&Candidate> = selection
+                            .inputs
+                            .iter()
+                            .map(|out| candidates.iter().find(|c| c.out == *out).unwrap())
+                            .collect();
+                        let mut distinct = selection.inputs.clone();
+                        distinct.sort();
+                        distinct.dedup();
+                        prop_assert_eq!(distinct.len(), inputs.len());
+
+                        let total: u64 = inputs.iter().map(|c| c.amount).sum();
+                        let change = selection.change.unwrap_or(0);
+                        prop_assert_eq!(total, amount + selection.fee + change);
+                        let size = goal.size(&inputs, selection.change.is_some());
+                        prop_assert!(selection.fee >= size as u64 * fee_rate);
+                        prop_assert!(selection.change.is_none_or(|change| change >= goal.dust));
+                    }
+                    Err(WalletError::InsufficientFunds {
+                        available,
+                        required,
+                    }) => prop_assert!(available < required),
+                    Err(err) => prop_assert!(false, "unexpected {}", err),
+                }
+            }
+        }
+    }
+}
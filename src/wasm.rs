@@ -0,0 +1,173 @@
+//! Merkle proof and header proof-of-work checks exported to JavaScript, for
+//! light clients in a browser.
+//!
+//! Build for `wasm32-unknown-unknown` with the `wasm` feature and run the
+//! output through `wasm-bindgen`. The checks here only decode and hash, so
+//! nothing they call reads a clock or starts a thread, neither of which a
+//! browser offers. Input that cannot be checked at all, such as bytes that
+//! are not a proof, throws an `Error` with a message; input that decodes but
+//! does not check out returns `false`.
+//!
+//! The tests run in a JavaScript engine, with `wasm-bindgen-test-runner` as
+//! the target's runner:
+//!
+//! ```text
+//! cargo test --target wasm32-unknown-unknown --features wasm --lib wasm::
+//! ```
+
+use js_sys::{Array, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::block::BlockHeader;
+use crate::codec::DecodeLimits;
+use crate::difficulty::Difficulty;
+use crate::encoding;
+use crate::merkle_trie::{MerkleProof, MerkleTree};
+
+/// Whether `proof_bytes`, a proof in the format of
+/// [`MerkleProof::to_bytes`], places `leaf_data` under the root whose hex is
+/// `root_hex`
+#[wasm_bindgen]
+pub fn verify_proof(root_hex: &str, leaf_data: &[u8], proof_bytes: &[u8]) -> Result<bool, JsError> {
+    let root =
+        encoding::from_hex(root_hex).map_err(|err| JsError::new(&format!("root: {}", err)))?;
+    if root.len() != 32 {
+        return Err(JsError::new(&format!(
+            "root is {} bytes, expected 32",
+            root.len()
+        )));
+    }
+    let proof = MerkleProof::from_bytes(proof_bytes, &DecodeLimits::default())
+        .map_err(|err| JsError::new(&format!("proof: {}", err)))?;
+    Ok(proof.root_hash() == root.as_slice() && proof.verify(leaf_data))
+}
+
+/// Whether the header encoded as `header_bytes` meets the difficulty its
+/// bits commit to, and at least `difficulty`, a target in the same compact
+/// form as the bits
+#[wasm_bindgen]
+pub fn verify_header(header_bytes: &[u8], difficulty: u32) -> Result<bool, JsError> {
+    let header = BlockHeader::from_bytes(header_bytes)
+        .map_err(|err| JsError::new(&format!("header: {}", err)))?;
+    let hash = header.hash();
+    Ok(header.difficulty().is_met_by(hash.as_bytes())
+        && Difficulty::CompactTarget(difficulty).is_met_by(hash.as_bytes()))
+}
+
+/// The hex of the merkle root of `leaves`, a non-empty array of
+/// `Uint8Array`s
+#[wasm_bindgen]
+pub fn merkle_root(leaves: JsValue) -> Result<String, JsError> {
+    let leaves: Array = leaves
+        .dyn_into()
+        .map_err(|_| JsError::new("leaves must be an array"))?;
+    if leaves.length() == 0 {
+        return Err(JsError::new("leaves must not be empty"));
+    }
+    let leaves = leaves
+        .iter()
+        .enumerate()
+        .map(|(index, leaf)| {
+            leaf.dyn_into::<Uint8Array>()
+                .map(|leaf| leaf.to_vec())
+                .map_err(|_| JsError::new(&format!("leaf {} is not a Uint8Array", index)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let tree = MerkleTree::new(&leaves).map_err(|err| JsError::new(&err.to_string()))?;
+    Ok(hex::encode(tree.root_hash()))
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+    use crate::block::{BlockBuilder, BlockHash};
+
+    fn leaves() -> Vec<Vec<u8>> {
+        (0..5u8).map(|i| vec![i; 3]).collect()
+    }
+
+    fn message(err: JsValue) -> String {
+        err.dyn_into::<js_sys::Error>().unwrap().message().into()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_merkle_root_and_proofs() {
+        let leaves = leaves();
+        let tree = MerkleTree::new(&leaves);
+        let array: Array = leaves
+            .iter()
+            .map(|leaf| Uint8Array::from(leaf.as_slice()))
+            .collect();
+        let root = merkle_root(array.into()).unwrap();
+        assert_eq!(root, hex::encode(tree.root_hash()));
+
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = tree.generate_proof(index).to_bytes();
+            assert_eq!(verify_proof(&root, leaf, &proof).ok(), Some(true));
+            // The wrong leaf, or the right leaf under another root
+            assert_eq!(verify_proof(&root, b"other", &proof).ok(), Some(false));
+            assert_eq!(
+                verify_proof(&"00".repeat(32), leaf, &proof).ok(),
+                Some(false)
+            );
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_invalid_input_throws() {
+        let leaves = leaves();
+        let tree = MerkleTree::new(&leaves);
+        let root = hex::encode(tree.root_hash());
+        let proof = tree.generate_proof(0).to_bytes();
+
+        let err = verify_proof(&root, &leaves[0], &proof[..40]).unwrap_err();
+        assert_eq!(message(err.into()), "proof: unexpected end of input");
+        let err = verify_proof("not hex", &leaves[0], &proof).unwrap_err();
+        assert!(message(err.into()).starts_with("root: "));
+        let err = verify_proof(&root[..62], &leaves[0], &proof).unwrap_err();
+        assert_eq!(message(err.into()), "root is 31 bytes, expected 32");
+
+        let err = merkle_root(Array::new().into()).unwrap_err();
+        assert_eq!(message(err.into()), "leaves must not be empty");
+        let err = merkle_root(JsValue::from_str("leaf")).unwrap_err();
+        assert_eq!(message(err.into()), "leaves must be an array");
+        let mixed = Array::of2(&Uint8Array::from(&[1u8][..]), &JsValue::from(2));
+        let err = merkle_root(mixed.into()).unwrap_err();
+        assert_eq!(message(err.into()), "leaf 1 is not a Uint8Array");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_verify_header() {
+        let easy = Difficulty::LeadingZeroBits(4);
+        let header = |nonce| {
+            BlockBuilder::new(BlockHash::from_bytes([0; 32]))
+                .transaction(b"tx".to_vec())
+                .timestamp(1_700_000_000)
+                .difficulty(easy)
+                .nonce(nonce)
+                .build()
+                .header()
+                .clone()
+        };
+        let mined = (0..)
+            .map(header)
+            .find(|h| easy.is_met_by(h.hash().as_bytes()));
+        let unmined = (0..)
+            .map(header)
+            .find(|h| !easy.is_met_by(h.hash().as_bytes()));
+        let mined = mined.unwrap().to_bytes();
+        assert_eq!(verify_header(&mined, easy.to_compact()).ok(), Some(true));
+
+        // A hash short of its own bits, or of a harder difficulty asked for
+        let unmined = unmined.unwrap().to_bytes();
+        assert_eq!(verify_header(&unmined, easy.to_compact()).ok(), Some(false));
+        let hard = Difficulty::LeadingZeroBits(200).to_compact();
+        assert_eq!(verify_header(&mined, hard).ok(), Some(false));
+
+        let err = verify_header(&mined[1..], easy.to_compact()).unwrap_err();
+        assert!(message(err.into()).starts_with("header: "));
+    }
+}
@@ -0,0 +1,49 @@
+//! Builds `tests/ffi/smoke.c` against `include/aarwyn.h` and the crate's
+//! shared library with the system C compiler, or `$CC`, and runs it
+
+#![cfg(unix)]
+
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+use aarwyn_chain::block::{BlockBuilder, BlockHash};
+
+#[test]
+fn test_c_smoke() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // Cargo builds the shared library next to this test, in
+    // target/<profile>/deps, with the same features
+    let lib_dir = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let exe = lib_dir.join("aarwyn_ffi_smoke");
+    let compiled = Command::new(env::var("CC").unwrap_or_else(|_| "cc".to_string()))
+        .arg(root.join("tests/ffi/smoke.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .args(["-laarwyn_chain", "-o"])
+        .arg(&exe)
+        .status()
+        .unwrap();
+    assert!(compiled.success());
+
+    let block = BlockBuilder::new(BlockHash::from_bytes([3; 32]))
+        .transaction(b"ffi".to_vec())
+        .timestamp(1_700_000_000)
+        .build()
+        .unwrap();
+    let output = Command::new(&exe)
+        .arg(hex::encode(block.header().to_bytes()))
+        .arg(hex::encode(block.hash().as_bytes()))
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"ok\n");
+}
@@ -0,0 +1,76 @@
+/*
+ * Calls each function of aarwyn.h through the shared library, from C.
+ * Takes a serialized header and its hash, both in hex, to check
+ * aarwyn_block_hash against. Exits non-zero, naming the check, if any
+ * result is not as expected.
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "aarwyn.h"
+
+#define CHECK(cond)                                          \
+    do {                                                     \
+        if (!(cond)) {                                       \
+            fprintf(stderr, "check failed: %s\n", #cond);    \
+            return 1;                                        \
+        }                                                    \
+    } while (0)
+
+/* Decode the hex `text` into `out`, returning its length in bytes */
+static size_t unhex(const char *text, uint8_t *out, size_t max) {
+    size_t len = strlen(text) / 2;
+    for (size_t i = 0; i < len && i < max; i++) {
+        char byte[3] = {text[2 * i], text[2 * i + 1], 0};
+        out[i] = (uint8_t)strtoul(byte, NULL, 16);
+    }
+    return len < max ? len : max;
+}
+
+int main(int argc, char **argv) {
+    CHECK(argc == 3);
+    const uint8_t a[] = {'a'};
+    const uint8_t b[] = {'b'};
+    const uint8_t *leaves[] = {a, b};
+    const size_t lens[] = {1, 1};
+    uint8_t root[AARWYN_HASH_LEN];
+    uint8_t small[AARWYN_HASH_LEN - 1];
+
+    CHECK(aarwyn_merkle_root(leaves, lens, 2, root, sizeof root) == AARWYN_OK);
+    CHECK(aarwyn_merkle_root(leaves, lens, 0, root, sizeof root) == AARWYN_ERR_EMPTY);
+    CHECK(aarwyn_merkle_root(leaves, lens, 2, small, sizeof small) ==
+          AARWYN_ERR_BUFFER_TOO_SMALL);
+
+    /* The proof of leaf "a": its hash, the root, one sibling on the right */
+    uint8_t proof[32 + 32 + 1 + 1 + 32];
+    uint8_t leaf_hash[AARWYN_HASH_LEN];
+    uint8_t sibling[AARWYN_HASH_LEN];
+    CHECK(aarwyn_merkle_root(leaves, lens, 1, leaf_hash, sizeof leaf_hash) == AARWYN_OK);
+    CHECK(aarwyn_merkle_root(leaves + 1, lens + 1, 1, sibling, sizeof sibling) == AARWYN_OK);
+    memcpy(proof, leaf_hash, 32);
+    memcpy(proof + 32, root, 32);
+    proof[64] = 1;
+    proof[65] = 1;
+    memcpy(proof + 66, sibling, 32);
+
+    CHECK(aarwyn_verify_proof(root, sizeof root, a, 1, proof, sizeof proof) == 1);
+    CHECK(aarwyn_verify_proof(root, sizeof root, b, 1, proof, sizeof proof) == 0);
+    CHECK(aarwyn_verify_proof(root, 31, a, 1, proof, sizeof proof) == AARWYN_ERR_INVALID_LENGTH);
+    CHECK(aarwyn_verify_proof(root, sizeof root, a, 1, proof, 40) == AARWYN_ERR_DECODE);
+    CHECK(aarwyn_verify_proof(root, sizeof root, NULL, 1, proof, sizeof proof) ==
+          AARWYN_ERR_NULL_POINTER);
+
+    uint8_t header[256];
+    uint8_t expected[AARWYN_HASH_LEN];
+    uint8_t hash[AARWYN_HASH_LEN];
+    size_t header_len = unhex(argv[1], header, sizeof header);
+    CHECK(unhex(argv[2], expected, sizeof expected) == AARWYN_HASH_LEN);
+    CHECK(aarwyn_block_hash(header, header_len, hash, sizeof hash) == AARWYN_OK);
+    CHECK(memcmp(hash, expected, AARWYN_HASH_LEN) == 0);
+    CHECK(aarwyn_block_hash(header, 10, hash, sizeof hash) == AARWYN_ERR_DECODE);
+
+    puts("ok");
+    return 0;
+}